@@ -0,0 +1,10 @@
+fn main() {
+    // protoc comes with the build rather than having to be installed
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("No protoc is vendored for this platform.");
+    std::env::set_var("PROTOC", protoc);
+
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/armada.proto"], &["proto"])
+        .expect("Unable to generate the gRPC service from proto/armada.proto.");
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+
+use armada_lib::{analyze_capture, AnalyzeOptions, CaptureAnalysis, PortIterator};
+use tokio::sync::mpsc::unbounded_channel;
+
+use crate::output::{create_output_file, NmapScanInfo, OutputFormat, ResultWriter};
+use crate::post_scan::PostScan;
+
+pub(crate) struct AnalyzeConfig {
+    pub(crate) capture: PathBuf,
+    pub(crate) options: AnalyzeOptions,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) output_file: Option<PathBuf>,
+}
+
+/// Works out the results of a scan from a capture of it and writes them out the way the scan would have
+pub(crate) async fn run(config: AnalyzeConfig) {
+    let AnalyzeConfig { capture, options, output_format, output_file } = config;
+
+    let CaptureAnalysis { scan_type, results } =
+        analyze_capture(&capture, options).unwrap_or_else(|error| panic!("{:#}", error));
+
+    let ports = results.iter().fold(PortIterator::new(), |ports, result| ports.add_port(result.addr.port()));
+    let destination = match &output_file {
+        Some(output_file) => create_output_file(output_file),
+        None => Box::new(std::io::stdout()),
+    };
+    let writer = ResultWriter::new()
+        .with_output(output_format, destination, false)
+        .with_nmap_scan_info(NmapScanInfo::new(scan_type, ports));
+
+    let (result_sender, result_receiver) = unbounded_channel();
+    results.into_iter().for_each(|result| {
+        let _ = result_sender.send(result);
+    });
+    drop(result_sender);
+
+    PostScan::default().run(result_receiver, writer).await;
+}
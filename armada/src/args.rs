@@ -1,210 +1,1959 @@
+use std::ffi::OsString;
 use std::fs::read_to_string;
 use std::io::{stdin, BufRead};
-use std::net::IpAddr;
+use std::iter::once;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
-use armada_lib::{HostIterator, PortIterator};
+use armada_lib::utils::parse_cpu_list;
+use armada_lib::{
+    AdaptiveTimeout, AnalyzeOptions, BannerOptions, ConnectionOptions, Decoys, Engine, HostIterator, MacAddr, Pacing,
+    PacketCapture, PortIterator, RateLimitStrategy, ScanShard, ScanType, SubnetRateLimit, TcpProbeOption, TcpProbeProfile,
+    DEFAULT_SERVICE_TIMEOUT,
+};
 use atty::Stream;
-use cidr_utils::cidr::IpCidr;
-use clap::{crate_version, Arg, ArgGroup, ArgMatches, Command};
-use rand::Rng;
+use cidr_utils::cidr::{IpCidr, Ipv4Cidr};
+use clap::{crate_version, Arg, ArgGroup, ArgMatches, Command, ErrorKind, ValueSource};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-use crate::config::get_toml_config;
+use crate::analyze::AnalyzeConfig;
+use crate::asn::parse_asn;
+use crate::checkpoint::Checkpointer;
+use crate::cloud::{CloudSource, CLOUD_PROVIDERS};
+use crate::completions::{generate as generate_completions, SHELL_NAMES};
+use crate::config::{get_toml_config, init_config};
+use crate::diff::{DiffFormat, DiffOptions};
+use crate::distributed::{AgentConfig, ControllerConfig};
+use crate::ipv6::{Ipv6Strategy, DEFAULT_IPV6_SAMPLE_SIZE, IPV6_STRATEGIES};
+use crate::logging::LogFile;
+use crate::output::{CsvField, OutputFormat, SinkTarget, SyslogFacility, SyslogSeverity, SyslogTransport};
+use crate::plan::PlanFormat;
+#[cfg(unix)]
+use crate::privileges::{self, RunAs};
+use crate::schedule::ScanWindow;
+use crate::scope::{Scope, ScopeMode, SCOPE_MODES};
+use crate::serve::ServeConfig;
+use crate::services::service_port;
 
-const DEFAULT_RATE_LIMIT: usize = 10_000; // default rate limit
+pub(crate) const DEFAULT_RATE_LIMIT: usize = 10_000; // default rate limit
 const DEFAULT_PORT_RETRY: u8 = 2; // default number of additional attempts to make against ports
 const DEFAULT_TIMEOUT_IN_MS: u64 = 1_000;
+const DEFAULT_BANNER_BYTES: usize = 512;
+const DEFAULT_BANNER_TIMEOUT_IN_MS: u64 = 3_000;
+const DEFAULT_RESOLVE_TIMEOUT_IN_MS: u64 = 5_000;
+/// How long a downloaded exclusion list is used for before it's fetched again
+const DEFAULT_EXCLUDE_URL_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// How many probes a scan may send before it's only started once it's confirmed
+const DEFAULT_CONFIRM_ABOVE: u128 = 10_000_000;
+const DEFAULT_PACKET_BATCH_SIZE: usize = 32;
+/// The most messages the kernel takes in one `sendmmsg` or `recvmmsg` call (`UIO_MAXIOV`)
+const MAX_PACKET_BATCH_SIZE: usize = 1024;
+/// Each listening port gets a worker thread of its own, so a range, or `--threads`, stays small
+const MAX_LISTENING_PORTS: u16 = 64;
+const OUTPUT_FORMATS: [&str; 7] = ["default", "json", "ndjson", "csv", "nmap-xml", "grepable", "hosts"];
+const SQLITE_OUTPUT_PREFIX: &str = "sqlite:";
+const POSTGRES_OUTPUT_PREFIXES: [&str; 2] = ["postgres://", "postgresql://"];
+const ELASTICSEARCH_OUTPUT_PREFIX: &str = "elasticsearch:";
+const KAFKA_OUTPUT_PREFIX: &str = "kafka://";
+const DEFAULT_ELASTICSEARCH_INDEX: &str = "armada";
+const DEFAULT_ELASTICSEARCH_BATCH_SIZE: usize = 500;
+const DEFAULT_WEBHOOK_BATCH_SIZE: usize = 1;
+const DEFAULT_LOG_MAX_FILES: usize = 5;
+const DEFAULT_SERVE_ADDRESS: &str = "127.0.0.1:8080";
+const DEFAULT_CONTROLLER_ADDRESS: &str = "0.0.0.0:7700";
+const DEFAULT_SHARDS: usize = 64;
+/// IPv6 networks are capped per /64 by `--max-rate-per-net`, whatever the IPv4 prefix
+const SUBNET_RATE_LIMIT_IPV6_PREFIX: u8 = 64;
 
 pub(crate) struct ArmadaConfig {
     pub(crate) targets: HostIterator,
+    /// Targets given by name, which still need to be resolved before they can be scanned
+    pub(crate) hostname_targets: Vec<String>,
+    /// Targets given as AS numbers, which still need to be expanded into the prefixes they announce
+    pub(crate) asn_targets: Vec<u32>,
+    /// Targets given as cloud providers' regions and services, which still need to be expanded into the ranges
+    /// published for them
+    pub(crate) cloud_targets: Vec<CloudSource>,
+    /// Whether targets that can't be parsed or looked up are left out rather than stopping the scan
+    pub(crate) skip_invalid: bool,
+    /// Ranges removed from the targets once every one of them is known
+    pub(crate) exclusions: Vec<IpCidr>,
+    /// Exclusion lists downloaded once the targets are known, and cached for as long as `exclude_url_max_age`
+    pub(crate) exclude_urls: Vec<String>,
+    pub(crate) exclude_url_max_age: Duration,
+    /// The ranges the targets have to be within, once every one of them is known
+    pub(crate) scope: Option<Scope>,
+    pub(crate) resolve_timeout: Duration,
+    pub(crate) rdns: bool,
     pub(crate) ports: PortIterator,
     pub(crate) quiet_mode: bool,
+    /// How many times `-v` was given, for how much is logged
+    pub(crate) verbosity: u8,
+    /// Where logs are written in place of stderr
+    pub(crate) log_file: Option<LogFile>,
+    /// How progress is shown when not in quiet mode
+    pub(crate) progress_format: ProgressFormat,
     pub(crate) rate_limit: Option<usize>,
-    pub(crate) listening_port: u16,
+    /// How the rate limit is held to
+    pub(crate) rate_limiter: RateLimitStrategy,
+    /// Whether the rate is adjusted to the network as the scan goes, with the rate limit as the most it goes up to
+    pub(crate) auto_rate: bool,
+    pub(crate) subnet_rate_limit: Option<SubnetRateLimit>,
+    /// The most packets per second sent from any one source address
+    pub(crate) source_rate_limit: Option<usize>,
+    /// How far apart probes are spaced, if they're not sent as fast as the rate limits allow
+    pub(crate) pacing: Option<Pacing>,
+    /// The time of day probes are sent in, with the scan paused the rest of the day
+    pub(crate) scan_window: Option<ScanWindow>,
+    /// How long armada may send for before it stops and reports what it got through
+    pub(crate) max_runtime: Option<Duration>,
+    /// How the scan's plan is printed instead of running it, for `--dry-run` and `armada estimate`
+    pub(crate) plan_format: Option<PlanFormat>,
+    /// How many probes the scan may send before it has to be confirmed, unless it was with `--yes`
+    pub(crate) confirm_above: Option<u128>,
+    /// A worker receives on each of these, the first of which is what discovery listens on
+    pub(crate) listening_ports: Vec<u16>,
+    /// The cores the workers are pinned to, if they're not left to the scheduler
+    pub(crate) core_pinning: Option<CorePinning>,
+    /// Seeds the random listening port and the scan itself, so runs can be repeated exactly
+    pub(crate) seed: Option<u64>,
+    pub(crate) randomize: bool,
+    /// This machine's share of a scan split between several
+    pub(crate) shard: Option<ScanShard>,
     pub(crate) retries: u8,
     pub(crate) timeout: Duration,
+    /// The bounds of the timeout when it's worked out from round trip times rather than fixed
+    pub(crate) adaptive_timeout: Option<AdaptiveTimeout>,
+    pub(crate) packet_batch_size: usize,
+    /// Whether packets are sent and received a batch per system call rather than one per call
+    pub(crate) batched_io: bool,
+    pub(crate) engine: Engine,
+    /// The interface raw sockets are bound to and source addresses are picked from, rather than the routing table's
+    pub(crate) interface: Option<String>,
+    /// The interface the link-local targets are on, which the scan goes out on and their results are reported with
+    pub(crate) ipv6_zone: Option<String>,
+    /// Where every probe and response of the port scan is written to for `--pcap`
+    pub(crate) capture: Option<PacketCapture>,
     pub(crate) source_ips: Option<Vec<IpAddr>>,
     pub(crate) stream_results: bool,
+    pub(crate) scan_type: ScanType,
+    /// The TTL probes go out with, if not the system's default
+    pub(crate) ttl: Option<u8>,
+    /// How many bytes of a probe each IPv4 fragment carries, when probes are fragmented
+    pub(crate) fragment_size: Option<usize>,
+    /// Spoofed sources every probe is also sent from, with where our own address falls among them
+    pub(crate) decoys: Option<Decoys>,
+    /// The window and options TCP probes go out with, if any of them were set
+    pub(crate) probe_profile: Option<TcpProbeProfile>,
+    pub(crate) discover: bool,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) csv_fields: Vec<CsvField>,
+    pub(crate) csv_header: bool,
+    /// The database results are sent to instead of stdout, given as `-o sqlite:<path>` or `-o postgres://...`
+    pub(crate) sink_target: Option<SinkTarget>,
+    /// Where results are written in the output format instead of stdout
+    pub(crate) output_file: Option<PathBuf>,
+    /// The path, minus extension, results are written to in every `-oA` format instead of stdout
+    pub(crate) output_basename: Option<PathBuf>,
+    /// An HTTP endpoint results are POSTed to as they arrive, on top of any other output
+    pub(crate) webhook_url: Option<String>,
+    pub(crate) webhook_batch_size: usize,
+    /// Where Prometheus metrics about the scan are served from
+    pub(crate) metrics_address: Option<SocketAddr>,
+    /// The unix domain socket the scan takes commands on while it runs
+    pub(crate) control_socket: Option<PathBuf>,
+    /// Saves the scan's progress for `--checkpoint`, and holds what was loaded for `--resume`
+    pub(crate) checkpointer: Option<Checkpointer>,
+    pub(crate) banner_options: Option<BannerOptions>,
+    pub(crate) tls_options: Option<ConnectionOptions>,
+    pub(crate) tls_all_ports: bool,
+    pub(crate) service_options: Option<ConnectionOptions>,
+    pub(crate) os_guess: bool,
+    pub(crate) report_all_states: bool,
+    /// Whether a port answering more than once is reported every time
+    pub(crate) raw_results: bool,
+    /// Where the end of scan summary is written as JSON
+    pub(crate) summary_file: Option<PathBuf>,
+    /// Who the scan runs as once its raw sockets are open, when armada was started as root
+    #[cfg(unix)]
+    pub(crate) run_as: Option<RunAs>,
 }
 
-pub(crate) fn get_armada_config() -> ArmadaConfig {
-    let mut matches = app_config().get_matches();
-    if matches.is_present("toml_config") {
-        let args = get_toml_config(matches.value_of("toml_config").unwrap().to_string());
-        matches = app_config().get_matches_from(args);
+/// How the scan's progress is shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ProgressFormat {
+    Bars,
+    /// A full-screen dashboard, for `--tui`
+    Dashboard,
+    /// A JSON event on stderr every second, for `--progress json`
+    Json,
+}
+
+/// Which cores `--pin-cores` pins the workers to
+pub(crate) enum CorePinning {
+    Cores(Vec<usize>),
+    /// The cores on the NUMA node of the interface the scan goes out on
+    Auto,
+}
+
+/// What armada was asked to do: scan, or plan or estimate one, compare the results of earlier scans, or work out results
+/// from a capture
+pub(crate) enum ArmadaCommand {
+    Scan(Box<ArmadaConfig>),
+    Diff(DiffOptions),
+    Analyze(AnalyzeConfig),
+    /// Run scans submitted over HTTP, for `armada serve`
+    Serve(ServeConfig),
+    /// Scan the shards a controller hands out, for `armada agent`
+    Agent(AgentConfig),
+    /// Split a scan up between agents, for `armada controller`
+    Controller(ControllerConfig),
+    /// Print a generated TOML config or completion script
+    Print(String),
+}
+
+pub(crate) fn get_armada_command() -> ArmadaCommand {
+    let arguments: Vec<OsString> = std::env::args_os().map(expand_output_all_flag).collect();
+    let matches = app_config().get_matches_from(&arguments);
+
+    match matches.subcommand() {
+        Some(("diff", diff_matches)) => ArmadaCommand::Diff(get_diff_options(diff_matches)),
+        Some(("analyze", analyze_matches)) => ArmadaCommand::Analyze(get_analyze_config(analyze_matches)),
+        Some(("serve", serve_matches)) => ArmadaCommand::Serve(get_serve_config(serve_matches)),
+        Some(("agent", agent_matches)) => ArmadaCommand::Agent(get_agent_config(agent_matches)),
+        Some(("controller", controller_matches)) => ArmadaCommand::Controller(get_controller_config(controller_matches)),
+        Some(("config", config_matches)) => {
+            let init_matches = config_matches.subcommand_matches("init").expect("config requires a subcommand");
+            let arguments = init_matches.values_of_os("scan_arguments").into_iter().flatten().map(OsString::from);
+            let arguments: Vec<OsString> = once(OsString::from("armada")).chain(arguments).map(expand_output_all_flag).collect();
+            let matches = match app_config().try_get_matches_from(&arguments) {
+                Ok(matches) => matches,
+                // a config doesn't have to hold a whole scan, so what it's missing can be given when it's used
+                Err(error) if error.kind() == ErrorKind::MissingRequiredArgument => {
+                    app_config().ignore_errors(true).get_matches_from(&arguments)
+                }
+                Err(error) => error.exit(),
+            };
+
+            ArmadaCommand::Print(init_config(&app_config(), &matches))
+        }
+        Some(("completions", completions_matches)) => {
+            let shell = completions_matches
+                .value_of("shell")
+                .and_then(|shell| shell.parse::<clap_complete::Shell>().ok())
+                .expect("a shell is required");
+            // --output takes connection strings as well as formats, so only their start can be completed
+            let output_formats: Vec<&str> = OUTPUT_FORMATS
+                .into_iter()
+                .chain([SQLITE_OUTPUT_PREFIX, POSTGRES_OUTPUT_PREFIXES[0], ELASTICSEARCH_OUTPUT_PREFIX, KAFKA_OUTPUT_PREFIX])
+                .chain(["syslog://", "syslog+tcp://", "syslog:"])
+                .collect();
+
+            ArmadaCommand::Print(generate_completions(shell, app_config(), &[("output", &output_formats)]))
+        }
+        Some(("estimate", estimate_matches)) => {
+            // the scan being estimated is set up from the same arguments it would be run with
+            let arguments = estimate_matches.values_of_os("scan_arguments").into_iter().flatten().map(OsString::from);
+            let arguments: Vec<OsString> = once(OsString::from("armada")).chain(arguments).map(expand_output_all_flag).collect();
+            let matches = app_config().get_matches_from(&arguments);
+
+            let mut armada_config = get_armada_config(merge_toml_config(matches, arguments), None);
+            armada_config.plan_format = Some(PlanFormat::Estimate);
+
+            ArmadaCommand::Scan(Box::new(armada_config))
+        }
+        _ => match matches.value_of("resume") {
+            Some(checkpoint_path) => {
+                // the checkpoint remembers what the scan was started with, so the same scan can be set up again
+                let (checkpointer, arguments) = Checkpointer::resume(PathBuf::from(checkpoint_path));
+                let arguments: Vec<OsString> =
+                    once("armada".to_string()).chain(arguments).map(OsString::from).map(expand_output_all_flag).collect();
+                let matches = app_config().get_matches_from(&arguments);
+
+                ArmadaCommand::Scan(Box::new(get_armada_config(merge_toml_config(matches, arguments), Some(checkpointer))))
+            }
+            None => ArmadaCommand::Scan(Box::new(get_armada_config(merge_toml_config(matches, arguments), None))),
+        },
+    }
+}
+
+fn get_diff_options(matches: &ArgMatches) -> DiffOptions {
+    DiffOptions {
+        old_results: PathBuf::from(matches.value_of("old_results").expect("old results are required")),
+        new_results: PathBuf::from(matches.value_of("new_results").expect("new results are required")),
+        format: match matches.value_of("output") {
+            Some("json") => DiffFormat::Json,
+            _ => DiffFormat::Default,
+        },
+    }
+}
+
+fn get_analyze_config(matches: &ArgMatches) -> AnalyzeConfig {
+    let scan_type = matches.value_of("scan_type").map(|scan_type| match scan_type {
+        "fin" => ScanType::Fin,
+        "null" => ScanType::Null,
+        "xmas" => ScanType::Xmas,
+        "ack" => ScanType::Ack,
+        "window" => ScanType::Window,
+        "sctp" => ScanType::SctpInit,
+        _ => ScanType::Syn,
+    });
+
+    AnalyzeConfig {
+        capture: PathBuf::from(matches.value_of("capture").expect("a capture is required")),
+        options: AnalyzeOptions {
+            scan_type,
+            collect_fingerprints: matches.is_present("os_guess"),
+            report_all_states: matches.is_present("report_all_states"),
+        },
+        output_format: get_output_format(matches),
+        output_file: matches.value_of("output_file").map(PathBuf::from),
+    }
+}
+
+fn get_serve_config(matches: &ArgMatches) -> ServeConfig {
+    let mut invalid_entries = InvalidEntries::new(false);
+    let listen = invalid_entries.flag(get_listen_address(matches, DEFAULT_SERVE_ADDRESS).map(Some));
+    let grpc_listen = invalid_entries.flag(
+        matches
+            .value_of("grpc_listen")
+            .map(|grpc_listen| {
+                SocketAddr::from_str(grpc_listen).map_err(|_| {
+                    format!("Unable to parse gRPC listen address '{}'. Expected an ip:port, e.g. 127.0.0.1:50051.", grpc_listen)
+                })
+            })
+            .transpose(),
+    );
+    let listening_port = invalid_entries.flag(get_job_listening_port(matches));
+    let source_ips = invalid_entries.flag(get_job_source_ips(matches));
+    invalid_entries.finish();
+
+    ServeConfig {
+        listen: listen.expect("invalid flags exit before this"),
+        grpc_listen,
+        listening_port,
+        source_ips,
+        #[cfg(unix)]
+        run_as: get_run_as(matches),
+    }
+}
+
+fn get_agent_config(matches: &ArgMatches) -> AgentConfig {
+    let mut invalid_entries = InvalidEntries::new(false);
+    let listening_port = invalid_entries.flag(get_job_listening_port(matches));
+    let source_ips = invalid_entries.flag(get_job_source_ips(matches));
+    invalid_entries.finish();
+
+    AgentConfig {
+        controller: matches.value_of("controller").expect("a controller is required").to_string(),
+        listening_port,
+        source_ips,
+        #[cfg(unix)]
+        run_as: get_run_as(matches),
+    }
+}
+
+fn get_controller_config(matches: &ArgMatches) -> ControllerConfig {
+    let mut invalid_entries = InvalidEntries::new(false);
+    let listen = invalid_entries.flag(get_listen_address(matches, DEFAULT_CONTROLLER_ADDRESS).map(Some));
+    let shards = invalid_entries.flag(matches.value_of("shards").map_or(Ok(DEFAULT_SHARDS), |value| {
+        value
+            .parse::<usize>()
+            .ok()
+            .filter(|shards| *shards > 0)
+            .ok_or_else(|| format!("Unable to parse shard count '{}'. Expected a positive number.", value))
+    }));
+    invalid_entries.finish();
+    let listen = listen.expect("invalid flags exit before this");
+
+    // the scan being split up is set up from the same arguments it would be run with
+    let arguments = matches.values_of_os("scan_arguments").into_iter().flatten().map(OsString::from);
+    let arguments: Vec<OsString> = once(OsString::from("armada")).chain(arguments).map(expand_output_all_flag).collect();
+    let scan_matches = app_config().get_matches_from(&arguments);
+
+    ControllerConfig { listen, shards, scan: Box::new(get_armada_config(merge_toml_config(scan_matches, arguments), None)) }
+}
+
+/// `--listen` of `serve` and `controller`
+fn get_listen_address(matches: &ArgMatches, default: &str) -> Result<SocketAddr, String> {
+    let listen = matches.value_of("listen").unwrap_or(default);
+    SocketAddr::from_str(listen)
+        .map_err(|_| format!("Unable to parse listen address '{}'. Expected an ip:port, e.g. {}.", listen, default))
+}
+
+/// The listening port of `serve` and `agent`, which run jobs on a single worker
+fn get_job_listening_port(matches: &ArgMatches) -> Result<u16, String> {
+    match matches.value_of("listening_port") {
+        Some(value) => value.parse().map_err(|_| format!("Unable to parse listening port value '{}'.", value)),
+        None => Ok(rand::thread_rng().gen_range(50_000..60_000)),
+    }
+}
+
+fn get_job_source_ips(matches: &ArgMatches) -> Result<Option<Vec<IpAddr>>, String> {
+    matches
+        .values_of("source_ip")
+        .map(|values| {
+            values
+                .map(|value| IpAddr::from_str(value).map_err(|_| format!("Unable to parse source ip '{}'.", value)))
+                .collect()
+        })
+        .transpose()
+}
+
+/// Lays the settings in `--toml-config` underneath the command line they were given with. A flag set on the command line
+/// or through its environment variable wins over the same setting in the file, and so does a flag it can't be used
+/// together with, so `--top 100` replaces the file's `ports`.
+fn merge_toml_config(matches: ArgMatches, arguments: Vec<OsString>) -> ArgMatches {
+    let Some(toml_path) = matches.value_of("toml_config") else {
+        return matches;
+    };
+
+    let command = app_config();
+    let given: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| {
+            matches!(matches.value_source(arg.get_id()), Some(ValueSource::CommandLine | ValueSource::EnvVariable))
+        })
+        .collect();
+    let conflict = |arg: &Arg, other: &Arg| command.get_arg_conflicts_with(arg).iter().any(|conflict| conflict.get_id() == other.get_id());
+    let overridden = |key: &str| {
+        command.get_arguments().find(|arg| arg.get_long() == Some(key)).is_some_and(|arg| {
+            given
+                .iter()
+                .any(|given_arg| given_arg.get_id() == arg.get_id() || conflict(arg, given_arg) || conflict(given_arg, arg))
+        })
+    };
+
+    let toml_arguments: Vec<OsString> = get_toml_config(toml_path.to_string(), matches.value_of("profile"))
+        .into_iter()
+        .filter(|(key, _)| !overridden(key))
+        .flat_map(|(_, flag)| flag)
+        .map(OsString::from)
+        .collect();
+
+    // the file's settings go before the command line's, right after the program name
+    let mut arguments = arguments.into_iter();
+    let merged_arguments: Vec<OsString> = arguments.next().into_iter().chain(toml_arguments).chain(arguments).collect();
+
+    app_config().get_matches_from(merged_arguments)
+}
+
+fn get_armada_config(matches: ArgMatches, resumed_checkpointer: Option<Checkpointer>) -> ArmadaConfig {
+    let randomize = matches.is_present("randomize");
+    let ipv6_strategy = get_ipv6_strategy(&matches);
+    let shuffled = randomize || ipv6_strategy == Some(Ipv6Strategy::Sample);
+    let skip_invalid = matches.is_present("skip_invalid");
+    let mut invalid_entries = InvalidEntries::new(skip_invalid);
+    let seed = invalid_entries
+        .flag(get_seed(&matches))
+        .or_else(|| (shuffled && matches.is_present("checkpoint")).then(rand::random));
+
+    let checkpointer = resumed_checkpointer.or_else(|| {
+        matches.value_of("checkpoint").map(|checkpoint_path| {
+            let mut arguments: Vec<String> =
+                std::env::args_os().skip(1).map(|arg| arg.to_string_lossy().into_owned()).collect();
+            // a randomized scan can only pick up where it stopped if it's resumed in the same order, as can a scan of
+            // samples drawn from IPv6 ranges if it's drawn the same ones, so the seed picked for them is kept with it
+            if let (Some(seed), false) = (seed, matches.is_present("seed")) {
+                arguments.extend(["--seed".to_string(), seed.to_string()]);
+            }
+
+            Checkpointer::new(PathBuf::from(checkpoint_path), arguments)
+        })
+    });
+
+    let shard = invalid_entries.flag(get_shard(&matches));
+    // a randomized scan has to be shuffled the same way on every machine for their shares not to overlap
+    if let (Some(_), true, false) = (shard, randomize, matches.is_present("seed")) {
+        invalid_entries.reject(
+            "--shard with --randomize needs a --seed, the same one on every machine, so they all probe the pairs in the same order.",
+        );
     }
 
-    let targets = get_targets(&matches);
-    let ports = get_ports(&matches);
+    let (targets, hostname_targets, asn_targets, ipv6_zone) = get_targets(&matches, &mut invalid_entries);
+    let cloud_targets = get_cloud_targets(&matches, &mut invalid_entries);
+    let sample_size = invalid_entries.flag(get_ipv6_sample_size(&matches));
+    let targets = get_ipv6_subset(targets, ipv6_strategy, sample_size, seed);
+    let exclusions = get_exclusions(&matches, &mut invalid_entries);
+    let exclude_urls = matches.values_of("exclude_url").map(|urls| urls.map(str::to_string).collect()).unwrap_or_default();
+    let exclude_url_max_age = invalid_entries.flag(get_exclude_url_max_age(&matches));
+    let scope = get_scope(&matches, &mut invalid_entries);
+    let resolve_timeout = invalid_entries.flag(get_resolve_timeout(&matches));
+    let rdns = matches.is_present("rdns");
+    let ports = get_ports(&matches, &mut invalid_entries);
     let quiet_mode = get_quiet_mode(&matches);
-    let rate_limit = get_rate_limit(&matches);
-    let listening_port = get_listening_port(&matches);
-    let retries = get_retries(&matches);
-    let timeout = get_timeout(&matches);
-    let source_ips = get_source_ip_addresses(&matches);
+    let verbosity = matches.occurrences_of("verbose").min(u8::MAX as u64) as u8;
+    let log_file = invalid_entries.flag(get_log_file(&matches));
+    let progress_format = get_progress_format(&matches);
+    let rate_limit = invalid_entries.flag(get_rate_limit(&matches));
+    let rate_limiter = invalid_entries.flag(get_rate_limiter(&matches, rate_limit));
+    let auto_rate = matches.is_present("auto_rate");
+    let subnet_rate_limit = invalid_entries.flag(get_subnet_rate_limit(&matches));
+    let source_rate_limit = invalid_entries.flag(get_source_rate_limit(&matches));
+    let pacing = invalid_entries.flag(get_pacing(&matches));
+    let scan_window = invalid_entries.flag(get_scan_window(&matches));
+    let max_runtime = invalid_entries.flag(get_max_runtime(&matches));
+    let plan_format = matches.is_present("dry_run").then_some(PlanFormat::Summary);
+    let confirm_above = invalid_entries.flag(get_confirm_above(&matches));
+    let listening_ports = invalid_entries.flag(get_listening_ports(&matches, seed));
+    let core_pinning = invalid_entries.flag(get_core_pinning(&matches));
+    let retries = invalid_entries.flag(get_retries(&matches));
+    let timeout = invalid_entries.flag(get_timeout(&matches));
+    let adaptive_timeout = invalid_entries.flag(get_adaptive_timeout(&matches));
+    let packet_batch_size = invalid_entries.flag(get_packet_batch_size(&matches));
+    let batched_io = !matches.is_present("no_batched_io");
+    let engine = invalid_entries.flag(get_engine(&matches));
+    let interface = get_interface(&matches, ipv6_zone.as_deref());
+    let capture = get_capture(&matches);
+    let source_ips = get_source_ip_addresses(&matches, &mut invalid_entries);
     let stream_results = get_stream_results(&matches);
+    let scan_type = get_scan_type(&matches);
+    let ttl = invalid_entries.flag(get_ttl(&matches));
+    let fragment_size = invalid_entries.flag(get_fragment_size(&matches));
+    let decoys = invalid_entries.flag(get_decoys(&matches, seed));
+    let probe_profile = invalid_entries.flag(get_probe_profile(&matches));
+    let discover = matches.is_present("discover");
+    let output_format = get_output_format(&matches);
+    let csv_fields = invalid_entries.flag(get_csv_fields(&matches));
+    let csv_header = matches.is_present("csv_header");
+    let sink_target = invalid_entries.flag(get_sink_target(&matches));
+    let output_file = matches.value_of("output_file").map(PathBuf::from);
+    let output_basename = matches.value_of("output_all").map(PathBuf::from);
+    let webhook_url = matches.value_of("webhook").map(str::to_string);
+    let webhook_batch_size = invalid_entries.flag(get_webhook_batch_size(&matches));
+    let metrics_address = invalid_entries.flag(get_metrics_address(&matches));
+    let control_socket = matches.value_of("control_socket").map(PathBuf::from);
+    let banner_options = invalid_entries.flag(get_banner_options(&matches));
+    let tls_options = matches.is_present("tls_info").then(ConnectionOptions::default);
+    let tls_all_ports = matches.is_present("tls_all_ports");
+    let service_options = matches.is_present("service_detect").then(|| ConnectionOptions {
+        timeout: DEFAULT_SERVICE_TIMEOUT,
+        ..Default::default()
+    });
+    let os_guess = matches.is_present("os_guess");
+    let report_all_states = matches.is_present("report_all_states");
+    let raw_results = matches.is_present("raw_results");
+    let summary_file = matches.value_of("summary_file").map(PathBuf::from);
+    #[cfg(unix)]
+    let run_as = get_run_as(&matches);
+
+    if stream_results && !quiet_mode && atty::is(Stream::Stdout) {
+        invalid_entries.reject("Streaming only enabled when in quiet mode or when piping results out from armada.");
+    }
+
+    if sink_target.is_some() && output_file.is_some() {
+        invalid_entries.reject("--output-file can't be used with database output.");
+    }
 
-    if stream_results {
-        if !quiet_mode && atty::is(Stream::Stdout) {
-            panic!("Streaming only enabled when in quiet mode or when piping results out from armada.");
+    if stream_results && !output_format.supports_streaming() {
+        invalid_entries.reject("Streaming isn't supported with JSON, nmap XML or greppable output.");
+    }
+
+    if skip_invalid && !invalid_entries.is_empty() {
+        // skipping can leave nothing to scan, which is as much a mistake as the entries themselves
+        if targets.size() == 0 && hostname_targets.is_empty() && asn_targets.is_empty() && cloud_targets.is_empty() {
+            invalid_entries.reject("None of the targets are valid.");
+        }
+        if ports.size() == 0 {
+            invalid_entries.reject("None of the ports are valid.");
         }
     }
+    invalid_entries.finish();
 
     ArmadaConfig {
         targets,
+        hostname_targets,
+        asn_targets,
+        cloud_targets,
+        skip_invalid,
+        exclusions,
+        exclude_urls,
+        exclude_url_max_age,
+        scope,
+        resolve_timeout,
+        rdns,
         ports,
         quiet_mode,
+        verbosity,
+        log_file,
+        progress_format,
         rate_limit,
-        listening_port,
+        rate_limiter,
+        auto_rate,
+        subnet_rate_limit,
+        source_rate_limit,
+        pacing,
+        scan_window,
+        max_runtime,
+        plan_format,
+        confirm_above,
+        listening_ports,
+        core_pinning,
+        seed,
+        randomize,
+        shard,
         retries,
         timeout,
+        adaptive_timeout,
+        packet_batch_size,
+        batched_io,
+        engine,
+        interface,
+        ipv6_zone,
+        capture,
         source_ips,
         stream_results,
+        scan_type,
+        ttl,
+        fragment_size,
+        decoys,
+        probe_profile,
+        discover,
+        output_format,
+        csv_fields,
+        csv_header,
+        sink_target,
+        output_file,
+        output_basename,
+        webhook_url,
+        webhook_batch_size,
+        metrics_address,
+        control_socket,
+        checkpointer,
+        banner_options,
+        tls_options,
+        tls_all_ports,
+        service_options,
+        os_guess,
+        report_all_states,
+        raw_results,
+        summary_file,
+        #[cfg(unix)]
+        run_as,
     }
 }
 
-fn get_targets(matches: &ArgMatches) -> HostIterator {
-    let targets: Vec<String> = if let Some(targets_cli) = matches.values_of("targets") {
+/// Splits the targets into the IPs and CIDRs we can scan right away and the hostnames and ASNs that have to be looked
+/// up first. Link-local targets come with the interface they're on as a zone, like `fe80::1%eth0`, which has to be the
+/// same one for all of them.
+fn get_targets(
+    matches: &ArgMatches,
+    invalid_entries: &mut InvalidEntries,
+) -> (HostIterator, Vec<String>, Vec<u32>, Option<String>) {
+    let targets: Vec<(Origin, String)> = if let Some(targets_cli) = matches.values_of("targets") {
         // use targets passed in via cli
-        targets_cli.map(str::to_owned).collect()
+        targets_cli.map(|target| (Origin::Flag("--targets"), target.to_owned())).collect()
     } else if let Some(target_file) = matches.value_of("target_file") {
         // read newline delimited targets from target file
-        read_to_string(target_file)
-            .expect("Unable to open target file")
-            .lines()
-            .map(str::to_owned)
-            .collect()
+        let contents = read_to_string(target_file)
+            .unwrap_or_else(|error| panic!("Unable to open target file '{}': {}", target_file, error));
+
+        numbered_lines(contents.lines().map(str::to_owned), |line| Origin::Line(target_file.to_owned(), line))
+    } else if matches.is_present("targets_cloud") {
+        // the cloud ranges are the targets on their own
+        Vec::new()
     } else {
         // we'll assume that the user is passing newline delimited targets via stdin
-        stdin().lock().lines().filter_map(Result::ok).collect()
+        numbered_lines(stdin().lock().lines().map_while(Result::ok), |line| Origin::Line("stdin".to_string(), line))
     };
 
-    targets
+    let mut hostname_targets = Vec::new();
+    let mut asn_targets = Vec::new();
+    let mut ipv6_zone: Option<String> = None;
+
+    let host_iterator = targets
         .into_iter()
-        .fold(HostIterator::new(), |host_iterator, target_str| {
-            if let Ok(ip_addr) = IpAddr::from_str(&target_str) {
+        .fold(HostIterator::new(), |host_iterator, (origin, target_str)| {
+            if let Some((address, zone)) = target_str.split_once('%') {
+                let range = IpCidr::from_str(address).ok().filter(crate::ipv6::is_link_local);
+                let interface = crate::ipv6::zone_interface(zone);
+
+                match (range, interface, &ipv6_zone) {
+                    (None, _, _) => invalid_entries.record(
+                        origin,
+                        format!("'{}' has a zone, which only link-local IPv6 addresses (fe80::/10) take", target_str),
+                    ),
+                    (_, None, _) => invalid_entries
+                        .record(origin, format!("'{}' is on zone {}, but there's no interface by that name or index", target_str, zone)),
+                    (_, Some(interface), Some(other)) if interface.ne(other) => invalid_entries.record(
+                        origin,
+                        format!("'{}' is on {}, but the other link-local targets are on {}. Please scan each link separately", target_str, interface, other),
+                    ),
+                    (Some(range), Some(interface), _) => {
+                        ipv6_zone = Some(interface);
+                        return host_iterator.add_cidr(range);
+                    }
+                }
+
+                host_iterator
+            } else if IpCidr::from_str(&target_str).is_ok_and(|range| crate::ipv6::is_link_local(&range)) {
+                invalid_entries.record(
+                    origin,
+                    format!("'{}' is link-local, so it needs the interface it's on as a zone, like {}%eth0", target_str, target_str),
+                );
+                host_iterator
+            } else if let Ok(ip_addr) = IpAddr::from_str(&target_str) {
                 host_iterator.add_ip(ip_addr)
+            } else if let Ok(cidr) = IpCidr::from_str(&target_str) {
+                host_iterator.add_cidr(cidr)
+            } else if let Some(cidrs) = parse_octet_ranges(&target_str) {
+                cidrs.into_iter().fold(host_iterator, HostIterator::add_cidr)
+            } else if let Some(asn) = parse_asn(&target_str) {
+                asn_targets.push(asn);
+                host_iterator
+            } else if is_hostname(&target_str) {
+                hostname_targets.push(target_str);
+                host_iterator
             } else {
-                // we'll force this to parse. If it fails, then an illegal value was placed into the target list and we should panic here.
-                let cidr = IpCidr::from_str(&target_str).expect(&format!("Unable to parse target '{}'.", target_str));
+                invalid_entries.record(origin, format!("'{}' isn't an IP, CIDR, range, ASN or hostname", target_str));
+                host_iterator
+            }
+        });
 
-                host_iterator.add_cidr(cidr)
+    (host_iterator, hostname_targets, asn_targets, ipv6_zone)
+}
+
+fn get_cloud_targets(matches: &ArgMatches, invalid_entries: &mut InvalidEntries) -> Vec<CloudSource> {
+    matches
+        .values_of("targets_cloud")
+        .into_iter()
+        .flatten()
+        .filter_map(|source| {
+            let cloud_source = CloudSource::parse(source);
+            if cloud_source.is_none() {
+                invalid_entries.record(
+                    Origin::Flag("--targets-cloud"),
+                    format!("'{}' isn't a cloud source. Expected <provider>[:<region>[:<service>]] with a provider of {}", source, CLOUD_PROVIDERS.join(", ")),
+                );
             }
+
+            cloud_source
         })
+        .collect()
 }
 
-fn get_ports(matches: &ArgMatches) -> PortIterator {
-    use regex::Regex;
+fn get_exclusions(matches: &ArgMatches, invalid_entries: &mut InvalidEntries) -> Vec<IpCidr> {
+    let mut exclusions: Vec<(Origin, String)> = matches
+        .values_of("exclude")
+        .map(|values| values.map(|value| (Origin::Flag("--exclude"), value.to_owned())).collect())
+        .unwrap_or_default();
+
+    if let Some(exclude_file) = matches.value_of("exclude_file") {
+        let contents = read_to_string(exclude_file)
+            .unwrap_or_else(|error| panic!("Unable to open exclude file '{}': {}", exclude_file, error));
+
+        exclusions.extend(numbered_lines(contents.lines().map(str::to_owned), |line| {
+            Origin::Line(exclude_file.to_owned(), line)
+        }));
+    }
+
+    // the reserved ranges take in the private ones
+    let built_in = match (matches.is_present("exclude_reserved"), matches.is_present("exclude_private")) {
+        (true, _) => crate::reserved::reserved_ranges(),
+        (false, true) => crate::reserved::private_ranges(),
+        (false, false) => Vec::new(),
+    };
+
+    exclusions
+        .into_iter()
+        .flat_map(|(origin, exclusion)| match IpCidr::from_str(&exclusion) {
+            Ok(cidr) => vec![cidr],
+            Err(_) => parse_octet_ranges(&exclusion).unwrap_or_else(|| {
+                invalid_entries.record(origin, format!("'{}' isn't an IP, CIDR or range to exclude", exclusion));
+                Vec::new()
+            }),
+        })
+        .chain(built_in)
+        .collect()
+}
+
+/// The ranges in `--scope-file`. Entries that can't be parsed are reported like any other, and with `--skip-invalid`
+/// they only narrow the scope.
+fn get_scope(matches: &ArgMatches, invalid_entries: &mut InvalidEntries) -> Option<Scope> {
+    let file = matches.value_of("scope_file")?;
+    let contents =
+        read_to_string(file).unwrap_or_else(|error| panic!("Unable to open scope file '{}': {}", file, error));
+
+    let ranges: Vec<IpCidr> = numbered_lines(contents.lines().map(str::to_owned), |line| Origin::Line(file.to_owned(), line))
+        .into_iter()
+        // comments are common in scope files handed over with an engagement
+        .filter(|(_, entry)| !entry.starts_with('#'))
+        .flat_map(|(origin, entry)| match IpCidr::from_str(&entry) {
+            Ok(cidr) => vec![cidr],
+            Err(_) => parse_octet_ranges(&entry).unwrap_or_else(|| {
+                invalid_entries.record(origin, format!("'{}' isn't an IP, CIDR or range for the scope", entry));
+                Vec::new()
+            }),
+        })
+        .collect();
+
+    // a scope file skipped down to nothing isn't one to scan within either
+    if ranges.is_empty() && (invalid_entries.is_empty() || matches.is_present("skip_invalid")) {
+        panic!("The scope file '{}' has no ranges in it that can be parsed, so nothing would be in scope.", file);
+    }
+
+    let mode = matches.value_of("scope_mode").map_or(ScopeMode::Refuse, |mode| {
+        ScopeMode::from_name(mode).unwrap_or_else(|| panic!("Unknown scope mode '{}'.", mode))
+    });
+
+    Some(Scope { file: file.to_owned(), ranges, mode })
+}
+
+/// Where a target, port or address came from, for pointing at the ones that can't be parsed
+enum Origin {
+    Flag(&'static str),
+    /// A line of a file or stdin, counting from 1
+    Line(String, usize),
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Flag(flag) => write!(f, "{}", flag),
+            Origin::Line(source, line) => write!(f, "{}:{}", source, line),
+        }
+    }
+}
+
+/// The targets, ports and addresses that couldn't be parsed, gathered up so they're reported all at once rather than one
+/// at a time. With `--skip-invalid` they're left out of the scan instead.
+struct InvalidEntries {
+    skip_invalid: bool,
+    errors: Vec<String>,
+    /// Flags that couldn't be parsed, which `--skip-invalid` can't scan past
+    invalid_flags: Vec<String>,
+}
+
+impl InvalidEntries {
+    /// How many errors are listed before the rest are just counted
+    const MAX_LISTED: usize = 50;
 
-    use crate::ranges::{TOP_100, TOP_1000};
+    fn new(skip_invalid: bool) -> Self {
+        Self { skip_invalid, errors: Vec::new(), invalid_flags: Vec::new() }
+    }
+
+    fn record(&mut self, origin: Origin, message: String) {
+        self.errors.push(format!("{}: {}", origin, message));
+    }
+
+    /// Takes the value a flag parsed to, or notes why it didn't and carries on with the default so that every
+    /// invalid flag gets reported at once
+    fn flag<T: Default>(&mut self, parsed: Result<T, String>) -> T {
+        parsed.unwrap_or_else(|error| {
+            self.reject(error);
+            T::default()
+        })
+    }
+
+    /// Notes flags that can't be used the way they were given, which always stops the scan
+    fn reject(&mut self, message: impl Into<String>) {
+        self.invalid_flags.push(message.into());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Exits with every error if there were any, or warns about the entries left out with `--skip-invalid`
+    fn finish(&self) {
+        if !self.errors.is_empty() {
+            let label = match self.skip_invalid {
+                true => "warning: skipping",
+                false => "error:",
+            };
+            for error in self.errors.iter().take(Self::MAX_LISTED) {
+                eprintln!("{} {}", label, error);
+            }
+            if self.errors.len() > Self::MAX_LISTED {
+                eprintln!("{} and {} more", label, self.errors.len() - Self::MAX_LISTED);
+            }
+            if !self.skip_invalid {
+                eprintln!("{}, pass --skip-invalid to scan the rest anyway.", self.count());
+            }
+        }
+        for error in &self.invalid_flags {
+            eprintln!("error: {}", error);
+        }
+
+        if !self.invalid_flags.is_empty() || (!self.errors.is_empty() && !self.skip_invalid) {
+            std::process::exit(crate::exit::INVALID_ARGUMENTS);
+        }
+    }
+
+    fn count(&self) -> String {
+        match self.errors.len() {
+            1 => "1 invalid entry".to_string(),
+            count => format!("{} invalid entries", count),
+        }
+    }
+}
+
+/// Pairs up the lines that aren't blank with where they came from, counting lines from 1
+fn numbered_lines(lines: impl Iterator<Item = String>, origin: impl Fn(usize) -> Origin) -> Vec<(Origin, String)> {
+    lines
+        .enumerate()
+        .map(|(index, line)| (origin(index + 1), line.trim().to_owned()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect()
+}
+
+/// Translates an nmap style IPv4 range, where any octet may be a range (e.g. `10.0.0.1-50` or `10.0.0-3.1-254`), into
+/// the CIDRs covering it. Octets after the last partial one are whole, so they fold into each CIDR's prefix length
+/// instead of multiplying the number of CIDRs. Returns `None` if the target isn't such a range.
+pub(crate) fn parse_octet_ranges(target: &str) -> Option<Vec<IpCidr>> {
+    if !target.contains('-') {
+        return None;
+    }
+
+    let octet_ranges: Vec<(u32, u32)> = target.split('.').map(parse_octet_range).collect::<Option<_>>()?;
+
+    if octet_ranges.len() != 4 {
+        return None;
+    }
+
+    let last_partial_octet = octet_ranges
+        .iter()
+        .rposition(|&octet_range| octet_range != (0, 255))
+        .unwrap_or(0);
+    let whole_octet_bits = 8 * (3 - last_partial_octet) as u32;
 
+    let prefixes = octet_ranges[..last_partial_octet]
+        .iter()
+        .fold(vec![0u32], |prefixes, &(start, end)| {
+            prefixes
+                .iter()
+                .flat_map(|prefix| (start..=end).map(move |octet| (prefix << 8) | octet))
+                .collect()
+        });
+
+    let (start, end) = octet_ranges[last_partial_octet];
+    let blocks = octet_blocks(start, end);
+
+    Some(
+        prefixes
+            .iter()
+            .flat_map(|prefix| {
+                blocks.iter().map(move |&(block_start, block_bits)| {
+                    let network = ((prefix << 8) | block_start) << whole_octet_bits;
+                    let bits = 8 * last_partial_octet as u8 + block_bits;
+
+                    IpCidr::V4(Ipv4Cidr::from_prefix_and_bits(network, bits).expect("Failed to convert to IPv4 CIDR"))
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Parses a single octet (`10`) or an inclusive range of them (`1-254`)
+fn parse_octet_range(octet: &str) -> Option<(u32, u32)> {
+    let (start, end) = octet.split_once('-').unwrap_or((octet, octet));
+    let (start, end) = (start.parse::<u8>().ok()?, end.parse::<u8>().ok()?);
+
+    (start <= end).then_some((start as u32, end as u32))
+}
+
+/// Splits an inclusive range of octet values into the fewest aligned blocks, returned as their first value and how
+/// many of the octet's 8 bits they fix
+fn octet_blocks(mut start: u32, end: u32) -> Vec<(u32, u8)> {
+    let mut blocks = Vec::new();
+
+    while start <= end {
+        let mut block_size = if start == 0 { 256 } else { 1 << start.trailing_zeros() };
+        while start + block_size - 1 > end {
+            block_size /= 2;
+        }
+
+        blocks.push((start, 8 - block_size.trailing_zeros() as u8));
+        start += block_size;
+    }
+
+    blocks
+}
+
+/// Whether the target is made up of valid DNS labels, e.g. `example.com` or `localhost`. The last label has to hold a
+/// letter, so malformed IPs and ranges aren't mistaken for hostnames.
+fn is_hostname(target: &str) -> bool {
+    let target = target.strip_suffix('.').unwrap_or(target);
+
+    !target.is_empty()
+        && target.len() <= 253
+        && target
+            .rsplit('.')
+            .next()
+            .is_some_and(|last_label| last_label.chars().any(|c| c.is_ascii_alphabetic()))
+        && target.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn get_ports(matches: &ArgMatches, invalid_entries: &mut InvalidEntries) -> PortIterator {
     let user_port_string = matches.values_of("ports");
-    let top_100_flag = matches.is_present("top100");
-    let top_1000_flag = matches.is_present("top1000");
+    let port_file = matches.value_of("port_file");
+    let top_ports_count = invalid_entries.flag(get_top_ports_count(matches));
 
-    let port_strings: Vec<String> = match (user_port_string, top_100_flag, top_1000_flag) {
-        (Some(values), ..) => values.map(|value| value.to_string()).collect(),
-        (_, true, _) => TOP_100.split(",").map(|def| def.to_string()).collect(),
-        (_, _, true) => TOP_1000.split(",").map(|def| def.to_string()).collect(),
+    let ports = match (user_port_string, port_file, top_ports_count) {
+        (Some(values), ..) => parse_port_specs(values.map(|value| (Origin::Flag("--ports"), value.to_owned())), invalid_entries),
+        (_, Some(port_file), _) => parse_port_specs(read_port_file(port_file).into_iter(), invalid_entries),
+        (_, _, Some(top_ports_count)) => {
+            use crate::ranges::TOP_PORTS;
+
+            TOP_PORTS[..top_ports_count]
+                .iter()
+                .fold(PortIterator::new(), |port_iterator, &port| port_iterator.add_port(port))
+        }
         _ => panic!("Ports are required to be supplied for armada to run."),
     };
 
-    let port_regex = Regex::new(r"^(\d+)(?:-(\d+))?$").unwrap();
+    match matches.values_of("exclude_ports") {
+        Some(values) => values.fold(ports, |ports, port_str| match parse_port_range(port_str) {
+            Ok((start_port, end_port)) => ports.exclude_range(start_port, end_port),
+            Err(error) => {
+                invalid_entries.record(Origin::Flag("--exclude-ports"), error);
+                ports
+            }
+        }),
+        None => ports,
+    }
+}
+
+fn parse_port_specs(port_strings: impl Iterator<Item = (Origin, String)>, invalid_entries: &mut InvalidEntries) -> PortIterator {
+    port_strings.fold(PortIterator::new(), |port_iterator, (origin, port_str)| match parse_port_range(&port_str) {
+        Ok((start_port, end_port)) if start_port == end_port => port_iterator.add_port(start_port),
+        Ok((start_port, end_port)) => port_iterator.add_range(start_port, end_port),
+        Err(error) => {
+            invalid_entries.record(origin, error);
+            port_iterator
+        }
+    })
+}
+
+/// How many of the most common ports to scan, if the ports were picked with `--top`, `--top100` or `--top1000`
+fn get_top_ports_count(matches: &ArgMatches) -> Result<Option<usize>, String> {
+    use crate::ranges::TOP_PORTS;
+
+    if matches.is_present("top100") {
+        return Ok(Some(100));
+    }
+
+    if matches.is_present("top1000") {
+        return Ok(Some(1000));
+    }
+
+    matches
+        .value_of("top")
+        .map(|value| match value.parse::<usize>() {
+            Ok(count) if (1..=TOP_PORTS.len()).contains(&count) => Ok(count),
+            _ => Err(format!("Unable to parse top ports value '{}'. It must be between 1 and {}.", value, TOP_PORTS.len())),
+        })
+        .transpose()
+}
+
+/// Reads newline or comma delimited port specs from a file. Anything after a `#` is a comment.
+fn read_port_file(port_file: &str) -> Vec<(Origin, String)> {
+    read_to_string(port_file)
+        .unwrap_or_else(|error| panic!("Unable to open port file '{}': {}", port_file, error))
+        .lines()
+        .enumerate()
+        .flat_map(|(index, line)| {
+            line.split('#')
+                .next()
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|port_str| !port_str.is_empty())
+                .map(move |port_str| (Origin::Line(port_file.to_owned(), index + 1), port_str.to_owned()))
+        })
+        .collect()
+}
+
+/// Parses a single port (`80`) or an inclusive range of ports (`8000-8100`). Either end of a range may be left off to
+/// run it to the first or last port (`1000-`, `-1024`), so `-` on its own covers every port. Anything else is taken to
+/// be the name of a service (`ssh`).
+pub(crate) fn parse_port_range(port_str: &str) -> Result<(u16, u16), String> {
+    use regex::Regex;
+
+    let port_regex = Regex::new(r"^(\d*)(-)?(\d*)$").unwrap();
+
+    let Some(capture) = port_regex.captures(port_str) else {
+        let port = service_port(port_str).ok_or_else(|| format!("'{}' isn't a port, port range or service name", port_str))?;
+
+        return Ok((port, port));
+    };
+
+    let parse_port = |port_str: &str| {
+        port_str
+            .parse::<u16>()
+            .ok()
+            .filter(|&port| port != 0)
+            .ok_or_else(|| format!("'{}' isn't a port between 1 and {}", port_str, u16::MAX))
+    };
+
+    let start_port = capture.get(1).map(|m| m.as_str()).filter(|port_str| !port_str.is_empty()).map(parse_port).transpose()?;
+    let end_port = capture.get(3).map(|m| m.as_str()).filter(|port_str| !port_str.is_empty()).map(parse_port).transpose()?;
+
+    match (start_port, capture.get(2).is_some(), end_port) {
+        (Some(port), false, None) => Ok((port, port)),
+        (Some(start_port), true, Some(end_port)) if start_port > end_port => {
+            Err(format!("'{}' runs backwards, the first port has to come before the last", port_str))
+        }
+        (start_port, true, end_port) => Ok((start_port.unwrap_or(1), end_port.unwrap_or(u16::MAX))),
+        _ => Err(format!("'{}' isn't a port, port range or service name", port_str)),
+    }
+}
+
+fn get_quiet_mode(matches: &ArgMatches) -> bool {
+    matches.is_present("quiet")
+}
+
+fn get_progress_format(matches: &ArgMatches) -> ProgressFormat {
+    if matches.is_present("tui") && !atty::is(Stream::Stderr) {
+        panic!("--tui needs a terminal to draw the dashboard on.");
+    }
+
+    match (matches.is_present("tui"), matches.value_of("progress")) {
+        (true, _) => ProgressFormat::Dashboard,
+        (false, Some("json")) => ProgressFormat::Json,
+        _ => ProgressFormat::Bars,
+    }
+}
+
+fn get_rate_limit(matches: &ArgMatches) -> Result<Option<usize>, String> {
+    let rate_limit = matches
+        .value_of("rate_limit")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("Unable to parse rate limit '{}'. Expected a non-negative number of packets per second.", value))
+        })
+        .transpose()?;
+
+    Ok(match rate_limit {
+        _ if matches.is_present("sanic") => None,
+        Some(0) => None,
+        Some(rate) if rate < 10 => Some(10),
+        Some(rate) => Some(rate),
+        None => Some(DEFAULT_RATE_LIMIT),
+    })
+}
+
+/// `--burst` implies the token bucket, which otherwise saves up a tenth of a second's worth of the rate limit, the same
+/// burst the fixed window lets out at once
+fn get_rate_limiter(matches: &ArgMatches, rate_limit: Option<usize>) -> Result<RateLimitStrategy, String> {
+    let burst = matches
+        .value_of("burst")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|burst| *burst > 0)
+                .ok_or_else(|| format!("Unable to parse burst '{}'. Expected a number of packets above 0.", value))
+        })
+        .transpose()?;
+
+    match (matches.value_of("rate_limiter"), burst) {
+        (Some("fixed-window"), Some(_)) => Err("--burst only applies to --rate-limiter token-bucket.".to_string()),
+        (Some("token-bucket"), _) | (None, Some(_)) => Ok(RateLimitStrategy::TokenBucket {
+            burst: burst.unwrap_or_else(|| rate_limit.map_or(1, |rate_limit| (rate_limit / 10).max(1))),
+        }),
+        _ => Ok(RateLimitStrategy::FixedWindow),
+    }
+}
+
+/// Parses a single port, or a range of them as `<start>-<end>`, e.g. `50000-50015`. `--threads` stretches a single
+/// port into a range of that many ports.
+fn get_listening_ports(matches: &ArgMatches, seed: Option<u64>) -> Result<Vec<u16>, String> {
+    let threads = matches
+        .value_of("threads")
+        .map(|value| {
+            value
+                .parse::<u16>()
+                .ok()
+                .filter(|threads| (1..=MAX_LISTENING_PORTS).contains(threads))
+                .ok_or_else(|| format!("Unable to parse thread count '{}'. Expected 1 to {}.", value, MAX_LISTENING_PORTS))
+        })
+        .transpose()?;
+
+    let listening_ports: Vec<u16> = match matches.value_of("listening_port") {
+        Some(value) => {
+            let parse_port = |port: &str| {
+                port.parse::<u16>()
+                    .map_err(|_| format!("Unable to parse listening port value '{}'.", value))
+            };
+
+            let (start, end) = match value.split_once('-') {
+                Some((start, end)) => (parse_port(start)?, parse_port(end)?),
+                None => (parse_port(value)?, parse_port(value)?),
+            };
+
+            if start > end {
+                return Err(format!("Listening port range '{}' ends before it starts.", value));
+            }
+
+            if end - start >= MAX_LISTENING_PORTS {
+                return Err(format!("Listening port range '{}' has more than {} ports.", value, MAX_LISTENING_PORTS));
+            }
+
+            (start..=end).collect()
+        }
+        None => {
+            let listening_port = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed).gen_range(50_000..60_000),
+                None => rand::thread_rng().gen_range(50_000..60_000),
+            };
+
+            vec![listening_port]
+        }
+    };
+
+    match threads {
+        Some(threads) if listening_ports.len() == 1 => {
+            let start = listening_ports[0];
+            let end = start
+                .checked_add(threads - 1)
+                .ok_or_else(|| format!("Listening port {} leaves no room for {} threads.", start, threads))?;
+
+            Ok((start..=end).collect())
+        }
+        Some(threads) if listening_ports.len() != threads as usize => Err(format!(
+            "--threads {} doesn't match the {} ports given to --listening-port.",
+            threads,
+            listening_ports.len()
+        )),
+        _ => Ok(listening_ports),
+    }
+}
+
+/// Parses `auto`, or a list of cores, e.g. `0-3,8`
+fn get_core_pinning(matches: &ArgMatches) -> Result<Option<CorePinning>, String> {
+    matches
+        .value_of("pin_cores")
+        .map(|value| match value {
+            "auto" => Ok(CorePinning::Auto),
+            cores => parse_cpu_list(cores)
+                .map(CorePinning::Cores)
+                .ok_or_else(|| format!("Unable to parse core list '{}'. Expected e.g. 0-3,8 or auto.", value)),
+        })
+        .transpose()
+}
+
+/// Parses `<prefix>:<packets per second>`, e.g. `24:100`
+fn get_subnet_rate_limit(matches: &ArgMatches) -> Result<Option<SubnetRateLimit>, String> {
+    matches
+        .value_of("max_rate_per_net")
+        .map(|value| {
+            let parsed = value.split_once(':').and_then(|(prefix, packets_per_second)| {
+                let prefix = prefix.trim_start_matches('/').parse::<u8>().ok().filter(|prefix| (1..=32).contains(prefix))?;
+                let packets_per_second = packets_per_second.parse::<usize>().ok().filter(|pps| *pps > 0)?;
+
+                Some(SubnetRateLimit { ipv4_prefix: prefix, ipv6_prefix: SUBNET_RATE_LIMIT_IPV6_PREFIX, packets_per_second })
+            });
+
+            parsed.ok_or_else(|| format!("Unable to parse per network rate limit '{}'. Expected <prefix>:<packets per second>, e.g. 24:100.", value))
+        })
+        .transpose()
+}
+
+/// `--delay` and `--jitter` in milliseconds, either of which paces the scan
+fn get_pacing(matches: &ArgMatches) -> Result<Option<Pacing>, String> {
+    let milliseconds = |arg: &str| {
+        matches
+            .value_of(arg)
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .map(Duration::from_millis)
+                    .map_err(|_| format!("Unable to parse {} value '{}'. Expected a number of milliseconds.", arg, value))
+            })
+            .transpose()
+    };
+
+    let (delay, jitter) = (milliseconds("delay")?, milliseconds("jitter")?);
+    if delay.is_none() && jitter.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(Pacing {
+        delay: delay.unwrap_or_default(),
+        jitter: jitter.unwrap_or_default(),
+        per_destination: matches.is_present("pace_per_host"),
+    }))
+}
+
+fn get_scan_window(matches: &ArgMatches) -> Result<Option<ScanWindow>, String> {
+    matches
+        .value_of("scan_window")
+        .map(|value| {
+            ScanWindow::from_str(value).map_err(|err| {
+                format!("Unable to parse scan window '{}': {}. Expected e.g. 22:00-06:00 or 22:00-06:00 +02:00.", value, err)
+            })
+        })
+        .transpose()
+}
+
+/// The file to log to, which is only rotated with `--log-max-size`, keeping `--log-max-files` of the old ones
+fn get_log_file(matches: &ArgMatches) -> Result<Option<LogFile>, String> {
+    let Some(path) = matches.value_of("log_file").map(PathBuf::from) else {
+        return Ok(None);
+    };
+
+    let max_size = matches
+        .value_of("log_max_size")
+        .map(|value| {
+            let (number, unit_bytes) = match value.char_indices().last() {
+                Some((index, 'K' | 'k')) => (&value[..index], 1 << 10),
+                Some((index, 'M' | 'm')) => (&value[..index], 1 << 20),
+                Some((index, 'G' | 'g')) => (&value[..index], 1 << 30),
+                _ => (value, 1),
+            };
+
+            number
+                .parse::<u64>()
+                .ok()
+                .and_then(|number| number.checked_mul(unit_bytes))
+                .filter(|bytes| *bytes > 0)
+                .ok_or_else(|| format!("Unable to parse log file size '{}'. Expected e.g. 500K, 100M or 1G.", value))
+        })
+        .transpose()?;
+
+    let max_files = match matches.value_of("log_max_files") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| format!("Unable to parse log file count '{}'. Expected a non-negative number.", value))?,
+        None => DEFAULT_LOG_MAX_FILES,
+    };
+
+    Ok(Some(LogFile { path, max_size, max_files }))
+}
+
+/// A number of seconds, or of minutes, hours or days with an `m`, `h` or `d` after it, e.g. `90m`
+fn get_max_runtime(matches: &ArgMatches) -> Result<Option<Duration>, String> {
+    matches
+        .value_of("max_runtime")
+        .map(|value| {
+            parse_duration(value)
+                .ok_or_else(|| format!("Unable to parse max runtime '{}'. Expected e.g. 90s, 30m, 2h or 1d.", value))
+        })
+        .transpose()
+}
+
+fn get_exclude_url_max_age(matches: &ArgMatches) -> Result<Duration, String> {
+    matches.value_of("exclude_url_max_age").map_or(Ok(DEFAULT_EXCLUDE_URL_MAX_AGE), |value| {
+        parse_duration(value).ok_or_else(|| format!("Unable to parse max age '{}'. Expected e.g. 90s, 30m, 2h or 1d.", value))
+    })
+}
+
+/// Parses a positive duration with a unit of `s`, `m`, `h` or `d`, or in seconds without one
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, unit_seconds) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], 1),
+        Some((index, 'm')) => (&value[..index], 60),
+        Some((index, 'h')) => (&value[..index], 60 * 60),
+        Some((index, 'd')) => (&value[..index], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit_seconds))
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs)
+}
+
+fn get_source_rate_limit(matches: &ArgMatches) -> Result<Option<usize>, String> {
+    matches
+        .value_of("max_rate_per_source")
+        .map(|value| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|packets_per_second| *packets_per_second > 0)
+                .ok_or_else(|| format!("Unable to parse per source rate limit '{}'. Expected a positive number of packets per second.", value))
+        })
+        .transpose()
+}
+
+fn get_ipv6_sample_size(matches: &ArgMatches) -> Result<usize, String> {
+    matches.value_of("sample").map_or(Ok(DEFAULT_IPV6_SAMPLE_SIZE), |value| match value.parse() {
+        Ok(sample_size) if sample_size > 0 => Ok(sample_size),
+        _ => Err(format!("Unable to parse sample size '{}'. Expected a positive number of addresses.", value)),
+    })
+}
+
+/// `--sample` on its own samples IPv6 ranges too large to scan whole
+fn get_ipv6_strategy(matches: &ArgMatches) -> Option<Ipv6Strategy> {
+    match matches.value_of("v6_strategy") {
+        Some(name) => Some(Ipv6Strategy::from_name(name).expect("clap only allows the known strategies")),
+        None => matches.is_present("sample").then_some(Ipv6Strategy::Sample),
+    }
+}
+
+/// Refuses IPv6 ranges too large to scan every address of unless a strategy for them was picked, and swaps them for
+/// the subset it picks otherwise
+fn get_ipv6_subset(
+    targets: HostIterator,
+    ipv6_strategy: Option<Ipv6Strategy>,
+    sample_size: usize,
+    seed: Option<u64>,
+) -> HostIterator {
+    match (ipv6_strategy, crate::ipv6::oversized_range(&targets)) {
+        (_, None) => targets,
+        (None, Some(range)) => panic!(
+            "{} holds 2^{} addresses, too many to scan every one of them. Please pick --v6-strategy low-addrs or sample to scan a subset of it, \
+            or --v6-strategy list to scan it whole anyway.",
+            range,
+            128 - range.get_bits(),
+        ),
+        (Some(Ipv6Strategy::List), Some(range)) if range.get_bits() == 0 => {
+            panic!("{} can't be scanned whole. Please pick --v6-strategy low-addrs or sample to scan a subset of it.", range)
+        }
+        (Some(Ipv6Strategy::List), Some(_)) => targets,
+        (Some(ipv6_strategy), Some(_)) => crate::ipv6::apply_strategy(targets, ipv6_strategy, sample_size, seed),
+    }
+}
+
+/// `--interface`, or else the zone of the link-local targets, since they can only be reached through it
+fn get_interface(matches: &ArgMatches, ipv6_zone: Option<&str>) -> Option<String> {
+    match (matches.value_of("interface"), ipv6_zone) {
+        (Some(interface), Some(zone)) if interface.ne(zone) => panic!(
+            "The link-local targets are on {}, which can't be reached from --interface {}. Please scan them separately.",
+            zone, interface
+        ),
+        (interface, zone) => interface.or(zone).map(str::to_string),
+    }
+}
+
+/// `--run-as`, or else the user who ran armada through sudo. `--run-as root` keeps running as root.
+#[cfg(unix)]
+fn get_run_as(matches: &ArgMatches) -> Option<RunAs> {
+    match matches.value_of("run_as") {
+        Some("root") => None,
+        Some(_) if !privileges::is_root() => panic!("--run-as only works when armada is started as root."),
+        Some(user) => Some(RunAs::lookup(user).unwrap_or_else(|| panic!("There's no user named {}.", user))),
+        None => privileges::is_root().then(RunAs::sudo_user).flatten(),
+    }
+}
+
+/// `--confirm-above`, or none at all with `--yes` or a threshold of 0
+fn get_confirm_above(matches: &ArgMatches) -> Result<Option<u128>, String> {
+    if matches.is_present("yes") {
+        return Ok(None);
+    }
+
+    let threshold = match matches.value_of("confirm_above") {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("Unable to parse probe count '{}'. Expected a number of probes, or 0 to never ask.", value))?,
+        None => DEFAULT_CONFIRM_ABOVE,
+    };
+
+    Ok((threshold > 0).then_some(threshold))
+}
+
+fn get_seed(matches: &ArgMatches) -> Result<Option<u64>, String> {
+    matches
+        .value_of("seed")
+        .map(|value| value.parse::<u64>().map_err(|_| format!("Unable to parse seed value '{}'.", value)))
+        .transpose()
+}
+
+/// Parses `<index>/<count>`, e.g. `3/8`, with the index counting from 0
+fn get_shard(matches: &ArgMatches) -> Result<Option<ScanShard>, String> {
+    matches
+        .value_of("shard")
+        .map(|value| {
+            value
+                .split_once('/')
+                .and_then(|(index, count)| Some(ScanShard { index: index.parse().ok()?, count: count.parse().ok()? }))
+                .filter(|shard| shard.index < shard.count)
+                .ok_or_else(|| format!("Unable to parse shard '{}'. Expected <index>/<count> with the index counting from 0, e.g. 3/8.", value))
+        })
+        .transpose()
+}
+
+fn get_retries(matches: &ArgMatches) -> Result<u8, String> {
+    let retries = matches
+        .value_of("retries")
+        .map(|value| value.parse::<u8>().map_err(|_| format!("Unable to parse port retry value '{}'.", value)))
+        .transpose()?;
+
+    Ok(retries.or(matches.is_present("sanic").then_some(0)).unwrap_or(DEFAULT_PORT_RETRY))
+}
+
+fn get_adaptive_timeout(matches: &ArgMatches) -> Result<Option<AdaptiveTimeout>, String> {
+    if !matches.is_present("adaptive_timeout") {
+        return Ok(None);
+    }
+
+    let bound = |name: &str| {
+        matches
+            .value_of(name)
+            .map(|value| {
+                value
+                    .parse::<u64>()
+                    .map(Duration::from_millis)
+                    .map_err(|_| format!("Unable to parse {} value '{}'.", name.replace('_', " "), value))
+            })
+            .transpose()
+    };
+
+    let defaults = AdaptiveTimeout::default();
+    let adaptive_timeout = AdaptiveTimeout {
+        min_timeout: bound("min_timeout")?.unwrap_or(defaults.min_timeout),
+        max_timeout: bound("max_timeout")?.unwrap_or(defaults.max_timeout),
+    };
+
+    if adaptive_timeout.min_timeout > adaptive_timeout.max_timeout {
+        return Err("--min-timeout can't be longer than --max-timeout.".to_string());
+    }
+
+    Ok(Some(adaptive_timeout))
+}
+
+fn get_packet_batch_size(matches: &ArgMatches) -> Result<usize, String> {
+    matches.value_of("batch_size").map_or(Ok(DEFAULT_PACKET_BATCH_SIZE), |value| match value.parse::<usize>() {
+        Ok(batch_size) if (1..=MAX_PACKET_BATCH_SIZE).contains(&batch_size) => Ok(batch_size),
+        _ => Err(format!("Unable to parse packet batch size '{}'. Expected a number from 1 to {}.", value, MAX_PACKET_BATCH_SIZE)),
+    })
+}
+
+/// `--vlan`, `--router-mac` and `--target-mac` imply the xdp engine, the only one writing its own Ethernet frames, other
+/// than on Windows where every scan goes through npcap
+fn get_engine(matches: &ArgMatches) -> Result<Engine, String> {
+    let vlan = get_vlan(matches)?;
+    let router_mac = get_mac_address(matches, "router_mac")?;
+    let target_mac = get_mac_address(matches, "target_mac")?;
+    let framing = vlan.is_some() || router_mac.is_some() || target_mac.is_some();
+    let interface = |engine: &str| {
+        matches
+            .value_of("interface")
+            .map(str::to_string)
+            .ok_or_else(|| format!("--engine {} requires --interface.", engine))
+    };
+
+    Ok(match (matches.value_of("engine"), framing) {
+        // Windows raw sockets don't send TCP at all, so scans there always go through Npcap unless asked otherwise
+        (None, _) if cfg!(windows) => Engine::Npcap { interface: get_bpf_interface(matches), vlan, router_mac, target_mac },
+        (Some("xdp"), _) | (None, true) => Engine::Xdp { interface: interface("xdp")?, vlan, router_mac, target_mac },
+        (Some("npcap"), _) => Engine::Npcap { interface: interface("npcap")?, vlan, router_mac, target_mac },
+        (Some("bpf"), _) if vlan.is_some() => return Err("--vlan only works with the xdp and npcap engines.".to_string()),
+        (Some("bpf"), _) => Engine::Bpf { interface: get_bpf_interface(matches), router_mac, target_mac },
+        // macOS raw sockets don't hand TCP responses back, so scans there go through BPF unless asked otherwise
+        (None, false) if cfg!(target_os = "macos") => {
+            Engine::Bpf { interface: get_bpf_interface(matches), router_mac: None, target_mac: None }
+        }
+        (_, true) => {
+            return Err("--vlan, --router-mac and --target-mac only work with the xdp, npcap and bpf engines, which frame probes themselves."
+                .to_string())
+        }
+        (Some("io-uring"), false) => Engine::IoUring,
+        _ => Engine::RawSocket,
+    })
+}
+
+/// `--interface`, or else the interface holding the default route, for the engines that default to it
+fn get_bpf_interface(matches: &ArgMatches) -> String {
+    matches.value_of("interface").map(str::to_string).or_else(armada_lib::utils::get_default_interface).unwrap_or_else(|| {
+        panic!("There's no default route to pick an interface for the scan from. Please give one via --interface.")
+    })
+}
+
+fn get_mac_address(matches: &ArgMatches, arg: &str) -> Result<Option<MacAddr>, String> {
+    matches
+        .value_of(arg)
+        .map(|value| {
+            MacAddr::from_str(value)
+                .map_err(|_| format!("Unable to parse MAC address '{}'. Expected one like 00:11:22:33:44:55.", value))
+        })
+        .transpose()
+}
+
+fn get_vlan(matches: &ArgMatches) -> Result<Option<u16>, String> {
+    matches
+        .value_of("vlan")
+        .map(|value| {
+            value
+                .parse::<u16>()
+                .ok()
+                .filter(|vlan| (1..=4094).contains(vlan))
+                .ok_or_else(|| format!("Unable to parse VLAN ID '{}'. Expected a number from 1 to 4094.", value))
+        })
+        .transpose()
+}
+
+fn get_capture(matches: &ArgMatches) -> Option<PacketCapture> {
+    matches
+        .value_of("pcap")
+        .map(|path| PacketCapture::create(path).unwrap_or_else(|error| panic!("{:#}", error)))
+}
+
+fn get_timeout(matches: &ArgMatches) -> Result<Duration, String> {
+    let timeout = match matches.value_of("timeout") {
+        Some(value) => value.parse::<u64>().map_err(|_| format!("Unable to parse timeout value '{}'.", value))?,
+        None => DEFAULT_TIMEOUT_IN_MS,
+    };
+
+    Ok(Duration::from_millis(timeout))
+}
+
+fn get_resolve_timeout(matches: &ArgMatches) -> Result<Duration, String> {
+    let resolve_timeout = match matches.value_of("resolve_timeout") {
+        Some(value) => value.parse::<u64>().map_err(|_| format!("Unable to parse resolve timeout value '{}'.", value))?,
+        None => DEFAULT_RESOLVE_TIMEOUT_IN_MS,
+    };
+
+    Ok(Duration::from_millis(resolve_timeout))
+}
+
+fn get_source_ip_addresses(matches: &ArgMatches, invalid_entries: &mut InvalidEntries) -> Option<Vec<IpAddr>> {
+    let origin = match matches.value_source("source_ip") {
+        Some(ValueSource::EnvVariable) => "ARMADA_SOURCE_IP",
+        _ => "--source-ip",
+    };
+
+    matches.values_of("source_ip").map(|values| {
+        values
+            .filter_map(|value| match IpAddr::from_str(value) {
+                Ok(ip) => Some(ip),
+                Err(_) => {
+                    invalid_entries.record(Origin::Flag(origin), format!("'{}' isn't an IP address", value));
+                    None
+                }
+            })
+            .collect()
+    })
+    // with every one skipped the sources are picked as if none were given
+    .filter(|source_ips: &Vec<IpAddr>| !source_ips.is_empty())
+}
+
+fn get_stream_results(matches: &ArgMatches) -> bool {
+    matches.is_present("stream")
+}
+
+fn get_scan_type(matches: &ArgMatches) -> ScanType {
+    if matches.value_of("protocol") == Some("sctp") {
+        return ScanType::SctpInit;
+    }
+
+    match matches.value_of("scan_type") {
+        Some("fin") => ScanType::Fin,
+        Some("null") => ScanType::Null,
+        Some("xmas") => ScanType::Xmas,
+        Some("ack") => ScanType::Ack,
+        Some("window") => ScanType::Window,
+        _ => ScanType::Syn,
+    }
+}
+
+fn get_ttl(matches: &ArgMatches) -> Result<Option<u8>, String> {
+    matches
+        .value_of("ttl")
+        .map(|value| {
+            value
+                .parse::<u8>()
+                .ok()
+                .filter(|ttl| *ttl > 0)
+                .ok_or_else(|| format!("Unable to parse TTL '{}'. Expected a number from 1 to 255.", value))
+        })
+        .transpose()
+}
+
+/// `--fragment` splits probes into 8 byte fragments, the smallest there are, and `--mtu` into larger ones
+fn get_fragment_size(matches: &ArgMatches) -> Result<Option<usize>, String> {
+    match matches.value_of("mtu") {
+        Some(value) => value
+            .parse::<usize>()
+            .ok()
+            .filter(|mtu| *mtu > 0 && mtu % 8 == 0)
+            .map(Some)
+            .ok_or_else(|| format!("Unable to parse MTU '{}'. Expected a positive multiple of 8.", value)),
+        None => Ok(matches.is_present("fragment").then_some(8)),
+    }
+}
+
+/// Decoys are listed in the order they're sent in, with `ME` standing in for our own probe. Without it, our probe is
+/// slotted in at random.
+fn get_decoys(matches: &ArgMatches, seed: Option<u64>) -> Result<Option<Decoys>, String> {
+    let Some(values) = matches.value_of("decoys") else {
+        return Ok(None);
+    };
+
+    let mut real_position = None;
+    let mut addrs = Vec::new();
+    for value in values.split(',').map(str::trim) {
+        if value.eq_ignore_ascii_case("me") {
+            if real_position.replace(addrs.len()).is_some() {
+                return Err("ME can only be listed once in --decoys.".to_string());
+            }
+            continue;
+        }
+
+        addrs.push(IpAddr::from_str(value).map_err(|_| format!("Unable to parse decoy address '{}'.", value))?);
+    }
+
+    if addrs.is_empty() {
+        return Err("--decoys needs at least one decoy address besides ME.".to_string());
+    }
+
+    let real_position = real_position.unwrap_or_else(|| match seed {
+        Some(seed) => StdRng::seed_from_u64(seed).gen_range(0..=addrs.len()),
+        None => rand::thread_rng().gen_range(0..=addrs.len()),
+    });
+
+    Ok(Some(Decoys { addrs, real_position }))
+}
+
+/// Starts from `--probe-profile`, or what probes are sent with by default, and applies `--tcp-options`, `--mss` and
+/// `--tcp-window` on top of it
+fn get_probe_profile(matches: &ArgMatches) -> Result<Option<TcpProbeProfile>, String> {
+    if !["probe_profile", "tcp_options", "mss", "tcp_window"].iter().any(|arg| matches.is_present(arg)) {
+        return Ok(None);
+    }
+
+    let mut probe_profile = match matches.value_of("probe_profile") {
+        Some("linux") => TcpProbeProfile::linux(),
+        Some("windows") => TcpProbeProfile::windows(),
+        Some("macos") => TcpProbeProfile::macos(),
+        // the options asked for when guessing operating systems
+        _ if matches.is_present("os_guess") => TcpProbeProfile::linux(),
+        _ => TcpProbeProfile::default(),
+    };
+
+    if let Some(value) = matches.value_of("tcp_options") {
+        probe_profile.options = get_tcp_options(value, &probe_profile.options)?;
+    }
+
+    if let Some(value) = matches.value_of("mss") {
+        let mss = value
+            .parse::<u16>()
+            .map_err(|_| format!("Unable to parse MSS '{}'. Expected a number from 0 to 65535.", value))?;
+
+        match probe_profile.options.iter_mut().find(|option| matches!(option, TcpProbeOption::Mss(_))) {
+            Some(option) => *option = TcpProbeOption::Mss(mss),
+            None => probe_profile.options.insert(0, TcpProbeOption::Mss(mss)),
+        }
+    }
+
+    if let Some(value) = matches.value_of("tcp_window") {
+        probe_profile.window = value
+            .parse::<u16>()
+            .map_err(|_| format!("Unable to parse window size '{}'. Expected a number from 0 to 65535.", value))?;
+    }
+
+    if probe_profile.options_length() > 40 {
+        return Err("The TCP options of probes can't take up more than 40 bytes.".to_string());
+    }
+
+    Ok(Some(probe_profile))
+}
+
+/// Parses a comma separated list of `mss`, `sack`, `ts`, `wscale` and `nop`, or `none`, keeping the MSS and window
+/// scale of the options they replace
+fn get_tcp_options(value: &str, replaced: &[TcpProbeOption]) -> Result<Vec<TcpProbeOption>, String> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(Vec::new());
+    }
+
+    let mss = replaced.iter().find_map(|option| match option {
+        TcpProbeOption::Mss(mss) => Some(*mss),
+        _ => None,
+    });
+    let window_scale = replaced.iter().find_map(|option| match option {
+        TcpProbeOption::WindowScale(shift) => Some(*shift),
+        _ => None,
+    });
+
+    value
+        .split(',')
+        .map(|option| match option.trim().to_lowercase().as_str() {
+            "mss" => Ok(TcpProbeOption::Mss(mss.unwrap_or(1460))),
+            "sack" => Ok(TcpProbeOption::SackPermitted),
+            "ts" => Ok(TcpProbeOption::Timestamp),
+            "wscale" => Ok(TcpProbeOption::WindowScale(window_scale.unwrap_or(7))),
+            "nop" => Ok(TcpProbeOption::Nop),
+            _ => Err(format!("Unknown TCP option '{}'. Expected mss, sack, ts, wscale or nop.", option)),
+        })
+        .collect()
+}
+
+fn get_output_format(matches: &ArgMatches) -> OutputFormat {
+    match matches.value_of("output") {
+        Some("json") => OutputFormat::Json,
+        Some("ndjson") => OutputFormat::Ndjson,
+        Some("csv") => OutputFormat::Csv,
+        Some("nmap-xml") => OutputFormat::NmapXml,
+        Some("grepable") => OutputFormat::Grepable,
+        Some("hosts") => OutputFormat::Hosts,
+        _ => OutputFormat::Default,
+    }
+}
+
+fn get_sink_target(matches: &ArgMatches) -> Result<Option<SinkTarget>, String> {
+    let Some(output) = matches.value_of("output") else {
+        return Ok(None);
+    };
+
+    Ok(if let Some(path) = output.strip_prefix(SQLITE_OUTPUT_PREFIX) {
+        Some(SinkTarget::Sqlite(PathBuf::from(path)))
+    } else if POSTGRES_OUTPUT_PREFIXES.iter().any(|prefix| output.starts_with(prefix)) {
+        Some(SinkTarget::Postgres(output.to_string()))
+    } else if output.starts_with(KAFKA_OUTPUT_PREFIX) {
+        Some(SinkTarget::Kafka(output.to_string()))
+    } else if let Some(transport) = SyslogTransport::parse(output) {
+        Some(SinkTarget::Syslog {
+            transport,
+            facility: get_syslog_facility(matches)?,
+            severity: get_syslog_severity(matches)?,
+        })
+    } else {
+        match output.strip_prefix(ELASTICSEARCH_OUTPUT_PREFIX) {
+            Some(url) => Some(SinkTarget::Elasticsearch {
+                url: url.to_string(),
+                index: matches.value_of("elasticsearch_index").unwrap_or(DEFAULT_ELASTICSEARCH_INDEX).to_string(),
+                batch_size: get_elasticsearch_batch_size(matches)?,
+            }),
+            None => None,
+        }
+    })
+}
+
+fn get_elasticsearch_batch_size(matches: &ArgMatches) -> Result<usize, String> {
+    matches.value_of("elasticsearch_batch_size").map_or(Ok(DEFAULT_ELASTICSEARCH_BATCH_SIZE), |value| {
+        match value.parse::<usize>() {
+            Ok(batch_size) if batch_size > 0 => Ok(batch_size),
+            _ => Err(format!("Unable to parse Elasticsearch batch size '{}'.", value)),
+        }
+    })
+}
+
+fn get_syslog_facility(matches: &ArgMatches) -> Result<SyslogFacility, String> {
+    matches
+        .value_of("syslog_facility")
+        .map_or(Ok(SyslogFacility::default()), |value| SyslogFacility::from_str(value).map_err(|error| error.to_string()))
+}
+
+fn get_syslog_severity(matches: &ArgMatches) -> Result<SyslogSeverity, String> {
+    matches
+        .value_of("syslog_severity")
+        .map_or(Ok(SyslogSeverity::default()), |value| SyslogSeverity::from_str(value).map_err(|error| error.to_string()))
+}
+
+fn get_metrics_address(matches: &ArgMatches) -> Result<Option<SocketAddr>, String> {
+    matches
+        .value_of("metrics_address")
+        .map(|value| SocketAddr::from_str(value).map_err(|_| format!("Unable to parse metrics address '{}'.", value)))
+        .transpose()
+}
+
+fn get_webhook_batch_size(matches: &ArgMatches) -> Result<usize, String> {
+    matches.value_of("webhook_batch_size").map_or(Ok(DEFAULT_WEBHOOK_BATCH_SIZE), |value| {
+        match value.parse::<usize>() {
+            Ok(batch_size) if batch_size > 0 => Ok(batch_size),
+            _ => Err(format!("Unable to parse webhook batch size '{}'.", value)),
+        }
+    })
+}
+
+/// Accepts the formats as well as databases and search indices, which don't fit clap's possible values
+fn validate_output(output: &str) -> Result<(), String> {
+    if OUTPUT_FORMATS.contains(&output) || POSTGRES_OUTPUT_PREFIXES.iter().any(|prefix| output.starts_with(prefix)) {
+        return Ok(());
+    }
+
+    if let Some(path) = output.strip_prefix(SQLITE_OUTPUT_PREFIX) {
+        return match path {
+            "" => Err("sqlite output needs a database path, e.g. sqlite:results.db".to_string()),
+            _ => Ok(()),
+        };
+    }
+
+    if let Some(transport) = SyslogTransport::parse(output) {
+        return match transport {
+            SyslogTransport::Unix(path) if path.is_empty() => {
+                Err("syslog output needs a socket path, e.g. syslog:/dev/log".to_string())
+            }
+            _ => Ok(()),
+        };
+    }
 
-    port_strings
-        .into_iter()
-        .fold(PortIterator::new(), |port_iterator, port_str| {
-            let capture = port_regex
-                .captures(&port_str)
-                .expect(&format!("Failed to interpret port flag with value '{}'.", port_str));
-
-            let start_port = capture.get(1).map(|m| m.as_str()).map(|port_str| {
-                port_str
-                    .parse::<u16>()
-                    .expect(&format!("Failed to parse port '{}' into int.", port_str))
-            });
+    if let Some(url) = output.strip_prefix(KAFKA_OUTPUT_PREFIX) {
+        return match url.split_once('/') {
+            Some((brokers, topic)) if !brokers.is_empty() && !topic.is_empty() => Ok(()),
+            _ => Err("kafka output needs a broker and a topic, e.g. kafka://localhost:9092/scans".to_string()),
+        };
+    }
 
-            let end_port = capture.get(2).map(|m| m.as_str()).map(|port_str| {
-                port_str
-                    .parse::<u16>()
-                    .expect(&format!("Failed to parse port '{}' into int.", port_str))
-            });
+    if let Some(url) = output.strip_prefix(ELASTICSEARCH_OUTPUT_PREFIX) {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(());
+        }
 
-            match (start_port, end_port) {
-                (Some(start_port), Some(end_port)) => port_iterator.add_range(start_port, end_port),
-                (Some(port), None) => port_iterator.add_port(port),
-                _ => panic!("Failed to interpret port flag with value '{}'.", port_str),
-            }
-        })
-}
+        return Err("elasticsearch output needs an http(s) URL, e.g. elasticsearch:http://localhost:9200".to_string());
+    }
 
-fn get_quiet_mode(matches: &ArgMatches) -> bool {
-    matches.is_present("quiet")
+    Err(format!(
+        "expected one of {}, sqlite:<path>, postgres://..., elasticsearch:<url>, kafka://broker/topic, syslog://host, syslog+tcp://host or syslog:<socket>",
+        OUTPUT_FORMATS.join(", ")
+    ))
 }
 
-fn get_rate_limit(matches: &ArgMatches) -> Option<usize> {
-    let rate_limit = matches.value_of("rate_limit").map(|value| {
-        value
-            .parse::<usize>()
-            .expect("Rate limit must be a non-negative number.")
-    });
+/// `--run-as`, for the scan, `serve` and `agent`. Only unix systems have users to switch to.
+fn run_as_arg() -> Option<Arg<'static>> {
+    let arg = Arg::new("run_as")
+        .help("Switches to this user, by name or uid, once the raw sockets are open, so the scan doesn't run as root. \
+        Defaults to the user who ran armada through sudo, if it was, and `root` keeps running as root. Only works when started as root.")
+        .long("run-as")
+        .env("ARMADA_RUN_AS")
+        .takes_value(true);
 
-    match rate_limit {
-        _ if matches.is_present("sanic") => None,
-        Some(rate) if rate == 0 => None,
-        Some(rate) if rate < 10 => Some(10),
-        Some(rate) => Some(rate),
-        None => Some(DEFAULT_RATE_LIMIT),
-    }
+    cfg!(unix).then_some(arg)
 }
 
-fn get_listening_port(matches: &ArgMatches) -> u16 {
-    matches
-        .value_of("listening_port")
-        .map(|value| {
-            value
-                .parse::<u16>()
-                .expect(&format!("Unable to parse listening port value '{}'.", value))
-        })
-        .unwrap_or_else(|| rand::thread_rng().gen_range(50_000..60_000))
+/// nmap users reach for `-oA`, which clap would otherwise read as `-o A`
+fn expand_output_all_flag(arg: OsString) -> OsString {
+    if arg == "-oA" {
+        OsString::from("--output-all")
+    } else {
+        arg
+    }
 }
 
-fn get_retries(matches: &ArgMatches) -> u8 {
-    matches
-        .value_of("retries")
-        .map(|value| {
-            value
-                .parse::<u8>()
-                .expect(&format!("Unable to parse port retry value '{}'.", value))
-        })
-        .or(matches.is_present("sanic").then(|| 0))
-        .unwrap_or(DEFAULT_PORT_RETRY)
+fn get_csv_fields(matches: &ArgMatches) -> Result<Vec<CsvField>, String> {
+    match matches.values_of("csv_fields") {
+        Some(values) => values.map(|value| CsvField::from_str(value).map_err(|error| error.to_string())).collect(),
+        None => Ok(CsvField::DEFAULT.to_vec()),
+    }
 }
 
-fn get_timeout(matches: &ArgMatches) -> Duration {
-    let timeout = matches
-        .value_of("timeout")
-        .map(|value| {
-            value
-                .parse::<u64>()
-                .expect(&format!("Unable to parse timeout value '{}'.", value))
-        })
-        .unwrap_or(DEFAULT_TIMEOUT_IN_MS);
+fn get_banner_options(matches: &ArgMatches) -> Result<Option<BannerOptions>, String> {
+    if !matches.is_present("banners") {
+        return Ok(None);
+    }
 
-    Duration::from_millis(timeout)
-}
+    let max_bytes = match matches.value_of("banner_bytes") {
+        Some(value) => value.parse::<usize>().map_err(|_| format!("Unable to parse banner bytes value '{}'.", value))?,
+        None => DEFAULT_BANNER_BYTES,
+    };
 
-fn get_source_ip_addresses(matches: &ArgMatches) -> Option<Vec<IpAddr>> {
-    matches.values_of("source_ip").map(|values| {
-        values
-            .map(|value| IpAddr::from_str(value).expect(&format!("Unable to parse source IP address '{}'.", value)))
-            .collect()
-    })
-}
+    let timeout = match matches.value_of("banner_timeout") {
+        Some(value) => value.parse::<u64>().map_err(|_| format!("Unable to parse banner timeout value '{}'.", value))?,
+        None => DEFAULT_BANNER_TIMEOUT_IN_MS,
+    };
 
-fn get_stream_results(matches: &ArgMatches) -> bool {
-    matches.is_present("stream")
+    Ok(Some(BannerOptions {
+        max_bytes,
+        connection: ConnectionOptions {
+            timeout: Duration::from_millis(timeout),
+            ..Default::default()
+        },
+    }))
 }
 
 fn app_config() -> Command<'static> {
@@ -212,8 +1961,144 @@ fn app_config() -> Command<'static> {
         .author("d0nut <d0nut@resync.gg>")
         .about("High performance TCP SYN port scanner")
         .version(crate_version!())
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(Command::new("diff")
+            .about("Compares two JSON or ndjson result files and reports newly opened ports, newly closed ports and new hosts. \
+            Exits with 1 when anything changed and 0 otherwise.")
+            .arg(Arg::new("old_results")
+                .help("The results of the earlier scan.")
+                .required(true))
+            .arg(Arg::new("new_results")
+                .help("The results of the later scan.")
+                .required(true))
+            .arg(Arg::new("output")
+                .help("Sets the format the changes are written in. Default output is one 'opened ip:port', 'closed ip:port' or 'new-host ip' line per change. Defaults to default.")
+                .long("output")
+                .short('o')
+                .takes_value(true)
+                .possible_values(["default", "json"])))
+        .subcommand(Command::new("analyze")
+            .about("Works out the results of a scan from a pcap capture of it, like one written by --pcap or tcpdump, \
+            by putting the responses in it through the same classification a scan does. Responses only count when they answer a probe in the capture.")
+            .arg(Arg::new("capture")
+                .help("The pcap file to analyze.")
+                .required(true))
+            .arg(Arg::new("scan_type")
+                .help("Sets the kind of scan the capture was taken of. Defaults to the kind most of the probes in the capture were sent for, which takes window scans for ACK scans.")
+                .long("scan-type")
+                .takes_value(true)
+                .possible_values(["syn", "fin", "null", "xmas", "ack", "window", "sctp"]))
+            .arg(Arg::new("report_all_states")
+                .help("Reports every probed port, including the ones the scan type would leave out. Ports no response was captured for are reported in the state a scan would time them out in.")
+                .long("report-all-states")
+                .takes_value(false))
+            .arg(Arg::new("os_guess")
+                .help("Guesses the operating system of hosts with open ports from the SYN-ACKs in the capture.")
+                .long("os-guess")
+                .takes_value(false))
+            .arg(Arg::new("output")
+                .help("Sets the format the results are written in. Defaults to default.")
+                .long("output")
+                .short('o')
+                .takes_value(true)
+                .possible_values(OUTPUT_FORMATS))
+            .arg(Arg::new("output_file")
+                .help("Writes the results to the file instead of stdout.")
+                .long("output-file")
+                .takes_value(true)))
+        .subcommand(Command::new("serve")
+            .about("Runs as a daemon taking scan jobs over a REST API: POST /jobs submits one, GET /jobs/<id> polls it, GET /jobs/<id>/results fetches its results, \
+            GET /jobs/<id>/events streams them as server-sent events and DELETE /jobs/<id> cancels it. Jobs run one after another, in the order they were submitted.")
+            .arg(Arg::new("listen")
+                .help("Sets the address the API is served on. Defaults to 127.0.0.1:8080.")
+                .long("listen")
+                .takes_value(true))
+            .arg(Arg::new("grpc_listen")
+                .help("Serves the gRPC API of proto/armada.proto on the address as well, e.g. 127.0.0.1:50051. Its scans are run on the same engine as the REST API's.")
+                .long("grpc-listen")
+                .takes_value(true))
+            .arg(Arg::new("listening_port")
+                .help("Sets the port responses to the probes are received on. Defaults to a random port from 50000 to 60000.")
+                .long("listening-port")
+                .takes_value(true))
+            .arg(Arg::new("source_ip")
+                .help("The IP addresses probes are sent from. Defaults to the ones on the interface holding the default route.")
+                .long("source-ip")
+                .takes_value(true)
+                .multiple_values(true)
+                .require_value_delimiter(true)
+                .value_delimiter(','))
+            .args(run_as_arg()))
+        .subcommand(Command::new("controller")
+            .about("Splits a scan into shards of its (host, port) space and hands them out to the agents that connect, one at a time each, \
+            then writes out what they find as one scan. A shard whose agent disconnects or goes quiet is handed to another. \
+            Takes the same arguments as the scan after its own, e.g. armada controller --listen 0.0.0.0:7700 -- -t 10.0.0.0/8 -p 1-1024 --rate-limit 50000 -o json.")
+            .trailing_var_arg(true)
+            .arg(Arg::new("listen")
+                .help("Sets the address agents connect to. Defaults to 0.0.0.0:7700.")
+                .long("listen")
+                .takes_value(true))
+            .arg(Arg::new("shards")
+                .help("Sets how many shards the scan is split into. More shards spread the scan more evenly and lose less of it when an agent fails. Defaults to 64.")
+                .long("shards")
+                .takes_value(true))
+            .arg(Arg::new("scan_arguments")
+                .help("The arguments the scan would be run with.")
+                .takes_value(true)
+                .multiple_values(true)
+                .allow_hyphen_values(true)
+                .allow_invalid_utf8(true)))
+        .subcommand(Command::new("agent")
+            .about("Connects to an armada controller and scans the shards it hands out, sending back what's found.")
+            .arg(Arg::new("controller")
+                .help("The controller's address, as host:port.")
+                .long("controller")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::new("listening_port")
+                .help("Sets the port responses to the probes are received on. Defaults to a random port from 50000 to 60000.")
+                .long("listening-port")
+                .takes_value(true))
+            .arg(Arg::new("source_ip")
+                .help("The IP addresses probes are sent from. Defaults to the ones on the interface holding the default route.")
+                .long("source-ip")
+                .takes_value(true)
+                .multiple_values(true)
+                .require_value_delimiter(true)
+                .value_delimiter(','))
+            .args(run_as_arg()))
+        .subcommand(Command::new("estimate")
+            .about("Prints how many probes a scan would send, how many bytes they come to and how long it would take, with and without retries, \
+            as JSON and without sending anything. Takes the same arguments as the scan, e.g. armada estimate -t 10.0.0.0/8 -p 1-1024 --rate-limit 50000.")
+            .trailing_var_arg(true)
+            .arg(Arg::new("scan_arguments")
+                .help("The arguments the scan would be run with.")
+                .takes_value(true)
+                .multiple_values(true)
+                .allow_hyphen_values(true)
+                .allow_invalid_utf8(true)))
+        .subcommand(Command::new("completions")
+            .about("Prints a completion script for the shell, e.g. armada completions bash > /etc/bash_completion.d/armada.")
+            .arg(Arg::new("shell")
+                .help("The shell to complete for.")
+                .required(true)
+                .possible_values(SHELL_NAMES)))
+        .subcommand(Command::new("config")
+            .about("Works with the TOML config files read by --toml-config.")
+            .subcommand_required(true)
+            .subcommand(Command::new("init")
+                .about("Prints a TOML config with a key for every setting, each commented with its help. \
+                Takes the same arguments as a scan, which are filled in while the rest are left commented out, e.g. armada config init -t 10.0.0.0/8 --rate-limit 50000 > scan.toml.")
+                .trailing_var_arg(true)
+                .arg(Arg::new("scan_arguments")
+                    .help("The settings to fill in.")
+                    .takes_value(true)
+                    .multiple_values(true)
+                    .allow_hyphen_values(true)
+                    .allow_invalid_utf8(true))))
         .arg(Arg::new("targets")
-            .help("The IP and CIDR ranges to scan.")
+            .help("The IPs, CIDR ranges, nmap style ranges (e.g. 10.0.0-3.1-254), hostnames and ASNs to scan. Hostnames are scanned on every IPv4 and IPv6 address they resolve to and ASNs (e.g. AS13335) on every IPv4 prefix they announce, according to RIPEstat. Link-local IPv6 targets take the interface they're on as a zone, e.g. fe80::1%eth0.")
             .long("targets")
             .short('t')
             .takes_value(true)
@@ -221,50 +2106,333 @@ fn app_config() -> Command<'static> {
             .require_value_delimiter(true)
             .value_delimiter(','))
         .arg(Arg::new("target_file")
-            .help("A newline delimited file containing IP addresses and CIDR ranges to scan.")
+            .help("A newline delimited file containing IP addresses, CIDR ranges, nmap style ranges, hostnames and ASNs to scan.")
             .long("target_file")
+            .takes_value(true)
+            .conflicts_with("targets"))
+        .arg(Arg::new("targets_cloud")
+            .help("Scans the IPv4 ranges a cloud provider publishes, narrowed down to a region and service, e.g. aws:us-east-1:EC2, gcp:europe-west1 or azure::AzureStorage. \
+            The providers are aws, gcp and azure, and a region left empty or * matches every region. Combines with --targets and --target_file.")
+            .long("targets-cloud")
+            .value_name("provider:region:service")
+            .takes_value(true)
+            .multiple_values(true)
+            .require_value_delimiter(true)
+            .value_delimiter(','))
+        .arg(Arg::new("exclude")
+            .help("The IPs, CIDR ranges and nmap style ranges to leave out of the scan, even if they fall within a target.")
+            .long("exclude")
+            .takes_value(true)
+            .multiple_values(true)
+            .require_value_delimiter(true)
+            .value_delimiter(','))
+        .arg(Arg::new("exclude_file")
+            .help("A newline delimited file containing IP addresses, CIDR ranges and nmap style ranges to leave out of the scan.")
+            .long("exclude-file")
+            .takes_value(true))
+        .arg(Arg::new("exclude_url")
+            .help("URLs of newline delimited exclusion lists, e.g. an organisation's do-not-scan list, with IPs, CIDR ranges and nmap style ranges to leave out of the scan. \
+            They're downloaded before the scan starts and cached, and the scan is refused if one can't be fetched, so the list is always honored. # and ; start comments.")
+            .long("exclude-url")
+            .takes_value(true)
+            .multiple_values(true)
+            .require_value_delimiter(true)
+            .value_delimiter(','))
+        .arg(Arg::new("exclude_url_max_age")
+            .help("Sets how long a cached --exclude-url list is used for before it's checked for changes, e.g. 30m or 1d. Defaults to 1h.")
+            .long("exclude-url-max-age")
+            .takes_value(true)
+            .requires("exclude_url"))
+        .arg(Arg::new("exclude_reserved")
+            .help("Leaves every bogon out of the scan: private, loopback, link-local, documentation, benchmarking, multicast and other reserved IPv4 and IPv6 ranges, \
+            the broadcast address included, none of which are reachable on the internet.")
+            .long("exclude-reserved")
+            .takes_value(false))
+        .arg(Arg::new("exclude_private")
+            .help("Leaves private address space out of the scan: the RFC 1918 ranges, carrier-grade NAT's 100.64.0.0/10 and IPv6 unique local addresses (fc00::/7).")
+            .long("exclude-private")
+            .takes_value(false))
+        .arg(Arg::new("scope_file")
+            .help("A newline delimited file containing the IP addresses, CIDR ranges and nmap style ranges the scan is allowed to probe, with # starting a comment. \
+            Scans with targets outside of them, hostnames and ASNs once they're looked up included, are refused, unless --scope-mode trim is given.")
+            .long("scope-file")
+            .takes_value(true))
+        .arg(Arg::new("scope_mode")
+            .help("Sets what's done with targets outside --scope-file. refuse exits before anything is sent and trim leaves them out of the scan with a warning. Defaults to refuse.")
+            .long("scope-mode")
+            .takes_value(true)
+            .possible_values(SCOPE_MODES)
+            .requires("scope_file"))
+        .arg(Arg::new("v6_strategy")
+            .help("Sets how IPv6 ranges shorter than a /96, which hold more addresses than the whole IPv4 space, are scanned. \
+            low-addrs scans the lowest addresses of each, ::1 up, sample scans a mix of low and well known interface IDs, SLAAC interface IDs made from MAC addresses and random ones, \
+            and list scans every address however long that takes. Scans with such ranges are refused unless a strategy is picked.")
+            .long("v6-strategy")
+            .takes_value(true)
+            .possible_values(IPV6_STRATEGIES))
+        .arg(Arg::new("sample")
+            .help("Sets how many addresses low-addrs and sample scan of each IPv6 range too large to scan whole. Implies --v6-strategy sample when no strategy is given. \
+            Samples are drawn from --seed when it's given, so they can be repeated. Defaults to 1024.")
+            .long("sample")
+            .value_name("n")
             .takes_value(true))
+        .arg(Arg::new("skip_invalid")
+            .help("Leaves out targets, exclusions, ports and source IPs that can't be parsed, with a warning for each, rather than exiting. \
+            Also goes ahead with a scan whose hostnames, ASNs and cloud sources couldn't be looked up, even if that leaves nothing to scan.")
+            .long("skip-invalid")
+            .takes_value(false))
         .group(ArgGroup::new("scan_targets")
             .args(&["targets", "target_file"])
             .required(false))
         .arg(Arg::new("ports")
-            .help("Sets which ports to scan.")
+            .help("Sets which ports to scan, e.g. 22,80,8000-8100,https. Ranges may be left open (1000-, -1024) and -p- scans every port. Service names are looked up in /etc/services.")
             .short('p')
             .long("ports")
             .multiple_values(true)
             .takes_value(true)
+            .allow_hyphen_values(true)
             .require_value_delimiter(true)
             .value_delimiter(',')
-            .conflicts_with_all(&["top100", "top1000"])
-            .required_unless_present_any(&["top100", "top1000", "toml_config"]))
+            .conflicts_with_all(&["port_file", "top", "top100", "top1000"])
+            .required_unless_present_any(["port_file", "top", "top100", "top1000", "toml_config", "resume"]))
+        .arg(Arg::new("port_file")
+            .help("A newline or comma delimited file containing the ports, port ranges and service names to scan. Anything after a # is ignored.")
+            .long("port-file")
+            .takes_value(true)
+            .conflicts_with_all(&["top", "top100", "top1000"]))
         .arg(Arg::new("quiet")
             .help("Disables any progress reporting during the scan.")
             .short('q')
             .long("quiet")
             .takes_value(false))
+        .arg(Arg::new("verbose")
+            .help("Logs what the scan is doing to stderr. -v logs the interface, sources and engine it goes out with, -vv adds the ICMP errors received \
+            and each worker's share of the scan, and -vvv every retransmission and every packet dropped for not matching a probe.")
+            .short('v')
+            .long("verbose")
+            .multiple_occurrences(true)
+            .takes_value(false))
+        .arg(Arg::new("log_file")
+            .help("Writes logs to the file instead of stderr, with a timestamp on every line, so quiet and streaming pipelines stay clean while the diagnostics are still kept. \
+            Takes -v for more of them.")
+            .long("log-file")
+            .env("ARMADA_LOG_FILE")
+            .takes_value(true))
+        .arg(Arg::new("log_max_size")
+            .help("Rotates the --log-file once it grows past this size, e.g. 500K, 100M or 1G, moving it aside to <path>.1.")
+            .long("log-max-size")
+            .takes_value(true)
+            .requires("log_file"))
+        .arg(Arg::new("log_max_files")
+            .help("How many rotated log files --log-max-size keeps, as <path>.1 to <path>.N, the oldest being removed. Defaults to 5.")
+            .long("log-max-files")
+            .takes_value(true)
+            .requires("log_max_size"))
+        .arg(Arg::new("tui")
+            .help("Shows a full-screen dashboard of the scan in place of the progress bars. \
+            Up/+ and Down/- raise and lower the rate limit, p pauses and resumes sending and q stops the scan once the probes in flight are settled.")
+            .long("tui")
+            .takes_value(false)
+            .conflicts_with("quiet"))
+        .arg(Arg::new("progress")
+            .help("How progress is reported. bar draws progress bars, and json writes a JSON event with the percent complete, rate, hosts done, open ports found and ETA \
+            to stderr every second instead, for wrappers and CI jobs to pick up.")
+            .long("progress")
+            .takes_value(true)
+            .possible_values(["bar", "json"])
+            .conflicts_with_all(&["quiet", "tui"]))
         .arg(Arg::new("rate_limit")
             .help("Sets the maximum packets per second. \
             If this is explicitly set to 0, we'll run with no maximum. \
             Defaults to 10kpps. Keep in mind that faster != better.")
             .long("rate-limit")
+            .env("ARMADA_RATE_LIMIT")
+            .takes_value(true))
+        .arg(Arg::new("rate_limiter")
+            .help("How --rate-limit is held to. fixed-window lets a tenth of a second's worth of probes out at the start of every tenth of a second, \
+            while token-bucket lets probes out as its tokens drip in at the rate, saving up to --burst of them while the scan sends slower than that.")
+            .long("rate-limiter")
+            .env("ARMADA_RATE_LIMITER")
+            .possible_values(["fixed-window", "token-bucket"])
+            .takes_value(true))
+        .arg(Arg::new("burst")
+            .help("The most probes the token bucket saves up to send at once, a tenth of a second's worth of --rate-limit by default. Implies --rate-limiter token-bucket.")
+            .long("burst")
+            .env("ARMADA_BURST")
+            .takes_value(true))
+        .arg(Arg::new("auto_rate")
+            .help("Starts at a conservative rate and adjusts it to the network as the scan goes: backing off when ports only answer retries or fewer ports answer per probe, and speeding up while neither happens. \
+            The rate limit becomes the most the rate goes up to, so pair it with --rate-limit 0 to let it climb as far as the network allows.")
+            .long("auto-rate")
+            .takes_value(false))
+        .arg(Arg::new("max_rate_per_net")
+            .help("Caps the packets per second sent into any one network on top of --rate-limit, given as <prefix>:<packets per second>, e.g. 24:100 for 100pps per IPv4 /24. IPv6 networks are capped per /64. \
+            Probes to networks over their cap wait while other networks are scanned, so pair it with --randomize to keep the scan moving.")
+            .long("max-rate-per-net")
+            .env("ARMADA_MAX_RATE_PER_NET")
+            .takes_value(true))
+        .arg(Arg::new("max_rate_per_source")
+            .help("Caps the packets per second sent from any one --source-ip on top of --rate-limit. Probes take turns across the source ips, passing over the ones at their cap, \
+            so giving several of them lets a scan go faster than targets that rate limit per address would allow.")
+            .long("max-rate-per-source")
+            .env("ARMADA_MAX_RATE_PER_SOURCE")
+            .takes_value(true))
+        .arg(Arg::new("delay")
+            .help("Waits at least this many milliseconds between probes, on top of --rate-limit, so the scan doesn't go out in bursts. Paced scans are sent by a single worker.")
+            .long("delay")
+            .takes_value(true))
+        .arg(Arg::new("jitter")
+            .help("Waits up to this many milliseconds more between probes, picked at random for every probe, so their spacing has no pattern to it.")
+            .long("jitter")
+            .takes_value(true))
+        .arg(Arg::new("pace_per_host")
+            .help("Only spaces out probes to the same host by --delay and --jitter, sending probes to other hosts in between.")
+            .long("pace-per-host")
+            .takes_value(false))
+        .arg(Arg::new("scan_window")
+            .help("Only sends probes between these times of day, e.g. 22:00-06:00, pausing the scan outside of them and resuming it when the window opens again, \
+            however many days that takes. Times are local unless followed by UTC or an offset, e.g. \"22:00-06:00 +02:00\".")
+            .long("window")
+            .takes_value(true))
+        .arg(Arg::new("max_runtime")
+            .help("Stops sending probes once armada has run this long, e.g. 30m or 2h, waits for the probes in flight and reports the results found \
+            along with how much of the scan was covered. A bare number is in seconds. With --checkpoint, the checkpoint is kept for --resume.")
+            .long("max-runtime")
+            .env("ARMADA_MAX_RUNTIME")
+            .takes_value(true))
+        .arg(Arg::new("dry_run")
+            .help("Checks the arguments, resolves the targets and prints what the scan would do, from how many probes it sends and how long that \
+            takes at the rate limit to the sources and interface they go out from, without sending a single packet.")
+            .long("dry-run")
+            .takes_value(false))
+        .arg(Arg::new("confirm_above")
+            .help("Sets how many probes, decoys included, a scan may send before armada prints its plan and asks whether to start it, refusing outright without a terminal to ask on. \
+            0 never asks. Defaults to 10000000.")
+            .long("confirm-above")
+            .value_name("probes")
+            .env("ARMADA_CONFIRM_ABOVE")
             .takes_value(true))
+        .arg(Arg::new("yes")
+            .help("Starts scans larger than --confirm-above without asking.")
+            .long("yes")
+            .short('y')
+            .takes_value(false))
         .arg(Arg::new("listening_port")
-            .help("Sets the port to listen on. If unset, armada will pick a random port from 50000-60000.")
+            .help("Sets the port to listen on, or a range of them, e.g. 50000-50015, to spread probes across that many source ports with a worker receiving on each in parallel. If unset, armada will pick a random port from 50000-60000.")
             .long("listening-port")
+            .env("ARMADA_LISTENING_PORT")
+            .takes_value(true))
+        .arg(Arg::new("threads")
+            .help("Sets how many workers send probes and receive responses, each on a listening port of its own, so a single --listening-port becomes a range of this many ports. Up to 64. Defaults to 1, or to the size of the --listening-port range.")
+            .long("threads")
+            .env("ARMADA_THREADS")
+            .takes_value(true))
+        .arg(Arg::new("pin_cores")
+            .help("Pins each worker to one of the listed cores, e.g. 0-3,8, going round them in order. `auto` picks the cores on the NUMA node of the interface the scan goes out on. Linux only.")
+            .long("pin-cores")
             .takes_value(true))
+        .arg(Arg::new("seed")
+            .help("Seeds the random parts of the scan, like the --randomize order, the listening port and TCP sequence numbers, so a run can be repeated exactly. Useful for debugging missed responses.")
+            .long("seed")
+            .takes_value(true))
+        .arg(Arg::new("shard")
+            .help("Probes only one share of the target/port pairs, every <count>th pair in the scan order starting from the <index>th, so one scan can be split between machines run with the same settings, e.g. --shard 0/4 to --shard 3/4. \
+            The index counts from 0. Randomized scans need the same --seed on every machine.")
+            .long("shard")
+            .env("ARMADA_SHARD")
+            .takes_value(true))
+        .arg(Arg::new("randomize")
+            .help("Probes targets and ports in a random order spread across the whole scan instead of one target after the other, so no single subnet or host takes the probes all at once. Uses constant memory however large the scan.")
+            .long("randomize")
+            .takes_value(false))
         .arg(Arg::new("retries")
             .help("Sets the number of additional attempts aramada will take to verify that a port is open. Setting this to '0' will result in ports only being checked once. Defaults to 2.")
             .long("retries")
+            .env("ARMADA_RETRIES")
             .takes_value(true))
         .arg(Arg::new("timeout")
             .help("Sets the amount of time, in milliseconds, waited until a sent packet is determined to have been timed out. Defaults to 1 second.")
             .long("timeout")
+            .env("ARMADA_TIMEOUT")
+            .takes_value(true))
+        .arg(Arg::new("adaptive_timeout")
+            .help("Works out how long to wait for each response from the round trip times of earlier responses from the same /24 (or /64), the way TCP does, doubling it for every retry. \
+            --timeout only applies until the first responses come in, so fast local networks aren't waited on for long and slow far away hosts aren't given up on too early.")
+            .long("adaptive-timeout")
+            .takes_value(false))
+        .arg(Arg::new("min_timeout")
+            .help("Sets the shortest time, in milliseconds, an adaptive timeout waits for a response. Defaults to 50 milliseconds.")
+            .long("min-timeout")
+            .takes_value(true)
+            .requires("adaptive_timeout"))
+        .arg(Arg::new("max_timeout")
+            .help("Sets the longest time, in milliseconds, an adaptive timeout waits for a response. Defaults to 5 seconds.")
+            .long("max-timeout")
+            .takes_value(true)
+            .requires("adaptive_timeout"))
+        .arg(Arg::new("batch_size")
+            .help("Sets the most packets sent, or received off of each socket, per system call. Larger batches cut down on system calls at high rates. Defaults to 32.")
+            .long("batch-size")
+            .takes_value(true))
+        .arg(Arg::new("no_batched_io")
+            .help("Sends and receives every packet with a system call of its own instead of batching them with sendmmsg and recvmmsg. For kernels or network setups that don't handle batches well.")
+            .long("no-batched-io")
+            .takes_value(false))
+        .arg(Arg::new("pcap")
+            .help("Writes every probe sent and every response received during the port scan to a pcap file, for working out why a port came out the way it did.")
+            .long("pcap")
+            .takes_value(true))
+        .arg(Arg::new("engine")
+            .help("Selects how probes are sent and responses received. `raw` goes through raw sockets and the kernel's network stack. `io-uring` drives the same raw sockets through an io_uring, falling back to raw where io_uring isn't available. `xdp` uses AF_XDP sockets on --interface, bypassing the kernel's network stack for the port scan (Linux only). `npcap` sends and receives whole frames through the packet capture driver of --interface: Npcap on Windows, AF_PACKET or BPF elsewhere. `bpf` does the same through a /dev/bpf device on --interface, or the interface holding the default route. Defaults to bpf on macOS, whose raw sockets don't hand TCP responses back, to npcap on the interface holding the default route on Windows, whose raw sockets don't send TCP, and to raw everywhere else.")
+            .long("engine")
+            .env("ARMADA_ENGINE")
+            .takes_value(true)
+            .possible_values(["raw", "io-uring", "xdp", "npcap", "bpf"]))
+        .arg(Arg::new("interface")
+            .help("Sets the network interface probes are sent and responses received on, rather than leaving it to the routing table. Source addresses are picked from it when --source-ip isn't given. Required by the xdp and npcap engines. Linux only for the raw and io-uring engines.")
+            .long("interface")
+            .env("ARMADA_INTERFACE")
+            .takes_value(true)
+            .required_if_eq_any(&[("engine", "xdp"), ("engine", "npcap")]))
+        .arg(Arg::new("vlan")
+            .help("Tags every probe with this 802.1Q VLAN ID and only takes responses tagged with it, for scanning from a trunk port. \
+            Implies --engine xdp unless --engine npcap is given, and needs --interface to be the trunk. Next hops and source addresses come from the VLAN's interface when one is set up on top of it.")
+            .long("vlan")
+            .takes_value(true)
+            .requires("interface"))
+        .arg(Arg::new("router_mac")
+            .help("Sends the frames of probes to targets behind a gateway, or that the routing table has no route to, to this MAC address rather than the gateway's \
+            the kernel has resolved. Implies --engine xdp unless --engine npcap or bpf is given.")
+            .long("router-mac")
+            .env("ARMADA_ROUTER_MAC")
+            .takes_value(true)
+            .requires("interface"))
+        .arg(Arg::new("target_mac")
+            .help("Sends the frames of probes to targets on the link to this MAC address rather than their own the kernel has resolved. Implies --engine xdp unless --engine npcap or bpf is given.")
+            .long("target-mac")
+            .takes_value(true)
+            .requires("interface"))
+        .arg(Arg::new("resolve_timeout")
+            .help("Sets the amount of time, in milliseconds, waited for each hostname target to resolve. Hostnames that don't resolve in time are skipped. Also bounds each --rdns lookup. Defaults to 5 seconds.")
+            .long("resolve-timeout")
+            .env("ARMADA_RESOLVE_TIMEOUT")
             .takes_value(true))
+        .arg(Arg::new("rdns")
+            .help("Looks up the PTR record of every host with an open port and records the hostname it points to.")
+            .long("rdns")
+            .takes_value(false))
         .arg(Arg::new("source_ip")
-            .help("Adds an ip address (v4 or v6) that armada should use when creating TCP packets. If not set, it will try to use sensible defaults.")
+            .help("Adds an ip address (v4 or v6) that armada should use when creating TCP packets, or several comma separated ones. If not set, it will try to use sensible defaults.")
             .long("source-ip")
+            .env("ARMADA_SOURCE_IP")
             .multiple_occurrences(true)
+            .value_delimiter(',')
             .takes_value(true))
+        .arg(Arg::new("top")
+            .help("Scans for the given number of most common ports, up to 1,000.")
+            .long("top")
+            .takes_value(true)
+            .conflicts_with_all(&["top100", "top1000"]))
         .arg(Arg::new("top100")
             .help("Scans for the top 100 most common ports.")
             .long("top100")
@@ -274,16 +2442,627 @@ fn app_config() -> Command<'static> {
             .help("Scans for the top 1,000 most common ports.")
             .long("top1000")
             .takes_value(false))
+        .arg(Arg::new("exclude_ports")
+            .help("Sets which ports to leave out of the scan, using the same format as -p. Applies to --top100 and --top1000 too.")
+            .long("exclude-ports")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .multiple_values(true)
+            .require_value_delimiter(true)
+            .value_delimiter(','))
         .arg(Arg::new("toml_config")
             .help("Reads configuration from TOML file instead of command line args.")
             .long("toml-config")
             .takes_value(true))
+        .arg(Arg::new("profile")
+            .help("Picks the [profile.<name>] table of the --toml-config file to scan with, on top of the settings outside any profile.")
+            .long("profile")
+            .takes_value(true)
+            .requires("toml_config"))
         .arg(Arg::new("stream")
             .help("Enable streaming the results into stdout as they come in. Only works if piping the results out or if quiet mode is enabled.")
             .long("stream")
             .short('s'))
+        .arg(Arg::new("scan_type")
+            .help("Sets the kind of probe sent to each port. FIN, NULL and Xmas scans report ports that didn't answer as 'open|filtered'. \
+            ACK scans map firewall rules by reporting ports as 'unfiltered' or 'filtered'. \
+            Window scans are ACK scans that infer open ports from the window size of returned RSTs. Defaults to syn.")
+            .long("scan-type")
+            .takes_value(true)
+            .possible_values(["syn", "fin", "null", "xmas", "ack", "window"]))
+        .arg(Arg::new("protocol")
+            .help("Sets the transport protocol to scan. SCTP scans send INIT chunks and report ports answering with an INIT-ACK as open. Defaults to tcp.")
+            .long("protocol")
+            .takes_value(true)
+            .possible_values(["tcp", "sctp"])
+            .conflicts_with("scan_type"))
+        .arg(Arg::new("fragment")
+            .help("Splits every IPv4 probe into IP fragments of 8 bytes, so packet filters that only look at whole TCP headers let them through. Only works for IPv4 targets.")
+            .long("fragment")
+            .short('f')
+            .takes_value(false))
+        .arg(Arg::new("mtu")
+            .help("Splits every IPv4 probe into IP fragments carrying this many bytes of it each, a multiple of 8. Implies --fragment.")
+            .long("mtu")
+            .takes_value(true))
+        .arg(Arg::new("ttl")
+            .help("Sets the TTL, or hop limit for IPv6 targets, of every probe, from 1 to 255. Probes that run out of hops before reaching a port aren't answered by it, \
+            so low TTLs are useful for working out where along the path a port is filtered. Defaults to the system's TTL.")
+            .long("ttl")
+            .env("ARMADA_TTL")
+            .takes_value(true))
+        .arg(Arg::new("probe_profile")
+            .help("Sends TCP probes with the window size and options of a SYN from the given operating system, so they look like any other connection \
+            to intrusion detection systems. --tcp-options, --mss and --tcp-window go on top of the profile.")
+            .long("probe-profile")
+            .takes_value(true)
+            .possible_values(["linux", "windows", "macos"]))
+        .arg(Arg::new("tcp_options")
+            .help("Sets which options TCP probes carry, in order, as a comma separated list of mss, sack, ts, wscale and nop, or none for no options at all. \
+            Defaults to mss.")
+            .long("tcp-options")
+            .takes_value(true))
+        .arg(Arg::new("mss")
+            .help("Sets the MSS TCP probes offer, adding the option when it's not already sent. Defaults to 1460.")
+            .long("mss")
+            .takes_value(true))
+        .arg(Arg::new("tcp_window")
+            .help("Sets the window size of TCP probes. Defaults to 1024.")
+            .long("tcp-window")
+            .takes_value(true))
+        .arg(Arg::new("decoys")
+            .help("Sends every probe along with copies of it spoofed from each of these comma separated addresses, in order, so the target can't tell which source \
+            is really scanning it. ME marks where our own probe goes, e.g. 192.0.2.7,ME,198.51.100.3, and without it our probe is slotted in at random. \
+            Decoys should be hosts that are up, since a flood of SYNs from down hosts gives the game away.")
+            .long("decoys")
+            .short('D')
+            .takes_value(true))
+        .arg(Arg::new("discover")
+            .help("Before port scanning, probes every target with ICMP echo and timestamp requests and TCP SYNs to ports 80 and 443. \
+            IPv4 targets on a directly attached network are sent ARP requests instead and have their MAC address reported. \
+            Only hosts that answer are port scanned.")
+            .long("discover")
+            .takes_value(false))
+        .arg(Arg::new("output")
+            .help("Sets the format results are written in. CSV rows hold the --csv-fields columns. \
+            JSON output is a single array, ndjson output one JSON object per line that can be streamed, nmap-xml output a single nmap XML document and grepable output one nmap style line per host, all written once the scan finishes. \
+            hosts output is one 'ip: port, port' line per host, streamed a host at a time once the host's results stop coming for as long as the timeout and retries take. \
+            sqlite:<path> writes every result into an SQLite database as it arrives, creating the database and its tables if needed. \
+            A postgres:// connection string inserts results into PostgreSQL in batches, creating the tables if needed. \
+            elasticsearch:<url> ships results to an Elasticsearch or OpenSearch index through the bulk API. \
+            kafka://broker[:port][,broker...]/topic publishes every result to a Kafka topic as a JSON message as it arrives. \
+            syslog://host[:port], syslog+tcp://host[:port] and syslog:<socket> forward every result as an RFC 5424 message over UDP, TCP or a unix socket such as /dev/log. \
+            Formats: default, json, ndjson, csv, nmap-xml, grepable, hosts, sqlite:<path>, postgres://..., elasticsearch:<url>, kafka://broker/topic, syslog://host, syslog+tcp://host, syslog:<socket>. Defaults to default.")
+            .long("output")
+            .env("ARMADA_OUTPUT")
+            .short('o')
+            .takes_value(true)
+            .validator(validate_output))
+        .arg(Arg::new("elasticsearch_index")
+            .help("Sets the index Elasticsearch output is written to. Defaults to armada.")
+            .long("elasticsearch-index")
+            .takes_value(true))
+        .arg(Arg::new("elasticsearch_batch_size")
+            .help("Sets how many results are sent per Elasticsearch bulk request. Defaults to 500.")
+            .long("elasticsearch-batch-size")
+            .takes_value(true))
+        .arg(Arg::new("csv_fields")
+            .help("Sets which columns CSV output holds and their order, out of ip, port, state, mac_address, banner, tls_subject_cn, tls_sans, tls_issuer, tls_not_after, service, version, os_guess, ttl, window, tcp_options, hostname, rdns, rtt and attempt. Defaults to ip,port,state. \
+            rtt is the time the port took to answer, in milliseconds, and attempt which probe to it was answered, 1 for the first and 2 for its first retry.")
+            .long("csv-fields")
+            .takes_value(true)
+            .multiple_values(true)
+            .require_value_delimiter(true)
+            .value_delimiter(','))
+        .arg(Arg::new("csv_header")
+            .help("Writes a header row naming the columns before any CSV results.")
+            .long("csv-header")
+            .takes_value(false))
+        .arg(Arg::new("output_file")
+            .help("Writes results to the file instead of stdout. Formats that can be streamed are written as results arrive, \
+            so a scan that's cut short still leaves the results found so far.")
+            .long("output-file")
+            .env("ARMADA_OUTPUT_FILE")
+            .takes_value(true))
+        .arg(Arg::new("output_all")
+            .help("Writes results to <basename>.txt, <basename>.json and <basename>.csv at once, in the default, JSON and CSV formats, instead of stdout. \
+            Also accepted as -oA.")
+            .long("output-all")
+            .value_name("basename")
+            .takes_value(true)
+            .conflicts_with_all(&["output", "output_file"]))
+        .args(run_as_arg())
+        .arg(Arg::new("summary_file")
+            .help("Writes the summary printed once the scan is over to the file as JSON: the hosts scanned, the hosts with open ports, the open ports, \
+            the packets sent and received, the retransmissions, the time taken and the packets per second sent over it.")
+            .long("summary-file")
+            .takes_value(true))
+        .arg(Arg::new("syslog_facility")
+            .help("Sets the facility of syslog output. Defaults to user.")
+            .long("syslog-facility")
+            .takes_value(true)
+            .possible_values(SyslogFacility::NAMES))
+        .arg(Arg::new("syslog_severity")
+            .help("Sets the severity of syslog output. Defaults to notice.")
+            .long("syslog-severity")
+            .takes_value(true)
+            .possible_values(SyslogSeverity::NAMES))
+        .arg(Arg::new("webhook")
+            .help("POSTs results as JSON to the URL while the scan runs, alongside any other output. \
+            Requests that fail are retried with backoff; results that still can't be delivered are reported and dropped.")
+            .long("webhook")
+            .env("ARMADA_WEBHOOK")
+            .takes_value(true))
+        .arg(Arg::new("webhook_batch_size")
+            .help("Sets how many results are sent per webhook request. A batch size of 1 sends every result as its own JSON object, larger batches are sent as JSON arrays. Defaults to 1.")
+            .long("webhook-batch-size")
+            .takes_value(true)
+            .requires("webhook"))
+        .arg(Arg::new("metrics_address")
+            .help("Serves Prometheus metrics about the scan (packets sent and received, retries, ports found, progress) on http://<address>/metrics while it runs, e.g. 127.0.0.1:9900.")
+            .long("metrics-addr")
+            .env("ARMADA_METRICS_ADDRESS")
+            .takes_value(true))
+        .arg(Arg::new("control_socket")
+            .help("Takes commands on a unix domain socket at the path while the scan runs, one a line: status, set-rate <pps>, pause, resume and abort. \
+            status answers with a line of JSON, the others with ok or an error.")
+            .long("control-socket")
+            .env("ARMADA_CONTROL_SOCKET")
+            .takes_value(true))
+        .arg(Arg::new("checkpoint")
+            .help("Saves how far the scan got and the results found so far to the file every few seconds, so an interrupted scan can be continued with --resume. \
+            The file is removed once the scan completes.")
+            .long("checkpoint")
+            .takes_value(true)
+            .conflicts_with("discover"))
+        .arg(Arg::new("resume")
+            .help("Continues the scan a --checkpoint file was saved by, with the arguments it was started with. Results found before it stopped are written out again with the rest.")
+            .long("resume")
+            .takes_value(true)
+            .exclusive(true))
+        .arg(Arg::new("banners")
+            .help("Connects to every open port after it is found and records the first bytes the service sends.")
+            .long("banners")
+            .takes_value(false))
+        .arg(Arg::new("banner_bytes")
+            .help("Sets the most bytes of banner kept per port. Defaults to 512.")
+            .long("banner-bytes")
+            .takes_value(true)
+            .requires("banners"))
+        .arg(Arg::new("banner_timeout")
+            .help("Sets the amount of time, in milliseconds, waited to connect and for a banner to arrive. Defaults to 3 seconds.")
+            .long("banner-timeout")
+            .takes_value(true)
+            .requires("banners"))
+        .arg(Arg::new("tls_info")
+            .help("Performs a TLS handshake with open ports that commonly serve TLS (443, 8443, 993, ...) and records the subject CN, SANs, issuer and expiry of their certificate.")
+            .long("tls-info")
+            .takes_value(false))
+        .arg(Arg::new("tls_all_ports")
+            .help("Collects TLS certificates from every open port instead of only the common TLS ports.")
+            .long("tls-all-ports")
+            .takes_value(false)
+            .requires("tls_info"))
+        .arg(Arg::new("service_detect")
+            .help("Sends protocol specific probes to open ports and matches the responses against a bundled database to identify the service and its version.")
+            .long("service-detect")
+            .takes_value(false))
+        .arg(Arg::new("os_guess")
+            .help("Records the TTL, window size and TCP options of the SYN-ACKs open ports answer with and guesses the operating system of the host from them. Only works with SYN scans.")
+            .long("os-guess")
+            .takes_value(false)
+            .conflicts_with_all(&["scan_type", "protocol"]))
+        .arg(Arg::new("report_all_states")
+            .help("Reports every probed port with its state instead of only the interesting ones. Ports answering with a RST are 'closed', \
+            ports that never answered are 'filtered' and ports whose probes were rejected with an ICMP unreachable are 'filtered(admin)'.")
+            .long("report-all-states")
+            .takes_value(false))
+        .arg(Arg::new("raw_results")
+            .help("Reports every response as it's received, even for a port that was already reported, e.g. when a host answers both a probe and its retry. By default each target/port pair is reported once.")
+            .long("raw-results")
+            .takes_value(false))
         .arg(Arg::new("sanic")
             .hide(true)
             .long("sanic")
             .takes_value(false))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(cidrs: &[&str]) -> Vec<IpCidr> {
+        cidrs.iter().map(|cidr| IpCidr::from_str(cidr).unwrap()).collect()
+    }
+
+    #[test]
+    fn ports_and_ranges_parse() {
+        assert_eq!(parse_port_range("80"), Ok((80, 80)));
+        assert_eq!(parse_port_range("8000-8100"), Ok((8000, 8100)));
+        assert_eq!(parse_port_range("443-443"), Ok((443, 443)));
+    }
+
+    #[test]
+    fn open_ended_ranges_run_to_the_first_or_last_port() {
+        assert_eq!(parse_port_range("1000-"), Ok((1000, u16::MAX)));
+        assert_eq!(parse_port_range("-1024"), Ok((1, 1024)));
+        assert_eq!(parse_port_range("-"), Ok((1, u16::MAX)));
+    }
+
+    #[test]
+    fn ports_past_the_last_are_rejected() {
+        assert_eq!(parse_port_range("80-65536"), Err("'65536' isn't a port between 1 and 65535".to_string()));
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        assert_eq!(parse_port_range("80--90"), Err("'80--90' isn't a port, port range or service name".to_string()));
+        assert_eq!(parse_port_range("1-2-3"), Err("'1-2-3' isn't a port, port range or service name".to_string()));
+        assert_eq!(parse_port_range(""), Err("'' isn't a port, port range or service name".to_string()));
+        assert_eq!(parse_port_range("65536"), Err("'65536' isn't a port between 1 and 65535".to_string()));
+    }
+
+    #[test]
+    fn port_zero_is_rejected() {
+        assert_eq!(parse_port_range("0"), Err("'0' isn't a port between 1 and 65535".to_string()));
+        assert_eq!(parse_port_range("0-80"), Err("'0' isn't a port between 1 and 65535".to_string()));
+    }
+
+    #[test]
+    fn backwards_ranges_are_rejected() {
+        assert_eq!(
+            parse_port_range("100-50"),
+            Err("'100-50' runs backwards, the first port has to come before the last".to_string()),
+        );
+    }
+
+    #[test]
+    fn service_names_stand_for_their_port() {
+        assert_eq!(parse_port_range("ssh"), Ok((22, 22)));
+        assert_eq!(parse_port_range("HTTPS"), Ok((443, 443)));
+    }
+
+    #[test]
+    fn unknown_service_names_are_rejected() {
+        assert_eq!(
+            parse_port_range("not-a-service"),
+            Err("'not-a-service' isn't a port, port range or service name".to_string()),
+        );
+    }
+
+    #[test]
+    fn invalid_ports_are_recorded_with_where_they_came_from() {
+        let mut invalid_entries = InvalidEntries::new(false);
+        let port_strings = [
+            (Origin::Flag("--ports"), "80".to_string()),
+            (Origin::Flag("--ports"), "http-alt-ish".to_string()),
+            (Origin::Line("ports.txt".to_string(), 3), "70000".to_string()),
+            (Origin::Line("ports.txt".to_string(), 4), "0".to_string()),
+            (Origin::Line("ports.txt".to_string(), 5), "100-50".to_string()),
+        ];
+
+        let ports = parse_port_specs(port_strings.into_iter(), &mut invalid_entries);
+
+        assert_eq!(ports.collect::<Vec<_>>(), [80]);
+        assert_eq!(
+            invalid_entries.errors,
+            [
+                "--ports: 'http-alt-ish' isn't a port, port range or service name",
+                "ports.txt:3: '70000' isn't a port between 1 and 65535",
+                "ports.txt:4: '0' isn't a port between 1 and 65535",
+                "ports.txt:5: '100-50' runs backwards, the first port has to come before the last",
+            ],
+        );
+    }
+
+    #[test]
+    fn port_specs_are_scanned_once_each_in_the_order_given() {
+        let mut invalid_entries = InvalidEntries::new(false);
+        let port_strings = ["443", "80-82", "ssh", "81", "80-443"].map(|port_str| (Origin::Flag("--ports"), port_str.to_string()));
+
+        let ports: Vec<u16> = parse_port_specs(port_strings.into_iter(), &mut invalid_entries).collect();
+
+        assert!(invalid_entries.is_empty());
+        assert_eq!(ports[..5], [443, 80, 81, 82, 22]);
+        assert_eq!(ports.len(), 443 - 80 + 1 + 1);
+    }
+
+    #[test]
+    fn valid_entries_leave_nothing_to_report() {
+        let mut invalid_entries = InvalidEntries::new(false);
+
+        let port_strings = [(Origin::Flag("--ports"), "22".to_string()), (Origin::Flag("--ports"), "80-90".to_string())];
+
+        parse_port_specs(port_strings.into_iter(), &mut invalid_entries);
+
+        assert!(invalid_entries.is_empty());
+    }
+
+    #[test]
+    fn lines_are_numbered_from_one_and_trimmed() {
+        let lines = ["10.0.0.1", "  10.0.0.2  ", ""].into_iter().map(String::from);
+
+        let numbered = numbered_lines(lines, |line| Origin::Line("targets.txt".to_string(), line));
+
+        assert_eq!(
+            numbered.iter().map(|(origin, line)| (origin.to_string(), line.as_str())).collect::<Vec<_>>(),
+            [("targets.txt:1".to_string(), "10.0.0.1"), ("targets.txt:2".to_string(), "10.0.0.2")],
+        );
+    }
+
+    #[test]
+    fn sink_outputs_are_told_apart_from_formats() {
+        assert!(validate_output("csv").is_ok());
+        assert!(validate_output("sqlite:results.db").is_ok());
+        assert!(validate_output("postgres://armada@localhost/scans").is_ok());
+        assert!(validate_output("elasticsearch:https://localhost:9200").is_ok());
+        assert!(validate_output("kafka://localhost:9092/scans").is_ok());
+        assert!(validate_output("sqlite:").is_err());
+        assert!(validate_output("elasticsearch:localhost:9200").is_err());
+        assert!(validate_output("kafka://localhost:9092").is_err());
+        assert!(validate_output("xml").is_err());
+    }
+
+    #[test]
+    fn sink_targets_carry_their_settings() {
+        let sink_target = |args: &[&str]| {
+            let args = ["armada", "-t", "192.0.2.1", "-p", "80"].iter().chain(args);
+            get_sink_target(&app_config().get_matches_from(args)).unwrap()
+        };
+
+        assert_eq!(sink_target(&["-o", "sqlite:results.db"]), Some(SinkTarget::Sqlite(PathBuf::from("results.db"))));
+        assert_eq!(
+            sink_target(&["-o", "postgresql://localhost/scans"]),
+            Some(SinkTarget::Postgres("postgresql://localhost/scans".to_string())),
+        );
+        assert_eq!(
+            sink_target(&["-o", "elasticsearch:http://localhost:9200"]),
+            Some(SinkTarget::Elasticsearch {
+                url: "http://localhost:9200".to_string(),
+                index: "armada".to_string(),
+                batch_size: 500,
+            }),
+        );
+        assert_eq!(
+            sink_target(&["-o", "elasticsearch:http://localhost:9200", "--elasticsearch-index", "scans", "--elasticsearch-batch-size", "50"]),
+            Some(SinkTarget::Elasticsearch {
+                url: "http://localhost:9200".to_string(),
+                index: "scans".to_string(),
+                batch_size: 50,
+            }),
+        );
+        assert_eq!(
+            sink_target(&["-o", "kafka://localhost/scans"]),
+            Some(SinkTarget::Kafka("kafka://localhost/scans".to_string())),
+        );
+        assert_eq!(sink_target(&["-o", "ndjson"]), None);
+    }
+
+    #[test]
+    fn tcp_probes_are_only_shaped_when_asked_to() {
+        let probe_profile = |args: &[&str]| {
+            let args = ["armada", "-t", "192.0.2.1", "-p", "80"].iter().chain(args);
+            get_probe_profile(&app_config().get_matches_from(args)).unwrap()
+        };
+
+        assert!(probe_profile(&[]).is_none());
+
+        let shaped = probe_profile(&["--probe-profile", "windows", "--tcp-window", "4096"]).unwrap();
+        assert_eq!(shaped.window, 4096);
+        assert_eq!(shaped.options, TcpProbeProfile::windows().options);
+    }
+
+    #[test]
+    fn bursts_imply_the_token_bucket() {
+        let rate_limiter = |args: &[&str]| {
+            let args = ["armada", "-t", "192.0.2.1", "-p", "80"].iter().chain(args);
+            let matches = app_config().get_matches_from(args);
+            get_rate_limiter(&matches, get_rate_limit(&matches).unwrap()).unwrap()
+        };
+
+        assert!(matches!(rate_limiter(&[]), RateLimitStrategy::FixedWindow));
+        assert!(matches!(rate_limiter(&["--burst", "64"]), RateLimitStrategy::TokenBucket { burst: 64 }));
+        assert!(matches!(
+            rate_limiter(&["--rate-limiter", "token-bucket", "--rate-limit", "5000"]),
+            RateLimitStrategy::TokenBucket { burst: 500 },
+        ));
+    }
+
+    #[test]
+    fn shards_are_an_index_below_their_count() {
+        let shard = |value: &str| get_shard(&app_config().get_matches_from(["armada", "-t", "192.0.2.1", "-p", "80", "--shard", value]));
+
+        assert_eq!(shard("3/8"), Ok(Some(ScanShard { index: 3, count: 8 })));
+        assert_eq!(shard("0/1"), Ok(Some(ScanShard { index: 0, count: 1 })));
+    }
+
+    #[test]
+    fn shards_past_the_count_are_rejected() {
+        assert_eq!(
+            get_shard(&app_config().get_matches_from(["armada", "-t", "192.0.2.1", "-p", "80", "--shard", "8/8"])),
+            Err("Unable to parse shard '8/8'. Expected <index>/<count> with the index counting from 0, e.g. 3/8.".to_string()),
+        );
+    }
+
+    #[test]
+    fn excluded_ports_are_left_out() {
+        let matches = app_config().get_matches_from(["armada", "-t", "192.0.2.1", "-p", "1-10,80", "--exclude-ports", "3-4,10,80"]);
+
+        assert_eq!(get_ports(&matches, &mut InvalidEntries::new(false)).collect::<Vec<_>>(), [1, 2, 5, 6, 7, 8, 9]);
+    }
+
+    /// The IPs and ranges of the targets, the zone of the link-local ones and the targets that couldn't be parsed
+    fn targets(targets: &[&str]) -> (HostIterator, Option<String>, Vec<String>) {
+        let targets = targets.join(",");
+        let matches = app_config().get_matches_from(["armada", "-p", "80", "-t", &targets]);
+        let mut invalid_entries = InvalidEntries::new(false);
+
+        let (hosts, _, _, zone) = get_targets(&matches, &mut invalid_entries);
+
+        (hosts, zone, invalid_entries.errors)
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn link_local_targets_carry_their_zone() {
+        let (hosts, zone, errors) = targets(&["fe80::1%lo", "fe80::2%lo", "192.0.2.1"]);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(zone.as_deref(), Some("lo"));
+        assert_eq!(hosts.size(), 3);
+    }
+
+    #[test]
+    fn link_local_targets_need_a_zone_and_only_they_take_one() {
+        let (_, _, errors) = targets(&["fe80::1", "fe80::/64", "2001:db8::1%lo", "fe80::1%no-such-interface"]);
+
+        assert_eq!(
+            errors,
+            [
+                "--targets: 'fe80::1' is link-local, so it needs the interface it's on as a zone, like fe80::1%eth0",
+                "--targets: 'fe80::/64' is link-local, so it needs the interface it's on as a zone, like fe80::/64%eth0",
+                "--targets: '2001:db8::1%lo' has a zone, which only link-local IPv6 addresses (fe80::/10) take",
+                "--targets: 'fe80::1%no-such-interface' is on zone no-such-interface, but there's no interface by that name or index",
+            ],
+        );
+    }
+
+    #[test]
+    fn scope_files_skip_comments_and_report_what_cant_be_parsed() {
+        let file = std::env::temp_dir().join(format!("armada-scope-{}.txt", std::process::id()));
+        std::fs::write(&file, "# engagement 42\n10.0.0.0/24\n\n192.0.2.1-5\nnot-a-range\n").unwrap();
+        let file_name = file.to_str().unwrap();
+        let matches = app_config().get_matches_from(["armada", "-t", "10.0.0.1", "-p", "80", "--scope-file", file_name, "--scope-mode", "trim"]);
+        let mut invalid_entries = InvalidEntries::new(false);
+
+        let scope = get_scope(&matches, &mut invalid_entries).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(scope.mode, ScopeMode::Trim);
+        assert_eq!(scope.ranges, cidrs(&["10.0.0.0/24", "192.0.2.1/32", "192.0.2.2/31", "192.0.2.4/31"]));
+        assert_eq!(invalid_entries.errors, [format!("{}:5: 'not-a-range' isn't an IP, CIDR or range for the scope", file_name)]);
+    }
+
+    #[test]
+    fn reserved_ranges_take_in_the_private_ones() {
+        let exclusions = |flags: &[&str]| {
+            let args = ["armada", "-t", "192.0.2.1", "-p", "80", "--exclude", "198.51.100.7"].iter().chain(flags);
+            get_exclusions(&app_config().get_matches_from(args), &mut InvalidEntries::new(false))
+        };
+
+        assert_eq!(exclusions(&[]), cidrs(&["198.51.100.7"]));
+        assert_eq!(exclusions(&["--exclude-private"]).len(), 1 + crate::reserved::private_ranges().len());
+        assert_eq!(
+            exclusions(&["--exclude-private", "--exclude-reserved"]).len(),
+            1 + crate::reserved::reserved_ranges().len(),
+        );
+    }
+
+    #[test]
+    fn durations_take_a_unit_or_are_in_seconds() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("1d"), Some(Duration::from_secs(24 * 60 * 60)));
+        for value in ["0", "0m", "", "h", "1w", "-1s", "1.5h"] {
+            assert_eq!(parse_duration(value), None, "{} parsed", value);
+        }
+    }
+
+    #[test]
+    fn octet_ranges_become_the_fewest_cidrs() {
+        assert_eq!(parse_octet_ranges("10.0.0.0-255"), Some(cidrs(&["10.0.0.0/24"])));
+        assert_eq!(parse_octet_ranges("10.0.0.1-6"), Some(cidrs(&["10.0.0.1/32", "10.0.0.2/31", "10.0.0.4/31", "10.0.0.6/32"])));
+        assert_eq!(parse_octet_ranges("10.0-1.0-255.0-255"), Some(cidrs(&["10.0.0.0/15"])));
+        assert_eq!(parse_octet_ranges("10.0-1.5.1"), Some(cidrs(&["10.0.5.1/32", "10.1.5.1/32"])));
+    }
+
+    #[test]
+    fn only_octet_ranges_parse_as_them() {
+        for target in ["10.0.0.1", "10.0.0.5-1", "10.0.0.1-256", "10.0.1-50", "10.0.0.0.1-2", "example-host.com"] {
+            assert_eq!(parse_octet_ranges(target), None, "{} parsed", target);
+        }
+    }
+
+    #[test]
+    fn octet_blocks_are_aligned() {
+        assert_eq!(octet_blocks(0, 255), [(0, 0)]);
+        assert_eq!(octet_blocks(128, 191), [(128, 2)]);
+        assert_eq!(
+            octet_blocks(1, 254),
+            [(1, 8), (2, 7), (4, 6), (8, 5), (16, 4), (32, 3), (64, 2), (128, 2), (192, 3), (224, 4), (240, 5), (248, 6), (252, 7), (254, 8)],
+        );
+    }
+
+    #[test]
+    fn hostnames_are_made_of_dns_labels() {
+        for target in ["example.com", "localhost", "scan-me.example.org.", "host1.co", "xn--bcher-kva.example"] {
+            assert!(is_hostname(target), "{} isn't a hostname", target);
+        }
+    }
+
+    #[test]
+    fn malformed_hostnames_are_rejected() {
+        let long_label = format!("{}.com", "a".repeat(64));
+
+        for target in ["", ".", "-leading.example.com", "trailing-.example.com", "two..dots", "spa ce.com", "ex*ample.com", "_dmarc.example.com", "under_score.example.com", &long_label, "10.0.0.256", "1.2.3", "10.0.0.1-300"] {
+            assert!(!is_hostname(target), "{} is a hostname", target);
+        }
+    }
+
+    #[test]
+    fn targets_are_sorted_into_addresses_hostnames_and_asns() {
+        let matches = app_config().get_matches_from([
+            "armada",
+            "-p",
+            "80",
+            "-t",
+            "192.0.2.1,198.51.100.0/30,203.0.113.1-2,2001:db8::1,example.com,AS13335",
+        ]);
+        let mut invalid_entries = InvalidEntries::new(false);
+
+        let (hosts, hostname_targets, asn_targets, _) = get_targets(&matches, &mut invalid_entries);
+
+        assert!(invalid_entries.errors.is_empty(), "{:?}", invalid_entries.errors);
+        assert_eq!(hosts.size(), 1 + 4 + 2 + 1);
+        assert_eq!(hostname_targets, ["example.com"]);
+        assert_eq!(asn_targets, [13335]);
+    }
+
+    #[test]
+    fn invalid_targets_are_pointed_at() {
+        let (hosts, _, errors) = targets(&["192.0.2.1", "300.1.1.1-2", "fe80::1", "192.0.2.1%eth0"]);
+
+        assert_eq!(hosts.size(), 1);
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].contains("isn't an IP, CIDR, range, ASN or hostname"), "{}", errors[0]);
+        assert!(errors[1].contains("needs the interface it's on as a zone"), "{}", errors[1]);
+        assert!(errors[2].contains("which only link-local IPv6 addresses"), "{}", errors[2]);
+    }
+
+    #[test]
+    fn invalid_entries_are_counted() {
+        let mut invalid_entries = InvalidEntries::new(false);
+        invalid_entries.record(Origin::Flag("--ports"), "'0' isn't a port between 1 and 65535".to_string());
+        assert_eq!(invalid_entries.count(), "1 invalid entry");
+
+        invalid_entries.record(Origin::Flag("--ports"), "'100-50' runs backwards".to_string());
+        assert_eq!(invalid_entries.count(), "2 invalid entries");
+    }
+
+    #[test]
+    fn invalid_flags_are_noted_instead_of_panicking() {
+        let matches = app_config().get_matches_from(["armada", "-t", "192.0.2.1", "-p", "80", "--rate-limit", "fast", "--ttl", "0"]);
+        let mut invalid_entries = InvalidEntries::new(true);
+
+        assert_eq!(invalid_entries.flag(get_rate_limit(&matches)), None);
+        assert_eq!(invalid_entries.flag(get_ttl(&matches)), None);
+        assert_eq!(invalid_entries.flag(get_timeout(&matches)), Duration::from_millis(DEFAULT_TIMEOUT_IN_MS));
+        assert_eq!(invalid_entries.invalid_flags.len(), 2, "{:?}", invalid_entries.invalid_flags);
+        assert!(invalid_entries.invalid_flags[0].starts_with("Unable to parse rate limit 'fast'."));
+        assert!(invalid_entries.invalid_flags[1].starts_with("Unable to parse TTL '0'."));
+        // flags aren't entries that --skip-invalid can pass over
+        assert!(invalid_entries.is_empty());
+    }
+}
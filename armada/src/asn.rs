@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use cidr_utils::cidr::IpCidr;
+use serde::Deserialize;
+
+/// RIPEstat's view of the prefixes an AS announced over the past two weeks, as seen by the RIS route collectors
+const RIPESTAT_ANNOUNCED_PREFIXES_URL: &str = "https://stat.ripe.net/data/announced-prefixes/data.json";
+/// Large networks announce thousands of prefixes, which RIPEstat can take a while to gather
+const ASN_LOOKUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+struct RipeStatResponse {
+    data: AnnouncedPrefixes,
+}
+
+#[derive(Deserialize)]
+struct AnnouncedPrefixes {
+    prefixes: Vec<AnnouncedPrefix>,
+}
+
+#[derive(Deserialize)]
+struct AnnouncedPrefix {
+    prefix: String,
+}
+
+/// Parses an `AS13335` style target into its AS number
+pub(crate) fn parse_asn(target: &str) -> Option<u32> {
+    let number = target.strip_prefix("AS").or_else(|| target.strip_prefix("as"))?;
+
+    number.parse().ok()
+}
+
+/// Expands every AS into the IPv4 prefixes it announces. IPv6 prefixes are left out, since even the smallest prefix
+/// announced globally is far too large to sweep. ASNs that can't be looked up are reported on stderr and skipped.
+pub(crate) async fn expand_asns(asns: Vec<u32>) -> Vec<IpCidr> {
+    if asns.is_empty() {
+        return Vec::new();
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(ASN_LOOKUP_TIMEOUT)
+        .build()
+        .expect("Unable to create an HTTP client for ASN lookups.");
+
+    let mut prefixes = Vec::new();
+
+    for asn in asns {
+        let announced_prefixes = match lookup_announced_prefixes(&client, asn).await {
+            Ok(announced_prefixes) => announced_prefixes,
+            Err(error) => {
+                eprintln!("Unable to look up the prefixes of target 'AS{}': {:#}. Skipping it.", asn, error);
+                continue;
+            }
+        };
+
+        let (ipv4_prefixes, ipv6_prefixes): (Vec<_>, Vec<_>) =
+            announced_prefixes.into_iter().partition(|prefix| matches!(prefix, IpCidr::V4(_)));
+
+        if !ipv6_prefixes.is_empty() {
+            eprintln!("Leaving out the {} IPv6 prefixes announced by AS{}.", ipv6_prefixes.len(), asn);
+        }
+
+        prefixes.extend(ipv4_prefixes);
+    }
+
+    prefixes
+}
+
+async fn lookup_announced_prefixes(client: &reqwest::Client, asn: u32) -> anyhow::Result<Vec<IpCidr>> {
+    let response: RipeStatResponse = client
+        .get(RIPESTAT_ANNOUNCED_PREFIXES_URL)
+        .query(&[("resource", format!("AS{}", asn))])
+        .send()
+        .await
+        .context("RIPEstat request failed")?
+        .error_for_status()
+        .context("RIPEstat returned an error")?
+        .json()
+        .await
+        .context("RIPEstat returned an unexpected response")?;
+
+    response
+        .data
+        .prefixes
+        .into_iter()
+        .map(|announced_prefix| {
+            IpCidr::from_str(&announced_prefix.prefix)
+                .map_err(|_| anyhow::anyhow!("RIPEstat returned an invalid prefix '{}'", announced_prefix.prefix))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asn_targets_parse_into_their_number() {
+        assert_eq!(parse_asn("AS13335"), Some(13335));
+        assert_eq!(parse_asn("as15169"), Some(15169));
+        assert_eq!(parse_asn("AS4200000000"), Some(4_200_000_000));
+    }
+
+    #[test]
+    fn anything_else_isnt_an_asn() {
+        for target in ["13335", "AS", "ASN13335", "AS-13335", "AS13335x", "AS4294967296", "aspen.example.com"] {
+            assert_eq!(parse_asn(target), None, "{} parsed", target);
+        }
+    }
+
+    #[test]
+    fn ripestat_responses_hold_the_announced_prefixes() {
+        let response = r#"{"status": "ok", "data": {"prefixes": [{"prefix": "1.1.1.0/24", "timelines": []}, {"prefix": "2606:4700::/32"}], "resource": "13335"}}"#;
+        let response: RipeStatResponse = serde_json::from_str(response).unwrap();
+
+        let prefixes: Vec<_> = response.data.prefixes.into_iter().map(|announced_prefix| announced_prefix.prefix).collect();
+        assert_eq!(prefixes, ["1.1.1.0/24", "2606:4700::/32"]);
+    }
+}
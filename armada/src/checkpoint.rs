@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+use std::fs::{read_to_string, remove_file, rename, write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use armada_lib::{ArmadaWorkMessage, PortResult, PortState};
+use serde::{Deserialize, Serialize};
+
+/// How often the scan position is written out. A scan that dies loses at most this much progress.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointFile {
+    /// What armada was started with, minus the program name, so `--resume` can run the same scan again
+    arguments: Vec<String>,
+    /// Target/port pairs from the start of the scan that are all settled
+    scan_cursor: u128,
+    results: Vec<CheckpointResult>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CheckpointResult {
+    addr: SocketAddr,
+    state: String,
+    rtt: Option<Duration>,
+    /// Left out of checkpoints saved before attempts were recorded
+    #[serde(default)]
+    attempt: Option<u16>,
+}
+
+/// Periodically saves how far a scan got and the results it found, so an interrupted scan can pick up from there
+pub(crate) struct Checkpointer {
+    path: PathBuf,
+    arguments: Vec<String>,
+    /// The cursor of the latest stats update, whose results haven't necessarily arrived yet
+    latest_cursor: u128,
+    /// The cursor of the stats update before it, whose results have all been recorded
+    settled_cursor: u128,
+    results: Vec<CheckpointResult>,
+    /// Results found before the scan was resumed, which are dropped if the resumed scan finds them again
+    reported_addrs: HashSet<SocketAddr>,
+    last_saved: Instant,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(path: PathBuf, arguments: Vec<String>) -> Self {
+        Self {
+            path,
+            arguments,
+            latest_cursor: 0,
+            settled_cursor: 0,
+            results: Vec::new(),
+            reported_addrs: HashSet::new(),
+            last_saved: Instant::now(),
+        }
+    }
+
+    /// Loads the checkpoint a scan left behind, returning it along with the arguments to run the scan again with
+    pub(crate) fn resume(path: PathBuf) -> (Self, Vec<String>) {
+        let checkpoint = read_checkpoint(&path).unwrap_or_else(|error| panic!("{:#}", error));
+        let reported_addrs = checkpoint.results.iter().map(|result| result.addr).collect();
+
+        let checkpointer = Self {
+            path,
+            arguments: checkpoint.arguments.clone(),
+            latest_cursor: checkpoint.scan_cursor,
+            settled_cursor: checkpoint.scan_cursor,
+            results: checkpoint.results,
+            reported_addrs,
+            last_saved: Instant::now(),
+        };
+
+        (checkpointer, checkpoint.arguments)
+    }
+
+    /// The file the checkpoint is saved to
+    #[cfg(unix)]
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn scan_cursor(&self) -> u128 {
+        self.settled_cursor
+    }
+
+    /// The results found before the scan was resumed, to be written out again along with the new ones
+    pub(crate) fn saved_results(&self) -> Vec<PortResult> {
+        self.results
+            .iter()
+            .filter_map(|result| {
+                let state = PortState::from_str(&result.state).ok()?;
+
+                Some(PortResult::new(result.addr, state).with_rtt(result.rtt).with_attempt(result.attempt))
+            })
+            .collect()
+    }
+
+    /// Keeps track of the scan's progress, leaving out results that were already reported before it was resumed
+    pub(crate) fn observe(&mut self, message: &mut ArmadaWorkMessage) {
+        match message {
+            ArmadaWorkMessage::Results(results) => {
+                results.retain(|result| !self.reported_addrs.contains(&result.addr));
+
+                self.results.extend(results.iter().map(|result| CheckpointResult {
+                    addr: result.addr,
+                    state: result.state.to_string(),
+                    rtt: result.rtt,
+                    attempt: result.attempt,
+                }));
+            }
+            ArmadaWorkMessage::Stats { scan_cursor, .. } => {
+                // a stats update is followed by the results it counts, so only the previous cursor is safe to save
+                self.settled_cursor = self.latest_cursor;
+                self.latest_cursor = *scan_cursor;
+
+                if self.last_saved.elapsed() >= CHECKPOINT_INTERVAL {
+                    self.save();
+                }
+            }
+            ArmadaWorkMessage::Failed(_) => {}
+        }
+    }
+
+    /// The scan completed, so there's nothing left to resume
+    pub(crate) fn finish(self) {
+        let _ = remove_file(&self.path);
+    }
+
+    /// The scan stopped before the end, so everything it got through is saved for `--resume` to carry on from
+    pub(crate) fn keep(mut self) {
+        // the scan is over, so the results of the latest stats update have all arrived
+        self.settled_cursor = self.latest_cursor;
+        self.save();
+    }
+
+    fn save(&mut self) {
+        let checkpoint = CheckpointFile {
+            arguments: self.arguments.clone(),
+            scan_cursor: self.settled_cursor,
+            results: self.results.clone(),
+        };
+
+        // written next to the checkpoint and moved over it, so a scan killed mid-write doesn't leave a broken one
+        let temporary_path = self.path.with_extension("tmp");
+        let contents = serde_json::to_string(&checkpoint).expect("Failed to serialize checkpoint.");
+
+        write(&temporary_path, contents)
+            .and_then(|_| rename(&temporary_path, &self.path))
+            .unwrap_or_else(|error| panic!("Unable to write checkpoint '{}': {}", self.path.display(), error));
+
+        self.last_saved = Instant::now();
+    }
+}
+
+fn read_checkpoint(path: &Path) -> anyhow::Result<CheckpointFile> {
+    let contents = read_to_string(path).with_context(|| format!("Unable to read checkpoint '{}'", path.display()))?;
+
+    serde_json::from_str(&contents).with_context(|| format!("Unable to parse checkpoint '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(scan_cursor: u128) -> ArmadaWorkMessage { ArmadaWorkMessage::stats(0, 0, 0, 0, 0, scan_cursor, None, Vec::new()) }
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("armada-checkpoint-test-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn only_the_cursor_before_the_latest_is_settled() {
+        let mut checkpointer = Checkpointer::new(checkpoint_path("cursor"), Vec::new());
+
+        checkpointer.observe(&mut stats(100));
+        assert_eq!(checkpointer.scan_cursor(), 0);
+
+        checkpointer.observe(&mut stats(250));
+        assert_eq!(checkpointer.scan_cursor(), 100);
+    }
+
+    #[test]
+    fn resumed_scans_start_from_the_saved_cursor_and_results() {
+        let path = checkpoint_path("resume");
+        let arguments = vec!["-t".to_string(), "192.0.2.0/24".to_string(), "-p".to_string(), "1-1000".to_string()];
+        let open_addr: SocketAddr = "192.0.2.1:22".parse().unwrap();
+
+        let mut checkpointer = Checkpointer::new(path.clone(), arguments.clone());
+        let mut results = ArmadaWorkMessage::results(vec![
+            PortResult::new(open_addr, PortState::Open).with_rtt(Some(Duration::from_millis(3))),
+        ]);
+        checkpointer.observe(&mut stats(10));
+        checkpointer.observe(&mut results);
+        checkpointer.observe(&mut stats(20));
+        checkpointer.save();
+
+        let (checkpointer, resumed_arguments) = Checkpointer::resume(path.clone());
+        let _ = remove_file(&path);
+
+        assert_eq!(resumed_arguments, arguments);
+        assert_eq!(checkpointer.scan_cursor(), 10);
+        assert_eq!(
+            checkpointer.saved_results(),
+            vec![PortResult::new(open_addr, PortState::Open).with_rtt(Some(Duration::from_millis(3)))],
+        );
+    }
+
+    #[test]
+    fn results_reported_before_resuming_are_left_out() {
+        let path = checkpoint_path("reported");
+        let reported_addr: SocketAddr = "192.0.2.1:22".parse().unwrap();
+        let new_addr: SocketAddr = "192.0.2.1:80".parse().unwrap();
+
+        let mut checkpointer = Checkpointer::new(path.clone(), Vec::new());
+        checkpointer.observe(&mut ArmadaWorkMessage::results(vec![PortResult::new(reported_addr, PortState::Open)]));
+        checkpointer.save();
+
+        let (mut checkpointer, _) = Checkpointer::resume(path.clone());
+        let mut results = ArmadaWorkMessage::results(vec![
+            PortResult::new(reported_addr, PortState::Open),
+            PortResult::new(new_addr, PortState::Open),
+        ]);
+        checkpointer.observe(&mut results);
+
+        assert!(matches!(&results, ArmadaWorkMessage::Results(results) if results.len() == 1 && results[0].addr == new_addr));
+        assert_eq!(checkpointer.results.len(), 2);
+
+        checkpointer.finish();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,349 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use cidr_utils::cidr::IpCidr;
+use regex::Regex;
+use serde::Deserialize;
+
+/// Every range AWS uses, tagged with its region and service
+const AWS_IP_RANGES_URL: &str = "https://ip-ranges.amazonaws.com/ip-ranges.json";
+/// The ranges of Google Cloud's customer-facing services, tagged with their region as the scope
+const GCP_IP_RANGES_URL: &str = "https://www.gstatic.com/ipranges/cloud.json";
+/// The page Azure's service tags are published on. The file itself is renamed every week, so its URL is read off here.
+const AZURE_SERVICE_TAGS_PAGE_URL: &str = "https://www.microsoft.com/en-us/download/details.aspx?id=56519";
+/// The files are a few megabytes, Azure's especially
+const CLOUD_LOOKUP_TIMEOUT: Duration = Duration::from_secs(60);
+pub(crate) const CLOUD_PROVIDERS: [&str; 3] = ["aws", "gcp", "azure"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CloudProvider {
+    Aws,
+    Gcp,
+    Azure,
+}
+
+/// A cloud provider's published ranges, narrowed down to a region and service, like `aws:us-east-1:EC2`
+#[derive(Clone, Debug)]
+pub(crate) struct CloudSource {
+    pub(crate) provider: CloudProvider,
+    pub(crate) region: Option<String>,
+    pub(crate) service: Option<String>,
+}
+
+impl CloudSource {
+    /// Parses `<provider>[:<region>[:<service>]]`, where a region that's left empty or `*` matches every region, e.g.
+    /// `aws::S3`
+    pub(crate) fn parse(source: &str) -> Option<Self> {
+        let mut parts = source.split(':');
+        let provider = match parts.next()?.to_lowercase().as_str() {
+            "aws" => CloudProvider::Aws,
+            "gcp" => CloudProvider::Gcp,
+            "azure" => CloudProvider::Azure,
+            _ => return None,
+        };
+        let mut filter = || parts.next().filter(|part| !part.is_empty() && *part != "*").map(str::to_string);
+        let (region, service) = (filter(), filter());
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Self { provider, region, service }),
+        }
+    }
+
+    /// Whether a range's region and one of its services are the ones asked for, ignoring case
+    fn matches(&self, region: &str, services: &[&str]) -> bool {
+        let region_matches = self.region.as_ref().is_none_or(|wanted| wanted.eq_ignore_ascii_case(region));
+        let service_matches =
+            self.service.as_ref().is_none_or(|wanted| services.iter().any(|service| wanted.eq_ignore_ascii_case(service)));
+
+        region_matches && service_matches
+    }
+}
+
+impl std::fmt::Display for CloudSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let provider = match self.provider {
+            CloudProvider::Aws => "aws",
+            CloudProvider::Gcp => "gcp",
+            CloudProvider::Azure => "azure",
+        };
+
+        match (&self.region, &self.service) {
+            (region, Some(service)) => write!(f, "{}:{}:{}", provider, region.as_deref().unwrap_or("*"), service),
+            (Some(region), None) => write!(f, "{}:{}", provider, region),
+            (None, None) => write!(f, "{}", provider),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AwsIpRanges {
+    prefixes: Vec<AwsPrefix>,
+    ipv6_prefixes: Vec<AwsIpv6Prefix>,
+}
+
+#[derive(Deserialize)]
+struct AwsPrefix {
+    ip_prefix: String,
+    region: String,
+    service: String,
+}
+
+#[derive(Deserialize)]
+struct AwsIpv6Prefix {
+    ipv6_prefix: String,
+    region: String,
+    service: String,
+}
+
+#[derive(Deserialize)]
+struct GcpIpRanges {
+    prefixes: Vec<GcpPrefix>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcpPrefix {
+    ipv4_prefix: Option<String>,
+    ipv6_prefix: Option<String>,
+    service: String,
+    scope: String,
+}
+
+#[derive(Deserialize)]
+struct AzureServiceTags {
+    values: Vec<AzureServiceTag>,
+}
+
+#[derive(Deserialize)]
+struct AzureServiceTag {
+    /// The tag, like `Storage.EastUS`, which is the service and region it's for
+    name: String,
+    properties: AzureServiceTagProperties,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureServiceTagProperties {
+    /// Empty for tags of every region
+    region: String,
+    system_service: String,
+    address_prefixes: Vec<String>,
+}
+
+/// Expands every cloud source into the IPv4 ranges its provider publishes for it. IPv6 ranges are left out, as they're
+/// far too large to sweep, like those of ASNs. Sources that can't be looked up are reported on stderr and skipped.
+pub(crate) async fn expand_cloud_sources(sources: Vec<CloudSource>) -> Vec<IpCidr> {
+    if sources.is_empty() {
+        return Vec::new();
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(CLOUD_LOOKUP_TIMEOUT)
+        .build()
+        .expect("Unable to create an HTTP client for cloud range lookups.");
+
+    let mut ranges = Vec::new();
+
+    for source in sources {
+        let published_ranges = match lookup_published_ranges(&client, &source).await {
+            Ok(published_ranges) => published_ranges,
+            Err(error) => {
+                eprintln!("Unable to look up the ranges of target '{}': {:#}. Skipping it.", source, error);
+                continue;
+            }
+        };
+
+        let (ipv4_ranges, ipv6_ranges): (Vec<_>, Vec<_>) =
+            published_ranges.into_iter().partition(|range| matches!(range, IpCidr::V4(_)));
+
+        if ipv4_ranges.is_empty() && ipv6_ranges.is_empty() {
+            eprintln!("No ranges are published for target '{}'. Please check the region and service names.", source);
+        }
+        if !ipv6_ranges.is_empty() {
+            eprintln!("Leaving out the {} IPv6 ranges published for {}.", ipv6_ranges.len(), source);
+        }
+
+        ranges.extend(ipv4_ranges);
+    }
+
+    ranges
+}
+
+async fn lookup_published_ranges(client: &reqwest::Client, source: &CloudSource) -> anyhow::Result<Vec<IpCidr>> {
+    let prefixes: Vec<String> = match source.provider {
+        CloudProvider::Aws => {
+            let ranges: AwsIpRanges = fetch_json(client, AWS_IP_RANGES_URL).await?;
+            let ipv4_prefixes = ranges
+                .prefixes
+                .into_iter()
+                .filter(|prefix| source.matches(&prefix.region, &[&prefix.service]))
+                .map(|prefix| prefix.ip_prefix);
+            let ipv6_prefixes = ranges
+                .ipv6_prefixes
+                .into_iter()
+                .filter(|prefix| source.matches(&prefix.region, &[&prefix.service]))
+                .map(|prefix| prefix.ipv6_prefix);
+
+            ipv4_prefixes.chain(ipv6_prefixes).collect()
+        }
+        CloudProvider::Gcp => {
+            let ranges: GcpIpRanges = fetch_json(client, GCP_IP_RANGES_URL).await?;
+
+            ranges
+                .prefixes
+                .into_iter()
+                .filter(|prefix| source.matches(&prefix.scope, &[&prefix.service]))
+                .flat_map(|prefix| prefix.ipv4_prefix.into_iter().chain(prefix.ipv6_prefix))
+                .collect()
+        }
+        CloudProvider::Azure => {
+            let page = fetch_text(client, AZURE_SERVICE_TAGS_PAGE_URL).await?;
+            let url = Regex::new(r"https://download\.microsoft\.com/download/[^\x22']+/ServiceTags_Public_\d+\.json")
+                .expect("Invalid service tags URL pattern")
+                .find(&page)
+                .context("the download page doesn't link to the service tags")?
+                .as_str()
+                .to_string();
+            let tags: AzureServiceTags = fetch_json(client, &url).await?;
+
+            // the tag names the service too, as in Storage.EastUS, and AzureCloud.eastus has no system service
+            tags.values
+                .into_iter()
+                .filter(|tag| {
+                    let tag_service = tag.name.split('.').next().unwrap_or_default();
+                    source.matches(&tag.properties.region, &[tag_service, &tag.properties.system_service])
+                })
+                .flat_map(|tag| tag.properties.address_prefixes)
+                .collect()
+        }
+    };
+
+    let ranges: Vec<IpCidr> = prefixes
+        .into_iter()
+        .map(|prefix| {
+            IpCidr::from_str(&prefix).map_err(|_| anyhow::anyhow!("the provider published an invalid range '{}'", prefix))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(distinct_ranges(ranges))
+}
+
+/// The same range is often published for several services, or within a larger one of the whole provider, so only the
+/// ranges no other one takes in are kept, for no address to be scanned twice
+fn distinct_ranges(mut ranges: Vec<IpCidr>) -> Vec<IpCidr> {
+    ranges.sort_by_key(|range| std::cmp::Reverse(range.size()));
+
+    let mut distinct_ranges: Vec<IpCidr> = Vec::new();
+    for range in ranges {
+        if !distinct_ranges.iter().any(|distinct_range| distinct_range.contains(range.first_as_ip_addr())) {
+            distinct_ranges.push(range);
+        }
+    }
+
+    distinct_ranges
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(client: &reqwest::Client, url: &str) -> anyhow::Result<T> {
+    client
+        .get(url)
+        .send()
+        .await
+        .context("request failed")?
+        .error_for_status()
+        .context("the provider returned an error")?
+        .json()
+        .await
+        .context("the provider returned an unexpected response")
+}
+
+async fn fetch_text(client: &reqwest::Client, url: &str) -> anyhow::Result<String> {
+    client
+        .get(url)
+        .send()
+        .await
+        .context("request failed")?
+        .error_for_status()
+        .context("the provider returned an error")?
+        .text()
+        .await
+        .context("the download was cut short")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(cidrs: &[&str]) -> Vec<IpCidr> {
+        cidrs.iter().map(|cidr| IpCidr::from_str(cidr).unwrap()).collect()
+    }
+
+    #[test]
+    fn sources_are_a_provider_with_an_optional_region_and_service() {
+        let source = CloudSource::parse("AWS:us-east-1:EC2").unwrap();
+        assert_eq!(source.provider, CloudProvider::Aws);
+        assert_eq!((source.region.as_deref(), source.service.as_deref()), (Some("us-east-1"), Some("EC2")));
+
+        let source = CloudSource::parse("gcp").unwrap();
+        assert_eq!((source.provider, source.region, source.service), (CloudProvider::Gcp, None, None));
+
+        for source in ["aws::S3", "aws:*:S3"] {
+            let source = CloudSource::parse(source).unwrap();
+            assert_eq!((source.region, source.service.as_deref()), (None, Some("S3")));
+        }
+    }
+
+    #[test]
+    fn unknown_providers_and_extra_parts_are_rejected() {
+        for source in ["oracle", "", "aws:us-east-1:EC2:extra", "AS13335"] {
+            assert!(CloudSource::parse(source).is_none(), "{} parsed", source);
+        }
+    }
+
+    #[test]
+    fn sources_are_written_back_the_way_they_parse() {
+        for source in ["aws", "azure:westeurope", "gcp:*:Google Cloud", "aws:us-east-1:EC2"] {
+            assert_eq!(CloudSource::parse(source).unwrap().to_string(), source);
+        }
+        assert_eq!(CloudSource::parse("aws::S3").unwrap().to_string(), "aws:*:S3");
+    }
+
+    #[test]
+    fn regions_and_services_match_regardless_of_case() {
+        let source = CloudSource::parse("azure:eastus:storage").unwrap();
+
+        assert!(source.matches("EastUS", &["AzureCloud", "Storage"]));
+        assert!(!source.matches("westus", &["Storage"]));
+        assert!(!source.matches("eastus", &["AzureCloud"]));
+        assert!(CloudSource::parse("azure").unwrap().matches("", &[]));
+    }
+
+    #[test]
+    fn ranges_within_others_are_left_out() {
+        let ranges = cidrs(&["3.5.140.0/24", "3.5.0.0/16", "3.5.140.0/22", "52.94.76.0/22", "52.94.76.0/22"]);
+
+        assert_eq!(distinct_ranges(ranges), cidrs(&["3.5.0.0/16", "52.94.76.0/22"]));
+    }
+
+    #[test]
+    fn published_ranges_are_read_from_each_providers_format() {
+        let aws: AwsIpRanges = serde_json::from_str(
+            r#"{"syncToken": "1", "prefixes": [{"ip_prefix": "3.5.140.0/22", "region": "ap-northeast-2", "service": "AMAZON", "network_border_group": "ap-northeast-2"}],
+            "ipv6_prefixes": [{"ipv6_prefix": "2600:1f14::/35", "region": "us-west-2", "service": "EC2", "network_border_group": "us-west-2"}]}"#,
+        )
+        .unwrap();
+        assert_eq!((aws.prefixes[0].ip_prefix.as_str(), aws.ipv6_prefixes[0].service.as_str()), ("3.5.140.0/22", "EC2"));
+
+        let gcp: GcpIpRanges = serde_json::from_str(
+            r#"{"prefixes": [{"ipv4Prefix": "34.1.208.0/20", "service": "Google Cloud", "scope": "africa-south1"}, {"ipv6Prefix": "2600:1900:8000::/44", "service": "Google Cloud", "scope": "us-east1"}]}"#,
+        )
+        .unwrap();
+        assert_eq!((gcp.prefixes[0].ipv4_prefix.as_deref(), gcp.prefixes[1].ipv4_prefix.as_deref()), (Some("34.1.208.0/20"), None));
+
+        let azure: AzureServiceTags = serde_json::from_str(
+            r#"{"changeNumber": 1, "values": [{"name": "Storage.EastUS", "id": "Storage.EastUS", "properties": {"region": "eastus", "systemService": "AzureStorage", "addressPrefixes": ["20.38.98.0/24"]}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(azure.values[0].properties.address_prefixes, ["20.38.98.0/24"]);
+    }
+}
@@ -0,0 +1,68 @@
+use clap::{Arg, Command};
+use clap_complete::Shell;
+
+/// The shells `armada completions` writes scripts for
+pub(crate) const SHELL_NAMES: [&str; 5] = ["bash", "zsh", "fish", "powershell", "elvish"];
+
+/// Writes the completion script for the shell, generated from the command. `value_hints` adds values to offer for
+/// options that take anything, like the output formats `--output` takes alongside its connection strings, which the
+/// command can't list without refusing the rest.
+pub(crate) fn generate(shell: Shell, command: Command<'static>, value_hints: &[(&'static str, &[&'static str])]) -> String {
+    let mut command = value_hints.iter().fold(command, |command, (id, values)| {
+        command.mut_arg(*id, |arg| arg.possible_values(values.iter().copied()))
+    });
+
+    let scan_options: Vec<Arg<'static>> =
+        command.get_arguments().filter(|arg| !arg.is_positional() && !arg.is_hide_set()).map(scan_option).collect();
+    add_scan_options(&mut command, &scan_options);
+
+    let name = command.get_name().to_string();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut script);
+
+    String::from_utf8(script).expect("Completion scripts are valid UTF-8.")
+}
+
+/// Subcommands that take the arguments of a scan, like `estimate`, complete the scan's options as well as their own
+fn add_scan_options(command: &mut Command<'static>, scan_options: &[Arg<'static>]) {
+    for subcommand in command.get_subcommands_mut() {
+        if subcommand.get_arguments().any(|arg| arg.get_id() == "scan_arguments") {
+            let own_options: Vec<Arg<'static>> = subcommand.get_arguments().cloned().collect();
+            let scan_options = scan_options.iter().filter(|scan_option| {
+                !own_options.iter().any(|own_option| {
+                    own_option.get_id() == scan_option.get_id()
+                        || (own_option.get_long().is_some() && own_option.get_long() == scan_option.get_long())
+                        || (own_option.get_short().is_some() && own_option.get_short() == scan_option.get_short())
+                })
+            });
+
+            *subcommand = std::mem::take(subcommand).args(scan_options.cloned());
+        }
+
+        add_scan_options(subcommand, scan_options);
+    }
+}
+
+/// An option of the scan as it's completed, without the conflicts and requirements on the scan's other arguments that
+/// only hold on the command it's defined on
+fn scan_option(arg: &Arg<'static>) -> Arg<'static> {
+    let mut option = Arg::new(arg.get_id()).takes_value(arg.is_takes_value_set());
+
+    if let Some(long) = arg.get_long() {
+        option = option.long(long);
+    }
+    if let Some(short) = arg.get_short() {
+        option = option.short(short);
+    }
+    if let Some(help) = arg.get_help() {
+        option = option.help(help);
+    }
+    if let Some(possible_values) = arg.get_possible_values() {
+        option = option.possible_values(possible_values.iter().cloned());
+    }
+    if arg.is_multiple_occurrences_set() {
+        option = option.multiple_occurrences(true);
+    }
+
+    option.value_hint(arg.get_value_hint())
+}
@@ -1,17 +1,34 @@
 use std::collections::HashMap;
 use std::fs::read_to_string;
 
+use clap::{ArgMatches, Command, ValueSource};
 use toml::value::Value;
 
-pub fn get_toml_config(toml_path: String) -> Vec<String> {
-    let toml_contents = read_to_string(&toml_path).expect(&format!("failed to read toml file at {}", &toml_path));
-    let parsed: HashMap<String, Value> =
-        toml::from_str(&toml_contents).expect(&format!("failed to parse toml file at {}", &toml_path));
-    let mut clap_args: Vec<String> = vec!["armada".to_string()];
-    for (key, val) in &parsed {
-        clap_args.append(&mut get_flag(key, val));
+/// Turns the settings in the TOML file into the command line arguments they stand for, alongside the key each came
+/// from. Settings at the top of the file apply to every profile, and the `[profile.<name>]` table picked with `profile`
+/// overrides them.
+pub fn get_toml_config(toml_path: String, profile: Option<&str>) -> Vec<(String, Vec<String>)> {
+    let toml_contents = read_to_string(&toml_path).unwrap_or_else(|_| panic!("failed to read toml file at {}", &toml_path));
+    let mut parsed: HashMap<String, Value> =
+        toml::from_str(&toml_contents).unwrap_or_else(|_| panic!("failed to parse toml file at {}", &toml_path));
+    let profiles = match parsed.remove("profile") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => panic!("profile in {} must be a table of named profiles, e.g. [profile.internal]", &toml_path),
+        None => Default::default(),
+    };
+    if let Some(profile) = profile {
+        match profiles.get(profile) {
+            Some(Value::Table(settings)) => parsed.extend(settings.clone()),
+            Some(_) => panic!("profile.{} in {} must be a table of settings", profile, &toml_path),
+            None => {
+                let mut names: Vec<&String> = profiles.keys().collect();
+                names.sort();
+                let names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+                panic!("no profile named {} in {}, the profiles are: {}", profile, &toml_path, names.join(", "))
+            }
+        }
     }
-    clap_args
+    parsed.iter().map(|(key, val)| (key.to_owned(), get_flag(key, val))).collect()
 }
 
 fn get_flag(key: &String, val: &Value) -> Vec<String> {
@@ -27,7 +44,7 @@ fn get_flag(key: &String, val: &Value) -> Vec<String> {
     arg
 }
 
-fn unpack_array_args(arr: &Vec<Value>) -> String {
+fn unpack_array_args(arr: &[Value]) -> String {
     let mut arg = "".to_string();
     for (i, v) in arr.iter().enumerate() {
         arg.push_str(&match v {
@@ -36,8 +53,84 @@ fn unpack_array_args(arr: &Vec<Value>) -> String {
             _ => panic!("Incorrect type found in TOML, array values must be Strings or Integers"),
         });
         if i != arr.len() - 1 {
-            arg.push_str(",")
+            arg.push(',')
         }
     }
     arg
 }
+
+/// The flags that only make sense on the command line, which a config file can't set
+const COMMAND_LINE_ONLY: [&str; 6] = ["help", "version", "toml_config", "profile", "resume", "sanic"];
+
+/// How wide the comments of a generated config are wrapped to
+const COMMENT_WIDTH: usize = 100;
+
+/// Writes out a TOML config with a key for every flag of `command`, each commented with its help. The flags set in
+/// `matches` are filled in, and the rest are left commented out for whoever edits the file.
+pub fn init_config(command: &Command, matches: &ArgMatches) -> String {
+    let mut config = String::from("# armada config, used with: armada --toml-config <this file>\n");
+    config.push_str("# Flags given on the command line or through ARMADA_* environment variables override these settings.\n");
+
+    let args = command.get_arguments().filter(|arg| !arg.is_hide_set() && !COMMAND_LINE_ONLY.contains(&arg.get_id()));
+    for arg in args {
+        let Some(key) = arg.get_long() else { continue };
+
+        config.push('\n');
+        for line in wrap_comment(arg.get_help().unwrap_or_default()) {
+            config.push_str(&format!("# {}\n", line));
+        }
+
+        let given = matches!(matches.value_source(arg.get_id()), Some(ValueSource::CommandLine | ValueSource::EnvVariable));
+        match (given, arg.is_takes_value_set()) {
+            (true, false) => config.push_str(&format!("{} = true\n", key)),
+            (true, true) => {
+                let values: Vec<Value> = matches
+                    .values_of(arg.get_id())
+                    .into_iter()
+                    .flatten()
+                    .map(toml_value)
+                    .collect();
+                let value = match (values.len(), arg.is_multiple_values_set()) {
+                    (1, false) => values[0].clone(),
+                    _ => Value::Array(values),
+                };
+                config.push_str(&format!("{} = {}\n", key, value));
+            }
+            (false, false) => config.push_str(&format!("# {} = false\n", key)),
+            (false, true) if arg.is_multiple_values_set() => config.push_str(&format!("# {} = []\n", key)),
+            (false, true) => {
+                // the first of the values it takes shows what sort of value goes there
+                let example = arg.get_possible_values().and_then(|values| values.first()).map(|value| value.get_name());
+                config.push_str(&format!("# {} = {}\n", key, Value::String(example.unwrap_or_default().to_string())));
+            }
+        }
+    }
+
+    config.push_str("\n# Settings for one kind of scan can be kept in a profile and picked with --profile <name>, overriding the\n");
+    config.push_str("# settings above.\n# [profile.<name>]\n# rate-limit = 1000\n");
+    config
+}
+
+/// Numbers are written as TOML integers and everything else as strings
+fn toml_value(value: &str) -> Value {
+    match value.parse::<i64>() {
+        Ok(integer) => Value::Integer(integer),
+        Err(_) => Value::String(value.to_string()),
+    }
+}
+
+fn wrap_comment(text: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for word in text.split_whitespace() {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.len() + 1 + word.len() > COMMENT_WIDTH {
+            lines.push(word.to_string());
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+    }
+    lines
+}
@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use armada_lib::Armada;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::status::ScanStatus;
+
+/// Longer lines than this aren't commands, and the connection is dropped
+const MAX_COMMAND_BYTES: u64 = 1024;
+
+/// The socket `--control-socket` takes commands on while the scan runs
+pub(crate) struct ControlSocket {
+    path: PathBuf,
+    status: Arc<ScanStatus>,
+}
+
+/// The reply to `status`
+#[derive(Debug, Serialize)]
+struct StatusReply {
+    /// `running`, `paused` or `stopping`
+    state: &'static str,
+    percent: f64,
+    pairs_done: u128,
+    total_pairs: u128,
+    results: u128,
+    open_ports: u128,
+    packets_sent: u128,
+    packets_per_second: f64,
+    inflight_packets: u128,
+    retries: u128,
+    /// `None` is unlimited
+    rate_limit: Option<usize>,
+    elapsed_seconds: f64,
+    eta_seconds: Option<f64>,
+}
+
+impl ControlSocket {
+    /// Listens on the socket and carries out the commands sent to it on the armada, until the scan exits. A socket left
+    /// behind by an earlier scan is replaced.
+    pub(crate) fn serve(path: PathBuf, armada: Armada, status: Arc<ScanStatus>) -> Arc<Self> {
+        let listener = bind(&path).unwrap_or_else(|error| panic!("Unable to listen on control socket '{}': {}", path.display(), error));
+
+        let control_socket = Arc::new(Self { path, status });
+
+        let server = control_socket.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(stream, armada.clone(), server.clone()));
+            }
+        });
+
+        control_socket
+    }
+
+    /// Takes the socket away once the scan is over
+    pub(crate) fn finish(&self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+
+    /// The status as a line of JSON, which is just the state until discovery and looking up hostnames are done
+    fn status(&self, armada: &Armada) -> String {
+        let state = match (armada.is_stopped(), armada.is_paused()) {
+            (true, _) => "stopping",
+            (false, true) => "paused",
+            (false, false) => "running",
+        };
+
+        let Some(snapshot) = self.status.snapshot(armada) else {
+            return serde_json::json!({ "state": "starting" }).to_string();
+        };
+
+        let reply = StatusReply {
+            state,
+            percent: round(snapshot.percent()),
+            pairs_done: snapshot.pairs_done,
+            total_pairs: snapshot.total_pairs,
+            results: snapshot.results,
+            open_ports: snapshot.open_ports,
+            packets_sent: snapshot.packets_sent,
+            packets_per_second: round(snapshot.packets_per_second),
+            inflight_packets: snapshot.inflight_packets,
+            retries: snapshot.retries,
+            rate_limit: snapshot.rate_limit,
+            elapsed_seconds: round(snapshot.elapsed.as_secs_f64()),
+            eta_seconds: snapshot.eta.map(|eta| round(eta.as_secs_f64())),
+        };
+
+        serde_json::to_string(&reply).expect("Failed to serialize status.")
+    }
+
+    /// Carries out one line sent to the socket and gives back the line to answer with
+    fn run_command(&self, armada: &Armada, line: &str) -> String {
+        let mut words = line.split_whitespace();
+
+        match (words.next(), words.next(), words.next()) {
+            (Some("status"), None, _) => self.status(armada),
+            (Some("set-rate"), Some(rate), None) => match rate.parse::<usize>() {
+                Ok(packets_per_second) if packets_per_second > 0 => {
+                    armada.set_rate_limit(packets_per_second);
+                    format!("ok rate limit set to {} pps", packets_per_second)
+                }
+                _ => format!("error: '{}' isn't a rate in packets per second", rate),
+            },
+            (Some("set-rate"), None, _) => "error: set-rate takes the packets per second to send at".to_string(),
+            (Some("pause"), None, _) => {
+                armada.pause();
+                "ok paused".to_string()
+            }
+            (Some("resume"), None, _) => {
+                armada.resume();
+                "ok resumed".to_string()
+            }
+            (Some("abort"), None, _) => {
+                armada.stop();
+                "ok stopping once the probes in flight are settled".to_string()
+            }
+            (Some(command), _, _) if ["status", "set-rate", "pause", "resume", "abort"].contains(&command) => {
+                format!("error: too many arguments for {}", command)
+            }
+            (Some(command), _, _) => format!("error: unknown command '{}', expected status, set-rate, pause, resume or abort", command),
+            (None, _, _) => "error: expected a command".to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind(path: &Path) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // only a socket is replaced, so a typo can't take out some other file
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+
+    tokio::net::UnixListener::bind(path)
+}
+
+#[cfg(not(unix))]
+fn bind(_path: &Path) -> std::io::Result<tokio::net::TcpListener> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix domain sockets are only available on unix"))
+}
+
+/// Answers each line sent over the connection in turn, until the other end hangs up
+async fn serve_connection<S>(stream: S, armada: Armada, control_socket: Arc<ControlSocket>)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        match (&mut reader).take(MAX_COMMAND_BYTES).read_line(&mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) if !line.ends_with('\n') && line.len() as u64 >= MAX_COMMAND_BYTES => return,
+            Ok(_) => {}
+        }
+
+        let reply = control_socket.run_command(&armada, line.trim());
+        if writer.write_all(format!("{}\n", reply).as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Rounds to two decimals, which is as precise as the status gets
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
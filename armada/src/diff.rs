@@ -0,0 +1,180 @@
+use std::collections::BTreeSet;
+use std::fs::read_to_string;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// The parts of a result a diff looks at. Every other field is ignored, so results written with any combination of
+/// `--banners`, `--tls-info` and friends can be compared.
+#[derive(Deserialize)]
+struct DiffRecord {
+    ip: IpAddr,
+    port: u16,
+    state: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+struct Endpoint {
+    ip: IpAddr,
+    port: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DiffFormat {
+    /// One `opened ip:port`, `closed ip:port` or `new-host ip` line per change
+    Default,
+    Json,
+}
+
+pub(crate) struct DiffOptions {
+    pub(crate) old_results: PathBuf,
+    pub(crate) new_results: PathBuf,
+    pub(crate) format: DiffFormat,
+}
+
+#[derive(Serialize)]
+struct ScanDiff {
+    /// Ports open in the new results that weren't open in the old ones
+    opened: Vec<Endpoint>,
+    /// Ports open in the old results that aren't open in the new ones
+    closed: Vec<Endpoint>,
+    /// Hosts in the new results that don't appear in the old ones at all
+    new_hosts: Vec<IpAddr>,
+}
+
+impl ScanDiff {
+    fn is_empty(&self) -> bool {
+        self.opened.is_empty() && self.closed.is_empty() && self.new_hosts.is_empty()
+    }
+}
+
+/// Compares two result files and prints what changed. Like diff(1), the exit code is 0 when nothing changed and 1
+/// when something did.
+pub(crate) fn run(options: DiffOptions) -> i32 {
+    let old_records = read_results(&options.old_results).unwrap_or_else(|error| panic!("{:#}", error));
+    let new_records = read_results(&options.new_results).unwrap_or_else(|error| panic!("{:#}", error));
+
+    let scan_diff = diff(&old_records, &new_records);
+
+    match options.format {
+        DiffFormat::Default => {
+            scan_diff.opened.iter().for_each(|endpoint| println!("opened {}:{}", endpoint.ip, endpoint.port));
+            scan_diff.closed.iter().for_each(|endpoint| println!("closed {}:{}", endpoint.ip, endpoint.port));
+            scan_diff.new_hosts.iter().for_each(|ip| println!("new-host {}", ip));
+        }
+        DiffFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&scan_diff).expect("Failed to serialize diff."));
+        }
+    }
+
+    if scan_diff.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+fn diff(old_records: &[DiffRecord], new_records: &[DiffRecord]) -> ScanDiff {
+    let open_endpoints = |records: &[DiffRecord]| -> BTreeSet<Endpoint> {
+        records
+            .iter()
+            .filter(|record| record.state == "open")
+            .map(|record| Endpoint { ip: record.ip, port: record.port })
+            .collect()
+    };
+    let hosts = |records: &[DiffRecord]| -> BTreeSet<IpAddr> { records.iter().map(|record| record.ip).collect() };
+
+    let (old_open, new_open) = (open_endpoints(old_records), open_endpoints(new_records));
+    let old_hosts = hosts(old_records);
+
+    ScanDiff {
+        opened: new_open.difference(&old_open).copied().collect(),
+        closed: old_open.difference(&new_open).copied().collect(),
+        new_hosts: hosts(new_records).difference(&old_hosts).copied().collect(),
+    }
+}
+
+/// Reads the output of `-o json` or `-o ndjson`
+fn read_results(path: &Path) -> anyhow::Result<Vec<DiffRecord>> {
+    let contents = read_to_string(path).with_context(|| format!("Unable to read results file '{}'", path.display()))?;
+
+    if contents.trim_start().starts_with('[') {
+        return serde_json::from_str(&contents)
+            .with_context(|| format!("Unable to parse results file '{}'", path.display()));
+    }
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Unable to parse line {} of results file '{}'", index + 1, path.display()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(ndjson: &str) -> Vec<DiffRecord> {
+        ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    fn endpoint(addr: &str) -> Endpoint {
+        let addr: std::net::SocketAddr = addr.parse().unwrap();
+
+        Endpoint { ip: addr.ip(), port: addr.port() }
+    }
+
+    #[test]
+    fn changes_in_open_ports_and_hosts_are_found() {
+        let old_records = records(
+            r#"{"ip":"192.0.2.1","port":22,"state":"open"}
+{"ip":"192.0.2.1","port":80,"state":"open"}
+{"ip":"192.0.2.2","port":443,"state":"closed"}"#,
+        );
+        let new_records = records(
+            r#"{"ip":"192.0.2.1","port":22,"state":"open"}
+{"ip":"192.0.2.1","port":80,"state":"filtered"}
+{"ip":"192.0.2.2","port":443,"state":"open"}
+{"ip":"2001:db8::1","port":22,"state":"open","banner":"SSH-2.0-OpenSSH_9.6"}"#,
+        );
+
+        let scan_diff = diff(&old_records, &new_records);
+
+        assert_eq!(scan_diff.opened, [endpoint("192.0.2.2:443"), endpoint("[2001:db8::1]:22")]);
+        assert_eq!(scan_diff.closed, [endpoint("192.0.2.1:80")]);
+        assert_eq!(scan_diff.new_hosts, ["2001:db8::1".parse::<IpAddr>().unwrap()]);
+        assert!(!scan_diff.is_empty());
+    }
+
+    #[test]
+    fn identical_results_have_no_changes() {
+        let results = r#"{"ip":"192.0.2.1","port":22,"state":"open"}"#;
+
+        assert!(diff(&records(results), &records(results)).is_empty());
+    }
+
+    #[test]
+    fn json_and_ndjson_results_are_both_read() {
+        let directory = std::env::temp_dir();
+        let json_path = directory.join(format!("armada-diff-test-{}.json", std::process::id()));
+        let ndjson_path = directory.join(format!("armada-diff-test-{}.ndjson", std::process::id()));
+        std::fs::write(&json_path, "[\n  {\"ip\": \"192.0.2.1\", \"port\": 22, \"state\": \"open\"}\n]\n").unwrap();
+        std::fs::write(&ndjson_path, "{\"ip\":\"192.0.2.1\",\"port\":22,\"state\":\"open\"}\n\nnot json\n").unwrap();
+
+        let json_records = read_results(&json_path).unwrap();
+        let ndjson_error = read_results(&ndjson_path).err().unwrap();
+        let _ = std::fs::remove_file(&json_path);
+        let _ = std::fs::remove_file(&ndjson_path);
+
+        assert_eq!(json_records.len(), 1);
+        assert_eq!(json_records[0].port, 22);
+        assert!(ndjson_error.to_string().starts_with("Unable to parse line 3 of results file"));
+        assert!(read_results(Path::new("/nonexistent/results.json")).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+use crate::distributed::{AgentMessage, ControllerMessage};
+#[cfg(unix)]
+use crate::privileges::RunAs;
+use crate::serve::{job_manager, Job, JobManager, SubmitError};
+
+/// How long to wait between attempts to reach the controller while it isn't up yet
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+/// How often progress is sent while a shard's being scanned
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which controller `armada agent` takes its shards from, and what it scans them from
+pub(crate) struct AgentConfig {
+    /// `host:port`
+    pub(crate) controller: String,
+    pub(crate) listening_port: u16,
+    pub(crate) source_ips: Option<Vec<IpAddr>>,
+    /// Who to switch to once the engine's raw sockets are open
+    #[cfg(unix)]
+    pub(crate) run_as: Option<RunAs>,
+}
+
+/// Scans the shards the controller hands out until the controller's done with it
+pub(crate) async fn run(config: AgentConfig) {
+    let AgentConfig { controller, listening_port, source_ips, #[cfg(unix)] run_as } = config;
+
+    crate::logging::init(0, None);
+    let jobs = job_manager(listening_port, source_ips).await;
+
+    // every shard is scanned on the engine set up above, so nothing after this takes root
+    #[cfg(unix)]
+    if let Some(run_as) = &run_as {
+        crate::privileges::drop_privileges(run_as);
+    }
+
+    // agents can be started ahead of the controller, and wait for it
+    let stream = loop {
+        match TcpStream::connect(&controller).await {
+            Ok(stream) => break stream,
+            Err(error) => {
+                info!(%controller, %error, "waiting for the controller");
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+            }
+        }
+    };
+    eprintln!("Connected to the controller at {}", controller);
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let ControllerMessage::Scan { shard, request } = serde_json::from_str(&line)
+            .unwrap_or_else(|error| panic!("Unable to read the controller's message '{}': {}", line, error));
+
+        let scanned = match jobs.submit(request) {
+            Ok(job) => scan_shard(&jobs, job, shard, &mut writer).await,
+            Err(SubmitError::Invalid(error)) => send(&mut writer, &AgentMessage::Failed { shard, error }).await,
+            Err(SubmitError::Engine(error)) => {
+                send(&mut writer, &AgentMessage::Failed { shard, error: format!("{:#}", error) }).await
+            }
+        };
+
+        if !scanned {
+            warn!(shard, "lost the connection to the controller");
+            break;
+        }
+    }
+
+    eprintln!("The controller at {} closed the connection.", controller);
+}
+
+/// Sends the shard's results back as they're found, followed by `done` once it's over. Returns whether the controller
+/// could be reached the whole time, cancelling the scan if it couldn't.
+async fn scan_shard(jobs: &JobManager, job: Arc<Job>, shard: usize, writer: &mut OwnedWriteHalf) -> bool {
+    let mut changes = job.subscribe();
+    let mut sent = 0;
+    let mut last_progress = Instant::now();
+
+    let reached = loop {
+        let (records, finished) = job.records_after(sent);
+        sent += records.len();
+
+        let mut messages: Vec<AgentMessage> = records
+            .into_iter()
+            .map(|record| AgentMessage::Result {
+                shard,
+                ip: record.ip,
+                port: record.port,
+                state: record.state.as_str().to_string(),
+                rtt: record.rtt_millis(),
+                attempt: record.attempt,
+            })
+            .collect();
+        if finished {
+            messages.push(AgentMessage::Done { shard });
+        } else if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            messages.push(AgentMessage::Progress { shard, percent: job.status().percent });
+            last_progress = Instant::now();
+        }
+
+        let mut reached = true;
+        for message in &messages {
+            reached = reached && send(writer, message).await;
+        }
+
+        if !reached || finished {
+            break reached;
+        }
+
+        let _ = tokio::time::timeout(PROGRESS_INTERVAL, changes.changed()).await;
+    };
+
+    if !reached {
+        job.cancel();
+    }
+    jobs.remove(job.id());
+
+    reached
+}
+
+async fn send(writer: &mut OwnedWriteHalf, message: &AgentMessage) -> bool {
+    let mut line = serde_json::to_string(message).expect("Unable to serialize a message for the controller.");
+    line.push('\n');
+
+    writer.write_all(line.as_bytes()).await.is_ok()
+}
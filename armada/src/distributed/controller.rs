@@ -0,0 +1,247 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use armada_lib::{HostIterator, PortResult, PortState, ResultSink};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::args::ArmadaConfig;
+use crate::distributed::{plan_shards, AgentMessage, ControllerMessage, Shard};
+use crate::output::{NmapScanInfo, ResultWriter, ScanRecord, WebhookSink};
+use crate::plan::scan_type_name;
+use crate::serve::JobRequest;
+use crate::{asn, cloud, exclude_list, exit, logging, resolve};
+
+/// An agent that hasn't sent anything for this long is taken to have failed, and its shard is handed to another
+const AGENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What `armada controller` listens for agents on and the scan it splits up between them
+pub(crate) struct ControllerConfig {
+    pub(crate) listen: SocketAddr,
+    pub(crate) shards: usize,
+    pub(crate) scan: Box<ArmadaConfig>,
+}
+
+/// The shards still to be handed out, and how many of them aren't done yet
+struct Dispatch {
+    state: Mutex<(VecDeque<Shard>, usize)>,
+    changed: Notify,
+}
+
+impl Dispatch {
+    /// Waits for a shard to hand out, until every shard is done
+    async fn next(&self) -> Option<Shard> {
+        loop {
+            let changed = self.changed.notified();
+
+            {
+                let mut state = self.state.lock().expect("Dispatch lock poisoned.");
+                if let Some(shard) = state.0.pop_front() {
+                    return Some(shard);
+                }
+                if state.1 == 0 {
+                    return None;
+                }
+            }
+
+            // a failed agent's shard comes back to be handed out again
+            changed.await;
+        }
+    }
+
+    fn done(&self) {
+        self.state.lock().expect("Dispatch lock poisoned.").1 -= 1;
+        self.changed.notify_waiters();
+    }
+
+    fn reassign(&self, shard: Shard) {
+        self.state.lock().expect("Dispatch lock poisoned.").0.push_front(shard);
+        self.changed.notify_waiters();
+    }
+}
+
+/// Splits the scan into shards, hands them out to the agents that connect and writes out what they find as one scan
+pub(crate) async fn run(config: ControllerConfig) {
+    let ControllerConfig { listen, shards, scan } = config;
+    let ArmadaConfig {
+        targets,
+        hostname_targets,
+        asn_targets,
+        cloud_targets,
+        skip_invalid,
+        exclusions,
+        exclude_urls,
+        exclude_url_max_age,
+        scope,
+        resolve_timeout,
+        ports,
+        verbosity,
+        log_file,
+        rate_limit,
+        retries,
+        timeout,
+        scan_type,
+        report_all_states,
+        stream_results,
+        output_format,
+        csv_fields,
+        csv_header,
+        sink_target,
+        output_file,
+        output_basename,
+        webhook_url,
+        webhook_batch_size,
+        ..
+    } = *scan;
+
+    logging::init(verbosity, log_file);
+
+    let resolved_hostnames = resolve::resolve_hostnames(hostname_targets, resolve_timeout).await;
+    let targets = targets.add_ips(resolved_hostnames.iter().map(|(ip, _)| *ip).collect());
+    let targets = asn::expand_asns(asn_targets).await.into_iter().fold(targets, HostIterator::add_cidr);
+    let targets = cloud::expand_cloud_sources(cloud_targets).await.into_iter().fold(targets, HostIterator::add_cidr);
+    // lookups that all failed leave a scan of nothing, which would otherwise pass for a scan that found nothing open
+    if targets.size() == 0 && !skip_invalid {
+        panic!("None of the targets could be looked up, so there's nothing to scan. Pass --skip-invalid to go ahead anyway.");
+    }
+    let exclusions = exclusions.into_iter().chain(exclude_list::fetch_exclusions(&exclude_urls, exclude_url_max_age).await);
+    let targets = exclusions.fold(targets, HostIterator::exclude_cidr);
+    let targets = match &scope {
+        Some(scope) => scope.enforce(targets),
+        None => targets,
+    };
+    let hostnames: Arc<HashMap<IpAddr, String>> = Arc::new(resolved_hostnames.into_iter().collect());
+
+    let shards = plan_shards(&targets, &ports, shards);
+    let shard_count = shards.len();
+    let template = Arc::new(JobRequest {
+        targets: Vec::new(),
+        ports: String::new(),
+        rate_limit: Some(rate_limit.unwrap_or(0)),
+        retries: Some(retries),
+        timeout_ms: Some(timeout.as_millis() as u64),
+        scan_type: Some(scan_type_name(scan_type).to_string()),
+        report_all_states,
+    });
+    let dispatch = Arc::new(Dispatch { state: Mutex::new((shards.into(), shard_count)), changed: Notify::new() });
+
+    let writer = ResultWriter::new()
+        .with_nmap_scan_info(NmapScanInfo::new(scan_type, ports.clone()))
+        .with_csv_fields(csv_fields, csv_header)
+        .with_host_window(timeout * (retries as u32 + 1));
+    let mut writer =
+        crate::result_writer(writer, output_format, stream_results, sink_target, output_file, output_basename).await;
+    if let Some(webhook_url) = webhook_url {
+        writer = writer.with_sink(Box::new(WebhookSink::new(webhook_url, webhook_batch_size)));
+    }
+
+    let listener = TcpListener::bind(listen)
+        .await
+        .unwrap_or_else(|error| panic!("Unable to listen for agents on '{}': {}", listen, error));
+    eprintln!("Waiting for agents on {}, with the scan split into {} shards", listen, shard_count);
+    exit::arguments_checked();
+
+    // each shard's records are only written once it's done, so one that's scanned again after its agent fails isn't
+    // written twice
+    let (shard_sender, mut shard_receiver) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((stream, agent)) = listener.accept().await {
+            info!(%agent, "agent connected");
+            tokio::spawn(serve_agent(stream, agent, dispatch.clone(), template.clone(), shard_sender.clone()));
+        }
+    });
+
+    let mut findings = 0;
+    for done in 1..=shard_count {
+        let records: Vec<ScanRecord> = shard_receiver.recv().await.expect("The agent listener stopped unexpectedly.");
+        info!(done, shards = shard_count, "shard done");
+
+        for mut record in records {
+            record.hostname = hostnames.get(&record.ip).cloned();
+            findings += scan_type.reports(record.state) as u128;
+            writer.accept(&record);
+        }
+    }
+    writer.finish();
+
+    std::process::exit(match findings {
+        0 => exit::NOTHING_OPEN,
+        _ => exit::OPEN_PORTS_FOUND,
+    });
+}
+
+/// Hands the agent shards one at a time until there are none left, putting its shard back for another agent if it fails
+async fn serve_agent(
+    stream: TcpStream,
+    agent: SocketAddr,
+    dispatch: Arc<Dispatch>,
+    template: Arc<JobRequest>,
+    shard_sender: UnboundedSender<Vec<ScanRecord>>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(shard) = dispatch.next().await {
+        match scan_shard(&mut lines, &mut writer, &shard, &template).await {
+            Ok(records) => {
+                let _ = shard_sender.send(records);
+                dispatch.done();
+            }
+            Err(error) => {
+                warn!(%agent, shard = shard.id, "agent failed, handing its shard to another: {}", error);
+                dispatch.reassign(shard);
+                return;
+            }
+        }
+    }
+}
+
+/// Has the agent scan the shard, collecting the records it sends back until it's done
+async fn scan_shard(
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    writer: &mut OwnedWriteHalf,
+    shard: &Shard,
+    template: &JobRequest,
+) -> Result<Vec<ScanRecord>, String> {
+    let message = ControllerMessage::Scan { shard: shard.id, request: shard.request(template) };
+    let mut line = serde_json::to_string(&message).expect("Unable to serialize a message for an agent.");
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await.map_err(|error| error.to_string())?;
+
+    let mut records = Vec::new();
+    loop {
+        let line = match tokio::time::timeout(AGENT_TIMEOUT, lines.next_line()).await {
+            Err(_) => return Err(format!("nothing heard from it for {}s", AGENT_TIMEOUT.as_secs())),
+            Ok(Err(error)) => return Err(error.to_string()),
+            Ok(Ok(None)) => return Err("it disconnected".to_string()),
+            Ok(Ok(Some(line))) => line,
+        };
+
+        match serde_json::from_str(&line).map_err(|error| format!("unreadable message '{}': {}", line, error))? {
+            AgentMessage::Result { shard: id, ip, port, state, rtt, attempt } if id == shard.id => {
+                let state = PortState::from_str(&state).map_err(|_| format!("unknown port state '{}'", state))?;
+
+                records.push(ScanRecord::from(PortResult {
+                    addr: SocketAddr::new(ip, port),
+                    state,
+                    fingerprint: None,
+                    rtt: rtt.map(|rtt| Duration::from_secs_f64(rtt / 1000.0)),
+                    attempt,
+                }));
+            }
+            AgentMessage::Progress { shard: id, percent } if id == shard.id => {
+                info!(shard = id, percent, "shard progress");
+            }
+            AgentMessage::Done { shard: id } if id == shard.id => return Ok(records),
+            AgentMessage::Failed { shard: id, error } if id == shard.id => return Err(error),
+            message => return Err(format!("message for another shard: {:?}", message)),
+        }
+    }
+}
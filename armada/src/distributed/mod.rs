@@ -0,0 +1,178 @@
+mod agent;
+mod controller;
+
+use std::net::IpAddr;
+
+use armada_lib::{HostIterator, PortIterator};
+use cidr_utils::cidr::{IpCidr, Ipv4Cidr, Ipv6Cidr};
+use serde::{Deserialize, Serialize};
+
+pub(crate) use crate::distributed::agent::{run as run_agent, AgentConfig};
+pub(crate) use crate::distributed::controller::{run as run_controller, ControllerConfig};
+use crate::serve::JobRequest;
+
+/// What the controller sends an agent, one JSON object a line
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ControllerMessage {
+    /// Scan the shard and send back what's found
+    Scan { shard: usize, request: JobRequest },
+}
+
+/// What an agent sends the controller, one JSON object a line
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum AgentMessage {
+    Result {
+        shard: usize,
+        ip: IpAddr,
+        port: u16,
+        state: String,
+        /// In milliseconds
+        rtt: Option<f64>,
+        attempt: Option<u16>,
+    },
+    /// Sent every second while the shard's being scanned, so the controller knows the agent's still at it
+    Progress { shard: usize, percent: f64 },
+    Done { shard: usize },
+    /// The agent couldn't scan the shard
+    Failed { shard: usize, error: String },
+}
+
+/// A piece of the scan handed to one agent at a time
+#[derive(Clone, Debug)]
+struct Shard {
+    id: usize,
+    targets: Vec<IpCidr>,
+    ports: Vec<u16>,
+}
+
+impl Shard {
+    fn size(&self) -> u128 {
+        self.targets.iter().map(cidr_size).fold(0, u128::saturating_add).saturating_mul(self.ports.len() as u128)
+    }
+
+    /// Halves the shard by its targets, or by its ports once it's down to a single target
+    fn split(&self) -> Option<(Shard, Shard)> {
+        let halves = |(first, second): (Vec<IpCidr>, Vec<IpCidr>)| {
+            (Shard { targets: first, ..self.clone() }, Shard { targets: second, ..self.clone() })
+        };
+
+        match split_targets(&self.targets) {
+            Some(targets) => Some(halves(targets)),
+            None if self.ports.len() > 1 => {
+                let (first, second) = self.ports.split_at(self.ports.len() / 2);
+
+                Some((Shard { ports: first.to_vec(), ..self.clone() }, Shard { ports: second.to_vec(), ..self.clone() }))
+            }
+            None => None,
+        }
+    }
+
+    /// The job an agent runs for the shard, with the rest of its settings taken from the template
+    fn request(&self, template: &JobRequest) -> JobRequest {
+        JobRequest {
+            targets: self.targets.iter().map(IpCidr::to_string).collect(),
+            ports: port_ranges(&self.ports),
+            rate_limit: template.rate_limit,
+            retries: template.retries,
+            timeout_ms: template.timeout_ms,
+            scan_type: template.scan_type.clone(),
+            report_all_states: template.report_all_states,
+        }
+    }
+}
+
+/// Splits the (host, port) space into about as many shards as asked for, each about as big as the others. A scan too
+/// small to split that far gets fewer.
+fn plan_shards(targets: &HostIterator, ports: &PortIterator, count: usize) -> Vec<Shard> {
+    let mut shards = vec![Shard { id: 0, targets: targets.ranges().to_vec(), ports: ports.clone().collect() }];
+
+    while shards.len() < count {
+        let largest = shards
+            .iter()
+            .enumerate()
+            .filter_map(|(index, shard)| shard.split().map(|halves| (index, shard.size(), halves)))
+            .max_by_key(|(_, size, _)| *size);
+
+        let Some((index, _, (first, second))) = largest else {
+            break;
+        };
+        shards[index] = first;
+        shards.push(second);
+    }
+
+    shards.into_iter().enumerate().map(|(id, shard)| Shard { id, ..shard }).collect()
+}
+
+/// Splits the ranges into two halves holding about as many addresses each, halving the range itself when there's
+/// only one
+fn split_targets(targets: &[IpCidr]) -> Option<(Vec<IpCidr>, Vec<IpCidr>)> {
+    if let [cidr] = targets {
+        let (first, second) = halve_cidr(cidr)?;
+
+        return Some((vec![first], vec![second]));
+    }
+
+    let total = targets.iter().map(cidr_size).fold(0, u128::saturating_add);
+    let mut first_size = 0u128;
+    let split_at = targets
+        .iter()
+        .position(|cidr| {
+            first_size = first_size.saturating_add(cidr_size(cidr));
+            first_size >= total / 2
+        })
+        .map_or(1, |index| index + 1)
+        .clamp(1, targets.len().checked_sub(1)?);
+
+    Some((targets[..split_at].to_vec(), targets[split_at..].to_vec()))
+}
+
+fn halve_cidr(cidr: &IpCidr) -> Option<(IpCidr, IpCidr)> {
+    match cidr {
+        IpCidr::V4(cidr) if cidr.get_bits() < 32 => {
+            let bits = cidr.get_bits() + 1;
+            let first = Ipv4Cidr::from_prefix_and_bits(cidr.get_prefix(), bits).ok()?;
+            let second = Ipv4Cidr::from_prefix_and_bits(cidr.get_prefix() | (1 << (32 - bits)), bits).ok()?;
+
+            Some((IpCidr::V4(first), IpCidr::V4(second)))
+        }
+        IpCidr::V6(cidr) if cidr.get_bits() < 128 => {
+            let bits = cidr.get_bits() + 1;
+            let first = Ipv6Cidr::from_prefix_and_bits(cidr.get_prefix(), bits).ok()?;
+            let second = Ipv6Cidr::from_prefix_and_bits(cidr.get_prefix() | (1 << (128 - bits)), bits).ok()?;
+
+            Some((IpCidr::V6(first), IpCidr::V6(second)))
+        }
+        _ => None,
+    }
+}
+
+/// How many addresses the range holds, which only saturates for the whole IPv6 space
+fn cidr_size(cidr: &IpCidr) -> u128 {
+    match cidr {
+        IpCidr::V4(cidr) => 1 << (32 - cidr.get_bits() as u32),
+        IpCidr::V6(cidr) => 1u128.checked_shl(128 - cidr.get_bits() as u32).unwrap_or(u128::MAX),
+    }
+}
+
+/// Writes the ports the way `-p` takes them, joining runs of consecutive ports into ranges
+fn port_ranges(ports: &[u16]) -> String {
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+
+    for &port in ports {
+        match ranges.last_mut() {
+            Some((_, end)) if end.checked_add(1) == Some(port) => *end = port,
+            _ => ranges.push((port, port)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
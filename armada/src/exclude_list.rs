@@ -0,0 +1,250 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use cidr_utils::cidr::IpCidr;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::args::parse_octet_ranges;
+
+/// Lists are plain text files, which even a slow server should hand over well within this
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many of a list's lines that can't be parsed are listed before the rest are just counted
+const MAX_LISTED: usize = 10;
+
+/// What's kept next to a cached list, for telling when it's due to be fetched again and whether it changed since
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    /// Seconds since the Unix epoch
+    fetched_at: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Downloads every exclusion list and parses the ranges out of them. Lists fetched less than `max_age` ago are read
+/// from the cache instead, and older ones are only downloaded again if they changed on the server. A list that can't
+/// be fetched or parsed refuses the scan, since scanning what it would have left out is exactly what it's there to
+/// prevent.
+pub(crate) async fn fetch_exclusions(urls: &[String], max_age: Duration) -> Vec<IpCidr> {
+    if urls.is_empty() {
+        return Vec::new();
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .expect("Unable to create an HTTP client for exclusion lists.");
+
+    let mut exclusions = Vec::new();
+    for url in urls {
+        let contents = fetch_list(&client, url, max_age).await.unwrap_or_else(|error| {
+            panic!(
+                "Unable to fetch exclusion list {}: {:#}. Please try again once it can be reached, or raise --exclude-url-max-age to use a cached copy of it.",
+                url, error
+            )
+        });
+
+        let ranges = parse_list(&contents).unwrap_or_else(|errors| {
+            let unlisted = match errors.len().saturating_sub(MAX_LISTED) {
+                0 => String::new(),
+                count => format!(" and {} more", count),
+            };
+            panic!(
+                "Exclusion list {} has entries that can't be parsed, so it can't be honored: {}{}",
+                url,
+                errors.into_iter().take(MAX_LISTED).collect::<Vec<_>>().join(", "),
+                unlisted,
+            )
+        });
+        info!(url = url.as_str(), ranges = ranges.len(), "excluding the ranges in the list");
+
+        exclusions.extend(ranges);
+    }
+
+    exclusions
+}
+
+async fn fetch_list(client: &reqwest::Client, url: &str, max_age: Duration) -> anyhow::Result<String> {
+    let (list_path, entry_path) = cache_paths(url);
+    let cached = fs::read_to_string(&entry_path)
+        .ok()
+        .and_then(|entry| serde_json::from_str::<CacheEntry>(&entry).ok())
+        .filter(|entry| entry.url == url)
+        .and_then(|entry| Some((entry, fs::read_to_string(&list_path).ok()?)));
+
+    if let Some((entry, contents)) = &cached {
+        if now().saturating_sub(entry.fetched_at) < max_age.as_secs() {
+            debug!(url, "using the cached exclusion list");
+            return Ok(contents.clone());
+        }
+    }
+
+    let mut request = client.get(url);
+    if let Some((entry, _)) = &cached {
+        if let Some(etag) = &entry.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await.context("request failed")?;
+    let (entry, contents) = match (response.status(), cached) {
+        (StatusCode::NOT_MODIFIED, Some((entry, contents))) => (CacheEntry { fetched_at: now(), ..entry }, contents),
+        _ => {
+            let response = response.error_for_status().context("the server returned an error")?;
+            let header = |name| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+            let entry = CacheEntry { url: url.to_string(), fetched_at: now(), etag: header(ETAG), last_modified: header(LAST_MODIFIED) };
+
+            (entry, response.text().await.context("the download was cut short")?)
+        }
+    };
+
+    // a cache that can't be written to only means downloading the list every time
+    let written = list_path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&list_path, &contents)).and_then(|_| {
+        fs::write(&entry_path, serde_json::to_string(&entry).expect("Failed to serialize the cache entry."))
+    });
+    if let Err(error) = written {
+        debug!(url, %error, "unable to cache the exclusion list");
+    }
+
+    Ok(contents)
+}
+
+/// Every entry of the list, one IP, CIDR range or nmap style range per line, or each line that isn't one. Blank lines
+/// and comments starting with `#` or `;`, as DROP style lists have them, are skipped.
+fn parse_list(contents: &str) -> Result<Vec<IpCidr>, Vec<String>> {
+    let mut ranges = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let entry = line.split(['#', ';']).next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        match IpCidr::from_str(entry).ok().map(|cidr| vec![cidr]).or_else(|| parse_octet_ranges(entry)) {
+            Some(cidrs) => ranges.extend(cidrs),
+            None => errors.push(format!("line {}: '{}'", index + 1, entry)),
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(ranges),
+        false => Err(errors),
+    }
+}
+
+/// Where the list from the URL and what's known about it are cached, under `$XDG_CACHE_HOME/armada` or
+/// `~/.cache/armada`, named for a hash of the URL
+fn cache_paths(url: &str) -> (PathBuf, PathBuf) {
+    let directory = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|directory| !directory.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("armada")
+        .join("exclude-lists");
+
+    // FNV-1a, which unlike the standard library's hasher is the same from one build to the next
+    let hash = url.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3));
+
+    (directory.join(format!("{:016x}.txt", hash)), directory.join(format!("{:016x}.json", hash)))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn cidrs(cidrs: &[&str]) -> Vec<IpCidr> {
+        cidrs.iter().map(|cidr| IpCidr::from_str(cidr).unwrap()).collect()
+    }
+
+    /// Serves the list with an ETag, answering requests that already have it with a 304, and passes on the head of
+    /// every request
+    async fn server(list: &'static str) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/drop.txt", listener.local_addr().unwrap());
+        let (heads, received) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let head = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                let response = match head.contains("if-none-match: \"v1\"") {
+                    true => "HTTP/1.1 304 Not Modified\r\netag: \"v1\"\r\nconnection: close\r\n\r\n".to_string(),
+                    false => format!(
+                        "HTTP/1.1 200 OK\r\netag: \"v1\"\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        list.len(),
+                        list
+                    ),
+                };
+                let _ = heads.send(head);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (url, received)
+    }
+
+    #[test]
+    fn lists_skip_blank_lines_and_comments() {
+        let list = "; Spamhaus DROP List\n\n192.0.2.0/24 ; SBL1\n198.51.100.7\n# ours\n10.0.0.1-2\n2001:db8::/32\n";
+
+        assert_eq!(parse_list(list), Ok(cidrs(&["192.0.2.0/24", "198.51.100.7/32", "10.0.0.1/32", "10.0.0.2/32", "2001:db8::/32"])));
+    }
+
+    #[test]
+    fn every_line_that_cant_be_parsed_is_reported() {
+        assert_eq!(
+            parse_list("192.0.2.0/24\nexample.com\n\n10.0.0.0/33 # typo\n"),
+            Err(vec!["line 2: 'example.com'".to_string(), "line 4: '10.0.0.0/33'".to_string()]),
+        );
+    }
+
+    #[test]
+    fn every_url_is_cached_apart() {
+        assert_eq!(cache_paths("https://example.com/drop.txt"), cache_paths("https://example.com/drop.txt"));
+        assert_ne!(cache_paths("https://example.com/drop.txt").0, cache_paths("https://example.com/edrop.txt").0);
+    }
+
+    #[tokio::test]
+    async fn lists_are_cached_and_only_downloaded_again_when_they_changed() {
+        let (url, mut heads) = server("192.0.2.0/24\n").await;
+        let client = reqwest::Client::new();
+        let hour = Duration::from_secs(60 * 60);
+
+        assert_eq!(fetch_list(&client, &url, hour).await.unwrap(), "192.0.2.0/24\n");
+        assert!(!heads.recv().await.unwrap().contains("if-none-match"));
+
+        assert_eq!(fetch_list(&client, &url, hour).await.unwrap(), "192.0.2.0/24\n");
+        assert!(heads.try_recv().is_err(), "the cached list was downloaded again");
+
+        assert_eq!(fetch_list(&client, &url, Duration::ZERO).await.unwrap(), "192.0.2.0/24\n");
+        assert!(heads.recv().await.unwrap().contains("if-none-match: \"v1\""));
+
+        let (list_path, entry_path) = cache_paths(&url);
+        let _ = fs::remove_file(list_path);
+        let _ = fs::remove_file(entry_path);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::OnceLock;
+
+use armada_lib::CancellationToken;
+
+/// The scan got to the end and found at least one port in a state its scan type looks for, open for a SYN scan
+pub(crate) const OPEN_PORTS_FOUND: i32 = 0;
+/// The scan got to the end without finding a port in a state its scan type looks for
+pub(crate) const NOTHING_OPEN: i32 = 1;
+pub(crate) const INVALID_ARGUMENTS: i32 = 2;
+/// Raw sockets couldn't be opened, which usually means armada isn't running as root or with `CAP_NET_RAW`
+pub(crate) const PERMISSION_DENIED: i32 = 3;
+pub(crate) const RUNTIME_FAILURE: i32 = 4;
+/// The scan was stopped before it got to the end, by a signal, `--max-runtime` or being told to
+pub(crate) const INTERRUPTED: i32 = 5;
+
+/// What a panic exits with. Panics while the arguments are being checked are about the arguments, and any after that
+/// are failures of the scan itself.
+static PANIC_EXIT_CODE: AtomicI32 = AtomicI32::new(INVALID_ARGUMENTS);
+
+/// The token an interrupt cancels the scan with, once there's a scan
+static INTERRUPTION: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Exits with the code for the kind of failure once a panic's message is printed, rather than the usual 101. Panics
+/// about the arguments only print their message.
+pub(crate) fn exit_on_panic() {
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        let exit_code = PANIC_EXIT_CODE.load(Ordering::Relaxed);
+
+        // a mistake in the arguments is the user's to fix rather than a bug, so it's reported without a backtrace
+        if exit_code == INVALID_ARGUMENTS {
+            let message = match (info.payload().downcast_ref::<&str>(), info.payload().downcast_ref::<String>()) {
+                (Some(message), _) => message.to_string(),
+                (_, Some(message)) => message.clone(),
+                _ => "invalid arguments".to_string(),
+            };
+            eprintln!("error: {}", message);
+        } else {
+            default_hook(info);
+        }
+
+        std::process::exit(exit_code);
+    }));
+}
+
+/// Marks the arguments as checked, so panics from here on are failures of the scan
+pub(crate) fn arguments_checked() {
+    PANIC_EXIT_CODE.store(RUNTIME_FAILURE, Ordering::Relaxed);
+}
+
+/// Whether setting up the scan engine failed for lack of privileges
+pub(crate) fn is_permission_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|error| error.kind() == std::io::ErrorKind::PermissionDenied || error.raw_os_error() == Some(libc::EPERM))
+    })
+}
+
+/// Makes `SIGINT` and `SIGTERM` cancel the scan once it's set up, so the probes in flight still get their answers and
+/// the results found so far are written out. A second one, or one before the scan is set up, exits right away.
+#[cfg(unix)]
+pub(crate) fn stop_on_interrupt() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt_signal = signal(SignalKind::interrupt()).expect("Unable to listen for SIGINT.");
+    let mut terminate_signal = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM.");
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(_) = interrupt_signal.recv() => {},
+                Some(_) = terminate_signal.recv() => {},
+                else => break,
+            }
+
+            interrupted();
+        }
+    });
+}
+
+/// Makes Ctrl-C cancel the scan once it's set up, the way `SIGINT` does elsewhere
+#[cfg(not(unix))]
+pub(crate) fn stop_on_interrupt() {
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            interrupted();
+        }
+    });
+}
+
+fn interrupted() {
+    match INTERRUPTION.get() {
+        Some(interruption) if !interruption.is_cancelled() => {
+            eprintln!("Interrupted, stopping once the probes in flight are settled. Interrupt again to exit right away.");
+            interruption.cancel();
+        }
+        _ => std::process::exit(INTERRUPTED),
+    }
+}
+
+/// Hands over the token the scan is cancelled with, for interrupts to cancel
+pub(crate) fn interrupt_cancels(interruption: CancellationToken) {
+    let _ = INTERRUPTION.set(interruption);
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv6Addr};
+
+use armada_lib::HostIterator;
+use cidr_utils::cidr::{IpCidr, Ipv6Cidr};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// IPv6 ranges with a shorter prefix than this hold more addresses than the whole IPv4 space, too many to scan every
+/// one of them
+pub(crate) const MIN_FULL_SCAN_IPV6_PREFIX: u8 = 96;
+pub(crate) const DEFAULT_IPV6_SAMPLE_SIZE: usize = 1024;
+pub(crate) const IPV6_STRATEGIES: [&str; 3] = ["low-addrs", "sample", "list"];
+
+/// The interface IDs hosts are numbered with by hand when they aren't numbered from 1 up: round numbers, the port of
+/// the service the host runs and words spelled out in hex
+const WELL_KNOWN_INTERFACE_IDS: [u128; 16] = [
+    0x100, 0x1000, 0x1_0001, 0x2_0001, 0x22, 0x25, 0x53, 0x80, 0x443, 0x8080, 0xbeef, 0xcafe, 0xface, 0xdead_beef,
+    0xcafe_babe, 0xface_b00c,
+];
+/// How many interface IDs from 1 up a sample starts with
+const SAMPLED_LOW_INTERFACE_IDS: u128 = 16;
+
+/// How IPv6 ranges too large to scan every address of are scanned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Ipv6Strategy {
+    /// The lowest addresses of each range, `::1` up
+    LowAddrs,
+    /// A mix of the addresses hosts are most often found on: low and well known interface IDs, SLAAC interface IDs made
+    /// from MAC addresses and random ones like privacy addresses
+    Sample,
+    /// Every address, however long that takes
+    List,
+}
+
+impl Ipv6Strategy {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "low-addrs" => Some(Ipv6Strategy::LowAddrs),
+            "sample" => Some(Ipv6Strategy::Sample),
+            "list" => Some(Ipv6Strategy::List),
+            _ => None,
+        }
+    }
+}
+
+/// fe80::/10
+pub(crate) fn link_local_range() -> IpCidr {
+    IpCidr::V6(Ipv6Cidr::from_prefix_and_bits(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).expect("fe80::/10 is a valid range"))
+}
+
+/// Whether the range lies within fe80::/10, whose addresses are only unique on the link they're on
+pub(crate) fn is_link_local(range: &IpCidr) -> bool {
+    match range {
+        IpCidr::V6(range) => range.get_bits() >= 10 && Ipv6Addr::from(range.first()).is_unicast_link_local(),
+        IpCidr::V4(_) => false,
+    }
+}
+
+/// The interface a zone identifier stands for, given by name like `fe80::1%eth0` or by index like `fe80::1%2`
+pub(crate) fn zone_interface(zone: &str) -> Option<String> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return armada_lib::utils::get_interface_name(index);
+    }
+
+    armada_lib::utils::get_interface_ips(zone).map(|_| zone.to_string())
+}
+
+/// The index of the interface, which a socket address to a link-local address needs as its scope to be connected to
+pub(crate) fn interface_index(interface: &str) -> Option<u32> {
+    armada_lib::utils::get_interface_index(interface)
+}
+
+/// The interface's link-local address, which probes to the rest of the link go out from
+pub(crate) fn link_local_address(interface: &str) -> Option<Ipv6Addr> {
+    armada_lib::utils::get_interface_ips(interface)?.into_iter().find_map(|ip| match ip {
+        IpAddr::V6(ip) if ip.is_unicast_link_local() => Some(ip),
+        _ => None,
+    })
+}
+
+/// The first IPv6 range of the targets too large to scan every address of
+pub(crate) fn oversized_range(targets: &HostIterator) -> Option<Ipv6Cidr> {
+    targets.ranges().iter().find_map(|range| match range {
+        IpCidr::V6(range) if range.get_bits() < MIN_FULL_SCAN_IPV6_PREFIX => Some(*range),
+        _ => None,
+    })
+}
+
+/// Swaps every IPv6 range too large to scan whole for the subset of it the strategy picks, up to `sample_size`
+/// addresses of each. Samples are drawn from the seed when there's one, so runs can be repeated.
+pub(crate) fn apply_strategy(
+    targets: HostIterator,
+    strategy: Ipv6Strategy,
+    sample_size: usize,
+    seed: Option<u64>,
+) -> HostIterator {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    targets.ranges().iter().fold(HostIterator::new(), |subset, range| match (range, strategy) {
+        (IpCidr::V6(range), Ipv6Strategy::LowAddrs) if range.get_bits() < MIN_FULL_SCAN_IPV6_PREFIX => {
+            subset.add_ips((1..=sample_size as u128).map(|id| address(range, 0, id)).collect())
+        }
+        (IpCidr::V6(range), Ipv6Strategy::Sample) if range.get_bits() < MIN_FULL_SCAN_IPV6_PREFIX => {
+            subset.add_ips(sample(range, sample_size, &mut rng))
+        }
+        (range, _) => subset.add_cidr(*range),
+    })
+}
+
+/// Low and well known interface IDs first, in the range's first /64, then SLAAC and random interface IDs spread over
+/// its /64s
+fn sample(range: &Ipv6Cidr, sample_size: usize, rng: &mut StdRng) -> Vec<IpAddr> {
+    let mut addresses = BTreeSet::new();
+
+    let fixed = (1..=SAMPLED_LOW_INTERFACE_IDS).chain(WELL_KNOWN_INTERFACE_IDS);
+    for id in fixed.take(sample_size) {
+        addresses.insert(address(range, 0, id));
+    }
+
+    let subnets = 1u128.checked_shl(64u32.saturating_sub(range.get_bits() as u32)).unwrap_or(u128::MAX);
+    // picked at random, a few draws could land on the same address
+    for draw in 0..sample_size.saturating_mul(2) {
+        if addresses.len() >= sample_size {
+            break;
+        }
+
+        let id: u64 = rng.gen();
+        let id = match draw % 2 {
+            // a MAC address with ff:fe in its middle, and the universal/local bit flipped
+            0 => (id & 0xffff_ff00_00ff_ffff) | 0x0200_00ff_fe00_0000,
+            _ => id,
+        };
+        addresses.insert(address(range, rng.gen_range(0..subnets), id as u128));
+    }
+
+    addresses.into_iter().collect()
+}
+
+/// The address with the interface ID in the range's nth /64, or its nth address for ranges of a /64 or less, where
+/// the interface ID is cut to what's left of it
+fn address(range: &Ipv6Cidr, subnet: u128, id: u128) -> IpAddr {
+    let host_bits = 128 - range.get_bits() as u32;
+    let host_mask = 1u128.checked_shl(host_bits).map_or(u128::MAX, |size| size - 1);
+    let offset = match host_bits > 64 {
+        true => (subnet << 64) | (id & u64::MAX as u128),
+        false => id,
+    };
+
+    IpAddr::V6(Ipv6Addr::from(range.first() | (offset & host_mask)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_ranges_within_fe80_10_are_link_local() {
+        for range in ["fe80::1", "fe80::/64", "febf:ffff::1", "fe80::/10"] {
+            assert!(is_link_local(&IpCidr::from_str(range).unwrap()), "{} isn't link-local", range);
+        }
+        for range in ["fec0::1", "fe80::/9", "2001:db8::1", "169.254.0.1", "::1"] {
+            assert!(!is_link_local(&IpCidr::from_str(range).unwrap()), "{} is link-local", range);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn zones_name_an_interface_or_its_index() {
+        let index = interface_index("lo").unwrap();
+
+        assert_eq!(zone_interface("lo").as_deref(), Some("lo"));
+        assert_eq!(zone_interface(&index.to_string()).as_deref(), Some("lo"));
+        assert_eq!(zone_interface("no-such-interface"), None);
+        assert_eq!(zone_interface(&u32::MAX.to_string()), None);
+    }
+}
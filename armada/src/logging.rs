@@ -0,0 +1,151 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use atty::Stream;
+use tracing_subscriber::EnvFilter;
+
+/// Where `--log-file` writes logs to, and when it starts a new file
+#[derive(Clone, Debug)]
+pub(crate) struct LogFile {
+    pub(crate) path: PathBuf,
+    /// The size in bytes a file may grow to before it's rotated, `None` letting it grow for as long as the scan runs
+    pub(crate) max_size: Option<u64>,
+    /// How many rotated files are kept around as `<path>.1`, `<path>.2` and so on, newest first
+    pub(crate) max_files: usize,
+}
+
+impl LogFile {
+    /// The file and the rotated files kept next to it that are already there
+    #[cfg(unix)]
+    pub(crate) fn existing_paths(&self) -> Vec<PathBuf> {
+        std::iter::once(self.path.clone())
+            .chain((1..=self.max_files).map(|index| rotated_path(&self.path, index)))
+            .filter(|path| path.exists())
+            .collect()
+    }
+}
+
+/// Starts writing armada's logs, to stderr or else to a `--log-file` as one JSON object an event with the spans it
+/// happened in. Warnings and errors are always written, while `-v`, `-vv` and `-vvv` go down to info, debug and trace.
+/// Below warnings only armada's own logs are written, not its dependencies'. `RUST_LOG` takes over from `-v` when
+/// it's set, with the same directives as everywhere else, e.g. `RUST_LOG=armada_lib::armada::worker=trace`.
+pub(crate) fn init(verbosity: u8, log_file: Option<LogFile>) {
+    let level = match verbosity {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,armada={},armada_lib={}", level, level)));
+
+    let initialized = match log_file {
+        Some(log_file) => {
+            let path = log_file.path.clone();
+            let file = RotatingFile::open(log_file)
+                .unwrap_or_else(|error| panic!("Unable to open log file '{}': {}", path.display(), error));
+
+            tracing_subscriber::fmt()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_env_filter(filter)
+                .with_writer(Mutex::new(file))
+                .try_init()
+        }
+        None => tracing_subscriber::fmt()
+            .without_time()
+            .with_ansi(atty::is(Stream::Stderr))
+            .with_env_filter(filter)
+            .with_writer(io::stderr)
+            .try_init(),
+    };
+
+    initialized.expect("Unable to set up logging.");
+}
+
+/// A log file that's moved aside to `<path>.1` once it gets too big, shifting the older ones along. Once it can't be
+/// written to or rotated any more, that's reported and the rest of the logs go to stderr instead.
+struct RotatingFile {
+    log_file: LogFile,
+    file: File,
+    size: u64,
+    failed: bool,
+}
+
+impl RotatingFile {
+    fn open(log_file: LogFile) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&log_file.path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self { log_file, file, size, failed: false })
+    }
+
+    fn write_event(&mut self, event: &[u8]) -> io::Result<()> {
+        let full = self.log_file.max_size.is_some_and(|max_size| self.size > 0 && self.size + event.len() as u64 > max_size);
+        if full {
+            self.rotate()?;
+        }
+
+        self.file.write_all(event)?;
+        self.size += event.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let LogFile { path, max_files, .. } = &self.log_file;
+
+        if *max_files > 0 {
+            // the oldest file is overwritten by the one before it, and the ones that aren't there yet are skipped
+            for index in (1..*max_files).rev() {
+                match std::fs::rename(rotated_path(path, index), rotated_path(path, index + 1)) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                    _ => {}
+                }
+            }
+            std::fs::rename(path, rotated_path(path, 1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    /// Every event is handed over in a single write, so a file is only ever rotated between events
+    fn write(&mut self, event: &[u8]) -> io::Result<usize> {
+        if !self.failed {
+            match self.write_event(event) {
+                Ok(()) => return Ok(event.len()),
+                Err(error) => {
+                    self.failed = true;
+                    eprintln!(
+                        "Unable to write to log file '{}': {}. Logging to stderr from here on.",
+                        self.log_file.path.display(),
+                        error
+                    );
+                }
+            }
+        }
+
+        io::stderr().write_all(event)?;
+
+        Ok(event.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated_path = path.as_os_str().to_owned();
+    rotated_path.push(format!(".{}", index));
+
+    PathBuf::from(rotated_path)
+}
@@ -1,64 +1,611 @@
+mod analyze;
 mod args;
+mod asn;
+mod checkpoint;
+mod cloud;
+mod completions;
+mod diff;
+mod distributed;
+mod exclude_list;
+mod exit;
+mod ipv6;
+mod logging;
+mod metrics;
+mod output;
+mod plan;
+mod post_scan;
+#[cfg(unix)]
+mod privileges;
 mod ranges;
+mod reserved;
+mod resolve;
 mod run_variants;
+mod schedule;
+mod scope;
+mod serve;
+mod services;
+mod status;
+mod summary;
 mod config;
+mod control;
 
 use std::net::{
     IpAddr,
     Ipv4Addr,
     Ipv6Addr,
 };
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
-use armada_lib::Armada;
+use armada_lib::utils::{
+    get_interface_ips, get_interface_local_cores, get_interface_with_ip, get_source_routes, get_vlan_interface,
+};
+use armada_lib::{
+    Armada, ArmadaOptions, ArmadaWorkMessage, BannerGrabber, CancellationToken, Engine, HostIterator, LiveHost,
+    ScanOptions, ServiceDetector, TlsInspector,
+};
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::info;
 
-use crate::args::ArmadaConfig;
+use crate::args::{ArmadaCommand, ArmadaConfig, CorePinning, ProgressFormat};
+use crate::checkpoint::Checkpointer;
+use crate::control::ControlSocket;
+use crate::metrics::{serve_metrics, ScanMetrics};
+use crate::output::{create_output_file, NmapScanInfo, OutputFormat, ResultWriter, SinkTarget, WebhookSink};
+use crate::plan::ScanPlan;
+use crate::post_scan::PostScan;
+use crate::resolve::ReverseResolver;
+use crate::run_variants::{RuntimeLimit, ScanHooks};
+use crate::schedule::pause_outside_window;
+use crate::status::ScanStatus;
+use crate::summary::ScanSummary;
 
 #[tokio::main]
 async fn main() {
+    exit::exit_on_panic();
+
     let ArmadaConfig {
         targets,
+        hostname_targets,
+        asn_targets,
+        cloud_targets,
+        skip_invalid,
+        exclusions,
+        exclude_urls,
+        exclude_url_max_age,
+        scope,
+        resolve_timeout,
+        rdns,
         ports,
         quiet_mode,
+        verbosity,
+        log_file,
+        progress_format,
         rate_limit,
-        listening_port,
+        rate_limiter,
+        auto_rate,
+        subnet_rate_limit,
+        source_rate_limit,
+        pacing,
+        scan_window,
+        max_runtime,
+        plan_format,
+        confirm_above,
+        listening_ports,
+        core_pinning,
+        seed,
+        randomize,
+        shard,
         retries,
         timeout,
+        adaptive_timeout,
+        packet_batch_size,
+        batched_io,
+        engine,
+        interface,
+        ipv6_zone,
+        capture,
         source_ips,
-        stream_results
-    } = args::get_armada_config();
+        stream_results,
+        scan_type,
+        ttl,
+        fragment_size,
+        decoys,
+        probe_profile,
+        discover,
+        output_format,
+        csv_fields,
+        csv_header,
+        sink_target,
+        output_file,
+        output_basename,
+        webhook_url,
+        webhook_batch_size,
+        metrics_address,
+        control_socket,
+        checkpointer,
+        banner_options,
+        tls_options,
+        tls_all_ports,
+        service_options,
+        os_guess,
+        report_all_states,
+        raw_results,
+        summary_file,
+        #[cfg(unix)]
+        run_as,
+    } = match args::get_armada_command() {
+        ArmadaCommand::Scan(armada_config) => *armada_config,
+        ArmadaCommand::Diff(diff_options) => std::process::exit(diff::run(diff_options)),
+        ArmadaCommand::Analyze(analyze_config) => return analyze::run(analyze_config).await,
+        ArmadaCommand::Serve(serve_config) => return serve::run(serve_config).await,
+        ArmadaCommand::Agent(agent_config) => return distributed::run_agent(agent_config).await,
+        ArmadaCommand::Controller(controller_config) => return distributed::run_controller(controller_config).await,
+        ArmadaCommand::Print(text) => {
+            print!("{}", text);
+            return;
+        }
+    };
+
+    logging::init(verbosity, log_file.clone());
+    exit::stop_on_interrupt();
+
+    // the runtime counts from armada starting, so lookups and discovery come out of it as well
+    let deadline = max_runtime.map(|max_runtime| Instant::now() + max_runtime);
+
+    let resolved_hostnames = resolve::resolve_hostnames(hostname_targets, resolve_timeout).await;
+    let targets = targets.add_ips(resolved_hostnames.iter().map(|(ip, _)| *ip).collect());
+    let targets = asn::expand_asns(asn_targets)
+        .await
+        .into_iter()
+        .fold(targets, HostIterator::add_cidr);
+    let targets = cloud::expand_cloud_sources(cloud_targets)
+        .await
+        .into_iter()
+        .fold(targets, HostIterator::add_cidr);
+    // lookups that all failed leave a scan of nothing, which would otherwise pass for a scan that found nothing open
+    if targets.size() == 0 && !skip_invalid {
+        panic!("None of the targets could be looked up, so there's nothing to scan. Pass --skip-invalid to go ahead anyway.");
+    }
+    let exclusions = exclusions.into_iter().chain(exclude_list::fetch_exclusions(&exclude_urls, exclude_url_max_age).await);
+    let targets = exclusions.fold(targets, HostIterator::exclude_cidr);
+    let targets = match &scope {
+        Some(scope) => scope.enforce(targets),
+        None => targets,
+    };
+
+    // IPv6 routers never fragment, and a probe split ahead of time mostly gets dropped along the way
+    if fragment_size.is_some() && targets.has_ipv6() {
+        panic!("Fragmenting probes is only supported for IPv4 targets. Please scan the IPv6 targets separately, without --fragment or --mtu.");
+    }
+
+    if let Some(decoys) = &decoys {
+        enforce_remote_decoys(&decoys.addrs);
+    }
+
+    // raw sockets only send from our own addresses, while XDP, Npcap and BPF write whatever source it's given into the frame
+    if let (Some(source_ips), false) = (&source_ips, matches!(engine, Engine::Xdp { .. } | Engine::Npcap { .. } | Engine::Bpf { .. })) {
+        enforce_local_source_ips(source_ips);
+    }
+
+    // a VLAN's addresses and routes are on its own interface, if there is one on top of the trunk
+    let address_interface = match (&engine, &interface) {
+        (Engine::Xdp { vlan: Some(vlan), .. } | Engine::Npcap { vlan: Some(vlan), .. }, Some(interface)) => {
+            get_vlan_interface(interface, *vlan).or(Some(interface.clone()))
+        }
+        _ => interface.clone(),
+    };
+
+    // the routing table leaves the source of link-local routes to the kernel, which sends from the link-local address
+    let link_local_source = match (&source_ips, &ipv6_zone) {
+        (None, Some(zone)) => Some(ipv6::link_local_address(zone).unwrap_or_else(|| {
+            panic!("Interface {} has no link-local address to probe the link-local targets from.", zone)
+        })),
+        _ => None,
+    };
+
+    // without --source-ip, probes go out from the source the routing table has for their target
+    let source_routes = match source_ips {
+        Some(_) => None,
+        None => Some(get_source_routes(address_interface.as_deref()).await),
+    }
+    .map(|source_routes| match link_local_source {
+        Some(source) => source_routes.add_route(ipv6::link_local_range(), IpAddr::V6(source)),
+        None => source_routes,
+    });
+    let (source_ipv4_addrs, source_ipv6_addrs) =
+        split_and_enforce_source_ips(source_ips, address_interface.as_deref(), link_local_source, &targets).await;
+    info!(
+        interface = address_interface.as_deref().unwrap_or("picked by the routing table"),
+        ipv4 = ?source_ipv4_addrs,
+        ipv6 = ?source_ipv6_addrs,
+        "sending from these source addresses"
+    );
+    if source_routes.as_ref().is_some_and(|source_routes| !source_routes.is_empty()) {
+        info!("picking each target's source address from the routing table first");
+    }
+
+    // interrupting armada cancels the scan, which still waits for the probes in flight
+    let interruption = CancellationToken::new();
+
+    // a resumed scan skips the target/port pairs the checkpoint already covers
+    let skip_pairs = checkpointer.as_ref().map_or(0, Checkpointer::scan_cursor);
+
+    let options = ScanOptions {
+        source_ipv4_addrs,
+        source_ipv6_addrs,
+        source_routes,
+        port_retries: retries,
+        port_timeout: timeout,
+        packets_per_second: rate_limit,
+        rate_limiter,
+        scan_type,
+        ttl,
+        fragment_size,
+        decoys,
+        probe_profile,
+        collect_fingerprints: os_guess,
+        report_all_states,
+        raw_results,
+        skip_pairs,
+        shard,
+        seed,
+        randomize,
+        auto_rate,
+        adaptive_timeout,
+        subnet_rate_limit,
+        source_rate_limit,
+        pacing,
+        deadline,
+        packet_batch_size,
+        batched_io,
+        capture,
+        packet_hooks: None,
+        cancellation: Some(interruption.clone()),
+    };
+
+    let plan = ScanPlan {
+        targets: &targets,
+        ports: &ports,
+        options: &options,
+        engine: &engine,
+        interface: interface.as_deref(),
+        listening_ports: &listening_ports,
+        discover,
+        max_runtime,
+    };
+    if let Some(plan_format) = plan_format {
+        plan.print(plan_format);
+
+        return;
+    }
+    if let Some(confirm_above) = confirm_above {
+        plan.confirm(confirm_above);
+    }
+
+    exit::arguments_checked();
+
+    let cores = get_worker_cores(core_pinning, interface.as_deref(), &options.source_ipv4_addrs, &options.source_ipv6_addrs);
+    let armada = match Armada::with_options(ArmadaOptions { listening_ports, engine, cores, interface }) {
+        Ok(armada) => armada,
+        Err(error) if exit::is_permission_error(&error) => {
+            eprintln!("Unable to open raw sockets: {:#}. Please run armada as root or with CAP_NET_RAW.", error);
+            std::process::exit(exit::PERMISSION_DENIED);
+        }
+        Err(error) => panic!("Unable to set up the scan engine: {:?}", error),
+    };
+    exit::interrupt_cancels(interruption.clone());
+    #[cfg(unix)]
+    pause_on_signals(armada.clone());
+    // listened for as soon as the scan is set up, since the default for the signal is to exit
+    let status = Arc::new(ScanStatus::default());
+    #[cfg(unix)]
+    status::report_on_signal(armada.clone(), status.clone());
+    let control_socket = control_socket.map(|path| ControlSocket::serve(path, armada.clone(), status.clone()));
+    if let Some(scan_window) = scan_window {
+        pause_outside_window(armada.clone(), scan_window);
+    }
+
+    let post_scan = PostScan::default()
+        .with_hostnames(resolved_hostnames.into_iter().collect())
+        .with_reverse_resolver(rdns.then(|| ReverseResolver::new(resolve_timeout)))
+        .with_banner_grabber(banner_options.map(BannerGrabber::new))
+        .with_tls_inspector(tls_options.map(TlsInspector::new), tls_all_ports)
+        .with_service_detector(service_options.map(ServiceDetector::new))
+        .with_ipv6_zone(ipv6_zone);
+
+    let (targets, post_scan) = if discover {
+        // the spinner would get in the way of JSON progress events just as much as of quiet mode
+        let quiet_discovery = quiet_mode || progress_format == ProgressFormat::Json;
+        let live_hosts = discover_live_hosts(&armada, targets, options.clone(), quiet_discovery).await;
+        let post_scan = post_scan.with_live_hosts(&live_hosts);
+
+        (HostIterator::new().add_ips(live_hosts.into_iter().map(|live_host| live_host.ip).collect()), post_scan)
+    } else {
+        (targets, post_scan)
+    };
+
+    // results flow from the scan into the post-scan stage as they're found
+    let (result_sender, result_receiver) = unbounded_channel();
+    // a host's last result comes in at most one timeout after each of its probe's attempts
+    let probe_timeout = options.adaptive_timeout.map_or(options.port_timeout, |adaptive| adaptive.max_timeout);
+    let writer = ResultWriter::new()
+        .with_nmap_scan_info(NmapScanInfo::new(scan_type, ports.clone()))
+        .with_csv_fields(csv_fields, csv_header)
+        .with_host_window(probe_timeout * (options.port_retries as u32 + 1));
+    let mut writer = result_writer(writer, output_format, stream_results, sink_target, output_file, output_basename).await;
+    if let Some(webhook_url) = webhook_url {
+        writer = writer.with_sink(Box::new(WebhookSink::new(webhook_url, webhook_batch_size)));
+    }
+    let metrics = match metrics_address {
+        Some(metrics_address) => {
+            let metrics = Arc::new(ScanMetrics::new(options.probed_pairs(targets.size() * ports.size() as u128)));
+            serve_metrics(metrics_address, metrics.clone()).await;
+
+            Some(metrics)
+        }
+        None => None,
+    };
+
+    let post_scan_handle = tokio::spawn(post_scan.run(result_receiver, writer));
 
-    let armada = Armada::new(listening_port);
+    let saved_results = checkpointer.as_ref().map_or(Vec::new(), Checkpointer::saved_results);
+    saved_results.iter().for_each(|result| {
+        let _ = result_sender.send(result.clone());
+    });
 
-    let (source_ipv4, source_ipv6) = split_and_enforce_source_ips(source_ips).await;
+    let runtime_limit = max_runtime.map(|max_runtime| {
+        RuntimeLimit::new(
+            max_runtime,
+            options.probed_pairs(targets.size() * ports.size() as u128),
+            options.probed_pairs(skip_pairs),
+        )
+    });
+    status.start(&targets, &ports, &options);
+    // the results found before resuming count towards the scan as a whole, like the pairs they were found in
+    status.record(&ArmadaWorkMessage::results(saved_results));
+    // everything that takes root is open by now: the workers' raw sockets, discovery's, the output files and the
+    // listening sockets
+    #[cfg(unix)]
+    if let Some(run_as) = &run_as {
+        // the log file is rotated by the user, who has to be able to rename it and the older ones
+        let rotated_logs = log_file.iter().filter(|log_file| log_file.max_size.is_some());
+        rotated_logs.clone().flat_map(logging::LogFile::existing_paths).for_each(|path| privileges::hand_over(&path, run_as));
 
-    let mut syn_scan_results = if quiet_mode {
+        privileges::drop_privileges(run_as);
+
+        // better to find out now than hours into the scan
+        let written_later = checkpointer
+            .iter()
+            .map(Checkpointer::path)
+            .chain(summary_file.as_deref())
+            .chain(rotated_logs.map(|log_file| log_file.path.as_path()));
+        if let Some(path) = written_later.into_iter().find(|path| !privileges::can_write(path)) {
+            panic!(
+                "User {} can't write '{}'. Please write it somewhere they can, or keep running as root with --run-as root.",
+                run_as.name,
+                path.display()
+            );
+        }
+    }
+
+    let hooks = ScanHooks {
+        metrics,
+        checkpointer,
+        runtime_limit,
+        status: Some(status.clone()),
+        control_socket,
+        cancellation: Some(interruption.clone()),
+        failed: false,
+    };
+
+    if quiet_mode {
         use run_variants::QuietArmada;
 
         armada
-            .run_quiet(targets, ports, source_ipv4, source_ipv6, retries, timeout, rate_limit, stream_results)
+            .run_quiet(targets, ports, options, result_sender, hooks)
             .await
     } else {
-        use run_variants::ProgressArmada;
+        match progress_format {
+            ProgressFormat::Bars => {
+                use run_variants::ProgressArmada;
 
-        armada
-            .run_with_stats(targets, ports, source_ipv4, source_ipv6, retries, timeout, rate_limit, stream_results)
-            .await
+                armada
+                    .run_with_stats(targets, ports, options, result_sender, hooks)
+                    .await
+            }
+            ProgressFormat::Dashboard => {
+                use run_variants::DashboardArmada;
+
+                armada
+                    .run_with_dashboard(targets, ports, options, result_sender, hooks)
+                    .await
+            }
+            ProgressFormat::Json => {
+                use run_variants::JsonProgressArmada;
+
+                armada
+                    .run_with_json_progress(targets, ports, options, result_sender, hooks)
+                    .await
+            }
+        }
+    };
+
+    post_scan_handle.await.expect("Post-scan stage stopped unexpectedly.");
+
+    let snapshot = status.snapshot(&armada).expect("The scan's status is kept from when it starts.");
+    if interruption.is_cancelled() && snapshot.pairs_done < snapshot.total_pairs {
+        eprintln!(
+            "Interrupted, having covered {} of {} target/port pairs ({:.2}%) and found {} open ports.",
+            snapshot.pairs_done,
+            snapshot.total_pairs,
+            snapshot.percent(),
+            snapshot.open_ports,
+        );
+    }
+
+    let summary = ScanSummary::from(&snapshot);
+    if !quiet_mode && progress_format != ProgressFormat::Json {
+        summary.print();
+    }
+    if let Some(summary_file) = summary_file {
+        summary.write(&summary_file);
+    }
+
+    if let Some(failure) = &snapshot.failure {
+        eprintln!("The scan failed before getting to the end: {}", failure);
+    }
+
+    // only a scan that was told to stop or ran out of time was interrupted, however much of it is left
+    let deadline_passed = deadline.is_some_and(|deadline| deadline <= Instant::now());
+    let stopped = interruption.is_cancelled() || armada.is_stopped() || deadline_passed;
+    let interrupted = stopped && snapshot.pairs_done < snapshot.total_pairs;
+    let exit_code = match (snapshot.failure.is_some(), interrupted, snapshot.findings) {
+        (true, ..) => exit::RUNTIME_FAILURE,
+        (false, true, _) => exit::INTERRUPTED,
+        (false, false, 0) => exit::NOTHING_OPEN,
+        (false, false, _) => exit::OPEN_PORTS_FOUND,
     };
 
-    if !stream_results {
-        syn_scan_results.sort();
+    std::process::exit(exit_code);
+}
 
-        syn_scan_results.into_iter().for_each(|remote| {
-            println!("{}:{}", remote.ip(), remote.port());
-        });
+/// Points results at stdout, a database, an `--output-file` or every `-oA` file. Files are always streamed to where the format
+/// allows it, so killing a scan still leaves the results found so far behind.
+async fn result_writer(
+    writer: ResultWriter,
+    output_format: OutputFormat,
+    stream_results: bool,
+    sink_target: Option<SinkTarget>,
+    output_file: Option<PathBuf>,
+    output_basename: Option<PathBuf>,
+) -> ResultWriter {
+    if let Some(output_basename) = output_basename {
+        [(OutputFormat::Default, "txt"), (OutputFormat::Json, "json"), (OutputFormat::Csv, "csv")]
+            .into_iter()
+            .fold(writer, |writer, (format, extension)| {
+                writer.with_output(format, create_output_file(&output_basename.with_extension(extension)), true)
+            })
+    } else if let Some(sink_target) = sink_target {
+        writer.with_sink(sink_target.open().await)
+    } else if let Some(output_file) = output_file {
+        writer.with_output(output_format, create_output_file(&output_file), true)
+    } else {
+        writer.with_output(output_format, Box::new(std::io::stdout()), stream_results)
     }
 }
 
-async fn split_and_enforce_source_ips(source_ips: Option<Vec<IpAddr>>) -> (Vec<Ipv4Addr>, Vec<Ipv6Addr>) {
+/// Ctrl-Z (SIGTSTP) pauses sending and SIGCONT resumes it, while SIGUSR2 toggles between the two. armada keeps
+/// running while paused, so responses to probes already sent still arrive.
+#[cfg(unix)]
+fn pause_on_signals(armada: Armada) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut pause_signal = signal(SignalKind::from_raw(libc::SIGTSTP)).expect("Unable to listen for SIGTSTP.");
+    let mut resume_signal = signal(SignalKind::from_raw(libc::SIGCONT)).expect("Unable to listen for SIGCONT.");
+    let mut toggle_signal = signal(SignalKind::user_defined2()).expect("Unable to listen for SIGUSR2.");
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(_) = pause_signal.recv() => armada.pause(),
+                Some(_) = resume_signal.recv() => armada.resume(),
+                Some(_) = toggle_signal.recv() => {
+                    if armada.is_paused() {
+                        armada.resume();
+                    } else {
+                        armada.pause();
+                    }
+                }
+                else => break,
+            }
+        }
+    });
+}
+
+async fn discover_live_hosts(armada: &Armada, targets: HostIterator, options: ScanOptions, quiet_mode: bool) -> Vec<LiveHost> {
+    use indicatif::{ProgressBar, ProgressStyle};
+
+    let spinner = (!quiet_mode).then(|| {
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_style(ProgressStyle::default_spinner().template("{spinner:.yellow} Discovering live hosts...").expect("invalid template"));
+        spinner.enable_steady_tick(std::time::Duration::from_millis(50));
+        spinner
+    });
+
+    let live_hosts = armada
+        .discover(targets, options)
+        .await
+        .expect("Host discovery failed.");
+
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+
+    live_hosts
+}
+
+/// Works out the cores `--pin-cores` asked for. `auto` goes by the interface the scan goes out on: `--interface`, or
+/// else the one holding the first source address.
+fn get_worker_cores(
+    core_pinning: Option<CorePinning>,
+    interface: Option<&str>,
+    source_ipv4_addrs: &[Ipv4Addr],
+    source_ipv6_addrs: &[Ipv6Addr],
+) -> Vec<usize> {
+    match core_pinning {
+        None => Vec::new(),
+        Some(CorePinning::Cores(cores)) => cores,
+        Some(CorePinning::Auto) => {
+            let interface_name = interface.map(str::to_string).or_else(|| {
+                source_ipv4_addrs
+                    .first()
+                    .map(|ip| IpAddr::V4(*ip))
+                    .or_else(|| source_ipv6_addrs.first().map(|ip| IpAddr::V6(*ip)))
+                    .and_then(get_interface_with_ip)
+            });
+
+            match interface_name {
+                Some(interface_name) => get_interface_local_cores(&interface_name),
+                None => (0..std::thread::available_parallelism().map_or(1, |cores| cores.get())).collect(),
+            }
+        }
+    }
+}
+
+/// The kernel refuses to send from an address that isn't configured on one of our interfaces
+fn enforce_local_source_ips(source_ips: &[IpAddr]) {
+    if let Some(source_ip) = source_ips.iter().find(|source_ip| get_interface_with_ip(**source_ip).is_none()) {
+        panic!("Source ip {} isn't configured on any network interface, so probes can't be sent from it. Please add it to one first.", source_ip);
+    }
+}
+
+/// Responses to decoys would end up at our own stack, and some of them couldn't be told apart from responses to us
+fn enforce_remote_decoys(decoys: &[IpAddr]) {
+    if let Some(decoy) = decoys.iter().find(|decoy| get_interface_with_ip(**decoy).is_some()) {
+        panic!("Decoy {} is one of our own addresses. Please list it as a --source-ip or leave it out.", decoy);
+    }
+}
+
+/// Splits the source addresses by family. When none were given they're picked from `--interface`, or else the
+/// interface holding the default route.
+async fn split_and_enforce_source_ips(
+    source_ips: Option<Vec<IpAddr>>,
+    interface: Option<&str>,
+    link_local_source: Option<Ipv6Addr>,
+    targets: &HostIterator,
+) -> (Vec<Ipv4Addr>, Vec<Ipv6Addr>) {
     // we need to try to
-    let source_ips = match source_ips {
-        Some(source_ips) => source_ips,
-        _ => armada_lib::utils::get_default_ips()
+    let source_ips = match (source_ips, interface) {
+        (Some(source_ips), _) => source_ips,
+        (None, Some(interface)) => get_interface_ips(interface)
+            .unwrap_or_else(|| panic!("There's no network interface named {}.", interface))
+            .into_iter()
+            // link-local addresses can't be sent from to anything off of the link
+            .filter(|ip| !matches!(ip, IpAddr::V6(ip) if ip.is_unicast_link_local()))
+            .collect(),
+        (None, None) => armada_lib::utils::get_default_ips()
             .await
             .expect("Unable to identify source ip addresses automatically. Please supply them via --source-ip."),
     };
@@ -73,7 +620,7 @@ async fn split_and_enforce_source_ips(source_ips: Option<Vec<IpAddr>>) -> (Vec<I
         })
         .collect();
 
-    let source_ipv6_addrs: Vec<_> = ipv6_addrs
+    let mut source_ipv6_addrs: Vec<_> = ipv6_addrs
         .into_iter()
         .map(|ip| match ip {
             IpAddr::V6(ip_v6) => ip_v6,
@@ -81,5 +628,20 @@ async fn split_and_enforce_source_ips(source_ips: Option<Vec<IpAddr>>) -> (Vec<I
         })
         .collect();
 
+    // an interface with nothing but its link-local address can still probe the rest of the link
+    if let (true, Some(link_local_source)) = (source_ipv6_addrs.is_empty(), link_local_source) {
+        source_ipv6_addrs.push(link_local_source);
+    }
+
+    if let Some(interface) = interface {
+        if targets.has_ipv4() && source_ipv4_addrs.is_empty() {
+            panic!("Interface {} has no IPv4 address to probe the IPv4 targets from. Please supply one via --source-ip.", interface);
+        }
+
+        if targets.has_ipv6() && source_ipv6_addrs.is_empty() {
+            panic!("Interface {} has no IPv6 address to probe the IPv6 targets from. Please supply one via --source-ip.", interface);
+        }
+    }
+
     (source_ipv4_addrs, source_ipv6_addrs)
 }
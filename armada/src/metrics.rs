@@ -0,0 +1,191 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use armada_lib::{ArmadaWorkMessage, SourceStats};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long the per second rates are averaged over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// What the scan has done so far, as published on the metrics endpoint
+pub(crate) struct ScanMetrics {
+    state: Mutex<MetricsState>,
+}
+
+struct MetricsState {
+    total_ports: u128,
+    processed_ports: u128,
+    inflight_packets: u128,
+    packets_sent: u128,
+    responses_received: u128,
+    retries: u128,
+    ports_found: u128,
+    /// Only known when the scan is rate limited
+    rate_limit: Option<usize>,
+    /// When the current rate window started, and the counters at that point
+    rate_window: (Instant, u128, u128),
+    packets_sent_per_second: f64,
+    responses_received_per_second: f64,
+    source_stats: Vec<SourceStats>,
+}
+
+impl ScanMetrics {
+    pub(crate) fn new(total_ports: u128) -> Self {
+        Self {
+            state: Mutex::new(MetricsState {
+                total_ports,
+                processed_ports: 0,
+                inflight_packets: 0,
+                packets_sent: 0,
+                responses_received: 0,
+                retries: 0,
+                ports_found: 0,
+                rate_limit: None,
+                rate_window: (Instant::now(), 0, 0),
+                packets_sent_per_second: 0.0,
+                responses_received_per_second: 0.0,
+                source_stats: Vec::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn record(&self, message: &ArmadaWorkMessage) {
+        let mut state = self.state.lock().expect("Metrics lock poisoned.");
+
+        match message {
+            ArmadaWorkMessage::Results(results) => state.ports_found += results.len() as u128,
+            ArmadaWorkMessage::Stats {
+                total_processed_ports,
+                current_inflight_packets,
+                total_packets_sent,
+                total_responses_received,
+                total_retries,
+                packets_per_second,
+                source_stats,
+                ..
+            } => {
+                state.processed_ports = *total_processed_ports;
+                state.inflight_packets = *current_inflight_packets;
+                state.packets_sent = *total_packets_sent;
+                state.responses_received = *total_responses_received;
+                state.retries = *total_retries;
+                state.rate_limit = *packets_per_second;
+                state.source_stats.clone_from(source_stats);
+
+                let (window_start, window_packets_sent, window_responses_received) = state.rate_window;
+                let elapsed = window_start.elapsed();
+
+                if elapsed >= RATE_WINDOW {
+                    let seconds = elapsed.as_secs_f64();
+                    state.packets_sent_per_second = (state.packets_sent - window_packets_sent) as f64 / seconds;
+                    state.responses_received_per_second =
+                        (state.responses_received - window_responses_received) as f64 / seconds;
+                    state.rate_window = (Instant::now(), state.packets_sent, state.responses_received);
+                }
+            }
+            ArmadaWorkMessage::Failed(_) => {}
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let state = self.state.lock().expect("Metrics lock poisoned.");
+
+        let progress_percent = match state.total_ports {
+            0 => 100.0,
+            total_ports => state.processed_ports as f64 / total_ports as f64 * 100.0,
+        };
+
+        let metrics: [(&str, &str, &str, f64); 10] = [
+            ("armada_packets_sent_total", "counter", "Probe packets sent.", state.packets_sent as f64),
+            ("armada_responses_received_total", "counter", "Responses that settled the state of a port.", state.responses_received as f64),
+            ("armada_retries_total", "counter", "Probes sent again after going unanswered.", state.retries as f64),
+            ("armada_ports_found_total", "counter", "Ports reported as results.", state.ports_found as f64),
+            ("armada_ports_processed_total", "counter", "Ports whose state is settled.", state.processed_ports as f64),
+            ("armada_ports", "gauge", "Ports the scan covers in total.", state.total_ports as f64),
+            ("armada_inflight_packets", "gauge", "Probes still waiting for a response.", state.inflight_packets as f64),
+            ("armada_packets_sent_per_second", "gauge", "Probe packets sent per second.", state.packets_sent_per_second),
+            ("armada_responses_received_per_second", "gauge", "Responses received per second.", state.responses_received_per_second),
+            ("armada_progress_percent", "gauge", "Share of ports whose state is settled.", progress_percent),
+        ];
+
+        let rate_limit = state
+            .rate_limit
+            .map(|rate_limit| ("armada_rate_limit_packets_per_second", "gauge", "Packets per second the scan is limited to, which changes as the scan goes with --auto-rate.", rate_limit as f64));
+
+        let mut body = String::new();
+        for (name, kind, help, value) in metrics.into_iter().chain(rate_limit) {
+            let _ = writeln!(body, "# HELP {} {}", name, help);
+            let _ = writeln!(body, "# TYPE {} {}", name, kind);
+            let _ = writeln!(body, "{} {}", name, value);
+        }
+
+        if !state.source_stats.is_empty() {
+            let packets_sent = state.source_stats.iter().map(|stats| (stats.source, stats.packets_sent));
+            let responses_received = state.source_stats.iter().map(|stats| (stats.source, stats.responses_received));
+            let source_metrics = [
+                ("armada_source_packets_sent_total", "Probe packets sent from the source address.", packets_sent.collect::<Vec<_>>()),
+                ("armada_source_responses_received_total", "Responses that settled the state of a port probed from the source address.", responses_received.collect()),
+            ];
+
+            for (name, help, values) in source_metrics {
+                let _ = writeln!(body, "# HELP {} {}", name, help);
+                let _ = writeln!(body, "# TYPE {} counter", name);
+                for (source, value) in values {
+                    let _ = writeln!(body, "{}{{source=\"{}\"}} {}", name, source, value);
+                }
+            }
+        }
+
+        body
+    }
+}
+
+/// Serves the metrics on `/metrics` until the scan exits
+pub(crate) async fn serve_metrics(address: SocketAddr, metrics: Arc<ScanMetrics>) {
+    let listener = TcpListener::bind(address)
+        .await
+        .unwrap_or_else(|error| panic!("Unable to serve metrics on '{}': {}", address, error));
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(respond(stream, metrics.clone()));
+        }
+    });
+}
+
+/// Just enough HTTP for Prometheus and curl: the request line is read and every other header ignored
+async fn respond(mut stream: TcpStream, metrics: Arc<ScanMetrics>) {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_REQUEST_BYTES {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+    let (method, path) = (request_line.next(), request_line.next());
+
+    let response = match (method, path.map(|path| path.split('?').next().unwrap_or_default())) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,241 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use crate::output::ScanRecord;
+
+/// A column of CSV output, named in `--csv-fields` and the header row after the record field it holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CsvField {
+    Ip,
+    Port,
+    State,
+    MacAddress,
+    Banner,
+    TlsSubjectCn,
+    TlsSans,
+    TlsIssuer,
+    TlsNotAfter,
+    Service,
+    Version,
+    OsGuess,
+    Ttl,
+    Window,
+    TcpOptions,
+    Hostname,
+    Rdns,
+    Rtt,
+    Attempt,
+}
+
+impl CsvField {
+    /// The fields written when no `--csv-fields` are given, which stay the same from one release to the next so scripts
+    /// reading CSV by position keep working
+    pub(crate) const DEFAULT: [CsvField; 3] = [CsvField::Ip, CsvField::Port, CsvField::State];
+
+    /// Every field that can be picked. New ones are only ever added at the end.
+    pub(crate) const ALL: [CsvField; 19] = [
+        CsvField::Ip,
+        CsvField::Port,
+        CsvField::State,
+        CsvField::MacAddress,
+        CsvField::Banner,
+        CsvField::TlsSubjectCn,
+        CsvField::TlsSans,
+        CsvField::TlsIssuer,
+        CsvField::TlsNotAfter,
+        CsvField::Service,
+        CsvField::Version,
+        CsvField::OsGuess,
+        CsvField::Ttl,
+        CsvField::Window,
+        CsvField::TcpOptions,
+        CsvField::Hostname,
+        CsvField::Rdns,
+        CsvField::Rtt,
+        CsvField::Attempt,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            CsvField::Ip => "ip",
+            CsvField::Port => "port",
+            CsvField::State => "state",
+            CsvField::MacAddress => "mac_address",
+            CsvField::Banner => "banner",
+            CsvField::TlsSubjectCn => "tls_subject_cn",
+            CsvField::TlsSans => "tls_sans",
+            CsvField::TlsIssuer => "tls_issuer",
+            CsvField::TlsNotAfter => "tls_not_after",
+            CsvField::Service => "service",
+            CsvField::Version => "version",
+            CsvField::OsGuess => "os_guess",
+            CsvField::Ttl => "ttl",
+            CsvField::Window => "window",
+            CsvField::TcpOptions => "tcp_options",
+            CsvField::Hostname => "hostname",
+            CsvField::Rdns => "rdns",
+            CsvField::Rtt => "rtt",
+            CsvField::Attempt => "attempt",
+        }
+    }
+
+    /// The field's value for the record, empty when it isn't known
+    fn value(&self, record: &ScanRecord) -> String {
+        let tls = record.tls.as_ref();
+        let os = record.os.as_ref();
+
+        match self {
+            CsvField::Ip => record.host(),
+            CsvField::Port => record.port.to_string(),
+            CsvField::State => record.state.to_string(),
+            CsvField::MacAddress => record.mac_address.clone().unwrap_or_default(),
+            CsvField::Banner => record.banner.clone().unwrap_or_default(),
+            CsvField::TlsSubjectCn => tls.and_then(|tls| tls.subject_cn.clone()).unwrap_or_default(),
+            CsvField::TlsSans => tls.map(|tls| tls.sans.join(";")).unwrap_or_default(),
+            CsvField::TlsIssuer => tls.map(|tls| tls.issuer.clone()).unwrap_or_default(),
+            CsvField::TlsNotAfter => tls.map(|tls| tls.not_after.clone()).unwrap_or_default(),
+            CsvField::Service => record.service.clone().unwrap_or_default(),
+            CsvField::Version => record.version.clone().unwrap_or_default(),
+            CsvField::OsGuess => os.and_then(|os| os.guess).unwrap_or_default().to_string(),
+            CsvField::Ttl => os.and_then(|os| os.ttl).map(|ttl| ttl.to_string()).unwrap_or_default(),
+            CsvField::Window => os.map(|os| os.window.to_string()).unwrap_or_default(),
+            CsvField::TcpOptions => os.map(|os| os.tcp_options.clone()).unwrap_or_default(),
+            CsvField::Hostname => record.hostname.clone().unwrap_or_default(),
+            CsvField::Rdns => record.rdns.clone().unwrap_or_default(),
+            CsvField::Rtt => record.rtt_millis().map(|rtt| format!("{:.3}", rtt)).unwrap_or_default(),
+            CsvField::Attempt => record.attempt.map(|attempt| attempt.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for CsvField {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match CsvField::ALL.iter().find(|field| field.name() == name) {
+            Some(field) => Ok(*field),
+            None => bail!("Unknown CSV field '{}'.", name),
+        }
+    }
+}
+
+pub(crate) fn header_line(fields: &[CsvField]) -> String {
+    fields.iter().map(CsvField::name).collect::<Vec<_>>().join(",")
+}
+
+pub(crate) fn record_line(record: &ScanRecord, fields: &[CsvField]) -> String {
+    fields
+        .iter()
+        .map(|field| escape_csv_field(&field.value(record)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Quotes fields holding separators, quotes or line breaks, which banners routinely do
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use armada_lib::PortState;
+
+    use super::*;
+    use crate::output::TlsRecord;
+
+    fn record(addr: &str, state: PortState) -> ScanRecord {
+        let addr: SocketAddr = addr.parse().unwrap();
+
+        ScanRecord {
+            ip: addr.ip(),
+            zone: None,
+            hostname: None,
+            rdns: None,
+            port: addr.port(),
+            state,
+            rtt: None,
+            attempt: None,
+            mac_address: None,
+            banner: None,
+            tls: None,
+            service: None,
+            version: None,
+            os: None,
+        }
+    }
+
+    #[test]
+    fn plain_fields_are_left_alone() {
+        assert_eq!(escape_csv_field("192.0.2.1"), "192.0.2.1");
+        assert_eq!(escape_csv_field("SSH-2.0-OpenSSH_9.6"), "SSH-2.0-OpenSSH_9.6");
+        assert_eq!(escape_csv_field(""), "");
+    }
+
+    #[test]
+    fn only_the_ip_port_and_state_are_written_by_default() {
+        assert_eq!(header_line(&CsvField::DEFAULT), "ip,port,state");
+        assert_eq!(record_line(&record("192.0.2.1:443", PortState::Open), &CsvField::DEFAULT), "192.0.2.1,443,open");
+    }
+
+    #[test]
+    fn fields_are_written_in_the_order_picked() {
+        let fields: Vec<CsvField> = ["state", "rtt", "attempt", "port"].iter().map(|name| name.parse().unwrap()).collect();
+        let mut record = record("192.0.2.1:80", PortState::Open);
+        record.rtt = Some(Duration::from_micros(12_345));
+        record.attempt = Some(2);
+
+        assert_eq!(header_line(&fields), "state,rtt,attempt,port");
+        assert_eq!(record_line(&record, &fields), "open,12.345,2,80");
+    }
+
+    #[test]
+    fn link_local_ips_are_written_with_their_zone() {
+        let mut record = record("[fe80::1]:22", PortState::Open);
+        record.zone = Some("eth0".to_string());
+
+        assert_eq!(record_line(&record, &[CsvField::Ip, CsvField::Port]), "fe80::1%eth0,22");
+    }
+
+    #[test]
+    fn unknown_values_are_left_empty() {
+        let fields = [CsvField::Ip, CsvField::Banner, CsvField::TlsSans, CsvField::Ttl, CsvField::Rtt];
+
+        assert_eq!(record_line(&record("192.0.2.1:80", PortState::Filtered), &fields), "192.0.2.1,,,,");
+        assert_eq!(record_line(&record("[2001:db8::1]:22", PortState::Closed), &[CsvField::Ip]), "2001:db8::1");
+    }
+
+    #[test]
+    fn fields_with_separators_quotes_or_line_breaks_are_quoted() {
+        let mut record = record("192.0.2.1:22", PortState::Open);
+        record.banner = Some("SSH-2.0-OpenSSH_9.6 \"Ubuntu\", hi\r\n".to_string());
+        record.tls = Some(TlsRecord {
+            subject_cn: Some("example.com".to_string()),
+            sans: vec!["example.com".to_string(), "www.example.com".to_string()],
+            issuer: "CN=Example CA, O=Example".to_string(),
+            not_after: "2027-01-01T00:00:00Z".to_string(),
+        });
+        let fields = [CsvField::Banner, CsvField::TlsSubjectCn, CsvField::TlsSans, CsvField::TlsIssuer];
+
+        assert_eq!(
+            record_line(&record, &fields),
+            "\"SSH-2.0-OpenSSH_9.6 \"\"Ubuntu\"\", hi\r\n\",example.com,example.com;www.example.com,\"CN=Example CA, O=Example\"",
+        );
+    }
+
+    #[test]
+    fn every_field_is_named_after_itself_and_unknown_names_are_rejected() {
+        for field in CsvField::ALL {
+            assert_eq!(field.name().parse::<CsvField>().unwrap(), field);
+        }
+        assert_eq!(header_line(&CsvField::ALL[..3]), "ip,port,state");
+        assert!("ports".parse::<CsvField>().is_err());
+    }
+}
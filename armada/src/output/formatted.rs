@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use armada_lib::ResultSink;
+
+use crate::output::{
+    csv, grepable, hosts, nmap_xml, CsvField, NmapScanInfo, OutputFormat, ScanRecord, DEFAULT_HOST_WINDOW,
+};
+
+/// Writes records to stdout or a file in one of the output formats, either as they arrive or all at once, sorted by
+/// address, when the scan is done
+pub(crate) struct FormattedOutput {
+    format: OutputFormat,
+    destination: Box<dyn Write + Send>,
+    /// Whether records are written as they arrive rather than sorted once the scan is done
+    stream_results: bool,
+    buffered_records: Vec<ScanRecord>,
+    nmap_scan_info: NmapScanInfo,
+    csv_fields: Vec<CsvField>,
+    csv_header: bool,
+    csv_header_written: bool,
+    /// How long a host's results have to have stopped coming for its line of streamed host output to be written
+    host_window: Duration,
+    /// The records of streamed host output not written yet, by host, along with when the host's last one came
+    pending_hosts: HashMap<IpAddr, (Instant, Vec<ScanRecord>)>,
+}
+
+impl FormattedOutput {
+    /// Formats that don't support streaming are always written once the scan is done
+    pub(crate) fn new(format: OutputFormat, destination: Box<dyn Write + Send>, stream_results: bool) -> Self {
+        Self {
+            format,
+            destination,
+            stream_results: stream_results && format.supports_streaming(),
+            buffered_records: Vec::new(),
+            nmap_scan_info: NmapScanInfo::default(),
+            csv_fields: CsvField::DEFAULT.to_vec(),
+            csv_header: false,
+            csv_header_written: false,
+            host_window: DEFAULT_HOST_WINDOW,
+            pending_hosts: HashMap::new(),
+        }
+    }
+
+    /// Picks the columns of CSV output and whether a header row naming them comes first
+    pub(crate) fn with_csv_fields(mut self, csv_fields: Vec<CsvField>, csv_header: bool) -> Self {
+        self.csv_fields = csv_fields;
+        self.csv_header = csv_header;
+
+        self
+    }
+
+    /// Describes the scan in the header of nmap XML and greppable output
+    pub(crate) fn with_nmap_scan_info(mut self, nmap_scan_info: NmapScanInfo) -> Self {
+        self.nmap_scan_info = nmap_scan_info;
+
+        self
+    }
+
+    /// Sets how long a host's results have to have stopped coming for its line of streamed host output to be written
+    pub(crate) fn with_host_window(mut self, host_window: Duration) -> Self {
+        self.host_window = host_window;
+
+        self
+    }
+
+    /// Writes a line for each host of streamed host output whose results stopped coming a host window ago, or for
+    /// every host left once the scan is done
+    fn write_quiet_hosts(&mut self, all: bool) {
+        let mut quiet_hosts: Vec<IpAddr> = self
+            .pending_hosts
+            .iter()
+            .filter(|(_, (last_seen, _))| all || last_seen.elapsed() >= self.host_window)
+            .map(|(ip, _)| *ip)
+            .collect();
+        quiet_hosts.sort();
+
+        for ip in &quiet_hosts {
+            let (_, mut records) = self.pending_hosts.remove(ip).expect("Quiet hosts are pending.");
+            records.sort_by_key(|record| record.port);
+
+            writeln!(self.destination, "{}", hosts::host_line(&records)).expect("Failed to write result.");
+        }
+
+        if !quiet_hosts.is_empty() {
+            self.destination.flush().expect("Failed to write result.");
+        }
+    }
+
+    /// Writes the CSV header row ahead of the first record, or on its own if there are none
+    fn write_csv_header(&mut self) {
+        if self.format == OutputFormat::Csv && self.csv_header && !self.csv_header_written {
+            writeln!(self.destination, "{}", csv::header_line(&self.csv_fields)).expect("Failed to write results.");
+            self.csv_header_written = true;
+        }
+    }
+
+    fn record_line(&self, record: &ScanRecord) -> String {
+        match self.format {
+            OutputFormat::Default => record.to_default_line(),
+            OutputFormat::Csv => csv::record_line(record, &self.csv_fields),
+            OutputFormat::Ndjson => serde_json::to_string(record).expect("Failed to serialize result."),
+            OutputFormat::Json | OutputFormat::NmapXml | OutputFormat::Grepable | OutputFormat::Hosts => {
+                unreachable!("Document output is never written a record at a time.")
+            }
+        }
+    }
+}
+
+impl ResultSink<ScanRecord> for FormattedOutput {
+    fn accept(&mut self, record: &ScanRecord) {
+        if !self.stream_results {
+            self.buffered_records.push(record.clone());
+            return;
+        }
+
+        if self.format == OutputFormat::Hosts {
+            let (last_seen, records) =
+                self.pending_hosts.entry(record.ip).or_insert_with(|| (Instant::now(), Vec::new()));
+            *last_seen = Instant::now();
+            records.push(record.clone());
+
+            self.write_quiet_hosts(false);
+            return;
+        }
+
+        self.write_csv_header();
+
+        let line = self.record_line(record);
+        writeln!(self.destination, "{}", line).expect("Failed to write result.");
+        // whatever reads the stream, or is left over if the scan is killed, shouldn't wait on our buffering
+        self.destination.flush().expect("Failed to write result.");
+    }
+
+    fn finish(&mut self) {
+        self.write_csv_header();
+        self.write_quiet_hosts(true);
+
+        if !self.stream_results {
+            let mut records = std::mem::take(&mut self.buffered_records);
+            records.sort_by_key(|record| (record.ip, record.port));
+
+            let document = match self.format {
+                OutputFormat::Json => serde_json::to_string_pretty(&records).expect("Failed to serialize results.") + "\n",
+                OutputFormat::NmapXml => nmap_xml::render(&records, &self.nmap_scan_info),
+                OutputFormat::Grepable => grepable::render(&records, &self.nmap_scan_info),
+                OutputFormat::Hosts => hosts::render(&records),
+                _ => records.iter().map(|record| self.record_line(record) + "\n").collect(),
+            };
+
+            self.destination.write_all(document.as_bytes()).expect("Failed to write results.");
+        }
+
+        self.destination.flush().expect("Failed to write results.");
+    }
+}
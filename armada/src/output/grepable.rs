@@ -0,0 +1,82 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use armada_lib::PortState;
+
+use crate::output::nmap_xml::format_time;
+use crate::output::{NmapScanInfo, ScanRecord};
+
+/// Renders the records, which must be sorted by address, in nmap's greppable format: one `Host: ... Ports: ...` line
+/// per host, with each port written as `port/state/protocol/owner/service/rpc info/version/`.
+pub(crate) fn render(records: &[ScanRecord], scan_info: &NmapScanInfo) -> String {
+    let end_time = SystemTime::now();
+    let (_, protocol) = scan_info.scan_type_names();
+
+    let mut document = String::new();
+    let _ = writeln!(
+        document,
+        "# armada {} scan initiated {} as: {}",
+        env!("CARGO_PKG_VERSION"),
+        format_time(scan_info.start_time),
+        scan_info.arguments,
+    );
+
+    let hosts: Vec<&[ScanRecord]> = records.chunk_by(|a, b| a.ip == b.ip).collect();
+
+    for host_records in &hosts {
+        let host = &host_records[0];
+        let hostname = host_records
+            .iter()
+            .find_map(|record| record.rdns.as_deref())
+            .or(host.hostname.as_deref())
+            .unwrap_or_default();
+
+        let ports: Vec<String> = host_records
+            .iter()
+            .map(|record| {
+                // nmap has no admin filtered state of its own
+                let state = match record.state {
+                    PortState::AdminFiltered => PortState::Filtered,
+                    state => state,
+                };
+
+                format!(
+                    "{}/{}/{}//{}//{}/",
+                    record.port,
+                    state,
+                    protocol,
+                    escape_field(record.service.as_deref().unwrap_or_default()),
+                    escape_field(record.version.as_deref().unwrap_or_default()),
+                )
+            })
+            .collect();
+
+        let _ = writeln!(document, "Host: {} ({})\tStatus: Up", host.host(), hostname);
+        let _ = writeln!(document, "Host: {} ({})\tPorts: {}", host.host(), hostname, ports.join(", "));
+    }
+
+    let elapsed = end_time.duration_since(scan_info.start_time).unwrap_or_default();
+    let _ = writeln!(
+        document,
+        "# armada done at {} -- {} IP addresses ({} hosts up) scanned in {:.2} seconds",
+        format_time(end_time),
+        hosts.len(),
+        hosts.len(),
+        elapsed.as_secs_f64(),
+    );
+
+    document
+}
+
+/// Slashes and commas separate the fields and ports, so nmap swaps them out of the values
+fn escape_field(field: &str) -> String {
+    field
+        .chars()
+        .map(|character| match character {
+            '/' => '|',
+            ',' => ';',
+            character if character.is_control() => ' ',
+            character => character,
+        })
+        .collect()
+}
@@ -0,0 +1,29 @@
+use armada_lib::PortState;
+
+use crate::output::ScanRecord;
+
+/// Renders the records, which must be sorted by address, as one line per host
+pub(crate) fn render(records: &[ScanRecord]) -> String {
+    records.chunk_by(|a, b| a.ip == b.ip).map(|host_records| host_line(host_records) + "\n").collect()
+}
+
+/// The host's ports on one line, like `10.0.0.5: 22, 80, 443`, with the hostname the IP was resolved from in
+/// parentheses after it. Ports in a state other than open have it appended, like `81/closed`. The records must all be
+/// of the same host and sorted by port.
+pub(crate) fn host_line(records: &[ScanRecord]) -> String {
+    let host = &records[0];
+    let name = host.hostname.as_deref().or_else(|| records.iter().find_map(|record| record.rdns.as_deref()));
+
+    let ports: Vec<String> = records
+        .iter()
+        .map(|record| match record.state {
+            PortState::Open => record.port.to_string(),
+            state => format!("{}/{}", record.port, state),
+        })
+        .collect();
+
+    match name {
+        Some(name) => format!("{} ({}): {}", host.host(), name, ports.join(", ")),
+        None => format!("{}: {}", host.host(), ports.join(", ")),
+    }
+}
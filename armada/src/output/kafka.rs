@@ -0,0 +1,119 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+use armada_lib::ResultSink;
+use rskafka::chrono::DateTime;
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::ClientBuilder;
+use rskafka::record::Record;
+use rskafka::BackoffConfig;
+use time::OffsetDateTime;
+
+use crate::output::{block_on, ScanRecord};
+
+const DEFAULT_KAFKA_PORT: u16 = 9092;
+/// rskafka retries failed requests forever unless told otherwise, which would stall the scan on a dead broker
+const KAFKA_RETRY_DEADLINE: Duration = Duration::from_secs(30);
+
+/// Publishes every result to a Kafka topic as a JSON message as soon as it's found
+pub(crate) struct KafkaSink {
+    /// One client per partition of the topic, ordered by partition
+    partitions: Vec<PartitionClient>,
+}
+
+impl KafkaSink {
+    /// Connects to the brokers of a `kafka://broker[:port][,broker...]/topic` URL and looks up the topic's partitions
+    pub(crate) async fn connect(url: &str) -> Self {
+        let (brokers, topic) = parse_url(url)
+            .unwrap_or_else(|| panic!("Invalid Kafka URL '{}', expected kafka://broker/topic.", url));
+
+        let client = ClientBuilder::new(brokers)
+            .client_id("armada")
+            .backoff_config(BackoffConfig { deadline: Some(KAFKA_RETRY_DEADLINE), ..BackoffConfig::default() })
+            .build()
+            .await
+            .unwrap_or_else(|error| panic!("Unable to connect to Kafka: {}", error));
+
+        let partition_ids = client
+            .list_topics()
+            .await
+            .unwrap_or_else(|error| panic!("Unable to list Kafka topics: {}", error))
+            .into_iter()
+            .find(|listed_topic| listed_topic.name == topic)
+            .map(|listed_topic| listed_topic.partitions)
+            .filter(|partition_ids| !partition_ids.is_empty())
+            .unwrap_or_else(|| panic!("Kafka topic '{}' doesn't exist.", topic));
+
+        let mut partitions = Vec::with_capacity(partition_ids.len());
+        for partition_id in partition_ids {
+            let partition = client
+                .partition_client(topic, partition_id, UnknownTopicHandling::Retry)
+                .await
+                .unwrap_or_else(|error| panic!("Unable to reach partition {} of Kafka topic '{}': {}", partition_id, topic, error));
+
+            partitions.push(partition);
+        }
+
+        Self { partitions }
+    }
+}
+
+impl ResultSink<ScanRecord> for KafkaSink {
+    fn accept(&mut self, record: &ScanRecord) {
+        let key = record.ip.to_string();
+
+        // results for a host always land on the same partition, so consumers see them in the order they were found
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let partition = &self.partitions[hasher.finish() as usize % self.partitions.len()];
+
+        let message = Record {
+            key: Some(key.into_bytes()),
+            value: Some(serde_json::to_vec(record).expect("Failed to serialize result.")),
+            headers: BTreeMap::new(),
+            timestamp: DateTime::from_timestamp_nanos(OffsetDateTime::now_utc().unix_timestamp_nanos() as i64),
+        };
+
+        block_on(partition.produce(vec![message], Compression::NoCompression))
+            .unwrap_or_else(|error| panic!("Failed to publish result to Kafka: {}", error));
+    }
+}
+
+/// Splits a `kafka://broker[:port][,broker...]/topic` URL into the brokers, each with a port, and the topic
+fn parse_url(url: &str) -> Option<(Vec<String>, &str)> {
+    let (brokers, topic) = url
+        .strip_prefix("kafka://")
+        .and_then(|rest| rest.split_once('/'))
+        .filter(|(brokers, topic)| !brokers.is_empty() && !topic.is_empty())?;
+
+    let brokers = brokers
+        .split(',')
+        .map(|broker| if broker.contains(':') { broker.to_string() } else { format!("{}:{}", broker, DEFAULT_KAFKA_PORT) })
+        .collect();
+
+    Some((brokers, topic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brokers_without_a_port_get_the_default_one() {
+        assert_eq!(parse_url("kafka://localhost/scans"), Some((vec!["localhost:9092".to_string()], "scans")));
+        assert_eq!(
+            parse_url("kafka://kafka-1:19092,kafka-2/armada.results"),
+            Some((vec!["kafka-1:19092".to_string(), "kafka-2:9092".to_string()], "armada.results")),
+        );
+    }
+
+    #[test]
+    fn urls_need_a_broker_and_a_topic() {
+        assert_eq!(parse_url("kafka://localhost"), None);
+        assert_eq!(parse_url("kafka://localhost/"), None);
+        assert_eq!(parse_url("kafka:///scans"), None);
+        assert_eq!(parse_url("localhost/scans"), None);
+    }
+}
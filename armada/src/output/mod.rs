@@ -0,0 +1,374 @@
+mod csv;
+mod elasticsearch;
+mod formatted;
+mod grepable;
+mod hosts;
+mod kafka;
+mod nmap_xml;
+mod postgres;
+mod sqlite;
+mod syslog;
+mod webhook;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use armada_lib::{CertificateInfo, PortResult, PortState, ResultSink, SynAckFingerprint};
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub(crate) use crate::output::csv::CsvField;
+pub(crate) use crate::output::nmap_xml::NmapScanInfo;
+pub(crate) use crate::output::syslog::{SyslogFacility, SyslogSeverity, SyslogTransport};
+pub(crate) use crate::output::webhook::WebhookSink;
+use crate::output::elasticsearch::ElasticsearchSink;
+use crate::output::formatted::FormattedOutput;
+use crate::output::kafka::KafkaSink;
+use crate::output::postgres::PostgresSink;
+use crate::output::sqlite::SqliteSink;
+use crate::output::syslog::SyslogSink;
+
+/// How results are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    /// One `ip:port` line per result, followed by anything else known about it
+    Default,
+    /// A single JSON array of every result, written once the scan has finished
+    Json,
+    /// One self-contained JSON object per line and result, which can be streamed into jq or a log shipper
+    Ndjson,
+    /// One row per result holding the chosen `CsvField`s, all of them by default
+    Csv,
+    /// An nmap XML document, written once the scan has finished, for tools that already import nmap results
+    NmapXml,
+    /// nmap's greppable format, one `Host: ... Ports: ...` line per host, written once the scan has finished
+    Grepable,
+    /// One `ip: port, port` line per host, streamed a host at a time once the host's results stop coming
+    Hosts,
+}
+
+impl OutputFormat {
+    /// Whether results can be written out one at a time as they come in
+    pub(crate) fn supports_streaming(&self) -> bool {
+        !matches!(self, OutputFormat::Json | OutputFormat::NmapXml | OutputFormat::Grepable)
+    }
+}
+
+/// How long a host's results have to have stopped coming for its line of streamed host output to be written, unless
+/// the scan's timeouts call for another
+pub(crate) const DEFAULT_HOST_WINDOW: Duration = Duration::from_secs(5);
+
+/// Everything armada learned about a single scanned port
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ScanRecord {
+    pub(crate) ip: IpAddr,
+    /// The interface a link-local IP is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) zone: Option<String>,
+    /// The hostname target the IP was resolved from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) hostname: Option<String>,
+    /// The hostname the IP's PTR record points to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rdns: Option<String>,
+    pub(crate) port: u16,
+    #[serde(serialize_with = "serialize_state")]
+    pub(crate) state: PortState,
+    /// In milliseconds
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_rtt")]
+    pub(crate) rtt: Option<Duration>,
+    /// Which probe to the port it answered, 1 for the first and 2 for its first retry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) attempt: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) mac_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) banner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tls: Option<TlsRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) os: Option<OsRecord>,
+}
+
+/// A result as it came out of the scan, which follow-up work like banner grabbing can still add to
+impl From<PortResult> for ScanRecord {
+    fn from(result: PortResult) -> Self {
+        Self {
+            ip: result.addr.ip(),
+            zone: None,
+            hostname: None,
+            rdns: None,
+            port: result.addr.port(),
+            state: result.state,
+            rtt: result.rtt,
+            attempt: result.attempt,
+            mac_address: None,
+            banner: None,
+            tls: None,
+            service: None,
+            version: None,
+            os: result.fingerprint.map(OsRecord::from),
+        }
+    }
+}
+
+/// The certificate a TLS port presented
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct TlsRecord {
+    pub(crate) subject_cn: Option<String>,
+    pub(crate) sans: Vec<String>,
+    pub(crate) issuer: String,
+    /// RFC 3339 timestamp of when the certificate expires
+    pub(crate) not_after: String,
+}
+
+impl From<CertificateInfo> for TlsRecord {
+    fn from(certificate: CertificateInfo) -> Self {
+        Self {
+            subject_cn: certificate.subject_common_name,
+            sans: certificate.subject_alt_names,
+            issuer: certificate.issuer,
+            not_after: OffsetDateTime::from(certificate.not_after)
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The SYN-ACK traits of an open port and the operating system they point to
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct OsRecord {
+    pub(crate) guess: Option<&'static str>,
+    pub(crate) ttl: Option<u8>,
+    pub(crate) window: u16,
+    pub(crate) mss: Option<u16>,
+    pub(crate) window_scale: Option<u8>,
+    pub(crate) tcp_options: String,
+}
+
+impl From<SynAckFingerprint> for OsRecord {
+    fn from(fingerprint: SynAckFingerprint) -> Self {
+        Self {
+            guess: fingerprint.os_guess(),
+            ttl: fingerprint.ttl,
+            window: fingerprint.window,
+            mss: fingerprint.mss,
+            window_scale: fingerprint.window_scale,
+            tcp_options: fingerprint.options_layout,
+        }
+    }
+}
+
+impl ScanRecord {
+    /// The IP with its zone after it when it's link-local, like `fe80::1%eth0`
+    pub(crate) fn host(&self) -> String {
+        match &self.zone {
+            Some(zone) => format!("{}%{}", self.ip, zone),
+            None => self.ip.to_string(),
+        }
+    }
+
+    /// Open ports are printed as `ip:port`; any other state gets appended after the address, followed by the hostname
+    /// the IP was resolved from in parentheses. Reverse DNS names, MAC addresses, banners, certificates, services and
+    /// OS guesses, when known, are appended last.
+    fn to_default_line(&self) -> String {
+        let mut line = match self.state {
+            PortState::Open => format!("{}:{}", self.host(), self.port),
+            state => format!("{}:{} {}", self.host(), self.port, state),
+        };
+
+        if let Some(hostname) = &self.hostname {
+            line.push_str(&format!(" ({})", hostname));
+        }
+
+        if let Some(rdns) = &self.rdns {
+            line.push_str(&format!(" rdns({})", rdns));
+        }
+
+        if let Some(mac_address) = &self.mac_address {
+            line.push_str(&format!(" {}", mac_address));
+        }
+
+        if let Some(banner) = &self.banner {
+            line.push_str(&format!(" \"{}\"", banner.escape_debug()));
+        }
+
+        if let Some(tls) = &self.tls {
+            line.push_str(&format!(
+                " tls(cn={}, sans={}, issuer={}, expires={})",
+                tls.subject_cn.as_deref().unwrap_or_default(),
+                tls.sans.join(";"),
+                tls.issuer,
+                tls.not_after,
+            ));
+        }
+
+        if let Some(service) = &self.service {
+            match &self.version {
+                Some(version) => line.push_str(&format!(" service(name={}, version={})", service, version)),
+                None => line.push_str(&format!(" service(name={})", service)),
+            }
+        }
+
+        if let Some(os) = &self.os {
+            // the TTL is unknown for IPv6 hosts
+            let ttl = os.ttl.map(|ttl| format!(", ttl={}", ttl)).unwrap_or_default();
+
+            line.push_str(&format!(
+                " os(guess={}{}, window={}, options={})",
+                os.guess.unwrap_or("unknown"),
+                ttl,
+                os.window,
+                os.tcp_options,
+            ));
+        }
+
+        line
+    }
+
+    /// The round trip time in milliseconds, which is how it's written in every format
+    pub(crate) fn rtt_millis(&self) -> Option<f64> { self.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0) }
+}
+
+fn serialize_state<S: serde::Serializer>(state: &PortState, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(state.as_str())
+}
+
+fn serialize_rtt<S: serde::Serializer>(rtt: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    match rtt {
+        Some(rtt) => serializer.serialize_f64((rtt.as_secs_f64() * 1_000_000.0).round() / 1000.0),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Waits on a sink's network IO from within `ResultSink`, which isn't async. The runtime is multi-threaded, so the
+/// scan's other tasks carry on on the other workers meanwhile.
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+/// A sink picked with `-o`, which results are sent to instead of stdout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SinkTarget {
+    /// `sqlite:<path>`
+    Sqlite(PathBuf),
+    /// A `postgres://` or `postgresql://` connection string
+    Postgres(String),
+    /// `elasticsearch:<url>`, along with the index and how many results go in each bulk request
+    Elasticsearch { url: String, index: String, batch_size: usize },
+    /// A `kafka://broker/topic` URL
+    Kafka(String),
+    Syslog { transport: SyslogTransport, facility: SyslogFacility, severity: SyslogSeverity },
+}
+
+impl SinkTarget {
+    pub(crate) async fn open(&self) -> Box<dyn ResultSink<ScanRecord>> {
+        match self {
+            SinkTarget::Sqlite(path) => Box::new(SqliteSink::open(path)),
+            SinkTarget::Postgres(url) => Box::new(PostgresSink::connect(url).await),
+            SinkTarget::Elasticsearch { url, index, batch_size } => {
+                Box::new(ElasticsearchSink::new(url, index, *batch_size))
+            }
+            SinkTarget::Kafka(url) => Box::new(KafkaSink::connect(url).await),
+            SinkTarget::Syslog { transport, facility, severity } => {
+                Box::new(SyslogSink::connect(transport, *facility, *severity).await)
+            }
+        }
+    }
+}
+
+/// Hands every record to each of the sinks in turn, in the order they were added
+pub(crate) struct ResultWriter {
+    sinks: Vec<Box<dyn ResultSink<ScanRecord>>>,
+    nmap_scan_info: NmapScanInfo,
+    csv_fields: Vec<CsvField>,
+    csv_header: bool,
+    host_window: Duration,
+}
+
+impl ResultWriter {
+    pub(crate) fn new() -> Self {
+        Self {
+            sinks: Vec::new(),
+            nmap_scan_info: NmapScanInfo::default(),
+            csv_fields: CsvField::DEFAULT.to_vec(),
+            csv_header: false,
+            host_window: DEFAULT_HOST_WINDOW,
+        }
+    }
+
+    /// Adds somewhere for results to be written to in the format, laid out the way the CSV fields and nmap scan info
+    /// given so far say. Formats that don't support streaming are always written once the scan is done.
+    pub(crate) fn with_output(
+        self,
+        format: OutputFormat,
+        destination: Box<dyn Write + Send>,
+        stream_results: bool,
+    ) -> Self {
+        let output = FormattedOutput::new(format, destination, stream_results)
+            .with_csv_fields(self.csv_fields.clone(), self.csv_header)
+            .with_nmap_scan_info(self.nmap_scan_info.clone())
+            .with_host_window(self.host_window);
+
+        self.with_sink(Box::new(output))
+    }
+
+    /// Adds a sink every result is sent to as it arrives
+    pub(crate) fn with_sink(mut self, sink: Box<dyn ResultSink<ScanRecord>>) -> Self {
+        self.sinks.push(sink);
+
+        self
+    }
+
+    /// Picks the columns of CSV output added after this, and whether a header row naming them comes first
+    pub(crate) fn with_csv_fields(mut self, csv_fields: Vec<CsvField>, csv_header: bool) -> Self {
+        self.csv_fields = csv_fields;
+        self.csv_header = csv_header;
+
+        self
+    }
+
+    /// Sets how long a host's results have to have stopped coming for its line of host output added after this to be
+    /// written, when it's streamed. A host whose results come further apart than this gets a line for each batch.
+    pub(crate) fn with_host_window(mut self, host_window: Duration) -> Self {
+        self.host_window = host_window;
+
+        self
+    }
+
+    /// Describes the scan in the header of nmap XML and greppable output added after this
+    pub(crate) fn with_nmap_scan_info(mut self, nmap_scan_info: NmapScanInfo) -> Self {
+        self.nmap_scan_info = nmap_scan_info;
+
+        self
+    }
+
+}
+
+impl ResultSink<ScanRecord> for ResultWriter {
+    fn accept(&mut self, record: &ScanRecord) {
+        for sink in &mut self.sinks {
+            sink.accept(record);
+        }
+    }
+
+    fn finish(&mut self) {
+        for sink in &mut self.sinks {
+            sink.finish();
+        }
+    }
+}
+
+pub(crate) fn create_output_file(path: &Path) -> Box<dyn Write + Send> {
+    let file = File::create(path).unwrap_or_else(|error| panic!("Unable to create output file '{}': {}", path.display(), error));
+
+    Box::new(BufWriter::new(file))
+}
@@ -0,0 +1,385 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use armada_lib::{PortIterator, PortState, ScanType};
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::output::ScanRecord;
+
+/// nmap's own `Wed Oct 14 10:00:00 2026` style timestamps
+const NMAP_TIME_FORMAT: &[FormatItem<'static>] = format_description!(
+    "[weekday repr:short] [month repr:short] [day padding:space] [hour]:[minute]:[second] [year]"
+);
+
+/// What the `nmaprun` and `scaninfo` elements say about the scan as a whole
+#[derive(Clone, Debug)]
+pub(crate) struct NmapScanInfo {
+    scan_type: ScanType,
+    /// The scanned ports in nmap's `1-1000,8080` notation
+    services: String,
+    service_count: usize,
+    pub(super) arguments: String,
+    pub(super) start_time: SystemTime,
+}
+
+impl Default for NmapScanInfo {
+    fn default() -> Self {
+        Self::new(ScanType::default(), PortIterator::new())
+    }
+}
+
+impl NmapScanInfo {
+    pub(crate) fn new(scan_type: ScanType, ports: PortIterator) -> Self {
+        let mut ports: Vec<u16> = ports.collect();
+        ports.sort_unstable();
+        ports.dedup();
+
+        Self {
+            scan_type,
+            services: collapse_port_ranges(&ports),
+            service_count: ports.len(),
+            arguments: std::env::args().collect::<Vec<_>>().join(" "),
+            start_time: SystemTime::now(),
+        }
+    }
+
+    /// nmap's names for the scan type and the protocol it probes
+    pub(super) fn scan_type_names(&self) -> (&'static str, &'static str) {
+        match self.scan_type {
+            ScanType::Syn => ("syn", "tcp"),
+            ScanType::Fin => ("fin", "tcp"),
+            ScanType::Null => ("null", "tcp"),
+            ScanType::Xmas => ("xmas", "tcp"),
+            ScanType::Ack => ("ack", "tcp"),
+            ScanType::Window => ("window", "tcp"),
+            ScanType::SctpInit => ("sctpinit", "sctp"),
+        }
+    }
+
+    /// The reason nmap would give for a port ending up in the state, which depends on what the scan sent it
+    fn state_reason(&self, state: PortState) -> &'static str {
+        match (state, self.scan_type) {
+            (PortState::Open, ScanType::Syn) => "syn-ack",
+            (PortState::Open, ScanType::SctpInit) => "init-ack",
+            (PortState::Closed, ScanType::SctpInit) => "abort",
+            (PortState::Open | PortState::Closed | PortState::Unfiltered, _) => "reset",
+            (PortState::AdminFiltered, _) => "admin-prohibited",
+            (PortState::Filtered | PortState::OpenFiltered, _) => "no-response",
+        }
+    }
+}
+
+/// Renders the records, which must be sorted by address, as an nmap XML document
+pub(crate) fn render(records: &[ScanRecord], scan_info: &NmapScanInfo) -> String {
+    let end_time = SystemTime::now();
+    let (scan_type, protocol) = scan_info.scan_type_names();
+
+    let mut document = String::new();
+    let _ = writeln!(document, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(document, "<!DOCTYPE nmaprun>");
+    let _ = writeln!(
+        document,
+        r#"<nmaprun scanner="armada" args="{}" start="{}" startstr="{}" version="{}" xmloutputversion="1.05">"#,
+        escape_xml(&scan_info.arguments),
+        unix_seconds(scan_info.start_time),
+        format_time(scan_info.start_time),
+        env!("CARGO_PKG_VERSION"),
+    );
+    let _ = writeln!(
+        document,
+        r#"<scaninfo type="{}" protocol="{}" numservices="{}" services="{}"/>"#,
+        scan_type, protocol, scan_info.service_count, scan_info.services,
+    );
+
+    let hosts: Vec<&[ScanRecord]> = records.chunk_by(|a, b| a.ip == b.ip).collect();
+
+    for host_records in &hosts {
+        render_host(&mut document, host_records, scan_info, protocol, end_time);
+    }
+
+    let elapsed = end_time.duration_since(scan_info.start_time).unwrap_or_default();
+    let _ = writeln!(document, "<runstats>");
+    let _ = writeln!(
+        document,
+        r#"<finished time="{}" timestr="{}" elapsed="{:.2}" summary="armada done; {} IP addresses ({} hosts up) scanned in {:.2} seconds" exit="success"/>"#,
+        unix_seconds(end_time),
+        format_time(end_time),
+        elapsed.as_secs_f64(),
+        hosts.len(),
+        hosts.len(),
+        elapsed.as_secs_f64(),
+    );
+    let _ = writeln!(document, r#"<hosts up="{}" down="0" total="{}"/>"#, hosts.len(), hosts.len());
+    let _ = writeln!(document, "</runstats>");
+    let _ = writeln!(document, "</nmaprun>");
+
+    document
+}
+
+fn render_host(
+    document: &mut String,
+    records: &[ScanRecord],
+    scan_info: &NmapScanInfo,
+    protocol: &str,
+    end_time: SystemTime,
+) {
+    let host = &records[0];
+
+    // hosts only make it into the results by answering something, so they're up for whichever reason came first
+    let status_reason = if host.mac_address.is_some() {
+        "arp-response"
+    } else {
+        records
+            .iter()
+            .find(|record| !matches!(record.state, PortState::Filtered | PortState::OpenFiltered))
+            .map(|record| scan_info.state_reason(record.state))
+            .unwrap_or("user-set")
+    };
+
+    let _ = writeln!(
+        document,
+        r#"<host starttime="{}" endtime="{}"><status state="up" reason="{}" reason_ttl="0"/>"#,
+        unix_seconds(scan_info.start_time),
+        unix_seconds(end_time),
+        status_reason,
+    );
+    let _ = writeln!(
+        document,
+        r#"<address addr="{}" addrtype="{}"/>"#,
+        host.ip,
+        if host.ip.is_ipv4() { "ipv4" } else { "ipv6" },
+    );
+
+    if let Some(mac_address) = &host.mac_address {
+        let _ = writeln!(document, r#"<address addr="{}" addrtype="mac"/>"#, mac_address.to_uppercase());
+    }
+
+    let _ = writeln!(document, "<hostnames>");
+    if let Some(hostname) = &host.hostname {
+        let _ = writeln!(document, r#"<hostname name="{}" type="user"/>"#, escape_xml(hostname));
+    }
+    if let Some(rdns) = records.iter().find_map(|record| record.rdns.as_ref()) {
+        let _ = writeln!(document, r#"<hostname name="{}" type="PTR"/>"#, escape_xml(rdns));
+    }
+    let _ = writeln!(document, "</hostnames>");
+
+    let _ = writeln!(document, "<ports>");
+    for record in records {
+        render_port(document, record, scan_info, protocol);
+    }
+    let _ = writeln!(document, "</ports>");
+
+    let _ = writeln!(document, "</host>");
+}
+
+fn render_port(document: &mut String, record: &ScanRecord, scan_info: &NmapScanInfo, protocol: &str) {
+    // nmap has no admin filtered state of its own; the reason is what tells it apart
+    let state = match record.state {
+        PortState::AdminFiltered => PortState::Filtered,
+        state => state,
+    };
+
+    let _ = write!(
+        document,
+        r#"<port protocol="{}" portid="{}"><state state="{}" reason="{}" reason_ttl="{}"/>"#,
+        protocol,
+        record.port,
+        state,
+        scan_info.state_reason(record.state),
+        record.os.as_ref().and_then(|os| os.ttl).unwrap_or_default(),
+    );
+
+    if let Some(service) = &record.service {
+        let _ = write!(document, r#"<service name="{}""#, escape_xml(service));
+        if let Some(version) = &record.version {
+            let _ = write!(document, r#" product="{}""#, escape_xml(version));
+        }
+        let _ = write!(document, r#" method="probed" conf="10"/>"#);
+    }
+
+    if let Some(banner) = &record.banner {
+        let _ = write!(document, r#"<script id="banner" output="{}"/>"#, escape_xml(banner));
+    }
+
+    if let Some(tls) = &record.tls {
+        let mut output = String::new();
+        if let Some(subject_cn) = &tls.subject_cn {
+            let _ = writeln!(output, "Subject: commonName={}", subject_cn);
+        }
+        if !tls.sans.is_empty() {
+            let sans: Vec<String> = tls.sans.iter().map(|san| format!("DNS:{}", san)).collect();
+            let _ = writeln!(output, "Subject Alternative Name: {}", sans.join(", "));
+        }
+        let _ = writeln!(output, "Issuer: {}", tls.issuer);
+        let _ = write!(output, "Not valid after:  {}", tls.not_after);
+
+        let _ = write!(document, r#"<script id="ssl-cert" output="{}"/>"#, escape_xml(&output));
+    }
+
+    let _ = writeln!(document, "</port>");
+}
+
+/// Turns sorted, deduplicated ports into `1-3,80,443` notation
+fn collapse_port_ranges(ports: &[u16]) -> String {
+    let mut ranges = Vec::new();
+    let mut ports = ports.iter().copied().peekable();
+
+    while let Some(start) = ports.next() {
+        let mut end = start;
+        while ports.peek() == Some(&end.wrapping_add(1)) && end != u16::MAX {
+            end = ports.next().unwrap_or(end);
+        }
+
+        ranges.push(if start == end { start.to_string() } else { format!("{}-{}", start, end) });
+    }
+
+    ranges.join(",")
+}
+
+/// Escapes text for use in an attribute. Banners regularly hold control characters XML 1.0 can't represent at all,
+/// so those are written out as `\xHH` instead.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#xa;"),
+            '\r' => escaped.push_str("&#xd;"),
+            '\t' => escaped.push_str("&#x9;"),
+            character if character.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", character as u32);
+            }
+            character => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
+
+pub(super) fn format_time(time: SystemTime) -> String {
+    OffsetDateTime::from(time).format(NMAP_TIME_FORMAT).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    fn record(addr: &str, state: PortState) -> ScanRecord {
+        let addr: SocketAddr = addr.parse().unwrap();
+
+        ScanRecord {
+            ip: addr.ip(),
+            zone: None,
+            hostname: None,
+            rdns: None,
+            port: addr.port(),
+            state,
+            rtt: None,
+            attempt: None,
+            mac_address: None,
+            banner: None,
+            tls: None,
+            service: None,
+            version: None,
+            os: None,
+        }
+    }
+
+    fn scan_info(scan_type: ScanType, ports: PortIterator) -> NmapScanInfo {
+        NmapScanInfo { arguments: "armada -t 192.0.2.0/24 -p 1-3,80".to_string(), ..NmapScanInfo::new(scan_type, ports) }
+    }
+
+    #[test]
+    fn the_document_describes_the_scan() {
+        let ports = PortIterator::new().add_range(1, 3).add_port(80).add_port(2);
+        let document = render(&[], &scan_info(ScanType::Syn, ports));
+
+        assert!(document.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE nmaprun>\n<nmaprun scanner=\"armada\" args=\"armada -t 192.0.2.0/24 -p 1-3,80\""));
+        assert!(document.contains(r#"<scaninfo type="syn" protocol="tcp" numservices="4" services="1-3,80"/>"#));
+        assert!(document.contains(r#"<hosts up="0" down="0" total="0"/>"#));
+        assert!(document.ends_with("</runstats>\n</nmaprun>\n"));
+    }
+
+    #[test]
+    fn records_are_grouped_into_hosts() {
+        let records = [
+            record("192.0.2.1:22", PortState::Open),
+            record("192.0.2.1:80", PortState::Closed),
+            record("[2001:db8::1]:443", PortState::Open),
+        ];
+        let document = render(&records, &scan_info(ScanType::Syn, PortIterator::new().add_port(22)));
+
+        assert_eq!(document.matches("<host ").count(), 2);
+        assert!(document.contains(r#"<hosts up="2" down="0" total="2"/>"#));
+        assert!(document.contains(r#"<address addr="192.0.2.1" addrtype="ipv4"/>"#));
+        assert!(document.contains(r#"<address addr="2001:db8::1" addrtype="ipv6"/>"#));
+        assert!(document.contains(r#"<port protocol="tcp" portid="22"><state state="open" reason="syn-ack" reason_ttl="0"/></port>"#));
+        assert!(document.contains(r#"<port protocol="tcp" portid="80"><state state="closed" reason="reset" reason_ttl="0"/></port>"#));
+    }
+
+    #[test]
+    fn states_take_the_reasons_nmap_gives() {
+        let records = [
+            record("192.0.2.1:1", PortState::OpenFiltered),
+            record("192.0.2.1:2", PortState::AdminFiltered),
+            record("192.0.2.1:3", PortState::Closed),
+        ];
+        let document = render(&records, &scan_info(ScanType::Fin, PortIterator::new()));
+
+        assert!(document.contains(r#"<scaninfo type="fin" protocol="tcp""#));
+        assert!(document.contains(r#"portid="1"><state state="open|filtered" reason="no-response""#));
+        // nmap has no admin filtered state, only the reason
+        assert!(document.contains(r#"portid="2"><state state="filtered" reason="admin-prohibited""#));
+        assert!(document.contains(r#"portid="3"><state state="closed" reason="reset""#));
+        // the host is up for the first answer that isn't silence
+        assert!(document.contains(r#"<status state="up" reason="admin-prohibited" reason_ttl="0"/>"#));
+    }
+
+    #[test]
+    fn sctp_scans_are_rendered_as_sctp() {
+        let records = [record("192.0.2.1:38412", PortState::Open), record("192.0.2.1:2905", PortState::Closed)];
+        let document = render(&records, &scan_info(ScanType::SctpInit, PortIterator::new().add_port(38412)));
+
+        assert!(document.contains(r#"<scaninfo type="sctpinit" protocol="sctp" numservices="1" services="38412"/>"#));
+        assert!(document.contains(r#"<port protocol="sctp" portid="38412"><state state="open" reason="init-ack""#));
+        assert!(document.contains(r#"<port protocol="sctp" portid="2905"><state state="closed" reason="abort""#));
+    }
+
+    #[test]
+    fn banners_hostnames_and_macs_are_escaped_into_the_host() {
+        let mut record = record("192.0.2.1:80", PortState::Open);
+        record.hostname = Some("example.com".to_string());
+        record.rdns = Some("host-1.example.net".to_string());
+        record.mac_address = Some("02:fc:00:00:00:05".to_string());
+        record.service = Some("http".to_string());
+        record.banner = Some("HTTP/1.1 200 OK\r\nServer: <\"x\" & 'y'>\x07".to_string());
+        let document = render(&[record], &scan_info(ScanType::Syn, PortIterator::new()));
+
+        assert!(document.contains(r#"<status state="up" reason="arp-response" reason_ttl="0"/>"#));
+        assert!(document.contains(r#"<address addr="02:FC:00:00:00:05" addrtype="mac"/>"#));
+        assert!(document.contains(r#"<hostname name="example.com" type="user"/>"#));
+        assert!(document.contains(r#"<hostname name="host-1.example.net" type="PTR"/>"#));
+        assert!(document.contains(r#"<service name="http" method="probed" conf="10"/>"#));
+        assert!(document.contains(r#"<script id="banner" output="HTTP/1.1 200 OK&#xd;&#xa;Server: &lt;&quot;x&quot; &amp; &apos;y&apos;&gt;\x07"/>"#));
+    }
+
+    #[test]
+    fn ports_collapse_into_ranges() {
+        assert_eq!(collapse_port_ranges(&[]), "");
+        assert_eq!(collapse_port_ranges(&[1, 2, 3, 5, 7, 8, 65535]), "1-3,5,7-8,65535");
+        assert_eq!(collapse_port_ranges(&[65534, 65535]), "65534-65535");
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::Path;
+
+use armada_lib::ResultSink;
+use rusqlite::{params, Connection};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::output::ScanRecord;
+
+/// Every run gets a row in `scans`, so results from repeated scans into the same database can be told apart
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS scans (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        started_at TEXT NOT NULL,
+        arguments TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS results (
+        scan_id INTEGER NOT NULL REFERENCES scans (id),
+        timestamp TEXT NOT NULL,
+        ip TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        state TEXT NOT NULL,
+        rtt REAL
+    );
+    CREATE INDEX IF NOT EXISTS results_ip_port ON results (ip, port);
+";
+
+/// Writes results into an SQLite database as they arrive, one row per result
+pub(crate) struct SqliteSink {
+    connection: Connection,
+    scan_id: i64,
+}
+
+impl SqliteSink {
+    /// Opens the database, creating it and its tables when they don't exist yet, and records the start of this scan
+    pub(crate) fn open(path: &Path) -> Self {
+        let connection = Connection::open(path)
+            .unwrap_or_else(|error| panic!("Unable to open SQLite database '{}': {}", path.display(), error));
+
+        // WAL lets the database be queried while a scan is still writing to it
+        connection
+            .execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .and_then(|_| connection.execute_batch(SCHEMA))
+            .unwrap_or_else(|error| panic!("Unable to create the schema of SQLite database '{}': {}", path.display(), error));
+
+        connection
+            .execute(
+                "INSERT INTO scans (started_at, arguments) VALUES (?1, ?2)",
+                params![now(), std::env::args().collect::<Vec<_>>().join(" ")],
+            )
+            .expect("Failed to record the scan in the SQLite database.");
+
+        let scan_id = connection.last_insert_rowid();
+
+        Self { connection, scan_id }
+    }
+}
+
+impl ResultSink<ScanRecord> for SqliteSink {
+    fn accept(&mut self, record: &ScanRecord) {
+        self.connection
+            .prepare_cached(
+                "INSERT INTO results (scan_id, timestamp, ip, port, state, rtt) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .and_then(|mut statement| {
+                statement.execute(params![
+                    self.scan_id,
+                    now(),
+                    record.ip.to_string(),
+                    record.port,
+                    record.state.to_string(),
+                    record.rtt_millis(),
+                ])
+            })
+            .expect("Failed to write result to the SQLite database.");
+    }
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use armada_lib::PortState;
+
+    use super::*;
+
+    fn record(addr: &str, state: PortState) -> ScanRecord {
+        let addr: SocketAddr = addr.parse().unwrap();
+
+        ScanRecord {
+            ip: addr.ip(),
+            zone: None,
+            hostname: None,
+            rdns: None,
+            port: addr.port(),
+            state,
+            rtt: None,
+            attempt: None,
+            mac_address: None,
+            banner: None,
+            tls: None,
+            service: None,
+            version: None,
+            os: None,
+        }
+    }
+
+    #[test]
+    fn results_are_written_as_rows_of_the_scan() {
+        let mut sink = SqliteSink::open(Path::new(":memory:"));
+        let mut open = record("192.0.2.1:443", PortState::Open);
+        open.rtt = Some(Duration::from_micros(1_500));
+        sink.accept(&open);
+        sink.accept(&record("[2001:db8::1]:22", PortState::Closed));
+
+        let mut statement = sink
+            .connection
+            .prepare("SELECT scan_id, ip, port, state, rtt FROM results ORDER BY port DESC")
+            .unwrap();
+        let rows: Vec<(i64, String, u16, String, Option<f64>)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (sink.scan_id, "192.0.2.1".to_string(), 443, "open".to_string(), Some(1.5)),
+                (sink.scan_id, "2001:db8::1".to_string(), 22, "closed".to_string(), None),
+            ],
+        );
+    }
+
+    #[test]
+    fn every_scan_into_a_database_gets_its_own_id() {
+        let path = std::env::temp_dir().join(format!("armada-sqlite-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut first = SqliteSink::open(&path);
+        first.accept(&record("192.0.2.1:80", PortState::Open));
+        let first_id = first.scan_id;
+        drop(first);
+
+        let second = SqliteSink::open(&path);
+        let scans: i64 = second.connection.query_row("SELECT COUNT(*) FROM scans", [], |row| row.get(0)).unwrap();
+        let results: i64 = second.connection.query_row("SELECT COUNT(*) FROM results", [], |row| row.get(0)).unwrap();
+
+        assert_ne!(second.scan_id, first_id);
+        assert_eq!((scans, results), (2, 1));
+
+        drop(second);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
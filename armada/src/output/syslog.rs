@@ -0,0 +1,312 @@
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+use armada_lib::ResultSink;
+use anyhow::bail;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::UnixDatagram;
+
+use crate::output::{block_on, ScanRecord};
+
+const DEFAULT_SYSLOG_PORT: u16 = 514;
+
+/// Where syslog messages are sent, picked by the `-o` URL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SyslogTransport {
+    /// `syslog://host[:port]`
+    Udp(String),
+    /// `syslog+tcp://host[:port]`
+    Tcp(String),
+    /// `syslog:<path>`, usually `/dev/log`
+    Unix(String),
+}
+
+impl SyslogTransport {
+    pub(crate) fn parse(output: &str) -> Option<Self> {
+        if let Some(address) = output.strip_prefix("syslog://") {
+            Some(SyslogTransport::Udp(with_default_port(address)))
+        } else if let Some(address) = output.strip_prefix("syslog+tcp://") {
+            Some(SyslogTransport::Tcp(with_default_port(address)))
+        } else {
+            output.strip_prefix("syslog:").map(|path| SyslogTransport::Unix(path.to_string()))
+        }
+    }
+}
+
+fn with_default_port(address: &str) -> String {
+    if let Ok(ip) = Ipv6Addr::from_str(address) {
+        return format!("[{}]:{}", ip, DEFAULT_SYSLOG_PORT);
+    }
+
+    let has_port = match address.rsplit_once(':') {
+        Some((host, port)) => port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']')),
+        None => false,
+    };
+
+    if has_port {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, DEFAULT_SYSLOG_PORT)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SyslogFacility(u8);
+
+impl SyslogFacility {
+    /// The facilities of RFC 5424 by the names syslog.conf uses, in the order of their codes
+    pub(crate) const NAMES: [&'static str; 24] = [
+        "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp",
+        "audit", "alert", "clock", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+    ];
+}
+
+impl Default for SyslogFacility {
+    fn default() -> Self {
+        SyslogFacility(1)
+    }
+}
+
+impl FromStr for SyslogFacility {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match SyslogFacility::NAMES.iter().position(|facility| *facility == name) {
+            Some(code) => Ok(SyslogFacility(code as u8)),
+            None => bail!("Unknown syslog facility '{}'.", name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SyslogSeverity(u8);
+
+impl SyslogSeverity {
+    /// The severities of RFC 5424, in the order of their codes
+    pub(crate) const NAMES: [&'static str; 8] = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+}
+
+impl Default for SyslogSeverity {
+    fn default() -> Self {
+        SyslogSeverity(5)
+    }
+}
+
+impl FromStr for SyslogSeverity {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match SyslogSeverity::NAMES.iter().position(|severity| *severity == name) {
+            Some(code) => Ok(SyslogSeverity(code as u8)),
+            None => bail!("Unknown syslog severity '{}'.", name),
+        }
+    }
+}
+
+enum SyslogConnection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+/// Forwards every result to a syslog server as an RFC 5424 message holding the result as JSON
+pub(crate) struct SyslogSink {
+    connection: SyslogConnection,
+    priority: u8,
+    hostname: String,
+    process_id: u32,
+}
+
+impl SyslogSink {
+    pub(crate) async fn connect(transport: &SyslogTransport, facility: SyslogFacility, severity: SyslogSeverity) -> Self {
+        let connection = match transport {
+            SyslogTransport::Udp(address) => {
+                let server = lookup_host(address.as_str())
+                    .await
+                    .ok()
+                    .and_then(|mut addresses| addresses.next())
+                    .unwrap_or_else(|| panic!("Unable to resolve syslog server '{}'.", address));
+
+                let bind_address = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(bind_address)
+                    .await
+                    .unwrap_or_else(|error| panic!("Unable to create a socket for syslog: {}", error));
+                socket
+                    .connect(server)
+                    .await
+                    .unwrap_or_else(|error| panic!("Unable to reach syslog server '{}': {}", address, error));
+
+                SyslogConnection::Udp(socket)
+            }
+            SyslogTransport::Tcp(address) => SyslogConnection::Tcp(
+                TcpStream::connect(address)
+                    .await
+                    .unwrap_or_else(|error| panic!("Unable to connect to syslog server '{}': {}", address, error)),
+            ),
+            #[cfg(unix)]
+            SyslogTransport::Unix(path) => {
+                let socket = UnixDatagram::unbound()
+                    .unwrap_or_else(|error| panic!("Unable to create a socket for syslog: {}", error));
+                socket
+                    .connect(path)
+                    .unwrap_or_else(|error| panic!("Unable to connect to syslog socket '{}': {}", path, error));
+
+                SyslogConnection::Unix(socket)
+            }
+            #[cfg(not(unix))]
+            SyslogTransport::Unix(_) => panic!("Syslog over a unix socket isn't supported on this platform."),
+        };
+
+        let hostname = dns_lookup::get_hostname()
+            .ok()
+            .filter(|hostname| !hostname.is_empty() && hostname.is_ascii() && !hostname.contains(' '))
+            .unwrap_or_else(|| "-".to_string());
+
+        Self { connection, priority: facility.0 * 8 + severity.0, hostname, process_id: std::process::id() }
+    }
+
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+    fn format_message(&self, record: &ScanRecord) -> String {
+        format!(
+            "<{}>1 {} {} armada {} result - {}",
+            self.priority,
+            OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            self.hostname,
+            self.process_id,
+            serde_json::to_string(record).expect("Failed to serialize result."),
+        )
+    }
+}
+
+impl ResultSink<ScanRecord> for SyslogSink {
+    fn accept(&mut self, record: &ScanRecord) {
+        let message = self.format_message(record);
+
+        let sent = block_on(async {
+            match &mut self.connection {
+                SyslogConnection::Udp(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+                // TCP needs framing to tell messages apart, which RFC 6587 does by prefixing their length
+                SyslogConnection::Tcp(stream) => {
+                    stream.write_all(format!("{} {}", message.len(), message).as_bytes()).await
+                }
+                #[cfg(unix)]
+                SyslogConnection::Unix(socket) => socket.send(message.as_bytes()).await.map(|_| ()),
+            }
+        });
+
+        sent.unwrap_or_else(|error| panic!("Failed to send result to syslog: {}", error));
+    }
+
+    fn finish(&mut self) {
+        if let SyslogConnection::Tcp(stream) = &mut self.connection {
+            let _ = block_on(stream.flush());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use armada_lib::PortState;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn record(addr: &str, state: PortState) -> ScanRecord {
+        let addr: SocketAddr = addr.parse().unwrap();
+
+        ScanRecord {
+            ip: addr.ip(),
+            zone: None,
+            hostname: None,
+            rdns: None,
+            port: addr.port(),
+            state,
+            rtt: None,
+            attempt: None,
+            mac_address: None,
+            banner: None,
+            tls: None,
+            service: None,
+            version: None,
+            os: None,
+        }
+    }
+
+    #[test]
+    fn transports_are_picked_by_scheme_and_get_the_default_port() {
+        assert_eq!(SyslogTransport::parse("syslog://logs.example.com"), Some(SyslogTransport::Udp("logs.example.com:514".to_string())));
+        assert_eq!(SyslogTransport::parse("syslog://192.0.2.10:1514"), Some(SyslogTransport::Udp("192.0.2.10:1514".to_string())));
+        assert_eq!(SyslogTransport::parse("syslog+tcp://2001:db8::10"), Some(SyslogTransport::Tcp("[2001:db8::10]:514".to_string())));
+        assert_eq!(SyslogTransport::parse("syslog+tcp://[2001:db8::10]:6514"), Some(SyslogTransport::Tcp("[2001:db8::10]:6514".to_string())));
+        assert_eq!(SyslogTransport::parse("syslog:/dev/log"), Some(SyslogTransport::Unix("/dev/log".to_string())));
+        assert_eq!(SyslogTransport::parse("sqlite:results.db"), None);
+    }
+
+    #[test]
+    fn facilities_and_severities_are_named_as_in_syslog_conf() {
+        assert_eq!("kern".parse::<SyslogFacility>().unwrap(), SyslogFacility(0));
+        assert_eq!("local7".parse::<SyslogFacility>().unwrap(), SyslogFacility(23));
+        assert_eq!("emerg".parse::<SyslogSeverity>().unwrap(), SyslogSeverity(0));
+        assert_eq!("debug".parse::<SyslogSeverity>().unwrap(), SyslogSeverity(7));
+        assert_eq!(SyslogFacility::default(), "user".parse().unwrap());
+        assert_eq!(SyslogSeverity::default(), "notice".parse().unwrap());
+        assert!("local8".parse::<SyslogFacility>().is_err());
+        assert!("warn".parse::<SyslogSeverity>().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn results_are_sent_as_rfc_5424_datagrams() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let transport = SyslogTransport::Udp(server.local_addr().unwrap().to_string());
+        let facility = "local0".parse().unwrap();
+        let mut sink = SyslogSink::connect(&transport, facility, SyslogSeverity::default()).await;
+        sink.accept(&record("192.0.2.1:22", PortState::Open));
+
+        let mut datagram = [0; 2048];
+        let read = server.recv(&mut datagram).await.unwrap();
+        let message = String::from_utf8_lossy(&datagram[..read]).to_string();
+        let fields: Vec<&str> = message.splitn(8, ' ').collect();
+
+        // local0 (16) * 8 + notice (5)
+        assert_eq!(fields[0], "<133>1");
+        assert!(OffsetDateTime::parse(fields[1], &Rfc3339).is_ok());
+        assert_eq!(fields[3..7], ["armada", &std::process::id().to_string(), "result", "-"]);
+        assert_eq!(fields[7], r#"{"ip":"192.0.2.1","port":22,"state":"open"}"#);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tcp_messages_are_prefixed_with_their_length() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let transport = SyslogTransport::Tcp(listener.local_addr().unwrap().to_string());
+        let mut sink = SyslogSink::connect(&transport, SyslogFacility::default(), SyslogSeverity::default()).await;
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        sink.accept(&record("192.0.2.1:22", PortState::Open));
+        sink.accept(&record("192.0.2.1:23", PortState::Closed));
+        sink.finish();
+        drop(sink);
+
+        let mut received = String::new();
+        server.read_to_string(&mut received).await.unwrap();
+
+        let mut rest = received.as_str();
+        let mut messages = Vec::new();
+        while let Some((length, after)) = rest.split_once(' ') {
+            let length: usize = length.parse().unwrap();
+            messages.push(&after[..length]);
+            rest = &after[length..];
+        }
+
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|message| message.starts_with("<13>1 ")));
+        assert!(messages[1].ends_with(r#"{"ip":"192.0.2.1","port":23,"state":"closed"}"#));
+    }
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use armada_lib::ResultSink;
+use reqwest::Client;
+use serde_json::Value;
+
+use crate::output::{block_on, ScanRecord};
+
+const WEBHOOK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBHOOK_ATTEMPTS: u32 = 5;
+/// Doubled after every failed attempt
+const WEBHOOK_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// POSTs results as JSON to an HTTP endpoint while the scan runs
+pub(crate) struct WebhookSink {
+    client: Client,
+    url: String,
+    batch_size: usize,
+    batch: Vec<Value>,
+}
+
+impl WebhookSink {
+    /// With a batch size of 1 every result is sent on its own as a JSON object, otherwise results are sent as JSON
+    /// arrays of up to `batch_size`
+    pub(crate) fn new(url: String, batch_size: usize) -> Self {
+        let client = Client::builder()
+            .timeout(WEBHOOK_REQUEST_TIMEOUT)
+            .build()
+            .expect("Unable to create an HTTP client for the webhook.");
+
+        Self { client, url, batch_size, batch: Vec::with_capacity(batch_size) }
+    }
+
+    /// Sends the batch, backing off between attempts. A batch that still can't be delivered is reported on stderr
+    /// and dropped rather than holding up the scan.
+    async fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+
+        let batch = std::mem::take(&mut self.batch);
+        let body = match self.batch_size {
+            1 => batch[0].clone(),
+            _ => Value::Array(batch),
+        };
+        let count = body.as_array().map_or(1, Vec::len);
+
+        let mut backoff = WEBHOOK_INITIAL_BACKOFF;
+        for attempt in 1..=WEBHOOK_ATTEMPTS {
+            let response = self
+                .client
+                .post(&self.url)
+                .json(&body)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            match response {
+                Ok(_) => return,
+                Err(error) if attempt == WEBHOOK_ATTEMPTS => {
+                    eprintln!("Dropping {} results the webhook didn't accept after {} attempts: {}", count, attempt, error);
+                }
+                Err(_) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+}
+
+impl ResultSink<ScanRecord> for WebhookSink {
+    fn accept(&mut self, record: &ScanRecord) {
+        self.batch.push(serde_json::to_value(record).expect("Failed to serialize result."));
+
+        if self.batch.len() >= self.batch_size {
+            block_on(self.flush());
+        }
+    }
+
+    fn finish(&mut self) {
+        block_on(self.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use armada_lib::PortState;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn record(addr: &str, state: PortState) -> ScanRecord {
+        let addr: SocketAddr = addr.parse().unwrap();
+
+        ScanRecord {
+            ip: addr.ip(),
+            zone: None,
+            hostname: None,
+            rdns: None,
+            port: addr.port(),
+            state,
+            rtt: None,
+            attempt: None,
+            mac_address: None,
+            banner: None,
+            tls: None,
+            service: None,
+            version: None,
+            os: None,
+        }
+    }
+
+    /// Accepts every request and passes on the bodies it was sent
+    async fn endpoint() -> (String, mpsc::UnboundedReceiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/results", listener.local_addr().unwrap());
+        let (bodies, received) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+
+                let body = loop {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let content_length = head
+                            .lines()
+                            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length: ").map(str::to_string))
+                            .and_then(|length| length.parse::<usize>().ok())
+                            .unwrap_or_default();
+
+                        if body.len() >= content_length {
+                            break body.to_string();
+                        }
+                    }
+                };
+
+                let _ = bodies.send(serde_json::from_str(&body).unwrap());
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n").await;
+            }
+        });
+
+        (url, received)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn results_are_sent_one_at_a_time_by_default() {
+        let (url, mut received) = endpoint().await;
+        let mut sink = WebhookSink::new(url, 1);
+        sink.accept(&record("192.0.2.1:22", PortState::Open));
+        sink.accept(&record("192.0.2.1:80", PortState::Open));
+        sink.finish();
+
+        assert_eq!(received.recv().await.unwrap()["port"], 22);
+        assert_eq!(received.recv().await.unwrap()["port"], 80);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn batches_are_sent_as_arrays_and_the_rest_when_finishing() {
+        let (url, mut received) = endpoint().await;
+        let mut sink = WebhookSink::new(url, 2);
+        for port in [22, 80, 443] {
+            sink.accept(&record(&format!("192.0.2.1:{}", port), PortState::Open));
+        }
+
+        let first = received.recv().await.unwrap();
+        assert_eq!(first.as_array().map(Vec::len), Some(2));
+        assert!(received.try_recv().is_err());
+
+        sink.finish();
+        let rest = received.recv().await.unwrap();
+        assert_eq!(rest.as_array().map(Vec::len), Some(1));
+        assert_eq!(rest[0]["port"], 443);
+    }
+
+}
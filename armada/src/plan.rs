@@ -0,0 +1,284 @@
+use std::io::stdin;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use armada_lib::{Engine, HostIterator, PortIterator, ScanOptions, ScanType};
+use atty::Stream;
+use serde::Serialize;
+
+/// How a scan's plan is printed in place of running it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PlanFormat {
+    /// The plan in full, for `--dry-run`
+    Summary,
+    /// Just the estimates, as JSON for `armada estimate`
+    Estimate,
+}
+
+/// What a scan is about to do
+pub(crate) struct ScanPlan<'s> {
+    pub(crate) targets: &'s HostIterator,
+    pub(crate) ports: &'s PortIterator,
+    pub(crate) options: &'s ScanOptions,
+    pub(crate) engine: &'s Engine,
+    pub(crate) interface: Option<&'s str>,
+    pub(crate) listening_ports: &'s [u16],
+    pub(crate) discover: bool,
+    pub(crate) max_runtime: Option<Duration>,
+}
+
+/// How much a scan sends and for how long, both for every pair answering its first probe and for none of them
+/// answering, which sends every retry
+#[derive(Debug, Serialize)]
+pub(crate) struct ScanEstimate {
+    hosts: u128,
+    ipv4_hosts: u128,
+    ipv6_hosts: u128,
+    ports: u64,
+    /// Target/port pairs the scan probes, leaving out the ones a resumed scan skips
+    pairs: u128,
+    retries: u8,
+    probes: u128,
+    probes_with_retries: u128,
+    /// Copies of the probes spoofed from the decoys, each of which goes to the targets of its family
+    decoy_probes: u128,
+    decoy_probes_with_retries: u128,
+    /// Bytes of every probe and decoy, from the IP header on
+    bytes: u128,
+    bytes_with_retries: u128,
+    /// The rate limit probes are sent at, or the most it goes up to with auto rate. `None` is unlimited.
+    packets_per_second: Option<usize>,
+    /// The bandwidth probes take up while they're being sent. `None` when there's no telling how fast that is.
+    bits_per_second: Option<u128>,
+    /// From the first probe to the last one timing out. `None` when there's no telling how fast probes go out.
+    duration_seconds: Option<f64>,
+    duration_with_retries_seconds: Option<f64>,
+    /// When the scan is stopped regardless of how far it got
+    max_runtime_seconds: Option<u64>,
+}
+
+impl ScanPlan<'_> {
+    pub(crate) fn print(&self, format: PlanFormat) {
+        match format {
+            PlanFormat::Summary => {
+                println!("Dry run, no packets were sent.");
+                print!("{}", self.summary());
+            }
+            PlanFormat::Estimate => println!(
+                "{}",
+                serde_json::to_string_pretty(&self.estimate()).expect("Failed to serialize estimate.")
+            ),
+        }
+    }
+
+    pub(crate) fn estimate(&self) -> ScanEstimate {
+        let ScanOptions { port_retries, port_timeout, packets_per_second, pacing, decoys, skip_pairs, shard, .. } =
+            self.options;
+
+        let (ipv4_hosts, ipv6_hosts) = self.targets.family_sizes();
+        let ports = self.ports.size() as u128;
+        // a resumed scan skips the pairs it got through, which are taken off the IPv4 ones first for simplicity
+        let ipv4_pairs = (ipv4_hosts * ports).saturating_sub(*skip_pairs);
+        let ipv6_pairs = (ipv6_hosts * ports).saturating_sub(skip_pairs.saturating_sub(ipv4_hosts * ports));
+        // a shard probes about its share of each family, as its pairs are spread evenly over the scan
+        let (ipv4_pairs, ipv6_pairs) = match shard {
+            Some(shard) => (ipv4_pairs / shard.count as u128, ipv6_pairs / shard.count as u128),
+            None => (ipv4_pairs, ipv6_pairs),
+        };
+        let pairs = ipv4_pairs + ipv6_pairs;
+        let attempts = 1 + *port_retries as u128;
+
+        let decoy_addrs = decoys.as_ref().map_or(&[][..], |decoys| decoys.addrs.as_slice());
+        let ipv4_decoys = decoy_addrs.iter().filter(|decoy| decoy.is_ipv4()).count() as u128;
+        let ipv6_decoys = decoy_addrs.len() as u128 - ipv4_decoys;
+        let decoy_probes = ipv4_pairs * ipv4_decoys + ipv6_pairs * ipv6_decoys;
+
+        let bytes = ipv4_pairs * (1 + ipv4_decoys) * self.options.probe_length(false) as u128
+            + ipv6_pairs * (1 + ipv6_decoys) * self.options.probe_length(true) as u128;
+
+        // every probe waits for the slowest of the rate limit and the pacing, though decoys aren't held to either
+        let probe_interval = packets_per_second
+            .map(|packets_per_second| Duration::from_secs(1) / packets_per_second.max(1) as u32)
+            .unwrap_or_default()
+            .max(pacing.filter(|pacing| !pacing.per_destination).map_or(Duration::ZERO, |pacing| pacing.delay + pacing.jitter / 2));
+        let sending_seconds = (!probe_interval.is_zero()).then_some(probe_interval.as_secs_f64() * pairs as f64);
+        let timeout_seconds = port_timeout.as_secs_f64();
+
+        ScanEstimate {
+            hosts: ipv4_hosts + ipv6_hosts,
+            ipv4_hosts,
+            ipv6_hosts,
+            ports: self.ports.size(),
+            pairs,
+            retries: *port_retries,
+            probes: pairs,
+            probes_with_retries: pairs * attempts,
+            decoy_probes,
+            decoy_probes_with_retries: decoy_probes * attempts,
+            bytes,
+            bytes_with_retries: bytes * attempts,
+            packets_per_second: *packets_per_second,
+            bits_per_second: sending_seconds
+                .filter(|sending_seconds| *sending_seconds > 0.0)
+                .map(|sending_seconds| (bytes as f64 * 8.0 / sending_seconds) as u128),
+            duration_seconds: sending_seconds.map(|sending_seconds| round_seconds(sending_seconds + timeout_seconds)),
+            duration_with_retries_seconds: sending_seconds
+                .map(|sending_seconds| round_seconds((sending_seconds + timeout_seconds) * attempts as f64)),
+            max_runtime_seconds: self.max_runtime.map(|max_runtime| max_runtime.as_secs()),
+        }
+    }
+
+    /// Asks before starting a scan that sends more than `threshold` probes, decoys included, with its plan printed
+    /// first. A mistyped range is far easier to catch here than once it's being flooded, so without a terminal to ask
+    /// on the scan is refused instead.
+    pub(crate) fn confirm(&self, threshold: u128) {
+        let estimate = self.estimate();
+        let probes = estimate.probes.saturating_add(estimate.decoy_probes);
+        if probes <= threshold {
+            return;
+        }
+
+        eprint!("{}", self.summary());
+        if !atty::is(Stream::Stdin) || !atty::is(Stream::Stderr) {
+            panic!(
+                "This scan sends {} probes, more than the {} it may send without asking first. Please pass --yes to start it anyway, or raise --confirm-above.",
+                probes, threshold
+            );
+        }
+
+        eprint!("This scan sends {} probes, more than {}. Start it? [y/N] ", probes, threshold);
+        let mut answer = String::new();
+        stdin().read_line(&mut answer).unwrap_or_else(|error| panic!("Unable to read the answer: {}", error));
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            panic!("Not starting the scan.");
+        }
+    }
+
+    /// The plan, a line of it per setting
+    fn summary(&self) -> String {
+        let mut lines = Vec::new();
+        let estimate = self.estimate();
+        let ScanOptions { source_ipv4_addrs, source_ipv6_addrs, source_routes, scan_type, skip_pairs, auto_rate, .. } =
+            self.options;
+
+        match self.discover {
+            true => lines.push(format!("Hosts: {} ({}), down to the ones that answer discovery", estimate.hosts, families(self.targets))),
+            false => lines.push(format!("Hosts: {} ({})", estimate.hosts, families(self.targets))),
+        }
+        lines.push(format!("Ports: {}", estimate.ports));
+        lines.push(format!("Scan type: {}", scan_type_name(*scan_type)));
+
+        match skip_pairs {
+            0 => lines.push(format!("Target/port pairs: {}", estimate.pairs)),
+            _ => lines.push(format!("Target/port pairs: {}, the rest already covered by the checkpoint", estimate.pairs)),
+        }
+        match estimate.retries {
+            0 => lines.push(format!("Probes: {}", estimate.probes)),
+            retries => lines.push(format!("Probes: {}, up to {} with {} retries", estimate.probes, estimate.probes_with_retries, retries)),
+        }
+        if estimate.decoy_probes > 0 {
+            lines.push(format!("Decoy probes: {}, up to {} with retries", estimate.decoy_probes, estimate.decoy_probes_with_retries));
+        }
+
+        let rate = match (estimate.packets_per_second, auto_rate) {
+            (None, _) => "unlimited".to_string(),
+            (Some(packets_per_second), false) => format!("{} packets per second", packets_per_second),
+            (Some(packets_per_second), true) => format!("up to {} packets per second, adjusted to the network", packets_per_second),
+        };
+        lines.push(format!("Rate: {}", rate));
+
+        match (estimate.duration_seconds, estimate.duration_with_retries_seconds) {
+            (Some(duration), Some(duration_with_retries)) => lines.push(format!(
+                "Estimated duration: {} to send every probe once, up to {} with every retry",
+                format_duration(duration),
+                format_duration(duration_with_retries),
+            )),
+            _ => lines.push("Estimated duration: as fast as the network allows".to_string()),
+        }
+        if let Some(max_runtime) = self.max_runtime {
+            lines.push(format!("Max runtime: {}", format_duration(max_runtime.as_secs_f64())));
+        }
+
+        lines.push(format!("Engine: {}", engine_name(self.engine)));
+        lines.push(format!("Interface: {}", self.interface.unwrap_or("whichever the routing table picks")));
+        match source_routes.as_ref().is_some_and(|source_routes| !source_routes.is_empty()) {
+            true => lines.push(format!(
+                "Sources: picked per target from the routing table, or else {}",
+                sources(source_ipv4_addrs, source_ipv6_addrs),
+            )),
+            false => lines.push(format!("Sources: {}", sources(source_ipv4_addrs, source_ipv6_addrs))),
+        }
+        lines.push(format!(
+            "Listening ports: {}",
+            self.listening_ports.iter().map(u16::to_string).collect::<Vec<_>>().join(", "),
+        ));
+
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
+
+fn families(targets: &HostIterator) -> &'static str {
+    match (targets.has_ipv4(), targets.has_ipv6()) {
+        (true, true) => "IPv4 and IPv6",
+        (true, false) => "IPv4",
+        (false, true) => "IPv6",
+        (false, false) => "none",
+    }
+}
+
+/// The scan type as `--scan-type` takes it
+pub(crate) fn scan_type_name(scan_type: ScanType) -> &'static str {
+    match scan_type {
+        ScanType::Syn => "syn",
+        ScanType::Fin => "fin",
+        ScanType::Null => "null",
+        ScanType::Xmas => "xmas",
+        ScanType::Ack => "ack",
+        ScanType::Window => "window",
+        ScanType::SctpInit => "sctp",
+    }
+}
+
+fn engine_name(engine: &Engine) -> String {
+    match engine {
+        Engine::RawSocket => "raw sockets".to_string(),
+        Engine::IoUring => "raw sockets through io_uring".to_string(),
+        Engine::Xdp { interface, vlan: None, .. } => format!("XDP on {}", interface),
+        Engine::Xdp { interface, vlan: Some(vlan), .. } => format!("XDP on {}, VLAN {}", interface, vlan),
+        Engine::Npcap { interface, vlan: None, .. } => format!("Npcap on {}", interface),
+        Engine::Npcap { interface, vlan: Some(vlan), .. } => format!("Npcap on {}, VLAN {}", interface, vlan),
+        Engine::Bpf { interface, .. } => format!("BPF on {}", interface),
+    }
+}
+
+fn sources(source_ipv4_addrs: &[Ipv4Addr], source_ipv6_addrs: &[Ipv6Addr]) -> String {
+    let sources = source_ipv4_addrs
+        .iter()
+        .map(|ip| IpAddr::V4(*ip))
+        .chain(source_ipv6_addrs.iter().map(|ip| IpAddr::V6(*ip)))
+        .map(|ip| ip.to_string())
+        .collect::<Vec<_>>();
+
+    match sources.is_empty() {
+        true => "none".to_string(),
+        false => sources.join(", "),
+    }
+}
+
+/// Rounds to the millisecond, which is as precise as an estimate gets
+fn round_seconds(seconds: f64) -> f64 {
+    (seconds * 1_000.0).round() / 1_000.0
+}
+
+/// Writes the seconds in days, hours, minutes and seconds, leaving out the units that are zero
+fn format_duration(seconds: f64) -> String {
+    let seconds = (seconds.ceil() as u64).max(1);
+    let units = [(seconds / 86_400, "d"), (seconds / 3_600 % 24, "h"), (seconds / 60 % 60, "m"), (seconds % 60, "s")];
+
+    units
+        .iter()
+        .filter(|(amount, _)| *amount > 0)
+        .map(|(amount, unit)| format!("{}{}", amount, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
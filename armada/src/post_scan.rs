@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use armada_lib::{
+    BannerGrabber,
+    LiveHost,
+    MacAddr,
+    PortResult,
+    PortState,
+    ResultSink,
+    ServiceDetector,
+    TlsInspector,
+    COMMON_TLS_PORTS,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+use crate::output::{ResultWriter, ScanRecord, TlsRecord};
+use crate::resolve::ReverseResolver;
+
+/// Turns port scan results into records, running any follow-up work against the open ports along the way.
+/// Each result is handled on its own task so slow services don't hold up the rest of the output.
+#[derive(Default)]
+pub(crate) struct PostScan {
+    mac_addresses: HashMap<IpAddr, MacAddr>,
+    hostnames: HashMap<IpAddr, String>,
+    reverse_resolver: Option<ReverseResolver>,
+    banner_grabber: Option<BannerGrabber>,
+    tls_inspector: Option<TlsInspector>,
+    /// Whether certificates are collected from every open port rather than only the common TLS ports
+    tls_all_ports: bool,
+    service_detector: Option<ServiceDetector>,
+    /// The interface the link-local targets are on, and its index
+    ipv6_zone: Option<(String, u32)>,
+}
+
+impl PostScan {
+    /// Remembers the MAC addresses discovery resolved so they can be attached to each result
+    pub(crate) fn with_live_hosts(mut self, live_hosts: &[LiveHost]) -> Self {
+        self.mac_addresses = live_hosts
+            .iter()
+            .filter_map(|live_host| live_host.mac_address.map(|mac_address| (live_host.ip, mac_address)))
+            .collect();
+
+        self
+    }
+
+    /// Remembers which hostname target each resolved IP came from so results stay attributable to it
+    pub(crate) fn with_hostnames(mut self, hostnames: HashMap<IpAddr, String>) -> Self {
+        self.hostnames = hostnames;
+
+        self
+    }
+
+    pub(crate) fn with_reverse_resolver(mut self, reverse_resolver: Option<ReverseResolver>) -> Self {
+        self.reverse_resolver = reverse_resolver;
+
+        self
+    }
+
+    pub(crate) fn with_banner_grabber(mut self, banner_grabber: Option<BannerGrabber>) -> Self {
+        self.banner_grabber = banner_grabber;
+
+        self
+    }
+
+    pub(crate) fn with_tls_inspector(mut self, tls_inspector: Option<TlsInspector>, tls_all_ports: bool) -> Self {
+        self.tls_inspector = tls_inspector;
+        self.tls_all_ports = tls_all_ports;
+
+        self
+    }
+
+    pub(crate) fn with_service_detector(mut self, service_detector: Option<ServiceDetector>) -> Self {
+        self.service_detector = service_detector;
+
+        self
+    }
+
+    /// Reports link-local results with the zone they were scanned on, and connects to them through it
+    pub(crate) fn with_ipv6_zone(mut self, ipv6_zone: Option<String>) -> Self {
+        self.ipv6_zone = ipv6_zone.and_then(|zone| Some((zone.clone(), crate::ipv6::interface_index(&zone)?)));
+
+        self
+    }
+
+    /// Consumes results until the scan closes the channel, then finishes the writer once every record is written
+    pub(crate) async fn run(self, mut results: UnboundedReceiver<PortResult>, mut writer: ResultWriter) {
+        let (record_sender, mut record_receiver) = unbounded_channel();
+        let post_scan = Arc::new(self);
+
+        let dispatcher = tokio::spawn(async move {
+            while let Some(result) = results.recv().await {
+                let post_scan = post_scan.clone();
+                let record_sender = record_sender.clone();
+
+                tokio::spawn(async move {
+                    let _ = record_sender.send(post_scan.build_record(result).await);
+                });
+            }
+        });
+
+        while let Some(record) = record_receiver.recv().await {
+            writer.accept(&record);
+        }
+
+        dispatcher.await.expect("Post-scan dispatcher stopped unexpectedly.");
+        writer.finish();
+    }
+
+    pub(crate) async fn build_record(&self, mut result: PortResult) -> ScanRecord {
+        let zone = match (&self.ipv6_zone, &mut result.addr) {
+            (Some((zone, index)), SocketAddr::V6(addr)) if addr.ip().is_unicast_link_local() => {
+                addr.set_scope_id(*index);
+                Some(zone.clone())
+            }
+            _ => None,
+        };
+
+        let rdns = match (&self.reverse_resolver, result.state) {
+            (Some(reverse_resolver), PortState::Open) => reverse_resolver.lookup(result.addr.ip()).await,
+            _ => None,
+        };
+
+        let banner = match (&self.banner_grabber, result.state) {
+            (Some(banner_grabber), PortState::Open) => banner_grabber.grab(result.addr).await,
+            _ => None,
+        };
+
+        let certificate = match (&self.tls_inspector, result.state) {
+            (Some(tls_inspector), PortState::Open)
+                if self.tls_all_ports || COMMON_TLS_PORTS.contains(&result.addr.port()) =>
+            {
+                tls_inspector.inspect(result.addr).await
+            }
+            _ => None,
+        };
+
+        let service_match = match (&self.service_detector, result.state) {
+            (Some(service_detector), PortState::Open) => service_detector.detect(result.addr).await,
+            _ => None,
+        };
+
+        let ip = result.addr.ip();
+        let mac_address = self.mac_addresses.get(&ip).map(MacAddr::to_string);
+
+        ScanRecord {
+            zone,
+            hostname: self.hostnames.get(&ip).cloned(),
+            rdns,
+            mac_address,
+            banner: banner.map(|banner| String::from_utf8_lossy(&banner).into_owned()),
+            tls: certificate.map(TlsRecord::from),
+            service: service_match.as_ref().map(|service_match| service_match.service.clone()),
+            version: service_match.and_then(|service_match| service_match.version),
+            ..ScanRecord::from(result)
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use tracing::info;
+
+/// The user armada switches to once its raw sockets are open, so the scan itself, parsing whatever packets come
+/// back for hours on end, doesn't run as root
+#[derive(Clone, Debug)]
+pub(crate) struct RunAs {
+    pub(crate) name: String,
+    pub(crate) uid: libc::uid_t,
+    pub(crate) gid: libc::gid_t,
+}
+
+impl RunAs {
+    /// Looks the user up by name, or by uid when it's a number
+    pub(crate) fn lookup(user: &str) -> Option<Self> {
+        let entry = match user.parse::<libc::uid_t>() {
+            Ok(uid) => unsafe { libc::getpwuid(uid) },
+            Err(_) => {
+                let name = CString::new(user).ok()?;
+                unsafe { libc::getpwnam(name.as_ptr()) }
+            }
+        };
+
+        if entry.is_null() {
+            return None;
+        }
+
+        let entry = unsafe { &*entry };
+
+        Some(Self {
+            name: unsafe { CStr::from_ptr(entry.pw_name) }.to_string_lossy().into_owned(),
+            uid: entry.pw_uid,
+            gid: entry.pw_gid,
+        })
+    }
+
+    /// The user who ran armada through sudo, going by the variables sudo sets
+    pub(crate) fn sudo_user() -> Option<Self> {
+        let uid = std::env::var("SUDO_UID").ok()?.parse::<libc::uid_t>().ok().filter(|uid| *uid != 0)?;
+        let gid = std::env::var("SUDO_GID").ok()?.parse::<libc::gid_t>().ok()?;
+        let name = std::env::var("SUDO_USER").unwrap_or_else(|_| uid.to_string());
+
+        Some(Self { name, uid, gid })
+    }
+}
+
+pub(crate) fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Gives up root for good, switching to the user and their groups. Each change of the process's credentials applies
+/// to every thread of it, the workers already holding the raw sockets included.
+pub(crate) fn drop_privileges(run_as: &RunAs) {
+    let name = CString::new(run_as.name.as_str()).unwrap_or_default();
+
+    // the groups go first, since changing them takes root
+    let groups_set = match unsafe { libc::initgroups(name.as_ptr(), run_as.gid as _) } {
+        0 => true,
+        _ => unsafe { libc::setgroups(1, &run_as.gid) == 0 },
+    };
+    if !groups_set || unsafe { libc::setgid(run_as.gid) } != 0 || unsafe { libc::setuid(run_as.uid) } != 0 {
+        panic!("Unable to switch to user {}: {}", run_as.name, std::io::Error::last_os_error());
+    }
+
+    // a process that can still become root again hasn't really given it up
+    if unsafe { libc::setuid(0) } == 0 {
+        panic!("Still able to become root again after switching to user {}.", run_as.name);
+    }
+
+    info!(user = run_as.name.as_str(), uid = run_as.uid, gid = run_as.gid, "dropped root privileges");
+}
+
+/// Makes the file the user's, for one armada made as root to be moved aside and made again once it's dropped to them
+pub(crate) fn hand_over(path: &Path, run_as: &RunAs) {
+    std::os::unix::fs::chown(path, Some(run_as.uid), Some(run_as.gid))
+        .unwrap_or_else(|error| panic!("Unable to hand '{}' over to user {}: {}", path.display(), run_as.name, error));
+}
+
+/// Whether the file can be written over, or made next to it and moved over it, as the checkpoint is, by the user the
+/// process runs as
+pub(crate) fn can_write(path: &Path) -> bool {
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+
+    let accessible = |path: &Path, mode| {
+        CString::new(path.as_os_str().as_bytes()).is_ok_and(|path| unsafe { libc::access(path.as_ptr(), mode) } == 0)
+    };
+
+    accessible(directory, libc::W_OK | libc::X_OK) && (!path.exists() || accessible(path, libc::W_OK))
+}
@@ -0,0 +1,93 @@
+use cidr_utils::cidr::IpCidr;
+
+/// Private address space, which is only reachable from within the network using it: RFC 1918, the shared address
+/// space of carrier-grade NAT and unique local IPv6 addresses
+const PRIVATE_RANGES: [&str; 5] = ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "100.64.0.0/10", "fc00::/7"];
+
+/// The rest of the ranges that are never routed on the internet: this network, loopback, link-local, protocol
+/// assignments, documentation and benchmarking ranges, multicast, and the reserved 240.0.0.0/4 with the broadcast
+/// address in it. IPv4-mapped addresses are in here too, as they're IPv4 targets in disguise.
+const RESERVED_RANGES: [&str; 22] = [
+    "0.0.0.0/8",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.88.99.0/24",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "::ffff:0:0/96",
+    "64:ff9b:1::/48",
+    "100::/64",
+    "2001:2::/48",
+    "2001:db8::/32",
+    "3fff::/20",
+    "fe80::/10",
+    "fec0::/10",
+    "ff00::/8",
+];
+
+/// The ranges `--exclude-private` leaves out of the scan
+pub(crate) fn private_ranges() -> Vec<IpCidr> {
+    parse(&PRIVATE_RANGES)
+}
+
+/// The ranges `--exclude-reserved` leaves out of the scan, which are all bogons, private ranges included
+pub(crate) fn reserved_ranges() -> Vec<IpCidr> {
+    parse(&PRIVATE_RANGES).into_iter().chain(parse(&RESERVED_RANGES)).collect()
+}
+
+fn parse(ranges: &[&str]) -> Vec<IpCidr> {
+    ranges
+        .iter()
+        .map(|range| IpCidr::from_str(range).unwrap_or_else(|_| panic!("{} is a valid range", range)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::*;
+
+    fn covers(ranges: &[IpCidr], ip: &str) -> bool {
+        let ip: IpAddr = ip.parse().unwrap();
+
+        ranges.iter().any(|range| range.contains(ip))
+    }
+
+    #[test]
+    fn every_range_parses() {
+        assert_eq!(private_ranges().len(), PRIVATE_RANGES.len());
+        assert_eq!(reserved_ranges().len(), PRIVATE_RANGES.len() + RESERVED_RANGES.len());
+    }
+
+    #[test]
+    fn private_ranges_are_only_private_address_space() {
+        let private = private_ranges();
+
+        for ip in ["10.1.2.3", "172.31.255.255", "192.168.0.1", "100.64.0.1", "fd00::1"] {
+            assert!(covers(&private, ip), "{} isn't private", ip);
+        }
+        for ip in ["172.32.0.1", "127.0.0.1", "8.8.8.8", "2606:4700::1111"] {
+            assert!(!covers(&private, ip), "{} is private", ip);
+        }
+    }
+
+    #[test]
+    fn reserved_ranges_are_every_bogon() {
+        let reserved = reserved_ranges();
+
+        for ip in ["10.0.0.1", "0.0.0.0", "127.0.0.1", "169.254.1.1", "192.0.2.1", "198.19.0.1", "224.0.0.251", "255.255.255.255", "::1", "::ffff:8.8.8.8", "2001:db8::1", "fe80::1", "ff02::1"] {
+            assert!(covers(&reserved, ip), "{} isn't reserved", ip);
+        }
+        for ip in ["1.1.1.1", "8.8.8.8", "192.0.3.1", "2606:4700::1111", "2001:4860:4860::8888"] {
+            assert!(!covers(&reserved, ip), "{} is reserved", ip);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::lookup_host;
+use tokio::sync::OnceCell;
+use tokio::task::spawn_blocking;
+use tokio::time::timeout;
+
+/// Resolves every hostname to all of its A and AAAA records concurrently. Returns each address alongside the hostname
+/// it came from; when several hostnames share an address, the first one listed wins. Hostnames that fail to resolve
+/// are reported on stderr and skipped so a single stale entry doesn't stop the whole scan.
+pub(crate) async fn resolve_hostnames(hostnames: Vec<String>, resolve_timeout: Duration) -> Vec<(IpAddr, String)> {
+    let lookups: Vec<_> = hostnames
+        .into_iter()
+        .map(|hostname| {
+            tokio::spawn(async move {
+                // the system resolver asks for both A and AAAA records when the address family is left open
+                let lookup = timeout(resolve_timeout, lookup_host((hostname.as_str(), 0)))
+                    .await
+                    .map(|lookup| lookup.map(|socket_addrs| socket_addrs.map(|socket_addr| socket_addr.ip()).collect::<Vec<_>>()));
+
+                (hostname, lookup)
+            })
+        })
+        .collect();
+
+    let mut addresses = Vec::new();
+    let mut seen_ips = HashSet::new();
+
+    for lookup in lookups {
+        let (hostname, lookup) = lookup.await.expect("Hostname resolution stopped unexpectedly.");
+
+        let resolved_ips = match lookup {
+            Ok(Ok(resolved_ips)) => resolved_ips,
+            Ok(Err(error)) => {
+                eprintln!("Unable to resolve target '{}': {}. Skipping it.", hostname, error);
+                continue;
+            }
+            Err(_) => {
+                eprintln!("Timed out resolving target '{}'. Skipping it.", hostname);
+                continue;
+            }
+        };
+
+        for ip in resolved_ips {
+            if seen_ips.insert(ip) {
+                addresses.push((ip, hostname.clone()));
+            }
+        }
+    }
+
+    addresses
+}
+
+/// Looks up the PTR record of hosts, remembering the answer so hosts with many open ports are only looked up once
+pub(crate) struct ReverseResolver {
+    lookup_timeout: Duration,
+    /// Concurrent lookups of the same host wait on the same cell instead of each sending their own query
+    cache: Mutex<HashMap<IpAddr, Arc<OnceCell<Option<String>>>>>,
+}
+
+impl ReverseResolver {
+    pub(crate) fn new(lookup_timeout: Duration) -> Self {
+        Self {
+            lookup_timeout,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `None` if the host has no PTR record or the lookup didn't finish in time
+    pub(crate) async fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let cell = self
+            .cache
+            .lock()
+            .expect("Reverse DNS cache lock poisoned.")
+            .entry(ip)
+            .or_default()
+            .clone();
+
+        cell.get_or_init(|| async {
+            // the system resolver only offers a blocking PTR lookup
+            let lookup = spawn_blocking(move || dns_lookup::lookup_addr(&ip));
+
+            match timeout(self.lookup_timeout, lookup).await {
+                Ok(Ok(Ok(hostname))) => Some(hostname),
+                _ => None,
+            }
+        })
+        .await
+        .clone()
+    }
+}
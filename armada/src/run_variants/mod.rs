@@ -1,18 +1,130 @@
 mod run_quiet;
+mod run_with_dashboard;
+mod run_with_json_progress;
 mod run_with_stats;
 
-use std::net::{
-    Ipv4Addr,
-    Ipv6Addr,
-    SocketAddr,
-};
+use std::sync::Arc;
 use std::time::Duration;
 
 use armada_lib::{
+    ArmadaWorkMessage,
+    CancellationToken,
     HostIterator,
     PortIterator,
+    PortResult,
+    ScanOptions,
 };
 use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::checkpoint::Checkpointer;
+use crate::control::ControlSocket;
+use crate::status::ScanStatus;
+use crate::metrics::ScanMetrics;
+
+/// Everything that watches the scan's progress on top of the run variant itself
+#[derive(Default)]
+pub(crate) struct ScanHooks {
+    pub(crate) metrics: Option<Arc<ScanMetrics>>,
+    pub(crate) checkpointer: Option<Checkpointer>,
+    pub(crate) runtime_limit: Option<RuntimeLimit>,
+    pub(crate) status: Option<Arc<ScanStatus>>,
+    pub(crate) control_socket: Option<Arc<ControlSocket>>,
+    /// The token interrupting armada cancels the scan with
+    pub(crate) cancellation: Option<CancellationToken>,
+    /// Whether a worker failed part way through the scan
+    pub(crate) failed: bool,
+}
+
+impl ScanHooks {
+    pub(crate) fn observe(&mut self, message: &mut ArmadaWorkMessage) {
+        if let ArmadaWorkMessage::Failed(_) = message {
+            self.failed = true;
+        }
+
+        if let Some(checkpointer) = &mut self.checkpointer {
+            checkpointer.observe(message);
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.record(message);
+        }
+
+        if let Some(runtime_limit) = &mut self.runtime_limit {
+            runtime_limit.observe(message);
+        }
+
+        if let Some(status) = &self.status {
+            status.record(message);
+        }
+    }
+
+    /// Wraps up once the scan is over, `stopped` being whether it was told to stop before getting to the end
+    pub(crate) fn finish(self, stopped: bool) {
+        let cancelled = self.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled);
+        let cut_short =
+            stopped || cancelled || self.failed || self.runtime_limit.as_ref().is_some_and(RuntimeLimit::cut_short);
+        if let Some(runtime_limit) = &self.runtime_limit {
+            runtime_limit.report();
+        }
+
+        if let Some(control_socket) = &self.control_socket {
+            control_socket.finish();
+        }
+
+        // a scan that was stopped, interrupted, failed or cut short by the runtime limit is left to be resumed
+        match (self.checkpointer, cut_short) {
+            (Some(checkpointer), true) => checkpointer.keep(),
+            (Some(checkpointer), false) => checkpointer.finish(),
+            (None, _) => {}
+        }
+    }
+}
+
+/// Keeps count of the pairs the scan settles, to tell how much of it got done if `--max-runtime` stops it early
+pub(crate) struct RuntimeLimit {
+    max_runtime: Duration,
+    total_pairs: u128,
+    /// Pairs settled before the scan was resumed, which count as covered
+    skipped_pairs: u128,
+    settled_pairs: u128,
+}
+
+impl RuntimeLimit {
+    pub(crate) fn new(max_runtime: Duration, total_pairs: u128, skipped_pairs: u128) -> Self {
+        Self { max_runtime, total_pairs, skipped_pairs, settled_pairs: 0 }
+    }
+
+    fn observe(&mut self, message: &ArmadaWorkMessage) {
+        if let ArmadaWorkMessage::Stats { total_processed_ports, .. } = message {
+            self.settled_pairs = *total_processed_ports;
+        }
+    }
+
+    fn covered_pairs(&self) -> u128 {
+        (self.skipped_pairs + self.settled_pairs).min(self.total_pairs)
+    }
+
+    fn cut_short(&self) -> bool {
+        self.covered_pairs() < self.total_pairs
+    }
+
+    /// Says how far the scan got, if it didn't get to the end
+    fn report(&self) {
+        if !self.cut_short() {
+            return;
+        }
+
+        let percentage = self.covered_pairs() as f64 * 100.0 / self.total_pairs as f64;
+        eprintln!(
+            "Stopped at the max runtime of {}, having covered {} of {} target/port pairs ({:.2}%).",
+            format_runtime(self.max_runtime),
+            self.covered_pairs(),
+            self.total_pairs,
+            percentage,
+        );
+    }
+}
 
 #[async_trait]
 pub(crate) trait QuietArmada {
@@ -20,13 +132,10 @@ pub(crate) trait QuietArmada {
         &self,
         targets: HostIterator,
         ports: PortIterator,
-        source_ipv4_addrs: Vec<Ipv4Addr>,
-        source_ipv6_addrs: Vec<Ipv6Addr>,
-        retries: u8,
-        timeout: Duration,
-        rate_limit: Option<usize>,
-        stream_results: bool
-    ) -> Vec<SocketAddr>;
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        hooks: ScanHooks,
+    );
 }
 
 #[async_trait]
@@ -35,11 +144,43 @@ pub(crate) trait ProgressArmada {
         &self,
         targets: HostIterator,
         ports: PortIterator,
-        source_ipv4_addrs: Vec<Ipv4Addr>,
-        source_ipv6_addrs: Vec<Ipv6Addr>,
-        retries: u8,
-        timeout: Duration,
-        rate_limit: Option<usize>,
-        stream_results: bool
-    ) -> Vec<SocketAddr>;
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        hooks: ScanHooks,
+    );
+}
+
+#[async_trait]
+pub(crate) trait DashboardArmada {
+    async fn run_with_dashboard(
+        &self,
+        targets: HostIterator,
+        ports: PortIterator,
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        hooks: ScanHooks,
+    );
+}
+
+#[async_trait]
+pub(crate) trait JsonProgressArmada {
+    async fn run_with_json_progress(
+        &self,
+        targets: HostIterator,
+        ports: PortIterator,
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        hooks: ScanHooks,
+    );
+}
+
+/// Writes the runtime in the largest unit that fits it exactly, the way `--max-runtime` takes it
+fn format_runtime(runtime: Duration) -> String {
+    let seconds = runtime.as_secs();
+    let (unit_seconds, unit) = [(24 * 60 * 60, 'd'), (60 * 60, 'h'), (60, 'm')]
+        .into_iter()
+        .find(|(unit_seconds, _)| seconds.is_multiple_of(*unit_seconds))
+        .unwrap_or((1, 's'));
+
+    format!("{}{}", seconds / unit_seconds, unit)
 }
@@ -1,14 +1,8 @@
-use std::net::{
-    Ipv4Addr,
-    Ipv6Addr,
-    SocketAddr,
-};
-use std::time::Duration;
-
-use armada_lib::{Armada, HostIterator, PortIterator, ArmadaWorkMessage};
+use armada_lib::{Armada, HostIterator, PortIterator, ArmadaWorkMessage, PortResult, ScanOptions};
 use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
 
-use crate::run_variants::QuietArmada;
+use crate::run_variants::{QuietArmada, ScanHooks};
 
 #[async_trait]
 impl QuietArmada for Armada {
@@ -16,48 +10,22 @@ impl QuietArmada for Armada {
         &self,
         targets: HostIterator,
         ports: PortIterator,
-        source_ipv4_addrs: Vec<Ipv4Addr>,
-        source_ipv6_addrs: Vec<Ipv6Addr>,
-        retries: u8,
-        timeout: Duration,
-        rate_limit: Option<usize>,
-        stream_results: bool
-    ) -> Vec<SocketAddr> {
-        if stream_results {
-            let mut reporting_handle = self.scan_with_handle(
-                targets,
-                ports,
-                source_ipv4_addrs,
-                source_ipv6_addrs,
-                retries,
-                timeout,
-                rate_limit,
-            ).unwrap();
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        mut hooks: ScanHooks,
+    ) {
+        let mut reporting_handle = self.scan_with_handle(targets, ports, options).unwrap();
 
-            while let Some(message) = reporting_handle.recv().await {
-                match message {
-                    ArmadaWorkMessage::Results(results) => {
-                        results.iter().for_each(|remote| {
-                            println!("{}:{}", remote.ip(), remote.port());
-                        });
-                    }
-                    _ => {}
-                }
-            }
+        while let Some(mut message) = reporting_handle.recv().await {
+            hooks.observe(&mut message);
 
-            vec![]
-        } else {
-            self.scan_collect(
-                targets,
-                ports,
-                source_ipv4_addrs,
-                source_ipv6_addrs,
-                retries,
-                timeout,
-                rate_limit,
-            )
-                .await
-                .unwrap()
+            if let ArmadaWorkMessage::Results(results) = message {
+                results.into_iter().for_each(|result| {
+                    let _ = result_sender.send(result);
+                });
+            }
         }
+
+        hooks.finish(self.is_stopped());
     }
 }
@@ -0,0 +1,521 @@
+use std::collections::VecDeque;
+use std::io::Stderr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use armada_lib::{Armada, ArmadaWorkMessage, HostIterator, PortIterator, PortResult, ScanOptions, ScanShard};
+use async_trait::async_trait;
+use cidr_utils::cidr::IpCidr;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::crossterm::{cursor, execute};
+use ratatui::style::Stylize;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Paragraph;
+use ratatui::Terminal;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::run_variants::{DashboardArmada, ScanHooks};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// How far back the send rate is measured over
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+/// How much a key press raises or lowers the rate limit by
+const RATE_STEP: f64 = 1.25;
+/// The lowest rate limit the keys go down to, which is the least a worker can send at
+const MIN_RATE_LIMIT: usize = 10;
+const RECENT_RESULTS: usize = 8;
+const BAR_WIDTH: usize = 24;
+
+/// Whether the dashboard has the terminal, for a panic to know to give it back
+static SCREEN_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+#[async_trait]
+impl DashboardArmada for Armada {
+    async fn run_with_dashboard(
+        &self,
+        targets: HostIterator,
+        ports: PortIterator,
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        mut hooks: ScanHooks,
+    ) {
+        let mut dashboard = Dashboard::new(&targets, &ports, &options);
+        let mut reporting_handle = self.scan_with_handle(targets, ports, options).unwrap();
+
+        let mut screen = Screen::enter().unwrap_or_else(|error| panic!("Unable to draw the dashboard: {}", error));
+        let (input_sender, mut input_receiver) = unbounded_channel();
+        let inputs = inputs::InputReader::start(input_sender);
+        let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+
+        loop {
+            tokio::select! {
+                message = reporting_handle.recv() => {
+                    let Some(mut message) = message else { break };
+                    hooks.observe(&mut message);
+
+                    match message {
+                        ArmadaWorkMessage::Results(results) => {
+                            dashboard.record_results(&results);
+                            results.into_iter().for_each(|result| {
+                                let _ = result_sender.send(result);
+                            });
+                        }
+                        stats => dashboard.record_stats(&stats),
+                    }
+                }
+                Some(input) = input_receiver.recv() => {
+                    match input {
+                        inputs::Input::RaiseRate => self.set_rate_limit(dashboard.stepped_rate_limit(self, RATE_STEP)),
+                        inputs::Input::LowerRate => self.set_rate_limit(dashboard.stepped_rate_limit(self, 1.0 / RATE_STEP)),
+                        inputs::Input::TogglePause if self.is_paused() => self.resume(),
+                        inputs::Input::TogglePause => self.pause(),
+                        inputs::Input::Stop => self.stop(),
+                        // the frame is drawn at the terminal's new size straight away
+                        inputs::Input::Resize => {}
+                    }
+
+                    screen.draw(&dashboard, self);
+                }
+                _ = redraw.tick() => screen.draw(&dashboard, self),
+            }
+        }
+
+        drop(inputs);
+        drop(screen);
+
+        hooks.finish(self.is_stopped());
+    }
+}
+
+/// The terminal while the dashboard has it: in raw mode, on the alternate screen so what was on it before comes back
+/// once the scan is over, and with a panic putting it back the way it was before the panic is printed
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stderr>>,
+}
+
+impl Screen {
+    fn enter() -> std::io::Result<Self> {
+        // installed once, for however many times the dashboard is brought up
+        static PANIC_HOOK: std::sync::Once = std::sync::Once::new();
+        PANIC_HOOK.call_once(|| {
+            let previous_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                Screen::restore();
+                previous_hook(info);
+            }));
+        });
+
+        terminal::enable_raw_mode()?;
+        SCREEN_ACTIVE.store(true, Ordering::SeqCst);
+        execute!(std::io::stderr(), EnterAlternateScreen, cursor::Hide)?;
+
+        // the alternate screen starts out blank, so the first frame is drawn onto it as it is
+        let terminal = Terminal::new(CrosstermBackend::new(std::io::stderr()))?;
+
+        Ok(Self { terminal })
+    }
+
+    /// Puts the terminal back the way it was, if the dashboard still has it
+    fn restore() {
+        if SCREEN_ACTIVE.swap(false, Ordering::SeqCst) {
+            let _ = terminal::disable_raw_mode();
+            let _ = execute!(std::io::stderr(), cursor::Show, LeaveAlternateScreen);
+        }
+    }
+
+    fn draw(&mut self, dashboard: &Dashboard, armada: &Armada) {
+        // the size is looked up for every frame, so the dashboard follows the terminal being resized. A frame that
+        // can't be drawn is drawn over by the next one.
+        let _ = self.terminal.draw(|frame| {
+            let area = frame.area();
+            frame.render_widget(Paragraph::new(dashboard.lines(armada, area.height as usize)), area);
+        });
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        Screen::restore();
+    }
+}
+
+/// A full-screen view of the scan on stderr, redrawn as its stats come in
+struct Dashboard {
+    started: Instant,
+    description: String,
+    total_pairs: u128,
+    shard: Option<ScanShard>,
+    randomized: bool,
+    /// The rate limit the scan was started with
+    initial_rate_limit: Option<usize>,
+    blocks: Vec<TargetBlock>,
+    stats: Stats,
+    /// Probes sent by when, over the last `RATE_WINDOW`
+    sent_samples: VecDeque<(Instant, u128)>,
+    found: usize,
+    recent_results: VecDeque<String>,
+}
+
+/// One of the ranges the targets are made up of
+struct TargetBlock {
+    range: IpCidr,
+    /// The position of the block's first pair in the scan order, which only means something for scans in order
+    first_pair: u128,
+    pairs: u128,
+    found: usize,
+}
+
+#[derive(Default)]
+struct Stats {
+    processed: u128,
+    inflight: u128,
+    sent: u128,
+    responses: u128,
+    retries: u128,
+    cursor: u128,
+    packets_per_second: Option<usize>,
+}
+
+impl Dashboard {
+    fn new(targets: &HostIterator, ports: &PortIterator, options: &ScanOptions) -> Self {
+        let ports_size = ports.size() as u128;
+
+        let mut next_pair = 0;
+        let blocks = targets
+            .ranges()
+            .iter()
+            .map(|range| {
+                let pairs = range_size(range) * ports_size;
+                let block = TargetBlock { range: *range, first_pair: next_pair, pairs, found: 0 };
+                next_pair += pairs;
+
+                block
+            })
+            .collect();
+
+        Self {
+            started: Instant::now(),
+            description: format!("{:?} scan of {} hosts and {} ports", options.scan_type, targets.size(), ports_size),
+            total_pairs: options.probed_pairs(targets.size() * ports_size),
+            shard: options.shard,
+            randomized: options.randomize,
+            initial_rate_limit: options.packets_per_second,
+            blocks,
+            stats: Stats { cursor: options.skip_pairs, ..Default::default() },
+            sent_samples: VecDeque::new(),
+            found: 0,
+            recent_results: VecDeque::with_capacity(RECENT_RESULTS),
+        }
+    }
+
+    fn record_results(&mut self, results: &[PortResult]) {
+        for result in results {
+            self.found += 1;
+
+            if let Some(block) = self.blocks.iter_mut().find(|block| block.range.contains(result.addr.ip())) {
+                block.found += 1;
+            }
+
+            if self.recent_results.len() == RECENT_RESULTS {
+                self.recent_results.pop_front();
+            }
+            self.recent_results.push_back(format!("{} {}", result.addr, result.state));
+        }
+    }
+
+    fn record_stats(&mut self, message: &ArmadaWorkMessage) {
+        if let ArmadaWorkMessage::Stats {
+            total_processed_ports,
+            current_inflight_packets,
+            total_packets_sent,
+            total_responses_received,
+            total_retries,
+            scan_cursor,
+            packets_per_second,
+            ..
+        } = message
+        {
+            self.stats = Stats {
+                processed: *total_processed_ports,
+                inflight: *current_inflight_packets,
+                sent: *total_packets_sent,
+                responses: *total_responses_received,
+                retries: *total_retries,
+                cursor: *scan_cursor,
+                packets_per_second: *packets_per_second,
+            };
+
+            let now = Instant::now();
+            self.sent_samples.push_back((now, *total_packets_sent));
+            while self.sent_samples.front().is_some_and(|(at, _)| now.duration_since(*at) > RATE_WINDOW) {
+                self.sent_samples.pop_front();
+            }
+        }
+    }
+
+    /// Probes sent per second, going by the stats updates of the last couple of seconds
+    fn send_rate(&self) -> f64 {
+        match (self.sent_samples.front(), self.sent_samples.back()) {
+            (Some((first_at, first_sent)), Some((last_at, last_sent))) if last_at > first_at => {
+                (last_sent - first_sent) as f64 / last_at.duration_since(*first_at).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// The rate limit a step up or down from where it's at takes it to. Scans without one start from how fast
+    /// they're sending.
+    fn stepped_rate_limit(&self, armada: &Armada, step: f64) -> usize {
+        let current = armada
+            .rate_limit()
+            .or(self.stats.packets_per_second)
+            .or(self.initial_rate_limit)
+            .unwrap_or_else(|| self.send_rate() as usize)
+            .max(MIN_RATE_LIMIT);
+
+        // small rates would round back to where they were
+        let stepped = (current as f64 * step).round() as usize;
+        match step > 1.0 {
+            true => stepped.max(current + 1),
+            false => stepped.min(current - 1).max(MIN_RATE_LIMIT),
+        }
+    }
+
+    /// Every line of the dashboard, for a terminal this many rows high. Lines too wide for it are cut off at its edge.
+    fn lines(&self, armada: &Armada, rows: usize) -> Vec<Line<'static>> {
+        let elapsed = self.started.elapsed();
+        // the cursor counts every pair of the scan, while a shard only probes its share of them
+        let cursor = self.shard.map_or(self.stats.cursor, |shard| shard.pairs_before(self.stats.cursor));
+        let covered = (cursor.max(self.stats.processed)).min(self.total_pairs);
+        let progress = match self.total_pairs {
+            0 => 1.0,
+            total_pairs => covered as f64 / total_pairs as f64,
+        };
+
+        let status = match (armada.is_stopped(), armada.is_paused()) {
+            (true, _) => "stopping, waiting on the probes in flight".red(),
+            (false, true) => "paused".yellow(),
+            (false, false) => "running".green(),
+        };
+        let rate_limit = match armada.rate_limit().or(self.stats.packets_per_second) {
+            Some(rate_limit) => format!("{} pps", rate_limit),
+            None => "unlimited".to_string(),
+        };
+        // the rate pairs have been getting settled at so far, which retries already slow down
+        let eta = match (self.stats.processed, armada.is_stopped()) {
+            (0, _) | (_, true) => "-".to_string(),
+            (processed, false) => {
+                let remaining = self.total_pairs.saturating_sub(covered) as f64;
+                format_elapsed(Duration::from_secs_f64(remaining * elapsed.as_secs_f64() / processed as f64))
+            }
+        };
+
+        let mut progress_line = vec![Span::raw("Progress  ")];
+        progress_line.extend(bar(progress));
+        progress_line.push(Span::raw(format!(
+            " {:>6.2}%  {}/{} pairs  ETA {}",
+            progress * 100.0,
+            covered,
+            self.total_pairs,
+            eta
+        )));
+
+        let mut lines = vec![
+            Line::from(vec![
+                "armada".bold(),
+                Span::raw(format!(" {}  ", self.description)),
+                status,
+                Span::raw(format!("  elapsed {}", format_elapsed(elapsed))),
+            ]),
+            Line::default(),
+            Line::from(progress_line),
+            Line::from(vec![
+                Span::raw("Rate      "),
+                format!("{:.0}", self.send_rate()).cyan(),
+                Span::raw(format!(" pps sent, limit {}", rate_limit)),
+            ]),
+            Line::from(vec![
+                Span::raw(format!("Probes    {} sent, ", self.stats.sent)),
+                self.stats.inflight.to_string().blue(),
+                Span::raw(format!(
+                    " in flight, {} retries, {} responses, {} timed out",
+                    self.stats.retries,
+                    self.stats.responses,
+                    self.stats.processed.saturating_sub(self.stats.responses),
+                )),
+            ]),
+            Line::from(vec![Span::raw("Found     "), self.found.to_string().green()]),
+            Line::default(),
+        ];
+
+        // room for the header above and the recent results and the key bindings below
+        let block_rows = rows.saturating_sub(lines.len() + RECENT_RESULTS + 5).max(1);
+        lines.push(Line::raw(match self.randomized {
+            true => "Target blocks, probed in random order",
+            false => "Target blocks",
+        }));
+        lines.extend(self.block_lines(block_rows, progress));
+
+        lines.push(Line::default());
+        lines.push(Line::raw("Recently found"));
+        lines.extend(self.recent_results.iter().map(|result| Line::from(vec![Span::raw("  "), result.clone().green()])));
+        lines.push(Line::default());
+        lines.push(Line::from("↑/+ raise rate  ↓/- lower rate  p pause/resume  q stop".dim()));
+
+        lines
+    }
+
+    /// A line for each of the blocks being scanned, picking up from the first the scan hasn't finished with. Blocks of
+    /// scans in random order are shown as far along as the scan as a whole.
+    fn block_lines(&self, max_rows: usize, progress: f64) -> Vec<Line<'static>> {
+        let first_unfinished = match self.randomized {
+            true => 0,
+            false => self
+                .blocks
+                .iter()
+                .position(|block| block.first_pair + block.pairs > self.stats.cursor)
+                .unwrap_or(self.blocks.len()),
+        };
+        // the line for the finished blocks takes one of the rows
+        let max_rows = match first_unfinished {
+            0 => max_rows,
+            _ => max_rows.saturating_sub(1).max(1),
+        };
+        let (shown, hidden) = match self.blocks.len().saturating_sub(first_unfinished) > max_rows {
+            true => (max_rows.saturating_sub(1), true),
+            false => (max_rows, false),
+        };
+
+        let mut lines = Vec::new();
+        if first_unfinished > 0 {
+            let found = self.blocks[..first_unfinished].iter().map(|block| block.found).sum::<usize>();
+            let blocks = if first_unfinished == 1 { "block" } else { "blocks" };
+            lines.push(Line::raw(format!("  {} {} done, {} found", first_unfinished, blocks, found)));
+        }
+
+        for block in self.blocks.iter().skip(first_unfinished).take(shown) {
+            let block_progress = match self.randomized {
+                true => progress,
+                false => self.stats.cursor.saturating_sub(block.first_pair).min(block.pairs) as f64 / block.pairs.max(1) as f64,
+            };
+
+            let mut line = vec![Span::raw(format!("  {:<20} ", block.range.to_string()))];
+            line.extend(bar(block_progress));
+            line.push(Span::raw(format!(" {:>6.2}%  {} found", block_progress * 100.0, block.found)));
+            lines.push(Line::from(line));
+        }
+
+        if hidden {
+            lines.push(Line::raw(format!("  and {} more", self.blocks.len() - first_unfinished - shown)));
+        }
+
+        lines
+    }
+}
+
+fn range_size(range: &IpCidr) -> u128 {
+    u128::try_from(&range.size()).unwrap_or(u128::MAX)
+}
+
+fn bar(progress: f64) -> [Span<'static>; 2] {
+    let filled = ((progress.clamp(0.0, 1.0) * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+
+    ["█".repeat(filled).blue(), "░".repeat(BAR_WIDTH - filled).dim()]
+}
+
+fn format_elapsed(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    format!("{:02}:{:02}:{:02}", seconds / 3_600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Reads key presses and resizes off of the terminal while the dashboard is up
+mod inputs {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    /// How long a read waits for input before checking whether the reader is done
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub(super) enum Input {
+        RaiseRate,
+        LowerRate,
+        TogglePause,
+        Stop,
+        Resize,
+    }
+
+    /// Sends the inputs the terminal reads on, until it's dropped. The terminal is in raw mode by then, so Ctrl-C
+    /// comes in as a key rather than a signal.
+    pub(super) struct InputReader {
+        done: Arc<AtomicBool>,
+        reader: Option<std::thread::JoinHandle<()>>,
+    }
+
+    impl InputReader {
+        pub(super) fn start(input_sender: UnboundedSender<Input>) -> Self {
+            let done = Arc::new(AtomicBool::new(false));
+            let reader_done = done.clone();
+
+            let reader = std::thread::spawn(move || {
+                let mut interrupts = 0;
+
+                while !reader_done.load(Ordering::Relaxed) {
+                    // the terminal rather than stdin is read from when targets are piped in
+                    let event = match event::poll(POLL_INTERVAL) {
+                        Ok(true) => match event::read() {
+                            Ok(event) => event,
+                            Err(_) => break,
+                        },
+                        Ok(false) => continue,
+                        Err(_) => break,
+                    };
+
+                    let input = match event {
+                        Event::Resize(..) => Some(Input::Resize),
+                        Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+                            // a second Ctrl-C is for when waiting on the probes in flight takes too long
+                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                interrupts += 1;
+                                Some(Input::Stop)
+                            }
+                            KeyCode::Up | KeyCode::Char('+') | KeyCode::Char('=') => Some(Input::RaiseRate),
+                            KeyCode::Down | KeyCode::Char('-') | KeyCode::Char('_') => Some(Input::LowerRate),
+                            KeyCode::Char('p') | KeyCode::Char(' ') => Some(Input::TogglePause),
+                            KeyCode::Char('q') => Some(Input::Stop),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+
+                    if interrupts > 1 {
+                        super::Screen::restore();
+                        std::process::exit(crate::exit::INTERRUPTED);
+                    }
+
+                    if let Some(input) = input {
+                        if input_sender.send(input).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+
+            Self { done, reader: Some(reader) }
+        }
+    }
+
+    impl Drop for InputReader {
+        fn drop(&mut self) {
+            self.done.store(true, Ordering::Relaxed);
+            if let Some(reader) = self.reader.take() {
+                let _ = reader.join();
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::{Duration, Instant};
+
+use armada_lib::{Armada, ArmadaWorkMessage, HostIterator, PortIterator, PortResult, PortState, ScanOptions};
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::run_variants::{JsonProgressArmada, ScanHooks};
+use crate::status::{pair_progress, percent, send_rate};
+
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One line of `--progress json`, written to stderr every `EVENT_INTERVAL` and once more when the scan is over
+#[derive(Debug, Serialize)]
+struct ProgressEvent {
+    /// `progress` while the scan runs and `finished` for the last event
+    event: &'static str,
+    elapsed_seconds: f64,
+    percent: f64,
+    /// Target/port pairs settled, counting the ones a resumed scan skips
+    pairs_done: u128,
+    total_pairs: u128,
+    /// Hosts with every one of their ports settled. `None` for scans in random order, which get to every host at once.
+    hosts_done: Option<u128>,
+    total_hosts: u128,
+    open_ports: usize,
+    /// Probes sent per second since the last event
+    packets_per_second: f64,
+    packets_sent: u128,
+    inflight_packets: u128,
+    retries: u128,
+    paused: bool,
+    /// `None` until the scan has settled a pair to go by
+    eta_seconds: Option<f64>,
+}
+
+#[async_trait]
+impl JsonProgressArmada for Armada {
+    async fn run_with_json_progress(
+        &self,
+        targets: HostIterator,
+        ports: PortIterator,
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        mut hooks: ScanHooks,
+    ) {
+        let mut progress = JsonProgress::new(&targets, &ports, &options);
+        let mut reporting_handle = self.scan_with_handle(targets, ports, options).unwrap();
+
+        let mut events = tokio::time::interval(EVENT_INTERVAL);
+        // the first tick is straight away, which has nothing to say yet
+        events.tick().await;
+
+        loop {
+            tokio::select! {
+                message = reporting_handle.recv() => {
+                    let Some(mut message) = message else { break };
+                    hooks.observe(&mut message);
+
+                    match message {
+                        ArmadaWorkMessage::Results(results) => {
+                            progress.open_ports += results.iter().filter(|result| result.state == PortState::Open).count();
+                            results.into_iter().for_each(|result| {
+                                let _ = result_sender.send(result);
+                            });
+                        }
+                        ArmadaWorkMessage::Stats {
+                            total_processed_ports,
+                            current_inflight_packets,
+                            total_packets_sent,
+                            total_retries,
+                            scan_cursor,
+                            ..
+                        } => {
+                            progress.processed = total_processed_ports;
+                            progress.inflight = current_inflight_packets;
+                            progress.sent = total_packets_sent;
+                            progress.retries = total_retries;
+                            progress.cursor = scan_cursor;
+                        }
+                        ArmadaWorkMessage::Failed(_) => {}
+                    }
+                }
+                _ = events.tick() => progress.emit("progress", self.is_paused()),
+            }
+        }
+
+        progress.emit("finished", self.is_paused());
+
+        hooks.finish(self.is_stopped());
+    }
+}
+
+struct JsonProgress {
+    started: Instant,
+    total_pairs: u128,
+    total_hosts: u128,
+    ports: u128,
+    skipped_pairs: u128,
+    randomized: bool,
+    processed: u128,
+    inflight: u128,
+    sent: u128,
+    retries: u128,
+    cursor: u128,
+    open_ports: usize,
+    /// When the last event was written and how many probes had been sent by then
+    last_event: (Instant, u128),
+}
+
+impl JsonProgress {
+    fn new(targets: &HostIterator, ports: &PortIterator, options: &ScanOptions) -> Self {
+        let started = Instant::now();
+
+        Self {
+            started,
+            total_pairs: options.probed_pairs(targets.size() * ports.size() as u128),
+            total_hosts: targets.size(),
+            ports: ports.size() as u128,
+            skipped_pairs: options.probed_pairs(options.skip_pairs),
+            randomized: options.randomize,
+            processed: 0,
+            inflight: 0,
+            sent: 0,
+            retries: 0,
+            cursor: options.skip_pairs,
+            open_ports: 0,
+            last_event: (started, 0),
+        }
+    }
+
+    fn emit(&mut self, event: &'static str, paused: bool) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.started);
+        let (pairs_done, eta) = pair_progress(elapsed, self.skipped_pairs, self.processed, self.total_pairs);
+
+        let (last_event_at, last_event_sent) = self.last_event;
+        let packets_per_second = send_rate(self.sent - last_event_sent, now.duration_since(last_event_at));
+        self.last_event = (now, self.sent);
+
+        let progress_event = ProgressEvent {
+            event,
+            elapsed_seconds: round(elapsed.as_secs_f64()),
+            percent: round(percent(pairs_done, self.total_pairs)),
+            pairs_done,
+            total_pairs: self.total_pairs,
+            // pairs go out a host at a time in order, so the hosts before the cursor are done
+            hosts_done: (!self.randomized).then(|| (self.cursor / self.ports.max(1)).min(self.total_hosts)),
+            total_hosts: self.total_hosts,
+            open_ports: self.open_ports,
+            packets_per_second: round(packets_per_second),
+            packets_sent: self.sent,
+            inflight_packets: self.inflight,
+            retries: self.retries,
+            paused,
+            eta_seconds: eta.map(|eta| round(eta.as_secs_f64())),
+        };
+
+        eprintln!("{}", serde_json::to_string(&progress_event).expect("Failed to serialize progress event."));
+    }
+}
+
+/// Rounds to two decimals, which is as precise as progress gets
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
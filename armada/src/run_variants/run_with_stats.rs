@@ -1,8 +1,3 @@
-use std::net::{
-    Ipv4Addr,
-    Ipv6Addr,
-    SocketAddr,
-};
 use std::time::Duration;
 
 use armada_lib::{
@@ -10,15 +5,18 @@ use armada_lib::{
     ArmadaWorkMessage,
     HostIterator,
     PortIterator,
+    PortResult,
+    ScanOptions,
 };
 use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
 use indicatif::{
     MultiProgress,
     ProgressBar,
     ProgressStyle,
 };
 
-use crate::run_variants::ProgressArmada;
+use crate::run_variants::{ProgressArmada, ScanHooks};
 
 const UPDATE_INTERVAL: Duration = Duration::from_millis(50);
 
@@ -28,15 +26,15 @@ impl ProgressArmada for Armada {
         &self,
         targets: HostIterator,
         ports: PortIterator,
-        source_ipv4_addrs: Vec<Ipv4Addr>,
-        source_ipv6_addrs: Vec<Ipv6Addr>,
-        retries: u8,
-        timeout: Duration,
-        rate_limit: Option<usize>,
-        stream_output: bool
-    ) -> Vec<SocketAddr> {
-        let mut total_open_ports = Vec::new();
-        let total_ports: u128 = targets.size() * ports.size() as u128;
+        options: ScanOptions,
+        result_sender: UnboundedSender<PortResult>,
+        mut hooks: ScanHooks,
+    ) {
+        let retries = options.port_retries;
+        let rate_limit = options.packets_per_second;
+
+        let mut total_found_ports = 0;
+        let total_ports: u128 = options.probed_pairs(targets.size() * ports.size() as u128);
         let _total_packets = total_ports * (1 + retries) as u128;
 
         let multi_pb = MultiProgress::new();
@@ -59,37 +57,35 @@ impl ProgressArmada for Armada {
         total_scan_progress_bar.enable_steady_tick(UPDATE_INTERVAL);
 
 
-        let mut reporting_handle = self.scan_with_handle(
-            targets,
-            ports,
-            source_ipv4_addrs,
-            source_ipv6_addrs,
-            retries,
-            timeout,
-            rate_limit,
-        ).unwrap();
+        let mut reporting_handle = self.scan_with_handle(targets, ports, options).unwrap();
+
+        while let Some(mut message) = reporting_handle.recv().await {
+            hooks.observe(&mut message);
 
-        while let Some(message) = reporting_handle.recv().await {
             match message {
                 ArmadaWorkMessage::Results(results) => {
-                    if stream_output {
-                        results.iter().for_each(|remote| {
-                            println!("{}:{}", remote.ip(), remote.port());
-                        });
-                    }
+                    total_found_ports += results.len();
 
-                    total_open_ports.extend(results);
+                    results.into_iter().for_each(|result| {
+                        let _ = result_sender.send(result);
+                    });
 
-                    found_and_stats_progress_bar.set_message(format!("{}", total_open_ports.len()));
+                    found_and_stats_progress_bar.set_message(format!("{}", total_found_ports));
                 }
                 ArmadaWorkMessage::Stats {
                     total_processed_ports: _,
                     current_inflight_packets,
                     total_packets_sent,
+                    ..
                 } => {
-                    inflight_progress_bar.set_message(format!("{}", current_inflight_packets));
+                    if self.is_paused() {
+                        inflight_progress_bar.set_message(format!("{} (paused)", current_inflight_packets));
+                    } else {
+                        inflight_progress_bar.set_message(format!("{}", current_inflight_packets));
+                    }
                     total_scan_progress_bar.set_position((total_packets_sent / (1 + retries) as u128) as u64);
                 }
+                ArmadaWorkMessage::Failed(_) => {}
             }
         }
 
@@ -97,7 +93,7 @@ impl ProgressArmada for Armada {
         found_and_stats_progress_bar.finish_and_clear();
         inflight_progress_bar.finish_and_clear();
 
-        total_open_ports
+        hooks.finish(self.is_stopped());
     }
 }
 
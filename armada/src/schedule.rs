@@ -0,0 +1,136 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use armada_lib::Armada;
+use time::{OffsetDateTime, UtcOffset};
+
+/// How often the clock is checked against the window
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A stretch of every day that probes may be sent in, like `22:00-06:00` for overnight. Times are in the offset given
+/// after them, e.g. `22:00-06:00 +02:00` or `22:00-06:00 UTC`, or else in the system's local time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ScanWindow {
+    /// Minutes since midnight the window opens at
+    start: u32,
+    /// Minutes since midnight the window closes at, which is before the start for windows that span midnight
+    end: u32,
+    /// `None` follows the local time, daylight saving changes included
+    offset: Option<UtcOffset>,
+}
+
+impl ScanWindow {
+    pub(crate) fn contains(&self, now: OffsetDateTime) -> bool {
+        let now = now.to_offset(self.offset.unwrap_or_else(local_offset));
+        let minute = now.hour() as u32 * 60 + now.minute() as u32;
+
+        match self.start < self.end {
+            true => (self.start..self.end).contains(&minute),
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+}
+
+impl FromStr for ScanWindow {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (times, offset) = match value.trim().split_once(' ') {
+            Some((times, offset)) => (times, Some(parse_offset(offset.trim())?)),
+            None => (value.trim(), None),
+        };
+
+        let (start, end) = times.split_once('-').ok_or_else(|| "expected <start>-<end>".to_string())?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+
+        if start == end {
+            return Err("the window can't start and end at the same time".to_string());
+        }
+
+        Ok(Self { start, end, offset })
+    }
+}
+
+/// Parses `HH:MM` into minutes since midnight
+fn parse_time(value: &str) -> Result<u32, String> {
+    let (hours, minutes) = value.split_once(':').ok_or_else(|| format!("expected HH:MM, not '{}'", value))?;
+
+    match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        _ => Err(format!("'{}' isn't a time of day", value)),
+    }
+}
+
+/// Parses `UTC`, `Z` or an offset like `+02:00` or `-0530`
+fn parse_offset(value: &str) -> Result<UtcOffset, String> {
+    if value.eq_ignore_ascii_case("utc") || value.eq_ignore_ascii_case("z") {
+        return Ok(UtcOffset::UTC);
+    }
+
+    let (sign, offset) = match value.split_at_checked(1) {
+        Some(("+", offset)) => (1, offset),
+        Some(("-", offset)) => (-1, offset),
+        _ => return Err(format!("expected UTC or an offset like +02:00, not '{}'", value)),
+    };
+
+    let digits = offset.replace(':', "");
+    let (hours, minutes) = match (digits.len(), digits.get(..2), digits.get(2..)) {
+        (2, Some(hours), _) => (hours.parse::<i8>(), Ok(0)),
+        (4, Some(hours), Some(minutes)) => (hours.parse::<i8>(), minutes.parse::<i8>()),
+        _ => return Err(format!("'{}' isn't an offset", value)),
+    };
+
+    match (hours, minutes) {
+        (Ok(hours), Ok(minutes)) => UtcOffset::from_hms(sign * hours, sign * minutes, 0)
+            .map_err(|_| format!("'{}' isn't an offset", value)),
+        _ => Err(format!("'{}' isn't an offset", value)),
+    }
+}
+
+/// The system's offset from UTC right now
+#[cfg(unix)]
+fn local_offset() -> UtcOffset {
+    let mut local_time: libc::tm = unsafe { std::mem::zeroed() };
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+
+    match unsafe { libc::localtime_r(&now, &mut local_time) }.is_null() {
+        true => UtcOffset::UTC,
+        false => UtcOffset::from_whole_seconds(local_time.tm_gmtoff as i32).unwrap_or(UtcOffset::UTC),
+    }
+}
+
+#[cfg(not(unix))]
+fn local_offset() -> UtcOffset {
+    UtcOffset::UTC
+}
+
+/// Pauses the scan whenever the window closes and resumes it once it opens again. The scan is only paused or resumed
+/// as the window opens or closes, so pausing and resuming by signal in between still works.
+pub(crate) fn pause_outside_window(armada: Armada, window: ScanWindow) {
+    let mut was_open = None;
+
+    tokio::spawn(async move {
+        loop {
+            let open = window.contains(OffsetDateTime::now_utc());
+
+            if was_open != Some(open) {
+                match open {
+                    true => armada.resume(),
+                    false => armada.pause(),
+                }
+
+                // the first check only says something when the scan has to wait
+                if was_open.is_some() || !open {
+                    eprintln!(
+                        "{}",
+                        if open { "Scan window opened, resuming the scan." } else { "Outside of the scan window, pausing the scan." }
+                    );
+                }
+
+                was_open = Some(open);
+            }
+
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,116 @@
+use armada_lib::HostIterator;
+use cidr_utils::cidr::IpCidr;
+
+pub(crate) const SCOPE_MODES: [&str; 2] = ["refuse", "trim"];
+/// How many of the targets outside the scope are listed before the rest are just counted
+const MAX_LISTED: usize = 10;
+
+/// What's done with targets outside the scope
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ScopeMode {
+    /// The scan isn't started at all
+    Refuse,
+    /// They're left out of the scan
+    Trim,
+}
+
+impl ScopeMode {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "refuse" => Some(ScopeMode::Refuse),
+            "trim" => Some(ScopeMode::Trim),
+            _ => None,
+        }
+    }
+}
+
+/// The ranges a scan is allowed to probe, from `--scope-file`
+#[derive(Clone, Debug)]
+pub(crate) struct Scope {
+    pub(crate) file: String,
+    pub(crate) ranges: Vec<IpCidr>,
+    pub(crate) mode: ScopeMode,
+}
+
+impl Scope {
+    /// Holds the targets to the scope once every one of them is known, hostnames and ASNs included. Refuses to go on
+    /// if any of them are outside it, or drops those with `--scope-mode trim`.
+    pub(crate) fn enforce(&self, targets: HostIterator) -> HostIterator {
+        let outside = self.ranges.iter().fold(targets.clone(), |outside, range| outside.exclude_cidr(*range));
+        if outside.ranges().is_empty() {
+            return targets;
+        }
+
+        let listed: Vec<String> = outside.ranges().iter().take(MAX_LISTED).map(IpCidr::to_string).collect();
+        let unlisted = match outside.ranges().len().saturating_sub(MAX_LISTED) {
+            0 => String::new(),
+            count => format!(" and {} more", count),
+        };
+
+        match self.mode {
+            ScopeMode::Refuse => panic!(
+                "{} of the targets are outside the scope in {}: {}{}. Please take them out of the scan, or pass --scope-mode trim to leave them out.",
+                outside.size(),
+                self.file,
+                listed.join(", "),
+                unlisted,
+            ),
+            ScopeMode::Trim => {
+                let targets = targets.retain_within(&self.ranges);
+                if targets.size() == 0 {
+                    panic!("None of the targets are within the scope in {}.", self.file);
+                }
+
+                eprintln!(
+                    "warning: leaving out {} targets outside the scope in {}: {}{}",
+                    outside.size(),
+                    self.file,
+                    listed.join(", "),
+                    unlisted,
+                );
+                targets
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(ranges: &[&str], mode: ScopeMode) -> Scope {
+        let ranges = ranges.iter().map(|range| IpCidr::from_str(range).unwrap()).collect();
+
+        Scope { file: "scope.txt".to_string(), ranges, mode }
+    }
+
+    fn targets(ranges: &[&str]) -> HostIterator {
+        ranges.iter().fold(HostIterator::new(), |targets, range| targets.add_cidr(IpCidr::from_str(range).unwrap()))
+    }
+
+    #[test]
+    fn targets_within_the_scope_are_left_alone() {
+        let targets = scope(&["10.0.0.0/8", "2001:db8::/32"], ScopeMode::Refuse).enforce(targets(&["10.1.0.0/16", "2001:db8::1"]));
+
+        assert_eq!(targets.ranges(), [IpCidr::from_str("10.1.0.0/16").unwrap(), IpCidr::from_str("2001:db8::1").unwrap()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "2 of the targets are outside the scope in scope.txt: 192.0.2.0/31.")]
+    fn scans_with_targets_outside_the_scope_are_refused() {
+        scope(&["10.0.0.0/8"], ScopeMode::Refuse).enforce(targets(&["10.0.0.1", "192.0.2.0/31"]));
+    }
+
+    #[test]
+    fn trimming_leaves_out_the_targets_outside_the_scope() {
+        let targets = scope(&["10.0.0.0/25"], ScopeMode::Trim).enforce(targets(&["10.0.0.0/24", "192.0.2.1"]));
+
+        assert_eq!(targets.ranges(), [IpCidr::from_str("10.0.0.0/25").unwrap()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "None of the targets are within the scope in scope.txt.")]
+    fn trimming_every_target_is_refused() {
+        scope(&["10.0.0.0/8"], ScopeMode::Trim).enforce(targets(&["192.0.2.0/24"]));
+    }
+}
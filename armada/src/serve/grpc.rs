@@ -0,0 +1,225 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::output::ScanRecord;
+use crate::serve::jobs::{Job, JobManager, JobRequest, JobState, SubmitError};
+use crate::serve::STATUS_INTERVAL;
+
+mod proto {
+    tonic::include_proto!("armada.v1");
+}
+
+use proto::armada_server::{Armada, ArmadaServer};
+use proto::scan_control::Control;
+use proto::scan_event::Event;
+use proto::scan_status::State;
+use proto::{ScanControl, ScanEvent, ScanRequest, ScanResult, ScanStatus, ScanType};
+
+/// How many messages are held back for a client that reads them slower than they're found
+const STREAM_BUFFER: usize = 256;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Serves the gRPC API on the listener until armada is stopped, running its scans on the same jobs as the REST API
+pub(crate) async fn serve(listener: TcpListener, jobs: Arc<JobManager>) {
+    tonic::transport::Server::builder()
+        .add_service(ArmadaServer::new(ArmadaService { jobs }))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+        .unwrap_or_else(|error| panic!("Unable to serve the gRPC API: {}", error));
+}
+
+struct ArmadaService {
+    jobs: Arc<JobManager>,
+}
+
+#[tonic::async_trait]
+impl Armada for ArmadaService {
+    type ScanStream = ResponseStream<ScanResult>;
+    type ControlledScanStream = ResponseStream<ScanEvent>;
+
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Response<Self::ScanStream>, Status> {
+        let job = self.submit(request.into_inner())?;
+        let (sender, receiver) = channel(STREAM_BUFFER);
+
+        tokio::spawn(async move {
+            follow(&job, &sender, |record| Ok(ScanResult::from(record)), |_| None).await;
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    async fn controlled_scan(
+        &self,
+        request: Request<Streaming<ScanControl>>,
+    ) -> Result<Response<Self::ControlledScanStream>, Status> {
+        let mut controls = request.into_inner();
+
+        let request = match controls.message().await? {
+            Some(ScanControl { control: Some(Control::Request(request)) }) => request,
+            _ => return Err(Status::invalid_argument("the first message has to be the scan request")),
+        };
+        let job = self.submit(request)?;
+        let (sender, receiver) = channel(STREAM_BUFFER);
+
+        // controls that come after the request steer the scan until the client stops sending them, without keeping the
+        // call open once the scan is over
+        let controlled_job = job.clone();
+        let control_sender = sender.downgrade();
+        tokio::spawn(async move {
+            while let Some(control) = controls.next().await {
+                let error = match control.map(|control| control.control) {
+                    Ok(Some(Control::SetRate(0))) => Status::invalid_argument("the rate has to be at least 1"),
+                    Ok(Some(Control::SetRate(rate))) => {
+                        controlled_job.set_rate_limit(usize::try_from(rate).unwrap_or(usize::MAX));
+                        continue;
+                    }
+                    Ok(Some(Control::Cancel(true))) => {
+                        controlled_job.cancel();
+                        continue;
+                    }
+                    Ok(Some(Control::Cancel(false))) | Ok(None) => continue,
+                    Ok(Some(Control::Request(_))) => Status::invalid_argument("a scan is already running on this call"),
+                    Err(status) => status,
+                };
+
+                if let Some(control_sender) = control_sender.upgrade() {
+                    let _ = control_sender.send(Err(error)).await;
+                }
+                break;
+            }
+        });
+
+        tokio::spawn(async move {
+            follow(
+                &job,
+                &sender,
+                |record| Ok(ScanEvent { event: Some(Event::Result(ScanResult::from(record))) }),
+                |job| Some(Ok(ScanEvent { event: Some(Event::Status(status_of(job))) })),
+            )
+            .await;
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+impl ArmadaService {
+    fn submit(&self, request: ScanRequest) -> Result<Arc<Job>, Status> {
+        let scan_type = match request.scan_type() {
+            ScanType::Syn => "syn",
+            ScanType::Fin => "fin",
+            ScanType::Null => "null",
+            ScanType::Xmas => "xmas",
+            ScanType::Ack => "ack",
+            ScanType::Window => "window",
+            ScanType::Sctp => "sctp",
+        };
+        let rate_limit = request
+            .rate_limit
+            .map(|rate_limit| usize::try_from(rate_limit).map_err(|_| Status::invalid_argument("the rate limit is too high")))
+            .transpose()?;
+        let retries = request
+            .retries
+            .map(|retries| u8::try_from(retries).map_err(|_| Status::invalid_argument("a port is retried at most 255 times")))
+            .transpose()?;
+
+        let request = JobRequest {
+            targets: request.targets,
+            ports: request.ports,
+            rate_limit,
+            retries,
+            timeout_ms: request.timeout_ms,
+            scan_type: Some(scan_type.to_string()),
+            report_all_states: request.report_all_states,
+        };
+
+        self.jobs.submit(request).map_err(|error| match error {
+            SubmitError::Invalid(error) => Status::invalid_argument(error),
+            SubmitError::Engine(error) => Status::internal(format!("{:#}", error)),
+        })
+    }
+}
+
+/// Sends the job's results as they're found, starting with the ones found already, along with its status every second
+/// if there's a message for it. The last status follows the last result once the job is over. A client that hangs up
+/// cancels the job, since nothing is left to hear its results.
+async fn follow<T>(
+    job: &Job,
+    sender: &Sender<Result<T, Status>>,
+    result: impl Fn(&ScanRecord) -> Result<T, Status>,
+    status: impl Fn(&Job) -> Option<Result<T, Status>>,
+) {
+    let mut changes = job.subscribe();
+    let mut sent = 0;
+    let mut last_status: Option<Instant> = None;
+
+    loop {
+        let (records, finished) = job.records_after(sent);
+        sent += records.len();
+
+        let status_due = finished || last_status.is_none_or(|last_status| last_status.elapsed() >= STATUS_INTERVAL);
+        let status = status_due.then(|| status(job)).flatten();
+        if status_due {
+            last_status = Some(Instant::now());
+        }
+
+        for message in records.iter().map(&result).chain(status) {
+            if sender.send(message).await.is_err() {
+                job.cancel();
+                return;
+            }
+        }
+
+        if finished {
+            return;
+        }
+
+        // woken up early by new results, and otherwise by the next status being due, or straight away if the client
+        // hangs up meanwhile
+        tokio::select! {
+            _ = tokio::time::timeout(STATUS_INTERVAL, changes.changed()) => {}
+            _ = sender.closed() => {
+                job.cancel();
+                return;
+            }
+        }
+    }
+}
+
+fn status_of(job: &Job) -> ScanStatus {
+    let status = job.status();
+    let state = match status.state {
+        JobState::Queued => State::Queued,
+        JobState::Running => State::Running,
+        JobState::Done => State::Done,
+        JobState::Cancelled => State::Cancelled,
+        JobState::Failed => State::Failed,
+    };
+
+    ScanStatus {
+        state: state.into(),
+        pairs_done: status.pairs_done.try_into().unwrap_or(u64::MAX),
+        total_pairs: status.total_pairs.try_into().unwrap_or(u64::MAX),
+        packets_sent: status.packets_sent.try_into().unwrap_or(u64::MAX),
+        results: status.results as u64,
+    }
+}
+
+impl From<&ScanRecord> for ScanResult {
+    fn from(record: &ScanRecord) -> Self {
+        Self {
+            ip: record.ip.to_string(),
+            port: u32::from(record.port),
+            state: record.state.to_string(),
+            rtt: record.rtt_millis(),
+        }
+    }
+}
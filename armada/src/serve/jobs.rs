@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use armada_lib::{
+    Armada, ArmadaWorkMessage, CancellationToken, HostIterator, PortIterator, PortState, ScanOptions, ScanType,
+};
+use cidr_utils::cidr::IpCidr;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+
+use crate::args::{parse_octet_ranges, parse_port_range, DEFAULT_RATE_LIMIT};
+use crate::output::ScanRecord;
+use crate::post_scan::PostScan;
+
+/// A scan to run, as it's POSTed to `/jobs`
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct JobRequest {
+    /// IPs, CIDR ranges and nmap style ranges
+    pub(crate) targets: Vec<String>,
+    /// Ports and port ranges, separated by commas, e.g. `22,80,8000-8100`
+    pub(crate) ports: String,
+    /// Packets per second, 0 for no limit. Defaults to the scan's default of 10kpps.
+    pub(crate) rate_limit: Option<usize>,
+    pub(crate) retries: Option<u8>,
+    pub(crate) timeout_ms: Option<u64>,
+    /// `syn`, `fin`, `null`, `xmas`, `ack`, `window` or `sctp`
+    pub(crate) scan_type: Option<String>,
+    #[serde(default)]
+    pub(crate) report_all_states: bool,
+}
+
+/// Where a job is at
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JobState {
+    /// Waiting for the scans submitted before it to finish, since the engine runs one at a time
+    Queued,
+    Running,
+    Done,
+    Cancelled,
+    /// The scan stopped part way because a worker failed
+    Failed,
+}
+
+impl JobState {
+    pub(crate) fn is_finished(&self) -> bool {
+        matches!(self, JobState::Done | JobState::Cancelled | JobState::Failed)
+    }
+}
+
+/// A job's status, as it's reported by the API
+#[derive(Debug, Serialize)]
+pub(crate) struct JobStatus {
+    id: u64,
+    pub(crate) state: JobState,
+    targets: Vec<String>,
+    ports: String,
+    /// RFC 3339 timestamp of when the job was submitted
+    submitted_at: String,
+    pub(crate) percent: f64,
+    pub(crate) pairs_done: u128,
+    pub(crate) total_pairs: u128,
+    pub(crate) results: usize,
+    open_ports: usize,
+    pub(crate) packets_sent: u128,
+    retries: u128,
+    /// How long the scan has been running for, or ran for if it's over
+    elapsed_seconds: f64,
+}
+
+/// A scan submitted to the daemon, along with everything it's found so far
+pub(crate) struct Job {
+    id: u64,
+    targets: Vec<String>,
+    ports: String,
+    submitted_at: String,
+    cancellation: CancellationToken,
+    /// The engine the job runs on, whose rate limit it holds while it's running if it was given one of its own
+    armada: Arc<Armada>,
+    progress: Mutex<JobProgress>,
+    /// Bumped whenever the job finds results or its state changes, for the results to be streamed as they come in
+    changes: watch::Sender<()>,
+}
+
+struct JobProgress {
+    state: JobState,
+    total_pairs: u128,
+    pairs_done: u128,
+    packets_sent: u128,
+    retries: u128,
+    records: Vec<ScanRecord>,
+    /// Whether a worker failed part way, which the job is only marked with once the rest of its scan is over
+    failed: bool,
+    /// The rate the job was set to after it was submitted, which takes over from the one it was submitted with
+    rate_limit: Option<usize>,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+}
+
+impl Job {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn status(&self) -> JobStatus {
+        let progress = self.progress.lock().expect("Job lock poisoned.");
+
+        let elapsed = match (progress.started, progress.finished) {
+            (Some(started), Some(finished)) => finished.duration_since(started),
+            (Some(started), None) => started.elapsed(),
+            (None, _) => Duration::ZERO,
+        };
+        let percent = match progress.total_pairs {
+            0 => 100.0,
+            total_pairs => progress.pairs_done as f64 * 100.0 / total_pairs as f64,
+        };
+
+        JobStatus {
+            id: self.id,
+            state: progress.state,
+            targets: self.targets.clone(),
+            ports: self.ports.clone(),
+            submitted_at: self.submitted_at.clone(),
+            percent: (percent * 100.0).round() / 100.0,
+            pairs_done: progress.pairs_done,
+            total_pairs: progress.total_pairs,
+            results: progress.records.len(),
+            open_ports: progress.records.iter().filter(|record| record.state == PortState::Open).count(),
+            packets_sent: progress.packets_sent,
+            retries: progress.retries,
+            elapsed_seconds: (elapsed.as_secs_f64() * 100.0).round() / 100.0,
+        }
+    }
+
+    /// The records found after the first `skip`, and whether the job is over, so no more will come
+    pub(crate) fn records_after(&self, skip: usize) -> (Vec<ScanRecord>, bool) {
+        let progress = self.progress.lock().expect("Job lock poisoned.");
+
+        (progress.records.iter().skip(skip).cloned().collect(), progress.state.is_finished())
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    /// Holds the job's scan to this many packets per second from now on. The engine runs one scan at a time, so the
+    /// rate of a job that's still queued only takes over once it starts.
+    pub(crate) fn set_rate_limit(&self, packets_per_second: usize) {
+        self.update(|progress| {
+            progress.rate_limit = Some(packets_per_second);
+            if progress.state == JobState::Running {
+                self.armada.set_rate_limit(packets_per_second);
+            }
+        });
+    }
+
+    fn update(&self, update: impl FnOnce(&mut JobProgress)) {
+        update(&mut self.progress.lock().expect("Job lock poisoned."));
+        self.changes.send_replace(());
+    }
+}
+
+pub(crate) enum SubmitError {
+    /// What's wrong with the request
+    Invalid(String),
+    /// The engine couldn't take the scan on
+    Engine(anyhow::Error),
+}
+
+/// Keeps every job submitted to the daemon and runs them on the one armada, in the order they were submitted
+pub(crate) struct JobManager {
+    armada: Arc<Armada>,
+    /// The sources every job's probes go out from
+    options: ScanOptions,
+    post_scan: Arc<PostScan>,
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    next_id: AtomicU64,
+}
+
+impl JobManager {
+    pub(crate) fn new(armada: Armada, options: ScanOptions) -> Self {
+        Self {
+            armada: Arc::new(armada),
+            options,
+            post_scan: Arc::new(PostScan::default()),
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Checks the request and starts its scan
+    pub(crate) fn submit(&self, request: JobRequest) -> Result<Arc<Job>, SubmitError> {
+        let (targets, ports, mut options) = self.scan_of(&request).map_err(SubmitError::Invalid)?;
+        let cancellation = CancellationToken::new();
+        options.cancellation = Some(cancellation.clone());
+
+        let job = Arc::new(Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            targets: request.targets,
+            ports: request.ports,
+            submitted_at: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            cancellation,
+            armada: self.armada.clone(),
+            progress: Mutex::new(JobProgress {
+                state: JobState::Queued,
+                total_pairs: targets.size() * ports.size() as u128,
+                pairs_done: 0,
+                packets_sent: 0,
+                retries: 0,
+                records: Vec::new(),
+                failed: false,
+                rate_limit: None,
+                started: None,
+                finished: None,
+            }),
+            changes: watch::channel(()).0,
+        });
+
+        let reporting_handle = self.armada.scan_with_handle(targets, ports, options).map_err(SubmitError::Engine)?;
+        self.jobs.lock().expect("Jobs lock poisoned.").insert(job.id, job.clone());
+        tokio::spawn(run(job.clone(), reporting_handle, self.post_scan.clone()));
+
+        Ok(job)
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.lock().expect("Jobs lock poisoned.").get(&id).cloned()
+    }
+
+    pub(crate) fn list(&self) -> Vec<JobStatus> {
+        self.jobs.lock().expect("Jobs lock poisoned.").values().map(|job| job.status()).collect()
+    }
+
+    /// Forgets a finished job, returning it if there was one
+    pub(crate) fn remove(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.lock().expect("Jobs lock poisoned.").remove(&id)
+    }
+
+    fn scan_of(&self, request: &JobRequest) -> Result<(HostIterator, PortIterator, ScanOptions), String> {
+        let targets = request.targets.iter().try_fold(HostIterator::new(), |targets, target| {
+            if let Ok(ip) = IpAddr::from_str(target) {
+                Ok(targets.add_ip(ip))
+            } else if let Ok(cidr) = IpCidr::from_str(target) {
+                Ok(targets.add_cidr(cidr))
+            } else if let Some(cidrs) = parse_octet_ranges(target) {
+                Ok(cidrs.into_iter().fold(targets, HostIterator::add_cidr))
+            } else {
+                Err(format!("'{}' isn't an IP, CIDR or range", target))
+            }
+        })?;
+        if let Some(range) = crate::ipv6::oversized_range(&targets) {
+            return Err(format!("{} holds 2^{} addresses, too many to scan every one of them", range, 128 - range.get_bits()));
+        }
+        if targets.size() == 0 {
+            return Err("no targets were given".to_string());
+        }
+
+        let ports = request.ports.split(',').map(str::trim).filter(|port| !port.is_empty()).try_fold(
+            PortIterator::new(),
+            |ports, port| match parse_port_range(port)? {
+                (start, end) if start == end => Ok::<_, String>(ports.add_port(start)),
+                (start, end) => Ok(ports.add_range(start, end)),
+            },
+        )?;
+        if ports.size() == 0 {
+            return Err("no ports were given".to_string());
+        }
+
+        let scan_type = match request.scan_type.as_deref() {
+            None | Some("syn") => ScanType::Syn,
+            Some("fin") => ScanType::Fin,
+            Some("null") => ScanType::Null,
+            Some("xmas") => ScanType::Xmas,
+            Some("ack") => ScanType::Ack,
+            Some("window") => ScanType::Window,
+            Some("sctp") => ScanType::SctpInit,
+            Some(scan_type) => return Err(format!("unknown scan type '{}'", scan_type)),
+        };
+
+        let mut options = self.options.clone();
+        options.packets_per_second = match request.rate_limit {
+            Some(0) => None,
+            Some(rate_limit) => Some(rate_limit.max(10)),
+            None => Some(DEFAULT_RATE_LIMIT),
+        };
+        options.port_retries = request.retries.unwrap_or(options.port_retries);
+        options.port_timeout = request.timeout_ms.map_or(options.port_timeout, Duration::from_millis);
+        options.scan_type = scan_type;
+        options.report_all_states = request.report_all_states;
+
+        Ok((targets, ports, options))
+    }
+}
+
+/// Follows the job's scan until it's over, keeping its progress and results up to date
+async fn run(
+    job: Arc<Job>,
+    mut reporting_handle: tokio::sync::mpsc::UnboundedReceiver<ArmadaWorkMessage>,
+    post_scan: Arc<PostScan>,
+) {
+    while let Some(message) = reporting_handle.recv().await {
+        match message {
+            ArmadaWorkMessage::Results(results) => {
+                let mut records = Vec::with_capacity(results.len());
+                for result in results {
+                    records.push(post_scan.build_record(result).await);
+                }
+
+                job.update(|progress| progress.records.extend(records));
+            }
+            ArmadaWorkMessage::Stats { total_processed_ports, total_packets_sent, total_retries, .. } => {
+                job.update(|progress| {
+                    // the engine only gets to the scan once the ones before it are done
+                    if progress.state == JobState::Queued {
+                        progress.state = JobState::Running;
+                        progress.started = Some(Instant::now());
+                        if let Some(rate_limit) = progress.rate_limit {
+                            job.armada.set_rate_limit(rate_limit);
+                        }
+                    }
+
+                    progress.pairs_done = total_processed_ports.min(progress.total_pairs);
+                    progress.packets_sent = total_packets_sent;
+                    progress.retries = total_retries;
+                });
+            }
+            ArmadaWorkMessage::Failed(_) => job.update(|progress| progress.failed = true),
+        }
+    }
+
+    let cancelled = job.cancellation.is_cancelled();
+    job.update(|progress| {
+        let now = Instant::now();
+        progress.started.get_or_insert(now);
+        progress.finished = Some(now);
+        progress.state = match (progress.failed, cancelled && progress.pairs_done < progress.total_pairs) {
+            (true, _) => JobState::Failed,
+            (false, true) => JobState::Cancelled,
+            (false, false) => JobState::Done,
+        };
+
+        // the jobs after it go at the rate they were submitted with, unless the next one already set its own
+        if progress.rate_limit.is_some() && job.armada.rate_limit() == progress.rate_limit {
+            job.armada.clear_rate_limit();
+        }
+    });
+}
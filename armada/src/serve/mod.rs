@@ -0,0 +1,290 @@
+mod grpc;
+mod jobs;
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use armada_lib::utils::get_source_routes;
+use armada_lib::{Armada, ArmadaOptions, HostIterator, ScanOptions};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+pub(crate) use crate::serve::jobs::{Job, JobManager, JobRequest, SubmitError};
+use crate::exit;
+#[cfg(unix)]
+use crate::privileges::RunAs;
+
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+/// How often a job's status is sent down an event stream while it's running
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+/// How many events can wait on a client that's slow to read them
+const EVENTS_BUFFERED: usize = 64;
+
+/// What `armada serve` listens on and scans from
+pub(crate) struct ServeConfig {
+    pub(crate) listen: SocketAddr,
+    /// Where the gRPC API is served from as well, if anywhere
+    pub(crate) grpc_listen: Option<SocketAddr>,
+    pub(crate) listening_port: u16,
+    pub(crate) source_ips: Option<Vec<IpAddr>>,
+    /// Who to switch to once the engine's raw sockets are open and the APIs are listening
+    #[cfg(unix)]
+    pub(crate) run_as: Option<RunAs>,
+}
+
+/// Runs scans submitted over HTTP, and over gRPC if it's served too, until armada is stopped
+pub(crate) async fn run(config: ServeConfig) {
+    let ServeConfig { listen, grpc_listen, listening_port, source_ips, #[cfg(unix)] run_as } = config;
+
+    crate::logging::init(0, None);
+    let jobs = Arc::new(job_manager(listening_port, source_ips).await);
+
+    let listener = TcpListener::bind(listen)
+        .await
+        .unwrap_or_else(|error| panic!("Unable to listen on '{}': {}", listen, error));
+    eprintln!("Listening on http://{}", listen);
+
+    let grpc_listener = match grpc_listen {
+        Some(grpc_listen) => {
+            let grpc_listener = TcpListener::bind(grpc_listen)
+                .await
+                .unwrap_or_else(|error| panic!("Unable to listen for gRPC on '{}': {}", grpc_listen, error));
+            eprintln!("Serving gRPC on {}", grpc_listen);
+
+            Some(grpc_listener)
+        }
+        None => None,
+    };
+
+    // every job is run on the engine set up above, so nothing after this takes root
+    #[cfg(unix)]
+    if let Some(run_as) = &run_as {
+        crate::privileges::drop_privileges(run_as);
+    }
+
+    if let Some(grpc_listener) = grpc_listener {
+        tokio::spawn(grpc::serve(grpc_listener, jobs.clone()));
+    }
+
+    axum::serve(listener, router().layer(Extension(jobs)))
+        .await
+        .unwrap_or_else(|error| panic!("Unable to serve on '{}': {}", listen, error));
+}
+
+/// Sets up the engine jobs are run on, with responses to their probes received on the listening port
+pub(crate) async fn job_manager(listening_port: u16, source_ips: Option<Vec<IpAddr>>) -> JobManager {
+    // without --source-ip, probes go out from the source the routing table has for their target
+    let source_routes = match source_ips {
+        Some(_) => None,
+        None => Some(get_source_routes(None).await),
+    };
+    let (source_ipv4_addrs, source_ipv6_addrs) =
+        crate::split_and_enforce_source_ips(source_ips, None, None, &HostIterator::new()).await;
+    let options = ScanOptions { source_ipv4_addrs, source_ipv6_addrs, source_routes, ..Default::default() };
+
+    // Windows raw sockets don't send TCP, so jobs there go through Npcap on the interface holding the default route
+    #[cfg(windows)]
+    let engine = armada_lib::Engine::Npcap {
+        interface: armada_lib::utils::get_default_interface()
+            .unwrap_or_else(|| panic!("There's no default route to pick an interface for the scans from.")),
+        vlan: None,
+        router_mac: None,
+        target_mac: None,
+    };
+    #[cfg(not(windows))]
+    let engine = Default::default();
+
+    let armada = match Armada::with_options(ArmadaOptions {
+        listening_ports: vec![listening_port],
+        engine,
+        cores: Vec::new(),
+        interface: None,
+    }) {
+        Ok(armada) => armada,
+        Err(error) if exit::is_permission_error(&error) => {
+            eprintln!("Unable to open raw sockets: {:#}. Please run armada as root or with CAP_NET_RAW.", error);
+            std::process::exit(exit::PERMISSION_DENIED);
+        }
+        Err(error) => panic!("Unable to set up the scan engine: {:?}", error),
+    };
+
+    JobManager::new(armada, options)
+}
+
+/// The REST API. Jobs are looked up through the `JobManager` it's layered with, and paths or methods it doesn't have
+/// are answered with a JSON error like the rest of its responses.
+fn router() -> Router {
+    Router::new()
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/{id}", get(job_status).delete(remove_job))
+        .route("/jobs/{id}/results", get(job_results))
+        .route("/jobs/{id}/events", get(job_events))
+        .fallback(|| async { error(StatusCode::NOT_FOUND, "not found") })
+        .method_not_allowed_fallback(|| async { error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed") })
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+}
+
+// the body is read whatever its Content-Type, as curl -d sends JSON as a form
+async fn submit_job(Extension(jobs): Extension<Arc<JobManager>>, body: Bytes) -> Response {
+    match serde_json::from_slice::<JobRequest>(&body) {
+        Err(parse_error) => error(StatusCode::BAD_REQUEST, &parse_error.to_string()),
+        Ok(request) => match jobs.submit(request) {
+            Ok(job) => (StatusCode::CREATED, Json(job.status())).into_response(),
+            Err(SubmitError::Invalid(invalid)) => error(StatusCode::BAD_REQUEST, &invalid),
+            Err(SubmitError::Engine(engine_error)) => {
+                error(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", engine_error))
+            }
+        },
+    }
+}
+
+async fn list_jobs(Extension(jobs): Extension<Arc<JobManager>>) -> Response { Json(jobs.list()).into_response() }
+
+async fn job_status(Extension(jobs): Extension<Arc<JobManager>>, Path(id): Path<String>) -> Response {
+    match find_job(&jobs, &id) {
+        Some(job) => Json(job.status()).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn job_results(Extension(jobs): Extension<Arc<JobManager>>, Path(id): Path<String>) -> Response {
+    match find_job(&jobs, &id) {
+        Some(job) => Json(job.records_after(0).0).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn job_events(Extension(jobs): Extension<Arc<JobManager>>, Path(id): Path<String>) -> Response {
+    match find_job(&jobs, &id) {
+        Some(job) => Sse::new(events(job)).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn remove_job(Extension(jobs): Extension<Arc<JobManager>>, Path(id): Path<String>) -> Response {
+    match find_job(&jobs, &id) {
+        // a finished job is forgotten, while one that's still going is cancelled and kept around for its results
+        Some(job) if job.status().state.is_finished() => {
+            jobs.remove(job.id());
+            Json(job.status()).into_response()
+        }
+        Some(job) => {
+            job.cancel();
+            (StatusCode::ACCEPTED, Json(job.status())).into_response()
+        }
+        None => not_found(),
+    }
+}
+
+/// Ids that aren't numbers can't be any job's either, so they aren't found rather than bad requests
+fn find_job(jobs: &JobManager, id: &str) -> Option<Arc<Job>> { id.parse().ok().and_then(|id| jobs.get(id)) }
+
+/// The job's results as server-sent events as they're found, starting with the ones found already, along with its
+/// status every second. The stream ends with a `done` event once the job is over, or once the client hangs up.
+fn events(job: Arc<Job>) -> ReceiverStream<Result<Event, Infallible>> {
+    let (sender, receiver) = mpsc::channel(EVENTS_BUFFERED);
+
+    tokio::spawn(async move {
+        let mut changes = job.subscribe();
+        let mut sent = 0;
+        let mut last_status: Option<Instant> = None;
+
+        loop {
+            let (records, finished) = job.records_after(sent);
+            sent += records.len();
+
+            let mut events: Vec<Event> =
+                records.iter().map(|record| Event::default().event("result").data(to_json(record))).collect();
+            if finished {
+                events.push(Event::default().event("done").data(to_json(&job.status())));
+            } else if last_status.is_none_or(|last_status| last_status.elapsed() >= STATUS_INTERVAL) {
+                events.push(Event::default().event("status").data(to_json(&job.status())));
+                last_status = Some(Instant::now());
+            }
+
+            for event in events {
+                if sender.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+            if finished {
+                return;
+            }
+
+            // woken up early by new results, and otherwise by the next status being due
+            let _ = tokio::time::timeout(STATUS_INTERVAL, changes.changed()).await;
+        }
+    });
+
+    ReceiverStream::new(receiver)
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).expect("Unable to serialize the response.")
+}
+
+fn error(status: StatusCode, error: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
+
+fn not_found() -> Response { error(StatusCode::NOT_FOUND, "no job with that id") }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves the API on a port of its own. It has no jobs to look up, which the paths and methods it doesn't have
+    /// are answered without.
+    async fn api() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router()).await });
+
+        format!("http://{}", address)
+    }
+
+    async fn request(method: reqwest::Method, url: String) -> (StatusCode, serde_json::Value) {
+        let response = reqwest::Client::new().request(method, url).send().await.unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+
+        (status, response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_are_not_found() {
+        let api = api().await;
+
+        for path in ["/", "/scans", "/jobs/1/logs", "/jobs/1/results/2"] {
+            let (status, body) = request(reqwest::Method::GET, format!("{}{}", api, path)).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+            assert_eq!(body["error"], "not found");
+        }
+    }
+
+    #[tokio::test]
+    async fn known_paths_only_take_their_methods() {
+        let api = api().await;
+
+        for (method, path) in [
+            (reqwest::Method::PUT, "/jobs"),
+            (reqwest::Method::POST, "/jobs/1"),
+            (reqwest::Method::DELETE, "/jobs/1/results"),
+            (reqwest::Method::POST, "/jobs/1/events"),
+        ] {
+            let (status, body) = request(method.clone(), format!("{}{}", api, path)).await;
+            assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, path);
+            assert_eq!(body["error"], "method not allowed");
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::sync::OnceLock;
+
+const SERVICES_PATH: &str = "/etc/services";
+
+/// Used when /etc/services is missing, as it is on Windows and in plenty of minimal containers
+const FALLBACK_SERVICES: [(&str, u16); 36] = [
+    ("ftp-data", 20),
+    ("ftp", 21),
+    ("ssh", 22),
+    ("telnet", 23),
+    ("smtp", 25),
+    ("domain", 53),
+    ("dns", 53),
+    ("http", 80),
+    ("www", 80),
+    ("kerberos", 88),
+    ("pop3", 110),
+    ("sunrpc", 111),
+    ("ntp", 123),
+    ("msrpc", 135),
+    ("netbios-ssn", 139),
+    ("imap", 143),
+    ("snmp", 161),
+    ("ldap", 389),
+    ("https", 443),
+    ("microsoft-ds", 445),
+    ("smb", 445),
+    ("submission", 587),
+    ("ldaps", 636),
+    ("imaps", 993),
+    ("pop3s", 995),
+    ("mssql", 1433),
+    ("nfs", 2049),
+    ("mysql", 3306),
+    ("rdp", 3389),
+    ("ms-wbt-server", 3389),
+    ("postgresql", 5432),
+    ("vnc", 5900),
+    ("redis", 6379),
+    ("http-alt", 8080),
+    ("https-alt", 8443),
+    ("mongodb", 27017),
+];
+
+/// Looks up the port of a service by name (e.g. `ssh`), preferring /etc/services over the built-in table
+pub(crate) fn service_port(name: &str) -> Option<u16> {
+    static SERVICES: OnceLock<HashMap<String, u16>> = OnceLock::new();
+
+    let services = SERVICES.get_or_init(|| {
+        let mut services: HashMap<String, u16> = FALLBACK_SERVICES
+            .iter()
+            .map(|&(name, port)| (name.to_string(), port))
+            .collect();
+
+        if let Ok(services_file) = read_to_string(SERVICES_PATH) {
+            services.extend(parse_services(&services_file));
+        }
+
+        services
+    });
+
+    services.get(&name.to_ascii_lowercase()).copied()
+}
+
+/// Parses `name port/protocol [aliases...] [# comment]` lines. Only TCP entries are kept since a name may map to a
+/// different port per protocol, and the TCP one is what scans are after.
+fn parse_services(services_file: &str) -> HashMap<String, u16> {
+    let mut services = HashMap::new();
+
+    for line in services_file.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+
+        let (Some(name), Some(port_and_protocol)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+
+        let port = match port_and_protocol.split_once('/') {
+            Some((port, "tcp")) => port.parse::<u16>().ok(),
+            _ => None,
+        };
+
+        if let Some(port) = port {
+            for name in std::iter::once(name).chain(fields) {
+                services.entry(name.to_ascii_lowercase()).or_insert(port);
+            }
+        }
+    }
+
+    services
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services_files_map_tcp_names_and_aliases_to_ports() {
+        let services = parse_services(
+            "# Network services\n\
+             \n\
+             ssh\t\t22/tcp\t\t\t# SSH Remote Login Protocol\n\
+             http\t\t80/tcp\t\twww\t\t# WorldWideWeb HTTP\n\
+             syslog\t\t514/udp\n\
+             HTTPS\t\t443/tcp\n\
+             bogus\t\tnot-a-port/tcp\n",
+        );
+
+        assert_eq!(services.get("ssh"), Some(&22));
+        assert_eq!(services.get("http"), Some(&80));
+        assert_eq!(services.get("www"), Some(&80));
+        assert_eq!(services.get("https"), Some(&443));
+        assert_eq!(services.get("syslog"), None);
+        assert_eq!(services.get("bogus"), None);
+        assert_eq!(services.len(), 4);
+    }
+
+    #[test]
+    fn the_first_entry_for_a_name_wins() {
+        let services = parse_services("kerberos 88/tcp kerberos5\nkerberos5 750/tcp\n");
+
+        assert_eq!(services.get("kerberos5"), Some(&88));
+    }
+
+    #[test]
+    fn service_names_are_looked_up_regardless_of_case() {
+        assert_eq!(service_port("SSH"), Some(22));
+        assert_eq!(service_port("https"), Some(443));
+        assert_eq!(service_port("not-a-service"), None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+#[cfg(unix)]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use armada_lib::{Armada, ArmadaWorkMessage, HostIterator, PortIterator, PortState, ScanOptions, ScanType};
+use cidr_utils::cidr::IpCidr;
+
+/// How long the packets per second are averaged over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// How far the scan got, kept up to date from its messages for the control socket and `SIGUSR1` to report on
+#[derive(Default)]
+pub(crate) struct ScanStatus {
+    /// `None` until the scan itself starts, after discovery and looking up hostnames
+    state: Mutex<Option<StatusState>>,
+}
+
+struct StatusState {
+    started: Instant,
+    hosts: u128,
+    total_pairs: u128,
+    /// Pairs settled before the scan was resumed
+    skipped_pairs: u128,
+    randomized: bool,
+    /// The ranges the targets are made up of, with the position of each one's first pair in the scan order
+    blocks: Vec<(IpCidr, u128)>,
+    processed_pairs: u128,
+    inflight_packets: u128,
+    packets_sent: u128,
+    responses_received: u128,
+    retries: u128,
+    cursor: u128,
+    results: u128,
+    /// Results in a state the scan type looks for, however many other states are reported with them
+    findings: u128,
+    /// What the findings are told apart from the other results by
+    scan_type: ScanType,
+    open_ports: u128,
+    /// The hosts with at least one open port
+    open_hosts: HashSet<IpAddr>,
+    rate_limit: Option<usize>,
+    /// When the current rate window started, and the packets sent by then
+    rate_window: (Instant, u128),
+    packets_per_second: f64,
+    /// What the first worker to fail failed with
+    failure: Option<String>,
+}
+
+/// The scan's status at one point in time
+pub(crate) struct StatusSnapshot {
+    pub(crate) elapsed: Duration,
+    pub(crate) hosts: u128,
+    pub(crate) pairs_done: u128,
+    pub(crate) total_pairs: u128,
+    pub(crate) results: u128,
+    /// Results in a state the scan type looks for: open for most scans, but open|filtered for FIN, NULL and Xmas scans
+    /// and unfiltered or filtered for ACK scans
+    pub(crate) findings: u128,
+    pub(crate) open_ports: u128,
+    pub(crate) open_hosts: u128,
+    pub(crate) packets_sent: u128,
+    /// Responses that settled the state of a port
+    pub(crate) responses_received: u128,
+    pub(crate) packets_per_second: f64,
+    pub(crate) inflight_packets: u128,
+    pub(crate) retries: u128,
+    /// The rate limit the scan is being sent at, `None` being unlimited
+    pub(crate) rate_limit: Option<usize>,
+    /// `None` until the scan has settled a pair to go by
+    pub(crate) eta: Option<Duration>,
+    /// The range of targets being probed, which scans in random order don't have
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) current_block: Option<IpCidr>,
+    /// Why the scan failed part way, if a worker did
+    pub(crate) failure: Option<String>,
+}
+
+impl StatusSnapshot {
+    pub(crate) fn percent(&self) -> f64 { percent(self.pairs_done, self.total_pairs) }
+}
+
+/// The pairs a scan has settled, counting the ones a resumed scan skips, and how long the rest should take at the rate
+/// pairs have been settled at so far, which already takes the retries into account. The time left is `None` until the
+/// scan has settled a pair to go by.
+pub(crate) fn pair_progress(
+    elapsed: Duration,
+    skipped_pairs: u128,
+    processed_pairs: u128,
+    total_pairs: u128,
+) -> (u128, Option<Duration>) {
+    let pairs_done = (skipped_pairs + processed_pairs).min(total_pairs);
+    let eta = (processed_pairs > 0).then(|| {
+        let remaining = total_pairs.saturating_sub(pairs_done) as f64;
+        Duration::from_secs_f64(remaining * elapsed.as_secs_f64() / processed_pairs as f64)
+    });
+
+    (pairs_done, eta)
+}
+
+pub(crate) fn percent(pairs_done: u128, total_pairs: u128) -> f64 {
+    match total_pairs {
+        0 => 100.0,
+        total_pairs => pairs_done as f64 * 100.0 / total_pairs as f64,
+    }
+}
+
+/// The probes sent per second over a window of however long
+pub(crate) fn send_rate(packets_sent: u128, window: Duration) -> f64 {
+    match window.is_zero() {
+        true => 0.0,
+        false => packets_sent as f64 / window.as_secs_f64(),
+    }
+}
+
+impl ScanStatus {
+    pub(crate) fn start(&self, targets: &HostIterator, ports: &PortIterator, options: &ScanOptions) {
+        let ports = ports.size() as u128;
+        let mut next_pair = 0;
+        let blocks = targets
+            .ranges()
+            .iter()
+            .map(|range| {
+                let first_pair = next_pair;
+                next_pair += u128::try_from(&range.size()).unwrap_or(u128::MAX).saturating_mul(ports);
+
+                (*range, first_pair)
+            })
+            .collect();
+
+        let now = Instant::now();
+        *self.state.lock().expect("Scan status lock poisoned.") = Some(StatusState {
+            started: now,
+            hosts: targets.size(),
+            total_pairs: options.probed_pairs(targets.size() * ports),
+            skipped_pairs: options.probed_pairs(options.skip_pairs),
+            randomized: options.randomize,
+            blocks,
+            processed_pairs: 0,
+            inflight_packets: 0,
+            packets_sent: 0,
+            responses_received: 0,
+            retries: 0,
+            cursor: options.skip_pairs,
+            results: 0,
+            findings: 0,
+            scan_type: options.scan_type,
+            open_ports: 0,
+            open_hosts: HashSet::new(),
+            rate_limit: options.packets_per_second,
+            rate_window: (now, 0),
+            packets_per_second: 0.0,
+            failure: None,
+        });
+    }
+
+    pub(crate) fn record(&self, message: &ArmadaWorkMessage) {
+        let mut state = self.state.lock().expect("Scan status lock poisoned.");
+        let Some(state) = state.as_mut() else { return };
+
+        match message {
+            ArmadaWorkMessage::Results(results) => {
+                state.results += results.len() as u128;
+                state.findings += results.iter().filter(|result| state.scan_type.reports(result.state)).count() as u128;
+                for result in results.iter().filter(|result| result.state == PortState::Open) {
+                    state.open_ports += 1;
+                    state.open_hosts.insert(result.addr.ip());
+                }
+            }
+            ArmadaWorkMessage::Stats {
+                total_processed_ports,
+                current_inflight_packets,
+                total_packets_sent,
+                total_responses_received,
+                total_retries,
+                scan_cursor,
+                packets_per_second,
+                ..
+            } => {
+                state.processed_pairs = *total_processed_ports;
+                state.inflight_packets = *current_inflight_packets;
+                state.packets_sent = *total_packets_sent;
+                state.responses_received = *total_responses_received;
+                state.retries = *total_retries;
+                state.cursor = *scan_cursor;
+                state.rate_limit = *packets_per_second;
+
+                let (window_start, window_packets_sent) = state.rate_window;
+                let elapsed = window_start.elapsed();
+
+                if elapsed >= RATE_WINDOW {
+                    state.packets_per_second = send_rate(state.packets_sent - window_packets_sent, elapsed);
+                    state.rate_window = (Instant::now(), state.packets_sent);
+                }
+            }
+            ArmadaWorkMessage::Failed(error) => {
+                state.failure.get_or_insert_with(|| format!("{:#}", error));
+            }
+        }
+    }
+
+    /// `None` while the scan hasn't started yet
+    pub(crate) fn snapshot(&self, armada: &Armada) -> Option<StatusSnapshot> {
+        let state = self.state.lock().expect("Scan status lock poisoned.");
+        let state = state.as_ref()?;
+
+        let elapsed = state.started.elapsed();
+        let (pairs_done, eta) = pair_progress(elapsed, state.skipped_pairs, state.processed_pairs, state.total_pairs);
+        let current_block = match state.randomized {
+            true => None,
+            false => state.blocks.iter().rev().find(|(_, first_pair)| *first_pair <= state.cursor).map(|(range, _)| *range),
+        };
+
+        Some(StatusSnapshot {
+            elapsed,
+            hosts: state.hosts,
+            pairs_done,
+            total_pairs: state.total_pairs,
+            results: state.results,
+            findings: state.findings,
+            open_ports: state.open_ports,
+            open_hosts: state.open_hosts.len() as u128,
+            packets_sent: state.packets_sent,
+            responses_received: state.responses_received,
+            packets_per_second: state.packets_per_second,
+            inflight_packets: state.inflight_packets,
+            retries: state.retries,
+            rate_limit: armada.rate_limit().or(state.rate_limit),
+            eta,
+            current_block,
+            failure: state.failure.clone(),
+        })
+    }
+}
+
+/// Prints a status report to stderr on every `SIGUSR1`, the way `dd` does, quiet mode or not
+#[cfg(unix)]
+pub(crate) fn report_on_signal(armada: Armada, status: Arc<ScanStatus>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut report_signal = signal(SignalKind::user_defined1()).expect("Unable to listen for SIGUSR1.");
+
+    tokio::spawn(async move {
+        while report_signal.recv().await.is_some() {
+            eprintln!("{}", report(&armada, status.snapshot(&armada)));
+        }
+    });
+}
+
+#[cfg(unix)]
+fn report(armada: &Armada, snapshot: Option<StatusSnapshot>) -> String {
+    let Some(snapshot) = snapshot else {
+        return "Status: the scan hasn't started yet.".to_string();
+    };
+
+    let state = match (armada.is_stopped(), armada.is_paused()) {
+        (true, _) => ", stopping",
+        (false, true) => ", paused",
+        (false, false) => "",
+    };
+    let rate_limit = match snapshot.rate_limit {
+        Some(rate_limit) => format!("limit {} pps", rate_limit),
+        None => "unlimited".to_string(),
+    };
+    let eta = snapshot.eta.map_or("-".to_string(), format_elapsed);
+    let current_block = match snapshot.current_block {
+        Some(current_block) => current_block.to_string(),
+        None => "every block at once, in random order".to_string(),
+    };
+
+    [
+        format!(
+            "Status: {} of {} target/port pairs done ({:.2}%){}",
+            snapshot.pairs_done,
+            snapshot.total_pairs,
+            snapshot.percent(),
+            state,
+        ),
+        format!("  elapsed {}, ETA {}", format_elapsed(snapshot.elapsed), eta),
+        format!(
+            "  {:.0} pps ({}), {} sent, {} in flight, {} retries",
+            snapshot.packets_per_second, rate_limit, snapshot.packets_sent, snapshot.inflight_packets, snapshot.retries,
+        ),
+        format!("  {} open ports found, {} results in all", snapshot.open_ports, snapshot.results),
+        format!("  probing {}", current_block),
+    ]
+    .join("\n")
+}
+
+pub(crate) fn format_elapsed(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    format!("{:02}:{:02}:{:02}", seconds / 3_600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_eta_goes_by_the_pairs_settled_so_far() {
+        // a quarter of the pairs in 10s leaves 30s
+        assert_eq!(pair_progress(Duration::from_secs(10), 0, 25, 100), (25, Some(Duration::from_secs(30))));
+        // skipped pairs are done but weren't settled in the time elapsed
+        assert_eq!(pair_progress(Duration::from_secs(10), 50, 25, 100), (75, Some(Duration::from_secs(10))));
+        assert_eq!(pair_progress(Duration::from_secs(10), 50, 0, 100), (50, None));
+        assert_eq!(pair_progress(Duration::from_secs(10), 90, 20, 100), (100, Some(Duration::ZERO)));
+    }
+
+    #[test]
+    fn nothing_to_scan_is_all_done() {
+        assert_eq!(percent(0, 0), 100.0);
+        assert_eq!(percent(1, 4), 25.0);
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::status::{format_elapsed, StatusSnapshot};
+
+/// The accounting of a finished scan, printed once it's over and written to `--summary-file`
+#[derive(Serialize)]
+pub(crate) struct ScanSummary {
+    pub(crate) hosts_scanned: u128,
+    pub(crate) hosts_with_open_ports: u128,
+    pub(crate) open_ports: u128,
+    pub(crate) packets_sent: u128,
+    /// Responses that settled the state of a port
+    pub(crate) packets_received: u128,
+    pub(crate) retransmissions: u128,
+    pub(crate) elapsed_seconds: f64,
+    /// The packets sent over the time the scan took, waiting out the last timeouts included
+    pub(crate) packets_per_second: f64,
+}
+
+impl From<&StatusSnapshot> for ScanSummary {
+    fn from(snapshot: &StatusSnapshot) -> Self {
+        let elapsed_seconds = snapshot.elapsed.as_secs_f64();
+
+        Self {
+            hosts_scanned: snapshot.hosts,
+            hosts_with_open_ports: snapshot.open_hosts,
+            open_ports: snapshot.open_ports,
+            packets_sent: snapshot.packets_sent,
+            packets_received: snapshot.responses_received,
+            retransmissions: snapshot.retries,
+            elapsed_seconds: (elapsed_seconds * 1000.0).round() / 1000.0,
+            packets_per_second: match elapsed_seconds > 0.0 {
+                true => (snapshot.packets_sent as f64 / elapsed_seconds).round(),
+                false => 0.0,
+            },
+        }
+    }
+}
+
+impl ScanSummary {
+    /// Prints the summary to stderr, out of the way of results on stdout
+    pub(crate) fn print(&self) {
+        eprintln!(
+            "Scanned {} hosts in {}: {} open ports on {} hosts.\n  {} packets sent, {} received, {} retransmissions, {:.0} pps",
+            self.hosts_scanned,
+            format_elapsed(Duration::from_secs_f64(self.elapsed_seconds)),
+            self.open_ports,
+            self.hosts_with_open_ports,
+            self.packets_sent,
+            self.packets_received,
+            self.retransmissions,
+            self.packets_per_second,
+        );
+    }
+
+    pub(crate) fn write(&self, path: &Path) {
+        let summary = serde_json::to_string_pretty(self).expect("Failed to serialize the scan summary.");
+
+        std::fs::write(path, summary + "\n")
+            .unwrap_or_else(|error| panic!("Unable to write the scan summary to '{}': {}", path.display(), error));
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::bail;
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::tcp::TcpFlags::{ACK, FIN, PSH, RST, SYN, URG};
+use pnet::packet::tcp::TcpPacket;
+
+use crate::armada::capture::{CaptureReader, CapturedPacket};
+use crate::armada::fingerprint::SynAckFingerprint;
+use crate::armada::packet::{IPV6_HEADER_LENGTH, SCTP_CHUNK_TYPE_INIT};
+use crate::armada::response::{SctpResponse, TcpResponse};
+use crate::armada::result::PortResult;
+use crate::armada::scan_type::ScanType;
+use crate::armada::tcp_ext::{IcmpReceiverExt, SctpReceiverExt, TcpReceiverExt};
+use crate::armada::transport::{Channel, Transport};
+
+/// The TCP flags of our probes that a scan type can be told apart by. The rest, like the ECN bits, are left out.
+const PROBE_FLAGS: u16 = FIN | SYN | RST | PSH | ACK | URG;
+
+/// Settings for working out the results of a scan from a capture of it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AnalyzeOptions {
+    /// The scan type the capture was taken of. `None` goes by the most common kind of probe in the capture, which
+    /// can't tell a window scan from an ACK scan.
+    pub scan_type: Option<ScanType>,
+    /// Whether open ports have the fingerprint of their SYN-ACK recorded, for guessing the host's operating system
+    pub collect_fingerprints: bool,
+    /// Whether every probed port is reported, whatever its state, rather than only the ones interesting to the scan
+    /// type. Ports no response was captured for come out in the state a scan would time them out in.
+    pub report_all_states: bool,
+}
+
+/// What a capture says about the scan it was taken of
+#[derive(Clone, Debug)]
+pub struct CaptureAnalysis {
+    /// The scan type the capture was analyzed as, whether it was given or worked out
+    pub scan_type: ScanType,
+    /// The results of the scan, sorted by address
+    pub results: Vec<PortResult>,
+}
+
+/// A probe found in the capture
+struct Probe {
+    listening_port: u16,
+    /// The acknowledgement number an answer to the probe carries, when it carries one
+    expected_acknowledgement: u32,
+    sent_at: Duration,
+}
+
+/// Works out the results of a scan from a pcap capture of its traffic, like one written by `--pcap` or tcpdump.
+/// Responses go through the same classification the scan itself puts them through, and only count when they answer
+/// a probe in the capture: they have to come back to the port the probe went out from, and TCP responses that
+/// acknowledge something have to acknowledge the probe's sequence number. Like a scan, the first telling response
+/// to a port decides its state.
+pub fn analyze_capture(path: impl AsRef<Path>, options: AnalyzeOptions) -> anyhow::Result<CaptureAnalysis> {
+    let path = path.as_ref();
+
+    let scan_type = match options.scan_type {
+        Some(scan_type) => scan_type,
+        None => infer_scan_type(path)?,
+    };
+
+    let mut probes: HashMap<SocketAddr, Vec<Probe>> = HashMap::new();
+    let mut settled = HashSet::new();
+    let mut results = Vec::new();
+    let mut replay = Replay::default();
+
+    for captured in CaptureReader::open(path)? {
+        let CapturedPacket { timestamp, ip_packet } = captured?;
+
+        let (channel, datagram, source, destination) = match split_ip_packet(&ip_packet) {
+            Some(split) => split,
+            None => continue,
+        };
+
+        let transport_packet = match transport_packet(channel, datagram) {
+            Some(transport_packet) => transport_packet,
+            None => continue,
+        };
+
+        if let Some(probe) = parse_probe(scan_type, channel, transport_packet, timestamp) {
+            let remote_port = u16::from_be_bytes([transport_packet[2], transport_packet[3]]);
+            probes.entry(SocketAddr::new(destination, remote_port)).or_default().push(probe);
+
+            continue;
+        }
+
+        replay.load(channel, datagram, source);
+
+        let result = match channel {
+            Channel::Ipv4Tcp | Channel::Ipv6Tcp if !scan_type.is_sctp() => {
+                let (packet, remote, ttl, _) = match replay.try_next(channel) {
+                    Ok(Some(received)) => received,
+                    _ => continue,
+                };
+                let response = TcpResponse::from(&packet);
+                let remote = SocketAddr::new(remote, response.source_port);
+
+                let answered = probes.get(&remote).and_then(|probes| {
+                    probes.iter().rev().find(|probe| {
+                        probe.listening_port == response.destination_port
+                            && (response.flags & ACK == 0 || response.acknowledgement == probe.expected_acknowledgement)
+                    })
+                });
+
+                answered.zip(scan_type.classify_response(&response)).map(|(probe, state)| {
+                    let fingerprint = (options.collect_fingerprints && response.flags & (SYN | ACK) == SYN | ACK)
+                        .then(|| SynAckFingerprint::from_packet(&packet, ttl));
+
+                    PortResult::new(remote, state)
+                        .with_fingerprint(fingerprint)
+                        .with_rtt(timestamp.checked_sub(probe.sent_at))
+                })
+            }
+            Channel::Ipv4Sctp | Channel::Ipv6Sctp if scan_type.is_sctp() => {
+                let (response, remote, _) = match replay.try_next_sctp(channel) {
+                    Ok(Some(received)) => received,
+                    _ => continue,
+                };
+                let remote = SocketAddr::new(remote, response.source_port);
+
+                let answered = probes
+                    .get(&remote)
+                    .and_then(|probes| probes.iter().rev().find(|probe| probe.listening_port == response.destination_port));
+
+                answered.zip(scan_type.classify_sctp_response(&response)).map(|(probe, state)| {
+                    PortResult::new(remote, state).with_rtt(timestamp.checked_sub(probe.sent_at))
+                })
+            }
+            Channel::Ipv4Icmp | Channel::Ipv6Icmp => {
+                let (listening_port, remote, _) = match replay.try_next_unreachable(channel) {
+                    Ok(Some(received)) => received,
+                    _ => continue,
+                };
+
+                let answered = probes
+                    .get(&remote)
+                    .and_then(|probes| probes.iter().rev().find(|probe| probe.listening_port == listening_port));
+
+                answered.map(|probe| {
+                    PortResult::new(remote, scan_type.unreachable_state()).with_rtt(timestamp.checked_sub(probe.sent_at))
+                })
+            }
+            _ => continue,
+        };
+
+        if let Some(result) = result {
+            if settled.insert(result.addr) {
+                let attempts = probes.get(&result.addr).map(|probes| u16::try_from(probes.len()).unwrap_or(u16::MAX));
+                results.push(result.with_attempt(attempts));
+            }
+        }
+    }
+
+    if probes.is_empty() {
+        bail!("{} holds no probes of a {:?} scan.", path.display(), scan_type);
+    }
+
+    if options.report_all_states {
+        results.extend(
+            probes
+                .keys()
+                .filter(|remote| !settled.contains(remote))
+                .map(|remote| PortResult::new(*remote, scan_type.timeout_state())),
+        );
+    } else {
+        results.retain(|result| scan_type.reports(result.state));
+    }
+
+    results.sort_by_key(|result| result.addr);
+
+    Ok(CaptureAnalysis { scan_type, results })
+}
+
+/// Picks the scan type most of the capture's probes were sent for
+fn infer_scan_type(path: &Path) -> anyhow::Result<ScanType> {
+    let mut probe_counts: HashMap<ScanType, usize> = HashMap::new();
+
+    for captured in CaptureReader::open(path)? {
+        let CapturedPacket { ip_packet, .. } = captured?;
+
+        let scan_type = split_ip_packet(&ip_packet).and_then(|(channel, datagram, _, _)| {
+            probed_scan_type(channel, transport_packet(channel, datagram)?)
+        });
+
+        if let Some(scan_type) = scan_type {
+            *probe_counts.entry(scan_type).or_default() += 1;
+        }
+    }
+
+    match probe_counts.into_iter().max_by_key(|(_, count)| *count) {
+        Some((scan_type, _)) => Ok(scan_type),
+        None => bail!("{} holds no probes armada could have sent.", path.display()),
+    }
+}
+
+/// The scan type the packet would be a probe of, if it looks like one of ours at all
+fn probed_scan_type(channel: Channel, transport_packet: &[u8]) -> Option<ScanType> {
+    match channel {
+        Channel::Ipv4Tcp | Channel::Ipv6Tcp => {
+            let packet = TcpPacket::new(transport_packet)?;
+
+            match packet.get_flags() & PROBE_FLAGS {
+                SYN => Some(ScanType::Syn),
+                FIN => Some(ScanType::Fin),
+                0 => Some(ScanType::Null),
+                flags if flags == FIN | PSH | URG => Some(ScanType::Xmas),
+                // a window scan's probes look the same
+                ACK => Some(ScanType::Ack),
+                _ => None,
+            }
+        }
+        Channel::Ipv4Sctp | Channel::Ipv6Sctp => {
+            (SctpResponse::parse(transport_packet)?.chunk_type == SCTP_CHUNK_TYPE_INIT).then_some(ScanType::SctpInit)
+        }
+        _ => None,
+    }
+}
+
+/// Reads the packet as a probe of the scan type, if it is one
+fn parse_probe(scan_type: ScanType, channel: Channel, transport_packet: &[u8], sent_at: Duration) -> Option<Probe> {
+    let probed_scan_type = probed_scan_type(channel, transport_packet)?;
+
+    // window scans send the same probes as ACK scans
+    let matches_scan_type =
+        probed_scan_type == scan_type || (probed_scan_type, scan_type) == (ScanType::Ack, ScanType::Window);
+    if !matches_scan_type {
+        return None;
+    }
+
+    let listening_port = u16::from_be_bytes([transport_packet[0], transport_packet[1]]);
+
+    // SYNs and FINs take up a sequence number of their own, so answers to them acknowledge the one after
+    let expected_acknowledgement = TcpPacket::new(transport_packet).map_or(0, |packet| {
+        let takes_sequence_number = packet.get_flags() & (SYN | FIN) != 0;
+
+        packet.get_sequence().wrapping_add(takes_sequence_number as u32)
+    });
+
+    Some(Probe { listening_port, expected_acknowledgement, sent_at })
+}
+
+/// Splits an IP packet into the channel it would have been received on and the datagram the raw socket of that
+/// channel would have handed over, along with where it came from and went to
+fn split_ip_packet(ip_packet: &[u8]) -> Option<(Channel, &[u8], IpAddr, IpAddr)> {
+    match ip_packet.first()? >> 4 {
+        4 => {
+            let ipv4_packet = Ipv4Packet::new(ip_packet)?;
+            let channel = Channel::for_response(ipv4_packet.get_next_level_protocol(), false)?;
+            // anything past the total length is padding to the minimum frame size
+            let datagram = ip_packet.get(..ipv4_packet.get_total_length() as usize).unwrap_or(ip_packet);
+
+            Some((channel, datagram, IpAddr::V4(ipv4_packet.get_source()), IpAddr::V4(ipv4_packet.get_destination())))
+        }
+        6 => {
+            let ipv6_packet = Ipv6Packet::new(ip_packet)?;
+            let channel = Channel::for_response(ipv6_packet.get_next_header(), true)?;
+            let payload = ip_packet.get(IPV6_HEADER_LENGTH..)?;
+            let datagram = payload.get(..ipv6_packet.get_payload_length() as usize).unwrap_or(payload);
+
+            Some((channel, datagram, IpAddr::V6(ipv6_packet.get_source()), IpAddr::V6(ipv6_packet.get_destination())))
+        }
+        _ => None,
+    }
+}
+
+/// Where the transport layer packet starts in the datagram. IPv4 datagrams start with the IP header, IPv6 ones don't.
+fn transport_packet(channel: Channel, datagram: &[u8]) -> Option<&[u8]> {
+    match channel.is_ipv6() {
+        true => Some(datagram),
+        false => datagram.get(Ipv4Packet::new(datagram)?.get_header_length() as usize * 4..),
+    }
+}
+
+/// A transport that hands over one captured datagram at a time, so responses read out of a capture are parsed by
+/// the same code as responses read off of a socket
+#[derive(Default)]
+struct Replay {
+    loaded: Option<(Channel, Vec<u8>, IpAddr)>,
+    current: Vec<u8>,
+}
+
+impl Replay {
+    fn load(&mut self, channel: Channel, datagram: &[u8], source: IpAddr) {
+        self.loaded = Some((channel, datagram.to_vec(), source));
+    }
+}
+
+impl Transport for Replay {
+    fn queue(&mut self, _channel: Channel, _source: IpAddr, _remote: SocketAddr, _packet: &[u8]) -> bool {
+        false
+    }
+
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        (Vec::new(), Vec::new())
+    }
+
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>> {
+        match self.loaded.take() {
+            Some((loaded_channel, datagram, source)) if loaded_channel == channel => {
+                self.current = datagram;
+
+                Ok(Some((&self.current, source)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
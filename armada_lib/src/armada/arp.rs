@@ -0,0 +1,157 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use pnet::datalink::{self, Channel, DataLinkReceiver, DataLinkSender, NetworkInterface};
+use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::{MutablePacket, Packet};
+use pnet::util::MacAddr;
+use tracing::warn;
+
+use crate::utils::OnLinkNetwork;
+
+const ETHERNET_HEADER_LENGTH: usize = 14;
+const ARP_PACKET_LENGTH: usize = 28;
+
+/// A datalink channel onto one of the interfaces holding on-link networks
+struct ArpLink {
+    networks: Vec<OnLinkNetwork>,
+    source_mac: MacAddr,
+    source_ip: Ipv4Addr,
+    sender: Box<dyn DataLinkSender>,
+    receiver: Box<dyn DataLinkReceiver>,
+}
+
+/// Resolves hosts on our local networks with ARP. Unlike ICMP or TCP, hosts can't ignore ARP and still be reachable.
+pub(crate) struct ArpProber {
+    links: Vec<ArpLink>,
+}
+
+impl ArpProber {
+    /// Opens a datalink channel for every interface holding one of the networks. Interfaces we can't send ARP requests
+    /// from are skipped, leaving their hosts to the regular discovery probes.
+    pub(crate) fn open(on_link_networks: Vec<OnLinkNetwork>) -> Self {
+        let interfaces = datalink::interfaces();
+        let mut links: Vec<ArpLink> = Vec::new();
+
+        for on_link_network in on_link_networks {
+            if let Some(link) = links.iter_mut().find(|link| {
+                link.networks.first().map(|network| &network.interface_name) == Some(&on_link_network.interface_name)
+            }) {
+                link.networks.push(on_link_network);
+                continue;
+            }
+
+            let interface = match interfaces.iter().find(|interface| interface.name == on_link_network.interface_name) {
+                Some(interface) => interface,
+                None => continue,
+            };
+
+            match open_link(interface, on_link_network) {
+                Ok(Some(link)) => links.push(link),
+                Ok(None) => {}
+                Err(e) => warn!("Unable to ARP on interface {}: {}", interface.name, e),
+            }
+        }
+
+        Self { links }
+    }
+
+    /// Broadcasts an ARP request for the host if it's on one of our links. Returns `None` when the host isn't on-link
+    /// and `Some(sent)` otherwise.
+    pub(crate) fn try_send_request(&mut self, remote_ip: Ipv4Addr) -> Option<bool> {
+        let link = self.links.iter_mut().find(|link| link.networks.iter().any(|on_link| on_link.network.contains(remote_ip)))?;
+
+        let mut frame_buffer = [0; ETHERNET_HEADER_LENGTH + ARP_PACKET_LENGTH];
+        let sent = match create_arp_request_frame(link.source_mac, link.source_ip, remote_ip, &mut frame_buffer) {
+            Some(frame) => matches!(link.sender.send_to(frame.packet(), None), Some(Ok(_))),
+            None => false,
+        };
+
+        Some(sent)
+    }
+
+    /// Drains every link of ARP replies, handing each responder's address and MAC to `on_reply`
+    pub(crate) fn receive_replies(&mut self, mut on_reply: impl FnMut(Ipv4Addr, MacAddr)) {
+        for link in self.links.iter_mut() {
+            while let Ok(frame) = link.receiver.next() {
+                let ethernet = match EthernetPacket::new(frame) {
+                    Some(ethernet) if ethernet.get_ethertype() == EtherTypes::Arp => ethernet,
+                    _ => continue,
+                };
+
+                let arp = match ArpPacket::new(ethernet.payload()) {
+                    Some(arp) if arp.get_operation() == ArpOperations::Reply => arp,
+                    _ => continue,
+                };
+
+                if arp.get_target_proto_addr() == link.source_ip {
+                    on_reply(arp.get_sender_proto_addr(), arp.get_sender_hw_addr());
+                }
+            }
+        }
+    }
+}
+
+fn open_link(interface: &NetworkInterface, on_link_network: OnLinkNetwork) -> anyhow::Result<Option<ArpLink>> {
+    let source_mac = match interface.mac {
+        Some(source_mac) if !source_mac.is_zero() => source_mac,
+        // loopbacks and tunnels don't speak ARP
+        _ => return Ok(None),
+    };
+
+    let source_ip = on_link_network.source_ip.or_else(|| {
+        interface.ips.iter().find_map(|network| match network.ip() {
+            IpAddr::V4(ipv4) => Some(ipv4),
+            IpAddr::V6(_) => None,
+        })
+    });
+
+    let source_ip = match source_ip {
+        Some(source_ip) => source_ip,
+        None => return Ok(None),
+    };
+
+    let config = datalink::Config {
+        // a zero timeout lets us poll the channel without blocking
+        read_timeout: Some(Duration::ZERO),
+        promiscuous: false,
+        ..Default::default()
+    };
+
+    match datalink::channel(interface, config)? {
+        Channel::Ethernet(sender, receiver) => Ok(Some(ArpLink {
+            networks: vec![on_link_network],
+            source_mac,
+            source_ip,
+            sender,
+            receiver,
+        })),
+        _ => Ok(None),
+    }
+}
+
+fn create_arp_request_frame(
+    source_mac: MacAddr,
+    source_ip: Ipv4Addr,
+    remote_ip: Ipv4Addr,
+    buffer: &mut [u8],
+) -> Option<MutableEthernetPacket<'_>> {
+    let mut ethernet = MutableEthernetPacket::new(buffer)?;
+    ethernet.set_destination(MacAddr::broadcast());
+    ethernet.set_source(source_mac);
+    ethernet.set_ethertype(EtherTypes::Arp);
+
+    let mut arp = MutableArpPacket::new(ethernet.payload_mut())?;
+    arp.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp.set_protocol_type(EtherTypes::Ipv4);
+    arp.set_hw_addr_len(6);
+    arp.set_proto_addr_len(4);
+    arp.set_operation(ArpOperations::Request);
+    arp.set_sender_hw_addr(source_mac);
+    arp.set_sender_proto_addr(source_ip);
+    arp.set_target_hw_addr(MacAddr::zero());
+    arp.set_target_proto_addr(remote_ip);
+
+    Some(ethernet)
+}
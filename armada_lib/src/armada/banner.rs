@@ -0,0 +1,79 @@
+//! Reads the banner services send when they're connected to
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+use crate::armada::connection::ConnectionOptions;
+
+/// How long we keep reading once a service has started talking, so multi-line banners aren't cut off after the first
+/// packet without waiting out the full timeout on services that send a single line.
+const TRAILING_READ_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Settings for banner grabbing
+#[derive(Clone, Debug)]
+pub struct BannerOptions {
+    /// The most bytes of banner kept per port
+    pub max_bytes: usize,
+    pub connection: ConnectionOptions,
+}
+
+impl Default for BannerOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: 512,
+            connection: ConnectionOptions::default(),
+        }
+    }
+}
+
+/// Completes the TCP handshake with open ports and reads whatever the service sends first
+#[derive(Clone)]
+pub struct BannerGrabber {
+    options: BannerOptions,
+    connection_permits: Arc<Semaphore>,
+}
+
+impl BannerGrabber {
+    pub fn new(options: BannerOptions) -> Self {
+        let connection_permits = options.connection.connection_permits();
+
+        Self { options, connection_permits }
+    }
+
+    /// Returns `None` if the connection failed or the service didn't send anything before the timeout.
+    pub async fn grab(&self, addr: SocketAddr) -> Option<Vec<u8>> {
+        let _permit = self.connection_permits.acquire().await.ok()?;
+
+        let connect_timeout = self.options.connection.timeout;
+        let mut stream = timeout(connect_timeout, TcpStream::connect(addr)).await.ok()?.ok()?;
+        let banner = read_response(&mut stream, self.options.max_bytes, connect_timeout).await;
+
+        (!banner.is_empty()).then_some(banner)
+    }
+}
+
+/// Reads up to `max_bytes` of whatever the peer sends, waiting at most `first_read_timeout` for it to start talking
+pub(crate) async fn read_response(stream: &mut TcpStream, max_bytes: usize, first_read_timeout: Duration) -> Vec<u8> {
+    let mut response = vec![0; max_bytes];
+    let mut response_length = 0;
+    let mut read_timeout = first_read_timeout;
+
+    while response_length < response.len() {
+        match timeout(read_timeout, stream.read(&mut response[response_length..])).await {
+            Ok(Ok(read)) if read > 0 => response_length += read,
+            _ => break,
+        }
+
+        read_timeout = TRAILING_READ_TIMEOUT;
+    }
+
+    response.truncate(response_length);
+
+    response
+}
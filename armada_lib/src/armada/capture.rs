@@ -0,0 +1,320 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+
+use crate::armada::packet::{build_ip_packet, IPV6_HEADER_LENGTH, PROBE_TTL};
+use crate::armada::transport::{Channel, Transport};
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+/// The magic of captures with nanosecond rather than microsecond timestamps
+const PCAP_NANOSECOND_MAGIC: u32 = 0xa1b2_3c4d;
+/// The magic pcapng files start with, in their section header block
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 65_535;
+/// Packets start with their IPv4 or IPv6 header, with no link layer header before it
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86dd;
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+/// Room for the largest datagram a raw socket hands over along with the IP header we put in front of it
+const MAX_RECORD_LENGTH: usize = 65_535 + IPV6_HEADER_LENGTH;
+
+/// A pcap file every probe sent and every response received during a scan is written to, for working out after the
+/// fact why a port came out the way it did. Clones write to the same file, so one capture can be shared by every
+/// worker of a scan.
+#[derive(Clone, Debug)]
+pub struct PacketCapture {
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl PacketCapture {
+    /// Creates the file, replacing whatever was there before
+    pub fn create(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Unable to create pcap file {}.", path.display()))?;
+        let mut writer = BufWriter::new(file);
+
+        let mut header = Vec::with_capacity(24);
+        header.extend(PCAP_MAGIC.to_ne_bytes());
+        header.extend(PCAP_VERSION_MAJOR.to_ne_bytes());
+        header.extend(PCAP_VERSION_MINOR.to_ne_bytes());
+        // the timezone offset and timestamp accuracy, which everyone leaves at zero
+        header.extend([0; 8]);
+        header.extend(PCAP_SNAPLEN.to_ne_bytes());
+        header.extend(LINKTYPE_RAW.to_ne_bytes());
+
+        writer
+            .write_all(&header)
+            .with_context(|| format!("Unable to write to pcap file {}.", path.display()))?;
+
+        Ok(Self { writer: Arc::new(Mutex::new(writer)) })
+    }
+
+    /// Writes out whatever packets are still buffered
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.lock().map_or(Ok(()), |mut writer| writer.flush())
+    }
+
+    fn record(&self, ip_packet: &[u8]) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let length = ip_packet.len().min(PCAP_SNAPLEN as usize);
+
+        let mut record_header = [0; 16];
+        record_header[..4].copy_from_slice(&(timestamp.as_secs() as u32).to_ne_bytes());
+        record_header[4..8].copy_from_slice(&timestamp.subsec_micros().to_ne_bytes());
+        record_header[8..12].copy_from_slice(&(length as u32).to_ne_bytes());
+        record_header[12..].copy_from_slice(&(ip_packet.len() as u32).to_ne_bytes());
+
+        // a capture that can't be written to isn't worth failing the scan over
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.write_all(&record_header).and_then(|_| writer.write_all(&ip_packet[..length]));
+        }
+    }
+}
+
+/// Sits in front of the transport a scan goes through, recording what passes through it both ways. Transports deal
+/// in transport layer packets for the most part, so IP headers are written in front of them much like the kernel's
+/// would be.
+pub(crate) struct CapturingTransport<'t> {
+    transport: &'t mut dyn Transport,
+    capture: PacketCapture,
+    /// The IPv6 address probes last went out from. Raw sockets don't say which of our addresses an IPv6 response was
+    /// sent to, so they're recorded as sent to this one.
+    ipv6_source: Ipv6Addr,
+    identification: u16,
+    /// The TTL probes are recorded with, the one they go out with as far as we know
+    ttl: u8,
+    buffer: Vec<u8>,
+}
+
+impl<'t> CapturingTransport<'t> {
+    pub(crate) fn new(transport: &'t mut dyn Transport, capture: PacketCapture) -> Self {
+        Self {
+            transport,
+            capture,
+            ipv6_source: Ipv6Addr::UNSPECIFIED,
+            identification: 0,
+            ttl: PROBE_TTL,
+            buffer: vec![0; MAX_RECORD_LENGTH],
+        }
+    }
+}
+
+impl Transport for CapturingTransport<'_> {
+    fn set_batching(&mut self, batch_size: usize, batched_io: bool) {
+        self.transport.set_batching(batch_size, batched_io);
+    }
+
+    /// Probes are still recorded whole, the way they'd be reassembled
+    fn set_fragmentation(&mut self, fragment_size: Option<usize>) -> anyhow::Result<()> {
+        self.transport.set_fragmentation(fragment_size)
+    }
+
+    fn set_ttl(&mut self, ttl: Option<u8>) -> anyhow::Result<()> {
+        self.ttl = ttl.unwrap_or(PROBE_TTL);
+        self.transport.set_ttl(ttl)
+    }
+
+    fn queue(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        if !self.transport.queue(channel, source, remote, packet) {
+            return false;
+        }
+
+        if let IpAddr::V6(source) = source {
+            self.ipv6_source = source;
+        }
+
+        self.identification = self.identification.wrapping_add(1);
+
+        let ip_length =
+            build_ip_packet(&mut self.buffer, channel.protocol(), source, remote.ip(), packet, self.identification, self.ttl);
+        if let Some(ip_length) = ip_length {
+            self.capture.record(&self.buffer[..ip_length]);
+        }
+
+        true
+    }
+
+    fn send_decoy(&mut self, channel: Channel, decoy: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        if !self.transport.send_decoy(channel, decoy, remote, packet) {
+            return false;
+        }
+
+        self.identification = self.identification.wrapping_add(1);
+
+        let ip_length =
+            build_ip_packet(&mut self.buffer, channel.protocol(), decoy, remote.ip(), packet, self.identification, self.ttl);
+        if let Some(ip_length) = ip_length {
+            self.capture.record(&self.buffer[..ip_length]);
+        }
+
+        true
+    }
+
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        self.transport.flush()
+    }
+
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>> {
+        let received = self.transport.try_recv(channel)?;
+
+        if let Some((datagram, remote)) = &received {
+            match remote {
+                // IPv4 datagrams come with their IP header already
+                IpAddr::V4(_) => self.capture.record(datagram),
+                IpAddr::V6(_) => {
+                    let destination = IpAddr::V6(self.ipv6_source);
+                    let ip_length =
+                        build_ip_packet(&mut self.buffer, channel.protocol(), *remote, destination, datagram, 0, PROBE_TTL);
+
+                    if let Some(ip_length) = ip_length {
+                        self.capture.record(&self.buffer[..ip_length]);
+                    }
+                }
+            }
+        }
+
+        Ok(received)
+    }
+}
+
+impl Drop for CapturingTransport<'_> {
+    /// Makes sure everything the scan sent and received is in the file by the time its results are all in
+    fn drop(&mut self) {
+        let _ = self.capture.flush();
+    }
+}
+
+/// A packet read back out of a capture, with whatever link layer header it was captured with taken off
+pub(crate) struct CapturedPacket {
+    /// When the packet was captured, since the Unix epoch
+    pub(crate) timestamp: Duration,
+    pub(crate) ip_packet: Vec<u8>,
+}
+
+/// Reads the IP packets out of a pcap file, whether it was written by `PacketCapture` or captured with tcpdump and
+/// the like. Packets that aren't IPv4 or IPv6 are skipped over.
+pub(crate) struct CaptureReader {
+    reader: BufReader<File>,
+    link_type: u32,
+    swapped: bool,
+    nanosecond_timestamps: bool,
+}
+
+impl CaptureReader {
+    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Unable to open pcap file {}.", path.display()))?;
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0; 24];
+        reader
+            .read_exact(&mut header)
+            .with_context(|| format!("{} is too short to be a pcap file.", path.display()))?;
+
+        let magic = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanosecond_timestamps) = match magic {
+            PCAP_MAGIC => (false, false),
+            PCAP_NANOSECOND_MAGIC => (false, true),
+            magic if magic.swap_bytes() == PCAP_MAGIC => (true, false),
+            magic if magic.swap_bytes() == PCAP_NANOSECOND_MAGIC => (true, true),
+            PCAPNG_MAGIC => bail!(
+                "{} is a pcapng file, which isn't supported. Convert it with `editcap -F pcap` first.",
+                path.display()
+            ),
+            _ => bail!("{} isn't a pcap file.", path.display()),
+        };
+
+        let mut link_type = u32::from_ne_bytes([header[20], header[21], header[22], header[23]]);
+        if swapped {
+            link_type = link_type.swap_bytes();
+        }
+
+        // only the low 16 bits name the link type, the rest are flags
+        let link_type = link_type & 0xffff;
+        if ![LINKTYPE_RAW, LINKTYPE_NULL, LINKTYPE_ETHERNET, LINKTYPE_LINUX_SLL, LINKTYPE_IPV4, LINKTYPE_IPV6, LINKTYPE_LINUX_SLL2]
+            .contains(&link_type)
+        {
+            bail!("{} has packets of link type {}, which isn't supported.", path.display(), link_type);
+        }
+
+        Ok(Self { reader, link_type, swapped, nanosecond_timestamps })
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        let value = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        if self.swapped {
+            value.swap_bytes()
+        } else {
+            value
+        }
+    }
+
+    /// Where the IP packet starts in the frame, if the frame holds one at all
+    fn ip_offset(&self, frame: &[u8]) -> Option<usize> {
+        let ether_type_at = |offset: usize| frame.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+        let is_ip = |ether_type: u16| ether_type == ETHER_TYPE_IPV4 || ether_type == ETHER_TYPE_IPV6;
+
+        match self.link_type {
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(0),
+            // the address family, in the byte order of the capturing host
+            LINKTYPE_NULL => Some(4),
+            LINKTYPE_ETHERNET => match ether_type_at(12)? {
+                ETHER_TYPE_VLAN => is_ip(ether_type_at(16)?).then_some(18),
+                ether_type => is_ip(ether_type).then_some(14),
+            },
+            LINKTYPE_LINUX_SLL => is_ip(ether_type_at(14)?).then_some(16),
+            LINKTYPE_LINUX_SLL2 => is_ip(ether_type_at(0)?).then_some(20),
+            _ => None,
+        }
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = anyhow::Result<CapturedPacket>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut record_header = [0; 16];
+            match self.reader.read_exact(&mut record_header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+                Err(e) => return Some(Err(e).context("Unable to read pcap file.")),
+            }
+
+            let seconds = self.read_u32(&record_header[..4]) as u64;
+            let fraction = self.read_u32(&record_header[4..8]);
+            let captured_length = self.read_u32(&record_header[8..12]) as usize;
+
+            let mut frame = vec![0; captured_length];
+            if let Err(e) = self.reader.read_exact(&mut frame) {
+                return Some(Err(e).context("The pcap file ends partway through a packet."));
+            }
+
+            let timestamp = match self.nanosecond_timestamps {
+                true => Duration::new(seconds, fraction),
+                false => Duration::new(seconds, 0) + Duration::from_micros(fraction as u64),
+            };
+
+            if let Some(offset) = self.ip_offset(&frame) {
+                frame.drain(..offset);
+
+                return Some(Ok(CapturedPacket { timestamp, ip_packet: frame }));
+            }
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::{io, iter, mem, net};
+
+use anyhow::Context;
+use itertools::Either;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::transport::{
+    transport_channel, TransportChannelType,
+    TransportProtocol, TransportReceiver, TransportSender,
+};
+use pnet_sys::{BufLen, MutBuf, SockAddr, SockLen};
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+use crate::armada::mmsg::{ReceiveBatch, SendBatch};
+use crate::armada::packet::{
+    build_ip_packet, build_ipv4_fragment, fragment_parts, IPV6_HEADER_LENGTH, MAX_TCP_PROBE_LENGTH, PROBE_TTL,
+};
+#[cfg(target_os = "linux")]
+use crate::armada::socket_filter::attach_tcp_filter;
+use crate::armada::tcp_ext::TcpSenderExt;
+use crate::armada::transport::{Channel, Transport};
+
+/// Room left in each receive buffer per probe of a batch. A probe can be met by several packets (the response, an
+/// RST from our own stack, and on loopback the probe itself), and a whole batch of them can land between reads.
+const RECEIVE_BUFFER_PER_PROBE: usize = 4096;
+/// Room for any probe along with the IP header we write for it
+const MAX_IP_PACKET_LENGTH: usize = IPV6_HEADER_LENGTH + MAX_TCP_PROBE_LENGTH;
+
+/// The raw sockets probes are sent from and responses are received on
+pub(crate) struct TransportChannels {
+    pub(crate) ipv4_tcp_sender: TransportSender,
+    ipv4_tcp_receiver: Receiver,
+    pub(crate) ipv6_tcp_sender: TransportSender,
+    ipv6_tcp_receiver: Receiver,
+    pub(crate) ipv4_sctp_sender: TransportSender,
+    ipv4_sctp_receiver: Receiver,
+    pub(crate) ipv6_sctp_sender: TransportSender,
+    ipv6_sctp_receiver: Receiver,
+    pub(crate) ipv4_icmp_sender: TransportSender,
+    ipv4_icmp_receiver: Receiver,
+    pub(crate) ipv6_icmp_sender: TransportSender,
+    ipv6_icmp_receiver: Receiver,
+    /// Probes waiting to be sent in one go per channel, when sending in batches
+    send_batches: [Option<SendBatch>; Channel::ALL.len()],
+    /// Probes that went out one at a time since the last flush
+    sent_addrs: Vec<SocketAddr>,
+    /// Send IP packets we write the header of ourselves, which fragmented probes and decoys go out through
+    ipv4_header_socket: OwnedFd,
+    ipv6_header_socket: OwnedFd,
+    /// When set, IPv4 probes are split into fragments carrying up to this many bytes of them each
+    fragment_size: Option<usize>,
+    /// The TTL of the IP headers we write ourselves
+    ttl: u8,
+    identification: u16,
+    ip_buffer: [u8; MAX_IP_PACKET_LENGTH],
+}
+
+/// A raw socket responses are received on, along with the responses read off of it ahead of time when receiving in
+/// batches
+struct Receiver {
+    transport: TransportReceiver,
+    batch: Option<ReceiveBatch>,
+}
+
+impl TransportChannels {
+    /// Opens a raw socket per channel. On Linux, the TCP ones are filtered down to responses to the listening port in
+    /// the kernel, which falls back to filtering them here if the filter can't be attached. Given an interface, every
+    /// socket is bound to it, so probes go out on it whatever the routing table says and only its packets are
+    /// received.
+    pub(crate) fn open(listening_port: u16, interface: Option<&str>) -> anyhow::Result<Self> {
+        let (ipv4_tcp_sender, ipv4_tcp_receiver) =
+            open_channel(TransportProtocol::Ipv4(IpNextHeaderProtocols::Tcp))?;
+        let (ipv6_tcp_sender, ipv6_tcp_receiver) =
+            open_channel(TransportProtocol::Ipv6(IpNextHeaderProtocols::Tcp))?;
+        let (ipv4_sctp_sender, ipv4_sctp_receiver) =
+            open_channel(TransportProtocol::Ipv4(IpNextHeaderProtocols::Sctp))?;
+        let (ipv6_sctp_sender, ipv6_sctp_receiver) =
+            open_channel(TransportProtocol::Ipv6(IpNextHeaderProtocols::Sctp))?;
+        let (ipv4_icmp_sender, ipv4_icmp_receiver) =
+            open_channel(TransportProtocol::Ipv4(IpNextHeaderProtocols::Icmp))?;
+        let (ipv6_icmp_sender, ipv6_icmp_receiver) =
+            open_channel(TransportProtocol::Ipv6(IpNextHeaderProtocols::Icmpv6))?;
+        let ipv4_header_socket = open_header_socket(libc::AF_INET)?;
+        let ipv6_header_socket = open_header_socket(libc::AF_INET6)?;
+
+        if let Some(interface) = interface {
+            for sender in [
+                &ipv4_tcp_sender,
+                &ipv6_tcp_sender,
+                &ipv4_sctp_sender,
+                &ipv6_sctp_sender,
+                &ipv4_icmp_sender,
+                &ipv6_icmp_sender,
+            ] {
+                bind_to_interface(sender.socket.fd, interface)?;
+            }
+            bind_to_interface(ipv4_header_socket.as_raw_fd(), interface)?;
+            bind_to_interface(ipv6_header_socket.as_raw_fd(), interface)?;
+        }
+
+        #[cfg(target_os = "linux")]
+        for (fd, ipv6) in [(ipv4_tcp_sender.socket.fd, false), (ipv6_tcp_sender.socket.fd, true)] {
+            if let Err(e) = attach_tcp_filter(fd, listening_port, ipv6) {
+                warn!(err = ?e, "unable to filter the raw TCP socket in the kernel, filtering responses in armada instead");
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = listening_port;
+
+        Ok(Self {
+            ipv4_tcp_sender,
+            ipv4_tcp_receiver,
+            ipv6_tcp_sender,
+            ipv6_tcp_receiver,
+            ipv4_sctp_sender,
+            ipv4_sctp_receiver,
+            ipv6_sctp_sender,
+            ipv6_sctp_receiver,
+            ipv4_icmp_sender,
+            ipv4_icmp_receiver,
+            ipv6_icmp_sender,
+            ipv6_icmp_receiver,
+            send_batches: Default::default(),
+            sent_addrs: Vec::new(),
+            ipv4_header_socket,
+            ipv6_header_socket,
+            fragment_size: None,
+            ttl: PROBE_TTL,
+            identification: rand::random(),
+            ip_buffer: [0; MAX_IP_PACKET_LENGTH],
+        })
+    }
+
+    fn sender(&mut self, channel: Channel) -> &mut TransportSender {
+        match channel {
+            Channel::Ipv4Tcp => &mut self.ipv4_tcp_sender,
+            Channel::Ipv6Tcp => &mut self.ipv6_tcp_sender,
+            Channel::Ipv4Sctp => &mut self.ipv4_sctp_sender,
+            Channel::Ipv6Sctp => &mut self.ipv6_sctp_sender,
+            Channel::Ipv4Icmp => &mut self.ipv4_icmp_sender,
+            Channel::Ipv6Icmp => &mut self.ipv6_icmp_sender,
+        }
+    }
+
+    /// The raw socket behind the channel, which probes go out on and responses come in on alike
+    pub(crate) fn fd(&self, channel: Channel) -> libc::c_int {
+        let sender = match channel {
+            Channel::Ipv4Tcp => &self.ipv4_tcp_sender,
+            Channel::Ipv6Tcp => &self.ipv6_tcp_sender,
+            Channel::Ipv4Sctp => &self.ipv4_sctp_sender,
+            Channel::Ipv6Sctp => &self.ipv6_sctp_sender,
+            Channel::Ipv4Icmp => &self.ipv4_icmp_sender,
+            Channel::Ipv6Icmp => &self.ipv6_icmp_sender,
+        };
+
+        sender.socket.fd
+    }
+
+    fn receiver(&mut self, channel: Channel) -> &mut Receiver {
+        match channel {
+            Channel::Ipv4Tcp => &mut self.ipv4_tcp_receiver,
+            Channel::Ipv6Tcp => &mut self.ipv6_tcp_receiver,
+            Channel::Ipv4Sctp => &mut self.ipv4_sctp_receiver,
+            Channel::Ipv6Sctp => &mut self.ipv6_sctp_receiver,
+            Channel::Ipv4Icmp => &mut self.ipv4_icmp_receiver,
+            Channel::Ipv6Icmp => &mut self.ipv6_icmp_receiver,
+        }
+    }
+
+    /// Sends the probe from the source through the sockets we write the IP header for ourselves, in fragments when
+    /// IPv4 probes are being fragmented. Returns false if not even the first packet could go out right now. The probe
+    /// is as good as lost when a later fragment can't, which its retries make up for.
+    fn send_with_ip_header(&mut self, channel: Channel, source: IpAddr, destination: IpAddr, packet: &[u8]) -> bool {
+        let mut socket_addr_storage: pnet_sys::SockAddrStorage = unsafe { mem::zeroed() };
+        let socket_len = pnet_sys::addr_to_sockaddr(net::SocketAddr::new(destination, 0), &mut socket_addr_storage);
+        self.identification = self.identification.wrapping_add(1);
+
+        let (fd, parts) = match (source, destination, self.fragment_size) {
+            (IpAddr::V4(_), IpAddr::V4(_), Some(fragment_size)) => {
+                (self.ipv4_header_socket.as_raw_fd(), Either::Left(fragment_parts(packet.len(), fragment_size)))
+            }
+            (IpAddr::V4(_), IpAddr::V4(_), None) => {
+                (self.ipv4_header_socket.as_raw_fd(), Either::Right(iter::once(0..packet.len())))
+            }
+            (IpAddr::V6(_), IpAddr::V6(_), _) => {
+                (self.ipv6_header_socket.as_raw_fd(), Either::Right(iter::once(0..packet.len())))
+            }
+            _ => return false,
+        };
+
+        for (index, part) in parts.enumerate() {
+            let ip_length = match (source, destination) {
+                (IpAddr::V4(source), IpAddr::V4(destination)) => build_ipv4_fragment(
+                    &mut self.ip_buffer,
+                    channel.protocol(),
+                    source,
+                    destination,
+                    packet,
+                    part,
+                    self.identification,
+                    self.ttl,
+                ),
+                (source, destination) => build_ip_packet(
+                    &mut self.ip_buffer,
+                    channel.protocol(),
+                    source,
+                    destination,
+                    packet,
+                    self.identification,
+                    self.ttl,
+                ),
+            };
+            let ip_length = match ip_length {
+                Some(ip_length) => ip_length,
+                None => return false,
+            };
+
+            let sent = unsafe {
+                libc::sendto(
+                    fd,
+                    self.ip_buffer.as_ptr() as *const libc::c_void,
+                    ip_length as BufLen,
+                    libc::MSG_DONTWAIT,
+                    (&socket_addr_storage as *const pnet_sys::SockAddrStorage) as *const SockAddr,
+                    socket_len,
+                )
+            };
+
+            if sent < 0 && index == 0 {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl Transport for TransportChannels {
+    /// Hands the kernel a whole batch in one system call when batched IO is on, and one packet per call otherwise
+    fn set_batching(&mut self, batch_size: usize, batched_io: bool) {
+        let batch = batched_io.then_some(batch_size);
+
+        self.send_batches = Channel::ALL.map(|_| batch.map(SendBatch::new));
+
+        for channel in Channel::ALL {
+            let receiver = self.receiver(channel);
+
+            receiver.batch = batch.map(ReceiveBatch::new);
+            grow_receive_buffer(&receiver.transport, batch_size * RECEIVE_BUFFER_PER_PROBE);
+        }
+    }
+
+    /// Only the TCP and SCTP sockets carry probes, so the ICMP ones are left alone
+    fn set_ttl(&mut self, ttl: Option<u8>) -> anyhow::Result<()> {
+        self.ttl = ttl.unwrap_or(PROBE_TTL);
+        // -1 has the kernel go back to its default
+        let ttl = ttl.map_or(-1, libc::c_int::from);
+
+        for channel in [Channel::Ipv4Tcp, Channel::Ipv6Tcp, Channel::Ipv4Sctp, Channel::Ipv6Sctp] {
+            let (level, option) = match channel.is_ipv6() {
+                false => (libc::IPPROTO_IP, libc::IP_TTL),
+                true => (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS),
+            };
+
+            let result = unsafe {
+                libc::setsockopt(
+                    self.fd(channel),
+                    level,
+                    option,
+                    &ttl as *const libc::c_int as *const libc::c_void,
+                    mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+
+            if result != 0 {
+                anyhow::bail!("Unable to set the TTL of the {:?} socket: {}", channel, io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_fragmentation(&mut self, fragment_size: Option<usize>) -> anyhow::Result<()> {
+        self.fragment_size = fragment_size;
+
+        Ok(())
+    }
+
+    fn queue(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        if self.fragment_size.is_some() && remote.is_ipv4() {
+            return match self.send_with_ip_header(channel, source, remote.ip(), packet) {
+                true => {
+                    self.sent_addrs.push(remote);
+                    true
+                }
+                false => false,
+            };
+        }
+
+        // batched probes all go out together once the batch is flushed
+        if let Some(batch) = &mut self.send_batches[channel.index()] {
+            return batch.push(packet, source, remote);
+        }
+
+        match self.sender(channel).try_send_to(packet, source, remote.ip()) {
+            Ok(Some(_)) => {
+                self.sent_addrs.push(remote);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn send_decoy(&mut self, channel: Channel, decoy: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        self.send_with_ip_header(channel, decoy, remote.ip(), packet)
+    }
+
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        let mut flushed = (mem::take(&mut self.sent_addrs), Vec::new());
+
+        for (channel, batch) in Channel::ALL.into_iter().zip(&mut self.send_batches) {
+            let batch = match batch {
+                Some(batch) if !batch.is_empty() => batch,
+                _ => continue,
+            };
+
+            let (sent, unsent) = batch.flush(match channel {
+                Channel::Ipv4Tcp => &self.ipv4_tcp_sender,
+                Channel::Ipv6Tcp => &self.ipv6_tcp_sender,
+                Channel::Ipv4Sctp => &self.ipv4_sctp_sender,
+                Channel::Ipv6Sctp => &self.ipv6_sctp_sender,
+                Channel::Ipv4Icmp => &self.ipv4_icmp_sender,
+                Channel::Ipv6Icmp => &self.ipv6_icmp_sender,
+            });
+
+            flushed.0.extend(sent);
+            flushed.1.extend(unsent);
+        }
+
+        flushed
+    }
+
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>> {
+        let Receiver { transport, batch } = self.receiver(channel);
+
+        match batch {
+            Some(batch) => batch.try_recv(transport),
+            None => Ok(try_recv_from(transport)?.map(|(recv_len, ip)| (&transport.buffer[..recv_len], ip))),
+        }
+    }
+}
+
+/// Opens a raw socket of the family that sends IP packets whole, header and all. IPPROTO_RAW sockets only ever send.
+fn open_header_socket(family: libc::c_int) -> anyhow::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(family, libc::SOCK_RAW, libc::IPPROTO_RAW) };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("Error on raw socket initialization");
+    }
+
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn open_channel(protocol: TransportProtocol) -> anyhow::Result<(TransportSender, Receiver)> {
+    // todo: increase buffer size
+    let (sender, receiver) = transport_channel(1024 * 16, TransportChannelType::Layer4(protocol))
+        .context("Error on raw socket initialization")?;
+
+    Ok((sender, Receiver { transport: receiver, batch: None }))
+}
+
+/// Has the raw socket send and receive on the interface alone
+#[cfg(target_os = "linux")]
+fn bind_to_interface(fd: libc::c_int, interface: &str) -> anyhow::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        anyhow::bail!("Unable to bind a raw socket to interface {}: {}", interface, io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_interface(_fd: libc::c_int, _interface: &str) -> anyhow::Result<()> {
+    anyhow::bail!("Binding to an interface is only supported on Linux.")
+}
+
+/// Receives the next datagram off of the socket without blocking, returning its length and sender
+fn try_recv_from(receiver: &mut TransportReceiver) -> io::Result<Option<(usize, IpAddr)>> {
+    let buffer = &mut receiver.buffer;
+    let mut socket_addr_storage: pnet_sys::SockAddrStorage = unsafe { mem::zeroed() };
+    let mut caddrlen = mem::size_of::<pnet_sys::SockAddrStorage>() as SockLen;
+
+    // this is safe as we're not moving or deallocating the memory while in use
+    let recv_len = unsafe {
+        match libc::recvfrom(
+            receiver.socket.fd,
+            buffer.as_ptr() as MutBuf,
+            buffer.len() as BufLen,
+            libc::MSG_DONTWAIT, // nonblocking so we just try to get the next message, fail otherwise
+            (&mut socket_addr_storage as *mut pnet_sys::SockAddrStorage) as *mut SockAddr,
+            &mut caddrlen,
+        ) {
+            // -1 == Would block! We don't have a packet immediately available so let's return None
+            -1 => return Ok(None),
+            len if len < 0 => Err(io::Error::last_os_error()),
+            len => Ok(len as usize),
+        }?
+    };
+
+    let addr = pnet_sys::sockaddr_to_addr(
+        &socket_addr_storage,
+        mem::size_of::<pnet_sys::SockAddrStorage>(),
+    )?;
+    let ip = match addr {
+        net::SocketAddr::V4(sa) => IpAddr::V4(*sa.ip()),
+        net::SocketAddr::V6(sa) => IpAddr::V6(*sa.ip()),
+    };
+
+    Ok(Some((recv_len, ip)))
+}
+
+/// Makes sure the socket's receive buffer holds at least this many bytes, so responses to a large batch aren't dropped
+/// before they're read. The kernel caps the size at `net.core.rmem_max`.
+fn grow_receive_buffer(receiver: &TransportReceiver, size: usize) {
+    let mut current_size: libc::c_int = 0;
+    let mut option_length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    unsafe {
+        let result = libc::getsockopt(
+            receiver.socket.fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &mut current_size as *mut libc::c_int as *mut libc::c_void,
+            &mut option_length,
+        );
+
+        if result == 0 && size > current_size as usize {
+            let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+
+            libc::setsockopt(
+                receiver.socket.fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                &size as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            );
+        }
+    }
+}
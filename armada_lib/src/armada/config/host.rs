@@ -1,5 +1,5 @@
 use cidr_utils::cidr::{IpCidr, IpCidrIpAddrIterator, Ipv4Cidr, Ipv6Cidr};
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 const IPV4_BITMASK: u8 = 32;
 const IPV6_BITMASK: u8 = 128;
@@ -22,6 +22,10 @@ impl Clone for HostIterator {
     }
 }
 
+impl Default for HostIterator {
+    fn default() -> Self { Self::new() }
+}
+
 impl HostIterator {
     pub fn new() -> Self {
         Self {
@@ -38,19 +42,37 @@ impl HostIterator {
             .fold(0, |acc, cidr| acc + cidr.size().to_u128().expect("Cidr range is too large to report back a size. Crashing here is in your best interest."))
     }
 
-    pub fn add_ip(mut self, addr: IpAddr) -> Self {
-        let ip_cidr = match addr {
-            IpAddr::V4(ipv4_addr) => IpCidr::V4(
-                Ipv4Cidr::from_prefix_and_bits(ipv4_addr, IPV4_BITMASK)
-                    .expect("Failed to convert to IPv4 CIDR"),
-            ),
-            IpAddr::V6(ipv6_addr) => IpCidr::V6(
-                Ipv6Cidr::from_prefix_and_bits(ipv6_addr, IPV6_BITMASK)
-                    .expect("Failed to convert to IPv6 CIDR"),
-            ),
-        };
+    /// The ranges the targets are made up of, with exclusions already taken out, in the order they're scanned in
+    pub fn ranges(&self) -> &[IpCidr] {
+        &self.inner
+    }
+
+    /// How many of the targets are IPv4 addresses and how many are IPv6 addresses
+    pub fn family_sizes(&self) -> (u128, u128) {
+        use num_traits::ToPrimitive;
 
-        self.inner.push(ip_cidr);
+        self.inner.iter().fold((0, 0), |(ipv4_size, ipv6_size), cidr| {
+            let size = cidr.size().to_u128().expect("Cidr range is too large to report back a size.");
+
+            match cidr {
+                IpCidr::V4(_) => (ipv4_size + size, ipv6_size),
+                IpCidr::V6(_) => (ipv4_size, ipv6_size + size),
+            }
+        })
+    }
+
+    /// Whether any of the targets are IPv4 addresses
+    pub fn has_ipv4(&self) -> bool {
+        self.inner.iter().any(|cidr| matches!(cidr, IpCidr::V4(_)))
+    }
+
+    /// Whether any of the targets are IPv6 addresses
+    pub fn has_ipv6(&self) -> bool {
+        self.inner.iter().any(|cidr| matches!(cidr, IpCidr::V6(_)))
+    }
+
+    pub fn add_ip(mut self, addr: IpAddr) -> Self {
+        self.inner.push(single_host_cidr(addr));
 
         self
     }
@@ -68,11 +90,68 @@ impl HostIterator {
         self
     }
 
+    /// Removes an IP from the targets added so far
+    pub fn exclude_ip(self, addr: IpAddr) -> Self {
+        self.exclude_cidr(single_host_cidr(addr))
+    }
+
+    /// Removes a range from the targets added so far. The ranges it overlaps are split around it rather than filtered
+    /// while iterating, so excluded addresses are never generated and don't count towards `size`.
+    pub fn exclude_cidr(mut self, range: IpCidr) -> Self {
+        self.inner = self
+            .inner
+            .into_iter()
+            .flat_map(|cidr| subtract_cidr(cidr, &range))
+            .collect();
+        self.reset();
+
+        self
+    }
+
+    /// Keeps only the targets within the ranges. Whatever lies outside of all of them is split off the ranges the same
+    /// way `exclude_cidr` does.
+    pub fn retain_within(mut self, ranges: &[IpCidr]) -> Self {
+        let subtract_all = |cidrs: Vec<IpCidr>, excluded: &[IpCidr]| {
+            excluded.iter().fold(cidrs, |cidrs, excluded| {
+                cidrs.into_iter().flat_map(|cidr| subtract_cidr(cidr, excluded)).collect()
+            })
+        };
+
+        self.inner = self
+            .inner
+            .into_iter()
+            .flat_map(|cidr| {
+                let outside = subtract_all(vec![cidr], ranges);
+                subtract_all(vec![cidr], &outside)
+            })
+            .collect();
+        self.reset();
+
+        self
+    }
+
     pub fn reset(&mut self) {
         self.current_cidr_idx = None;
         self.current_cidr_iterator = None;
     }
 
+    /// Looks targets up by their position in iteration order, for scanning them in another order
+    pub(crate) fn index(&self) -> HostIndex {
+        let mut next_start = 0u128;
+        let starts = self
+            .inner
+            .iter()
+            .map(|cidr| {
+                let start = next_start;
+                next_start += cidr_size(cidr);
+
+                start
+            })
+            .collect();
+
+        HostIndex { starts, cidrs: self.inner.clone() }
+    }
+
     fn rotate_iterator(&mut self) {
         match &mut self.current_cidr_idx {
             Some(idx) if *idx < self.inner.len() => *idx += 1,
@@ -108,3 +187,227 @@ impl Iterator for HostIterator {
         }
     }
 }
+
+/// The targets of a `HostIterator` by position, without having to iterate over the ones before
+#[derive(Debug)]
+pub(crate) struct HostIndex {
+    /// The position of the first address of each CIDR
+    starts: Vec<u128>,
+    cidrs: Vec<IpCidr>,
+}
+
+impl HostIndex {
+    pub(crate) fn get(&self, index: u128) -> Option<IpAddr> {
+        let cidr_idx = self.starts.partition_point(|start| *start <= index).checked_sub(1)?;
+        let offset = index - self.starts[cidr_idx];
+
+        match &self.cidrs[cidr_idx] {
+            IpCidr::V4(cidr) if offset < cidr.size() as u128 => Some(IpAddr::V4(Ipv4Addr::from(cidr.first() + offset as u32))),
+            IpCidr::V6(cidr) if offset < ipv6_cidr_size(cidr) => Some(IpAddr::V6(Ipv6Addr::from(cidr.first() + offset))),
+            _ => None,
+        }
+    }
+}
+
+fn cidr_size(cidr: &IpCidr) -> u128 {
+    match cidr {
+        IpCidr::V4(cidr) => cidr.size() as u128,
+        IpCidr::V6(cidr) => ipv6_cidr_size(cidr),
+    }
+}
+
+fn ipv6_cidr_size(cidr: &Ipv6Cidr) -> u128 {
+    1u128
+        .checked_shl((IPV6_BITMASK - cidr.get_bits()) as u32)
+        .expect("Cidr range is too large to report back a size. Crashing here is in your best interest.")
+}
+
+fn single_host_cidr(addr: IpAddr) -> IpCidr {
+    match addr {
+        IpAddr::V4(ipv4_addr) => IpCidr::V4(
+            Ipv4Cidr::from_prefix_and_bits(ipv4_addr, IPV4_BITMASK)
+                .expect("Failed to convert to IPv4 CIDR"),
+        ),
+        IpAddr::V6(ipv6_addr) => IpCidr::V6(
+            Ipv6Cidr::from_prefix_and_bits(ipv6_addr, IPV6_BITMASK)
+                .expect("Failed to convert to IPv6 CIDR"),
+        ),
+    }
+}
+
+/// Returns the CIDRs covering whatever is left of `cidr` once `excluded` is taken out of it
+fn subtract_cidr(cidr: IpCidr, excluded: &IpCidr) -> Vec<IpCidr> {
+    match (cidr, excluded) {
+        (IpCidr::V4(cidr), IpCidr::V4(excluded)) => subtract(cidr, excluded).into_iter().map(IpCidr::V4).collect(),
+        (IpCidr::V6(cidr), IpCidr::V6(excluded)) => subtract(cidr, excluded).into_iter().map(IpCidr::V6).collect(),
+        (cidr, _) => vec![cidr],
+    }
+}
+
+/// What subtracting needs from a CIDR of either family, with its prefix widened to fit both
+trait Subnet: Sized {
+    const ADDRESS_BITS: u8;
+
+    fn bits(&self) -> u8;
+    fn prefix(&self) -> u128;
+    fn contains_prefix(&self, prefix: u128) -> bool;
+    fn from_prefix(prefix: u128, bits: u8) -> Self;
+}
+
+impl Subnet for Ipv4Cidr {
+    const ADDRESS_BITS: u8 = IPV4_BITMASK;
+
+    fn bits(&self) -> u8 { self.get_bits() }
+
+    fn prefix(&self) -> u128 { self.first() as u128 }
+
+    fn contains_prefix(&self, prefix: u128) -> bool { self.contains(prefix as u32) }
+
+    fn from_prefix(prefix: u128, bits: u8) -> Self {
+        Ipv4Cidr::from_prefix_and_bits(prefix as u32, bits).expect("Failed to convert to IPv4 CIDR")
+    }
+}
+
+impl Subnet for Ipv6Cidr {
+    const ADDRESS_BITS: u8 = IPV6_BITMASK;
+
+    fn bits(&self) -> u8 { self.get_bits() }
+
+    fn prefix(&self) -> u128 { self.first() }
+
+    fn contains_prefix(&self, prefix: u128) -> bool { self.contains(prefix) }
+
+    fn from_prefix(prefix: u128, bits: u8) -> Self {
+        Ipv6Cidr::from_prefix_and_bits(prefix, bits).expect("Failed to convert to IPv6 CIDR")
+    }
+}
+
+fn subtract<C: Subnet>(cidr: C, excluded: &C) -> Vec<C> {
+    // two CIDRs either nest or don't overlap at all
+    if excluded.bits() <= cidr.bits() {
+        return if excluded.contains_prefix(cidr.prefix()) { Vec::new() } else { vec![cidr] };
+    }
+
+    if !cidr.contains_prefix(excluded.prefix()) {
+        return vec![cidr];
+    }
+
+    // halve the range until we reach the excluded one, keeping every half it isn't in
+    (cidr.bits() + 1..=excluded.bits())
+        .map(|bits| {
+            let sibling_prefix = excluded.prefix() ^ (1 << (C::ADDRESS_BITS - bits));
+
+            C::from_prefix(sibling_prefix, bits)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn cidr(cidr: &str) -> IpCidr {
+        IpCidr::from_str(cidr).unwrap()
+    }
+
+    fn cidrs(cidrs: &[&str]) -> Vec<IpCidr> {
+        cidrs.iter().map(|range| cidr(range)).collect()
+    }
+
+    fn addresses(cidrs: &[IpCidr]) -> Vec<IpAddr> {
+        let mut addresses: Vec<_> = cidrs.iter().flat_map(|cidr| cidr.iter()).collect();
+        addresses.sort_unstable();
+
+        addresses
+    }
+
+    #[test]
+    fn subtracting_a_range_inside_splits_around_it() {
+        assert_eq!(
+            subtract_cidr(cidr("10.0.0.0/24"), &cidr("10.0.0.64/26")),
+            cidrs(&["10.0.0.128/25", "10.0.0.0/26"]),
+        );
+        assert_eq!(
+            subtract_cidr(cidr("10.0.0.0/30"), &cidr("10.0.0.2/32")),
+            cidrs(&["10.0.0.0/31", "10.0.0.3/32"]),
+        );
+        assert_eq!(
+            subtract_cidr(cidr("2001:db8::/126"), &cidr("2001:db8::1/128")),
+            cidrs(&["2001:db8::2/127", "2001:db8::/128"]),
+        );
+    }
+
+    #[test]
+    fn subtracting_leaves_every_other_address() {
+        let range = cidr("192.168.0.0/22");
+        let excluded = cidr("192.168.2.17/32");
+
+        let expected: Vec<_> = range.iter().filter(|addr| !excluded.contains(*addr)).collect();
+        assert_eq!(addresses(&subtract_cidr(range, &excluded)), expected);
+    }
+
+    #[test]
+    fn subtracting_a_range_around_or_apart_from_it() {
+        assert_eq!(subtract_cidr(cidr("10.0.0.0/24"), &cidr("10.0.0.0/16")), Vec::<IpCidr>::new());
+        assert_eq!(subtract_cidr(cidr("10.0.0.0/24"), &cidr("10.0.0.0/24")), Vec::<IpCidr>::new());
+        assert_eq!(subtract_cidr(cidr("10.0.0.0/24"), &cidr("10.0.1.0/24")), cidrs(&["10.0.0.0/24"]));
+        assert_eq!(subtract_cidr(cidr("10.0.0.0/24"), &cidr("10.1.0.0/16")), cidrs(&["10.0.0.0/24"]));
+        assert_eq!(subtract_cidr(cidr("10.0.0.0/24"), &cidr("::/0")), cidrs(&["10.0.0.0/24"]));
+    }
+
+    #[test]
+    fn excluded_addresses_are_neither_generated_nor_counted() {
+        let hosts = HostIterator::new()
+            .add_cidr(cidr("10.0.0.0/29"))
+            .exclude_ip(IpAddr::from_str("10.0.0.3").unwrap())
+            .exclude_cidr(cidr("10.0.0.6/31"));
+
+        assert_eq!(hosts.size(), 5);
+        let mut generated: Vec<_> = hosts.collect();
+        generated.sort_unstable();
+        assert_eq!(generated, ["10.0.0.0", "10.0.0.1", "10.0.0.2", "10.0.0.4", "10.0.0.5"].map(|addr| IpAddr::from_str(addr).unwrap()));
+    }
+
+    #[test]
+    fn retaining_keeps_only_what_lies_within_the_ranges() {
+        let hosts = HostIterator::new()
+            .add_cidr(cidr("10.0.0.0/24"))
+            .add_cidr(cidr("10.0.1.0/30"))
+            .add_cidr(cidr("2001:db8::/120"))
+            .retain_within(&cidrs(&["10.0.0.128/25", "10.0.0.4/30", "10.0.1.1/32", "172.16.0.0/12"]));
+
+        assert_eq!(addresses(hosts.ranges()), addresses(&cidrs(&["10.0.0.4/30", "10.0.0.128/25", "10.0.1.1/32"])));
+        assert_eq!(hosts.size(), 4 + 128 + 1);
+    }
+
+    #[test]
+    fn retaining_within_wider_ranges_keeps_everything() {
+        let hosts = HostIterator::new()
+            .add_cidr(cidr("10.0.0.0/24"))
+            .retain_within(&cidrs(&["10.0.0.0/8"]));
+
+        assert_eq!(hosts.ranges(), cidrs(&["10.0.0.0/24"]).as_slice());
+    }
+
+    #[test]
+    fn retaining_within_nothing_keeps_nothing() {
+        let hosts = HostIterator::new().add_cidr(cidr("10.0.0.0/24")).retain_within(&[]);
+
+        assert_eq!(hosts.size(), 0);
+    }
+
+    #[test]
+    fn the_index_follows_iteration_order() {
+        let hosts = HostIterator::new()
+            .add_cidr(cidr("10.0.0.0/30"))
+            .add_ip(IpAddr::from_str("2001:db8::1").unwrap())
+            .add_cidr(cidr("192.168.0.8/31"));
+        let index = hosts.index();
+
+        let by_index: Vec<_> = (0..hosts.size()).map(|position| index.get(position).unwrap()).collect();
+        assert_eq!(by_index, hosts.clone().collect::<Vec<_>>());
+        assert_eq!(index.get(hosts.size()), None);
+    }
+}
@@ -1,2 +1,4 @@
 pub mod host;
 pub mod port;
+pub mod route;
+pub mod scan;
@@ -4,6 +4,10 @@ pub struct PortIterator {
     idx: Option<usize>,
 }
 
+impl Default for PortIterator {
+    fn default() -> Self { Self::new() }
+}
+
 impl PortIterator {
     pub fn new() -> Self {
         Self {
@@ -17,24 +21,65 @@ impl PortIterator {
             .fold(0, |acc, port_region| acc + port_region.size() as u64)
     }
 
-    pub fn add_port(mut self, port: u16) -> Self {
-        self.inner.push(PortRegion {
-            start: port,
-            end: port,
-            idx: None,
-        });
-        self
+    pub fn add_port(self, port: u16) -> Self {
+        self.add_range(port, port)
     }
 
+    /// Adds an inclusive range, leaving out the ports already in the list so none of them is counted or probed twice.
+    /// Ports keep the position they were first added at, which for the top ports is from most to least common, and a
+    /// range that carries on from the last region is merged into it.
     pub fn add_range(mut self, start: u16, end: u16) -> Self {
-        self.inner.push(PortRegion {
-            start,
-            end,
-            idx: None,
+        let new_regions = self.inner.iter().fold(vec![PortRegion { start, end, idx: None }], |new_regions, region| {
+            new_regions
+                .into_iter()
+                .flat_map(|new_region| new_region.subtract(region.start, region.end))
+                .collect()
         });
+
+        for new_region in new_regions {
+            match self.inner.last_mut() {
+                Some(last) if last.end.checked_add(1) == Some(new_region.start) => last.end = new_region.end,
+                _ => self.inner.push(new_region),
+            }
+        }
+        self.idx = None;
+
         self
     }
 
+    pub fn exclude_port(self, port: u16) -> Self {
+        self.exclude_range(port, port)
+    }
+
+    /// Removes an inclusive range from the ports added so far, splitting any region it falls in the middle of
+    pub fn exclude_range(mut self, start: u16, end: u16) -> Self {
+        self.inner = self
+            .inner
+            .into_iter()
+            .flat_map(|port_region| port_region.subtract(start, end))
+            .collect();
+        self.idx = None;
+
+        self
+    }
+
+    /// Looks ports up by their position in iteration order, for scanning them in another order
+    pub(crate) fn index(&self) -> PortIndex {
+        let mut next_start = 0u64;
+        let regions = self
+            .inner
+            .iter()
+            .map(|port_region| {
+                let start = next_start;
+                next_start += port_region.size() as u64;
+
+                (start, port_region.start, port_region.end)
+            })
+            .collect();
+
+        PortIndex { regions }
+    }
+
     #[allow(dead_code)]
     fn reset(&mut self) {
         self.inner.iter_mut().for_each(|inner| inner.reset());
@@ -42,6 +87,23 @@ impl PortIterator {
     }
 }
 
+/// The ports of a `PortIterator` by position, without having to iterate over the ones before
+#[derive(Debug)]
+pub(crate) struct PortIndex {
+    /// The position of the first port of each region along with the region's first and last port
+    regions: Vec<(u64, u16, u16)>,
+}
+
+impl PortIndex {
+    pub(crate) fn get(&self, index: u64) -> Option<u16> {
+        let region_idx = self.regions.partition_point(|(start, _, _)| *start <= index).checked_sub(1)?;
+        let (start, first_port, last_port) = self.regions[region_idx];
+        let port = first_port as u64 + (index - start);
+
+        (port <= last_port as u64).then_some(port as u16)
+    }
+}
+
 #[derive(Clone, Debug)]
 struct PortRegion {
     start: u16,
@@ -56,6 +118,26 @@ impl PortRegion {
         let _ = idx.take();
     }
 
+    /// Returns whatever is left of this region once the inclusive range is taken out of it
+    fn subtract(self, start: u16, end: u16) -> Vec<PortRegion> {
+        if end < self.start || start > self.end {
+            return vec![self];
+        }
+
+        let below = (start > self.start).then(|| PortRegion {
+            start: self.start,
+            end: start - 1,
+            idx: None,
+        });
+        let above = (end < self.end).then(|| PortRegion {
+            start: end + 1,
+            end: self.end,
+            idx: None,
+        });
+
+        below.into_iter().chain(above).collect()
+    }
+
     fn size(&self) -> u16 {
         self.end - self.start + 1
     }
@@ -103,3 +185,59 @@ impl Iterator for PortIterator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_are_generated_in_the_order_added() {
+        let ports = PortIterator::new().add_port(443).add_range(80, 82).add_port(22);
+
+        assert_eq!(ports.size(), 5);
+        assert_eq!(ports.collect::<Vec<_>>(), [443, 80, 81, 82, 22]);
+    }
+
+    #[test]
+    fn ports_already_in_the_list_are_left_out() {
+        let ports = PortIterator::new().add_port(80).add_range(79, 81).add_port(80).add_range(1, 3).add_range(2, 5);
+
+        assert_eq!(ports.size(), 8);
+        assert_eq!(ports.collect::<Vec<_>>(), [80, 79, 81, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn ranges_carrying_on_from_the_last_are_merged_into_it() {
+        let ports = PortIterator::new().add_range(1, 10).add_range(11, 20).add_port(21).add_port(65535);
+
+        assert_eq!(ports.inner.len(), 2);
+        assert_eq!(ports.size(), 22);
+    }
+
+    #[test]
+    fn excluding_splits_the_ranges_it_falls_in() {
+        let ports = PortIterator::new().add_range(1, 10).add_port(80).exclude_range(3, 4).exclude_port(10).exclude_port(80);
+
+        assert_eq!(ports.size(), 7);
+        assert_eq!(ports.collect::<Vec<_>>(), [1, 2, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn excluding_around_or_apart_from_a_range() {
+        let ports = PortIterator::new().add_range(20, 30).add_range(100, 200).exclude_range(10, 40).exclude_range(300, 400);
+
+        assert_eq!(ports.size(), 101);
+        assert_eq!(ports.clone().next(), Some(100));
+        assert_eq!(ports.last(), Some(200));
+    }
+
+    #[test]
+    fn the_index_follows_iteration_order() {
+        let ports = PortIterator::new().add_port(443).add_range(80, 82).add_range(1, 65535).exclude_range(2, 65534);
+        let index = ports.index();
+
+        let by_index: Vec<_> = (0..ports.size()).map(|position| index.get(position).unwrap()).collect();
+        assert_eq!(by_index, ports.clone().collect::<Vec<_>>());
+        assert_eq!(index.get(ports.size()), None);
+    }
+}
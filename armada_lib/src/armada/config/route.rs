@@ -0,0 +1,56 @@
+use std::net::IpAddr;
+
+use cidr_utils::cidr::IpCidr;
+
+/// Which source address probes to a destination go out from, going by the routes covering it. Probes sent through
+/// raw sockets get their IP header from the kernel, which picks the source by its routing tables, so a source picked
+/// any other way only holds as long as it happens to be the one the kernel would've picked.
+#[derive(Clone, Debug, Default)]
+pub struct SourceRoutes {
+    /// Kept from the longest prefix to the shortest, so the first route covering a destination is the one it takes
+    routes: Vec<SourceRoute>,
+}
+
+#[derive(Clone, Debug)]
+struct SourceRoute {
+    network: IpCidr,
+    source: IpAddr,
+}
+
+impl SourceRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a route sending probes to the network from the source. Of routes with the same prefix length, the one
+    /// added first wins.
+    pub fn add_route(mut self, network: IpCidr, source: IpAddr) -> Self {
+        let position = self
+            .routes
+            .iter()
+            .position(|route| prefix_length(&route.network) < prefix_length(&network))
+            .unwrap_or(self.routes.len());
+        self.routes.insert(position, SourceRoute { network, source });
+
+        self
+    }
+
+    /// The source of the most specific route to the destination, if any of them cover it
+    pub fn source_for(&self, destination: IpAddr) -> Option<IpAddr> {
+        self.routes
+            .iter()
+            .find(|route| route.network.contains(destination))
+            .map(|route| route.source)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+fn prefix_length(network: &IpCidr) -> u8 {
+    match network {
+        IpCidr::V4(network) => network.get_bits(),
+        IpCidr::V6(network) => network.get_bits(),
+    }
+}
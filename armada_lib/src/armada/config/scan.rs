@@ -0,0 +1,384 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::armada::capture::PacketCapture;
+use crate::armada::config::route::SourceRoutes;
+use crate::armada::hooks::PacketHooks;
+use crate::armada::packet::probe_length;
+use crate::armada::rate::RateLimiter;
+use crate::armada::scan_type::ScanType;
+use tokio_util::sync::CancellationToken;
+
+const DEFAULT_PORT_RETRIES: u8 = 2;
+const DEFAULT_PORT_TIMEOUT: Duration = Duration::from_millis(1_000);
+const DEFAULT_MIN_PORT_TIMEOUT: Duration = Duration::from_millis(50);
+const DEFAULT_MAX_PORT_TIMEOUT: Duration = Duration::from_millis(5_000);
+const DEFAULT_PACKET_BATCH_SIZE: usize = 32;
+
+/// Settings that control how a single scan is performed
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// IPv4 addresses probes to IPv4 targets are sent from
+    pub source_ipv4_addrs: Vec<Ipv4Addr>,
+    /// IPv6 addresses probes to IPv6 targets are sent from
+    pub source_ipv6_addrs: Vec<Ipv6Addr>,
+    /// When set, probes go out from the source of the route to their target, and targets none of the routes cover
+    /// fall back to the source addresses above
+    pub source_routes: Option<SourceRoutes>,
+    /// Number of additional attempts made against a port that didn't respond
+    pub port_retries: u8,
+    /// How long to wait for a response before a probe is considered lost
+    pub port_timeout: Duration,
+    /// Maximum number of packets sent per second. `None` disables rate limiting.
+    pub packets_per_second: Option<usize>,
+    /// How `packets_per_second` is held to, which decides how bursty the probes going out are
+    pub rate_limiter: RateLimitStrategy,
+    pub scan_type: ScanType,
+    /// The TTL, or hop limit for IPv6, probes go out with. `None` leaves it to the system's default.
+    pub ttl: Option<u8>,
+    /// When set, IPv4 probes are split into IP fragments carrying up to this many bytes of the probe each, rounded down
+    /// to a multiple of 8, so packet filters that only look at whole headers don't see them for what they are. IPv6
+    /// probes always go out whole.
+    pub fragment_size: Option<usize>,
+    /// When set, every probe is sent along with copies of it spoofed from the decoys, so the target can't tell our
+    /// probes apart from theirs. Decoys aren't counted as sent or held to any rate limit.
+    pub decoys: Option<Decoys>,
+    /// The window and options TCP probes go out with. `None` sends a bare MSS, or the options of a Linux client when
+    /// collecting fingerprints, since stacks only answer with the options they're offered.
+    pub probe_profile: Option<TcpProbeProfile>,
+    /// Whether open ports have the fingerprint of their SYN-ACK recorded, for guessing the host's operating system
+    pub collect_fingerprints: bool,
+    /// Whether every probed port is reported, whatever its state, rather than only the ones interesting to the scan
+    /// type (e.g. open ports for SYN scans)
+    pub report_all_states: bool,
+    /// Whether every result is reported as it's received, even for a port that was already reported, e.g. when a
+    /// host answers both a probe and its retry. Otherwise only the first response to a pair in flight is reported, and
+    /// the ones after it are dropped.
+    pub raw_results: bool,
+    /// Number of target/port pairs skipped at the start of the scan, for resuming one that was interrupted. Pairs are
+    /// counted in the order they're probed in; see the `scan_cursor` of `ArmadaWorkMessage::Stats`.
+    pub skip_pairs: u128,
+    /// When set, only the shard's share of the target/port pairs is probed, so one scan can be split between machines
+    /// running it with the same settings. A randomized scan needs the same seed on every machine for their shares not
+    /// to overlap.
+    pub shard: Option<ScanShard>,
+    /// Seeds the random parts of the scan, like the probe order, TCP sequence numbers and SCTP initiate tags, so a scan
+    /// can be repeated exactly. `None` seeds from the operating system.
+    pub seed: Option<u64>,
+    /// Whether target/port pairs are probed in a random order spread across every target, rather than one target
+    /// and port after the other. The order follows from the seed, so resuming a randomized scan needs the same seed.
+    pub randomize: bool,
+    /// Whether the packet rate is worked out from how the network responds, backing off when packets seem to be
+    /// dropped and speeding up while they aren't. `packets_per_second` becomes the most the rate goes up to. Ports
+    /// that only answer a retry are the clearest sign of dropped probes, so this works best with retries.
+    pub auto_rate: bool,
+    /// When set, how long to wait for a response is worked out from the round trip times of earlier responses from
+    /// the same subnet instead of always being `port_timeout`, which then only applies until the first responses
+    /// come in
+    pub adaptive_timeout: Option<AdaptiveTimeout>,
+    /// Caps the packets per second sent into any one subnet, on top of `packets_per_second`. Probes to subnets that
+    /// are over their budget wait while probes to other subnets go out in the meantime.
+    pub subnet_rate_limit: Option<SubnetRateLimit>,
+    /// Caps the packets per second sent from any one source address, on top of `packets_per_second`. Probes to
+    /// targets without a route of their own take turns across the source addresses, skipping the ones over their
+    /// budget, so several of them let a scan send faster than targets that rate limit per address would allow.
+    pub source_rate_limit: Option<usize>,
+    /// When set, probes are spaced out in time on top of the rate limits, so the scan doesn't go out in the bursts
+    /// that threshold based intrusion detection rules look for. Paced scans are sent by a single worker.
+    pub pacing: Option<Pacing>,
+    /// When set, no more probes are sent once it's reached, retries included. The scan ends as soon as the probes in
+    /// flight are answered or time out, with the pairs it didn't get to left out of the results; the `scan_cursor` of
+    /// its last stats update says where a later scan can pick up. Discovery stops sending at it too.
+    pub deadline: Option<Instant>,
+    /// The most probes sent, and responses read off of each socket, at a time
+    pub packet_batch_size: usize,
+    /// Whether a batch of packets is handed to the kernel in one system call, with `sendmmsg` and `recvmmsg` on Linux,
+    /// rather than one call per packet
+    pub batched_io: bool,
+    /// When set, every probe sent and every response received during the port scan is written to the capture
+    pub capture: Option<PacketCapture>,
+    /// When set, the hooks are shown every probe sent and every response received during the port scan
+    pub packet_hooks: Option<PacketHooks>,
+    /// When set, cancelling the token stops the scan the way reaching the deadline does. Nothing more is sent, and the
+    /// scan ends with the results found so far once the probes in flight are answered or time out. Unlike
+    /// `Armada::stop`, it only stops the scans it was given to.
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl ScanOptions {
+    /// The window and options TCP probes really go out with, which for no `probe_profile` depends on whether
+    /// fingerprints are collected, since stacks only answer with the options they're offered
+    pub fn tcp_probe_profile(&self) -> TcpProbeProfile {
+        self.probe_profile.clone().unwrap_or_else(|| match self.collect_fingerprints {
+            true => TcpProbeProfile::linux(),
+            false => TcpProbeProfile::default(),
+        })
+    }
+
+    /// How many of the first `pairs` target/port pairs in the scan order get probed, which with a shard is only its
+    /// share of them
+    pub fn probed_pairs(&self, pairs: u128) -> u128 {
+        self.shard.map_or(pairs, |shard| shard.pairs_before(pairs))
+    }
+
+    /// How many bytes each probe to an IPv4 or IPv6 target takes up on the wire, from the IP header on and counting
+    /// every fragment of fragmented probes
+    pub fn probe_length(&self, ipv6: bool) -> usize {
+        probe_length(self.scan_type, &self.tcp_probe_profile(), ipv6, self.fragment_size)
+    }
+}
+
+/// Addresses probes are also sent from, with ours slotted in among them. Only the decoys of a target's address family
+/// are sent to it, and answers to them are never looked at.
+#[derive(Clone, Debug)]
+pub struct Decoys {
+    pub addrs: Vec<IpAddr>,
+    /// How many of the decoys go out before our own probe, the rest going out after it
+    pub real_position: usize,
+}
+
+/// What the TCP header of a probe looks like besides its addressing and flags. The presets copy the SYNs of common
+/// operating systems, so probes don't stand out from the connections those make.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TcpProbeProfile {
+    pub window: u16,
+    /// Laid out in this order, with the header padded out to a multiple of 4 bytes after them
+    pub options: Vec<TcpProbeOption>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpProbeOption {
+    Mss(u16),
+    SackPermitted,
+    /// Sent with a random timestamp value and a zero echo reply, as a connection's first segment has
+    Timestamp,
+    WindowScale(u8),
+    Nop,
+}
+
+impl TcpProbeOption {
+    /// Number of bytes the option takes up in the header
+    pub(crate) fn length(self) -> usize {
+        match self {
+            TcpProbeOption::Mss(_) => 4,
+            TcpProbeOption::SackPermitted => 2,
+            TcpProbeOption::Timestamp => 10,
+            TcpProbeOption::WindowScale(_) => 3,
+            TcpProbeOption::Nop => 1,
+        }
+    }
+}
+
+impl TcpProbeProfile {
+    /// The SYN of a Linux client
+    pub fn linux() -> Self {
+        Self {
+            window: 64240,
+            options: vec![
+                TcpProbeOption::Mss(1460),
+                TcpProbeOption::SackPermitted,
+                TcpProbeOption::Timestamp,
+                TcpProbeOption::Nop,
+                TcpProbeOption::WindowScale(7),
+            ],
+        }
+    }
+
+    /// The SYN of a Windows 10 or 11 client
+    pub fn windows() -> Self {
+        Self {
+            window: 64240,
+            options: vec![
+                TcpProbeOption::Mss(1460),
+                TcpProbeOption::Nop,
+                TcpProbeOption::WindowScale(8),
+                TcpProbeOption::Nop,
+                TcpProbeOption::Nop,
+                TcpProbeOption::SackPermitted,
+            ],
+        }
+    }
+
+    /// The SYN of a macOS client
+    pub fn macos() -> Self {
+        Self {
+            window: 65535,
+            options: vec![
+                TcpProbeOption::Mss(1460),
+                TcpProbeOption::Nop,
+                TcpProbeOption::WindowScale(6),
+                TcpProbeOption::Nop,
+                TcpProbeOption::Nop,
+                TcpProbeOption::Timestamp,
+                TcpProbeOption::SackPermitted,
+            ],
+        }
+    }
+
+    /// Number of bytes the options take up in the header, padding included, which can't be more than 40
+    pub fn options_length(&self) -> usize {
+        let length = self.options.iter().map(|option| option.length()).sum::<usize>();
+
+        length.div_ceil(4) * 4
+    }
+}
+
+impl Default for TcpProbeProfile {
+    /// A bare MSS, keeping probes small
+    fn default() -> Self {
+        Self {
+            window: 1024,
+            options: vec![TcpProbeOption::Mss(1460)],
+        }
+    }
+}
+
+/// How many packets per second any one subnet may be sent, with subnets sized by prefix length
+#[derive(Clone, Copy, Debug)]
+pub struct SubnetRateLimit {
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    pub packets_per_second: usize,
+}
+
+impl SubnetRateLimit {
+    /// The network address of the subnet the remote falls in
+    pub(crate) fn subnet(&self, remote: IpAddr) -> IpAddr {
+        match remote {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - self.ipv4_prefix.min(32) as u32).unwrap_or(0);
+
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - self.ipv6_prefix.min(128) as u32).unwrap_or(0);
+
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        }
+    }
+}
+
+/// One of `count` shares of a scan's target/port pairs: every pair whose position in the scan order is the index,
+/// counting in the number of shards. The index counts from 0, so it's always below the count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanShard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl ScanShard {
+    pub(crate) fn owns(&self, pair: u128) -> bool {
+        pair % self.count as u128 == self.index as u128
+    }
+
+    /// How many of the pairs before the position in the scan order are the shard's
+    pub fn pairs_before(&self, position: u128) -> u128 {
+        (position + (self.count - 1 - self.index) as u128) / self.count as u128
+    }
+}
+
+/// How a scan holds to its packets per second
+#[derive(Clone, Debug, Default)]
+pub enum RateLimitStrategy {
+    /// A tenth of a second's worth of probes goes out at the start of every tenth of a second, and nothing more until
+    /// the next one
+    #[default]
+    FixedWindow,
+    /// Probes go out as tokens drip in at the rate, with up to `burst` tokens saved up while the scan sends slower than
+    /// that, e.g. while it's paused or waiting on other limits. Each worker gets its share of the burst.
+    TokenBucket { burst: usize },
+    /// A limiter of the caller's own
+    Custom(RateLimiterFactory),
+}
+
+/// Makes the `RateLimiter` of a `RateLimitStrategy::Custom`, once for every worker of a scan with the packets per
+/// second that worker's share of the rate limit comes to
+#[derive(Clone)]
+pub struct RateLimiterFactory(Arc<dyn Fn(usize) -> Box<dyn RateLimiter> + Send + Sync>);
+
+impl RateLimiterFactory {
+    pub fn new<F>(factory: F) -> Self
+    where
+        F: Fn(usize) -> Box<dyn RateLimiter> + Send + Sync + 'static,
+    {
+        Self(Arc::new(factory))
+    }
+
+    pub(crate) fn limiter(&self, packets_per_second: usize) -> Box<dyn RateLimiter> {
+        (self.0)(packets_per_second)
+    }
+}
+
+impl fmt::Debug for RateLimiterFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RateLimiterFactory")
+    }
+}
+
+/// How far apart probes are spaced
+#[derive(Clone, Copy, Debug)]
+pub struct Pacing {
+    /// The least time between two probes
+    pub delay: Duration,
+    /// The most time added to the delay, picked at random for every probe
+    pub jitter: Duration,
+    /// Whether only probes to the same host are spaced out, with probes to other hosts going out in between, rather
+    /// than every probe
+    pub per_destination: bool,
+}
+
+/// The bounds an adaptive timeout is kept within
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveTimeout {
+    pub min_timeout: Duration,
+    pub max_timeout: Duration,
+}
+
+impl Default for AdaptiveTimeout {
+    fn default() -> Self {
+        Self {
+            min_timeout: DEFAULT_MIN_PORT_TIMEOUT,
+            max_timeout: DEFAULT_MAX_PORT_TIMEOUT,
+        }
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            source_ipv4_addrs: Vec::new(),
+            source_ipv6_addrs: Vec::new(),
+            source_routes: None,
+            port_retries: DEFAULT_PORT_RETRIES,
+            port_timeout: DEFAULT_PORT_TIMEOUT,
+            packets_per_second: None,
+            rate_limiter: RateLimitStrategy::default(),
+            scan_type: ScanType::default(),
+            ttl: None,
+            fragment_size: None,
+            decoys: None,
+            probe_profile: None,
+            collect_fingerprints: false,
+            report_all_states: false,
+            raw_results: false,
+            skip_pairs: 0,
+            shard: None,
+            seed: None,
+            randomize: false,
+            auto_rate: false,
+            adaptive_timeout: None,
+            subnet_rate_limit: None,
+            source_rate_limit: None,
+            pacing: None,
+            deadline: None,
+            packet_batch_size: DEFAULT_PACKET_BATCH_SIZE,
+            batched_io: true,
+            capture: None,
+            packet_hooks: None,
+            cancellation: None,
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! The limits shared by everything that connects to open ports once they're found
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Semaphore;
+
+/// How banner grabbing, TLS certificate collection and service detection connect to open ports. Each of them holds
+/// its connections to the limit, and clones of a grabber, inspector or detector share it, so a single one can be
+/// handed to any number of tasks.
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    /// How long we wait to connect, and then again for each step over the connection: the banner, the TLS handshake
+    /// or the response to each service probe
+    pub timeout: Duration,
+    /// The most connections that are allowed to be open at once
+    pub concurrency: usize,
+}
+
+impl ConnectionOptions {
+    pub(crate) fn connection_permits(&self) -> Arc<Semaphore> {
+        Arc::new(Semaphore::new(self.concurrency.max(1)))
+    }
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(3),
+            concurrency: 256,
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// What the scans an `Armada` runs can be told to do while they're running, shared with every one of its workers
+#[derive(Debug, Default)]
+pub(crate) struct ScanControls {
+    /// Set while sending is paused. Responses are still received and probes in flight still expire.
+    paused: AtomicBool,
+    /// Set once sending has stopped for good
+    stopped: AtomicBool,
+    /// The rate limit scans are held to in place of their own, with zero leaving them to theirs
+    rate_limit: AtomicUsize,
+}
+
+impl ScanControls {
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_rate_limit(&self, packets_per_second: usize) {
+        self.rate_limit.store(packets_per_second.max(1), Ordering::Relaxed);
+    }
+
+    pub(crate) fn clear_rate_limit(&self) {
+        self.rate_limit.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn rate_limit(&self) -> Option<usize> {
+        match self.rate_limit.load(Ordering::Relaxed) {
+            0 => None,
+            packets_per_second => Some(packets_per_second),
+        }
+    }
+}
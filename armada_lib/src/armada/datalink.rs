@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use pnet::datalink::{self, DataLinkReceiver, DataLinkSender};
+use pnet::util::MacAddr;
+
+use crate::armada::frame::{destination_mac, parse_frame, Framer};
+use crate::armada::transport::{Channel, Transport};
+
+/// The most responses held per channel before they're read. The rest are dropped, as a full socket buffer would.
+const MAX_QUEUED_RESPONSES: usize = 4096;
+/// How long the receiving thread waits for a frame before checking whether the transport's still around, where the
+/// platform lets reads time out
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+/// Room for a good number of frames in the capture buffer between reads
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// Sends probes and receives responses as whole Ethernet frames through the interface's packet capture driver: Npcap
+/// on Windows and BPF devices on macOS, where raw sockets can't be relied on to put crafted packets on the wire or
+/// hand TCP responses back, and AF_PACKET on Linux. Probes are
+/// framed up to the Ethernet header here. Responses are read off the interface on a thread of their own, since
+/// capture drivers only offer reads that block.
+pub(crate) struct DatalinkTransport {
+    sender: Box<dyn DataLinkSender>,
+    framer: Framer,
+    /// Probes sent since the last flush, and the ones the driver refused
+    sent: Vec<SocketAddr>,
+    unsent: Vec<SocketAddr>,
+    /// Responses the receiving thread picked out of the interface's traffic
+    responses: Receiver<(Channel, Vec<u8>, IpAddr)>,
+    /// Responses taken off the receiving thread that haven't been read yet, per channel
+    received: [VecDeque<(Vec<u8>, IpAddr)>; Channel::ALL.len()],
+    /// The response being read
+    current: Vec<u8>,
+    /// Held for as long as the transport's around, which the receiving thread keeps an eye on
+    _alive: Arc<()>,
+}
+
+impl DatalinkTransport {
+    pub(crate) fn open(interface_name: &str, vlan: Option<u16>) -> anyhow::Result<Self> {
+        let interface = datalink::interfaces()
+            .into_iter()
+            .find(|interface| interface.name == interface_name)
+            .with_context(|| format!("There is no interface named {}.", interface_name))?;
+        let source_mac = interface
+            .mac
+            .with_context(|| format!("Interface {} has no link layer address to send from.", interface_name))?;
+
+        let config = datalink::Config {
+            write_buffer_size: BUFFER_SIZE,
+            read_buffer_size: BUFFER_SIZE,
+            read_timeout: Some(READ_TIMEOUT),
+            promiscuous: false,
+            ..Default::default()
+        };
+        let (sender, receiver) = match datalink::channel(&interface, config)
+            .with_context(|| format!("Unable to open a packet capture channel on {}.", interface_name))?
+        {
+            datalink::Channel::Ethernet(sender, receiver) => (sender, receiver),
+            _ => bail!("Interface {} isn't an Ethernet interface.", interface_name),
+        };
+
+        let alive = Arc::new(());
+
+        Ok(Self {
+            sender,
+            framer: Framer::new(interface_name, source_mac, vlan),
+            sent: Vec::new(),
+            unsent: Vec::new(),
+            responses: spawn_receiver(receiver, Arc::downgrade(&alive), source_mac, vlan),
+            received: Default::default(),
+            current: Vec::new(),
+            _alive: alive,
+        })
+    }
+
+    /// Sends frames to targets behind a gateway, or that there's no route to, to this address rather than the
+    /// gateway's in the neighbor table
+    pub(crate) fn with_router_mac(mut self, router_mac: Option<MacAddr>) -> Self {
+        self.framer = self.framer.with_router_mac(router_mac);
+        self
+    }
+
+    /// Sends frames to targets on the link to this address rather than their own in the neighbor table
+    pub(crate) fn with_target_mac(mut self, target_mac: Option<MacAddr>) -> Self {
+        self.framer = self.framer.with_target_mac(target_mac);
+        self
+    }
+
+    fn send_frames(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        let sender = &mut self.sender;
+
+        self.framer.write_frames(channel, source, remote, packet, |frame| {
+            matches!(sender.send_to(frame, None), Some(Ok(())))
+        })
+    }
+}
+
+impl Transport for DatalinkTransport {
+    fn set_ttl(&mut self, ttl: Option<u8>) -> anyhow::Result<()> {
+        self.framer.set_ttl(ttl);
+
+        Ok(())
+    }
+
+    fn set_fragmentation(&mut self, fragment_size: Option<usize>) -> anyhow::Result<()> {
+        self.framer.set_fragmentation(fragment_size);
+
+        Ok(())
+    }
+
+    /// Frames go out right away, so a probe the driver refuses is reported as unsent on the next flush
+    fn queue(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        match self.send_frames(channel, source, remote, packet) {
+            true => self.sent.push(remote),
+            false => self.unsent.push(remote),
+        }
+
+        true
+    }
+
+    fn send_decoy(&mut self, channel: Channel, decoy: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        self.send_frames(channel, decoy, remote, packet)
+    }
+
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        (std::mem::take(&mut self.sent), std::mem::take(&mut self.unsent))
+    }
+
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>> {
+        for (response_channel, datagram, remote) in self.responses.try_iter() {
+            let responses = &mut self.received[response_channel.index()];
+
+            if responses.len() < MAX_QUEUED_RESPONSES {
+                responses.push_back((datagram, remote));
+            }
+        }
+
+        match self.received[channel.index()].pop_front() {
+            Some((datagram, remote)) => {
+                self.current = datagram;
+
+                Ok(Some((&self.current, remote)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads frames off the interface until the transport's dropped, passing on the responses addressed to us. Our own
+/// probes show up in the capture too, along with everything else on the link, and are left out. A driver whose reads
+/// can't time out keeps the thread around until the next frame after the transport's gone.
+fn spawn_receiver(
+    mut receiver: Box<dyn DataLinkReceiver>,
+    transport: Weak<()>,
+    source_mac: MacAddr,
+    vlan: Option<u16>,
+) -> Receiver<(Channel, Vec<u8>, IpAddr)> {
+    let (response_sender, responses) = sync_channel(MAX_QUEUED_RESPONSES);
+
+    thread::spawn(move || loop {
+        let frame = match receiver.next() {
+            Ok(frame) => frame,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => &[],
+            Err(_) => return,
+        };
+
+        let response = match destination_mac(frame) {
+            Some(destination) if destination == source_mac => parse_frame(frame, vlan),
+            _ => None,
+        };
+
+        if transport.strong_count() == 0 {
+            return;
+        }
+
+        // a full channel drops the response, as a full socket buffer would
+        if let Some((channel, datagram, remote)) = response {
+            let _ = response_sender.try_send((channel, datagram.to_vec(), remote));
+        }
+    });
+
+    responses
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+
+use pnet::packet::tcp::TcpFlags::SYN;
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use tracing::warn;
+use twox_hash::XxHash64;
+
+use crate::armada::arp::ArpProber;
+use crate::armada::channels::TransportChannels;
+use crate::armada::config::host::HostIterator;
+use crate::armada::config::scan::{ScanOptions, TcpProbeProfile};
+use crate::armada::packet::{
+    create_icmp_echo_packet_v4,
+    create_icmp_echo_packet_v6,
+    create_icmp_timestamp_packet_v4,
+    create_probe_tcp_packet_v4,
+    create_probe_tcp_packet_v6,
+    ProbePacket,
+    TcpProbe,
+    ICMPV6_TYPE_ECHO_REPLY,
+    ICMP_TYPE_ECHO_REPLY,
+    ICMP_TYPE_TIMESTAMP_REPLY,
+};
+use crate::armada::response::TcpResponse;
+use crate::armada::result::LiveHost;
+use crate::armada::tcp_ext::{IcmpReceiverExt, TcpReceiverExt, TcpSenderExt};
+use crate::armada::transport::{Channel, Transport};
+use crate::utils::OnLinkNetwork;
+
+/// Ports probed with a TCP SYN during discovery. Plenty of hosts drop ICMP but still serve the web.
+const DISCOVERY_TCP_PORTS: [u16; 2] = [80, 443];
+/// How long to back off for while waiting on responses with nothing to receive
+const IDLE_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Every host that answered, along with its MAC if it answered an ARP request
+type HostSet = HashMap<IpAddr, Option<MacAddr>, BuildHasherDefault<XxHash64>>;
+
+/// Figures out which hosts are up before committing to a full port scan of them
+pub(crate) struct DiscoveryWorker {
+    listening_port: u16,
+    /// The interface the raw sockets are bound to, if any
+    interface: Option<String>,
+    tcp_seq: u32,
+    icmp_sequence: u16,
+}
+
+impl DiscoveryWorker {
+    pub(crate) fn new(listening_port: u16, interface: Option<String>) -> Self {
+        Self {
+            listening_port,
+            interface,
+            tcp_seq: rand::random::<u32>(),
+            icmp_sequence: 0,
+        }
+    }
+
+    /// Probes every host with an ICMP echo, an ICMP timestamp request (IPv4 only) and TCP SYNs to common ports.
+    /// IPv4 hosts on one of the on-link networks are sent an ARP request instead. Returns the hosts that answered.
+    pub(crate) fn run(
+        mut self,
+        remote_hosts: HostIterator,
+        options: ScanOptions,
+        on_link_networks: Vec<OnLinkNetwork>,
+    ) -> anyhow::Result<Vec<LiveHost>> {
+        let mut channels = TransportChannels::open(self.listening_port, self.interface.as_deref())?;
+        let mut arp_prober = ArpProber::open(on_link_networks);
+        let mut live_hosts = HostSet::default();
+
+        let mut source_ipv4_cycle = options.source_ipv4_addrs.iter().cycle();
+        let mut source_ipv6_cycle = options.source_ipv6_addrs.iter().cycle();
+
+        // spacing between probes needed to stay under the rate limit
+        let probe_interval = options.packets_per_second
+            .map(|packets_per_second| Duration::from_secs(1) / packets_per_second.max(1) as u32);
+        let started = Instant::now();
+        let mut total_probes_sent = 0u32;
+
+        // every round only re-probes the hosts that haven't answered yet
+        'rounds: for _ in 0 ..= options.port_retries {
+            for remote_host in remote_hosts.clone() {
+                if live_hosts.contains_key(&remote_host) {
+                    continue;
+                }
+
+                // the hosts probed so far still get to answer before discovery gives up
+                let cancelled = options.cancellation.as_ref().is_some_and(|cancellation| cancellation.is_cancelled());
+                if cancelled || options.deadline.is_some_and(|deadline| deadline.le(&Instant::now())) {
+                    self.wait_for_stragglers(&mut channels, &mut arp_prober, &mut live_hosts, options.port_timeout);
+                    break 'rounds;
+                }
+
+                let arp_request_sent = match remote_host {
+                    IpAddr::V4(remote_ipv4) => arp_prober.try_send_request(remote_ipv4),
+                    IpAddr::V6(_) => None,
+                };
+
+                total_probes_sent += match arp_request_sent {
+                    Some(sent) => sent as u32,
+                    None => {
+                        let mut source_ipv4 = source_ipv4_cycle.next().copied();
+                        let mut source_ipv6 = source_ipv6_cycle.next().copied();
+                        match options.source_routes.as_ref().and_then(|source_routes| source_routes.source_for(remote_host)) {
+                            Some(IpAddr::V4(routed_source)) => source_ipv4 = Some(routed_source),
+                            Some(IpAddr::V6(routed_source)) => source_ipv6 = Some(routed_source),
+                            None => {}
+                        }
+
+                        self.send_probes(&mut channels, remote_host, source_ipv4.as_ref(), source_ipv6.as_ref())
+                    }
+                };
+
+                self.record_live_hosts(&mut channels, &mut arp_prober, &mut live_hosts);
+
+                if let Some(probe_interval) = probe_interval {
+                    let next_send = started + probe_interval * total_probes_sent;
+
+                    std::thread::sleep(next_send.saturating_duration_since(Instant::now()));
+                }
+            }
+
+            // give the stragglers a chance to answer before the next round
+            self.wait_for_stragglers(&mut channels, &mut arp_prober, &mut live_hosts, options.port_timeout);
+        }
+
+        Ok(live_hosts
+            .into_iter()
+            .map(|(ip, mac_address)| LiveHost { ip, mac_address })
+            .collect())
+    }
+
+    /// Sends every discovery probe to the host, returning how many were sent
+    fn send_probes(
+        &mut self,
+        channels: &mut TransportChannels,
+        remote_host: IpAddr,
+        source_ipv4: Option<&Ipv4Addr>,
+        source_ipv6: Option<&Ipv6Addr>,
+    ) -> u32 {
+        let mut probe_buffer = [0; 32];
+        let mut probes_sent = 0;
+        let probe_profile = TcpProbeProfile::default();
+        let syn_probe = TcpProbe {
+            flags: SYN,
+            profile: &probe_profile,
+        };
+
+        self.icmp_sequence = self.icmp_sequence.wrapping_add(1);
+
+        match (remote_host, source_ipv4, source_ipv6) {
+            (IpAddr::V4(remote_ipv4), Some(source_ipv4_addr), _) => {
+                let source = IpAddr::V4(*source_ipv4_addr);
+
+                if let Some(packet) = create_icmp_echo_packet_v4(self.listening_port, self.icmp_sequence, &mut probe_buffer) {
+                    probes_sent += send(&mut channels.ipv4_icmp_sender, ProbePacket::Raw(packet), source, remote_host);
+                }
+
+                if let Some(packet) = create_icmp_timestamp_packet_v4(self.listening_port, self.icmp_sequence, &mut probe_buffer) {
+                    probes_sent += send(&mut channels.ipv4_icmp_sender, ProbePacket::Raw(packet), source, remote_host);
+                }
+
+                for remote_port in DISCOVERY_TCP_PORTS {
+                    let packet = create_probe_tcp_packet_v4(
+                        source_ipv4_addr,
+                        &remote_ipv4,
+                        self.listening_port,
+                        remote_port,
+                        syn_probe,
+                        &mut probe_buffer,
+                        &mut self.tcp_seq,
+                    );
+
+                    if let Some(packet) = packet {
+                        probes_sent += send(&mut channels.ipv4_tcp_sender, ProbePacket::Tcp(packet), source, remote_host);
+                    }
+                }
+            }
+            (IpAddr::V6(remote_ipv6), _, Some(source_ipv6_addr)) => {
+                let source = IpAddr::V6(*source_ipv6_addr);
+
+                let packet = create_icmp_echo_packet_v6(
+                    source_ipv6_addr,
+                    &remote_ipv6,
+                    self.listening_port,
+                    self.icmp_sequence,
+                    &mut probe_buffer,
+                );
+
+                if let Some(packet) = packet {
+                    probes_sent += send(&mut channels.ipv6_icmp_sender, ProbePacket::Raw(packet), source, remote_host);
+                }
+
+                for remote_port in DISCOVERY_TCP_PORTS {
+                    let packet = create_probe_tcp_packet_v6(
+                        source_ipv6_addr,
+                        &remote_ipv6,
+                        self.listening_port,
+                        remote_port,
+                        syn_probe,
+                        &mut probe_buffer,
+                        &mut self.tcp_seq,
+                    );
+
+                    if let Some(packet) = packet {
+                        probes_sent += send(&mut channels.ipv6_tcp_sender, ProbePacket::Tcp(packet), source, remote_host);
+                    }
+                }
+            }
+            (IpAddr::V4(_), None, _) => {
+                warn!("Attempted to discover an IPv4 host without any provided IPv4 source addresses. Host will be skipped.");
+            }
+            (IpAddr::V6(_), _, None) => {
+                warn!("Attempted to discover an IPv6 host without any provided IPv6 source addresses. Host will be skipped.");
+            }
+        }
+
+        probes_sent
+    }
+
+    /// Keeps recording answers for the timeout, so hosts probed last get as long to answer as the others
+    fn wait_for_stragglers(
+        &self,
+        channels: &mut TransportChannels,
+        arp_prober: &mut ArpProber,
+        live_hosts: &mut HostSet,
+        timeout: Duration,
+    ) {
+        let wait_deadline = Instant::now() + timeout;
+
+        while Instant::now() < wait_deadline {
+            if self.record_live_hosts(channels, arp_prober, live_hosts) == 0 {
+                std::thread::sleep(IDLE_BACKOFF);
+            }
+        }
+    }
+
+    /// Drains every socket of responses to our probes, returning how many previously unknown hosts answered
+    fn record_live_hosts(
+        &self,
+        channels: &mut TransportChannels,
+        arp_prober: &mut ArpProber,
+        live_hosts: &mut HostSet,
+    ) -> usize {
+        let icmp_reply_types = [ICMP_TYPE_ECHO_REPLY, ICMP_TYPE_TIMESTAMP_REPLY];
+        let icmpv6_reply_types = [ICMPV6_TYPE_ECHO_REPLY];
+
+        record_arp_responders(arp_prober, live_hosts)
+            + self.record_tcp_responders(channels, Channel::Ipv4Tcp, live_hosts)
+            + self.record_tcp_responders(channels, Channel::Ipv6Tcp, live_hosts)
+            + self.record_icmp_responders(channels, Channel::Ipv4Icmp, &icmp_reply_types, live_hosts)
+            + self.record_icmp_responders(channels, Channel::Ipv6Icmp, &icmpv6_reply_types, live_hosts)
+    }
+
+    /// Any answer to a discovery SYN, be it a SYN-ACK or a RST, means somebody is home
+    fn record_tcp_responders(&self, transport: &mut dyn Transport, channel: Channel, live_hosts: &mut HostSet) -> usize {
+        let mut new_hosts = 0;
+
+        while let Ok(Some((packet, remote, _, _))) = transport.try_next(channel) {
+            let response = TcpResponse::from(&packet);
+
+            if response.destination_port == self.listening_port
+                && DISCOVERY_TCP_PORTS.contains(&response.source_port)
+                && live_hosts.insert(remote, None).is_none()
+            {
+                new_hosts += 1;
+            }
+        }
+
+        new_hosts
+    }
+
+    fn record_icmp_responders(
+        &self,
+        transport: &mut dyn Transport,
+        channel: Channel,
+        reply_types: &[u8],
+        live_hosts: &mut HostSet,
+    ) -> usize {
+        let mut new_hosts = 0;
+
+        while let Ok(Some((response, remote))) = transport.try_next_icmp(channel) {
+            // our requests carry the listening port as their identifier so we can tell our replies apart from others
+            if reply_types.contains(&response.icmp_type)
+                && response.identifier == self.listening_port
+                && live_hosts.insert(remote, None).is_none()
+            {
+                new_hosts += 1;
+            }
+        }
+
+        new_hosts
+    }
+}
+
+/// Any ARP reply means somebody is home. The MAC is recorded even if the host already answered another probe.
+fn record_arp_responders(arp_prober: &mut ArpProber, live_hosts: &mut HostSet) -> usize {
+    let mut new_hosts = 0;
+
+    arp_prober.receive_replies(|remote_ip, mac_address| {
+        if live_hosts.insert(IpAddr::V4(remote_ip), Some(mac_address)).is_none() {
+            new_hosts += 1;
+        }
+    });
+
+    new_hosts
+}
+
+/// Sends a probe, returning 1 if it went out and 0 otherwise
+fn send(
+    sender: &mut pnet::transport::TransportSender,
+    packet: ProbePacket<'_>,
+    source: IpAddr,
+    remote_host: IpAddr,
+) -> u32 {
+    match sender.try_send_to(packet.packet(), source, remote_host) {
+        Ok(Some(_)) => 1,
+        _ => 0,
+    }
+}
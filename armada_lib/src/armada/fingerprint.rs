@@ -0,0 +1,103 @@
+use pnet::packet::tcp::{TcpOptionNumbers, TcpPacket};
+use pnet::packet::Packet;
+
+/// The traits of a SYN-ACK that vary between TCP/IP stacks, p0f style
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SynAckFingerprint {
+    /// Only known for IPv4, as raw IPv6 sockets don't hand us the hop limit
+    pub ttl: Option<u8>,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+    /// The order the TCP options came in, e.g. `mss,sok,ts,nop,ws`
+    pub options_layout: String,
+}
+
+/// A known stack: the TTL it starts from and the option layout of its SYN-ACKs
+struct OsSignature {
+    os: &'static str,
+    initial_ttl: u8,
+    options_layout: &'static str,
+}
+
+const OS_SIGNATURES: [OsSignature; 7] = [
+    OsSignature { os: "Linux", initial_ttl: 64, options_layout: "mss,sok,ts,nop,ws" },
+    OsSignature { os: "Linux", initial_ttl: 64, options_layout: "mss,nop,nop,sok,nop,ws" },
+    OsSignature { os: "FreeBSD", initial_ttl: 64, options_layout: "mss,nop,ws,sok,ts" },
+    OsSignature { os: "macOS", initial_ttl: 64, options_layout: "mss,nop,ws,nop,nop,ts,sok,eol" },
+    OsSignature { os: "Windows", initial_ttl: 128, options_layout: "mss,nop,ws,nop,nop,sok" },
+    OsSignature { os: "Windows", initial_ttl: 128, options_layout: "mss,nop,ws,sok,ts" },
+    OsSignature { os: "Solaris", initial_ttl: 64, options_layout: "nop,nop,ts,mss,nop,ws,nop,nop,sok" },
+];
+
+impl SynAckFingerprint {
+    pub(crate) fn from_packet(packet: &TcpPacket<'_>, ttl: Option<u8>) -> Self {
+        let mut mss = None;
+        let mut window_scale = None;
+        let mut options_layout = Vec::new();
+
+        for option in packet.get_options_iter() {
+            let number = option.get_number();
+            let payload = option.payload();
+
+            let name = match number {
+                TcpOptionNumbers::EOL => "eol".to_string(),
+                TcpOptionNumbers::NOP => "nop".to_string(),
+                TcpOptionNumbers::MSS => {
+                    mss = payload.get(..2).map(|mss| u16::from_be_bytes([mss[0], mss[1]]));
+                    "mss".to_string()
+                }
+                TcpOptionNumbers::WSCALE => {
+                    window_scale = payload.first().copied();
+                    "ws".to_string()
+                }
+                TcpOptionNumbers::SACK_PERMITTED => "sok".to_string(),
+                TcpOptionNumbers::SACK => "sack".to_string(),
+                TcpOptionNumbers::TIMESTAMPS => "ts".to_string(),
+                other => format!("?{}", other.0),
+            };
+
+            options_layout.push(name);
+        }
+
+        Self {
+            ttl,
+            window: packet.get_window(),
+            mss,
+            window_scale,
+            options_layout: options_layout.join(","),
+        }
+    }
+
+    /// The TTL the host most likely started from, assuming it's fewer than 32 hops away from the common defaults
+    pub fn initial_ttl(&self) -> Option<u8> {
+        self.ttl.map(|ttl| match ttl {
+            0..=32 => 32,
+            33..=64 => 64,
+            65..=128 => 128,
+            _ => 255,
+        })
+    }
+
+    /// Guesses the operating system of the host. Exact signature matches are preferred; failing that, the initial
+    /// TTL alone narrows it down to a family.
+    pub fn os_guess(&self) -> Option<&'static str> {
+        let initial_ttl = self.initial_ttl();
+
+        let exact_match = OS_SIGNATURES.iter().find(|signature| {
+            signature.options_layout == self.options_layout
+                && initial_ttl.is_none_or(|initial_ttl| initial_ttl == signature.initial_ttl)
+        });
+
+        if let Some(signature) = exact_match {
+            return Some(signature.os);
+        }
+
+        match initial_ttl? {
+            64 => Some("Unix-like"),
+            128 => Some("Windows"),
+            255 => Some("Network device"),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::iter;
+use std::net::{IpAddr, SocketAddr};
+
+use itertools::Either;
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::MutablePacket;
+use pnet::util::MacAddr;
+
+use crate::armada::neighbor::NeighborTable;
+use crate::armada::packet::{build_ip_packet, build_ipv4_fragment, fragment_parts, IPV6_HEADER_LENGTH, PROBE_TTL};
+use crate::armada::transport::Channel;
+use crate::utils::get_vlan_interface;
+
+const ETHERNET_HEADER_LENGTH: usize = 14;
+/// An 802.1Q tag sits between the source address and the EtherType of the frame
+const VLAN_TAG_LENGTH: usize = 4;
+/// Room for a probe of any protocol along with the headers around it, VLAN tag included
+pub(crate) const MAX_FRAME_LENGTH: usize = 1518;
+
+/// Puts probes in Ethernet frames addressed to their next hop, for transports that hand whole frames to the interface
+/// rather than IP packets to the kernel
+pub(crate) struct Framer {
+    neighbors: NeighborTable,
+    source_mac: MacAddr,
+    frame: [u8; MAX_FRAME_LENGTH],
+    identification: u16,
+    ttl: u8,
+    /// When set, IPv4 probes are split into fragments carrying up to this many bytes of them each
+    fragment_size: Option<usize>,
+    /// The VLAN probes are tagged with and responses are taken from, if any
+    vlan: Option<u16>,
+}
+
+impl Framer {
+    /// Frames on a VLAN are addressed from the routing and neighbor tables of its interface when one is set up on top
+    /// of the interface, and from the interface's own otherwise
+    pub(crate) fn new(interface_name: &str, source_mac: MacAddr, vlan: Option<u16>) -> Self {
+        let neighbor_interface_name = vlan.and_then(|vlan| get_vlan_interface(interface_name, vlan));
+
+        Self {
+            neighbors: NeighborTable::load(neighbor_interface_name.as_deref().unwrap_or(interface_name)),
+            source_mac,
+            frame: [0; MAX_FRAME_LENGTH],
+            identification: rand::random(),
+            ttl: PROBE_TTL,
+            fragment_size: None,
+            vlan,
+        }
+    }
+
+    /// Sends frames to targets behind a gateway, or that there's no route to, to this address rather than the
+    /// gateway's in the neighbor table
+    pub(crate) fn with_router_mac(mut self, router_mac: Option<MacAddr>) -> Self {
+        self.neighbors = self.neighbors.with_router_mac(router_mac);
+        self
+    }
+
+    /// Sends frames to targets on the link to this address rather than their own in the neighbor table
+    pub(crate) fn with_target_mac(mut self, target_mac: Option<MacAddr>) -> Self {
+        self.neighbors = self.neighbors.with_target_mac(target_mac);
+        self
+    }
+
+    pub(crate) fn set_ttl(&mut self, ttl: Option<u8>) {
+        self.ttl = ttl.unwrap_or(PROBE_TTL);
+    }
+
+    pub(crate) fn set_fragmentation(&mut self, fragment_size: Option<usize>) {
+        self.fragment_size = fragment_size;
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn vlan(&self) -> Option<u16> {
+        self.vlan
+    }
+
+    /// Puts the packet in frames, fragmenting it if need be, and hands each one to `send`. Returns false when `send`
+    /// turned the first of them away.
+    pub(crate) fn write_frames(
+        &mut self,
+        channel: Channel,
+        source: IpAddr,
+        remote: SocketAddr,
+        packet: &[u8],
+        mut send: impl FnMut(&[u8]) -> bool,
+    ) -> bool {
+        self.identification = self.identification.wrapping_add(1);
+
+        // probes we can't address a frame for are dropped like lost packets, leaving it to the retries
+        let destination_mac = match self.neighbors.next_hop_mac(remote.ip()) {
+            Some(destination_mac) => destination_mac,
+            None => return true,
+        };
+
+        let parts = match (self.fragment_size, source, remote.ip()) {
+            (Some(fragment_size), IpAddr::V4(_), IpAddr::V4(_)) => Either::Left(fragment_parts(packet.len(), fragment_size)),
+            _ => Either::Right(iter::once(0..packet.len())),
+        };
+
+        for (index, part) in parts.enumerate() {
+            let (identification, ttl) = (self.identification, self.ttl);
+            let frame_length = build_frame(&mut self.frame, self.source_mac, destination_mac, self.vlan, source.is_ipv4(), |buffer| {
+                match (source, remote.ip()) {
+                    (IpAddr::V4(source), IpAddr::V4(destination)) => build_ipv4_fragment(
+                        buffer,
+                        channel.protocol(),
+                        source,
+                        destination,
+                        packet,
+                        part,
+                        identification,
+                        ttl,
+                    ),
+                    (source, destination) => {
+                        build_ip_packet(buffer, channel.protocol(), source, destination, packet, identification, ttl)
+                    }
+                }
+            });
+
+            if let Some(frame_length) = frame_length {
+                // a probe is only turned away whole, later fragments that don't fit are lost like any other packet
+                if !send(&self.frame[..frame_length]) && index == 0 {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Puts an IP packet, as written into the frame by `write_ip_packet`, in an Ethernet frame, tagged with the VLAN if
+/// there is one, returning the frame's length
+fn build_frame(
+    buffer: &mut [u8],
+    source_mac: MacAddr,
+    destination_mac: MacAddr,
+    vlan: Option<u16>,
+    ipv4: bool,
+    write_ip_packet: impl FnOnce(&mut [u8]) -> Option<usize>,
+) -> Option<usize> {
+    let ethertype = if ipv4 { EtherTypes::Ipv4 } else { EtherTypes::Ipv6 };
+
+    let mut ethernet = MutableEthernetPacket::new(buffer)?;
+    ethernet.set_source(source_mac);
+    ethernet.set_destination(destination_mac);
+
+    let (payload, tag_length) = match vlan {
+        Some(vlan) => {
+            ethernet.set_ethertype(EtherTypes::Vlan);
+
+            // the tag leaves the priority at its default of 0
+            let payload = ethernet.payload_mut();
+            payload.get_mut(..2)?.copy_from_slice(&vlan.to_be_bytes());
+            payload.get_mut(2..VLAN_TAG_LENGTH)?.copy_from_slice(&ethertype.0.to_be_bytes());
+
+            (&mut payload[VLAN_TAG_LENGTH..], VLAN_TAG_LENGTH)
+        }
+        None => {
+            ethernet.set_ethertype(ethertype);
+
+            (ethernet.payload_mut(), 0)
+        }
+    };
+
+    let ip_length = write_ip_packet(payload)?;
+
+    Some(ETHERNET_HEADER_LENGTH + tag_length + ip_length)
+}
+
+/// Picks out the channel a frame belongs on and its datagram, laid out the way raw sockets would hand it over. On a
+/// VLAN, only frames tagged with it are taken.
+pub(crate) fn parse_frame(frame: &[u8], vlan: Option<u16>) -> Option<(Channel, &[u8], IpAddr)> {
+    let ethernet = EthernetPacket::new(frame)?;
+
+    let (ethertype, ip_packet) = match (ethernet.get_ethertype(), vlan) {
+        (EtherTypes::Vlan, Some(vlan)) => {
+            let tag = frame.get(ETHERNET_HEADER_LENGTH..ETHERNET_HEADER_LENGTH + VLAN_TAG_LENGTH)?;
+            if u16::from_be_bytes([tag[0], tag[1]]) & 0x0fff != vlan {
+                return None;
+            }
+
+            (EtherType(u16::from_be_bytes([tag[2], tag[3]])), &frame[ETHERNET_HEADER_LENGTH + VLAN_TAG_LENGTH..])
+        }
+        (_, Some(_)) => return None,
+        (ethertype, None) => (ethertype, &frame[ETHERNET_HEADER_LENGTH..]),
+    };
+
+    match ethertype {
+        EtherTypes::Ipv4 => {
+            let ipv4_packet = Ipv4Packet::new(ip_packet)?;
+            let channel = Channel::for_response(ipv4_packet.get_next_level_protocol(), false)?;
+            // anything past the total length is padding to the minimum frame size
+            let datagram = ip_packet.get(..ipv4_packet.get_total_length() as usize)?;
+
+            Some((channel, datagram, IpAddr::V4(ipv4_packet.get_source())))
+        }
+        EtherTypes::Ipv6 => {
+            let ipv6_packet = Ipv6Packet::new(ip_packet)?;
+            let channel = Channel::for_response(ipv6_packet.get_next_header(), true)?;
+            let datagram = ip_packet.get(IPV6_HEADER_LENGTH..)?.get(..ipv6_packet.get_payload_length() as usize)?;
+
+            Some((channel, datagram, IpAddr::V6(ipv6_packet.get_source())))
+        }
+        _ => None,
+    }
+}
+
+/// The destination link layer address of a frame, for telling the ones sent to us from the ones we sent
+pub(crate) fn destination_mac(frame: &[u8]) -> Option<MacAddr> {
+    EthernetPacket::new(frame).map(|ethernet| ethernet.get_destination())
+}
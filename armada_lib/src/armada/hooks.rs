@@ -0,0 +1,207 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use crate::armada::packet::PROBE_TTL;
+use crate::armada::transport::{Channel, Transport};
+
+/// The protocol of a packet a hook is shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketProtocol {
+    Tcp,
+    Sctp,
+    Icmp,
+    Icmpv6,
+}
+
+/// A probe going out or a response coming in during the port scan, with its headers picked apart
+#[derive(Clone, Copy, Debug)]
+pub struct PacketInfo<'p> {
+    pub protocol: PacketProtocol,
+    pub source: IpAddr,
+    /// Raw sockets don't say which of our addresses an IPv6 response was sent to, so it's the one probes last went
+    /// out from
+    pub destination: IpAddr,
+    /// Only known for TCP and SCTP packets
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+    /// Only known for TCP packets
+    pub tcp_flags: Option<u8>,
+    /// What probes go out with as far as we know, and what IPv4 responses arrive with. Unknown for IPv6 responses.
+    pub ttl: Option<u8>,
+    /// Whether the probe is a copy spoofed from one of the decoys rather than one of ours
+    pub decoy: bool,
+    /// The packet from its transport layer header on
+    pub transport_packet: &'p [u8],
+}
+
+type PacketHook = Arc<dyn Fn(&PacketInfo<'_>) + Send + Sync>;
+
+/// Callbacks run on every probe sent and every response received during the port scan, for telemetry of the
+/// embedder's own and the like. They run on the worker sending the scan, between sending and receiving, so they should
+/// be quick about it, and any time they do take holds up the probes after them.
+#[derive(Clone, Default)]
+pub struct PacketHooks {
+    on_send: Option<PacketHook>,
+    on_receive: Option<PacketHook>,
+}
+
+impl PacketHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the hook on every probe once it's been handed to the transport, decoys included
+    pub fn on_send<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&PacketInfo<'_>) + Send + Sync + 'static,
+    {
+        self.on_send = Some(Arc::new(hook));
+        self
+    }
+
+    /// Runs the hook on every packet received on the scan's sockets, before it's looked at to see whether it answers a
+    /// probe, so it's also shown packets that don't
+    pub fn on_receive<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&PacketInfo<'_>) + Send + Sync + 'static,
+    {
+        self.on_receive = Some(Arc::new(hook));
+        self
+    }
+}
+
+impl fmt::Debug for PacketHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketHooks")
+            .field("on_send", &self.on_send.is_some())
+            .field("on_receive", &self.on_receive.is_some())
+            .finish()
+    }
+}
+
+/// Shows the hooks every packet that goes through the transport
+pub(crate) struct HookedTransport<'t> {
+    transport: &'t mut dyn Transport,
+    hooks: PacketHooks,
+    /// The IPv6 address probes last went out from, which IPv6 responses are taken to be sent to
+    ipv6_source: Ipv6Addr,
+    ttl: u8,
+}
+
+impl<'t> HookedTransport<'t> {
+    pub(crate) fn new(transport: &'t mut dyn Transport, hooks: PacketHooks) -> Self {
+        Self { transport, hooks, ipv6_source: Ipv6Addr::UNSPECIFIED, ttl: PROBE_TTL }
+    }
+
+    fn sent(&self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8], decoy: bool) {
+        if let Some(on_send) = &self.hooks.on_send {
+            let mut info = transport_info(channel, source, remote.ip(), packet);
+            info.ttl = Some(self.ttl);
+            info.decoy = decoy;
+
+            on_send(&info);
+        }
+    }
+}
+
+impl Transport for HookedTransport<'_> {
+    fn set_batching(&mut self, batch_size: usize, batched_io: bool) {
+        self.transport.set_batching(batch_size, batched_io);
+    }
+
+    fn set_fragmentation(&mut self, fragment_size: Option<usize>) -> anyhow::Result<()> {
+        self.transport.set_fragmentation(fragment_size)
+    }
+
+    fn set_ttl(&mut self, ttl: Option<u8>) -> anyhow::Result<()> {
+        self.ttl = ttl.unwrap_or(PROBE_TTL);
+        self.transport.set_ttl(ttl)
+    }
+
+    fn queue(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        if !self.transport.queue(channel, source, remote, packet) {
+            return false;
+        }
+
+        if let IpAddr::V6(source) = source {
+            self.ipv6_source = source;
+        }
+        self.sent(channel, source, remote, packet, false);
+
+        true
+    }
+
+    fn send_decoy(&mut self, channel: Channel, decoy: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        if !self.transport.send_decoy(channel, decoy, remote, packet) {
+            return false;
+        }
+
+        self.sent(channel, decoy, remote, packet, true);
+
+        true
+    }
+
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        self.transport.flush()
+    }
+
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>> {
+        let received = self.transport.try_recv(channel)?;
+
+        if let (Some(on_receive), Some((datagram, remote))) = (&self.hooks.on_receive, &received) {
+            let info = match remote {
+                // IPv4 datagrams come with their IP header already
+                IpAddr::V4(_) => ipv4_info(channel, datagram),
+                IpAddr::V6(_) => Some(transport_info(channel, *remote, IpAddr::V6(self.ipv6_source), datagram)),
+            };
+
+            if let Some(info) = info {
+                on_receive(&info);
+            }
+        }
+
+        Ok(received)
+    }
+}
+
+/// Picks apart an IPv4 packet, as long as its header is all there
+fn ipv4_info(channel: Channel, datagram: &[u8]) -> Option<PacketInfo<'_>> {
+    let header_length = (*datagram.first()? as usize & 0x0f) * 4;
+    if header_length < 20 || datagram.len() < header_length {
+        return None;
+    }
+
+    let source = Ipv4Addr::new(datagram[12], datagram[13], datagram[14], datagram[15]);
+    let destination = Ipv4Addr::new(datagram[16], datagram[17], datagram[18], datagram[19]);
+
+    let mut info = transport_info(channel, source.into(), destination.into(), &datagram[header_length..]);
+    info.ttl = Some(datagram[8]);
+
+    Some(info)
+}
+
+/// Picks apart the transport layer header starting the packet, leaving out whatever of it is cut off
+fn transport_info(channel: Channel, source: IpAddr, destination: IpAddr, packet: &[u8]) -> PacketInfo<'_> {
+    let protocol = match channel {
+        Channel::Ipv4Tcp | Channel::Ipv6Tcp => PacketProtocol::Tcp,
+        Channel::Ipv4Sctp | Channel::Ipv6Sctp => PacketProtocol::Sctp,
+        Channel::Ipv4Icmp => PacketProtocol::Icmp,
+        Channel::Ipv6Icmp => PacketProtocol::Icmpv6,
+    };
+    let port = |offset: usize| packet.get(offset..offset + 2).map(|port| u16::from_be_bytes([port[0], port[1]]));
+    let has_ports = matches!(protocol, PacketProtocol::Tcp | PacketProtocol::Sctp);
+
+    PacketInfo {
+        protocol,
+        source,
+        destination,
+        source_port: port(0).filter(|_| has_ports),
+        destination_port: port(2).filter(|_| has_ports),
+        tcp_flags: packet.get(13).copied().filter(|_| protocol == PacketProtocol::Tcp),
+        ttl: None,
+        decoy: false,
+        transport_packet: packet,
+    }
+}
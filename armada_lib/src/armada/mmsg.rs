@@ -0,0 +1,285 @@
+use std::net::{IpAddr, SocketAddr};
+use std::{io, mem};
+
+use pnet::transport::{TransportReceiver, TransportSender};
+use pnet_sys::{SockAddrStorage, SockLen};
+
+use crate::armada::packet::MAX_TCP_PROBE_LENGTH;
+#[cfg(target_os = "linux")]
+use crate::armada::pktinfo::SourceControl;
+
+/// Fits both the largest TCP probe and an SCTP INIT
+const SEND_SLOT_LENGTH: usize = MAX_TCP_PROBE_LENGTH;
+/// Responses longer than this are cut short, which only ever loses the tail of a quote in an ICMP error
+const RECEIVE_SLOT_LENGTH: usize = 2048;
+
+/// Probes queued up to go out on one socket with as few system calls as possible
+pub(crate) struct SendBatch {
+    capacity: usize,
+    packets: Vec<u8>,
+    lengths: Vec<usize>,
+    /// The address each packet goes out from, where the platform lets us pick it
+    sources: Vec<IpAddr>,
+    remotes: Vec<SocketAddr>,
+}
+
+impl SendBatch {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            packets: vec![0; capacity * SEND_SLOT_LENGTH],
+            lengths: Vec::with_capacity(capacity),
+            sources: Vec::with_capacity(capacity),
+            remotes: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Queues a copy of the packet, returning false if the batch is already full
+    pub(crate) fn push(&mut self, packet: &[u8], source: IpAddr, remote: SocketAddr) -> bool {
+        let slot = self.remotes.len();
+
+        if slot == self.capacity || packet.len() > SEND_SLOT_LENGTH {
+            return false;
+        }
+
+        self.packets[slot * SEND_SLOT_LENGTH..][..packet.len()].copy_from_slice(packet);
+        self.lengths.push(packet.len());
+        self.sources.push(source);
+        self.remotes.push(remote);
+
+        true
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.remotes.is_empty()
+    }
+
+    /// Sends every queued packet, returning the remotes whose packets went out followed by the ones whose didn't.
+    /// The batch is empty afterwards.
+    pub(crate) fn flush(&mut self, sender: &TransportSender) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        let sent = if self.remotes.is_empty() { 0 } else { self.send(sender.socket.fd) };
+
+        let unsent = self.remotes.split_off(sent);
+        let sent = mem::take(&mut self.remotes);
+        self.remotes.reserve(self.capacity);
+        self.lengths.clear();
+        self.sources.clear();
+
+        (sent, unsent)
+    }
+
+    /// Hands the packets to the kernel in one `sendmmsg` call, or as many as it takes if it stops partway
+    #[cfg(target_os = "linux")]
+    fn send(&mut self, fd: libc::c_int) -> usize {
+        let count = self.remotes.len();
+        let mut destinations: Vec<SockAddrStorage> = vec![unsafe { mem::zeroed() }; count];
+        let mut destination_lengths = Vec::with_capacity(count);
+        let mut iovecs = Vec::with_capacity(count);
+        let mut controls = vec![SourceControl::default(); count];
+
+        for (slot, remote) in self.remotes.iter().enumerate() {
+            // raw sockets take the protocol from the socket, so the destination's port must be left out
+            let destination = SocketAddr::new(remote.ip(), 0);
+            destination_lengths.push(pnet_sys::addr_to_sockaddr(destination, &mut destinations[slot]));
+
+            iovecs.push(libc::iovec {
+                iov_base: self.packets[slot * SEND_SLOT_LENGTH..].as_mut_ptr() as *mut libc::c_void,
+                iov_len: self.lengths[slot],
+            });
+        }
+
+        // the headers point into the vecs above, none of which move or change size from here on
+        let mut headers: Vec<libc::mmsghdr> = (0..count)
+            .map(|slot| {
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = &mut destinations[slot] as *mut SockAddrStorage as *mut libc::c_void;
+                header.msg_hdr.msg_namelen = destination_lengths[slot] as SockLen;
+                header.msg_hdr.msg_iov = &mut iovecs[slot];
+                header.msg_hdr.msg_iovlen = 1;
+                controls[slot].attach(&mut header.msg_hdr, self.sources[slot]);
+
+                header
+            })
+            .collect();
+
+        let mut sent = 0;
+        while sent < count {
+            let result = unsafe {
+                libc::sendmmsg(fd, headers[sent..].as_mut_ptr(), (count - sent) as libc::c_uint, 0)
+            };
+
+            if result <= 0 {
+                break;
+            }
+
+            sent += result as usize;
+        }
+
+        sent
+    }
+
+    /// Sends the packets one `sendto` call at a time, where there's no `sendmmsg`
+    #[cfg(not(target_os = "linux"))]
+    fn send(&mut self, fd: libc::c_int) -> usize {
+        let mut sent = 0;
+
+        for (slot, remote) in self.remotes.iter().enumerate() {
+            let mut destination: SockAddrStorage = unsafe { mem::zeroed() };
+            let destination_length = pnet_sys::addr_to_sockaddr(SocketAddr::new(remote.ip(), 0), &mut destination);
+            let packet = &self.packets[slot * SEND_SLOT_LENGTH..][..self.lengths[slot]];
+
+            let result = unsafe {
+                libc::sendto(
+                    fd,
+                    packet.as_ptr() as pnet_sys::Buf,
+                    packet.len() as pnet_sys::BufLen,
+                    0,
+                    &destination as *const SockAddrStorage as *const pnet_sys::SockAddr,
+                    destination_length,
+                )
+            };
+
+            if result < 0 {
+                break;
+            }
+
+            sent += 1;
+        }
+
+        sent
+    }
+}
+
+/// Responses read off of a socket ahead of time, `recvmmsg` filling the batch whenever it runs dry
+pub(crate) struct ReceiveBatch {
+    buffers: Vec<u8>,
+    lengths: Vec<usize>,
+    senders: Vec<SockAddrStorage>,
+    /// Point into the buffers and senders, whose memory never moves since the vecs never change size
+    #[cfg(target_os = "linux")]
+    iovecs: Vec<libc::iovec>,
+    #[cfg(target_os = "linux")]
+    headers: Vec<libc::mmsghdr>,
+    /// How many of the slots hold responses, and which one is handed out next
+    filled: usize,
+    next: usize,
+}
+
+impl ReceiveBatch {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let mut batch = Self {
+            buffers: vec![0; capacity * RECEIVE_SLOT_LENGTH],
+            lengths: vec![0; capacity],
+            senders: vec![unsafe { mem::zeroed() }; capacity],
+            #[cfg(target_os = "linux")]
+            iovecs: Vec::with_capacity(capacity),
+            #[cfg(target_os = "linux")]
+            headers: Vec::with_capacity(capacity),
+            filled: 0,
+            next: 0,
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            batch.iovecs.extend(batch.buffers.chunks_exact_mut(RECEIVE_SLOT_LENGTH).map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            }));
+
+            for slot in 0..capacity {
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = &mut batch.senders[slot] as *mut SockAddrStorage as *mut libc::c_void;
+                header.msg_hdr.msg_iov = &mut batch.iovecs[slot];
+                header.msg_hdr.msg_iovlen = 1;
+
+                batch.headers.push(header);
+            }
+        }
+
+        batch
+    }
+
+    /// Hands out the next response without blocking, along with its sender
+    pub(crate) fn try_recv(&mut self, receiver: &TransportReceiver) -> io::Result<Option<(&[u8], IpAddr)>> {
+        if self.next == self.filled {
+            self.next = 0;
+            self.filled = self.receive(receiver.socket.fd)?;
+
+            if self.filled == 0 {
+                return Ok(None);
+            }
+        }
+
+        let slot = self.next;
+        self.next += 1;
+
+        let length = self.lengths[slot].min(RECEIVE_SLOT_LENGTH);
+        let sender = pnet_sys::sockaddr_to_addr(&self.senders[slot], mem::size_of::<SockAddrStorage>())?;
+
+        Ok(Some((&self.buffers[slot * RECEIVE_SLOT_LENGTH..][..length], sender.ip())))
+    }
+
+    /// Fills the batch with one `recvmmsg` call, returning how many responses came in
+    #[cfg(target_os = "linux")]
+    fn receive(&mut self, fd: libc::c_int) -> io::Result<usize> {
+        // the kernel shortens these to the sender address it wrote
+        for header in &mut self.headers {
+            header.msg_hdr.msg_namelen = mem::size_of::<SockAddrStorage>() as SockLen;
+        }
+
+        let received = unsafe {
+            libc::recvmmsg(
+                fd,
+                self.headers.as_mut_ptr(),
+                self.headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT, // nonblocking so we just take what's already there
+                std::ptr::null_mut(),
+            )
+        };
+
+        if received < 0 {
+            let error = io::Error::last_os_error();
+
+            return match error.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(0),
+                _ => Err(error),
+            };
+        }
+
+        for (length, header) in self.lengths.iter_mut().zip(&self.headers).take(received as usize) {
+            *length = header.msg_len as usize;
+        }
+
+        Ok(received as usize)
+    }
+
+    /// Fills the batch one `recvfrom` call at a time, where there's no `recvmmsg`
+    #[cfg(not(target_os = "linux"))]
+    fn receive(&mut self, fd: libc::c_int) -> io::Result<usize> {
+        let mut received = 0;
+
+        for (slot, buffer) in self.buffers.chunks_exact_mut(RECEIVE_SLOT_LENGTH).enumerate() {
+            let mut sender_length = mem::size_of::<SockAddrStorage>() as SockLen;
+
+            let length = unsafe {
+                libc::recvfrom(
+                    fd,
+                    buffer.as_mut_ptr() as pnet_sys::MutBuf,
+                    buffer.len() as pnet_sys::BufLen,
+                    libc::MSG_DONTWAIT,
+                    &mut self.senders[slot] as *mut SockAddrStorage as *mut pnet_sys::SockAddr,
+                    &mut sender_length,
+                )
+            };
+
+            if length < 0 {
+                break;
+            }
+
+            self.lengths[slot] = length as usize;
+            received += 1;
+        }
+
+        Ok(received)
+    }
+}
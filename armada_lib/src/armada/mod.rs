@@ -1,8 +1,48 @@
+pub mod analyze;
+#[cfg(unix)]
+mod arp;
+pub mod banner;
+pub mod capture;
+#[cfg(unix)]
+mod channels;
 pub mod config;
+pub mod connection;
+mod control;
+mod datalink;
+#[cfg(unix)]
+mod discovery;
+pub mod fingerprint;
+mod frame;
+pub mod hooks;
+#[cfg(unix)]
+mod mmsg;
+mod neighbor;
 mod packet;
+mod permutation;
+#[cfg(target_os = "linux")]
+mod pktinfo;
+mod rate;
+mod rtt;
+mod response;
+pub mod result;
+pub mod scan_type;
+mod scanner;
+pub mod service;
+mod sink;
+mod source;
+#[cfg(target_os = "linux")]
+mod socket_filter;
 mod tcp_ext;
+pub mod tls;
+mod transport;
+#[cfg(target_os = "linux")]
+mod uring;
 pub mod work;
 mod worker;
+#[cfg(target_os = "linux")]
+mod xdp;
+
+use std::sync::Arc;
 
 use tokio::sync::mpsc::{
     unbounded_channel,
@@ -12,38 +52,232 @@ use tokio::sync::mpsc::{
 
 use crate::armada::config::host::HostIterator;
 use crate::armada::config::port::PortIterator;
+use crate::armada::config::scan::ScanOptions;
+use crate::armada::control::ScanControls;
+#[cfg(unix)]
+use crate::armada::discovery::DiscoveryWorker;
+use crate::armada::result::{LiveHost, PortResult, ScanCoverage, ScanReport};
+pub use crate::armada::rate::RateLimiter;
+pub use crate::armada::scanner::Scanner;
+pub use crate::armada::sink::ResultSink;
+pub use crate::armada::transport::Engine;
 use crate::armada::work::{ArmadaWork, ArmadaWorkMessage};
 use crate::armada::worker::ArmadaWorker;
-use futures::stream::StreamExt;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::time::Duration;
-use anyhow::Context;
+#[cfg(unix)]
+use crate::utils::get_on_link_ipv4_networks;
+use crate::utils::pin_current_thread;
+use futures::stream::{Stream, StreamExt};
+use anyhow::{bail, Context};
 use tokio_stream::wrappers::UnboundedReceiverStream;
-use tracing::error;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
 
 /// High performance port scanner
 #[derive(Clone)]
 pub struct Armada {
-    // raw socket
-    work_sender: UnboundedSender<ArmadaWork>,
+    // raw socket, one worker per listening port
+    work_senders: Vec<UnboundedSender<ArmadaWork>>,
+    /// The first listening port, which discovery listens on
+    #[cfg_attr(not(unix), allow(dead_code))]
+    listening_port: u16,
+    /// The interface discovery is bound to as well as the workers
+    #[cfg_attr(not(unix), allow(dead_code))]
+    interface: Option<String>,
+    controls: Arc<ScanControls>,
+}
+
+/// Settings for how an `Armada` sends and receives, which hold for every scan it runs
+#[derive(Clone, Debug)]
+pub struct ArmadaOptions {
+    /// A worker is run for each, each sending and receiving on sockets of its own in parallel with the others. Every
+    /// scan is split evenly across the workers and their results merged back into one stream. The XDP engine only
+    /// takes a single listening port.
+    pub listening_ports: Vec<u16>,
+    /// What probes are sent and responses received through. Discovery always goes through raw sockets.
+    pub engine: Engine,
+    /// Each worker is pinned to one of the cores, going round them in order when there are more workers than cores.
+    /// Workers set up their sockets and buffers once pinned, so those end up on the memory of the core's NUMA node.
+    /// No cores leaves the workers to the scheduler. Pinning is Linux only.
+    pub cores: Vec<usize>,
+    /// The network interface raw sockets are bound to, for both port scans and discovery, so probes go out on it
+    /// rather than wherever the routing table points. `None` leaves it to the routing table. Binding is Linux only.
+    pub interface: Option<String>,
 }
 
 impl Armada {
     // todo: add options
     pub fn new(listening_port: u16) -> Self {
-        let (work_sender, work_receiver) = unbounded_channel();
+        let options = ArmadaOptions {
+            listening_ports: vec![listening_port],
+            engine: Engine::RawSocket,
+            cores: Vec::new(),
+            interface: None,
+        };
+
+        Self::spawn(&options).0
+    }
 
-        let armada_worker = ArmadaWorker::new(work_receiver);
+    /// Scans through the engine rather than raw sockets. Unlike `new`, this waits for the engine to be set up and
+    /// fails if it can't be, e.g. for lack of privileges. Discovery always goes through raw sockets.
+    pub fn with_engine(listening_port: u16, engine: Engine) -> anyhow::Result<Self> {
+        Self::with_listening_ports(&[listening_port], engine)
+    }
 
-        std::thread::Builder::new()
-            .name("armada_worker".to_string())
-            .spawn(move || {
-                if let Err(e) = armada_worker.run(listening_port) {
-                    error!(err = ?e);
-                }
-            }).expect("Failed to create armada worker thread.");
+    /// Runs a worker for each listening port, each sending and receiving on sockets of its own in parallel with the
+    /// others. Every scan is split evenly across the workers and their results merged back into one stream. The XDP
+    /// engine only takes a single listening port.
+    pub fn with_listening_ports(listening_ports: &[u16], engine: Engine) -> anyhow::Result<Self> {
+        Self::with_pinned_workers(listening_ports, engine, &[])
+    }
+
+    /// Like `with_listening_ports`, but with each worker pinned to one of the cores, going round them in order when
+    /// there are more workers than cores. Workers set up their sockets and buffers once pinned, so those end up on
+    /// the memory of the core's NUMA node. No cores leaves the workers to the scheduler. Pinning is Linux only.
+    pub fn with_pinned_workers(listening_ports: &[u16], engine: Engine, cores: &[usize]) -> anyhow::Result<Self> {
+        Self::with_options(ArmadaOptions {
+            listening_ports: listening_ports.to_vec(),
+            engine,
+            cores: cores.to_vec(),
+            interface: None,
+        })
+    }
+
+    /// Sets up the workers as the options say, waiting for every one of them to be ready. Fails if any can't be, e.g.
+    /// for lack of privileges or an interface that doesn't exist.
+    pub fn with_options(options: ArmadaOptions) -> anyhow::Result<Self> {
+        if options.listening_ports.is_empty() {
+            bail!("At least one listening port is needed.");
+        }
+
+        if matches!(options.engine, Engine::Xdp { .. }) && options.listening_ports.len() > 1 {
+            bail!("The XDP engine only takes a single listening port.");
+        }
+
+        let (armada, opened) = Self::spawn(&options);
+
+        for opened in opened {
+            opened.recv().context("Armada worker stopped unexpectedly.")??;
+        }
+
+        Ok(armada)
+    }
 
-        Self { work_sender }
+    /// Starts a worker thread per listening port, each of which reports back once it's pinned and its engine is set up
+    fn spawn(options: &ArmadaOptions) -> (Self, Vec<std::sync::mpsc::Receiver<anyhow::Result<()>>>) {
+        let ArmadaOptions { listening_ports, engine, cores, interface } = options;
+
+        let controls = Arc::new(ScanControls::default());
+        let mut work_senders = Vec::with_capacity(listening_ports.len());
+        let mut opened_receivers = Vec::with_capacity(listening_ports.len());
+
+        for (index, &listening_port) in listening_ports.iter().enumerate() {
+            let (work_sender, work_receiver) = unbounded_channel();
+            let (opened_sender, opened_receiver) = std::sync::mpsc::channel();
+
+            let armada_worker = ArmadaWorker::new(work_receiver, listening_port, controls.clone());
+            let engine = engine.clone();
+            let interface = interface.clone();
+            let core = (!cores.is_empty()).then(|| cores[index % cores.len()]);
+
+            std::thread::Builder::new()
+                .name("armada_worker".to_string())
+                .spawn(move || {
+                    let opened = core
+                        .map_or(Ok(()), pin_current_thread)
+                        .and_then(|_| transport::open(&engine, listening_port, interface.as_deref()));
+
+                    let transport = match opened {
+                        Ok(transport) => transport,
+                        Err(e) => {
+                            error!(err = ?e);
+                            let _ = opened_sender.send(Err(e));
+                            return;
+                        }
+                    };
+                    info!(engine = ?engine, listening_port, core = ?core, interface = interface.as_deref().unwrap_or("any"), "worker ready");
+                    let _ = opened_sender.send(Ok(()));
+
+                    if let Err(e) = armada_worker.run(transport) {
+                        error!(err = ?e);
+                    }
+                }).expect("Failed to create armada worker thread.");
+
+            work_senders.push(work_sender);
+            opened_receivers.push(opened_receiver);
+        }
+
+        let armada = Self { work_senders, listening_port: listening_ports[0], interface: interface.clone(), controls };
+
+        (armada, opened_receivers)
+    }
+
+    /// Stops sending port scan probes until `resume` is called. Responses to probes already sent are still received, so
+    /// nothing is lost and the scan carries on where it was once resumed.
+    pub fn pause(&self) {
+        self.controls.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.controls.set_paused(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.controls.is_paused()
+    }
+
+    /// Stops sending port scan probes for good, retries included. Running scans end once the probes in flight are
+    /// answered or time out, with the results they found so far, and scans started afterwards don't send anything.
+    pub fn stop(&self) {
+        self.controls.stop();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.controls.is_stopped()
+    }
+
+    /// Holds running port scans, and the ones started afterwards, to this many packets per second in place of their
+    /// own rate limit. It's split across the workers like any rate limit, though never below 10 packets per second
+    /// for each of them, and scans with auto rate stop adjusting it.
+    pub fn set_rate_limit(&self, packets_per_second: usize) {
+        self.controls.set_rate_limit(packets_per_second);
+    }
+
+    /// Leaves the scans started afterwards to their own rate limit again. A running scan keeps the rate it was held to.
+    pub fn clear_rate_limit(&self) {
+        self.controls.clear_rate_limit();
+    }
+
+    /// The rate limit set with `set_rate_limit`, if it's been set
+    pub fn rate_limit(&self) -> Option<usize> {
+        self.controls.rate_limit()
+    }
+
+    /// Probes the hosts with ICMP echo and timestamp requests and TCP SYNs to ports 80 and 443, returning only the
+    /// hosts that answered. Running this ahead of a port scan avoids wasting packets on empty parts of sparse ranges.
+    /// IPv4 hosts on a directly attached network are resolved with ARP instead, which also yields their MAC address.
+    /// The sources, retries, timeout and rate limit of the options are honored, the scan type is not.
+    #[cfg(unix)]
+    pub async fn discover(&self, remote_hosts: HostIterator, options: ScanOptions) -> anyhow::Result<Vec<LiveHost>> {
+        let discovery_worker = DiscoveryWorker::new(self.listening_port, self.interface.clone());
+        let mut on_link_networks = get_on_link_ipv4_networks().await;
+        if let Some(interface) = &self.interface {
+            on_link_networks.retain(|on_link_network| on_link_network.interface_name.eq(interface));
+        }
+
+        let mut live_hosts =
+            tokio::task::spawn_blocking(move || discovery_worker.run(remote_hosts, options, on_link_networks))
+                .await
+                .context("Discovery worker stopped unexpectedly.")??;
+
+        live_hosts.sort_by_key(|live_host| live_host.ip);
+
+        Ok(live_hosts)
+    }
+
+    /// Discovery goes through raw sockets, which Windows doesn't send TCP over
+    #[cfg(not(unix))]
+    pub async fn discover(&self, _remote_hosts: HostIterator, _options: ScanOptions) -> anyhow::Result<Vec<LiveHost>> {
+        anyhow::bail!("Host discovery isn't available on Windows, whose raw sockets don't send TCP.")
     }
 
     /// Initiates a port scan and returns the final port scan results.
@@ -51,34 +285,73 @@ impl Armada {
         &self,
         remote_hosts: HostIterator,
         ports: PortIterator,
-        source_ipv4_addrs: Vec<Ipv4Addr>,
-        source_ipv6_addrs: Vec<Ipv6Addr>,
-        port_retries: u8,
-        port_timeout: Duration,
-        packets_per_second: Option<usize>,
-    ) -> anyhow::Result<Vec<SocketAddr>> {
-        let armada_work_results_handle = self.scan_with_handle(
-            remote_hosts,
-            ports,
-            source_ipv4_addrs,
-            source_ipv6_addrs,
-            port_retries,
-            port_timeout,
-            packets_per_second
-        )?;
-
-        // receive all of the reports, filter out non-result messages, and flatten the result list
-        let results = UnboundedReceiverStream::new(armada_work_results_handle)
-            .filter_map(|report| async move {
-                if let ArmadaWorkMessage::Results(results) = report {
-                    Some(futures::stream::iter(results))
-                } else {
-                    None
+        options: ScanOptions,
+    ) -> anyhow::Result<Vec<PortResult>> {
+        Ok(self.scan_stream(remote_hosts, ports, options)?.collect().await)
+    }
+
+    /// Runs a port scan until it's done or the token is cancelled. Once it's cancelled nothing more is sent, the probes
+    /// in flight still get their timeout to be answered in, and the results found by then come back along with how
+    /// much of the scan they cover. Fails if a worker does, once the rest of the scan is over.
+    pub async fn scan_until_cancelled(
+        &self,
+        remote_hosts: HostIterator,
+        ports: PortIterator,
+        mut options: ScanOptions,
+        cancellation: CancellationToken,
+    ) -> anyhow::Result<ScanReport> {
+        let total_pairs = options.probed_pairs(remote_hosts.size() * ports.size() as u128);
+        let skipped_pairs = options.probed_pairs(options.skip_pairs);
+        options.cancellation = Some(cancellation.clone());
+
+        let mut reporting_handle = self.scan_with_handle(remote_hosts, ports, options)?;
+        let mut results = Vec::new();
+        let mut coverage = ScanCoverage {
+            total_pairs,
+            settled_pairs: skipped_pairs.min(total_pairs),
+            scan_cursor: skipped_pairs,
+            ..ScanCoverage::default()
+        };
+
+        let mut failure = None;
+
+        while let Some(message) = reporting_handle.recv().await {
+            match message {
+                ArmadaWorkMessage::Results(found) => results.extend(found),
+                ArmadaWorkMessage::Failed(error) => failure = failure.or(Some(error)),
+                ArmadaWorkMessage::Stats { total_processed_ports, total_packets_sent, total_retries, scan_cursor, .. } => {
+                    coverage.settled_pairs = (skipped_pairs + total_processed_ports).min(total_pairs);
+                    coverage.packets_sent = total_packets_sent;
+                    coverage.retries = total_retries;
+                    coverage.scan_cursor = scan_cursor;
                 }
+            }
+        }
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
+        coverage.cancelled = cancellation.is_cancelled() && !coverage.is_complete();
+
+        Ok(ScanReport { results, coverage })
+    }
+
+    /// Initiates a port scan and returns a stream of its results as they come in, which ends with the scan. Dropping
+    /// the stream abandons the scan. A worker that fails only logs it, and the stream ends without its share.
+    pub fn scan_stream(
+        &self,
+        remote_hosts: HostIterator,
+        ports: PortIterator,
+        options: ScanOptions,
+    ) -> anyhow::Result<impl Stream<Item = PortResult> + Send + Unpin + 'static> {
+        let armada_work_results_handle = self.scan_with_handle(remote_hosts, ports, options)?;
+
+        // filter out non-result messages and flatten the result lists
+        let results = UnboundedReceiverStream::new(armada_work_results_handle).flat_map(|report| {
+            futures::stream::iter(match report {
+                ArmadaWorkMessage::Results(results) => results,
+                ArmadaWorkMessage::Stats { .. } | ArmadaWorkMessage::Failed(_) => Vec::new(),
             })
-            .flatten()
-            .collect()
-            .await;
+        });
 
         Ok(results)
     }
@@ -88,28 +361,15 @@ impl Armada {
         &self,
         remote_hosts: HostIterator,
         ports: PortIterator,
-        source_ipv4_addrs: Vec<Ipv4Addr>,
-        source_ipv6_addrs: Vec<Ipv6Addr>,
-        port_retries: u8,
-        port_timeout: Duration,
-        packets_per_second: Option<usize>,
+        options: ScanOptions,
     ) -> anyhow::Result<UnboundedReceiver<ArmadaWorkMessage>> {
         let (reporting_channel, report_receiver) = unbounded_channel();
 
-        let work = ArmadaWork::new(
-            remote_hosts,
-            ports,
-            port_retries,
-            port_timeout,
-            packets_per_second,
-            source_ipv4_addrs,
-            source_ipv6_addrs,
-            reporting_channel,
-        );
-
-        let _ = self.work_sender
-            .send(work)
-            .context("Failed to send armada work over work sender channel.")?;
+        for work in ArmadaWork::split(remote_hosts, ports, options, reporting_channel, self.work_senders.len()) {
+            self.work_senders[work.shard.index]
+                .send(work)
+                .context("Failed to send armada work over work sender channel.")?;
+        }
 
         Ok(report_receiver)
     }
@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use pnet::util::MacAddr;
+use regex::Regex;
+use tracing::warn;
+
+use crate::utils::run_command;
+
+/// How often the neighbor table is read again while there are next hops we have no link layer address for
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// The discard port, which is as good as any for getting the kernel to resolve a next hop
+const RESOLUTION_PORT: u16 = 9;
+
+/// A route through the interface, to a network either on the link or behind a gateway
+struct Route {
+    network: IpAddr,
+    prefix_length: u32,
+    gateway: Option<IpAddr>,
+}
+
+impl Route {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_length).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_length).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Works out which link layer address frames to a remote go to: the remote's own when it's on the link, and the
+/// gateway's when it's behind one. Addresses come from the kernel's routing and neighbor tables, since probes framed
+/// by us skip the part of the stack that would look them up. Where there's no `ip` to read them with, like on Windows,
+/// the tables are empty and every frame goes to `router_mac`.
+pub(crate) struct NeighborTable {
+    interface_name: String,
+    routes: Vec<Route>,
+    neighbors: HashMap<IpAddr, MacAddr>,
+    /// Next hops the kernel has been asked to resolve, which are only warned about once
+    unresolved: HashSet<IpAddr>,
+    last_refresh: Instant,
+    /// Where frames to remotes behind a gateway, or without a route, go instead of the gateway's address
+    router_mac: Option<MacAddr>,
+    /// Where frames to remotes on the link go instead of their own address
+    target_mac: Option<MacAddr>,
+}
+
+impl NeighborTable {
+    pub(crate) fn load(interface_name: &str) -> Self {
+        let mut routes = Vec::new();
+        routes.extend(read_routes(interface_name, "-4"));
+        routes.extend(read_routes(interface_name, "-6"));
+
+        // the most specific route wins, so those are checked first
+        routes.sort_by_key(|route| std::cmp::Reverse(route.prefix_length));
+
+        Self {
+            interface_name: interface_name.to_string(),
+            routes,
+            neighbors: read_neighbors(interface_name),
+            unresolved: HashSet::new(),
+            last_refresh: Instant::now(),
+            router_mac: None,
+            target_mac: None,
+        }
+    }
+
+    pub(crate) fn with_router_mac(mut self, router_mac: Option<MacAddr>) -> Self {
+        self.router_mac = router_mac;
+        self
+    }
+
+    pub(crate) fn with_target_mac(mut self, target_mac: Option<MacAddr>) -> Self {
+        self.target_mac = target_mac;
+        self
+    }
+
+    /// The link layer address frames to the remote should go to, if it's known yet. Unknown next hops are handed to
+    /// the kernel to resolve, so they're likely to be known once the probe is retried. Addresses we were given win
+    /// over the kernel's.
+    pub(crate) fn next_hop_mac(&mut self, remote: IpAddr) -> Option<MacAddr> {
+        let next_hop = match (self.next_hop(remote), self.router_mac, self.target_mac) {
+            (Some(next_hop), _, Some(target_mac)) if next_hop == remote => return Some(target_mac),
+            (Some(next_hop), Some(router_mac), _) if next_hop != remote => return Some(router_mac),
+            (None, Some(router_mac), _) => return Some(router_mac),
+            (next_hop, _, _) => next_hop?,
+        };
+
+        if let Some(mac) = self.neighbors.get(&next_hop) {
+            return Some(*mac);
+        }
+
+        if self.last_refresh.elapsed() >= REFRESH_INTERVAL {
+            self.neighbors = read_neighbors(&self.interface_name);
+            self.last_refresh = Instant::now();
+
+            if let Some(mac) = self.neighbors.get(&next_hop) {
+                return Some(*mac);
+            }
+        }
+
+        if self.unresolved.insert(next_hop) {
+            warn!(
+                "No link layer address for {} on {} yet. Probes through it are dropped until the kernel resolves it.",
+                next_hop, self.interface_name
+            );
+            solicit(next_hop);
+        }
+
+        None
+    }
+
+    /// The gateway of the most specific route to the remote, or the remote itself when it's on the link
+    fn next_hop(&self, remote: IpAddr) -> Option<IpAddr> {
+        let route = self.routes.iter().find(|route| route.contains(remote))?;
+
+        Some(route.gateway.unwrap_or(remote))
+    }
+}
+
+/// Sends the next hop a datagram it'll never answer, which has the kernel resolve its link layer address on the way
+fn solicit(next_hop: IpAddr) {
+    let bind_address = match next_hop {
+        IpAddr::V4(_) => "0.0.0.0:0",
+        IpAddr::V6(_) => "[::]:0",
+    };
+
+    if let Ok(socket) = UdpSocket::bind(bind_address) {
+        let _ = socket.send_to(&[], (next_hop, RESOLUTION_PORT));
+    }
+}
+
+/// Reads the routes through the interface, `family` being `-4` or `-6`, from `ip` or else from `netstat` where there's
+/// no `ip`, like on macOS
+fn read_routes(interface_name: &str, family: &str) -> Vec<Route> {
+    match run_command("ip", &[family, "-o", "route", "show", "dev", interface_name]) {
+        Some(ip_output) => parse_ip_routes(&ip_output, family),
+        None => {
+            let address_family = if family == "-4" { "inet" } else { "inet6" };
+
+            parse_netstat_routes(&run_command("netstat", &["-rn", "-f", address_family]).unwrap_or_default(), interface_name)
+        }
+    }
+}
+
+fn parse_ip_routes(ip_output: &str, family: &str) -> Vec<Route> {
+    // parse out `198.51.100.0/24 via 10.99.0.2 ...`, or `default via 192.0.2.1 ...`
+    let route_regex = match Regex::new(r"^(\S+)(?:.*\bvia (\S+))?") {
+        Ok(route_regex) => route_regex,
+        Err(_) => return Vec::new(),
+    };
+
+    ip_output
+        .lines()
+        .filter_map(|route| route_regex.captures(route))
+        .filter_map(|captures| {
+            let destination = captures.get(1)?.as_str();
+            let gateway = captures.get(2).and_then(|gateway| IpAddr::from_str(gateway.as_str()).ok());
+
+            let (network, prefix_length) = match destination {
+                "default" if family == "-4" => (IpAddr::from([0u8; 4]), 0),
+                "default" => (IpAddr::from([0u8; 16]), 0),
+                destination => match destination.split_once('/') {
+                    Some((network, prefix_length)) => (IpAddr::from_str(network).ok()?, prefix_length.parse().ok()?),
+                    None => {
+                        let network = IpAddr::from_str(destination).ok()?;
+                        (network, if network.is_ipv4() { 32 } else { 128 })
+                    }
+                },
+            };
+
+            Some(Route { network, prefix_length, gateway })
+        })
+        .collect()
+}
+
+/// Parses the BSD routing table's `Destination Gateway Flags Netif ...` rows out of the interface. Destinations leave
+/// off the zero octets at their end, e.g. `192.168.1` for 192.168.1.0/24, and gateways on the link are given as
+/// `link#6` or a MAC address rather than an IP.
+fn parse_netstat_routes(netstat_output: &str, interface_name: &str) -> Vec<Route> {
+    netstat_output
+        .lines()
+        .filter_map(|route| {
+            let fields: Vec<&str> = route.split_whitespace().collect();
+            let (destination, gateway, flags, interface) = (fields.first()?, fields.get(1)?, fields.get(2)?, fields.get(3)?);
+            if *interface != interface_name {
+                return None;
+            }
+
+            let ipv6 = destination.contains(':') || parse_bsd_address(gateway).is_some_and(|gateway| gateway.is_ipv6());
+            let (network, prefix_length) = match (destination.split_once('/'), *destination) {
+                (_, "default") if ipv6 => (IpAddr::from([0u8; 16]), 0),
+                (_, "default") => (IpAddr::from([0u8; 4]), 0),
+                (Some((network, prefix_length)), _) => (parse_bsd_address(network)?, prefix_length.parse().ok()?),
+                (None, destination) => {
+                    let network = parse_bsd_address(destination)?;
+                    let prefix_length = match network {
+                        IpAddr::V4(_) if !flags.contains('H') => 8 * destination.split('.').count() as u32,
+                        IpAddr::V4(_) => 32,
+                        IpAddr::V6(_) => 128,
+                    };
+
+                    (network, prefix_length)
+                }
+            };
+
+            let gateway = parse_bsd_address(gateway).filter(|gateway| gateway.is_ipv4() == network.is_ipv4());
+
+            Some(Route { network, prefix_length, gateway })
+        })
+        .collect()
+}
+
+/// Parses an address the way the BSD tools write them, with the zero octets at the end of an IPv4 network left off
+/// and the zone of a link-local IPv6 address after a `%`
+fn parse_bsd_address(address: &str) -> Option<IpAddr> {
+    let address = address.split('%').next()?;
+
+    if address.contains(':') {
+        return IpAddr::from_str(address).ok();
+    }
+
+    let mut octets = [0u8; 4];
+    for (index, octet) in address.split('.').enumerate() {
+        *octets.get_mut(index)? = octet.parse().ok()?;
+    }
+
+    Some(IpAddr::from(octets))
+}
+
+/// Reads the link layer addresses the kernel has resolved on the interface, for both IPv4 and IPv6, from `ip` or else
+/// from `arp` and `ndp` where there's no `ip`
+fn read_neighbors(interface_name: &str) -> HashMap<IpAddr, MacAddr> {
+    match run_command("ip", &["neigh", "show", "dev", interface_name]) {
+        Some(ip_output) => parse_ip_neighbors(&ip_output),
+        None => {
+            let arp_output = run_command("arp", &["-an", "-i", interface_name]).unwrap_or_default();
+            let ndp_output = run_command("ndp", &["-an"]).unwrap_or_default();
+
+            parse_arp_neighbors(&arp_output).into_iter().chain(parse_ndp_neighbors(&ndp_output, interface_name)).collect()
+        }
+    }
+}
+
+fn parse_ip_neighbors(ip_output: &str) -> HashMap<IpAddr, MacAddr> {
+    // parse out `10.99.0.2 lladdr 9e:a8:bf:25:b3:61 STALE`, skipping entries that failed to resolve
+    let neighbor_regex = match Regex::new(r"^(\S+) .*\blladdr (\S+)") {
+        Ok(neighbor_regex) => neighbor_regex,
+        Err(_) => return HashMap::new(),
+    };
+
+    ip_output
+        .lines()
+        .filter_map(|neighbor| neighbor_regex.captures(neighbor))
+        .filter_map(|captures| {
+            let ip = IpAddr::from_str(captures.get(1)?.as_str()).ok()?;
+            let mac = MacAddr::from_str(captures.get(2)?.as_str()).ok()?;
+
+            Some((ip, mac))
+        })
+        .collect()
+}
+
+fn parse_arp_neighbors(arp_output: &str) -> HashMap<IpAddr, MacAddr> {
+    // parse out `? (192.168.1.1) at a4:2b:b0:1:2:3 on en0 ifscope [ethernet]`, skipping `(incomplete)` entries
+    let neighbor_regex = match Regex::new(r"\((\S+)\) at (\S+)") {
+        Ok(neighbor_regex) => neighbor_regex,
+        Err(_) => return HashMap::new(),
+    };
+
+    arp_output
+        .lines()
+        .filter_map(|neighbor| neighbor_regex.captures(neighbor))
+        .filter_map(|captures| {
+            let ip = IpAddr::from_str(captures.get(1)?.as_str()).ok()?;
+            let mac = MacAddr::from_str(captures.get(2)?.as_str()).ok()?;
+
+            Some((ip, mac))
+        })
+        .collect()
+}
+
+fn parse_ndp_neighbors(ndp_output: &str, interface_name: &str) -> HashMap<IpAddr, MacAddr> {
+    // parse out `fe80::1%en0 a4:2b:b0:1:2:3 en0 23h59m58s S R`, after a header line
+    ndp_output
+        .lines()
+        .filter_map(|neighbor| {
+            let fields: Vec<&str> = neighbor.split_whitespace().collect();
+            if fields.get(2) != Some(&interface_name) {
+                return None;
+            }
+
+            let ip = parse_bsd_address(fields.first()?)?;
+            let mac = MacAddr::from_str(fields.get(1)?).ok()?;
+
+            Some((ip, mac))
+        })
+        .collect()
+}
@@ -1,18 +1,104 @@
-use pnet::packet::ip::IpNextHeaderProtocols;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::{self, Ipv4Flags, MutableIpv4Packet};
+use pnet::packet::ipv6::MutableIpv6Packet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
 use pnet::packet::tcp::MutableTcpPacket;
+use pnet::packet::Packet;
 
-pub(crate) fn create_syn_tcp_packet_v4<'b>(
+use crate::armada::config::scan::{TcpProbeOption, TcpProbeProfile};
+use crate::armada::scan_type::ScanType;
+
+const TCP_HEADER_LENGTH: usize = 20;
+/// The most room the data offset leaves for options
+pub(crate) const MAX_TCP_OPTIONS_LENGTH: usize = 40;
+/// Room for the largest TCP probe we send, a header with every byte of options used
+pub(crate) const MAX_TCP_PROBE_LENGTH: usize = TCP_HEADER_LENGTH + MAX_TCP_OPTIONS_LENGTH;
+
+const SCTP_COMMON_HEADER_LENGTH: usize = 12;
+const SCTP_INIT_CHUNK_LENGTH: usize = 20;
+const SCTP_INIT_PACKET_LENGTH: usize = SCTP_COMMON_HEADER_LENGTH + SCTP_INIT_CHUNK_LENGTH;
+
+pub(crate) const SCTP_CHUNK_TYPE_INIT: u8 = 1;
+pub(crate) const SCTP_CHUNK_TYPE_INIT_ACK: u8 = 2;
+pub(crate) const SCTP_CHUNK_TYPE_ABORT: u8 = 6;
+
+const IPV4_HEADER_LENGTH: usize = 20;
+pub(crate) const IPV6_HEADER_LENGTH: usize = 40;
+/// The TTL, or hop limit, of the IP headers we write ourselves unless the scan sets one
+pub(crate) const PROBE_TTL: u8 = 64;
+
+// ICMP queries are only sent by discovery, which goes through raw sockets and so only runs on unix systems
+#[cfg(unix)]
+const ICMP_ECHO_REQUEST_LENGTH: usize = 8;
+#[cfg(unix)]
+const ICMP_TIMESTAMP_REQUEST_LENGTH: usize = 20;
+
+#[cfg(unix)]
+pub(crate) const ICMP_TYPE_ECHO_REPLY: u8 = 0;
+#[cfg(unix)]
+const ICMP_TYPE_ECHO_REQUEST: u8 = 8;
+#[cfg(unix)]
+const ICMP_TYPE_TIMESTAMP_REQUEST: u8 = 13;
+#[cfg(unix)]
+pub(crate) const ICMP_TYPE_TIMESTAMP_REPLY: u8 = 14;
+#[cfg(unix)]
+const ICMPV6_TYPE_ECHO_REQUEST: u8 = 128;
+#[cfg(unix)]
+pub(crate) const ICMPV6_TYPE_ECHO_REPLY: u8 = 129;
+
+/// What goes into the TCP header of a probe besides addressing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TcpProbe<'p> {
+    pub(crate) flags: u16,
+    pub(crate) profile: &'p TcpProbeProfile,
+}
+
+/// A probe that is ready to be put on the wire
+pub(crate) enum ProbePacket<'b> {
+    Tcp(MutableTcpPacket<'b>),
+    Raw(RawPacket<'b>),
+}
+
+impl Packet for ProbePacket<'_> {
+    fn packet(&self) -> &[u8] {
+        match self {
+            ProbePacket::Tcp(tcp_packet) => tcp_packet.packet(),
+            ProbePacket::Raw(raw_packet) => raw_packet.packet(),
+        }
+    }
+
+    fn payload(&self) -> &[u8] {
+        match self {
+            ProbePacket::Tcp(tcp_packet) => tcp_packet.payload(),
+            ProbePacket::Raw(raw_packet) => raw_packet.payload(),
+        }
+    }
+}
+
+/// A packet we laid out by hand, for protocols (or message types) pnet doesn't know how to build
+pub(crate) struct RawPacket<'b> {
+    buffer: &'b [u8],
+    header_length: usize,
+}
+
+impl Packet for RawPacket<'_> {
+    fn packet(&self) -> &[u8] { self.buffer }
+
+    fn payload(&self) -> &[u8] { &self.buffer[self.header_length..] }
+}
+
+pub(crate) fn create_probe_tcp_packet_v4<'b>(
     source_ip: &Ipv4Addr,
     remote_ip: &Ipv4Addr,
     source_port: u16,
     remote_port: u16,
+    probe: TcpProbe,
     buffer: &'b mut [u8],
     tcp_seq: &mut u32,
 ) -> Option<MutableTcpPacket<'b>> {
-    use pnet::packet::Packet;
 
-    let mut tcp_packet = create_syn_tcp_packet_inner(source_port, remote_port, buffer, tcp_seq)?;
+    let mut tcp_packet = create_probe_tcp_packet_inner(source_port, remote_port, probe, buffer, tcp_seq)?;
 
     let checksum = pnet::util::ipv4_checksum(
         tcp_packet.packet(),
@@ -27,17 +113,17 @@ pub(crate) fn create_syn_tcp_packet_v4<'b>(
     Some(tcp_packet)
 }
 
-pub(crate) fn create_syn_tcp_packet_v6<'b>(
+pub(crate) fn create_probe_tcp_packet_v6<'b>(
     source_ip: &Ipv6Addr,
     remote_ip: &Ipv6Addr,
     source_port: u16,
     remote_port: u16,
+    probe: TcpProbe,
     buffer: &'b mut [u8],
     tcp_seq: &mut u32,
 ) -> Option<MutableTcpPacket<'b>> {
-    use pnet::packet::Packet;
 
-    let mut tcp_packet = create_syn_tcp_packet_inner(source_port, remote_port, buffer, tcp_seq)?;
+    let mut tcp_packet = create_probe_tcp_packet_inner(source_port, remote_port, probe, buffer, tcp_seq)?;
 
     let checksum = pnet::util::ipv6_checksum(
         tcp_packet.packet(),
@@ -52,28 +138,316 @@ pub(crate) fn create_syn_tcp_packet_v6<'b>(
     Some(tcp_packet)
 }
 
-fn create_syn_tcp_packet_inner<'b>(
+/// Redoes the checksum of a TCP probe for sending it between another pair of addresses, whose pseudo-header it covers
+pub(crate) fn readdress_tcp_packet(packet: &mut [u8], source_ip: IpAddr, remote_ip: IpAddr) -> Option<()> {
+    let mut tcp_packet = MutableTcpPacket::new(packet)?;
+
+    let checksum = match (source_ip, remote_ip) {
+        (IpAddr::V4(source_ip), IpAddr::V4(remote_ip)) => pnet::util::ipv4_checksum(
+            tcp_packet.packet(),
+            8,
+            &[],
+            &source_ip,
+            &remote_ip,
+            IpNextHeaderProtocols::Tcp,
+        ),
+        (IpAddr::V6(source_ip), IpAddr::V6(remote_ip)) => pnet::util::ipv6_checksum(
+            tcp_packet.packet(),
+            8,
+            &[],
+            &source_ip,
+            &remote_ip,
+            IpNextHeaderProtocols::Tcp,
+        ),
+        _ => return None,
+    };
+    tcp_packet.set_checksum(checksum);
+
+    Some(())
+}
+
+fn create_probe_tcp_packet_inner<'b>(
     source_port: u16,
     remote_port: u16,
+    probe: TcpProbe,
     buffer: &'b mut [u8],
     tcp_seq: &mut u32,
 ) -> Option<MutableTcpPacket<'b>> {
-    use pnet::packet::tcp::TcpFlags::SYN;
-    use pnet::packet::tcp::TcpOption;
+    use pnet::packet::tcp::TcpFlags::ACK;
+    use pnet::packet::MutablePacket;
+
+    let TcpProbe { flags, profile } = probe;
+
+    let options_length = profile.options_length();
+    if options_length > MAX_TCP_OPTIONS_LENGTH {
+        return None;
+    }
+    let header_length = TCP_HEADER_LENGTH + options_length;
 
     // inc 2?
     *tcp_seq += 1;
 
-    let mut tcp_packet = MutableTcpPacket::new(buffer)?;
+    // the packet spans the whole buffer, so it can't be any longer than the header
+    let mut tcp_packet = MutableTcpPacket::new(buffer.get_mut(..header_length)?)?;
 
     tcp_packet.set_source(source_port);
     tcp_packet.set_destination(remote_port);
     tcp_packet.set_sequence(*tcp_seq);
-    tcp_packet.set_acknowledgement(0);
-    tcp_packet.set_window(1024);
-    tcp_packet.set_data_offset(8);
-    tcp_packet.set_flags(SYN);
-    tcp_packet.set_options(&[TcpOption::mss(1460)]);
+    // a zero acknowledgement number on an ACK probe stands out and is dropped by some stacks
+    tcp_packet.set_acknowledgement(if flags & ACK != 0 { rand::random::<u32>() } else { 0 });
+    tcp_packet.set_window(profile.window);
+    tcp_packet.set_data_offset((header_length / 4) as u8);
+    tcp_packet.set_flags(flags);
+    write_tcp_options(&profile.options, &mut tcp_packet.packet_mut()[TCP_HEADER_LENGTH..]);
 
     Some(tcp_packet)
 }
+
+/// Writes the options into the buffer one after the other, padding the rest of it with end of option list bytes
+fn write_tcp_options(options: &[TcpProbeOption], buffer: &mut [u8]) {
+    const END_OF_OPTIONS: u8 = 0;
+    const NOP: u8 = 1;
+    const MSS: u8 = 2;
+    const WINDOW_SCALE: u8 = 3;
+    const SACK_PERMITTED: u8 = 4;
+    const TIMESTAMP: u8 = 8;
+
+    let mut offset = 0;
+
+    for option in options {
+        let option_buffer = &mut buffer[offset..offset + option.length()];
+
+        match *option {
+            TcpProbeOption::Mss(mss) => {
+                option_buffer[..2].copy_from_slice(&[MSS, 4]);
+                option_buffer[2..].copy_from_slice(&mss.to_be_bytes());
+            }
+            TcpProbeOption::SackPermitted => option_buffer.copy_from_slice(&[SACK_PERMITTED, 2]),
+            TcpProbeOption::Timestamp => {
+                option_buffer[..2].copy_from_slice(&[TIMESTAMP, 10]);
+                option_buffer[2..6].copy_from_slice(&rand::random::<u32>().to_be_bytes());
+                option_buffer[6..].copy_from_slice(&0u32.to_be_bytes());
+            }
+            TcpProbeOption::WindowScale(shift) => option_buffer.copy_from_slice(&[WINDOW_SCALE, 3, shift]),
+            TcpProbeOption::Nop => option_buffer[0] = NOP,
+        }
+
+        offset += option.length();
+    }
+
+    buffer[offset..].fill(END_OF_OPTIONS);
+}
+
+/// Lays out an SCTP INIT packet in the buffer.
+/// Unlike TCP, the SCTP checksum doesn't cover a pseudo-header so this is the same for IPv4 and IPv6.
+pub(crate) fn create_sctp_init_packet<'b>(
+    source_port: u16,
+    remote_port: u16,
+    initiate_tag: u32,
+    buffer: &'b mut [u8],
+) -> Option<RawPacket<'b>> {
+    let buffer = buffer.get_mut(..SCTP_INIT_PACKET_LENGTH)?;
+
+    // common header, the verification tag of an INIT is always 0
+    buffer[0..2].copy_from_slice(&source_port.to_be_bytes());
+    buffer[2..4].copy_from_slice(&remote_port.to_be_bytes());
+    buffer[4..8].copy_from_slice(&0u32.to_be_bytes());
+    buffer[8..12].copy_from_slice(&0u32.to_be_bytes());
+
+    // INIT chunk
+    buffer[12] = SCTP_CHUNK_TYPE_INIT;
+    buffer[13] = 0;
+    buffer[14..16].copy_from_slice(&(SCTP_INIT_CHUNK_LENGTH as u16).to_be_bytes());
+    buffer[16..20].copy_from_slice(&initiate_tag.to_be_bytes());
+    // advertised receiver window credit
+    buffer[20..24].copy_from_slice(&65_535u32.to_be_bytes());
+    // outbound and inbound streams
+    buffer[24..26].copy_from_slice(&10u16.to_be_bytes());
+    buffer[26..28].copy_from_slice(&2_048u16.to_be_bytes());
+    // initial TSN
+    buffer[28..32].copy_from_slice(&initiate_tag.to_be_bytes());
+
+    // RFC 4960 appendix B: the crc is transmitted least significant byte first
+    let checksum = crc32c(buffer);
+    buffer[8..12].copy_from_slice(&checksum.to_le_bytes());
+
+    Some(RawPacket { buffer, header_length: SCTP_COMMON_HEADER_LENGTH })
+}
+
+/// Lays out an ICMP echo request in the buffer
+#[cfg(unix)]
+pub(crate) fn create_icmp_echo_packet_v4(identifier: u16, sequence: u16, buffer: &mut [u8]) -> Option<RawPacket<'_>> {
+    let buffer = buffer.get_mut(..ICMP_ECHO_REQUEST_LENGTH)?;
+
+    write_icmp_header(buffer, ICMP_TYPE_ECHO_REQUEST, identifier, sequence);
+    let checksum = pnet::util::checksum(buffer, 1);
+    buffer[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    Some(RawPacket { buffer, header_length: ICMP_ECHO_REQUEST_LENGTH })
+}
+
+/// Lays out an ICMP timestamp request in the buffer. Hosts that drop echo requests sometimes still answer these.
+#[cfg(unix)]
+pub(crate) fn create_icmp_timestamp_packet_v4(
+    identifier: u16,
+    sequence: u16,
+    buffer: &mut [u8],
+) -> Option<RawPacket<'_>> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const MS_IN_DAY: u128 = 24 * 60 * 60 * 1_000;
+
+    let buffer = buffer.get_mut(..ICMP_TIMESTAMP_REQUEST_LENGTH)?;
+
+    write_icmp_header(buffer, ICMP_TYPE_TIMESTAMP_REQUEST, identifier, sequence);
+
+    // originate timestamp is milliseconds since midnight UT, receive and transmit are filled in by the remote
+    let originate_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| (since_epoch.as_millis() % MS_IN_DAY) as u32)
+        .unwrap_or(0);
+    buffer[8..12].copy_from_slice(&originate_timestamp.to_be_bytes());
+    buffer[12..20].fill(0);
+
+    let checksum = pnet::util::checksum(buffer, 1);
+    buffer[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    Some(RawPacket { buffer, header_length: ICMP_TIMESTAMP_REQUEST_LENGTH })
+}
+
+/// Lays out an ICMPv6 echo request in the buffer
+#[cfg(unix)]
+pub(crate) fn create_icmp_echo_packet_v6<'b>(
+    source_ip: &Ipv6Addr,
+    remote_ip: &Ipv6Addr,
+    identifier: u16,
+    sequence: u16,
+    buffer: &'b mut [u8],
+) -> Option<RawPacket<'b>> {
+    let buffer = buffer.get_mut(..ICMP_ECHO_REQUEST_LENGTH)?;
+
+    write_icmp_header(buffer, ICMPV6_TYPE_ECHO_REQUEST, identifier, sequence);
+    let checksum = pnet::util::ipv6_checksum(buffer, 1, &[], source_ip, remote_ip, IpNextHeaderProtocols::Icmpv6);
+    buffer[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    Some(RawPacket { buffer, header_length: ICMP_ECHO_REQUEST_LENGTH })
+}
+
+/// Puts the transport layer packet in an IP packet the way the kernel would on its way out, returning the IP packet's
+/// length
+pub(crate) fn build_ip_packet(
+    buffer: &mut [u8],
+    protocol: IpNextHeaderProtocol,
+    source: IpAddr,
+    destination: IpAddr,
+    packet: &[u8],
+    identification: u16,
+    ttl: u8,
+) -> Option<usize> {
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            build_ipv4_fragment(buffer, protocol, source, destination, packet, 0..packet.len(), identification, ttl)
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => {
+            let ip_length = IPV6_HEADER_LENGTH + packet.len();
+            let mut ip_packet = MutableIpv6Packet::new(buffer.get_mut(..ip_length)?)?;
+            ip_packet.set_version(6);
+            ip_packet.set_payload_length(packet.len() as u16);
+            ip_packet.set_next_header(protocol);
+            ip_packet.set_hop_limit(ttl);
+            ip_packet.set_source(source);
+            ip_packet.set_destination(destination);
+            ip_packet.set_payload(packet);
+
+            Some(ip_length)
+        }
+        _ => None,
+    }
+}
+
+/// Puts part of the transport layer packet in an IPv4 fragment, returning the fragment's length. A part covering the
+/// whole packet goes out as a packet of its own, marked as not to be fragmented along the way. Every part but the last
+/// has to start and end on a multiple of 8 bytes, which fragment offsets are counted in.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn build_ipv4_fragment(
+    buffer: &mut [u8],
+    protocol: IpNextHeaderProtocol,
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    packet: &[u8],
+    part: Range<usize>,
+    identification: u16,
+    ttl: u8,
+) -> Option<usize> {
+    let flags = match (part.start, part.end) {
+        (0, end) if end >= packet.len() => Ipv4Flags::DontFragment,
+        (_, end) if end < packet.len() => Ipv4Flags::MoreFragments,
+        _ => 0,
+    };
+    let payload = packet.get(part.start..part.end.min(packet.len()))?;
+
+    let ip_length = IPV4_HEADER_LENGTH + payload.len();
+    let mut ip_packet = MutableIpv4Packet::new(buffer.get_mut(..ip_length)?)?;
+    ip_packet.set_version(4);
+    ip_packet.set_header_length((IPV4_HEADER_LENGTH / 4) as u8);
+    ip_packet.set_total_length(ip_length as u16);
+    ip_packet.set_identification(identification);
+    ip_packet.set_flags(flags);
+    ip_packet.set_fragment_offset((part.start / 8) as u16);
+    ip_packet.set_ttl(ttl);
+    ip_packet.set_next_level_protocol(protocol);
+    ip_packet.set_source(source);
+    ip_packet.set_destination(destination);
+    ip_packet.set_payload(payload);
+
+    let checksum = ipv4::checksum(&ip_packet.to_immutable());
+    ip_packet.set_checksum(checksum);
+
+    Some(ip_length)
+}
+
+/// How many bytes a probe of the scan type takes up once it's in IP packets, in as many fragments as it takes. Only
+/// IPv4 probes are fragmented.
+pub(crate) fn probe_length(scan_type: ScanType, profile: &TcpProbeProfile, ipv6: bool, fragment_size: Option<usize>) -> usize {
+    let probe_length = match scan_type.is_sctp() {
+        true => SCTP_INIT_PACKET_LENGTH,
+        false => TCP_HEADER_LENGTH + profile.options_length(),
+    };
+
+    match (ipv6, fragment_size) {
+        (true, _) => IPV6_HEADER_LENGTH + probe_length,
+        (false, Some(fragment_size)) => probe_length + IPV4_HEADER_LENGTH * fragment_parts(probe_length, fragment_size).count(),
+        (false, None) => IPV4_HEADER_LENGTH + probe_length,
+    }
+}
+
+/// The parts a packet of the length is split into, carrying up to `fragment_size` bytes each. The size is rounded down
+/// to a multiple of 8, and never below 8.
+pub(crate) fn fragment_parts(packet_length: usize, fragment_size: usize) -> impl Iterator<Item = Range<usize>> {
+    let fragment_size = (fragment_size / 8 * 8).max(8);
+
+    (0..packet_length)
+        .step_by(fragment_size)
+        .map(move |start| start..(start + fragment_size).min(packet_length))
+}
+
+/// Writes the header shared by echo and timestamp messages, leaving the checksum zeroed
+#[cfg(unix)]
+fn write_icmp_header(buffer: &mut [u8], icmp_type: u8, identifier: u16, sequence: u16) {
+    buffer[0] = icmp_type;
+    buffer[1] = 0;
+    buffer[2..4].fill(0);
+    buffer[4..6].copy_from_slice(&identifier.to_be_bytes());
+    buffer[6..8].copy_from_slice(&sequence.to_be_bytes());
+}
+
+/// CRC-32C (Castagnoli) as used by SCTP. Probes are tiny so a bitwise implementation is plenty.
+fn crc32c(bytes: &[u8]) -> u32 {
+    const POLYNOMIAL: u32 = 0x82F6_3B78;
+
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        (0 .. 8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 == 1 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 }
+        })
+    })
+}
@@ -0,0 +1,123 @@
+/// Number of Feistel rounds. A handful is plenty to spread consecutive indices across the whole range; the aim is
+/// to avoid hitting one subnet at a time, not cryptographic strength.
+const ROUNDS: u64 = 4;
+
+/// A random permutation of `0..range` computed one index at a time, in the style of masscan's blackrock. The range
+/// is split into `a * b` with both close to its square root and indices are put through a Feistel network that
+/// alternates between the two moduli. That maps `0..a * b` onto itself, and indices that land past the end of the
+/// range are put through again until they land inside it. Nothing is stored besides the seed and the moduli, so the
+/// largest ranges shuffle in constant memory.
+#[derive(Clone, Debug)]
+pub(crate) struct Permutation {
+    range: u128,
+    a: u128,
+    b: u128,
+    seed: u64,
+}
+
+impl Permutation {
+    pub(crate) fn new(range: u128, seed: u64) -> Self {
+        let split = integer_sqrt(range);
+        let a = split.saturating_sub(2).max(1);
+        let mut b = split + 3;
+
+        while a * b <= range {
+            b += 1;
+        }
+
+        Self { range, a, b, seed }
+    }
+
+    /// Where the index lands in the permutation. Every index below the range maps to a distinct index below it.
+    pub(crate) fn shuffle(&self, index: u128) -> u128 {
+        let mut shuffled = self.encrypt(index);
+
+        while shuffled >= self.range {
+            shuffled = self.encrypt(shuffled);
+        }
+
+        shuffled
+    }
+
+    fn encrypt(&self, index: u128) -> u128 {
+        let (mut left, mut right) = (index % self.a, index / self.a);
+
+        for round in 1..=ROUNDS {
+            let modulus = if round % 2 == 1 { self.a } else { self.b };
+            let next = (left + self.round_function(round, right) as u128) % modulus;
+
+            left = right;
+            right = next;
+        }
+
+        if ROUNDS % 2 == 1 {
+            self.a * left + right
+        } else {
+            self.a * right + left
+        }
+    }
+
+    fn round_function(&self, round: u64, value: u128) -> u64 {
+        mix(self.seed ^ mix(value as u64 ^ (value >> 64) as u64 ^ (round << 56)))
+    }
+}
+
+/// The splitmix64 finalizer
+fn mix(mut value: u64) -> u64 {
+    value = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+    value ^ (value >> 31)
+}
+
+fn integer_sqrt(value: u128) -> u128 {
+    let mut root = (value as f64).sqrt() as u128;
+
+    // floating point gets the root close, these get it exact
+    while root * root > value {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= value {
+        root += 1;
+    }
+
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shuffled(range: u128, seed: u64) -> Vec<u128> {
+        let permutation = Permutation::new(range, seed);
+
+        (0..range).map(|index| permutation.shuffle(index)).collect()
+    }
+
+    #[test]
+    fn every_index_lands_on_a_distinct_index_in_the_range() {
+        for range in [1, 2, 3, 7, 16, 100, 255, 256, 1000, 65536] {
+            for seed in [0, 1, 0xdead_beef] {
+                let mut shuffled = shuffled(range, seed);
+                shuffled.sort_unstable();
+
+                assert_eq!(shuffled, (0..range).collect::<Vec<_>>(), "range {} with seed {}", range, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn the_seed_picks_the_order() {
+        assert_eq!(shuffled(1000, 42), shuffled(1000, 42));
+        assert_ne!(shuffled(1000, 42), shuffled(1000, 43));
+        assert_ne!(shuffled(1000, 42), (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn integer_sqrt_rounds_down() {
+        for (value, root) in [(0, 0), (1, 1), (3, 1), (4, 2), (99, 9), (100, 10), (u64::MAX as u128, u32::MAX as u128)] {
+            assert_eq!(integer_sqrt(value), root);
+        }
+    }
+}
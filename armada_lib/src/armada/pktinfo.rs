@@ -0,0 +1,62 @@
+use std::net::IpAddr;
+use std::{mem, ptr};
+
+/// Room for an `IP_PKTINFO` or `IPV6_PKTINFO` control message, whichever is larger
+const CONTROL_LENGTH: usize =
+    unsafe { libc::CMSG_SPACE(mem::size_of::<libc::in6_pktinfo>() as libc::c_uint) } as usize;
+
+/// The control message that has the kernel send a packet from a given address of ours, rather than whichever one it
+/// would pick by route. Raw sockets fill in the IP header themselves, so this is the only say we get in the source
+/// of a probe, whose TCP checksum depends on it.
+#[repr(C, align(8))]
+#[derive(Clone, Copy)]
+pub(crate) struct SourceControl([u8; CONTROL_LENGTH]);
+
+impl Default for SourceControl {
+    fn default() -> Self {
+        Self([0; CONTROL_LENGTH])
+    }
+}
+
+impl SourceControl {
+    /// Points the header at a control message sending from the source. The unspecified address leaves picking the
+    /// source to the kernel.
+    pub(crate) fn attach(&mut self, header: &mut libc::msghdr, source: IpAddr) {
+        if source.is_unspecified() {
+            header.msg_control = ptr::null_mut();
+            header.msg_controllen = 0;
+            return;
+        }
+
+        let (level, kind, length) = match source {
+            IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_PKTINFO, mem::size_of::<libc::in_pktinfo>()),
+            IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, mem::size_of::<libc::in6_pktinfo>()),
+        };
+
+        header.msg_control = self.0.as_mut_ptr() as *mut libc::c_void;
+        header.msg_controllen = unsafe { libc::CMSG_SPACE(length as libc::c_uint) } as _;
+
+        unsafe {
+            let message = libc::CMSG_FIRSTHDR(header);
+            (*message).cmsg_level = level;
+            (*message).cmsg_type = kind;
+            (*message).cmsg_len = libc::CMSG_LEN(length as libc::c_uint) as _;
+
+            let data = libc::CMSG_DATA(message);
+            match source {
+                IpAddr::V4(source) => ptr::write_unaligned(
+                    data as *mut libc::in_pktinfo,
+                    libc::in_pktinfo {
+                        ipi_ifindex: 0,
+                        ipi_spec_dst: libc::in_addr { s_addr: u32::from_ne_bytes(source.octets()) },
+                        ipi_addr: libc::in_addr { s_addr: 0 },
+                    },
+                ),
+                IpAddr::V6(source) => ptr::write_unaligned(
+                    data as *mut libc::in6_pktinfo,
+                    libc::in6_pktinfo { ipi6_addr: libc::in6_addr { s6_addr: source.octets() }, ipi6_ifindex: 0 },
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use twox_hash::XxHash64;
+
+use rand::Rng;
+
+use crate::armada::config::scan::{Pacing, RateLimitStrategy, SubnetRateLimit};
+
+/// The rate an automatically rated scan starts at
+const START_RATE: usize = 1_000;
+/// The rate is never backed off below this, so a noisy network can't stall the scan
+const MIN_RATE: usize = 100;
+/// How long responses are collected before the rate is adjusted
+const ADJUSTMENT_WINDOW: Duration = Duration::from_secs(1);
+/// Windows with fewer responses than this say too little about the network to back off on
+const MIN_RESPONSES: u64 = 20;
+/// The share of responses that may only arrive after a retry before the rate is backed off
+const MAX_LATE_RESPONSE_RATIO: f64 = 0.05;
+/// How far the response ratio may fall below the best one seen before the rate is backed off
+const MIN_RESPONSE_RATIO_SHARE: f64 = 0.5;
+const INCREASE_FACTOR: f64 = 1.25;
+const DECREASE_FACTOR: f64 = 0.5;
+/// How long each window of the fixed window rate limiter lasts
+const FIXED_WINDOW: Duration = Duration::from_millis(100);
+
+/// Holds a worker of the scan to its packets per second. Workers send a batch of probes at a time, asking the limiter
+/// before each batch whether it may go out, so a batch can take the limiter past its budget, which it then makes up for
+/// by holding back later ones.
+pub trait RateLimiter: Send {
+    /// Whether the next batch of probes may go out now
+    fn try_acquire(&mut self) -> bool;
+
+    /// Takes the probes that went out from the budget
+    fn record_sent(&mut self, packets_sent: usize);
+
+    /// Changes the rate to hold to, when auto rate adjusts it or a new rate limit is set while the scan runs
+    fn set_rate(&mut self, packets_per_second: usize);
+
+    /// When the next batch may go out, once `try_acquire` has said it can't yet
+    fn ready_at(&self) -> Instant;
+}
+
+pub(crate) fn rate_limiter(strategy: &RateLimitStrategy, packets_per_second: usize) -> Box<dyn RateLimiter> {
+    match strategy {
+        RateLimitStrategy::FixedWindow => Box::new(FixedWindowLimiter::new(packets_per_second)),
+        RateLimitStrategy::TokenBucket { burst } => Box::new(TokenBucketLimiter::new(packets_per_second, *burst)),
+        RateLimitStrategy::Custom(factory) => factory.limiter(packets_per_second),
+    }
+}
+
+/// Lets a window's worth of the rate go out at the start of every window, and nothing more until the next one starts
+struct FixedWindowLimiter {
+    packets_per_window: usize,
+    window_packets_sent: usize,
+    window_end: Instant,
+}
+
+impl FixedWindowLimiter {
+    fn new(packets_per_second: usize) -> Self {
+        let mut limiter = Self { packets_per_window: 0, window_packets_sent: 0, window_end: Instant::now() + FIXED_WINDOW };
+        limiter.set_rate(packets_per_second);
+
+        limiter
+    }
+}
+
+impl RateLimiter for FixedWindowLimiter {
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        if self.window_end <= now {
+            self.window_packets_sent = 0;
+            self.window_end = now + FIXED_WINDOW;
+        }
+
+        self.packets_per_window > self.window_packets_sent
+    }
+
+    fn record_sent(&mut self, packets_sent: usize) {
+        self.window_packets_sent += packets_sent;
+    }
+
+    fn set_rate(&mut self, packets_per_second: usize) {
+        self.packets_per_window = packets_per_second * FIXED_WINDOW.as_millis() as usize / 1_000;
+    }
+
+    fn ready_at(&self) -> Instant {
+        self.window_end
+    }
+}
+
+/// Tokens drip into the bucket at the rate, up to the burst, and every probe sent takes one out. A batch may take it
+/// below empty, after which nothing goes out until it has filled back up to a token.
+struct TokenBucketLimiter {
+    packets_per_second: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucketLimiter {
+    fn new(packets_per_second: usize, burst: usize) -> Self {
+        let burst = burst.max(1) as f64;
+
+        Self { packets_per_second: packets_per_second.max(1) as f64, burst, tokens: burst, last_refill: Instant::now() }
+    }
+}
+
+impl RateLimiter for TokenBucketLimiter {
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let refilled = now.duration_since(self.last_refill).as_secs_f64() * self.packets_per_second;
+        self.tokens = (self.tokens + refilled).min(self.burst);
+        self.last_refill = now;
+
+        self.tokens >= 1.0
+    }
+
+    fn record_sent(&mut self, packets_sent: usize) {
+        self.tokens -= packets_sent as f64;
+    }
+
+    fn set_rate(&mut self, packets_per_second: usize) {
+        self.packets_per_second = packets_per_second.max(1) as f64;
+    }
+
+    fn ready_at(&self) -> Instant {
+        let missing_tokens = (1.0 - self.tokens).max(0.0);
+
+        self.last_refill + Duration::from_secs_f64(missing_tokens / self.packets_per_second)
+    }
+}
+
+/// Works out the packet rate of a scan from how the network responds to it. Two signals point at packets being
+/// dropped: ports that only answer once their probe is retried, and fewer ports answering per probe than at lower
+/// rates. While neither shows up the rate climbs, and as soon as one does it's halved.
+pub(crate) struct RateController {
+    ceiling: Option<usize>,
+    rate: usize,
+    window_start: Instant,
+    window_packets_sent: u64,
+    window_responses: u64,
+    /// Responses that only came in after the probe was retried, so the first probe was probably lost
+    window_late_responses: u64,
+    /// The most responses per probe sent seen so far
+    best_response_ratio: f64,
+}
+
+impl RateController {
+    /// The rate never goes past the ceiling, if there is one
+    pub(crate) fn new(ceiling: Option<usize>) -> Self {
+        Self {
+            ceiling,
+            rate: ceiling.map_or(START_RATE, |ceiling| ceiling.min(START_RATE)),
+            window_start: Instant::now(),
+            window_packets_sent: 0,
+            window_responses: 0,
+            window_late_responses: 0,
+            best_response_ratio: 0.0,
+        }
+    }
+
+    pub(crate) fn rate(&self) -> usize {
+        self.rate
+    }
+
+    pub(crate) fn record_sent(&mut self, packets_sent: usize) {
+        self.window_packets_sent += packets_sent as u64;
+    }
+
+    pub(crate) fn record_response(&mut self, retried: bool) {
+        self.window_responses += 1;
+
+        if retried {
+            self.window_late_responses += 1;
+        }
+    }
+
+    /// Adjusts the rate once the window is over, returning the new rate
+    pub(crate) fn adjust(&mut self) -> Option<usize> {
+        if self.window_start.elapsed() < ADJUSTMENT_WINDOW {
+            return None;
+        }
+
+        let dropping_packets = self.window_responses >= MIN_RESPONSES && {
+            let late_response_ratio = self.window_late_responses as f64 / self.window_responses as f64;
+            let response_ratio = self.window_responses as f64 / self.window_packets_sent.max(1) as f64;
+            self.best_response_ratio = self.best_response_ratio.max(response_ratio);
+
+            late_response_ratio > MAX_LATE_RESPONSE_RATIO
+                || response_ratio < self.best_response_ratio * MIN_RESPONSE_RATIO_SHARE
+        };
+
+        let rate = if dropping_packets {
+            (self.rate as f64 * DECREASE_FACTOR) as usize
+        } else {
+            (self.rate as f64 * INCREASE_FACTOR) as usize
+        };
+        self.rate = match self.ceiling {
+            Some(ceiling) => rate.clamp(MIN_RATE.min(ceiling), ceiling),
+            None => rate.max(MIN_RATE),
+        };
+
+        self.window_start = Instant::now();
+        self.window_packets_sent = 0;
+        self.window_responses = 0;
+        self.window_late_responses = 0;
+
+        Some(self.rate)
+    }
+}
+
+/// Subnets that haven't been sent to for this long have a full bucket again, so they no longer need tracking
+const IDLE_SUBNET_EXPIRY: Duration = Duration::from_secs(1);
+/// How many subnets are tracked before idle ones are cleared out, which happens at most once per expiry
+const MAX_TRACKED_SUBNETS: usize = 4096;
+
+/// Caps the packets per second sent into any one subnet with a token bucket per subnet. A bucket holds a tenth of a
+/// second worth of packets, the same burst the scan wide rate limit allows.
+pub(crate) struct SubnetRateLimiter {
+    limit: SubnetRateLimit,
+    burst: f64,
+    buckets: HashMap<IpAddr, SubnetBucket, BuildHasherDefault<XxHash64>>,
+    last_pruned: Instant,
+}
+
+struct SubnetBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl SubnetRateLimiter {
+    pub(crate) fn new(limit: SubnetRateLimit) -> Self {
+        Self {
+            limit,
+            burst: (limit.packets_per_second as f64 / 10.0).max(1.0),
+            buckets: HashMap::default(),
+            last_pruned: Instant::now(),
+        }
+    }
+
+    /// Takes a packet from the budget of the remote's subnet, returning whether there was one to take
+    pub(crate) fn try_acquire(&mut self, remote: IpAddr) -> bool {
+        let now = Instant::now();
+
+        if self.buckets.len() >= MAX_TRACKED_SUBNETS && now.duration_since(self.last_pruned) >= IDLE_SUBNET_EXPIRY {
+            self.buckets.retain(|_, bucket| now.duration_since(bucket.last_refill) < IDLE_SUBNET_EXPIRY);
+            self.last_pruned = now;
+        }
+
+        let (packets_per_second, burst) = (self.limit.packets_per_second as f64, self.burst);
+        let bucket = self
+            .buckets
+            .entry(self.limit.subnet(remote))
+            .or_insert(SubnetBucket { tokens: burst, last_refill: now });
+
+        let refilled = now.duration_since(bucket.last_refill).as_secs_f64() * packets_per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Decides when probes may go out, on top of the rate limits
+pub(crate) trait Pacer: Send {
+    /// Whether a probe to the remote may go out now, pushing back when the next ones may if it does
+    fn try_acquire(&mut self, remote: IpAddr) -> bool;
+
+    /// Whether probes that have to wait are set aside for probes to other hosts to overtake, rather than holding up
+    /// every probe behind them
+    fn defers(&self) -> bool;
+
+    /// When the next probe may go out, if every probe is waiting on the same time
+    fn ready_at(&self) -> Option<Instant>;
+}
+
+pub(crate) fn pacer(pacing: Pacing) -> Box<dyn Pacer> {
+    match pacing.per_destination {
+        true => Box::new(DestinationPacer { pacing, next_probes: HashMap::default(), last_pruned: Instant::now() }),
+        false => Box::new(ScanPacer { pacing, next_probe: Instant::now() }),
+    }
+}
+
+/// The delay, with a random share of the jitter on top
+fn spacing(pacing: &Pacing) -> Duration {
+    match pacing.jitter.is_zero() {
+        true => pacing.delay,
+        false => pacing.delay + rand::thread_rng().gen_range(Duration::ZERO..=pacing.jitter),
+    }
+}
+
+/// Spaces out every probe of the scan from the one before it
+struct ScanPacer {
+    pacing: Pacing,
+    next_probe: Instant,
+}
+
+impl Pacer for ScanPacer {
+    fn try_acquire(&mut self, _remote: IpAddr) -> bool {
+        let now = Instant::now();
+        if now < self.next_probe {
+            return false;
+        }
+
+        self.next_probe = now + spacing(&self.pacing);
+        true
+    }
+
+    fn defers(&self) -> bool {
+        false
+    }
+
+    fn ready_at(&self) -> Option<Instant> {
+        Some(self.next_probe)
+    }
+}
+
+/// Spaces out the probes to each host from the one before it, with hosts that have waited long enough dropped
+struct DestinationPacer {
+    pacing: Pacing,
+    next_probes: HashMap<IpAddr, Instant, BuildHasherDefault<XxHash64>>,
+    last_pruned: Instant,
+}
+
+impl Pacer for DestinationPacer {
+    fn try_acquire(&mut self, remote: IpAddr) -> bool {
+        let now = Instant::now();
+
+        if self.next_probes.len() >= MAX_TRACKED_SUBNETS && now.duration_since(self.last_pruned) >= IDLE_SUBNET_EXPIRY {
+            self.next_probes.retain(|_, next_probe| *next_probe > now);
+            self.last_pruned = now;
+        }
+
+        if self.next_probes.get(&remote).is_some_and(|next_probe| now < *next_probe) {
+            return false;
+        }
+
+        self.next_probes.insert(remote, now + spacing(&self.pacing));
+        true
+    }
+
+    fn defers(&self) -> bool {
+        true
+    }
+
+    fn ready_at(&self) -> Option<Instant> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_windows_let_a_tenth_of_the_rate_out_each() {
+        let mut limiter = FixedWindowLimiter::new(1_000);
+
+        assert!(limiter.try_acquire());
+        limiter.record_sent(100);
+        assert!(!limiter.try_acquire());
+        assert_eq!(limiter.ready_at(), limiter.window_end);
+
+        limiter.window_end = Instant::now();
+        assert!(limiter.try_acquire());
+        assert_eq!(limiter.window_packets_sent, 0);
+    }
+
+    #[test]
+    fn setting_the_rate_resizes_the_window() {
+        let mut limiter = FixedWindowLimiter::new(1_000);
+        limiter.record_sent(100);
+
+        limiter.set_rate(2_000);
+
+        assert!(limiter.try_acquire());
+        limiter.record_sent(100);
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn token_buckets_start_full_and_may_be_taken_below_empty() {
+        let mut limiter = TokenBucketLimiter::new(1_000, 5);
+
+        assert!(limiter.try_acquire());
+        limiter.record_sent(8);
+        assert!(!limiter.try_acquire());
+
+        let wait = limiter.ready_at().duration_since(limiter.last_refill);
+        assert!(wait > Duration::from_millis(3) && wait <= Duration::from_millis(4), "waits {:?}", wait);
+    }
+
+    #[test]
+    fn token_buckets_refill_at_the_rate_up_to_the_burst() {
+        let mut limiter = TokenBucketLimiter::new(100, 5);
+        limiter.tokens = 0.0;
+        limiter.last_refill -= Duration::from_millis(20);
+
+        assert!(limiter.try_acquire());
+        assert!(limiter.tokens >= 2.0 && limiter.tokens < 3.0, "{} tokens", limiter.tokens);
+
+        limiter.last_refill -= Duration::from_secs(10);
+        limiter.try_acquire();
+        assert_eq!(limiter.tokens, 5.0);
+    }
+
+    /// Runs a window in which every probe sent gets `responses` answers, `late_responses` of them after a retry
+    fn run_window(controller: &mut RateController, packets_sent: usize, responses: u64, late_responses: u64) -> Option<usize> {
+        controller.record_sent(packets_sent);
+        (0 .. responses).for_each(|response| controller.record_response(response < late_responses));
+        controller.window_start -= ADJUSTMENT_WINDOW;
+
+        controller.adjust()
+    }
+
+    #[test]
+    fn the_rate_is_only_adjusted_once_a_window_is_over() {
+        let mut controller = RateController::new(None);
+        controller.record_sent(1_000);
+
+        assert_eq!(controller.adjust(), None);
+        assert_eq!(controller.rate(), START_RATE);
+    }
+
+    #[test]
+    fn the_rate_climbs_while_nothing_is_dropped_up_to_the_ceiling() {
+        let mut controller = RateController::new(Some(1_500));
+
+        assert_eq!(run_window(&mut controller, 1_000, 100, 0), Some(1_250));
+        assert_eq!(run_window(&mut controller, 1_250, 125, 0), Some(1_500));
+        assert_eq!(run_window(&mut controller, 1_500, 150, 0), Some(1_500));
+    }
+
+    #[test]
+    fn late_responses_halve_the_rate() {
+        let mut controller = RateController::new(None);
+
+        assert_eq!(run_window(&mut controller, 1_000, 100, 10), Some(500));
+    }
+
+    #[test]
+    fn fewer_responses_per_probe_than_before_halve_the_rate() {
+        let mut controller = RateController::new(None);
+
+        assert_eq!(run_window(&mut controller, 1_000, 100, 0), Some(1_250));
+        assert_eq!(run_window(&mut controller, 1_250, 40, 0), Some(625));
+    }
+
+    #[test]
+    fn quiet_windows_never_back_off_and_the_rate_stays_above_the_minimum() {
+        let mut controller = RateController::new(None);
+        assert_eq!(run_window(&mut controller, 1_000, MIN_RESPONSES - 1, MIN_RESPONSES - 1), Some(1_250));
+
+        for _ in 0 .. 10 {
+            run_window(&mut controller, 1_000, 100, 100);
+        }
+        assert_eq!(controller.rate(), MIN_RATE);
+    }
+
+    fn subnet_limit(packets_per_second: usize) -> SubnetRateLimit {
+        SubnetRateLimit { ipv4_prefix: 24, ipv6_prefix: 64, packets_per_second }
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn subnets_get_a_tenth_of_a_second_of_burst() {
+        let mut limiter = SubnetRateLimiter::new(subnet_limit(100));
+
+        assert_eq!((0 .. 20).filter(|_| limiter.try_acquire(ip("192.0.2.1"))).count(), 10);
+        // other hosts in the /24 share the bucket, a separate /24 has its own
+        assert!(!limiter.try_acquire(ip("192.0.2.254")));
+        assert!(limiter.try_acquire(ip("192.0.3.1")));
+    }
+
+    #[test]
+    fn buckets_refill_at_the_rate() {
+        let mut limiter = SubnetRateLimiter::new(subnet_limit(100));
+        assert!(limiter.try_acquire(ip("2001:db8::1")));
+
+        for bucket in limiter.buckets.values_mut() {
+            bucket.tokens = 0.0;
+            bucket.last_refill -= Duration::from_millis(50);
+        }
+
+        assert_eq!((0 .. 20).filter(|_| limiter.try_acquire(ip("2001:db8::ffff"))).count(), 5);
+    }
+
+    #[test]
+    fn the_slowest_limits_still_let_a_packet_through() {
+        let mut limiter = SubnetRateLimiter::new(subnet_limit(1));
+
+        assert!(limiter.try_acquire(ip("192.0.2.1")));
+        assert!(!limiter.try_acquire(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn subnets_are_sized_by_prefix() {
+        let limit = SubnetRateLimit { ipv4_prefix: 16, ipv6_prefix: 48, packets_per_second: 1 };
+
+        assert_eq!(limit.subnet(ip("192.0.2.1")), ip("192.0.0.0"));
+        assert_eq!(limit.subnet(ip("2001:db8:1:2::3")), ip("2001:db8:1::"));
+        assert_eq!(SubnetRateLimit { ipv4_prefix: 0, ..limit }.subnet(ip("192.0.2.1")), ip("0.0.0.0"));
+        assert_eq!(SubnetRateLimit { ipv4_prefix: 32, ..limit }.subnet(ip("192.0.2.1")), ip("192.0.2.1"));
+    }
+
+    fn pacing(delay_ms: u64, jitter_ms: u64, per_destination: bool) -> Pacing {
+        Pacing { delay: Duration::from_millis(delay_ms), jitter: Duration::from_millis(jitter_ms), per_destination }
+    }
+
+    #[test]
+    fn scan_pacing_spaces_out_every_probe() {
+        let mut pacer = pacer(pacing(60_000, 0, false));
+
+        assert!(pacer.try_acquire(ip("192.0.2.1")));
+        assert!(!pacer.try_acquire(ip("192.0.2.2")));
+        assert!(!pacer.defers());
+
+        let ready_at = pacer.ready_at().unwrap();
+        assert!(ready_at > Instant::now() + Duration::from_secs(59));
+        assert!(ready_at <= Instant::now() + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn destination_pacing_only_holds_back_the_same_host() {
+        let mut pacer = pacer(pacing(60_000, 0, true));
+
+        assert!(pacer.try_acquire(ip("192.0.2.1")));
+        assert!(!pacer.try_acquire(ip("192.0.2.1")));
+        assert!(pacer.try_acquire(ip("192.0.2.2")));
+        assert!(pacer.defers());
+        assert_eq!(pacer.ready_at(), None);
+    }
+
+    #[test]
+    fn jitter_adds_up_to_its_length_to_the_delay() {
+        let pacing = pacing(100, 50, false);
+
+        for _ in 0 .. 100 {
+            let spacing = spacing(&pacing);
+            assert!(spacing >= Duration::from_millis(100) && spacing <= Duration::from_millis(150));
+        }
+        assert_eq!(spacing(&Pacing { jitter: Duration::ZERO, ..pacing }), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,89 @@
+use pnet::packet::tcp::TcpPacket;
+
+/// The header fields of a TCP response that scan types can base their classification on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TcpResponse {
+    pub(crate) source_port: u16,
+    pub(crate) destination_port: u16,
+    pub(crate) sequence: u32,
+    pub(crate) acknowledgement: u32,
+    pub(crate) flags: u16,
+    pub(crate) window: u16,
+}
+
+impl From<&TcpPacket<'_>> for TcpResponse {
+    fn from(packet: &TcpPacket<'_>) -> Self {
+        Self {
+            source_port: packet.get_source(),
+            destination_port: packet.get_destination(),
+            sequence: packet.get_sequence(),
+            acknowledgement: packet.get_acknowledgement(),
+            flags: packet.get_flags(),
+            window: packet.get_window(),
+        }
+    }
+}
+
+/// The parts of an SCTP response that scan types can base their classification on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SctpResponse {
+    pub(crate) source_port: u16,
+    pub(crate) destination_port: u16,
+    pub(crate) verification_tag: u32,
+    /// Type of the first chunk in the packet
+    pub(crate) chunk_type: u8,
+}
+
+impl SctpResponse {
+    /// Parses the common header and the type of the first chunk out of a raw SCTP packet
+    pub(crate) fn parse(packet: &[u8]) -> Option<Self> {
+        let header = packet.get(..13)?;
+
+        Some(Self {
+            source_port: u16::from_be_bytes([header[0], header[1]]),
+            destination_port: u16::from_be_bytes([header[2], header[3]]),
+            verification_tag: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            chunk_type: header[12],
+        })
+    }
+}
+
+/// The header of an ICMP or ICMPv6 query message (echo, timestamp) sent back to us
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct IcmpResponse {
+    pub(crate) icmp_type: u8,
+    pub(crate) code: u8,
+    pub(crate) identifier: u16,
+    pub(crate) sequence: u16,
+}
+
+#[cfg(unix)]
+impl IcmpResponse {
+    pub(crate) fn parse(packet: &[u8]) -> Option<Self> {
+        let header = packet.get(..8)?;
+
+        Some(Self {
+            icmp_type: header[0],
+            code: header[1],
+            identifier: u16::from_be_bytes([header[4], header[5]]),
+            sequence: u16::from_be_bytes([header[6], header[7]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sctp_responses_are_parsed_from_the_common_header_and_first_chunk() {
+        let packet = [0x96, 0x0c, 0xee, 0x48, 0xde, 0xad, 0xbe, 0xef, 0, 0, 0, 0, 2, 0, 0, 4];
+
+        assert_eq!(
+            SctpResponse::parse(&packet),
+            Some(SctpResponse { source_port: 38412, destination_port: 61000, verification_tag: 0xdead_beef, chunk_type: 2 }),
+        );
+        assert_eq!(SctpResponse::parse(&packet[..12]), None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use pnet::util::MacAddr;
+
+use crate::armada::fingerprint::SynAckFingerprint;
+
+/// The state armada inferred for a scanned port
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PortState {
+    Open,
+    Closed,
+    /// No response was received
+    Filtered,
+    /// The probe was actively rejected with an ICMP destination unreachable, typically by a firewall
+    AdminFiltered,
+    /// No response was received, which for some scan types can mean either open or filtered
+    OpenFiltered,
+    /// The port is reachable through any firewalls, but whether it is open is unknown
+    Unfiltered,
+}
+
+impl PortState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::AdminFiltered => "filtered(admin)",
+            PortState::OpenFiltered => "open|filtered",
+            PortState::Unfiltered => "unfiltered",
+        }
+    }
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+impl FromStr for PortState {
+    type Err = anyhow::Error;
+
+    /// Parses the names `as_str` gives states
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [
+            PortState::Open,
+            PortState::Closed,
+            PortState::Filtered,
+            PortState::AdminFiltered,
+            PortState::OpenFiltered,
+            PortState::Unfiltered,
+        ]
+        .into_iter()
+        .find(|state| state.as_str() == name)
+        .ok_or_else(|| anyhow::anyhow!("Unknown port state '{}'", name))
+    }
+}
+
+/// A single port scan result
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PortResult {
+    pub addr: SocketAddr,
+    pub state: PortState,
+    /// Only recorded for open ports, and only when fingerprints were asked for in the scan options
+    pub fingerprint: Option<SynAckFingerprint>,
+    /// How long the port took to answer the last probe sent to it. Unknown for ports that never answered.
+    pub rtt: Option<Duration>,
+    /// Which probe to the port was the last sent before it answered, counting from 1 for the first and 2 for its
+    /// first retry. Unknown for ports that never answered.
+    pub attempt: Option<u16>,
+}
+
+/// The results of a scan that ran until it was done or cancelled, along with how much of it was covered
+#[derive(Clone, Debug)]
+pub struct ScanReport {
+    pub results: Vec<PortResult>,
+    pub coverage: ScanCoverage,
+}
+
+/// How much of a scan got done
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScanCoverage {
+    pub total_pairs: u128,
+    /// Target/port pairs that were answered or ran out of retries, counting the ones the scan skipped to resume
+    pub settled_pairs: u128,
+    pub packets_sent: u128,
+    pub retries: u128,
+    /// Where a later scan can pick up from with `skip_pairs`
+    pub scan_cursor: u128,
+    /// Whether the scan was cancelled before it got to the end
+    pub cancelled: bool,
+}
+
+impl ScanCoverage {
+    pub fn is_complete(&self) -> bool {
+        self.settled_pairs >= self.total_pairs
+    }
+
+    pub fn percent(&self) -> f64 {
+        match self.total_pairs {
+            0 => 100.0,
+            total_pairs => self.settled_pairs as f64 * 100.0 / total_pairs as f64,
+        }
+    }
+}
+
+impl PortResult {
+    pub fn new(addr: SocketAddr, state: PortState) -> Self {
+        Self {
+            addr,
+            state,
+            fingerprint: None,
+            rtt: None,
+            attempt: None,
+        }
+    }
+
+    pub fn with_fingerprint(mut self, fingerprint: Option<SynAckFingerprint>) -> Self {
+        self.fingerprint = fingerprint;
+
+        self
+    }
+
+    pub fn with_rtt(mut self, rtt: Option<Duration>) -> Self {
+        self.rtt = rtt;
+
+        self
+    }
+
+    pub fn with_attempt(mut self, attempt: Option<u16>) -> Self {
+        self.attempt = attempt;
+
+        self
+    }
+}
+
+/// A host that answered host discovery
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LiveHost {
+    pub ip: IpAddr,
+    /// Only known for hosts on our local networks, which are resolved with ARP
+    pub mac_address: Option<MacAddr>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states_parse_from_their_names() {
+        for state in [
+            PortState::Open,
+            PortState::Closed,
+            PortState::Filtered,
+            PortState::AdminFiltered,
+            PortState::OpenFiltered,
+            PortState::Unfiltered,
+        ] {
+            assert_eq!(state.to_string().parse::<PortState>().unwrap(), state);
+        }
+        assert!("up".parse::<PortState>().is_err());
+    }
+}
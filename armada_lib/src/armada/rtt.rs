@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use twox_hash::XxHash64;
+
+use crate::armada::config::scan::AdaptiveTimeout;
+
+/// Round trip times are tracked per /24 for IPv4 and per /64 for IPv6, where hosts tend to sit behind the same links
+const IPV4_SUBNET_BITS: u32 = 24;
+const IPV6_SUBNET_BITS: u32 = 64;
+/// The lowest RTT variation a timeout allows for, so a few identical samples don't cut the timeout to the bone
+const MIN_RTT_VARIATION: Duration = Duration::from_millis(10);
+
+/// Works out how long to wait for a response from the round trip times seen so far, the way TCP does (RFC 6298).
+/// Subnets are timed on their own once they've answered; until then, the times seen across the whole scan are used,
+/// and before anything has answered, the scan's fixed timeout.
+pub(crate) struct RttEstimator {
+    bounds: AdaptiveTimeout,
+    initial_timeout: Duration,
+    scan_estimate: Option<RttEstimate>,
+    subnet_estimates: HashMap<IpAddr, RttEstimate, BuildHasherDefault<XxHash64>>,
+}
+
+struct RttEstimate {
+    smoothed_rtt: Duration,
+    rtt_variation: Duration,
+}
+
+impl RttEstimate {
+    fn new(rtt: Duration) -> Self {
+        Self { smoothed_rtt: rtt, rtt_variation: rtt / 2 }
+    }
+
+    fn update(&mut self, rtt: Duration) {
+        let deviation = rtt.abs_diff(self.smoothed_rtt);
+
+        self.rtt_variation = self.rtt_variation * 3 / 4 + deviation / 4;
+        self.smoothed_rtt = self.smoothed_rtt * 7 / 8 + rtt / 8;
+    }
+
+    fn timeout(&self) -> Duration {
+        self.smoothed_rtt + self.rtt_variation.max(MIN_RTT_VARIATION) * 4
+    }
+}
+
+impl RttEstimator {
+    pub(crate) fn new(bounds: AdaptiveTimeout, initial_timeout: Duration) -> Self {
+        Self {
+            bounds,
+            initial_timeout,
+            scan_estimate: None,
+            subnet_estimates: HashMap::default(),
+        }
+    }
+
+    /// Only times from probes that weren't retried should be recorded, since there's no telling which of the probes
+    /// a response to a retried one answers
+    pub(crate) fn record(&mut self, remote: IpAddr, rtt: Duration) {
+        match &mut self.scan_estimate {
+            Some(estimate) => estimate.update(rtt),
+            None => self.scan_estimate = Some(RttEstimate::new(rtt)),
+        }
+
+        self.subnet_estimates
+            .entry(subnet(remote))
+            .and_modify(|estimate| estimate.update(rtt))
+            .or_insert_with(|| RttEstimate::new(rtt));
+    }
+
+    /// How long to wait for a response from the remote, doubling for every time its probe has been retried
+    pub(crate) fn timeout(&self, remote: IpAddr, retries: u8) -> Duration {
+        let timeout = self
+            .subnet_estimates
+            .get(&subnet(remote))
+            .or(self.scan_estimate.as_ref())
+            .map_or(self.initial_timeout, RttEstimate::timeout);
+
+        timeout
+            .saturating_mul(1 << retries.min(16))
+            .clamp(self.bounds.min_timeout, self.bounds.max_timeout)
+    }
+}
+
+fn subnet(remote: IpAddr) -> IpAddr {
+    match remote {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & (u32::MAX << (32 - IPV4_SUBNET_BITS)))),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & (u128::MAX << (128 - IPV6_SUBNET_BITS)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn estimator() -> RttEstimator {
+        RttEstimator::new(AdaptiveTimeout { min_timeout: 20 * MS, max_timeout: 2_000 * MS }, 1_000 * MS)
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn the_scan_timeout_applies_until_something_answers() {
+        let estimator = estimator();
+
+        assert_eq!(estimator.timeout(ip("192.0.2.1"), 0), 1_000 * MS);
+        assert_eq!(estimator.timeout(ip("192.0.2.1"), 1), 2_000 * MS);
+    }
+
+    #[test]
+    fn the_first_sample_sets_the_estimate_like_rfc_6298() {
+        let mut estimator = estimator();
+        estimator.record(ip("192.0.2.1"), 100 * MS);
+
+        // srtt + 4 * (srtt / 2)
+        assert_eq!(estimator.timeout(ip("192.0.2.1"), 0), 300 * MS);
+    }
+
+    #[test]
+    fn later_samples_are_smoothed_in() {
+        let mut estimate = RttEstimate::new(100 * MS);
+        estimate.update(180 * MS);
+
+        assert_eq!(estimate.smoothed_rtt, 110 * MS);
+        assert_eq!(estimate.rtt_variation, 57_500 * Duration::from_micros(1));
+    }
+
+    #[test]
+    fn subnets_are_timed_on_their_own_once_they_answer() {
+        let mut estimator = estimator();
+        estimator.record(ip("192.0.2.1"), 10 * MS);
+        estimator.record(ip("198.51.100.7"), 400 * MS);
+
+        // the same /24 as the fast host, a /24 that hasn't answered, and IPv6 with its /64
+        assert_eq!(estimator.timeout(ip("192.0.2.200"), 0), 50 * MS);
+        assert_eq!(estimator.timeout(ip("203.0.113.1"), 0), estimator.scan_estimate.as_ref().unwrap().timeout());
+        estimator.record(ip("2001:db8::1"), 10 * MS);
+        assert_eq!(estimator.timeout(ip("2001:db8::ffff:1"), 0), 50 * MS);
+    }
+
+    #[test]
+    fn timeouts_double_per_retry_within_the_bounds() {
+        let mut estimator = estimator();
+        estimator.record(ip("192.0.2.1"), MS);
+
+        assert_eq!(estimator.timeout(ip("192.0.2.1"), 0), 41 * MS);
+        assert_eq!(estimator.timeout(ip("192.0.2.1"), 2), 164 * MS);
+        assert_eq!(estimator.timeout(ip("192.0.2.1"), 10), 2_000 * MS);
+        assert_eq!(estimator.timeout(ip("192.0.2.1"), u8::MAX), 2_000 * MS);
+
+        estimator.record(ip("198.51.100.1"), Duration::ZERO);
+        assert_eq!(estimator.timeout(ip("198.51.100.1"), 0), 40 * MS);
+    }
+}
@@ -0,0 +1,190 @@
+use pnet::packet::tcp::TcpFlags::{ACK, FIN, PSH, RST, SYN, URG};
+
+use crate::armada::packet::{SCTP_CHUNK_TYPE_ABORT, SCTP_CHUNK_TYPE_INIT_ACK};
+use crate::armada::response::{SctpResponse, TcpResponse};
+use crate::armada::result::PortState;
+
+/// The kind of probe armada sends to each port and how responses to it are interpreted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ScanType {
+    /// Half-open scan. A SYN-ACK means the port is open, a RST means it is closed.
+    #[default]
+    Syn,
+    /// Sends a bare FIN. Per RFC 793, closed ports answer with a RST and open ports stay silent.
+    Fin,
+    /// Sends a packet with no flags set. Interpreted the same way as a FIN scan.
+    Null,
+    /// Sends FIN, PSH and URG ("lit up like a christmas tree"). Interpreted the same way as a FIN scan.
+    Xmas,
+    /// Sends a bare ACK to map firewall rules. A RST means the port is unfiltered while silence or an ICMP
+    /// unreachable means something in the way is filtering it. Says nothing about whether a port is open.
+    Ack,
+    /// An ACK scan that also inspects the window of returned RSTs. Some stacks advertise a non-zero window on RSTs
+    /// from open ports and a zero window from closed ones. On stacks that don't, every port will look closed.
+    Window,
+    /// Sends an SCTP INIT chunk instead of a TCP segment. An INIT-ACK means the port is open, an ABORT means
+    /// it is closed.
+    SctpInit,
+}
+
+impl ScanType {
+    /// Whether probes for this scan type are SCTP packets rather than TCP segments
+    pub(crate) fn is_sctp(&self) -> bool { *self == ScanType::SctpInit }
+
+    /// The TCP flags set on probes for this scan type
+    pub(crate) fn probe_flags(&self) -> u16 {
+        match self {
+            ScanType::Syn => SYN,
+            ScanType::Fin => FIN,
+            ScanType::Null => 0,
+            ScanType::Xmas => FIN | PSH | URG,
+            ScanType::Ack | ScanType::Window => ACK,
+            // not a TCP probe
+            ScanType::SctpInit => 0,
+        }
+    }
+
+    /// Interprets a TCP response to one of our probes.
+    /// Returns `None` if the response doesn't tell us anything about the port.
+    pub(crate) fn classify_response(&self, response: &TcpResponse) -> Option<PortState> {
+        let rst_flag = response.flags & RST != 0;
+        // a bare ACK, as a stateful firewall or a stack mid-connection might send, says nothing about the port
+        let syn_ack_flags = response.flags & (SYN | ACK) == SYN | ACK;
+
+        match self {
+            ScanType::Syn if rst_flag => Some(PortState::Closed),
+            ScanType::Syn if syn_ack_flags => Some(PortState::Open),
+            ScanType::Fin | ScanType::Null | ScanType::Xmas if rst_flag => Some(PortState::Closed),
+            ScanType::Ack if rst_flag => Some(PortState::Unfiltered),
+            ScanType::Window if rst_flag && response.window > 0 => Some(PortState::Open),
+            ScanType::Window if rst_flag => Some(PortState::Closed),
+            _ => None,
+        }
+    }
+
+    /// Interprets an SCTP response to one of our probes.
+    /// Returns `None` if the response doesn't tell us anything about the port.
+    pub(crate) fn classify_sctp_response(&self, response: &SctpResponse) -> Option<PortState> {
+        match (self, response.chunk_type) {
+            (ScanType::SctpInit, SCTP_CHUNK_TYPE_INIT_ACK) => Some(PortState::Open),
+            (ScanType::SctpInit, SCTP_CHUNK_TYPE_ABORT) => Some(PortState::Closed),
+            _ => None,
+        }
+    }
+
+    /// The state a port is assumed to be in once all attempts to probe it went unanswered
+    pub(crate) fn timeout_state(&self) -> PortState {
+        match self {
+            ScanType::Syn | ScanType::Ack | ScanType::Window | ScanType::SctpInit => PortState::Filtered,
+            ScanType::Fin | ScanType::Null | ScanType::Xmas => PortState::OpenFiltered,
+        }
+    }
+
+    /// The state a port is in when a probe to it was answered with an ICMP destination unreachable
+    pub(crate) fn unreachable_state(&self) -> PortState { PortState::AdminFiltered }
+
+    /// Whether ports in this state are worth reporting back for this scan type
+    pub fn reports(&self, state: PortState) -> bool {
+        match self {
+            ScanType::Syn | ScanType::Window | ScanType::SctpInit => state == PortState::Open,
+            ScanType::Fin | ScanType::Null | ScanType::Xmas => state == PortState::OpenFiltered,
+            ScanType::Ack => matches!(state, PortState::Unfiltered | PortState::Filtered | PortState::AdminFiltered),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(flags: u16, window: u16) -> TcpResponse {
+        TcpResponse { source_port: 80, destination_port: 61000, sequence: 0, acknowledgement: 1, flags, window }
+    }
+
+    #[test]
+    fn probes_carry_the_flags_of_their_scan_type() {
+        assert_eq!(ScanType::Syn.probe_flags(), SYN);
+        assert_eq!(ScanType::Fin.probe_flags(), FIN);
+        assert_eq!(ScanType::Null.probe_flags(), 0);
+        assert_eq!(ScanType::Xmas.probe_flags(), FIN | PSH | URG);
+        assert_eq!(ScanType::Ack.probe_flags(), ACK);
+        assert_eq!(ScanType::Window.probe_flags(), ACK);
+    }
+
+    #[test]
+    fn syn_scans_tell_open_from_closed_ports() {
+        assert_eq!(ScanType::Syn.classify_response(&response(SYN | ACK, 64240)), Some(PortState::Open));
+        assert_eq!(ScanType::Syn.classify_response(&response(RST | ACK, 0)), Some(PortState::Closed));
+        assert_eq!(ScanType::Syn.classify_response(&response(FIN, 0)), None);
+    }
+
+    #[test]
+    fn syn_scans_need_both_syn_and_ack_for_open_ports() {
+        assert_eq!(ScanType::Syn.classify_response(&response(ACK, 64240)), None);
+        assert_eq!(ScanType::Syn.classify_response(&response(SYN, 64240)), None);
+        assert_eq!(ScanType::Syn.classify_response(&response(SYN | ACK | PSH, 64240)), Some(PortState::Open));
+    }
+
+    #[test]
+    fn stealth_scans_only_learn_from_resets() {
+        for scan_type in [ScanType::Fin, ScanType::Null, ScanType::Xmas] {
+            assert_eq!(scan_type.classify_response(&response(RST | ACK, 0)), Some(PortState::Closed));
+            assert_eq!(scan_type.classify_response(&response(SYN | ACK, 64240)), None);
+        }
+    }
+
+    #[test]
+    fn ack_scans_take_resets_for_unfiltered_ports() {
+        assert_eq!(ScanType::Ack.classify_response(&response(RST, 0)), Some(PortState::Unfiltered));
+        assert_eq!(ScanType::Ack.classify_response(&response(SYN | ACK, 64240)), None);
+        assert_eq!(ScanType::Ack.unreachable_state(), PortState::AdminFiltered);
+    }
+
+    #[test]
+    fn window_scans_go_by_the_window_of_resets() {
+        assert_eq!(ScanType::Window.classify_response(&response(RST, 1024)), Some(PortState::Open));
+        assert_eq!(ScanType::Window.classify_response(&response(RST, 0)), Some(PortState::Closed));
+        assert_eq!(ScanType::Window.classify_response(&response(ACK, 1024)), None);
+        // ACK scans don't care about the window
+        assert_eq!(ScanType::Ack.classify_response(&response(RST, 1024)), Some(PortState::Unfiltered));
+    }
+
+    #[test]
+    fn sctp_init_scans_tell_open_from_closed_ports() {
+        let response = |chunk_type| SctpResponse { source_port: 38412, destination_port: 61000, verification_tag: 1, chunk_type };
+
+        assert!(ScanType::SctpInit.is_sctp());
+        assert_eq!(ScanType::SctpInit.classify_sctp_response(&response(SCTP_CHUNK_TYPE_INIT_ACK)), Some(PortState::Open));
+        assert_eq!(ScanType::SctpInit.classify_sctp_response(&response(SCTP_CHUNK_TYPE_ABORT)), Some(PortState::Closed));
+        // a heartbeat says nothing about the port, and TCP scans don't listen to SCTP at all
+        assert_eq!(ScanType::SctpInit.classify_sctp_response(&response(4)), None);
+        assert_eq!(ScanType::Syn.classify_sctp_response(&response(SCTP_CHUNK_TYPE_INIT_ACK)), None);
+    }
+
+    #[test]
+    fn unanswered_ports_are_filtered_unless_silence_means_open() {
+        for scan_type in [ScanType::Syn, ScanType::Ack, ScanType::Window, ScanType::SctpInit] {
+            assert_eq!(scan_type.timeout_state(), PortState::Filtered);
+        }
+        for scan_type in [ScanType::Fin, ScanType::Null, ScanType::Xmas] {
+            assert_eq!(scan_type.timeout_state(), PortState::OpenFiltered);
+        }
+    }
+
+    #[test]
+    fn scans_report_the_states_they_can_find() {
+        assert!(ScanType::Syn.reports(PortState::Open));
+        assert!(!ScanType::Syn.reports(PortState::Closed));
+        assert!(!ScanType::Syn.reports(PortState::Filtered));
+
+        for scan_type in [ScanType::Fin, ScanType::Null, ScanType::Xmas] {
+            assert!(scan_type.reports(PortState::OpenFiltered));
+            assert!(!scan_type.reports(PortState::Closed));
+        }
+
+        assert!(ScanType::Ack.reports(PortState::Unfiltered));
+        assert!(ScanType::Ack.reports(PortState::Filtered));
+        assert!(ScanType::Ack.reports(PortState::AdminFiltered));
+        assert!(!ScanType::Ack.reports(PortState::Open));
+    }
+}
@@ -0,0 +1,109 @@
+use std::net::IpAddr;
+
+use anyhow::bail;
+use futures::stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::armada::config::host::HostIterator;
+use crate::armada::config::port::PortIterator;
+use crate::armada::config::scan::ScanOptions;
+use crate::armada::result::{PortResult, ScanReport};
+use crate::armada::sink::ResultSink;
+use crate::armada::{Armada, ArmadaOptions};
+use crate::utils::get_default_ips;
+
+/// A port scanner for embedding armada in other programs. Each scan's results come back as a stream, as they're
+/// found, rather than all at once at the end.
+#[derive(Clone)]
+pub struct Scanner {
+    armada: Armada,
+    options: ScanOptions,
+}
+
+impl Scanner {
+    /// Sets up a scanner listening for responses on the port, failing if it can't be, e.g. for lack of privileges
+    pub fn new(listening_port: u16) -> anyhow::Result<Self> {
+        Self::with_options(ArmadaOptions {
+            listening_ports: vec![listening_port],
+            engine: Default::default(),
+            cores: Vec::new(),
+            interface: None,
+        })
+    }
+
+    /// Sets up a scanner that sends and receives as the options say
+    pub fn with_options(options: ArmadaOptions) -> anyhow::Result<Self> {
+        Ok(Self::from_armada(Armada::with_options(options)?))
+    }
+
+    pub fn from_armada(armada: Armada) -> Self {
+        Self { armada, options: ScanOptions::default() }
+    }
+
+    /// Sets the options every scan is run with. Scans without any source addresses are sent from the addresses of
+    /// the interface holding the default route.
+    pub fn with_scan_options(mut self, options: ScanOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The armada scans are run on, for pausing, stopping and rate limiting them while they run
+    pub fn armada(&self) -> &Armada {
+        &self.armada
+    }
+
+    /// Starts scanning the ports of the targets and returns a stream of the results, which ends once the scan is
+    /// done. Dropping the stream abandons the scan.
+    pub async fn scan(
+        &self,
+        targets: HostIterator,
+        ports: PortIterator,
+    ) -> anyhow::Result<impl Stream<Item = PortResult> + Send + Unpin + 'static> {
+        self.armada.scan_stream(targets, ports, self.scan_options().await?)
+    }
+
+    /// Scans the ports of the targets, handing each result to the sink as it's found and finishing the sink once the
+    /// scan is done
+    pub async fn scan_into<S>(&self, targets: HostIterator, ports: PortIterator, sink: &mut S) -> anyhow::Result<()>
+    where
+        S: ResultSink + ?Sized,
+    {
+        let mut results = self.scan(targets, ports).await?;
+        while let Some(result) = results.next().await {
+            sink.accept(&result);
+        }
+        sink.finish();
+
+        Ok(())
+    }
+
+    /// Scans the ports of the targets until it's done or the token is cancelled, returning the results found by then
+    /// along with how much of the scan they cover
+    pub async fn scan_until_cancelled(
+        &self,
+        targets: HostIterator,
+        ports: PortIterator,
+        cancellation: CancellationToken,
+    ) -> anyhow::Result<ScanReport> {
+        self.armada.scan_until_cancelled(targets, ports, self.scan_options().await?, cancellation).await
+    }
+
+    async fn scan_options(&self) -> anyhow::Result<ScanOptions> {
+        let mut options = self.options.clone();
+
+        if options.source_ipv4_addrs.is_empty() && options.source_ipv6_addrs.is_empty() && options.source_routes.is_none() {
+            let Some(default_ips) = get_default_ips().await else {
+                bail!("No source addresses were given and there's no default route to take them from.");
+            };
+
+            for ip in default_ips {
+                match ip {
+                    IpAddr::V4(ipv4) => options.source_ipv4_addrs.push(ipv4),
+                    IpAddr::V6(ipv6) => options.source_ipv6_addrs.push(ipv6),
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, bail, Context};
+use regex::bytes::{Regex, RegexBuilder};
+
+/// A single entry of the probe database: something to send and the ways of recognizing what answers it
+pub(crate) struct ServiceProbe {
+    pub(crate) name: String,
+    pub(crate) payload: Vec<u8>,
+    /// The only ports this probe is sent to. Empty if it may be sent to any port.
+    pub(crate) ports: Vec<u16>,
+    pub(crate) matches: Vec<ServiceMatchRule>,
+}
+
+impl ServiceProbe {
+    pub(crate) fn applies_to(&self, port: u16) -> bool { self.ports.is_empty() || self.ports.contains(&port) }
+}
+
+pub(crate) struct ServiceMatchRule {
+    pub(crate) service: String,
+    pub(crate) pattern: Regex,
+    /// Template expanded with the pattern's capture groups
+    pub(crate) version: Option<String>,
+}
+
+impl ServiceMatchRule {
+    /// Returns the service's version when the response matches, which is empty if the rule doesn't name one
+    pub(crate) fn try_match(&self, response: &[u8]) -> Option<String> {
+        let captures = self.pattern.captures(response)?;
+
+        let mut version = Vec::new();
+        if let Some(template) = &self.version {
+            captures.expand(template.as_bytes(), &mut version);
+        }
+
+        Some(String::from_utf8_lossy(&version).trim().to_string())
+    }
+}
+
+/// Parses a probe database in the format documented at the top of `service-probes.txt`
+pub(crate) fn parse_database(database: &str) -> anyhow::Result<Vec<ServiceProbe>> {
+    let mut probes: Vec<ServiceProbe> = Vec::new();
+
+    for (line_index, line) in database.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (directive, arguments) = line.split_once(' ').unwrap_or((line, ""));
+
+        let parsed = match directive {
+            "Probe" => parse_probe(arguments).map(|probe| probes.push(probe)),
+            "ports" => current_probe(&mut probes).and_then(|probe| {
+                probe.ports = parse_ports(arguments)?;
+                Ok(())
+            }),
+            "match" => current_probe(&mut probes).and_then(|probe| {
+                probe.matches.push(parse_match(arguments)?);
+                Ok(())
+            }),
+            _ => Err(anyhow!("Unknown directive '{}'.", directive)),
+        };
+
+        parsed.with_context(|| format!("Invalid service probe database entry on line {}.", line_index + 1))?;
+    }
+
+    Ok(probes)
+}
+
+fn current_probe(probes: &mut [ServiceProbe]) -> anyhow::Result<&mut ServiceProbe> {
+    probes.last_mut().context("Directive appears before any Probe.")
+}
+
+fn parse_probe(arguments: &str) -> anyhow::Result<ServiceProbe> {
+    let (name, payload) = arguments.split_once(' ').context("Probe is missing its payload.")?;
+    let (payload, _) = parse_delimited(payload, 'q')?;
+
+    Ok(ServiceProbe {
+        name: name.to_string(),
+        payload: unescape(payload)?,
+        ports: Vec::new(),
+        matches: Vec::new(),
+    })
+}
+
+fn parse_ports(arguments: &str) -> anyhow::Result<Vec<u16>> {
+    arguments
+        .split(',')
+        .map(|port| port.trim().parse::<u16>().with_context(|| format!("Invalid port '{}'.", port)))
+        .collect()
+}
+
+fn parse_match(arguments: &str) -> anyhow::Result<ServiceMatchRule> {
+    let (service, rest) = arguments.split_once(' ').context("Match is missing its pattern.")?;
+    let (pattern, rest) = parse_delimited(rest, 'm')?;
+
+    // flags directly follow the pattern's closing delimiter
+    let flags_length = rest.find(' ').unwrap_or(rest.len());
+    let (flags, rest) = rest.split_at(flags_length);
+
+    let pattern = RegexBuilder::new(pattern)
+        .unicode(false)
+        .case_insensitive(flags.contains('i'))
+        .dot_matches_new_line(flags.contains('s'))
+        .build()
+        .with_context(|| format!("Invalid pattern for service '{}'.", service))?;
+
+    let rest = rest.trim_start();
+    let version = if rest.is_empty() {
+        None
+    } else {
+        let (version, _) = parse_delimited(rest, 'v')?;
+        Some(version.to_string())
+    };
+
+    Ok(ServiceMatchRule {
+        service: service.to_string(),
+        pattern,
+        version,
+    })
+}
+
+/// Splits `<prefix><delimiter>value<delimiter>rest` into the value and the rest
+fn parse_delimited(field: &str, prefix: char) -> anyhow::Result<(&str, &str)> {
+    let mut characters = field.chars();
+
+    if characters.next() != Some(prefix) {
+        bail!("Expected a field starting with '{}'.", prefix);
+    }
+
+    let delimiter = characters.next().context("Field is missing its delimiter.")?;
+    let value_start = prefix.len_utf8() + delimiter.len_utf8();
+    let value_length = field[value_start..].find(delimiter).context("Field is never closed.")?;
+
+    Ok((
+        &field[value_start..value_start + value_length],
+        &field[value_start + value_length + delimiter.len_utf8()..],
+    ))
+}
+
+fn unescape(payload: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(payload.len());
+    let mut characters = payload.chars();
+
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            let mut encoded = [0; 4];
+            bytes.extend_from_slice(character.encode_utf8(&mut encoded).as_bytes());
+            continue;
+        }
+
+        match characters.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = characters.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).with_context(|| format!("Invalid escape '\\x{}'.", hex))?);
+            }
+            other => bail!("Invalid escape '\\{}'.", other.map(String::from).unwrap_or_default()),
+        }
+    }
+
+    Ok(bytes)
+}
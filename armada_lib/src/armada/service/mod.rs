@@ -0,0 +1,121 @@
+//! Identifies the services behind open ports by the responses to protocol specific probes
+
+mod database;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+use crate::armada::banner::read_response;
+use crate::armada::connection::ConnectionOptions;
+use crate::armada::service::database::{parse_database, ServiceProbe};
+
+/// The probe database shipped with armada
+const BUNDLED_DATABASE: &str = include_str!("service-probes.txt");
+/// The name of the probe that sends nothing and waits for the service to greet us
+const NULL_PROBE_NAME: &str = "NULL";
+/// The most bytes of each response kept for matching
+const MAX_RESPONSE_BYTES: usize = 4096;
+
+/// How long service detection waits by default, which is shorter than for banners and certificates as every port may
+/// be sent several probes
+pub const DEFAULT_SERVICE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The service identified on a port
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ServiceMatch {
+    pub service: String,
+    /// Only known if the service gave its version away in its response
+    pub version: Option<String>,
+}
+
+/// Identifies services by sending protocol specific probes and matching the responses against a probe database
+#[derive(Clone)]
+pub struct ServiceDetector {
+    options: ConnectionOptions,
+    probes: Arc<Vec<ServiceProbe>>,
+    connection_permits: Arc<Semaphore>,
+}
+
+impl ServiceDetector {
+    /// Creates a detector using the probe database bundled with armada
+    pub fn new(options: ConnectionOptions) -> Self {
+        Self::with_database(options, BUNDLED_DATABASE).expect("The bundled service probe database is valid.")
+    }
+
+    /// Creates a detector using a custom probe database, written in the same format as the bundled one
+    pub fn with_database(options: ConnectionOptions, database: &str) -> anyhow::Result<Self> {
+        let probes = parse_database(database)?;
+
+        Ok(Self {
+            connection_permits: options.connection_permits(),
+            options,
+            probes: Arc::new(probes),
+        })
+    }
+
+    /// Tries every probe that applies to the port until a response matches. Every probe is sent over a fresh
+    /// connection, so a port may be connected to several times. Returns `None` if nothing matched.
+    pub async fn detect(&self, addr: SocketAddr) -> Option<ServiceMatch> {
+        let _permit = self.connection_permits.acquire().await.ok()?;
+
+        let null_probe = self.probes.iter().find(|probe| probe.name == NULL_PROBE_NAME);
+
+        for probe in self.probe_order(addr.port()) {
+            let response = match self.exchange(addr, &probe.payload).await {
+                Some(response) if !response.is_empty() => response,
+                Some(_) => continue,
+                // the port stopped accepting connections altogether
+                None => return None,
+            };
+
+            let service_match = probe
+                .matches
+                .iter()
+                .chain(null_probe.iter().flat_map(|null_probe| null_probe.matches.iter()))
+                .find_map(|rule| {
+                    rule.try_match(&response).map(|version| ServiceMatch {
+                        service: rule.service.clone(),
+                        version: (!version.is_empty()).then_some(version),
+                    })
+                });
+
+            if service_match.is_some() {
+                return service_match;
+            }
+        }
+
+        None
+    }
+
+    /// The NULL probe goes first since it costs nothing but time, then probes meant for this port, then the rest
+    fn probe_order(&self, port: u16) -> impl Iterator<Item = &ServiceProbe> {
+        let null_probe = self.probes.iter().filter(|probe| probe.name == NULL_PROBE_NAME);
+        let port_probes = self
+            .probes
+            .iter()
+            .filter(move |probe| probe.name != NULL_PROBE_NAME && !probe.ports.is_empty() && probe.applies_to(port));
+        let general_probes = self
+            .probes
+            .iter()
+            .filter(|probe| probe.name != NULL_PROBE_NAME && probe.ports.is_empty());
+
+        null_probe.chain(port_probes).chain(general_probes)
+    }
+
+    /// Sends the payload over a new connection and returns whatever comes back. `None` if we couldn't connect.
+    async fn exchange(&self, addr: SocketAddr, payload: &[u8]) -> Option<Vec<u8>> {
+        let mut stream = timeout(self.options.timeout, TcpStream::connect(addr)).await.ok()?.ok()?;
+
+        if !payload.is_empty() && stream.write_all(payload).await.is_err() {
+            return Some(Vec::new());
+        }
+
+        Some(read_response(&mut stream, MAX_RESPONSE_BYTES, self.options.timeout).await)
+    }
+}
@@ -0,0 +1,18 @@
+use crate::armada::result::PortResult;
+
+/// Somewhere a scan's results are handed to as they're found, such as a file, a database or a collector of the
+/// caller's own. `Scanner::scan_into` feeds a scan into one. Results are `PortResult`s unless a caller enriches them
+/// into records of its own first, which sinks can then take instead.
+pub trait ResultSink<R = PortResult>: Send {
+    fn accept(&mut self, result: &R);
+
+    /// Writes out or sends off anything still held back, once the scan is done
+    fn finish(&mut self) {}
+}
+
+/// Collects the results in the order they were found
+impl<R: Clone + Send> ResultSink<R> for Vec<R> {
+    fn accept(&mut self, result: &R) {
+        self.push(result.clone());
+    }
+}
@@ -0,0 +1,64 @@
+use std::{io, mem};
+
+use pnet::packet::tcp::TcpFlags::{ACK, RST, SYN};
+
+/// Where the fields we read sit in the TCP header
+const TCP_DESTINATION_PORT_OFFSET: u32 = 2;
+const TCP_FLAGS_OFFSET: u32 = 13;
+
+/// Packets that pass are handed over whole
+const ACCEPT_ALL: u32 = u32::MAX;
+const DROP: u32 = 0;
+
+/// Has the kernel drop everything but responses to our probes before they're queued on the raw TCP socket, instead of
+/// having every TCP packet the host receives copied over for us to throw away. Only SYN-ACKs and RSTs sent to the
+/// listening port get through, which covers what every scan type and discovery look for. IPv4 raw sockets see
+/// packets from the IP header on, IPv6 ones from the TCP header on.
+pub(crate) fn attach_tcp_filter(fd: libc::c_int, listening_port: u16, ipv6: bool) -> io::Result<()> {
+    let mut program = Vec::with_capacity(9);
+
+    // index the TCP header through x, so IPv4 options are skipped over
+    program.push(if ipv6 {
+        statement(libc::BPF_LDX | libc::BPF_IMM, 0)
+    } else {
+        statement(libc::BPF_LDX | libc::BPF_B | libc::BPF_MSH, 0)
+    });
+
+    program.extend([
+        statement(libc::BPF_LD | libc::BPF_H | libc::BPF_IND, TCP_DESTINATION_PORT_OFFSET),
+        jump(libc::BPF_JEQ | libc::BPF_K, listening_port as u32, 0, 5),
+        statement(libc::BPF_LD | libc::BPF_B | libc::BPF_IND, TCP_FLAGS_OFFSET),
+        jump(libc::BPF_JSET | libc::BPF_K, RST as u32, 2, 0),
+        statement(libc::BPF_ALU | libc::BPF_AND | libc::BPF_K, (SYN | ACK) as u32),
+        jump(libc::BPF_JEQ | libc::BPF_K, (SYN | ACK) as u32, 0, 1),
+        statement(libc::BPF_RET | libc::BPF_K, ACCEPT_ALL),
+        statement(libc::BPF_RET | libc::BPF_K, DROP),
+    ]);
+
+    let filter = libc::sock_fprog { len: program.len() as u16, filter: program.as_mut_ptr() };
+
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_FILTER,
+            &filter as *const libc::sock_fprog as *const libc::c_void,
+            mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn statement(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+/// Jumps over `jt` instructions when the condition holds, and `jf` when it doesn't
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code: (libc::BPF_JMP | code) as u16, jt, jf, k }
+}
@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasherDefault;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use twox_hash::XxHash64;
+
+use crate::armada::config::route::SourceRoutes;
+use crate::armada::config::scan::SubnetRateLimit;
+use crate::armada::rate::SubnetRateLimiter;
+use crate::armada::work::SourceStats;
+
+/// Where the next probe to a target/port pair goes out from
+pub(crate) enum SourcePick {
+    Source(IpAddr),
+    /// Every source the pair could go out from is over its rate limit for now
+    OverRateLimit,
+    /// There's no source of the pair's address family to send from
+    Unavailable,
+}
+
+/// The addresses a scan's probes go out from. Every target/port pair is given one the first time it's probed, taking
+/// turns with the other addresses of its family, and keeps it through its retries, so its responses are only taken
+/// when they come back to that address. Sources over their rate limit are passed over for the next one in line.
+pub(crate) struct SourcePool {
+    ipv4_addrs: Vec<Ipv4Addr>,
+    ipv6_addrs: Vec<Ipv6Addr>,
+    next_ipv4: usize,
+    next_ipv6: usize,
+    routes: Option<SourceRoutes>,
+    rate_limiter: Option<SubnetRateLimiter>,
+    /// The source of every pair that's been probed and not settled yet
+    pair_sources: HashMap<SocketAddr, IpAddr, BuildHasherDefault<XxHash64>>,
+    /// Probes sent and responses received from each source, ordered by source for stable stats
+    stats: BTreeMap<IpAddr, (u128, u128)>,
+}
+
+impl SourcePool {
+    pub(crate) fn new(
+        ipv4_addrs: Vec<Ipv4Addr>,
+        ipv6_addrs: Vec<Ipv6Addr>,
+        routes: Option<SourceRoutes>,
+        packets_per_second: Option<usize>,
+    ) -> Self {
+        // a limit on subnets the size of one address is one on every address
+        let rate_limiter = packets_per_second.map(|packets_per_second| {
+            SubnetRateLimiter::new(SubnetRateLimit { ipv4_prefix: 32, ipv6_prefix: 128, packets_per_second })
+        });
+
+        Self {
+            ipv4_addrs,
+            ipv6_addrs,
+            next_ipv4: 0,
+            next_ipv6: 0,
+            routes,
+            rate_limiter,
+            pair_sources: HashMap::default(),
+            stats: BTreeMap::new(),
+        }
+    }
+
+    /// Picks the source for the pair's next probe, taking a packet from its rate limit. Pairs already probed stick
+    /// with their source, and the route to the target has the final say over new ones.
+    pub(crate) fn pick(&mut self, remote: SocketAddr) -> SourcePick {
+        if let Some(source) = self.pair_sources.get(&remote).copied() {
+            return match self.try_acquire(source) {
+                true => SourcePick::Source(source),
+                false => SourcePick::OverRateLimit,
+            };
+        }
+
+        let routed_source = self.routes.as_ref().and_then(|routes| routes.source_for(remote.ip()));
+        let candidates = match (routed_source, remote.ip()) {
+            (Some(routed_source), _) => vec![routed_source],
+            (None, IpAddr::V4(_)) => rotate(&self.ipv4_addrs, self.next_ipv4).map(IpAddr::V4).collect(),
+            (None, IpAddr::V6(_)) => rotate(&self.ipv6_addrs, self.next_ipv6).map(IpAddr::V6).collect(),
+        };
+
+        if candidates.is_empty() {
+            return SourcePick::Unavailable;
+        }
+
+        for (offset, source) in candidates.into_iter().enumerate() {
+            if !self.try_acquire(source) {
+                continue;
+            }
+
+            // the turn passes to the source after the one taken
+            if routed_source.is_none() {
+                match source {
+                    IpAddr::V4(_) => self.next_ipv4 = (self.next_ipv4 + offset + 1) % self.ipv4_addrs.len(),
+                    IpAddr::V6(_) => self.next_ipv6 = (self.next_ipv6 + offset + 1) % self.ipv6_addrs.len(),
+                }
+            }
+
+            self.pair_sources.insert(remote, source);
+            return SourcePick::Source(source);
+        }
+
+        SourcePick::OverRateLimit
+    }
+
+    /// Counts the probes that went out against their sources
+    pub(crate) fn record_sent(&mut self, remotes: &[SocketAddr]) {
+        for remote in remotes {
+            if let Some(source) = self.pair_sources.get(remote) {
+                self.stats.entry(*source).or_default().0 += 1;
+            }
+        }
+    }
+
+    /// Whether a response from the remote sent to the destination could be to the remote's probe. Responses whose
+    /// destination isn't known are given the benefit of the doubt.
+    pub(crate) fn expects(&self, remote: SocketAddr, destination: Option<IpAddr>) -> bool {
+        match (self.pair_sources.get(&remote), destination) {
+            (Some(source), Some(destination)) => *source == destination,
+            _ => true,
+        }
+    }
+
+    /// Forgets the pair's source now that its state is settled, counting the response if it answered
+    pub(crate) fn settle(&mut self, remote: &SocketAddr, responded: bool) {
+        if let Some(source) = self.pair_sources.remove(remote) {
+            if responded {
+                self.stats.entry(source).or_default().1 += 1;
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<SourceStats> {
+        self.stats
+            .iter()
+            .map(|(source, (packets_sent, responses_received))| SourceStats {
+                source: *source,
+                packets_sent: *packets_sent,
+                responses_received: *responses_received,
+            })
+            .collect()
+    }
+
+    fn try_acquire(&mut self, source: IpAddr) -> bool {
+        self.rate_limiter.as_mut().is_none_or(|rate_limiter| rate_limiter.try_acquire(source))
+    }
+}
+
+/// Every address, starting from the one at `start`
+fn rotate<T: Copy>(addrs: &[T], start: usize) -> impl Iterator<Item = T> + '_ {
+    addrs[start..].iter().chain(&addrs[..start]).copied()
+}
@@ -1,28 +1,90 @@
+use pnet::packet::ip::IpNextHeaderProtocols;
 use pnet::packet::tcp::TcpPacket;
 use pnet::packet::{ipv4::Ipv4Packet, Packet};
-use pnet::transport::TransportChannelType::{Layer3, Layer4};
-use pnet::transport::TransportProtocol::{Ipv4, Ipv6};
-use pnet::transport::{TransportReceiver, TransportSender};
-use pnet_sys::{Buf, BufLen, MutBuf, SockAddr, SockLen};
-use std::net::IpAddr;
+#[cfg(unix)]
+use pnet::transport::TransportSender;
+#[cfg(all(unix, not(target_os = "linux")))]
+use pnet_sys::{Buf, BufLen, SockAddr};
+
+#[cfg(target_os = "linux")]
+use crate::armada::pktinfo::SourceControl;
+#[cfg(unix)]
+use crate::armada::response::IcmpResponse;
+use crate::armada::response::SctpResponse;
+use crate::armada::transport::{Channel, Transport};
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
 use std::{mem, net};
 
+/// Sending over raw sockets, which there are only unix implementations of
+#[cfg(unix)]
 pub trait TcpSenderExt {
-    fn try_send_to<T: Packet>(
+    /// Sends the packet to the destination without blocking. On Linux it goes out from the source, elsewhere the
+    /// kernel picks the source by route.
+    fn try_send_to(
         &mut self,
-        packet: T,
+        packet: &[u8],
+        source: IpAddr,
         destination: IpAddr,
     ) -> std::io::Result<Option<usize>>;
 }
 
 pub trait TcpReceiverExt {
-    fn try_next(&mut self) -> std::io::Result<Option<(TcpPacket, IpAddr)>>;
+    /// Receives the next TCP packet along with its sender and, for IPv4, the TTL it arrived with and the address it
+    /// was sent to. IPv6 raw sockets don't hand over the IP header those would come from.
+    #[allow(clippy::type_complexity)]
+    fn try_next(&mut self, channel: Channel) -> std::io::Result<Option<(TcpPacket<'_>, IpAddr, Option<u8>, Option<IpAddr>)>>;
+}
+
+pub trait SctpReceiverExt {
+    /// Receives the next SCTP packet along with its sender and, for IPv4, the address it was sent to
+    fn try_next_sctp(&mut self, channel: Channel) -> std::io::Result<Option<(SctpResponse, IpAddr, Option<IpAddr>)>>;
 }
 
+pub trait IcmpReceiverExt {
+    /// Receives the next ICMP (or ICMPv6) message, whatever its type
+    #[cfg(unix)]
+    fn try_next_icmp(&mut self, channel: Channel) -> std::io::Result<Option<(IcmpResponse, IpAddr)>>;
+
+    /// Receives the next ICMP (or ICMPv6) destination unreachable message quoting a TCP or SCTP packet.
+    /// Returns the source port, destination and source address of the packet that couldn't be delivered.
+    fn try_next_unreachable(&mut self, channel: Channel) -> std::io::Result<Option<(u16, SocketAddr, IpAddr)>>;
+}
+
+#[cfg(unix)]
 impl TcpSenderExt for TransportSender {
-    fn try_send_to<T: Packet>(
+    #[cfg(target_os = "linux")]
+    fn try_send_to(
+        &mut self,
+        packet: &[u8],
+        source: IpAddr,
+        destination: IpAddr,
+    ) -> std::io::Result<Option<usize>> {
+        let mut socket_addr_storage: pnet_sys::SockAddrStorage = unsafe { mem::zeroed() };
+        let socket_len = pnet_sys::addr_to_sockaddr(net::SocketAddr::new(destination, 0), &mut socket_addr_storage);
+
+        let mut iovec = libc::iovec { iov_base: packet.as_ptr() as *mut libc::c_void, iov_len: packet.len() };
+        let mut control = SourceControl::default();
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_name = &mut socket_addr_storage as *mut pnet_sys::SockAddrStorage as *mut libc::c_void;
+        header.msg_namelen = socket_len;
+        header.msg_iov = &mut iovec;
+        header.msg_iovlen = 1;
+        control.attach(&mut header, source);
+
+        match unsafe { libc::sendmsg(self.socket.fd, &header, 0) } {
+            // -1 == Would block! We couldn't send a packet immediately so let's return None
+            -1 => Ok(None),
+            len if len < 0 => Err(std::io::Error::last_os_error()),
+            len => Ok(Some(len as usize)),
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn try_send_to(
         &mut self,
-        packet: T,
+        packet: &[u8],
+        _source: IpAddr,
         destination: IpAddr,
     ) -> std::io::Result<Option<usize>> {
         let mut socket_addr_storage = unsafe { mem::zeroed() };
@@ -33,7 +95,7 @@ impl TcpSenderExt for TransportSender {
         };
 
         let socket_len = pnet_sys::addr_to_sockaddr(sockaddr, &mut socket_addr_storage);
-        let buffer = packet.packet();
+        let buffer = packet;
 
         let send_len = unsafe {
             match libc::sendto(
@@ -55,97 +117,270 @@ impl TcpSenderExt for TransportSender {
     }
 }
 
-impl TcpReceiverExt for TransportReceiver {
-    fn try_next(&mut self) -> std::io::Result<Option<(TcpPacket, IpAddr)>> {
-        let buffer = &mut self.buffer;
-        let mut socket_addr_storage: pnet_sys::SockAddrStorage = unsafe { mem::zeroed() };
-        let mut caddrlen = mem::size_of::<pnet_sys::SockAddrStorage>() as SockLen;
+impl<T: Transport + ?Sized> TcpReceiverExt for T {
+    fn try_next(&mut self, channel: Channel) -> std::io::Result<Option<(TcpPacket<'_>, IpAddr, Option<u8>, Option<IpAddr>)>> {
+        let (datagram, ip) = match self.try_recv(channel)? {
+            Some(received) => received,
+            None => return Ok(None),
+        };
 
-        // this is safe as we're not moving or deallocating the memory while in use
-        let recv_len = unsafe {
-            match libc::recvfrom(
-                self.socket.fd,
-                buffer.as_ptr() as MutBuf,
-                buffer.len() as BufLen,
-                libc::MSG_DONTWAIT, // nonblocking so we just try to get the next message, fail otherwise
-                (&mut socket_addr_storage as *mut pnet_sys::SockAddrStorage) as *mut SockAddr,
-                &mut caddrlen,
-            ) {
-                // -1 == Would block! We don't have a packet immediately available so let's return None
-                -1 => return Ok(None),
-                len if len < 0 => Err(std::io::Error::last_os_error()),
-                len => Ok(len as usize),
-            }?
+        // sometimes hosts will return odd packets...
+        let offset = match transport_offset(channel, datagram) {
+            Some(offset) => offset,
+            None => return Ok(None),
         };
 
-        let offset = match self.channel_type {
-            Layer4(Ipv4(_)) => {
-                let ip_header = Ipv4Packet::new(&self.buffer[..]).unwrap();
+        let ip_header = match channel.is_ipv6() {
+            false => Ipv4Packet::new(datagram),
+            true => None,
+        };
+        let ttl = ip_header.as_ref().map(|ip_header| ip_header.get_ttl());
+        let destination = ip_header.as_ref().map(|ip_header| IpAddr::V4(ip_header.get_destination()));
 
-                ip_header.get_header_length() as usize * 4usize
-            }
-            Layer4(Ipv6(_)) => {
-                /*let ip_header = Ipv6Packet::new(&self.buffer[..]).unwrap();
+        let packet = match TcpPacket::new(&datagram[offset..]) {
+            Some(tcp_packet) => tcp_packet,
+            None => return Ok(None),
+        };
 
-                ip_header.get_header_length() as usize * 4usize*/
+        Ok(Some((packet, ip, ttl, destination)))
+    }
+}
 
-                // https://en.wikipedia.org/wiki/IPv6_packet#Fixed_header -> 40 octets
-                40
-            }
-            Layer3(_) => {
-                fixup_packet(&mut self.buffer[..]);
+impl<T: Transport + ?Sized> SctpReceiverExt for T {
+    fn try_next_sctp(&mut self, channel: Channel) -> std::io::Result<Option<(SctpResponse, IpAddr, Option<IpAddr>)>> {
+        let (datagram, ip) = match self.try_recv(channel)? {
+            Some(received) => received,
+            None => return Ok(None),
+        };
 
-                0
-            }
+        let offset = match transport_offset(channel, datagram) {
+            Some(offset) => offset,
+            None => return Ok(None),
         };
 
-        // sometimes hosts will return odd packets...
-        if recv_len < offset {
-            return Ok(None);
-        }
+        let destination = match channel.is_ipv6() {
+            false => Ipv4Packet::new(datagram).map(|ip_header| IpAddr::V4(ip_header.get_destination())),
+            true => None,
+        };
 
-        let packet = match TcpPacket::new(&self.buffer[offset..recv_len]) {
-            Some(tcp_packet) => tcp_packet,
+        Ok(SctpResponse::parse(&datagram[offset..]).map(|response| (response, ip, destination)))
+    }
+}
+
+impl<T: Transport + ?Sized> IcmpReceiverExt for T {
+    #[cfg(unix)]
+    fn try_next_icmp(&mut self, channel: Channel) -> std::io::Result<Option<(IcmpResponse, IpAddr)>> {
+        let (datagram, ip) = match self.try_recv(channel)? {
+            Some(received) => received,
             None => return Ok(None),
         };
 
-        let addr = pnet_sys::sockaddr_to_addr(
-            &socket_addr_storage,
-            mem::size_of::<pnet_sys::SockAddrStorage>(),
-        )?;
-        let ip = match addr {
-            net::SocketAddr::V4(sa) => IpAddr::V4(*sa.ip()),
-            net::SocketAddr::V6(sa) => IpAddr::V6(*sa.ip()),
+        let offset = match transport_offset(channel, datagram) {
+            Some(offset) => offset,
+            None => return Ok(None),
         };
 
-        Ok(Some((packet, ip)))
+        Ok(IcmpResponse::parse(&datagram[offset..]).map(|response| (response, ip)))
+    }
+
+    fn try_next_unreachable(&mut self, channel: Channel) -> std::io::Result<Option<(u16, SocketAddr, IpAddr)>> {
+        use pnet::packet::icmp::destination_unreachable::DestinationUnreachablePacket;
+        use pnet::packet::icmp::IcmpTypes;
+
+        loop {
+            let (datagram, _) = match self.try_recv(channel)? {
+                Some(received) => received,
+                None => return Ok(None),
+            };
+
+            let offset = match transport_offset(channel, datagram) {
+                Some(offset) => offset,
+                None => continue,
+            };
+
+            if channel.is_ipv6() {
+                if let Some(quoted_segment) = quoted_transport_ports_v6(&datagram[offset..]) {
+                    return Ok(Some(quoted_segment));
+                }
+
+                continue;
+            }
+
+            let icmp_packet = match DestinationUnreachablePacket::new(&datagram[offset..]) {
+                Some(icmp_packet) => icmp_packet,
+                None => continue,
+            };
+
+            // we only care about errors, everything else (echo replies, etc.) is skipped
+            if icmp_packet.get_icmp_type() != IcmpTypes::DestinationUnreachable {
+                continue;
+            }
+
+            if let Some(quoted_segment) = quoted_transport_ports_v4(icmp_packet.payload()) {
+                return Ok(Some(quoted_segment));
+            }
+        }
+    }
+}
+
+/// Figures out where the transport layer header begins in the datagram, if it's long enough to have one
+fn transport_offset(channel: Channel, datagram: &[u8]) -> Option<usize> {
+    let offset = match channel.is_ipv6() {
+        false => Ipv4Packet::new(datagram)?.get_header_length() as usize * 4usize,
+        // unlike IPv4, raw IPv6 sockets never hand us the IP header (RFC 3542 section 3)
+        true => 0,
+    };
+
+    (offset <= datagram.len()).then_some(offset)
+}
+
+/// Pulls the source port, destination and source address out of the TCP or SCTP packet quoted by an ICMP error message
+fn quoted_transport_ports_v4(payload: &[u8]) -> Option<(u16, SocketAddr, IpAddr)> {
+    let original_ip_packet = Ipv4Packet::new(payload)?;
+
+    match original_ip_packet.get_next_level_protocol() {
+        IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Sctp => {}
+        _ => return None,
     }
+
+    // only the first 8 bytes of the original packet are guaranteed to be quoted, which is enough for the ports.
+    // both TCP and SCTP lead with the source and destination ports.
+    let header_length = original_ip_packet.get_header_length() as usize * 4usize;
+    let ports = payload.get(header_length .. header_length + 4)?;
+
+    let source_port = u16::from_be_bytes([ports[0], ports[1]]);
+    let destination_port = u16::from_be_bytes([ports[2], ports[3]]);
+
+    Some((
+        source_port,
+        SocketAddr::new(IpAddr::V4(original_ip_packet.get_destination()), destination_port),
+        IpAddr::V4(original_ip_packet.get_source()),
+    ))
 }
 
-#[cfg(any(target_os = "freebsd", target_os = "macos", target_os = "ios"))]
-fn fixup_packet(buffer: &mut [u8]) {
-    use pnet_packet::ipv4::MutableIpv4Packet;
+/// Pulls the source port, destination and source address out of the TCP or SCTP packet quoted by an ICMPv6
+/// destination unreachable
+fn quoted_transport_ports_v6(icmpv6_message: &[u8]) -> Option<(u16, SocketAddr, IpAddr)> {
+    use pnet::packet::ipv6::Ipv6Packet;
 
-    let buflen = buffer.len();
-    let mut new_packet = MutableIpv4Packet::new(buffer).unwrap();
+    const ICMPV6_TYPE_DESTINATION_UNREACHABLE: u8 = 1;
+    // type, code, checksum and 4 unused bytes precede the quoted packet
+    const ICMPV6_ERROR_HEADER_LENGTH: usize = 8;
+    const IPV6_HEADER_LENGTH: usize = 40;
 
-    let length = u16::from_be(new_packet.get_total_length());
-    new_packet.set_total_length(length);
+    if *icmpv6_message.first()? != ICMPV6_TYPE_DESTINATION_UNREACHABLE {
+        return None;
+    }
+
+    let quoted_packet = icmpv6_message.get(ICMPV6_ERROR_HEADER_LENGTH..)?;
+    let original_ip_packet = Ipv6Packet::new(quoted_packet)?;
 
-    // OS X does this awesome thing where it removes the header length
-    // from the total length sometimes.
-    let length =
-        new_packet.get_total_length() as usize + (new_packet.get_header_length() as usize * 4usize);
-    if length == buflen {
-        new_packet.set_total_length(length as u16)
+    // probes never carry extension headers, so the transport header directly follows the IPv6 header
+    match original_ip_packet.get_next_header() {
+        IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Sctp => {}
+        _ => return None,
     }
 
-    let offset = u16::from_be(new_packet.get_fragment_offset());
-    new_packet.set_fragment_offset(offset);
+    let ports = quoted_packet.get(IPV6_HEADER_LENGTH..IPV6_HEADER_LENGTH + 4)?;
+
+    let source_port = u16::from_be_bytes([ports[0], ports[1]]);
+    let destination_port = u16::from_be_bytes([ports[2], ports[3]]);
+
+    Some((
+        source_port,
+        SocketAddr::new(IpAddr::V6(original_ip_packet.get_destination()), destination_port),
+        IpAddr::V6(original_ip_packet.get_source()),
+    ))
 }
 
-#[cfg(all(
-    not(target_os = "freebsd"),
-    not(any(target_os = "macos", target_os = "ios"))
-))]
-fn fixup_packet(_buffer: &mut [u8]) {}
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    /// An IPv4 header with `options` 32-bit words of options, quoting the start of a transport header from port 61000
+    /// to port 443
+    fn quoted_ipv4_packet(protocol: u8, options: u8) -> Vec<u8> {
+        let mut packet = vec![0; 20 + options as usize * 4];
+        packet[0] = 0x40 | (5 + options);
+        packet[9] = protocol;
+        packet[12..16].copy_from_slice(&[192, 0, 2, 1]);
+        packet[16..20].copy_from_slice(&[198, 51, 100, 7]);
+        packet.extend_from_slice(&[0xee, 0x48, 0x01, 0xbb, 0, 0, 0, 1]);
+
+        packet
+    }
+
+    /// An ICMPv6 error message of the type, quoting an IPv6 header and the start of a transport header from port 61000
+    /// to port 443
+    fn icmpv6_error(icmpv6_type: u8, next_header: u8) -> Vec<u8> {
+        let mut message = vec![icmpv6_type, 1, 0, 0, 0, 0, 0, 0];
+        let mut ipv6_header = vec![0; 40];
+        ipv6_header[0] = 0x60;
+        ipv6_header[6] = next_header;
+        ipv6_header[8..24].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        ipv6_header[24..40].copy_from_slice(&"2001:db8:1::7".parse::<Ipv6Addr>().unwrap().octets());
+        message.extend(ipv6_header);
+        message.extend_from_slice(&[0xee, 0x48, 0x01, 0xbb, 0, 0, 0, 1]);
+
+        message
+    }
+
+    fn probe(source: IpAddr, target: IpAddr) -> Option<(u16, SocketAddr, IpAddr)> {
+        Some((61000, SocketAddr::new(target, 443), source))
+    }
+
+    #[test]
+    fn icmp_errors_quoting_tcp_and_sctp_give_the_probe_back() {
+        let source = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let target = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+
+        assert_eq!(quoted_transport_ports_v4(&quoted_ipv4_packet(6, 0)), probe(source, target));
+        assert_eq!(quoted_transport_ports_v4(&quoted_ipv4_packet(132, 0)), probe(source, target));
+        // the ports come after however many options the quoted header has
+        assert_eq!(quoted_transport_ports_v4(&quoted_ipv4_packet(6, 2)), probe(source, target));
+    }
+
+    #[test]
+    fn icmp_errors_quoting_anything_else_are_skipped() {
+        assert_eq!(quoted_transport_ports_v4(&quoted_ipv4_packet(17, 0)), None);
+        assert_eq!(quoted_transport_ports_v4(&quoted_ipv4_packet(1, 0)), None);
+    }
+
+    #[test]
+    fn icmp_errors_quoting_too_little_are_skipped() {
+        let packet = quoted_ipv4_packet(6, 0);
+
+        assert_eq!(quoted_transport_ports_v4(&packet[..22]), None);
+        assert_eq!(quoted_transport_ports_v4(&packet[..10]), None);
+        assert_eq!(quoted_transport_ports_v4(&[]), None);
+    }
+
+    #[test]
+    fn icmpv6_unreachables_quoting_tcp_and_sctp_give_the_probe_back() {
+        let source = IpAddr::V6("2001:db8::1".parse().unwrap());
+        let target = IpAddr::V6("2001:db8:1::7".parse().unwrap());
+
+        assert_eq!(quoted_transport_ports_v6(&icmpv6_error(1, 6)), probe(source, target));
+        assert_eq!(quoted_transport_ports_v6(&icmpv6_error(1, 132)), probe(source, target));
+    }
+
+    #[test]
+    fn other_icmpv6_messages_are_skipped() {
+        // packet too big, time exceeded and echo replies
+        for icmpv6_type in [2, 3, 129] {
+            assert_eq!(quoted_transport_ports_v6(&icmpv6_error(icmpv6_type, 6)), None);
+        }
+        assert_eq!(quoted_transport_ports_v6(&icmpv6_error(1, 17)), None);
+    }
+
+    #[test]
+    fn icmpv6_unreachables_quoting_too_little_are_skipped() {
+        let message = icmpv6_error(1, 6);
+
+        assert_eq!(quoted_transport_ports_v6(&message[..50]), None);
+        assert_eq!(quoted_transport_ports_v6(&message[..8]), None);
+        assert_eq!(quoted_transport_ports_v6(&[]), None);
+    }
+}
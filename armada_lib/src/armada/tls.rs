@@ -0,0 +1,165 @@
+//! Collects the certificates open ports present in a TLS handshake
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use tokio_rustls::TlsConnector;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::armada::connection::ConnectionOptions;
+
+/// Ports that commonly serve TLS from the first byte, as opposed to upgrading with STARTTLS
+pub const COMMON_TLS_PORTS: [u16; 18] = [
+    443, 465, 636, 853, 989, 990, 992, 993, 994, 995, 2376, 3269, 5061, 5986, 6443, 8443, 8883, 9443,
+];
+
+/// The parts of a server certificate that matter when taking inventory of an attack surface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertificateInfo {
+    pub subject_common_name: Option<String>,
+    /// DNS names and IP addresses the certificate is valid for
+    pub subject_alt_names: Vec<String>,
+    pub issuer: String,
+    pub not_after: SystemTime,
+}
+
+/// Performs TLS handshakes with open ports and records the certificate each server presents
+#[derive(Clone)]
+pub struct TlsInspector {
+    options: ConnectionOptions,
+    connector: TlsConnector,
+    connection_permits: Arc<Semaphore>,
+}
+
+impl TlsInspector {
+    pub fn new(options: ConnectionOptions) -> Self {
+        let provider = Arc::new(ring::default_provider());
+
+        let config = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("The ring provider supports the default protocol versions.")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate { provider }))
+            .with_no_client_auth();
+
+        Self {
+            connection_permits: options.connection_permits(),
+            options,
+            connector: TlsConnector::from(Arc::new(config)),
+        }
+    }
+
+    /// Returns `None` if the connection or handshake failed, or if the certificate couldn't be parsed.
+    pub async fn inspect(&self, addr: SocketAddr) -> Option<CertificateInfo> {
+        let _permit = self.connection_permits.acquire().await.ok()?;
+
+        let stream = timeout(self.options.timeout, TcpStream::connect(addr)).await.ok()?.ok()?;
+        let server_name = ServerName::IpAddress(addr.ip().into());
+        let tls_stream = timeout(self.options.timeout, self.connector.connect(server_name, stream))
+            .await
+            .ok()?
+            .ok()?;
+
+        let (_, connection) = tls_stream.get_ref();
+        let end_entity = connection.peer_certificates()?.first()?;
+
+        parse_certificate(end_entity)
+    }
+}
+
+fn parse_certificate(certificate: &CertificateDer<'_>) -> Option<CertificateInfo> {
+    let (_, certificate) = X509Certificate::from_der(certificate.as_ref()).ok()?;
+
+    let subject_common_name = certificate
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|common_name| common_name.as_str().ok())
+        .map(str::to_string);
+
+    let subject_alt_names = certificate
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|extension| {
+            extension
+                .value
+                .general_names
+                .iter()
+                .filter_map(|general_name| match general_name {
+                    GeneralName::DNSName(dns_name) => Some(dns_name.to_string()),
+                    GeneralName::IPAddress(ip_bytes) => parse_ip_address(ip_bytes).map(|ip| ip.to_string()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let not_after = UNIX_EPOCH + Duration::from_secs(certificate.validity().not_after.timestamp().max(0) as u64);
+
+    Some(CertificateInfo {
+        subject_common_name,
+        subject_alt_names,
+        issuer: certificate.issuer().to_string(),
+        not_after,
+    })
+}
+
+fn parse_ip_address(ip_bytes: &[u8]) -> Option<IpAddr> {
+    match ip_bytes.len() {
+        4 => <[u8; 4]>::try_from(ip_bytes).ok().map(|octets| IpAddr::V4(Ipv4Addr::from(octets))),
+        16 => <[u8; 16]>::try_from(ip_bytes).ok().map(|octets| IpAddr::V6(Ipv6Addr::from(octets))),
+        _ => None,
+    }
+}
+
+/// We're collecting certificates, not trusting them, so expired, self-signed and mismatched certificates are all fair
+/// game. Handshake signatures are still checked so the certificate is known to belong to the server we spoke to.
+#[derive(Debug)]
+struct AcceptAnyCertificate {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
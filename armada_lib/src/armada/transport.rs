@@ -0,0 +1,203 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::util::MacAddr;
+
+#[cfg(unix)]
+use crate::armada::channels::TransportChannels;
+use crate::armada::datalink::DatalinkTransport;
+use crate::armada::scan_type::ScanType;
+#[cfg(target_os = "linux")]
+use crate::armada::uring::IoUringTransport;
+#[cfg(target_os = "linux")]
+use crate::armada::xdp::XdpTransport;
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+/// How port scan probes get onto the network and their responses off of it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Raw sockets going through the kernel's network stack
+    #[default]
+    RawSocket,
+    /// Raw sockets driven through an io_uring, which sends a whole batch of probes and collects their completions in
+    /// one system call. Falls back to `RawSocket` where io_uring isn't available, like on kernels older than 5.4,
+    /// with io_uring disabled, or off of Linux.
+    IoUring,
+    /// AF_XDP sockets on the interface, which skip the kernel's network stack for both probes and responses. Only
+    /// available on Linux, and takes `CAP_NET_ADMIN` and `CAP_BPF` to set up. With a VLAN, probes go out with its
+    /// 802.1Q tag and only responses tagged with it are taken, for scanning from a trunk port. Frames go to the next
+    /// hop in the kernel's neighbor table, unless `router_mac` is given for targets behind a gateway, or the
+    /// routing table has no route to, and `target_mac` for targets on the link.
+    Xdp {
+        interface: String,
+        vlan: Option<u16>,
+        router_mac: Option<MacAddr>,
+        target_mac: Option<MacAddr>,
+    },
+    /// Whole Ethernet frames through the interface's packet capture driver, which is Npcap on Windows and AF_PACKET
+    /// or BPF elsewhere. Frames are addressed the way the XDP engine's are, but responses still reach the kernel's
+    /// network stack as well.
+    Npcap {
+        interface: String,
+        vlan: Option<u16>,
+        router_mac: Option<MacAddr>,
+        target_mac: Option<MacAddr>,
+    },
+    /// Whole Ethernet frames through a `/dev/bpf` device on the interface, for macOS, whose raw sockets don't hand TCP
+    /// responses back. Frames are addressed like the Npcap engine's, from the routing and neighbor tables that
+    /// `netstat`, `arp` and `ndp` show.
+    Bpf {
+        interface: String,
+        router_mac: Option<MacAddr>,
+        target_mac: Option<MacAddr>,
+    },
+}
+
+/// The kinds of traffic a scan sends and receives, each of which gets a raw socket of its own when going through the
+/// kernel's network stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Channel {
+    Ipv4Tcp,
+    Ipv6Tcp,
+    Ipv4Sctp,
+    Ipv6Sctp,
+    Ipv4Icmp,
+    Ipv6Icmp,
+}
+
+impl Channel {
+    pub(crate) const ALL: [Channel; 6] = [
+        Channel::Ipv4Tcp,
+        Channel::Ipv6Tcp,
+        Channel::Ipv4Sctp,
+        Channel::Ipv6Sctp,
+        Channel::Ipv4Icmp,
+        Channel::Ipv6Icmp,
+    ];
+
+    /// The channel probes of the scan type to the remote go out on
+    pub(crate) fn for_probe(scan_type: ScanType, remote: IpAddr) -> Self {
+        match (scan_type.is_sctp(), remote.is_ipv6()) {
+            (false, false) => Channel::Ipv4Tcp,
+            (false, true) => Channel::Ipv6Tcp,
+            (true, false) => Channel::Ipv4Sctp,
+            (true, true) => Channel::Ipv6Sctp,
+        }
+    }
+
+    /// The channel responses of the protocol arrive on, if it's one we listen for
+    pub(crate) fn for_response(protocol: IpNextHeaderProtocol, ipv6: bool) -> Option<Self> {
+        match (protocol, ipv6) {
+            (IpNextHeaderProtocols::Tcp, false) => Some(Channel::Ipv4Tcp),
+            (IpNextHeaderProtocols::Tcp, true) => Some(Channel::Ipv6Tcp),
+            (IpNextHeaderProtocols::Sctp, false) => Some(Channel::Ipv4Sctp),
+            (IpNextHeaderProtocols::Sctp, true) => Some(Channel::Ipv6Sctp),
+            (IpNextHeaderProtocols::Icmp, false) => Some(Channel::Ipv4Icmp),
+            (IpNextHeaderProtocols::Icmpv6, true) => Some(Channel::Ipv6Icmp),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_ipv6(self) -> bool {
+        matches!(self, Channel::Ipv6Tcp | Channel::Ipv6Sctp | Channel::Ipv6Icmp)
+    }
+
+    pub(crate) fn protocol(self) -> IpNextHeaderProtocol {
+        match self {
+            Channel::Ipv4Tcp | Channel::Ipv6Tcp => IpNextHeaderProtocols::Tcp,
+            Channel::Ipv4Sctp | Channel::Ipv6Sctp => IpNextHeaderProtocols::Sctp,
+            Channel::Ipv4Icmp => IpNextHeaderProtocols::Icmp,
+            Channel::Ipv6Icmp => IpNextHeaderProtocols::Icmpv6,
+        }
+    }
+
+    /// The channel's position in `Channel::ALL`, for keeping something per channel in an array
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Moves probes out onto the network and responses back in. Probes are queued up and handed over a batch at a time,
+/// so transports that can send many packets at once get the chance to.
+pub(crate) trait Transport {
+    /// Sets up for a scan that queues up to this many probes between flushes. With batched IO off, probes go out one
+    /// system call at a time, for transports where that's a choice.
+    fn set_batching(&mut self, _batch_size: usize, _batched_io: bool) {}
+
+    /// Sets the TTL, or hop limit, probes go out with from here on. `None` goes back to the system's default.
+    fn set_ttl(&mut self, _ttl: Option<u8>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Has IPv4 probes split into IP fragments carrying up to this many bytes of the probe each from here on, rounded
+    /// down to a multiple of 8. `None` sends probes whole.
+    fn set_fragmentation(&mut self, _fragment_size: Option<usize>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Queues the transport layer packet to go out from the source to the remote, returning false if it can't be
+    /// taken right now. Depending on the transport, the packet may go out right away.
+    fn queue(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool;
+
+    /// Sends the transport layer packet to the remote from a decoy source that isn't ours, no later than the next
+    /// flush. Nothing is expected back, so it isn't reported as sent. Returns false if it couldn't go out, which is
+    /// what transports that can't write their own IP headers always do.
+    fn send_decoy(&mut self, _channel: Channel, _decoy: IpAddr, _remote: SocketAddr, _packet: &[u8]) -> bool {
+        false
+    }
+
+    /// Sends whatever is still queued, returning the remotes whose probes went out since the last flush followed by
+    /// the ones whose didn't
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>);
+
+    /// Receives the next datagram on the channel without blocking, along with who sent it. Datagrams are laid out
+    /// the way raw sockets hand them over: IPv4 ones start with the IP header, IPv6 ones with the transport header
+    /// (RFC 3542 section 3).
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>>;
+}
+
+/// Sets up the transport the engine scans with, ready to send probes from the listening port. Raw sockets are bound
+/// to the interface when there is one, the XDP, Npcap and BPF engines always go through the interface they're given.
+pub(crate) fn open(
+    engine: &Engine,
+    listening_port: u16,
+    interface: Option<&str>,
+) -> anyhow::Result<Box<dyn Transport>> {
+    match engine {
+        #[cfg(unix)]
+        Engine::RawSocket => Ok(Box::new(TransportChannels::open(listening_port, interface)?)),
+        #[cfg(target_os = "linux")]
+        Engine::IoUring => match IoUringTransport::open(listening_port, interface) {
+            Ok(transport) => Ok(Box::new(transport)),
+            Err(e) => {
+                warn!(err = ?e, "io_uring is unavailable, falling back to raw sockets");
+                Ok(Box::new(TransportChannels::open(listening_port, interface)?))
+            }
+        },
+        #[cfg(all(unix, not(target_os = "linux")))]
+        Engine::IoUring => Ok(Box::new(TransportChannels::open(listening_port, interface)?)),
+        #[cfg(not(unix))]
+        Engine::RawSocket | Engine::IoUring => {
+            let _ = (listening_port, interface);
+            anyhow::bail!("Raw sockets don't send TCP on Windows. Please scan with the npcap engine instead.")
+        }
+        #[cfg(target_os = "linux")]
+        Engine::Xdp { interface, vlan, router_mac, target_mac } => Ok(Box::new(
+            XdpTransport::open(interface, *vlan, listening_port)?
+                .with_router_mac(*router_mac)
+                .with_target_mac(*target_mac),
+        )),
+        #[cfg(not(target_os = "linux"))]
+        Engine::Xdp { .. } => {
+            anyhow::bail!("The XDP engine is only available on Linux.")
+        }
+        Engine::Npcap { interface, vlan, router_mac, target_mac } => Ok(Box::new(
+            DatalinkTransport::open(interface, *vlan)?.with_router_mac(*router_mac).with_target_mac(*target_mac),
+        )),
+        Engine::Bpf { interface, router_mac, target_mac } => Ok(Box::new(
+            DatalinkTransport::open(interface, None)?.with_router_mac(*router_mac).with_target_mac(*target_mac),
+        )),
+    }
+}
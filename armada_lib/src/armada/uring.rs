@@ -0,0 +1,554 @@
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::{io, mem, ptr};
+
+use anyhow::{bail, Context};
+use pnet_sys::{SockAddrStorage, SockLen};
+use tracing::warn;
+
+use crate::armada::channels::TransportChannels;
+use crate::armada::packet::MAX_TCP_PROBE_LENGTH;
+use crate::armada::pktinfo::SourceControl;
+use crate::armada::transport::{Channel, Transport};
+
+/// Submission queue entries, enough for the largest batch of probes along with every receive being reposted at once
+const RING_ENTRIES: u32 = 2048;
+/// Receives kept posted on each socket
+const RECEIVE_SLOTS: usize = 64;
+/// Responses longer than this are cut short, which only ever loses the tail of a quote in an ICMP error
+const RECEIVE_SLOT_LENGTH: usize = 2048;
+/// Fits both the largest TCP probe and an SCTP INIT
+const SEND_SLOT_LENGTH: usize = MAX_TCP_PROBE_LENGTH;
+
+const IORING_OP_SENDMSG: u8 = 9;
+const IORING_OP_RECVMSG: u8 = 10;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+
+/// Tells completions of sends apart from those of receives, the rest of the user data being the slot and channel
+const SEND_COMPLETION: u64 = 1 << 63;
+
+/// Sends probes and receives responses on the raw sockets through an io_uring. A whole batch of probes goes out with
+/// a single system call that also collects their completions, and receives stay posted on every socket so responses
+/// are usually waiting in the completion queue by the time they're read.
+pub(crate) struct IoUringTransport {
+    /// Goes first so it's torn down before anything its receives point into
+    ring: Ring,
+    channels: TransportChannels,
+    sends: SendSlots,
+    receives: Vec<ReceiveSlots>,
+    /// The response being read
+    current: Vec<u8>,
+    /// Whether IPv4 probes are fragmented, in which case they go out through the raw sockets directly
+    fragmenting: bool,
+}
+
+impl IoUringTransport {
+    pub(crate) fn open(listening_port: u16, interface: Option<&str>) -> anyhow::Result<Self> {
+        let ring = Ring::new(RING_ENTRIES)?;
+        let channels = TransportChannels::open(listening_port, interface)?;
+
+        let mut transport = Self {
+            ring,
+            channels,
+            sends: SendSlots::new(0),
+            receives: Channel::ALL.iter().map(|_| ReceiveSlots::new()).collect(),
+            current: Vec::new(),
+            fragmenting: false,
+        };
+
+        for channel in Channel::ALL {
+            for slot in 0..RECEIVE_SLOTS {
+                transport.post_receive(channel, slot);
+            }
+        }
+        transport.ring.enter(0).context("Unable to post receives to the io_uring")?;
+
+        Ok(transport)
+    }
+
+    fn post_receive(&mut self, channel: Channel, slot: usize) {
+        let header = self.receives[channel.index()].reset(slot);
+
+        let entry = SubmissionEntry {
+            opcode: IORING_OP_RECVMSG,
+            fd: self.channels.fd(channel),
+            addr: header as u64,
+            len: 1,
+            user_data: (channel.index() as u64) << 32 | slot as u64,
+            ..SubmissionEntry::default()
+        };
+
+        // the ring holds every receive at once, so there's always room for one being reposted
+        self.ring.push(entry);
+    }
+
+    /// Sorts the completions that came in, returning how many sends completed
+    fn reap(&mut self) -> usize {
+        let mut sends_completed = 0;
+
+        while let Some(completion) = self.ring.pop() {
+            let slot = (completion.user_data & u32::MAX as u64) as usize;
+
+            if completion.user_data & SEND_COMPLETION != 0 {
+                self.sends.complete(slot, completion.res >= 0);
+                sends_completed += 1;
+                continue;
+            }
+
+            let channel = Channel::ALL[(completion.user_data >> 32) as usize];
+            match completion.res {
+                length if length > 0 => self.receives[channel.index()].ready.push_back((slot, length as usize)),
+                // errors like a full buffer don't stop the socket from receiving more
+                _ => self.post_receive(channel, slot),
+            }
+        }
+
+        sends_completed
+    }
+}
+
+impl Transport for IoUringTransport {
+    /// Only sizes the batches of sends, since receives are always posted ahead of time
+    fn set_batching(&mut self, batch_size: usize, _batched_io: bool) {
+        self.sends = SendSlots::new(batch_size);
+        self.channels.set_batching(batch_size, false);
+    }
+
+    fn set_ttl(&mut self, ttl: Option<u8>) -> anyhow::Result<()> {
+        self.channels.set_ttl(ttl)
+    }
+
+    fn set_fragmentation(&mut self, fragment_size: Option<usize>) -> anyhow::Result<()> {
+        self.fragmenting = fragment_size.is_some();
+        self.channels.set_fragmentation(fragment_size)
+    }
+
+    fn queue(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        if self.fragmenting && remote.is_ipv4() {
+            return self.channels.queue(channel, source, remote, packet);
+        }
+
+        let header = match self.sends.push(packet, source, remote) {
+            Some(header) => header,
+            None => return false,
+        };
+
+        let entry = SubmissionEntry {
+            opcode: IORING_OP_SENDMSG,
+            fd: self.channels.fd(channel),
+            addr: header as u64,
+            len: 1,
+            user_data: SEND_COMPLETION | (self.sends.remotes.len() - 1) as u64,
+            ..SubmissionEntry::default()
+        };
+
+        if !self.ring.push(entry) {
+            self.sends.remotes.pop();
+            return false;
+        }
+
+        true
+    }
+
+    /// Decoys are few and far between, so they skip the ring
+    fn send_decoy(&mut self, channel: Channel, decoy: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        self.channels.send_decoy(channel, decoy, remote, packet)
+    }
+
+    /// Submits the queued sends and waits for all of them to complete, since their slots are reused for the next batch
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        let mut pending = self.sends.remotes.len();
+
+        while pending > 0 {
+            pending -= self.reap().min(pending);
+
+            if pending == 0 {
+                break;
+            }
+
+            match self.ring.enter(pending as u32) {
+                Ok(()) => {}
+                // a full completion queue empties as the completions are reaped
+                Err(e) if e.kind() == io::ErrorKind::Interrupted || e.raw_os_error() == Some(libc::EBUSY) => {}
+                Err(e) => {
+                    warn!(err = ?e, "Unable to submit probes to the io_uring");
+                    break;
+                }
+            }
+        }
+
+        let (mut sent, unsent) = self.sends.take();
+        sent.extend(self.channels.flush().0);
+
+        (sent, unsent)
+    }
+
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>> {
+        if self.receives[channel.index()].ready.is_empty() {
+            self.reap();
+        }
+
+        // completions of receives only show up once we're in the kernel, so go and look for them
+        if self.receives[channel.index()].ready.is_empty() {
+            self.ring.enter(0)?;
+            self.reap();
+        }
+
+        let (slot, length) = match self.receives[channel.index()].ready.pop_front() {
+            Some(ready) => ready,
+            None => return Ok(None),
+        };
+
+        let receives = &self.receives[channel.index()];
+        let sender = pnet_sys::sockaddr_to_addr(&receives.senders[slot], mem::size_of::<SockAddrStorage>())?;
+        self.current.clear();
+        self.current.extend_from_slice(&receives.buffers[slot * RECEIVE_SLOT_LENGTH..][..length]);
+
+        self.post_receive(channel, slot);
+
+        Ok(Some((&self.current, sender.ip())))
+    }
+}
+
+/// Probes waiting for their sends to complete. The headers point into the other vecs, none of which move or change
+/// size while the kernel holds them.
+struct SendSlots {
+    packets: Vec<u8>,
+    destinations: Vec<SockAddrStorage>,
+    iovecs: Vec<libc::iovec>,
+    controls: Vec<SourceControl>,
+    headers: Vec<libc::msghdr>,
+    remotes: Vec<SocketAddr>,
+    sent: Vec<bool>,
+}
+
+impl SendSlots {
+    fn new(capacity: usize) -> Self {
+        Self {
+            packets: vec![0; capacity * SEND_SLOT_LENGTH],
+            destinations: vec![unsafe { mem::zeroed() }; capacity],
+            iovecs: vec![libc::iovec { iov_base: ptr::null_mut(), iov_len: 0 }; capacity],
+            controls: vec![SourceControl::default(); capacity],
+            headers: vec![unsafe { mem::zeroed() }; capacity],
+            remotes: Vec::with_capacity(capacity),
+            sent: vec![false; capacity],
+        }
+    }
+
+    /// Copies the packet into the next slot, returning the message header to send it with if there's a slot left
+    fn push(&mut self, packet: &[u8], source: IpAddr, remote: SocketAddr) -> Option<*mut libc::msghdr> {
+        let slot = self.remotes.len();
+
+        if slot == self.headers.len() || packet.len() > SEND_SLOT_LENGTH {
+            return None;
+        }
+
+        let buffer = &mut self.packets[slot * SEND_SLOT_LENGTH..][..packet.len()];
+        buffer.copy_from_slice(packet);
+
+        // raw sockets take the protocol from the socket, so the destination's port must be left out
+        let destination_length =
+            pnet_sys::addr_to_sockaddr(SocketAddr::new(remote.ip(), 0), &mut self.destinations[slot]);
+        self.iovecs[slot] = libc::iovec { iov_base: buffer.as_mut_ptr() as *mut libc::c_void, iov_len: buffer.len() };
+
+        let header = &mut self.headers[slot];
+        header.msg_name = &mut self.destinations[slot] as *mut SockAddrStorage as *mut libc::c_void;
+        header.msg_namelen = destination_length as SockLen;
+        header.msg_iov = &mut self.iovecs[slot];
+        header.msg_iovlen = 1;
+        self.controls[slot].attach(header, source);
+
+        self.remotes.push(remote);
+        self.sent[slot] = false;
+
+        Some(header)
+    }
+
+    fn complete(&mut self, slot: usize, sent: bool) {
+        if let Some(slot_sent) = self.sent.get_mut(slot) {
+            *slot_sent = sent;
+        }
+    }
+
+    /// Empties the slots, returning the remotes whose probes went out followed by the ones whose didn't
+    fn take(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        let (sent, unsent): (Vec<_>, Vec<_>) =
+            self.remotes.drain(..).zip(&self.sent).partition(|(_, sent)| **sent);
+
+        (sent.into_iter().map(|(remote, _)| remote).collect(), unsent.into_iter().map(|(remote, _)| remote).collect())
+    }
+}
+
+/// Receives kept posted on one socket, along with the slots whose responses are waiting to be read
+struct ReceiveSlots {
+    buffers: Vec<u8>,
+    senders: Vec<SockAddrStorage>,
+    iovecs: Vec<libc::iovec>,
+    headers: Vec<libc::msghdr>,
+    ready: VecDeque<(usize, usize)>,
+}
+
+impl ReceiveSlots {
+    fn new() -> Self {
+        let mut slots = Self {
+            buffers: vec![0; RECEIVE_SLOTS * RECEIVE_SLOT_LENGTH],
+            senders: vec![unsafe { mem::zeroed() }; RECEIVE_SLOTS],
+            iovecs: Vec::with_capacity(RECEIVE_SLOTS),
+            headers: vec![unsafe { mem::zeroed() }; RECEIVE_SLOTS],
+            ready: VecDeque::with_capacity(RECEIVE_SLOTS),
+        };
+
+        slots.iovecs.extend(slots.buffers.chunks_exact_mut(RECEIVE_SLOT_LENGTH).map(|buffer| libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        }));
+
+        slots
+    }
+
+    /// Readies the slot to receive into again, returning the message header to receive with
+    fn reset(&mut self, slot: usize) -> *mut libc::msghdr {
+        let header = &mut self.headers[slot];
+        header.msg_name = &mut self.senders[slot] as *mut SockAddrStorage as *mut libc::c_void;
+        // the kernel shortens this to the sender address it wrote
+        header.msg_namelen = mem::size_of::<SockAddrStorage>() as SockLen;
+        header.msg_iov = &mut self.iovecs[slot];
+        header.msg_iovlen = 1;
+
+        header
+    }
+}
+
+/// `struct io_uring_sqe`, with the fields we never set left as padding
+#[repr(C)]
+#[derive(Default)]
+struct SubmissionEntry {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    msg_flags: u32,
+    user_data: u64,
+    _padding: [u64; 3],
+}
+
+/// `struct io_uring_cqe`
+#[repr(C)]
+struct CompletionEntry {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SubmissionRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CompletionRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+/// `struct io_uring_params`
+#[repr(C)]
+#[derive(Default)]
+struct Parameters {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SubmissionRingOffsets,
+    cq_off: CompletionRingOffsets,
+}
+
+/// An io_uring with its submission and completion queues mapped in
+struct Ring {
+    fd: OwnedFd,
+    rings: *mut libc::c_void,
+    rings_length: usize,
+    entries: *mut SubmissionEntry,
+    entries_length: usize,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const CompletionEntry,
+    /// Where the next submission goes, and how far the kernel has been handed them
+    tail: u32,
+    submitted: u32,
+}
+
+impl Ring {
+    fn new(entries: u32) -> anyhow::Result<Self> {
+        let mut parameters = Parameters::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut parameters as *mut Parameters) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Unable to set up an io_uring");
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        // kernels since 5.4 map both queues in one go, which is old enough not to bother with anything earlier
+        if parameters.features & IORING_FEAT_SINGLE_MMAP == 0 {
+            bail!("The kernel's io_uring is too old.");
+        }
+
+        let submission_length = parameters.sq_off.array as usize + parameters.sq_entries as usize * mem::size_of::<u32>();
+        let completion_length =
+            parameters.cq_off.cqes as usize + parameters.cq_entries as usize * mem::size_of::<CompletionEntry>();
+        let rings_length = submission_length.max(completion_length);
+        let rings = map(fd.as_raw_fd(), 0, rings_length).context("Unable to map the io_uring")?;
+
+        let entries_length = parameters.sq_entries as usize * mem::size_of::<SubmissionEntry>();
+        let entries = match map(fd.as_raw_fd(), IORING_OFF_SQES, entries_length) {
+            Ok(entries) => entries as *mut SubmissionEntry,
+            Err(e) => {
+                unsafe { libc::munmap(rings, rings_length) };
+                return Err(e).context("Unable to map the io_uring submission entries");
+            }
+        };
+
+        unsafe {
+            let at = |offset: u32| (rings as *mut u8).add(offset as usize);
+            let tail = (*(at(parameters.sq_off.tail) as *const AtomicU32)).load(Ordering::Relaxed);
+
+            Ok(Self {
+                fd,
+                rings,
+                rings_length,
+                entries,
+                entries_length,
+                sq_head: at(parameters.sq_off.head) as *const AtomicU32,
+                sq_tail: at(parameters.sq_off.tail) as *const AtomicU32,
+                sq_mask: *(at(parameters.sq_off.ring_mask) as *const u32),
+                sq_entries: parameters.sq_entries,
+                sq_array: at(parameters.sq_off.array) as *mut u32,
+                cq_head: at(parameters.cq_off.head) as *const AtomicU32,
+                cq_tail: at(parameters.cq_off.tail) as *const AtomicU32,
+                cq_mask: *(at(parameters.cq_off.ring_mask) as *const u32),
+                cqes: at(parameters.cq_off.cqes) as *const CompletionEntry,
+                tail,
+                submitted: tail,
+            })
+        }
+    }
+
+    /// Queues the entry up for the next `enter`, returning false if the submission queue is full
+    fn push(&mut self, entry: SubmissionEntry) -> bool {
+        let head = unsafe { (*self.sq_head).load(Ordering::Acquire) };
+
+        if self.tail.wrapping_sub(head) == self.sq_entries {
+            return false;
+        }
+
+        let index = self.tail & self.sq_mask;
+        unsafe {
+            self.entries.add(index as usize).write(entry);
+            self.sq_array.add(index as usize).write(index);
+        }
+        self.tail = self.tail.wrapping_add(1);
+
+        true
+    }
+
+    /// Submits everything queued up and collects completions, waiting for at least this many of them
+    fn enter(&mut self, min_complete: u32) -> io::Result<()> {
+        unsafe { (*self.sq_tail).store(self.tail, Ordering::Release) };
+
+        let to_submit = self.tail.wrapping_sub(self.submitted);
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                to_submit,
+                min_complete,
+                IORING_ENTER_GETEVENTS,
+                ptr::null::<libc::sigset_t>(),
+                0,
+            )
+        };
+
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.submitted = self.submitted.wrapping_add(result as u32);
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<CompletionEntry> {
+        let head = unsafe { (*self.cq_head).load(Ordering::Relaxed) };
+        let tail = unsafe { (*self.cq_tail).load(Ordering::Acquire) };
+
+        if head == tail {
+            return None;
+        }
+
+        let completion = unsafe { self.cqes.add((head & self.cq_mask) as usize).read() };
+        unsafe { (*self.cq_head).store(head.wrapping_add(1), Ordering::Release) };
+
+        Some(completion)
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.entries as *mut libc::c_void, self.entries_length);
+            libc::munmap(self.rings, self.rings_length);
+        }
+    }
+}
+
+fn map(fd: i32, offset: libc::off_t, length: usize) -> io::Result<*mut libc::c_void> {
+    let memory = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            length,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            fd,
+            offset,
+        )
+    };
+
+    if memory == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(memory)
+}
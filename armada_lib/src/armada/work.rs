@@ -1,73 +1,369 @@
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::time::Duration;
-
-use crate::armada::config::host::HostIterator;
-use crate::armada::config::port::PortIterator;
+use crate::armada::config::host::{HostIndex, HostIterator};
+use crate::armada::config::port::{PortIndex, PortIterator};
+use crate::armada::config::scan::{RateLimitStrategy, ScanOptions, ScanShard};
+use crate::armada::permutation::Permutation;
+use crate::armada::result::PortResult;
 use itertools::{Itertools, Product};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
 
 use tokio::sync::mpsc::UnboundedSender;
 
 #[derive(Debug)]
 pub enum ArmadaWorkMessage {
-    Results(Vec<SocketAddr>),
+    Results(Vec<PortResult>),
     Stats {
         total_processed_ports: u128,
         current_inflight_packets: u128,
-        total_packets_sent: u128
-    }
+        total_packets_sent: u128,
+        /// Responses that settled the state of a port still in flight
+        total_responses_received: u128,
+        /// Probes sent again after an earlier one went unanswered
+        total_retries: u128,
+        /// How many target/port pairs from the start of the scan are all settled. Passing it as the `skip_pairs` of
+        /// a later scan of the same targets and ports picks up where this one left off.
+        scan_cursor: u128,
+        /// The rate limit the scan is currently sent at, which changes as the scan goes with auto rate
+        packets_per_second: Option<usize>,
+        /// How each source address the scan sends from is faring, for the ones it's sent from so far
+        source_stats: Vec<SourceStats>,
+    },
+    /// A worker's share of the scan failed part way, so the scan ends without the pairs it had left
+    Failed(anyhow::Error),
+}
+
+/// The probes sent from one source address and the responses to them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceStats {
+    pub source: IpAddr,
+    pub packets_sent: u128,
+    /// Responses that settled the state of a port probed from the source
+    pub responses_received: u128,
 }
 
 impl ArmadaWorkMessage {
-    pub fn results(results: Vec<SocketAddr>) -> ArmadaWorkMessage {
+    pub fn results(results: Vec<PortResult>) -> ArmadaWorkMessage {
         ArmadaWorkMessage::Results(results)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn stats(
         total_processed_ports: u128,
         current_inflight_packets: u128,
-        total_packets_sent: u128
+        total_packets_sent: u128,
+        total_responses_received: u128,
+        total_retries: u128,
+        scan_cursor: u128,
+        packets_per_second: Option<usize>,
+        source_stats: Vec<SourceStats>,
     ) -> ArmadaWorkMessage {
         ArmadaWorkMessage::Stats {
             total_processed_ports,
             current_inflight_packets,
-            total_packets_sent
+            total_packets_sent,
+            total_responses_received,
+            total_retries,
+            scan_cursor,
+            packets_per_second,
+            source_stats,
         }
     }
 }
 
-// todo: change to struct to carry work info
+/// The smallest rate limit a shard is given. The rate limit is enforced per tenth of a second, so anything lower
+/// would never send.
+const MIN_SHARD_RATE: usize = 10;
+
 #[derive(Debug)]
 pub(crate) struct ArmadaWork {
-    pub(crate) remote_addrs: Product<HostIterator, PortIterator>,
-    pub(crate) port_retries: u8,
-    pub(crate) port_timeout: Duration,
-    pub(crate) packets_per_second: Option<usize>,
-    pub(crate) source_ipv4_addrs: Vec<Ipv4Addr>,
-    pub(crate) source_ipv6_addrs: Vec<Ipv6Addr>,
+    pub(crate) remote_addrs: RemoteAddrs,
+    pub(crate) options: ScanOptions,
     pub(crate) reporting_channel: UnboundedSender<ArmadaWorkMessage>,
+    pub(crate) shard: Shard,
 }
 
 impl ArmadaWork {
-    pub(crate) fn new(
+    /// Splits the scan into a unit of work for each of up to this many workers. Every worker walks the same order of
+    /// target/port pairs and probes its own share of them, taking an even share of the rate limits along with it. Low
+    /// rate limits are split across fewer workers, so each share is still enough to send at.
+    pub(crate) fn split(
         remote_hosts: HostIterator,
         ports: PortIterator,
-        port_retries: u8,
-        port_timeout: Duration,
-        packets_per_second: Option<usize>,
-        source_ipv4_addrs: Vec<Ipv4Addr>,
-        source_ipv6_addrs: Vec<Ipv6Addr>,
+        options: ScanOptions,
         reporting_channel: UnboundedSender<ArmadaWorkMessage>,
-    ) -> Self {
-        let remote_addrs = remote_hosts.cartesian_product(ports);
+        workers: usize,
+    ) -> Vec<Self> {
+        let rate_limit = options
+            .packets_per_second
+            .into_iter()
+            .chain(options.subnet_rate_limit.map(|subnet_rate_limit| subnet_rate_limit.packets_per_second))
+            .chain(options.source_rate_limit);
+        let shard_count = rate_limit
+            .map(|packets_per_second| (packets_per_second / MIN_SHARD_RATE).max(1))
+            .fold(workers.max(1), usize::min)
+            // the spacing only holds between probes sent by the same worker
+            .min(if options.pacing.is_some() { 1 } else { usize::MAX });
+
+        // every shard has to walk the pairs in the same order for their shares not to overlap
+        let shuffle_seed = options.seed.unwrap_or_else(rand::random);
+        let progress = Arc::new(Mutex::new(vec![ShardStats::starting_at(options.skip_pairs); shard_count]));
+
+        (0..shard_count)
+            .map(|index| {
+                let remote_addrs = if options.randomize {
+                    RemoteAddrs::shuffled(&remote_hosts, &ports, shuffle_seed)
+                } else {
+                    RemoteAddrs::Sequential(remote_hosts.clone().cartesian_product(ports.clone()))
+                };
+
+                let mut options = options.clone();
+                options.packets_per_second = options.packets_per_second.map(|rate| share(rate, index, shard_count));
+                if let RateLimitStrategy::TokenBucket { burst } = &mut options.rate_limiter {
+                    *burst = share(*burst, index, shard_count).max(1);
+                }
+                if let Some(subnet_rate_limit) = &mut options.subnet_rate_limit {
+                    subnet_rate_limit.packets_per_second =
+                        share(subnet_rate_limit.packets_per_second, index, shard_count);
+                }
+                options.source_rate_limit = options.source_rate_limit.map(|rate| share(rate, index, shard_count));
+
+                Self {
+                    remote_addrs,
+                    shard: Shard {
+                        index,
+                        count: shard_count,
+                        scan_shard: options.shard,
+                        progress: progress.clone(),
+                    },
+                    options,
+                    reporting_channel: reporting_channel.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The shard's part of the total, with the remainder going to the first shards
+fn share(total: usize, index: usize, count: usize) -> usize {
+    total / count + usize::from(index < total % count)
+}
+
+/// The share of a scan's target/port pairs one worker probes: every pair whose position in the scan order is the
+/// shard's index, counting in the number of shards. A scan that's itself a shard is shared out by the position of
+/// each pair among the scan shard's.
+#[derive(Clone, Debug)]
+pub(crate) struct Shard {
+    pub(crate) index: usize,
+    count: usize,
+    scan_shard: Option<ScanShard>,
+    /// The latest stats of every shard of the scan, which each shard's stats updates are merged with
+    progress: Arc<Mutex<Vec<ShardStats>>>,
+}
+
+impl Shard {
+    /// The shard's part of a rate limit
+    pub(crate) fn share(&self, total: usize) -> usize {
+        share(total, self.index, self.count)
+    }
+
+    pub(crate) fn owns(&self, pair: u128) -> bool {
+        let pair = match self.scan_shard {
+            Some(scan_shard) if !scan_shard.owns(pair) => return false,
+            Some(scan_shard) => pair / scan_shard.count as u128,
+            None => pair,
+        };
+
+        pair % self.count as u128 == self.index as u128
+    }
+
+    /// Folds the shard's stats update in with the latest ones of the other shards, so the update covers the whole
+    /// scan. Every pair before the earliest scan cursor of any shard is settled.
+    pub(crate) fn merge(&self, message: ArmadaWorkMessage) -> ArmadaWorkMessage {
+        let stats = match message {
+            ArmadaWorkMessage::Stats {
+                total_processed_ports,
+                current_inflight_packets,
+                total_packets_sent,
+                total_responses_received,
+                total_retries,
+                scan_cursor,
+                packets_per_second,
+                source_stats,
+            } => ShardStats {
+                total_processed_ports,
+                current_inflight_packets,
+                total_packets_sent,
+                total_responses_received,
+                total_retries,
+                scan_cursor,
+                packets_per_second,
+                source_stats,
+            },
+            message @ (ArmadaWorkMessage::Results(_) | ArmadaWorkMessage::Failed(_)) => return message,
+        };
+
+        let mut progress = self.progress.lock().expect("Shard progress lock was poisoned.");
+        let scan_cursor = stats.scan_cursor;
+        progress[self.index] = stats;
+
+        // every shard sends from the same sources, so each source's stats are summed up across them
+        let mut source_stats = BTreeMap::<IpAddr, SourceStats>::new();
+        for stats in progress.iter().flat_map(|stats| &stats.source_stats) {
+            let merged = source_stats.entry(stats.source).or_insert(SourceStats {
+                source: stats.source,
+                packets_sent: 0,
+                responses_received: 0,
+            });
+            merged.packets_sent += stats.packets_sent;
+            merged.responses_received += stats.responses_received;
+        }
+
+        ArmadaWorkMessage::stats(
+            progress.iter().map(|stats| stats.total_processed_ports).sum(),
+            progress.iter().map(|stats| stats.current_inflight_packets).sum(),
+            progress.iter().map(|stats| stats.total_packets_sent).sum(),
+            progress.iter().map(|stats| stats.total_responses_received).sum(),
+            progress.iter().map(|stats| stats.total_retries).sum(),
+            progress.iter().map(|stats| stats.scan_cursor).min().unwrap_or(scan_cursor),
+            progress.iter().filter_map(|stats| stats.packets_per_second).reduce(|total, rate| total + rate),
+            source_stats.into_values().collect(),
+        )
+    }
+}
+
+/// The last stats update a shard sent
+#[derive(Clone, Debug)]
+struct ShardStats {
+    total_processed_ports: u128,
+    current_inflight_packets: u128,
+    total_packets_sent: u128,
+    total_responses_received: u128,
+    total_retries: u128,
+    scan_cursor: u128,
+    packets_per_second: Option<usize>,
+    source_stats: Vec<SourceStats>,
+}
 
+impl ShardStats {
+    /// Where a shard stands before its first update, with the pairs skipped by a resumed scan already settled
+    fn starting_at(scan_cursor: u128) -> Self {
         Self {
-            remote_addrs,
-            port_retries,
-            port_timeout,
-            packets_per_second,
-            source_ipv4_addrs,
-            source_ipv6_addrs,
-            reporting_channel,
+            total_processed_ports: 0,
+            current_inflight_packets: 0,
+            total_packets_sent: 0,
+            total_responses_received: 0,
+            total_retries: 0,
+            scan_cursor,
+            packets_per_second: None,
+            source_stats: Vec::new(),
+        }
+    }
+}
+
+/// The target/port pairs of a scan, in the order they're probed
+#[derive(Debug)]
+pub(crate) enum RemoteAddrs {
+    /// Every port of a target before moving on to the next target
+    Sequential(Product<HostIterator, PortIterator>),
+    /// Pairs spread across every target and port, so no one subnet takes the probes all at once
+    Shuffled {
+        hosts: HostIndex,
+        ports: PortIndex,
+        port_count: u128,
+        permutation: Permutation,
+        next_pair: u128,
+        total_pairs: u128,
+    },
+}
+
+impl RemoteAddrs {
+    fn shuffled(remote_hosts: &HostIterator, ports: &PortIterator, seed: u64) -> Self {
+        let port_count = ports.size() as u128;
+        let total_pairs = remote_hosts.size() * port_count;
+
+        RemoteAddrs::Shuffled {
+            hosts: remote_hosts.index(),
+            ports: ports.index(),
+            port_count,
+            permutation: Permutation::new(total_pairs, seed),
+            next_pair: 0,
+            total_pairs,
+        }
+    }
+}
+
+impl Iterator for RemoteAddrs {
+    type Item = (IpAddr, u16);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            RemoteAddrs::Sequential(remote_addrs) => remote_addrs.next(),
+            RemoteAddrs::Shuffled { hosts, ports, port_count, permutation, next_pair, total_pairs } => {
+                if *next_pair == *total_pairs {
+                    return None;
+                }
+
+                // the pair is numbered the way the sequential order would number it
+                let pair = permutation.shuffle(*next_pair);
+                *next_pair += 1;
+
+                Some((hosts.get(pair / *port_count)?, ports.get((pair % *port_count) as u64)?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cidr_utils::cidr::IpCidr;
+
+    #[test]
+    fn shuffled_pairs_are_the_sequential_ones_in_another_order() {
+        let hosts = HostIterator::new()
+            .add_cidr(IpCidr::from_str("192.0.2.0/28").unwrap())
+            .add_cidr(IpCidr::from_str("2001:db8::/126").unwrap());
+        let ports = PortIterator::new().add_range(20, 25).add_port(443);
+
+        let sequential: Vec<_> = RemoteAddrs::Sequential(hosts.clone().cartesian_product(ports.clone())).collect();
+        let shuffled: Vec<_> = RemoteAddrs::shuffled(&hosts, &ports, 7).collect();
+        let mut sorted_shuffled = shuffled.clone();
+        sorted_shuffled.sort_unstable();
+        let mut sorted_sequential = sequential.clone();
+        sorted_sequential.sort_unstable();
+
+        assert_eq!(sequential.len(), 20 * 7);
+        assert_eq!(sorted_shuffled, sorted_sequential);
+        assert_ne!(shuffled, sequential);
+        assert_eq!(shuffled, RemoteAddrs::shuffled(&hosts, &ports, 7).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scan_shards_split_the_pairs_between_them() {
+        let shards: Vec<_> = (0 .. 3).map(|index| ScanShard { index, count: 3 }).collect();
+
+        for pair in 0 .. 30 {
+            assert_eq!(shards.iter().filter(|shard| shard.owns(pair)).count(), 1, "pair {}", pair);
+        }
+        for position in 0 .. 30 {
+            let owned = shards.iter().map(|shard| shard.pairs_before(position)).collect::<Vec<_>>();
+            assert_eq!(owned.iter().sum::<u128>(), position);
+            assert_eq!(owned[1], (0 .. position).filter(|pair| shards[1].owns(*pair)).count() as u128);
+        }
+    }
+
+    #[test]
+    fn workers_split_their_scan_shard_between_them() {
+        let scan_shard = ScanShard { index: 1, count: 4 };
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let workers: Vec<_> =
+            (0 .. 2).map(|index| Shard { index, count: 2, scan_shard: Some(scan_shard), progress: progress.clone() }).collect();
+
+        for pair in 0 .. 40 {
+            let owners = workers.iter().filter(|worker| worker.owns(pair)).count();
+            assert_eq!(owners, scan_shard.owns(pair) as usize, "pair {}", pair);
         }
+        assert!(workers[0].owns(1) && workers[1].owns(5));
     }
 }
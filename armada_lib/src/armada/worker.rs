@@ -1,409 +1,767 @@
-use crate::armada::tcp_ext::{TcpReceiverExt, TcpSenderExt};
+use crate::armada::capture::CapturingTransport;
+use crate::armada::fingerprint::SynAckFingerprint;
+use crate::armada::config::scan::{Decoys, ScanOptions, TcpProbeProfile};
+use crate::armada::control::ScanControls;
+use crate::armada::hooks::HookedTransport;
+use crate::armada::rate::{pacer, rate_limiter, Pacer, RateController, SubnetRateLimiter};
+use crate::armada::response::TcpResponse;
+use crate::armada::rtt::RttEstimator;
+use crate::armada::result::PortResult;
+use crate::armada::scan_type::ScanType;
+use crate::armada::source::{SourcePick, SourcePool};
+use crate::armada::tcp_ext::{IcmpReceiverExt, SctpReceiverExt, TcpReceiverExt};
+use crate::armada::transport::{Channel, Transport};
 use crate::armada::work::{ArmadaWork, ArmadaWorkMessage};
-use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::transport::{
-    transport_channel, TransportChannelType,
-    TransportProtocol, TransportReceiver, TransportSender,
-};
-use std::collections::{HashMap, HashSet};
+use pnet::packet::tcp::TcpFlags::{ACK, SYN};
+use pnet::packet::Packet;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, VecDeque};
 use std::hash::BuildHasherDefault;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use anyhow::Context;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tracing::{error, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, trace, warn};
 use twox_hash::XxHash64;
 
 const BATCH_SEND_SIZE: usize = 32;
-const BATCH_RECV_SIZE: usize = 32;
 //const OPEN_PORT_REPORTING_SIZE: usize = 16;
 const RATE_LIMIT_RESOLUTION: u64 = 100;
 const MS_IN_SECOND: usize = 1_000;
+/// The lowest rate limit set while a scan runs that a worker is held to, since anything lower would never send
+const MIN_WORKER_RATE: usize = MS_IN_SECOND / RATE_LIMIT_RESOLUTION as usize;
+/// The most probes held back by a per subnet rate limit before no new pairs are taken on
+const MAX_DEFERRED_ADDRS: usize = BATCH_SEND_SIZE * 256;
 
 pub(crate) struct ArmadaWorker {
     work_queue: UnboundedReceiver<ArmadaWork>,
+    listening_port: u16,
+    tcp_seq: u32,
+    /// Pausing, stopping and rate limit changes from outside of the scan
+    controls: Arc<ScanControls>,
+    /// Reseeded at the start of every scan from its seed
+    rng: StdRng,
+    /// Set up at the start of every scan that limits the rate per subnet
+    subnet_rate_limiter: Option<SubnetRateLimiter>,
+    /// Set up at the start of every scan that spaces out its probes
+    pacer: Option<Box<dyn Pacer>>,
+    /// Probes to subnets that are over their rate limit, waiting for the next rate limit window
+    deferred_addrs: VecDeque<SocketAddr>,
+    /// Set up at the start of every scan with the addresses it sends from
+    sources: SourcePool,
+    /// Set at the start of every scan that sends decoys along with its probes
+    decoys: Option<Decoys>,
+    /// Set at the start of every scan to the window and options its TCP probes go out with
+    probe_profile: TcpProbeProfile,
+    /// The most probes sent, and responses received from each socket, per pass of the scan loop
+    batch_size: usize,
 }
 
 impl ArmadaWorker {
-    pub(crate) fn new(work_queue: UnboundedReceiver<ArmadaWork>) -> Self {
-        Self { work_queue }
+    pub(crate) fn new(work_queue: UnboundedReceiver<ArmadaWork>, listening_port: u16, controls: Arc<ScanControls>) -> Self {
+        Self {
+            work_queue,
+            listening_port,
+            tcp_seq: rand::random::<u32>(),
+            controls,
+            rng: StdRng::from_entropy(),
+            subnet_rate_limiter: None,
+            pacer: None,
+            deferred_addrs: VecDeque::new(),
+            sources: SourcePool::new(Vec::new(), Vec::new(), None, None),
+            decoys: None,
+            probe_profile: TcpProbeProfile::default(),
+            batch_size: BATCH_SEND_SIZE,
+        }
     }
 
-    /// Runs the Armada worker, only processing (and sending) packets with the specified port
-    pub(crate) fn run(mut self, port: u16) -> anyhow::Result<()> {
-        // todo: increase buffer size
-        let ipv4_protocol =
-            TransportChannelType::Layer4(TransportProtocol::Ipv4(IpNextHeaderProtocols::Tcp));
-        let ipv6_protocol =
-            TransportChannelType::Layer4(TransportProtocol::Ipv6(IpNextHeaderProtocols::Tcp));
-
-        let (mut ipv4_tcp_sender, mut ipv4_tcp_receiver) =
-            transport_channel(1024 * 16, ipv4_protocol)
-                .context("Error on raw socket initialization")?;
-
-        let (mut ipv6_tcp_sender, mut ipv6_tcp_receiver) =
-            transport_channel(1024 * 16, ipv6_protocol)
-                .context("Error on raw socket initialization")?;
-
-        let mut tcp_seq = rand::random::<u32>();
-
+    /// Runs the Armada worker, only processing (and sending) packets with the listening port
+    pub(crate) fn run(mut self, mut transport: Box<dyn Transport>) -> anyhow::Result<()> {
         while let Some(work) = self.work_queue.blocking_recv() {
-            if let Err(e) = self.process_work(
-                work,
-                &mut ipv4_tcp_sender,
-                &mut ipv4_tcp_receiver,
-                &mut ipv6_tcp_sender,
-                &mut ipv6_tcp_receiver,
-                port,
-                &mut tcp_seq,
-            ) {
+            let reporting_channel = work.reporting_channel.clone();
+
+            if let Err(e) = self.process_work(work, transport.as_mut()) {
                 error!(err = ?e, "scan failed");
+                let _ = reporting_channel.send(ArmadaWorkMessage::Failed(e));
             }
         }
 
         Ok(())
     }
 
-    fn process_work(
-        &self,
-        work_unit: ArmadaWork,
-        ipv4_tcp_sender: &mut TransportSender,
-        ipv4_tcp_receiver: &mut TransportReceiver,
-        ipv6_tcp_sender: &mut TransportSender,
-        ipv6_tcp_receiver: &mut TransportReceiver,
-        listening_port: u16,
-        tcp_seq: &mut u32,
-    ) -> anyhow::Result<()> {
+    fn process_work(&mut self, work_unit: ArmadaWork, transport: &mut dyn Transport) -> anyhow::Result<()> {
         let ArmadaWork {
             mut remote_addrs,
-            port_retries,
-            port_timeout,
-            packets_per_second,
-            source_ipv4_addrs,
-            source_ipv6_addrs,
+            options,
             reporting_channel,
+            shard,
         } = work_unit;
+        let probe_profile = options.tcp_probe_profile();
 
-        let mut requeued_addrs = Vec::with_capacity(1024 * 8);
+        let ScanOptions {
+            source_ipv4_addrs,
+            source_ipv6_addrs,
+            source_routes,
+            port_retries,
+            port_timeout,
+            mut packets_per_second,
+            rate_limiter: rate_limit_strategy,
+            scan_type,
+            ttl,
+            fragment_size,
+            decoys,
+            // already worked out above
+            probe_profile: _,
+            collect_fingerprints,
+            report_all_states,
+            skip_pairs,
+            // already applied to the shard the worker was given
+            shard: _,
+            raw_results,
+            seed,
+            // already applied to the order of the remote addrs
+            randomize: _,
+            auto_rate,
+            adaptive_timeout,
+            subnet_rate_limit,
+            source_rate_limit,
+            pacing,
+            deadline,
+            packet_batch_size,
+            batched_io,
+            capture,
+            packet_hooks,
+            cancellation,
+        } = options;
+
+        // flushed once dropped, by which point the scan is over
+        let mut capturing_transport;
+        let transport: &mut dyn Transport = match capture {
+            Some(capture) => {
+                capturing_transport = CapturingTransport::new(transport, capture);
+                &mut capturing_transport
+            }
+            None => transport,
+        };
+        let mut hooked_transport;
+        let transport: &mut dyn Transport = match packet_hooks {
+            Some(packet_hooks) => {
+                hooked_transport = HookedTransport::new(transport, packet_hooks);
+                &mut hooked_transport
+            }
+            None => transport,
+        };
+
+        self.batch_size = packet_batch_size.max(1);
+        transport.set_batching(self.batch_size, batched_io);
+        transport.set_ttl(ttl)?;
+        transport.set_fragmentation(fragment_size)?;
+
+        self.subnet_rate_limiter = subnet_rate_limit.map(SubnetRateLimiter::new);
+        self.pacer = pacing.map(pacer);
+        self.deferred_addrs.clear();
+        self.sources = SourcePool::new(source_ipv4_addrs, source_ipv6_addrs, source_routes, source_rate_limit);
+        self.decoys = decoys;
+        self.probe_profile = probe_profile;
+
+        let mut rtt_estimator = adaptive_timeout.map(|bounds| RttEstimator::new(bounds, port_timeout));
+
+        // with auto rate, the rate limit becomes the most the controller may go up to
+        let mut rate_controller = auto_rate.then(|| RateController::new(packets_per_second));
+        if let Some(rate_controller) = &rate_controller {
+            packets_per_second = Some(rate_controller.rate());
+        }
+        let mut limiter = packets_per_second.map(|rate| rate_limiter(&rate_limit_strategy, rate));
+
+        // shards of a seeded scan each get a seed of their own, so they don't all send the same sequence numbers
+        self.rng = seed.map_or_else(StdRng::from_entropy, |seed| StdRng::seed_from_u64(seed.wrapping_add(shard.index as u64)));
+        self.tcp_seq = self.rng.gen();
+
+        let mut requeued_addrs = VecDeque::with_capacity(1024 * 8);
 
         // results list
         let mut open_ports = Vec::new();
-        let mut expiry_list = Vec::with_capacity(1024 * 8);
+        // ordered soonest expiration first, since expirations aren't in send order with adaptive timeouts
+        let mut expiry_list = BinaryHeap::with_capacity(1024 * 8);
         let mut packet_retry_tracker =
             HashMap::<SocketAddr, u8, BuildHasherDefault<XxHash64>>::with_capacity_and_hasher(
                 1024 * 16,
                 Default::default(),
             );
+        // when each in-flight address was last sent a probe, so responses can be timed
         let mut inflight_addrs =
-            HashSet::<SocketAddr, BuildHasherDefault<XxHash64>>::with_capacity_and_hasher(
+            HashMap::<SocketAddr, Instant, BuildHasherDefault<XxHash64>>::with_capacity_and_hasher(
                 1024 * 16,
                 Default::default(),
             );
 
-        let mut source_ipv4_cycle = source_ipv4_addrs.iter().cycle();
-        let mut source_ipv6_cycle = source_ipv6_addrs.iter().cycle();
-
-        let mut current_packets_sent_for_bucket = 0usize;
         let mut next_packet_bucket_expiry = Instant::now()
             .checked_add(Duration::from_millis(RATE_LIMIT_RESOLUTION))
             .unwrap();
+        let mut next_deferred_release = next_packet_bucket_expiry;
 
         let mut total_processed_ports = 0u128;
         let mut total_packets_sent = 0u128;
+        let mut total_responses_received = 0u128;
+        let mut total_retries = 0u128;
+
+        // the position of every unsettled pair in the scan order, so the scan cursor can be worked out
+        let mut pair_positions =
+            HashMap::<SocketAddr, u128, BuildHasherDefault<XxHash64>>::with_capacity_and_hasher(
+                1024 * 16,
+                Default::default(),
+            );
+        let mut unsettled_pairs = BTreeSet::new();
+        let mut next_pair = 0u128;
+
+        while next_pair < skip_pairs && remote_addrs.next().is_some() {
+            next_pair += 1;
+        }
+
+        debug!(
+            shard = shard.index,
+            listening_port = self.listening_port,
+            rate_limit = ?packets_per_second,
+            retries = port_retries,
+            timeout = ?port_timeout,
+            "worker starting its share of the scan"
+        );
 
         'driver: loop {
             /*
-               1. Send up-to some limit of pending addrs a syn packet
-                   1. craft a tcp SYN packet
+               1. Send up-to some limit of pending addrs a probe packet
+                   1. craft a probe packet for the scan type (tcp segment or sctp init)
                    2. clone + swap remote with addr
                    3. send
                    4. add to inflight_addr
                    5. after batch, add batch to expiry_list
                2. Receive up-to some limit of packets
                    1. recv up-to some number of filtered (destination-bound) packets or when first terminated
-                   2. check if indicative of response (syn-ack)
+                   2. classify the response according to the scan type (e.g. syn-ack, rst or icmp unreachable)
                    3. convert to socket-addr
-                   4. remove from hashset and, if present, add to open_ports
+                   4. remove from hashset and, if present and reportable, add to open_ports
                3. process expired addrs
                    1. peek expiry_list
                    2. if instant older than 'now', pop set off of expiry_list
                    3. for each addr, remove from hashset
                    4. if present in hashset, add to pending, otherwise discard
+                   5. if out of retries, report the timeout state if the scan type cares about it
                4. if inflight is empty and pending is empty, return happily else loop
             */
 
-            while requeued_addrs.len() < BATCH_SEND_SIZE * 16 {
+            // nobody is left to take the results, which is how dropping a result stream ends its scan
+            if reporting_channel.is_closed() {
+                debug!("scan abandoned, its results are no longer being received");
+                return Ok(());
+            }
+
+            // once stopped, cancelled or past the deadline nothing more goes out, and the scan is over when the probes in flight
+            // are settled
+            let stopping = self.controls.is_stopped()
+                || deadline.is_some_and(|deadline| deadline.le(&Instant::now()))
+                || cancellation.as_ref().is_some_and(CancellationToken::is_cancelled);
+            if stopping {
+                requeued_addrs.clear();
+                self.deferred_addrs.clear();
+
+                if inflight_addrs.is_empty() {
+                    break 'driver;
+                }
+            }
+
+            while !stopping
+                && requeued_addrs.len() < BATCH_SEND_SIZE * 16
+                && self.deferred_addrs.len() < MAX_DEFERRED_ADDRS
+            {
                 match remote_addrs.next() {
-                    Some((addr, port)) => requeued_addrs.push(SocketAddr::new(addr, port)),
+                    Some((addr, port)) => {
+                        let remote_addr = SocketAddr::new(addr, port);
+                        let pair = next_pair;
+                        next_pair += 1;
+
+                        // the other shards of the scan probe the pairs that aren't ours
+                        if !shard.owns(pair) {
+                            continue;
+                        }
+
+                        pair_positions.insert(remote_addr, pair);
+                        unsettled_pairs.insert(pair);
+
+                        // new pairs queue up behind the retries, so they go out in scan order
+                        requeued_addrs.push_front(remote_addr);
+                    }
                     // if the iterator is empty AND we have no more addrs to process we can kill the whole driver loop
-                    None if inflight_addrs.is_empty() && requeued_addrs.is_empty() => break 'driver,
+                    None if inflight_addrs.is_empty() && requeued_addrs.is_empty() && self.deferred_addrs.is_empty() => {
+                        break 'driver
+                    }
                     None => break,
                 }
             }
 
-            // pick the next ipv4 and ipv6 addresses that we should send packets from
-            let source_ipv4_addr = source_ipv4_cycle.next();
-            let source_ipv6_addr = source_ipv6_cycle.next();
+            // probes held back by the subnet rate limit get another go once the buckets have had time to refill
+            if !self.deferred_addrs.is_empty() && next_deferred_release.le(&Instant::now()) {
+                requeued_addrs.extend(self.deferred_addrs.drain(..).rev());
+                next_deferred_release = Instant::now()
+                    .checked_add(Duration::from_millis(RATE_LIMIT_RESOLUTION))
+                    .unwrap();
+            }
+
+            // a rate limit set while the scan runs takes over from both its own and auto rate
+            let mut new_rate = None;
+            if let Some(rate_limit) = self.controls.rate_limit() {
+                new_rate = Some(shard.share(rate_limit).max(MIN_WORKER_RATE));
+                rate_controller = None;
+            }
+
+            if let Some(rate) = rate_controller.as_mut().and_then(RateController::adjust) {
+                new_rate = Some(rate);
+            }
+
+            if let Some(rate) = new_rate {
+                packets_per_second = Some(rate);
+                match &mut limiter {
+                    Some(limiter) => limiter.set_rate(rate),
+                    None => limiter = Some(rate_limiter(&rate_limit_strategy, rate)),
+                }
+            }
 
             // unless we send too many packets, we're clear to send
-            let mut rate_limit_unviolated = true;
+            let rate_limit_unviolated = limiter.as_mut().is_none_or(|limiter| limiter.try_acquire());
 
-            if let Some(packet_per_second_limit) = &packets_per_second {
-                if next_packet_bucket_expiry.le(&Instant::now()) {
-                    current_packets_sent_for_bucket = 0;
-                    next_packet_bucket_expiry = Instant::now()
-                        .checked_add(Duration::from_millis(RATE_LIMIT_RESOLUTION))
-                        .unwrap();
+            // the stats go out every tick whatever the rate limit
+            if next_packet_bucket_expiry.le(&Instant::now()) {
+                next_packet_bucket_expiry = Instant::now()
+                    .checked_add(Duration::from_millis(RATE_LIMIT_RESOLUTION))
+                    .unwrap();
 
-                    // might as well send a stats update
-                    reporting_channel.send(ArmadaWorkMessage::stats(
+                reporting_channel
+                    .send(shard.merge(ArmadaWorkMessage::stats(
                         total_processed_ports,
                         inflight_addrs.len() as u128,
-                        total_packets_sent
-                    )).context("Failed to send a stats update")?;
-                }
-
-                // if the number of packets we've sent so far is below the packet limit for our resolution, mark as "clear to send" otherwise don't
-                rate_limit_unviolated = (*packet_per_second_limit / (MS_IN_SECOND / RATE_LIMIT_RESOLUTION as usize)) > current_packets_sent_for_bucket;
-            } else {
-                // even though we don't need to enforce a rate limit, the bucket timeout presents a good opportunity to push a stats update
-
-                if next_packet_bucket_expiry.le(&Instant::now()) {
-                    next_packet_bucket_expiry = Instant::now()
-                        .checked_add(Duration::from_millis(RATE_LIMIT_RESOLUTION))
-                        .unwrap();
-
-                    // send our stats update
-                    reporting_channel
-                        .send(ArmadaWorkMessage::stats(
-                            total_processed_ports,
-                            inflight_addrs.len() as u128,
-                            total_packets_sent
-                        ))
-                        .context("Failed to send stats update over reporting channel.")?;
-                }
+                        total_packets_sent,
+                        total_responses_received,
+                        total_retries,
+                        scan_cursor(&unsettled_pairs, next_pair),
+                        packets_per_second,
+                        self.sources.stats(),
+                    )))
+                    .context("Failed to send stats update over reporting channel.")?;
             }
 
-            // if we're not pushing any rate limits, we should do some sending
-            if rate_limit_unviolated {
+            // if we're not pushing any rate limits or paused, we should do some sending
+            if rate_limit_unviolated && !self.controls.is_paused() {
                 // Send packets
                 let addresses_sent_packets = self.send_packets(
-                    ipv4_tcp_sender,
-                    ipv6_tcp_sender,
+                    transport,
                     &mut requeued_addrs,
-                    source_ipv4_addr,
-                    source_ipv6_addr,
-                    listening_port,
-                    tcp_seq,
+                    scan_type,
                 );
 
                 total_packets_sent += addresses_sent_packets.len() as u128;
+                self.sources.record_sent(&addresses_sent_packets);
+                if let Some(rate_controller) = &mut rate_controller {
+                    rate_controller.record_sent(addresses_sent_packets.len());
+                }
 
                 if !addresses_sent_packets.is_empty() {
-                    current_packets_sent_for_bucket += addresses_sent_packets.len();
+                    if let Some(limiter) = &mut limiter {
+                        limiter.record_sent(addresses_sent_packets.len());
+                    }
 
-                    inflight_addrs.extend(addresses_sent_packets.clone());
+                    let sent_at = Instant::now();
+                    inflight_addrs.extend(addresses_sent_packets.iter().map(|addr| (*addr, sent_at)));
 
                     // mark for expiration
-                    let expiration = Instant::now().checked_add(port_timeout.clone()).unwrap();
-                    expiry_list.push((expiration, addresses_sent_packets));
+                    match &rtt_estimator {
+                        Some(rtt_estimator) => {
+                            let mut addresses_by_timeout = BTreeMap::<Duration, Vec<SocketAddr>>::new();
+                            for addr in addresses_sent_packets {
+                                let retries = packet_retry_tracker.get(&addr).copied().unwrap_or(0);
+                                let timeout = rtt_estimator.timeout(addr.ip(), retries);
+
+                                addresses_by_timeout.entry(timeout).or_default().push(addr);
+                            }
+
+                            expiry_list.extend(
+                                addresses_by_timeout
+                                    .into_iter()
+                                    .map(|(timeout, addrs)| Reverse((sent_at.checked_add(timeout).unwrap(), addrs))),
+                            );
+                        }
+                        None => {
+                            let expiration = sent_at.checked_add(port_timeout).unwrap();
+                            expiry_list.push(Reverse((expiration, addresses_sent_packets)));
+                        }
+                    }
                 }
             } else {
-                std::thread::sleep(next_packet_bucket_expiry.duration_since(Instant::now()));
+                // paused scans still wake up for the stats updates
+                let ready_at = match (&limiter, rate_limit_unviolated) {
+                    (Some(limiter), false) => limiter.ready_at().min(next_packet_bucket_expiry),
+                    _ => next_packet_bucket_expiry,
+                };
+                std::thread::sleep(ready_at.duration_since(Instant::now()));
             }
 
-            // receive remotes that syn-ack'd
-            let received_remotes_v4 =
-                self.record_open_sockets_from_response(ipv4_tcp_receiver, listening_port);
-            let received_remotes_v6 =
-                self.record_open_sockets_from_response(ipv6_tcp_receiver, listening_port);
+            // every probe is waiting on the pacing, though stats updates still go out on time
+            if let Some(ready_at) = self.pacer.as_ref().and_then(|pacer| pacer.ready_at()) {
+                if !requeued_addrs.is_empty() {
+                    std::thread::sleep(ready_at.min(next_packet_bucket_expiry).duration_since(Instant::now()));
+                }
+            }
 
-            // save the remotes that were actually in-flight
+            // every probe left is waiting on the subnet rate limit, so there's nothing to do until it allows more
+            if requeued_addrs.is_empty() && !self.deferred_addrs.is_empty() {
+                std::thread::sleep(next_deferred_release.duration_since(Instant::now()));
+            }
+
+            // receive remotes that responded in a way that tells us something about the port
+            let (received_remotes_v4, received_remotes_v6) = if scan_type.is_sctp() {
+                (
+                    self.record_sctp_responses(transport, Channel::Ipv4Sctp, scan_type),
+                    self.record_sctp_responses(transport, Channel::Ipv6Sctp, scan_type),
+                )
+            } else {
+                (
+                    self.record_responses(transport, Channel::Ipv4Tcp, scan_type, collect_fingerprints),
+                    self.record_responses(transport, Channel::Ipv6Tcp, scan_type, collect_fingerprints),
+                )
+            };
+            let unreachable_remotes_v4 =
+                self.record_unreachables(transport, Channel::Ipv4Icmp, scan_type);
+            let unreachable_remotes_v6 =
+                self.record_unreachables(transport, Channel::Ipv6Icmp, scan_type);
+
+            // save the remotes that were actually in-flight, which is what keeps a port answering a probe and its retry
+            // from being reported twice
+            let mut repeated_results = Vec::new();
             received_remotes_v4
                 .into_iter()
                 .chain(received_remotes_v6)
-                .filter(|remote_addr| inflight_addrs.remove(&remote_addr))
-                .for_each(|remote_addr| {
-                    // if a port was deemed open, we can update this statistic
+                .chain(unreachable_remotes_v4)
+                .chain(unreachable_remotes_v6)
+                .filter_map(|result| match take_inflight(&mut inflight_addrs, result) {
+                    Ok(result) => Some(result),
+                    // raw results report the responses to pairs already settled too, without counting them again
+                    Err(result) if raw_results => {
+                        repeated_results.push(result);
+                        None
+                    }
+                    Err(result) => {
+                        trace!(remote = %result.addr, state = %result.state, "dropping a response to a pair that isn't in flight");
+                        None
+                    }
+                })
+                .for_each(|result| {
+                    // if a port's state was determined, we can update this statistic
                     total_processed_ports += 1;
-                    packet_retry_tracker.remove(&remote_addr);
-                    open_ports.push(remote_addr);
+                    total_responses_received += 1;
+                    let retries = packet_retry_tracker.remove(&result.addr);
+                    let retried = retries.is_some();
+                    let result = result.with_attempt(Some(retries.map_or(1, |retries| retries as u16 + 1)));
+                    if let Some(rate_controller) = &mut rate_controller {
+                        rate_controller.record_response(retried);
+                    }
+                    if let (Some(rtt_estimator), Some(rtt), false) = (&mut rtt_estimator, result.rtt, retried) {
+                        rtt_estimator.record(result.addr.ip(), rtt);
+                    }
+                    settle_pair(&mut pair_positions, &mut unsettled_pairs, &result.addr);
+                    self.sources.settle(&result.addr, true);
+
+                    if report_all_states || scan_type.reports(result.state) {
+                        open_ports.push(result);
+                    }
                 });
+            open_ports.extend(
+                repeated_results
+                    .into_iter()
+                    .filter(|result| report_all_states || scan_type.reports(result.state)),
+            );
 
             if !open_ports.is_empty() {
                 // send our stats update
-                reporting_channel.send(ArmadaWorkMessage::stats(
+                reporting_channel.send(shard.merge(ArmadaWorkMessage::stats(
                     total_processed_ports,
                     inflight_addrs.len() as u128,
-                    total_packets_sent
-                )).context("Failed to send stats message to reporting channel.")?;
+                    total_packets_sent,
+                    total_responses_received,
+                    total_retries,
+                    scan_cursor(&unsettled_pairs, next_pair),
+                    packets_per_second,
+                    self.sources.stats(),
+                ))).context("Failed to send stats message to reporting channel.")?;
                 // we'll empty the open ports vec into our update here
                 reporting_channel.send(
-                    ArmadaWorkMessage::results(open_ports.drain(.. open_ports.len()).collect())
+                    shard.merge(ArmadaWorkMessage::results(std::mem::take(&mut open_ports)))
                 ).context("Failed to send results message to reporting channel.")?;
             }
 
             self.process_expiration(&mut expiry_list)
                 .into_iter()
-                .filter(|expired_remote| inflight_addrs.remove(&expired_remote))
+                .filter(|expired_remote| inflight_addrs.remove(expired_remote).is_some())
                 .filter(|expired_remote| {
                     let retry_counter = packet_retry_tracker
-                        .entry(expired_remote.clone())
+                        .entry(*expired_remote)
                         .or_insert(0);
 
                     if *retry_counter == port_retries {
-                        // this port has been deemed closed and therefore has been "processed"
+                        trace!(remote = %expired_remote, attempts = port_retries as u16 + 1, "probe went unanswered after every retry");
+                        // this port never answered and therefore has been "processed"
                         total_processed_ports += 1;
-                        packet_retry_tracker.remove(&expired_remote);
+                        packet_retry_tracker.remove(expired_remote);
+                        settle_pair(&mut pair_positions, &mut unsettled_pairs, expired_remote);
+                        self.sources.settle(expired_remote, false);
+
+                        let timeout_state = scan_type.timeout_state();
+                        if report_all_states || scan_type.reports(timeout_state) {
+                            open_ports.push(PortResult::new(*expired_remote, timeout_state));
+                        }
+
+                        false
+                    } else if stopping {
+                        // there's no time left to retry it, so its state stays unknown
+                        packet_retry_tracker.remove(expired_remote);
+
                         false
                     } else {
                         *retry_counter += 1;
+                        total_retries += 1;
+                        trace!(remote = %expired_remote, attempt = *retry_counter as u16 + 1, "probe went unanswered, retransmitting");
                         true
                     }
                 })
                 .for_each(|expired_remote| {
-                    requeued_addrs.push(expired_remote);
+                    requeued_addrs.push_back(expired_remote);
                 });
         }
 
+        debug!(
+            shard = shard.index,
+            packets_sent = total_packets_sent,
+            responses = total_responses_received,
+            retries = total_retries,
+            settled = total_processed_ports,
+            "worker finished its share of the scan"
+        );
+
         // send the final stats and results before closing up shop
         reporting_channel
-            .send(ArmadaWorkMessage::stats(total_processed_ports, inflight_addrs.len() as u128, total_packets_sent))
+            .send(shard.merge(ArmadaWorkMessage::stats(
+                total_processed_ports,
+                inflight_addrs.len() as u128,
+                total_packets_sent,
+                total_responses_received,
+                total_retries,
+                scan_cursor(&unsettled_pairs, next_pair),
+                packets_per_second,
+                self.sources.stats(),
+            )))
             .context("Failed to send final stats message over reporting channel.")?;
 
         reporting_channel
-            .send(ArmadaWorkMessage::results(open_ports))
+            .send(shard.merge(ArmadaWorkMessage::results(open_ports)))
             .context("Failed to send final results message over reporting channel.")?;
 
         Ok(())
     }
 
-    /// Pulls socket addresses off the queued address list and sends them SYN TCP packets via IPv4 or IPv6
+    /// Pulls socket addresses off the queued address list and sends them TCP probes via IPv4 or IPv6
     fn send_packets(
-        &self,
-        ipv4_tcp_sender: &mut TransportSender,
-        ipv6_tcp_sender: &mut TransportSender,
-        requeued_addrs: &mut Vec<SocketAddr>,
-        source_ipv4: Option<&Ipv4Addr>,
-        source_ipv6: Option<&Ipv6Addr>,
-        listening_port: u16,
-        tcp_seq: &mut u32,
+        &mut self,
+        transport: &mut dyn Transport,
+        requeued_addrs: &mut VecDeque<SocketAddr>,
+        scan_type: ScanType,
     ) -> Vec<SocketAddr> {
-        use crate::armada::packet::{create_syn_tcp_packet_v4, create_syn_tcp_packet_v6};
-
-        let mut sent_addrs = Vec::with_capacity(BATCH_SEND_SIZE);
-        let mut syn_tcp_buffer = [0; 32];
-
-        for _ in 0 .. BATCH_SEND_SIZE {
-            let remote = match requeued_addrs.pop() {
+        use crate::armada::packet::{
+            create_probe_tcp_packet_v4,
+            create_probe_tcp_packet_v6,
+            create_sctp_init_packet,
+            ProbePacket,
+            TcpProbe,
+            MAX_TCP_PROBE_LENGTH,
+        };
+
+        let tcp_probe = TcpProbe {
+            flags: scan_type.probe_flags(),
+            profile: &self.probe_profile,
+        };
+        // fits both the largest TCP probe and an SCTP INIT
+        let mut probe_buffer = [0; MAX_TCP_PROBE_LENGTH];
+        // probes queued this batch that decoys still have to go out after, once they're sent
+        let mut trailing_decoys = Vec::new();
+
+        for _ in 0 .. self.batch_size {
+            let remote = match requeued_addrs.pop_back() {
                 Some(remote) => remote,
                 None => break,
             };
 
-            let remote_port = remote.port();
-
-            let (tcp_sender, packet) = match (&remote.ip(), source_ipv4, source_ipv6) {
-                (IpAddr::V4(remote_ipv4), Some(source_ipv4_addr), _) => {
-                    let packet = create_syn_tcp_packet_v4(
-                        source_ipv4_addr,
-                        remote_ipv4,
-                        listening_port,
-                        remote_port,
-                        &mut syn_tcp_buffer,
-                        tcp_seq,
-                    );
+            if let Some(pacer) = &mut self.pacer {
+                if !pacer.try_acquire(remote.ip()) {
+                    match pacer.defers() {
+                        true => self.deferred_addrs.push_back(remote),
+                        false => {
+                            requeued_addrs.push_back(remote);
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            }
 
-                    (&mut *ipv4_tcp_sender, packet)
+            if let Some(subnet_rate_limiter) = &mut self.subnet_rate_limiter {
+                if !subnet_rate_limiter.try_acquire(remote.ip()) {
+                    self.deferred_addrs.push_back(remote);
+                    continue;
                 }
-                (IpAddr::V6(remote_ipv6), _, Some(source_ipv6_addr)) => {
-                    let packet = create_syn_tcp_packet_v6(
-                        source_ipv6_addr,
-                        remote_ipv6,
-                        listening_port,
-                        remote_port,
-                        &mut syn_tcp_buffer,
-                        tcp_seq,
-                    );
+            }
+
+            let remote_port = remote.port();
 
-                    (&mut *ipv6_tcp_sender, packet)
+            let source_ip = match self.sources.pick(remote) {
+                SourcePick::Source(source_ip) => source_ip,
+                // another pair from a source with budget left goes out in the meantime
+                SourcePick::OverRateLimit => {
+                    self.deferred_addrs.push_back(remote);
+                    continue;
                 }
-                (IpAddr::V4(_), None, _) => {
+                SourcePick::Unavailable if remote.is_ipv4() => {
                     error!("Attempted to port scan an IPv4 address without any provided IPv4 source addresses. Port will be skipped.");
                     continue;
                 }
-                (IpAddr::V6(_), _, None) => {
+                SourcePick::Unavailable => {
                     error!("Attempted to port scan an IPv6 address without any provided IPv6 source addresses. Port will be skipped.");
                     continue;
                 }
             };
 
+            let packet = match (remote.ip(), source_ip) {
+                (IpAddr::V4(remote_ipv4), IpAddr::V4(source_ipv4_addr)) => {
+                    if scan_type.is_sctp() {
+                        create_sctp_init_packet(
+                            self.listening_port,
+                            remote_port,
+                            // the initiate tag must never be 0
+                            self.rng.gen::<u32>() | 1,
+                            &mut probe_buffer,
+                        ).map(ProbePacket::Raw)
+                    } else {
+                        create_probe_tcp_packet_v4(
+                            &source_ipv4_addr,
+                            &remote_ipv4,
+                            self.listening_port,
+                            remote_port,
+                            tcp_probe,
+                            &mut probe_buffer,
+                            &mut self.tcp_seq,
+                        ).map(ProbePacket::Tcp)
+                    }
+                }
+                (IpAddr::V6(remote_ipv6), IpAddr::V6(source_ipv6_addr)) => {
+                    if scan_type.is_sctp() {
+                        create_sctp_init_packet(
+                            self.listening_port,
+                            remote_port,
+                            // the initiate tag must never be 0
+                            self.rng.gen::<u32>() | 1,
+                            &mut probe_buffer,
+                        ).map(ProbePacket::Raw)
+                    } else {
+                        create_probe_tcp_packet_v6(
+                            &source_ipv6_addr,
+                            &remote_ipv6,
+                            self.listening_port,
+                            remote_port,
+                            tcp_probe,
+                            &mut probe_buffer,
+                            &mut self.tcp_seq,
+                        ).map(ProbePacket::Tcp)
+                    }
+                }
+                // routes only ever give a source of the target's own family
+                _ => continue,
+            };
+
             let packet = match packet {
                 Some(packet) => packet,
                 None => {
                     warn!(
-                        "Unable to create SYN packet for {}. Port will be skipped.",
+                        "Unable to create probe packet for {}. Port will be skipped.",
                         remote
                     );
                     continue;
                 }
             };
 
-            match tcp_sender.try_send_to(packet, remote.ip()) {
-                Ok(Some(_)) => sent_addrs.push(remote),
-                _ => {
-                    //eprintln!("ERR: {:?}", e);
-                    requeued_addrs.push(remote);
-                    break;
+            let channel = Channel::for_probe(scan_type, remote.ip());
+
+            if let Some(decoys) = &self.decoys {
+                send_decoys(transport, &decoys.addrs[..decoys.real_position], channel, remote, packet.packet());
+            }
+
+            if !transport.queue(channel, source_ip, remote, packet.packet()) {
+                requeued_addrs.push_back(remote);
+                break;
+            }
+
+            if self.decoys.as_ref().is_some_and(|decoys| decoys.real_position < decoys.addrs.len()) {
+                trailing_decoys.push((remote, channel, packet.packet().to_vec()));
+            }
+        }
+
+        let (sent_addrs, unsent_addrs) = transport.flush();
+        requeued_addrs.extend(unsent_addrs);
+
+        if let Some(decoys) = &self.decoys {
+            for (remote, channel, packet) in trailing_decoys {
+                if sent_addrs.contains(&remote) {
+                    send_decoys(transport, &decoys.addrs[decoys.real_position..], channel, remote, &packet);
                 }
             }
+
+            // what the transport holds back until a flush is only ever decoys by now
+            transport.flush();
         }
 
         sent_addrs
     }
 
-    /// Receives some number of responses from the socket and determines which sockets indicate an open status
-    fn record_open_sockets_from_response(
+    /// Receives some number of responses from the socket and classifies the state of the ports they came from
+    fn record_responses(
         &self,
-        tcp_receiver: &mut TransportReceiver,
-        listening_port: u16,
-    ) -> Vec<SocketAddr> {
-        use pnet::packet::tcp::TcpFlags::{NS, CWR, ECE, URG, ACK, RST, SYN, PSH, FIN};
-
-        let mut results = Vec::with_capacity(BATCH_RECV_SIZE);
-
-        while let Ok(Some((packet, remote))) = tcp_receiver.try_next() {
-            let flag = packet.get_flags();
-
-            let seq = packet.get_sequence();
-            let ns_flag = flag & NS != 0;
-            let cwr_flag = flag & CWR != 0;
-            let ece_flag = flag & ECE != 0;
-            let urg_flag = flag & URG != 0;
-            let ack_flag = flag & ACK != 0;
-            let psh_flag = flag & PSH != 0;
-            let rst_flag = flag & RST != 0;
-            let syn_flag = flag & SYN != 0;
-            let fin_flag = flag & FIN != 0;
-
-            if remote == IpAddr::from([103,97,3,19]) {
-                println!("{:#?}:", packet.get_source());
-                println!("\tRemote: {remote:#?}");
-                println!("\tSEQ: {seq}");
-                println!("\tNS: {ns_flag}");
-                println!("\tCWR: {cwr_flag}");
-                println!("\tECE: {ece_flag}");
-                println!("\tURG: {urg_flag}");
-                println!("\tACK: {ack_flag}");
-                println!("\tPSH: {psh_flag}");
-                println!("\tRST: {rst_flag}");
-                println!("\tSYN: {syn_flag}");
-                println!("\tFIN: {fin_flag}");
+        transport: &mut dyn Transport,
+        channel: Channel,
+        scan_type: ScanType,
+        collect_fingerprints: bool,
+    ) -> Vec<PortResult> {
+        let mut results = Vec::with_capacity(self.batch_size);
+
+        while let Ok(Some((packet, remote, ttl, destination))) = transport.try_next(channel) {
+            let response = TcpResponse::from(&packet);
+
+            if response.destination_port != self.listening_port {
+                trace!(remote = %SocketAddr::new(remote, response.source_port), port = response.destination_port, "dropping a TCP packet that isn't for the listening port");
+                continue;
             }
 
-            // todo: if reset packet back, return "definitely closed"
+            // answers to a probe from one of our other sources aren't about this pair's port
+            if !self.sources.expects(SocketAddr::new(remote, response.source_port), destination) {
+                trace!(remote = %SocketAddr::new(remote, response.source_port), destination = ?destination, "dropping a TCP packet sent to a source the pair wasn't probed from");
+                continue;
+            }
+
+            if let Some(state) = scan_type.classify_response(&response) {
+                // only SYN-ACKs carry the traits stacks differ in
+                let fingerprint = (collect_fingerprints && response.flags & (SYN | ACK) == SYN | ACK)
+                    .then(|| SynAckFingerprint::from_packet(&packet, ttl));
 
-            if packet.get_destination() == listening_port && !rst_flag && ack_flag {
-                results.push(SocketAddr::new(remote, packet.get_source()));
+                results.push(
+                    PortResult::new(SocketAddr::new(remote, response.source_port), state).with_fingerprint(fingerprint),
+                );
 
                 // if we've reached the capacity for this vec, we've processed enough and can return
                 if results.len() == results.capacity() {
@@ -415,10 +773,65 @@ impl ArmadaWorker {
         results
     }
 
+    /// Receives some number of SCTP responses from the socket and classifies the state of the ports they came from
+    fn record_sctp_responses(&self, transport: &mut dyn Transport, channel: Channel, scan_type: ScanType) -> Vec<PortResult> {
+        let mut results = Vec::with_capacity(self.batch_size);
+
+        while let Ok(Some((response, remote, destination))) = transport.try_next_sctp(channel) {
+            if response.destination_port != self.listening_port {
+                trace!(remote = %SocketAddr::new(remote, response.source_port), port = response.destination_port, "dropping an SCTP packet that isn't for the listening port");
+                continue;
+            }
+
+            if !self.sources.expects(SocketAddr::new(remote, response.source_port), destination) {
+                trace!(remote = %SocketAddr::new(remote, response.source_port), destination = ?destination, "dropping an SCTP packet sent to a source the pair wasn't probed from");
+                continue;
+            }
+
+            if let Some(state) = scan_type.classify_sctp_response(&response) {
+                results.push(PortResult::new(SocketAddr::new(remote, response.source_port), state));
+
+                if results.len() == results.capacity() {
+                    break;
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Receives some number of ICMP destination unreachable messages caused by our probes
+    fn record_unreachables(&self, transport: &mut dyn Transport, channel: Channel, scan_type: ScanType) -> Vec<PortResult> {
+        let mut results = Vec::with_capacity(self.batch_size);
+
+        while let Ok(Some((source_port, remote, source))) = transport.try_next_unreachable(channel) {
+            if source_port != self.listening_port {
+                trace!(remote = %remote, port = source_port, "dropping an ICMP error about a packet from another port");
+                continue;
+            }
+
+            // the quoted probe says which source it went out from, which has to be the one the pair was probed from
+            if !self.sources.expects(remote, Some(source)) {
+                trace!(remote = %remote, source = %source, "dropping an ICMP error about a probe from a source the pair wasn't probed from");
+                continue;
+            }
+
+            debug!(remote = %remote, "ICMP destination unreachable received");
+
+            results.push(PortResult::new(remote, scan_type.unreachable_state()));
+
+            if results.len() == results.capacity() {
+                break;
+            }
+        }
+
+        results
+    }
+
     /// Process all currently expired packets
     fn process_expiration(
         &self,
-        expiry_list: &mut Vec<(Instant, Vec<SocketAddr>)>,
+        expiry_list: &mut BinaryHeap<Reverse<(Instant, Vec<SocketAddr>)>>,
     ) -> Vec<SocketAddr> {
         // assume send size for efficient writing
         let mut all_expired_remotes = Vec::with_capacity(BATCH_SEND_SIZE);
@@ -426,9 +839,9 @@ impl ArmadaWorker {
         let now = Instant::now();
 
         loop {
-            match expiry_list.first() {
-                Some((expiry, _)) if expiry.le(&now) => {
-                    let (_, expired_remotes) = expiry_list.pop().expect("This should not be possible as we just confirmed an item exists.");
+            match expiry_list.peek() {
+                Some(Reverse((expiry, _))) if expiry.le(&now) => {
+                    let Reverse((_, expired_remotes)) = expiry_list.pop().expect("This should not be possible as we just confirmed an item exists.");
 
                     all_expired_remotes.extend(expired_remotes);
                 }
@@ -438,4 +851,91 @@ impl ArmadaWorker {
 
         all_expired_remotes
     }
-}
\ No newline at end of file
+}
+
+fn settle_pair(
+    pair_positions: &mut HashMap<SocketAddr, u128, BuildHasherDefault<XxHash64>>,
+    unsettled_pairs: &mut BTreeSet<u128>,
+    remote_addr: &SocketAddr,
+) {
+    if let Some(position) = pair_positions.remove(remote_addr) {
+        unsettled_pairs.remove(&position);
+    }
+}
+
+/// Takes the pair a response is for out of flight, timing it from when its last probe was sent. A response to a pair
+/// that isn't in flight, because an earlier one already settled it, is handed back as the error.
+fn take_inflight(
+    inflight_addrs: &mut HashMap<SocketAddr, Instant, BuildHasherDefault<XxHash64>>,
+    result: PortResult,
+) -> Result<PortResult, PortResult> {
+    match inflight_addrs.remove(&result.addr) {
+        Some(sent_at) => Ok(result.with_rtt(Some(sent_at.elapsed()))),
+        None => Err(result),
+    }
+}
+
+/// Every pair before the earliest unsettled one is settled
+fn scan_cursor(unsettled_pairs: &BTreeSet<u128>, next_pair: u128) -> u128 {
+    unsettled_pairs.first().copied().unwrap_or(next_pair)
+}
+
+/// Sends copies of the probe to the remote from each of the decoys of its address family, with TCP probes getting the
+/// checksum for the decoy's address. Decoys that can't go out are simply skipped.
+fn send_decoys(transport: &mut dyn Transport, decoys: &[IpAddr], channel: Channel, remote: SocketAddr, probe: &[u8]) {
+    use crate::armada::packet::{readdress_tcp_packet, MAX_TCP_PROBE_LENGTH};
+
+    let mut decoy_buffer = [0; MAX_TCP_PROBE_LENGTH];
+    let decoy_probe = &mut decoy_buffer[..probe.len()];
+
+    for decoy in decoys.iter().filter(|decoy| decoy.is_ipv4() == remote.is_ipv4()) {
+        decoy_probe.copy_from_slice(probe);
+
+        if matches!(channel, Channel::Ipv4Tcp | Channel::Ipv6Tcp) && readdress_tcp_packet(decoy_probe, *decoy, remote.ip()).is_none() {
+            continue;
+        }
+
+        transport.send_decoy(channel, *decoy, remote, decoy_probe);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::armada::result::PortState;
+
+    #[test]
+    fn the_scan_cursor_stops_at_the_earliest_unsettled_pair() {
+        let addrs: Vec<SocketAddr> = (1 ..= 4).map(|port| SocketAddr::new(IpAddr::from([127, 0, 0, 1]), port)).collect();
+        let mut pair_positions = HashMap::<SocketAddr, u128, BuildHasherDefault<XxHash64>>::default();
+        let mut unsettled_pairs = BTreeSet::new();
+        for (position, addr) in addrs.iter().enumerate() {
+            pair_positions.insert(*addr, position as u128);
+            unsettled_pairs.insert(position as u128);
+        }
+
+        settle_pair(&mut pair_positions, &mut unsettled_pairs, &addrs[1]);
+        assert_eq!(scan_cursor(&unsettled_pairs, 4), 0);
+
+        settle_pair(&mut pair_positions, &mut unsettled_pairs, &addrs[0]);
+        assert_eq!(scan_cursor(&unsettled_pairs, 4), 2);
+
+        settle_pair(&mut pair_positions, &mut unsettled_pairs, &addrs[3]);
+        settle_pair(&mut pair_positions, &mut unsettled_pairs, &addrs[2]);
+        assert_eq!(scan_cursor(&unsettled_pairs, 4), 4);
+    }
+
+    #[test]
+    fn only_the_first_response_to_a_pair_in_flight_settles_it() {
+        let addr = SocketAddr::new(IpAddr::from([127, 0, 0, 1]), 22);
+        let mut inflight_addrs = HashMap::<SocketAddr, Instant, BuildHasherDefault<XxHash64>>::default();
+        inflight_addrs.insert(addr, Instant::now());
+
+        let first = take_inflight(&mut inflight_addrs, PortResult::new(addr, PortState::Open)).unwrap();
+        let repeated = take_inflight(&mut inflight_addrs, PortResult::new(addr, PortState::Open)).unwrap_err();
+
+        assert!(first.rtt.is_some());
+        assert_eq!((repeated.addr, repeated.rtt), (addr, None));
+        assert!(inflight_addrs.is_empty());
+    }
+}
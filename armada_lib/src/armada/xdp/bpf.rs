@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use anyhow::{bail, Context};
+
+const BPF_MAP_CREATE: libc::c_int = 0;
+const BPF_MAP_UPDATE_ELEM: libc::c_int = 2;
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_LINK_CREATE: libc::c_int = 28;
+
+const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const BPF_XDP: u32 = 37;
+
+const XDP_PASS: i32 = 2;
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+
+/// Where the fields we read sit in `struct xdp_md`
+const XDP_MD_DATA: i16 = 0;
+const XDP_MD_DATA_END: i16 = 4;
+const XDP_MD_RX_QUEUE_INDEX: i16 = 16;
+
+const ETHERNET_HEADER_LENGTH: i32 = 14;
+const VLAN_TAG_LENGTH: i32 = 4;
+const IPV4_MIN_HEADER_LENGTH: i32 = 20;
+const IPV6_HEADER_LENGTH: i32 = 40;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86dd;
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+/// The part of an 802.1Q tag holding the VLAN ID, the rest being its priority
+const VLAN_ID_MASK: u16 = 0x0fff;
+const PROTOCOL_ICMP: i32 = 1;
+const PROTOCOL_TCP: i32 = 6;
+const PROTOCOL_ICMPV6: i32 = 58;
+const PROTOCOL_SCTP: i32 = 132;
+const ICMP_DESTINATION_UNREACHABLE: i32 = 3;
+const ICMPV6_DESTINATION_UNREACHABLE: i32 = 1;
+
+/// Plenty for the verifier to spell out why a program as short as ours was rejected
+const VERIFIER_LOG_LENGTH: usize = 64 * 1024;
+
+/// The XDP program steering responses to our probes into the AF_XDP sockets, attached to an interface for as long as
+/// this is held. Everything else carries on up the kernel's network stack as usual.
+pub(crate) struct XdpProgram {
+    _map: OwnedFd,
+    _program: OwnedFd,
+    /// Closing the link detaches the program
+    _link: OwnedFd,
+}
+
+/// An `XSKMAP` the program redirects packets through, indexed by the receive queue they arrived on
+pub(crate) struct SocketMap {
+    fd: OwnedFd,
+}
+
+impl SocketMap {
+    pub(crate) fn create(queue_count: u32) -> anyhow::Result<Self> {
+        let attr = MapCreateAttr {
+            map_type: BPF_MAP_TYPE_XSKMAP,
+            key_size: mem::size_of::<u32>() as u32,
+            value_size: mem::size_of::<u32>() as u32,
+            max_entries: queue_count,
+            map_flags: 0,
+        };
+
+        let fd = bpf_fd(BPF_MAP_CREATE, &attr).context("Unable to create the XDP socket map")?;
+
+        Ok(Self { fd })
+    }
+
+    /// Points the receive queue at the socket
+    pub(crate) fn insert(&self, queue_id: u32, socket_fd: RawFd) -> anyhow::Result<()> {
+        let value = socket_fd as u32;
+        let attr = MapUpdateElemAttr {
+            map_fd: self.fd.as_raw_fd() as u32,
+            _padding: 0,
+            key: &queue_id as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            flags: 0,
+        };
+
+        bpf(BPF_MAP_UPDATE_ELEM, &attr)
+            .with_context(|| format!("Unable to add the XDP socket for queue {} to the socket map", queue_id))?;
+
+        Ok(())
+    }
+}
+
+impl XdpProgram {
+    /// Loads the program and attaches it to the interface, in native mode if its driver supports XDP and generic mode
+    /// otherwise. Packets of the protocols we listen for that are headed to the listening port, along with ICMP
+    /// destination unreachables, are sent to the socket in the map for the queue they came in on. With a VLAN, only
+    /// packets in frames tagged with it are.
+    pub(crate) fn attach(
+        interface_index: u32,
+        listening_port: u16,
+        vlan: Option<u16>,
+        map: SocketMap,
+    ) -> anyhow::Result<Self> {
+        let instructions = redirect_program(map.fd.as_raw_fd(), listening_port, vlan);
+        let program = load_program(&instructions)?;
+
+        let attr = LinkCreateAttr {
+            prog_fd: program.as_raw_fd() as u32,
+            target_ifindex: interface_index,
+            attach_type: BPF_XDP,
+            flags: 0,
+        };
+
+        let link = bpf_fd(BPF_LINK_CREATE, &attr)
+            .context("Unable to attach the XDP program. Is another XDP program already attached to the interface?")?;
+
+        Ok(Self { _map: map.fd, _program: program, _link: link })
+    }
+}
+
+fn load_program(instructions: &[Instruction]) -> anyhow::Result<OwnedFd> {
+    let license = b"Dual BSD/GPL\0";
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_XDP,
+        insn_cnt: instructions.len() as u32,
+        insns: instructions.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+        prog_name: *b"armada_redirect\0",
+        prog_ifindex: 0,
+        expected_attach_type: BPF_XDP,
+    };
+
+    if let Ok(program) = bpf_fd(BPF_PROG_LOAD, &attr) {
+        return Ok(program);
+    }
+
+    // load it again, this time asking the verifier to explain itself
+    let mut log = vec![0u8; VERIFIER_LOG_LENGTH];
+    attr.log_level = 1;
+    attr.log_size = log.len() as u32;
+    attr.log_buf = log.as_mut_ptr() as u64;
+
+    match bpf_fd(BPF_PROG_LOAD, &attr) {
+        Ok(program) => Ok(program),
+        Err(e) => {
+            let log_length = log.iter().position(|byte| *byte == 0).unwrap_or(log.len());
+            let log = String::from_utf8_lossy(&log[..log_length]);
+
+            bail!("Unable to load the XDP program: {}\n{}", e, log.trim_end())
+        }
+    }
+}
+
+fn bpf<T>(command: libc::c_int, attr: &T) -> io::Result<libc::c_long> {
+    let result = unsafe { libc::syscall(libc::SYS_bpf, command, attr as *const T, mem::size_of::<T>() as u32) };
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(result)
+}
+
+/// Runs a command that hands out a new file descriptor
+fn bpf_fd<T>(command: libc::c_int, attr: &T) -> io::Result<OwnedFd> {
+    let fd = bpf(command, attr)?;
+
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+#[repr(C)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct MapUpdateElemAttr {
+    map_fd: u32,
+    _padding: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+#[repr(C)]
+struct LinkCreateAttr {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+/// A single eBPF instruction, as laid out in `struct bpf_insn`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Instruction {
+    code: u8,
+    /// The destination register in the low nibble and the source register in the high one
+    registers: u8,
+    offset: i16,
+    immediate: i32,
+}
+
+const LDX_W: u8 = 0x61;
+const LDX_H: u8 = 0x69;
+const LDX_B: u8 = 0x71;
+const LD_IMM64: u8 = 0x18;
+const MOV_K: u8 = 0xb7;
+const MOV_X: u8 = 0xbf;
+const ADD_K: u8 = 0x07;
+const ADD_X: u8 = 0x0f;
+const AND_K: u8 = 0x57;
+const LSH_K: u8 = 0x67;
+const JA: u8 = 0x05;
+const JEQ_K: u8 = 0x15;
+const JNE_K: u8 = 0x55;
+const JGT_X: u8 = 0x2d;
+const CALL: u8 = 0x85;
+const EXIT: u8 = 0x95;
+
+/// Marks the immediate of a 64 bit load as a map file descriptor, for the kernel to swap for the map itself
+const PSEUDO_MAP_FD: u8 = 1;
+
+const R0: u8 = 0;
+const R1: u8 = 1;
+const R2: u8 = 2;
+const R3: u8 = 3;
+const R4: u8 = 4;
+const R5: u8 = 5;
+const R6: u8 = 6;
+const R7: u8 = 7;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Label {
+    Ipv4,
+    Ipv6,
+    Icmp,
+    Icmpv6,
+    Ports,
+    Redirect,
+    Pass,
+}
+
+/// Just enough of an assembler to write the program with jumps to labels rather than counted offsets
+#[derive(Default)]
+struct Assembler {
+    instructions: Vec<Instruction>,
+    labels: HashMap<Label, usize>,
+    jumps: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    fn emit(&mut self, code: u8, destination: u8, source: u8, offset: i16, immediate: i32) {
+        self.instructions.push(Instruction { code, registers: destination | source << 4, offset, immediate });
+    }
+
+    fn jump(&mut self, code: u8, destination: u8, source: u8, immediate: i32, label: Label) {
+        self.jumps.push((self.instructions.len(), label));
+        self.emit(code, destination, source, 0, immediate);
+    }
+
+    fn label(&mut self, label: Label) {
+        self.labels.insert(label, self.instructions.len());
+    }
+
+    /// Jumps to `Pass` unless there are at least this many bytes of packet from the address in r2 on. The verifier
+    /// won't let the program read a byte of the packet it hasn't checked this way.
+    fn check_length(&mut self, length: i32) {
+        self.emit(MOV_X, R4, R2, 0, 0);
+        self.emit(ADD_K, R4, 0, 0, length);
+        self.jump(JGT_X, R4, R3, 0, Label::Pass);
+    }
+
+    fn finish(mut self) -> Vec<Instruction> {
+        for (at, label) in self.jumps {
+            // jumps are relative to the instruction after them
+            self.instructions[at].offset = (self.labels[&label] as isize - at as isize - 1) as i16;
+        }
+
+        self.instructions
+    }
+}
+
+/// Compares equal to the big endian value once loaded from the packet, whatever our own byte order
+fn network_u16(value: u16) -> i32 {
+    u16::from_ne_bytes(value.to_be_bytes()) as i32
+}
+
+/// Puts together the program `XdpProgram::attach` describes. r2 walks the packet, r3 holds its end and r6 the context.
+fn redirect_program(map_fd: RawFd, listening_port: u16, vlan: Option<u16>) -> Vec<Instruction> {
+    let mut program = Assembler::default();
+
+    program.emit(MOV_X, R6, R1, 0, 0);
+    program.emit(LDX_W, R2, R1, XDP_MD_DATA, 0);
+    program.emit(LDX_W, R3, R1, XDP_MD_DATA_END, 0);
+
+    program.check_length(ETHERNET_HEADER_LENGTH);
+    program.emit(LDX_H, R5, R2, 12, 0);
+
+    // on a VLAN, the EtherType we're after follows the tag
+    let link_length = match vlan {
+        Some(vlan) => {
+            program.jump(JNE_K, R5, 0, network_u16(ETHER_TYPE_VLAN), Label::Pass);
+            program.check_length(ETHERNET_HEADER_LENGTH + VLAN_TAG_LENGTH);
+            program.emit(LDX_H, R5, R2, 14, 0);
+            program.emit(AND_K, R5, 0, 0, network_u16(VLAN_ID_MASK));
+            program.jump(JNE_K, R5, 0, network_u16(vlan), Label::Pass);
+            program.emit(LDX_H, R5, R2, 16, 0);
+
+            ETHERNET_HEADER_LENGTH + VLAN_TAG_LENGTH
+        }
+        None => ETHERNET_HEADER_LENGTH,
+    };
+
+    program.jump(JEQ_K, R5, 0, network_u16(ETHER_TYPE_IPV4), Label::Ipv4);
+    program.jump(JEQ_K, R5, 0, network_u16(ETHER_TYPE_IPV6), Label::Ipv6);
+    program.jump(JA, 0, 0, 0, Label::Pass);
+
+    // IPv4 headers come in any length from 20 to 60 bytes
+    program.label(Label::Ipv4);
+    program.check_length(link_length + IPV4_MIN_HEADER_LENGTH);
+    program.emit(LDX_B, R5, R2, link_length as i16 + 9, 0);
+    program.emit(LDX_B, R7, R2, link_length as i16, 0);
+    program.emit(AND_K, R7, 0, 0, 0x0f);
+    program.emit(LSH_K, R7, 0, 0, 2);
+    program.emit(ADD_K, R2, 0, 0, link_length);
+    program.emit(ADD_X, R2, R7, 0, 0);
+    program.jump(JEQ_K, R5, 0, PROTOCOL_TCP, Label::Ports);
+    program.jump(JEQ_K, R5, 0, PROTOCOL_SCTP, Label::Ports);
+    program.jump(JEQ_K, R5, 0, PROTOCOL_ICMP, Label::Icmp);
+    program.jump(JA, 0, 0, 0, Label::Pass);
+
+    // probes never carry extension headers, so neither do the responses we're after
+    program.label(Label::Ipv6);
+    program.check_length(link_length + IPV6_HEADER_LENGTH);
+    program.emit(LDX_B, R5, R2, link_length as i16 + 6, 0);
+    program.emit(ADD_K, R2, 0, 0, link_length + IPV6_HEADER_LENGTH);
+    program.jump(JEQ_K, R5, 0, PROTOCOL_TCP, Label::Ports);
+    program.jump(JEQ_K, R5, 0, PROTOCOL_SCTP, Label::Ports);
+    program.jump(JEQ_K, R5, 0, PROTOCOL_ICMPV6, Label::Icmpv6);
+    program.jump(JA, 0, 0, 0, Label::Pass);
+
+    program.label(Label::Icmp);
+    program.check_length(1);
+    program.emit(LDX_B, R5, R2, 0, 0);
+    program.jump(JEQ_K, R5, 0, ICMP_DESTINATION_UNREACHABLE, Label::Redirect);
+    program.jump(JA, 0, 0, 0, Label::Pass);
+
+    program.label(Label::Icmpv6);
+    program.check_length(1);
+    program.emit(LDX_B, R5, R2, 0, 0);
+    program.jump(JEQ_K, R5, 0, ICMPV6_DESTINATION_UNREACHABLE, Label::Redirect);
+    program.jump(JA, 0, 0, 0, Label::Pass);
+
+    // TCP and SCTP both lead with the source and destination ports
+    program.label(Label::Ports);
+    program.check_length(4);
+    program.emit(LDX_H, R5, R2, 2, 0);
+    program.jump(JNE_K, R5, 0, network_u16(listening_port), Label::Pass);
+
+    // packets arriving on a queue without a socket carry on as if nothing happened
+    program.label(Label::Redirect);
+    program.emit(LDX_W, R2, R6, XDP_MD_RX_QUEUE_INDEX, 0);
+    program.emit(LD_IMM64, R1, PSEUDO_MAP_FD, 0, map_fd);
+    program.emit(0, 0, 0, 0, 0);
+    program.emit(MOV_K, R3, 0, 0, XDP_PASS);
+    program.emit(CALL, 0, 0, 0, BPF_FUNC_REDIRECT_MAP);
+    program.emit(EXIT, 0, 0, 0, 0);
+
+    program.label(Label::Pass);
+    program.emit(MOV_K, R0, 0, 0, XDP_PASS);
+    program.emit(EXIT, 0, 0, 0, 0);
+
+    program.finish()
+}
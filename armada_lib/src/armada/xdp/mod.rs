@@ -0,0 +1,170 @@
+mod bpf;
+mod socket;
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::{fs, io, mem};
+
+use anyhow::Context;
+use pnet::datalink;
+use pnet::util::MacAddr;
+
+use crate::armada::frame::{parse_frame, Framer};
+use crate::armada::transport::{Channel, Transport};
+use crate::armada::xdp::bpf::{SocketMap, XdpProgram};
+use crate::armada::xdp::socket::XdpSocket;
+
+/// The most responses held per channel before they're read. The rest are dropped, as a full socket buffer would.
+const MAX_QUEUED_RESPONSES: usize = 4096;
+
+/// Sends probes and receives responses through AF_XDP sockets, skipping the kernel's network stack both ways. Probes
+/// are framed up to the Ethernet header here, and responses are picked out of the interface's traffic by an XDP
+/// program before the kernel ever sees them.
+pub(crate) struct XdpTransport {
+    /// One per receive queue of the interface, the first of which probes go out on
+    sockets: Vec<XdpSocket>,
+    /// Kept attached for as long as the sockets are open
+    _program: XdpProgram,
+    framer: Framer,
+    /// Probes put in a frame since the last flush
+    queued: Vec<SocketAddr>,
+    /// Responses taken off the sockets that haven't been read yet, per channel
+    received: [VecDeque<(Vec<u8>, IpAddr)>; Channel::ALL.len()],
+    /// The response being read
+    current: Vec<u8>,
+}
+
+impl XdpTransport {
+    pub(crate) fn open(interface_name: &str, vlan: Option<u16>, listening_port: u16) -> anyhow::Result<Self> {
+        let interface = datalink::interfaces()
+            .into_iter()
+            .find(|interface| interface.name == interface_name)
+            .with_context(|| format!("There is no interface named {}.", interface_name))?;
+        let source_mac = interface
+            .mac
+            .with_context(|| format!("Interface {} has no link layer address to send from.", interface_name))?;
+
+        let queue_count = count_receive_queues(interface_name);
+        let socket_map = SocketMap::create(queue_count)?;
+
+        let sockets = (0..queue_count)
+            .map(|queue_id| XdpSocket::open(interface.index, queue_id))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for (queue_id, socket) in sockets.iter().enumerate() {
+            socket_map.insert(queue_id as u32, socket.fd())?;
+        }
+
+        let program = XdpProgram::attach(interface.index, listening_port, vlan, socket_map)?;
+
+        Ok(Self {
+            sockets,
+            _program: program,
+            framer: Framer::new(interface_name, source_mac, vlan),
+            queued: Vec::new(),
+            received: Default::default(),
+            current: Vec::new(),
+        })
+    }
+
+    /// Puts the packet in frames on the first socket's transmit ring. Returns false when the ring turned the packet
+    /// away.
+    fn write_frames(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        let socket = &mut self.sockets[0];
+
+        self.framer.write_frames(channel, source, remote, packet, |frame| socket.queue(frame))
+    }
+
+    /// Sends frames to targets behind a gateway, or that there's no route to, to this address rather than the
+    /// gateway's in the neighbor table
+    pub(crate) fn with_router_mac(mut self, router_mac: Option<MacAddr>) -> Self {
+        self.framer = self.framer.with_router_mac(router_mac);
+        self
+    }
+
+    /// Sends frames to targets on the link to this address rather than their own in the neighbor table
+    pub(crate) fn with_target_mac(mut self, target_mac: Option<MacAddr>) -> Self {
+        self.framer = self.framer.with_target_mac(target_mac);
+        self
+    }
+
+    /// Takes every frame that came in off of the sockets, sorting the responses in them by channel
+    fn receive(&mut self) {
+        let received = &mut self.received;
+        let vlan = self.framer.vlan();
+
+        for socket in &mut self.sockets {
+            socket.receive(|frame| {
+                if let Some((channel, datagram, remote)) = parse_frame(frame, vlan) {
+                    let responses = &mut received[channel.index()];
+
+                    if responses.len() < MAX_QUEUED_RESPONSES {
+                        responses.push_back((datagram.to_vec(), remote));
+                    }
+                }
+            });
+        }
+    }
+}
+
+impl Transport for XdpTransport {
+    fn set_ttl(&mut self, ttl: Option<u8>) -> anyhow::Result<()> {
+        self.framer.set_ttl(ttl);
+
+        Ok(())
+    }
+
+    fn set_fragmentation(&mut self, fragment_size: Option<usize>) -> anyhow::Result<()> {
+        self.framer.set_fragmentation(fragment_size);
+
+        Ok(())
+    }
+
+    fn queue(&mut self, channel: Channel, source: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        if !self.write_frames(channel, source, remote, packet) {
+            return false;
+        }
+
+        self.queued.push(remote);
+
+        true
+    }
+
+    fn send_decoy(&mut self, channel: Channel, decoy: IpAddr, remote: SocketAddr, packet: &[u8]) -> bool {
+        self.write_frames(channel, decoy, remote, packet)
+    }
+
+    fn flush(&mut self) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+        self.sockets[0].transmit();
+
+        (mem::take(&mut self.queued), Vec::new())
+    }
+
+    fn try_recv(&mut self, channel: Channel) -> io::Result<Option<(&[u8], IpAddr)>> {
+        if self.received[channel.index()].is_empty() {
+            self.receive();
+        }
+
+        match self.received[channel.index()].pop_front() {
+            Some((datagram, remote)) => {
+                self.current = datagram;
+
+                Ok(Some((&self.current, remote)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Counts the receive queues of the interface, each of which needs a socket for responses arriving on it
+fn count_receive_queues(interface_name: &str) -> u32 {
+    let queues = fs::read_dir(format!("/sys/class/net/{}/queues", interface_name))
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("rx-"))
+                .count()
+        })
+        .unwrap_or(0);
+
+    queues.max(1) as u32
+}
@@ -0,0 +1,370 @@
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anyhow::Context;
+
+/// Frames in the UMEM. The first half are handed to the kernel to receive into, the second half are ours to send from.
+const FRAME_COUNT: u32 = 4096;
+/// Fits a full sized Ethernet frame, and divides a page evenly as the kernel requires
+const FRAME_SIZE: u32 = 2048;
+/// Entries in each of the four rings, which are big enough to hold every frame of their half of the UMEM
+const RING_SIZE: u32 = FRAME_COUNT / 2;
+/// A kick only gets the kernel sending so many frames in copy mode, so it takes a few to get a full ring going
+const MAX_TRANSMIT_KICKS: usize = 128;
+
+/// An AF_XDP socket bound to a single receive queue of an interface, with a UMEM of its own its rings point into.
+/// The kernel copies packets in and out of the UMEM in copy mode, and the NIC reads and writes it directly in
+/// zero-copy mode.
+pub(crate) struct XdpSocket {
+    fd: OwnedFd,
+    umem: *mut u8,
+    fill: ProducerRing<u64>,
+    completion: ConsumerRing<u64>,
+    rx: ConsumerRing<libc::xdp_desc>,
+    tx: ProducerRing<libc::xdp_desc>,
+    /// Frames of the sending half that aren't queued up to go out or waiting to be completed
+    free_frames: Vec<u64>,
+    /// Whether frames went into the transmit ring since the kernel was last kicked
+    unsent: bool,
+}
+
+impl XdpSocket {
+    /// Sets up a socket on the queue, in zero-copy mode if the driver supports it and copy mode otherwise
+    pub(crate) fn open(interface_index: u32, queue_id: u32) -> anyhow::Result<Self> {
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Unable to open an AF_XDP socket");
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let umem_length = (FRAME_COUNT * FRAME_SIZE) as usize;
+        let umem = map(-1, 0, umem_length, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS)
+            .context("Unable to allocate the UMEM")?;
+
+        // from here on dropping the socket unmaps the UMEM, including when setting it up fails below
+        let mut socket = Self {
+            fd,
+            umem: umem as *mut u8,
+            fill: ProducerRing::unmapped(),
+            completion: ConsumerRing::unmapped(),
+            rx: ConsumerRing::unmapped(),
+            tx: ProducerRing::unmapped(),
+            free_frames: (RING_SIZE..FRAME_COUNT).map(|frame| (frame * FRAME_SIZE) as u64).collect(),
+            unsent: false,
+        };
+        let fd = socket.fd.as_raw_fd();
+
+        let registration = libc::xdp_umem_reg_v1 {
+            addr: umem as u64,
+            len: umem_length as u64,
+            chunk_size: FRAME_SIZE,
+            headroom: 0,
+        };
+        set_option(fd, libc::XDP_UMEM_REG, &registration).context("Unable to register the UMEM")?;
+
+        for ring in [libc::XDP_UMEM_FILL_RING, libc::XDP_UMEM_COMPLETION_RING, libc::XDP_RX_RING, libc::XDP_TX_RING] {
+            set_option(fd, ring, &(RING_SIZE as libc::c_int)).context("Unable to size the AF_XDP rings")?;
+        }
+
+        let mut offsets: libc::xdp_mmap_offsets = unsafe { mem::zeroed() };
+        let mut offsets_length = mem::size_of::<libc::xdp_mmap_offsets>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_XDP,
+                libc::XDP_MMAP_OFFSETS,
+                &mut offsets as *mut libc::xdp_mmap_offsets as *mut libc::c_void,
+                &mut offsets_length,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error()).context("Unable to look up the AF_XDP ring offsets");
+        }
+
+        socket.fill = ProducerRing::map(fd, &offsets.fr, libc::XDP_UMEM_PGOFF_FILL_RING as libc::off_t)?;
+        socket.completion =
+            ConsumerRing::map(fd, &offsets.cr, libc::XDP_UMEM_PGOFF_COMPLETION_RING as libc::off_t)?;
+        socket.rx = ConsumerRing::map(fd, &offsets.rx, libc::XDP_PGOFF_RX_RING)?;
+        socket.tx = ProducerRing::map(fd, &offsets.tx, libc::XDP_PGOFF_TX_RING)?;
+
+        // the receiving half of the UMEM is the kernel's to fill from the start
+        for frame in 0..RING_SIZE {
+            socket.fill.push((frame * FRAME_SIZE) as u64);
+        }
+        socket.fill.submit();
+
+        let mut address: libc::sockaddr_xdp = unsafe { mem::zeroed() };
+        address.sxdp_family = libc::AF_XDP as u16;
+        address.sxdp_ifindex = interface_index;
+        address.sxdp_queue_id = queue_id;
+
+        let bound = [libc::XDP_ZEROCOPY, libc::XDP_COPY].into_iter().any(|mode| {
+            address.sxdp_flags = mode;
+
+            unsafe {
+                libc::bind(
+                    fd,
+                    &address as *const libc::sockaddr_xdp as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_xdp>() as libc::socklen_t,
+                ) == 0
+            }
+        });
+        if !bound {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Unable to bind an AF_XDP socket to queue {}", queue_id));
+        }
+
+        Ok(socket)
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    /// Copies the frame into the UMEM and queues it up to go out on the next `transmit`, returning false if every
+    /// frame we send from is still in use
+    pub(crate) fn queue(&mut self, frame: &[u8]) -> bool {
+        self.reclaim_completed();
+
+        if frame.len() > FRAME_SIZE as usize || self.tx.free() == 0 {
+            return false;
+        }
+
+        let address = match self.free_frames.pop() {
+            Some(address) => address,
+            None => return false,
+        };
+
+        unsafe { ptr::copy_nonoverlapping(frame.as_ptr(), self.umem.add(address as usize), frame.len()) };
+        self.tx.push(libc::xdp_desc { addr: address, len: frame.len() as u32, options: 0 });
+        self.unsent = true;
+
+        true
+    }
+
+    /// Hands the queued frames to the kernel and gets it sending them
+    pub(crate) fn transmit(&mut self) {
+        if !self.unsent {
+            return;
+        }
+
+        self.tx.submit();
+        self.unsent = false;
+
+        for _ in 0..MAX_TRANSMIT_KICKS {
+            let result = unsafe { libc::sendto(self.fd(), ptr::null(), 0, libc::MSG_DONTWAIT, ptr::null(), 0) };
+
+            if result < 0 {
+                match io::Error::last_os_error().raw_os_error() {
+                    Some(libc::EAGAIN) | Some(libc::EBUSY) | Some(libc::ENOBUFS) => {}
+                    _ => break,
+                }
+            }
+
+            if self.tx.is_drained() {
+                break;
+            }
+        }
+    }
+
+    /// Passes every frame that came in to the handler, which gets the whole frame from the Ethernet header on
+    pub(crate) fn receive(&mut self, mut handle: impl FnMut(&[u8])) {
+        let mut received = 0;
+
+        while let Some(descriptor) = self.rx.pop() {
+            let frame =
+                unsafe { std::slice::from_raw_parts(self.umem.add(descriptor.addr as usize), descriptor.len as usize) };
+            handle(frame);
+
+            // the frame goes straight back to the kernel to receive into again
+            self.fill.push(descriptor.addr);
+            received += 1;
+        }
+
+        if received > 0 {
+            self.rx.release();
+            self.fill.submit();
+        }
+    }
+
+    /// Takes back the frames the kernel is done sending
+    fn reclaim_completed(&mut self) {
+        let mut reclaimed = false;
+
+        while let Some(address) = self.completion.pop() {
+            self.free_frames.push(address);
+            reclaimed = true;
+        }
+
+        if reclaimed {
+            self.completion.release();
+        }
+    }
+}
+
+impl Drop for XdpSocket {
+    fn drop(&mut self) {
+        // the rings unmap themselves
+        unsafe { libc::munmap(self.umem as *mut libc::c_void, (FRAME_COUNT * FRAME_SIZE) as usize) };
+    }
+}
+
+/// The memory a ring is mapped into, along with where its indices and entries sit in it
+struct RingMap<T> {
+    memory: *mut libc::c_void,
+    length: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    entries: *mut T,
+}
+
+impl<T> RingMap<T> {
+    fn unmapped() -> Self {
+        Self {
+            memory: ptr::null_mut(),
+            length: 0,
+            producer: ptr::null(),
+            consumer: ptr::null(),
+            entries: ptr::null_mut(),
+        }
+    }
+
+    fn map(fd: RawFd, offsets: &libc::xdp_ring_offset, page_offset: libc::off_t) -> anyhow::Result<Self> {
+        let length = offsets.desc as usize + RING_SIZE as usize * mem::size_of::<T>();
+        let memory = map(fd, page_offset, length, libc::MAP_SHARED | libc::MAP_POPULATE)
+            .context("Unable to map an AF_XDP ring")?;
+
+        unsafe {
+            Ok(Self {
+                memory,
+                length,
+                producer: memory.add(offsets.producer as usize) as *const AtomicU32,
+                consumer: memory.add(offsets.consumer as usize) as *const AtomicU32,
+                entries: memory.add(offsets.desc as usize) as *mut T,
+            })
+        }
+    }
+
+    fn entry(&self, index: u32) -> *mut T {
+        unsafe { self.entries.add((index & (RING_SIZE - 1)) as usize) }
+    }
+}
+
+impl<T> Drop for RingMap<T> {
+    fn drop(&mut self) {
+        if !self.memory.is_null() {
+            unsafe { libc::munmap(self.memory, self.length) };
+        }
+    }
+}
+
+/// A ring we put entries on for the kernel to take off
+struct ProducerRing<T> {
+    ring: RingMap<T>,
+    /// Where the next entry goes, which the kernel only learns of on `submit`
+    producer: u32,
+}
+
+impl<T> ProducerRing<T> {
+    fn unmapped() -> Self {
+        Self { ring: RingMap::unmapped(), producer: 0 }
+    }
+
+    fn map(fd: RawFd, offsets: &libc::xdp_ring_offset, page_offset: libc::off_t) -> anyhow::Result<Self> {
+        let ring = RingMap::map(fd, offsets, page_offset)?;
+        let producer = unsafe { (*ring.producer).load(Ordering::Relaxed) };
+
+        Ok(Self { ring, producer })
+    }
+
+    fn free(&self) -> u32 {
+        let consumer = unsafe { (*self.ring.consumer).load(Ordering::Acquire) };
+
+        RING_SIZE - self.producer.wrapping_sub(consumer)
+    }
+
+    /// Puts the entry on the ring, which must have room for it
+    fn push(&mut self, entry: T) {
+        unsafe { self.ring.entry(self.producer).write(entry) };
+        self.producer = self.producer.wrapping_add(1);
+    }
+
+    /// Makes the entries pushed so far visible to the kernel
+    fn submit(&mut self) {
+        unsafe { (*self.ring.producer).store(self.producer, Ordering::Release) };
+    }
+
+    /// Whether the kernel has taken everything submitted
+    fn is_drained(&self) -> bool {
+        unsafe { (*self.ring.consumer).load(Ordering::Acquire) == self.producer }
+    }
+}
+
+/// A ring the kernel puts entries on for us to take off
+struct ConsumerRing<T> {
+    ring: RingMap<T>,
+    /// Where the next entry is taken from, which the kernel only learns of on `release`
+    consumer: u32,
+}
+
+impl<T> ConsumerRing<T> {
+    fn unmapped() -> Self {
+        Self { ring: RingMap::unmapped(), consumer: 0 }
+    }
+
+    fn map(fd: RawFd, offsets: &libc::xdp_ring_offset, page_offset: libc::off_t) -> anyhow::Result<Self> {
+        let ring = RingMap::map(fd, offsets, page_offset)?;
+        let consumer = unsafe { (*ring.consumer).load(Ordering::Relaxed) };
+
+        Ok(Self { ring, consumer })
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let producer = unsafe { (*self.ring.producer).load(Ordering::Acquire) };
+
+        if producer == self.consumer {
+            return None;
+        }
+
+        let entry = unsafe { self.ring.entry(self.consumer).read() };
+        self.consumer = self.consumer.wrapping_add(1);
+
+        Some(entry)
+    }
+
+    /// Hands the slots of the entries popped so far back to the kernel
+    fn release(&mut self) {
+        unsafe { (*self.ring.consumer).store(self.consumer, Ordering::Release) };
+    }
+}
+
+fn map(fd: RawFd, offset: libc::off_t, length: usize, flags: libc::c_int) -> io::Result<*mut libc::c_void> {
+    let memory = unsafe { libc::mmap(ptr::null_mut(), length, libc::PROT_READ | libc::PROT_WRITE, flags, fd, offset) };
+
+    if memory == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(memory)
+}
+
+fn set_option<T>(fd: RawFd, option: libc::c_int, value: &T) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_XDP,
+            option,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
@@ -2,6 +2,26 @@ extern crate cidr_utils;
 mod armada;
 pub mod utils;
 
-pub use crate::armada::config::{host::HostIterator, port::PortIterator};
-pub use crate::armada::work::ArmadaWorkMessage;
-pub use crate::armada::Armada;
+pub use crate::armada::analyze::{analyze_capture, AnalyzeOptions, CaptureAnalysis};
+pub use crate::armada::banner::{BannerGrabber, BannerOptions};
+pub use crate::armada::capture::PacketCapture;
+pub use crate::armada::connection::ConnectionOptions;
+pub use crate::armada::fingerprint::SynAckFingerprint;
+pub use crate::armada::hooks::{PacketHooks, PacketInfo, PacketProtocol};
+pub use crate::armada::config::{
+    host::HostIterator,
+    port::PortIterator,
+    route::SourceRoutes,
+    scan::{
+        AdaptiveTimeout, Decoys, Pacing, RateLimitStrategy, RateLimiterFactory, ScanOptions, ScanShard, SubnetRateLimit, TcpProbeOption,
+        TcpProbeProfile,
+    },
+};
+pub use crate::armada::result::{LiveHost, PortResult, PortState, ScanCoverage, ScanReport};
+pub use crate::armada::scan_type::ScanType;
+pub use crate::armada::service::{ServiceDetector, ServiceMatch, DEFAULT_SERVICE_TIMEOUT};
+pub use crate::armada::tls::{CertificateInfo, TlsInspector, COMMON_TLS_PORTS};
+pub use crate::armada::work::{ArmadaWorkMessage, SourceStats};
+pub use crate::armada::{Armada, ArmadaOptions, Engine, RateLimiter, ResultSink, Scanner};
+pub use pnet::util::MacAddr;
+pub use tokio_util::sync::CancellationToken;
@@ -1,41 +1,357 @@
 use std::net::IpAddr;
+#[cfg(unix)]
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use cidr_utils::cidr::IpCidr;
+#[cfg(unix)]
+use cidr_utils::cidr::Ipv4Cidr;
+use pnet::datalink::NetworkInterface;
+
+use crate::armada::config::route::SourceRoutes;
+
+/// An IPv4 network that is reachable over one of our interfaces without going through a router
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub(crate) struct OnLinkNetwork {
+    pub(crate) network: Ipv4Cidr,
+    pub(crate) interface_name: String,
+    /// The preferred source address for the network, as cited in the route
+    pub(crate) source_ip: Option<Ipv4Addr>,
+}
 
 /// Attempts to figure out what IP addresses you *probably* want to set as "source" ips for armada
 /// It will check for the default route to the internet and grab the IPs configured for that interface.
 pub async fn get_default_ips() -> Option<Vec<IpAddr>> {
-    get_interface_holding_default_route().await.map(|interface_name| {
-        pnet::datalink::interfaces().into_iter()
-            .find(|interface| interface.name.eq(&interface_name))
-            .map(|interface| {
-                interface.ips.into_iter()
-                    .map(|networks| networks.ip())
-                    .collect::<Vec<_>>()
-            })
-    }).flatten()
-}
-
-/// Fetches the interface that is cited in the default route
+    get_default_interface().and_then(|interface_name| get_interface_ips(&interface_name))
+}
+
+/// Fetches the IPs configured on the interface, or `None` if there's no interface by that name
+pub fn get_interface_ips(interface_name: &str) -> Option<Vec<IpAddr>> {
+    pnet::datalink::interfaces().into_iter()
+        .find(|interface| interface.name.eq(interface_name))
+        .map(|interface| {
+            interface.ips.into_iter()
+                .map(|networks| networks.ip())
+                .collect::<Vec<_>>()
+        })
+}
+
+/// The index of the interface, which zone identifiers and the scopes of link-local socket addresses stand for it by
+pub fn get_interface_index(interface_name: &str) -> Option<u32> {
+    pnet::datalink::interfaces().into_iter()
+        .find(|interface| interface.name.eq(interface_name))
+        .map(|interface| interface.index)
+}
+
+/// The name of the interface with the index
+pub fn get_interface_name(index: u32) -> Option<String> {
+    pnet::datalink::interfaces().into_iter()
+        .find(|interface| interface.index == index)
+        .map(|interface| interface.name)
+}
+
+/// Fetches the interface that is cited in the default route, from `ip` or else from `route` where there's no `ip`,
+/// like on macOS
 /// It's embarrassing for Rust that we don't have an easier to use library for this
-async fn get_interface_holding_default_route() -> Option<String> {
+#[cfg(unix)]
+pub fn get_default_interface() -> Option<String> {
+    use regex::Regex;
+
+    // parse out device name from `... dev eth0 ...`, or `interface: en0` on a line of its own
+    let (output, interface_regex) = match run_command("ip", &["route", "show", "default", "0.0.0.0/0"]) {
+        Some(ip_output) => (ip_output, r"\bdev ([^\s]+)"),
+        None => (run_command("route", &["-n", "get", "default"])?, r"\binterface: ([^\s]+)"),
+    };
+
+    let interface_regex = Regex::new(interface_regex).ok()?;
+
+    interface_regex.captures(&output)
+        .and_then(|captures| captures.get(1))
+        .map(|interface_name| interface_name.as_str().to_string())
+}
+
+/// Fetches the Npcap device of the interface that is cited in the default route. `route print` only gives its address,
+/// so the device is the one that address is configured on.
+#[cfg(not(unix))]
+pub fn get_default_interface() -> Option<String> {
+    use regex::Regex;
+
+    // parse out the interface address from `0.0.0.0  0.0.0.0  <gateway>  <interface>  <metric>`
+    let output = run_command("route", &["print", "-4", "0.0.0.0"])?;
+    let interface_regex = Regex::new(r"(?m)^\s*0\.0\.0\.0\s+0\.0\.0\.0\s+\S+\s+(\S+)").ok()?;
+    let interface_ip = interface_regex.captures(&output)
+        .and_then(|captures| captures.get(1))
+        .and_then(|interface_ip| IpAddr::from_str(interface_ip.as_str()).ok())?;
+
+    pnet::datalink::interfaces().into_iter()
+        .find(|interface| interface.ips.iter().any(|network| network.ip() == interface_ip))
+        .map(|interface| interface.name)
+}
+
+/// Runs the program, returning what it wrote to stdout, or `None` when there's no such program to run
+pub(crate) fn run_command(program: &str, arguments: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(arguments).output().ok()?;
+
+    Some(String::from_utf8(output.stdout).unwrap_or_default())
+}
+
+/// Fetches the IPv4 networks our routing table considers directly attached (`scope link`)
+#[cfg(unix)]
+pub(crate) async fn get_on_link_ipv4_networks() -> Vec<OnLinkNetwork> {
     use regex::Regex;
     use tokio::process::Command;
 
     let ip_output = Command::new("ip")
+        .arg("-4")
         .arg("route")
         .arg("show")
-        .arg("default")
-        .arg("0.0.0.0/0")
+        .arg("scope")
+        .arg("link")
         .output()
         .await
         .ok()
-        .map(|stdout_bytes| String::from_utf8(stdout_bytes.stdout).ok())
-        .flatten()?;
+        .and_then(|stdout_bytes| String::from_utf8(stdout_bytes.stdout).ok())
+        .unwrap_or_default();
 
-    // parse out device name from `... dev eth0 ...`
-    let interface_regex = Regex::new(r"\bdev ([^\s]+)").ok()?;
+    // parse out `192.0.2.0/24 dev eth0 proto kernel scope link src 192.0.2.2`
+    let route_regex = match Regex::new(r"^(\S+) dev (\S+)(?:.*\bsrc (\S+))?") {
+        Ok(route_regex) => route_regex,
+        Err(_) => return Vec::new(),
+    };
 
-    interface_regex.captures(&ip_output)
-        .map(|captures| captures.get(1))
-        .flatten()
-        .map(|interface_name| interface_name.as_str().to_string())
-}
\ No newline at end of file
+    ip_output.lines()
+        .filter_map(|route| route_regex.captures(route))
+        .filter_map(|captures| {
+            let network = Ipv4Cidr::from_str(captures.get(1)?.as_str()).ok()?;
+            let interface_name = captures.get(2)?.as_str().to_string();
+            let source_ip = captures.get(3).and_then(|source_ip| Ipv4Addr::from_str(source_ip.as_str()).ok());
+
+            Some(OnLinkNetwork { network, interface_name, source_ip })
+        })
+        .collect()
+}
+
+/// Works out the source address the kernel sends from to each destination from its IPv4 and IPv6 routing tables, so
+/// probes to every target, whatever its family and whichever interface it's routed out of, go out from the right
+/// address. Routes without a preferred source get the address of their interface that's on the same network as their
+/// gateway, or else its first. Only the local and main tables are read, policy routing rules aren't followed. Given
+/// an interface, only the routes out of it are kept.
+pub async fn get_source_routes(interface: Option<&str>) -> SourceRoutes {
+    use tokio::process::Command;
+
+    let interfaces = pnet::datalink::interfaces();
+    let mut local_routes = Vec::new();
+    let mut main_routes = Vec::new();
+
+    for family in ["-4", "-6"] {
+        let ip_output = Command::new("ip")
+            .arg(family)
+            .arg("route")
+            .arg("show")
+            .arg("table")
+            .arg("all")
+            .output()
+            .await
+            .ok()
+            .and_then(|stdout_bytes| String::from_utf8(stdout_bytes.stdout).ok())
+            .unwrap_or_default();
+
+        for route in ip_output.lines().filter_map(|line| parse_route(line, family.eq("-6"))) {
+            if interface.is_some_and(|interface| route.interface_name.ne(interface)) {
+                continue;
+            }
+
+            let source = match (route.source, route.local) {
+                (Some(source), _) => Some(source),
+                // a route to one of our own addresses is sent from that very address
+                (None, true) => Some(route.network.first_as_ip_addr()),
+                (None, false) => get_route_interface_source(&interfaces, &route),
+            };
+
+            if let Some(source) = source {
+                match route.local {
+                    true => local_routes.push((route.metric, route.network, source)),
+                    false => main_routes.push((route.metric, route.network, source)),
+                }
+            }
+        }
+    }
+
+    // the local table is looked in before the main one, and of routes to the same network the lowest metric wins
+    local_routes.sort_by_key(|(metric, _, _)| *metric);
+    main_routes.sort_by_key(|(metric, _, _)| *metric);
+
+    local_routes.into_iter()
+        .chain(main_routes)
+        .fold(SourceRoutes::new(), |source_routes, (_, network, source)| source_routes.add_route(network, source))
+}
+
+/// The address of the route's interface that's on the same network as the gateway, or the route's network when it has
+/// no gateway, falling back to the interface's first address of the family. IPv6 link-local addresses are passed
+/// over, since they only reach the link, and so are routes to link-local networks.
+fn get_route_interface_source(interfaces: &[NetworkInterface], route: &Route) -> Option<IpAddr> {
+    let interface = interfaces.iter().find(|interface| interface.name.eq(&route.interface_name))?;
+    let destination = route.gateway.unwrap_or_else(|| route.network.first_as_ip_addr());
+    if matches!(route.network.first_as_ip_addr(), IpAddr::V6(ip) if ip.is_unicast_link_local()) {
+        return None;
+    }
+
+    let candidates = || {
+        interface.ips.iter().filter(|network| {
+            network.ip().is_ipv4() == destination.is_ipv4()
+                && !matches!(network.ip(), IpAddr::V6(ip) if ip.is_unicast_link_local())
+        })
+    };
+
+    candidates()
+        .find(|network| network.contains(destination))
+        .or_else(|| candidates().next())
+        .map(|network| network.ip())
+}
+
+/// A unicast route out of the local or main routing table
+struct Route {
+    network: IpCidr,
+    interface_name: String,
+    gateway: Option<IpAddr>,
+    source: Option<IpAddr>,
+    metric: u32,
+    /// Whether the route is to one of our own addresses
+    local: bool,
+}
+
+/// Parses a route as `ip route show table all` lists it, e.g. `10.0.0.0/8 via 192.0.2.1 dev eth0 metric 100` or
+/// `local 192.0.2.2 dev eth0 table local proto kernel scope host src 192.0.2.2`
+fn parse_route(line: &str, ipv6: bool) -> Option<Route> {
+    let mut words = line.split_whitespace().peekable();
+
+    let local = match *words.peek()? {
+        "local" => {
+            words.next();
+            true
+        }
+        "unicast" => {
+            words.next();
+            false
+        }
+        // broadcast, multicast, blackhole and the like, none of which probes go out through
+        "broadcast" | "multicast" | "anycast" | "unreachable" | "prohibit" | "blackhole" | "throw" | "nat" => return None,
+        _ => false,
+    };
+
+    let network = match words.next()? {
+        "default" if ipv6 => IpCidr::from_str("::/0").ok()?,
+        "default" => IpCidr::from_str("0.0.0.0/0").ok()?,
+        network => IpCidr::from_str(network).ok()?,
+    };
+
+    let mut interface_name = None;
+    let mut gateway = None;
+    let mut source = None;
+    let mut metric = 0;
+    let mut table = None;
+
+    while let Some(key) = words.next() {
+        match key {
+            "dev" => interface_name = words.next().map(str::to_string),
+            "via" => gateway = words.next().and_then(|gateway| IpAddr::from_str(gateway).ok()),
+            "src" => source = words.next().and_then(|source| IpAddr::from_str(source).ok()),
+            "metric" => metric = words.next().and_then(|metric| metric.parse().ok()).unwrap_or(0),
+            "table" => table = words.next(),
+            _ => {}
+        }
+    }
+
+    // routes outside of the main table are only listed along with the table they're in
+    if !matches!(table, None | Some("local")) {
+        return None;
+    }
+
+    Some(Route { network, interface_name: interface_name?, gateway, source, metric, local })
+}
+
+/// Finds the interface of the VLAN on top of the parent interface, if one has been set up
+pub fn get_vlan_interface(parent: &str, vlan: u16) -> Option<String> {
+    // parse out `eth0.100       | 100  | eth0`, after a couple of header lines
+    let vlan_config = std::fs::read_to_string("/proc/net/vlan/config").ok()?;
+
+    vlan_config.lines().find_map(|line| {
+        let mut fields = line.split('|').map(str::trim);
+        let (name, id, device) = (fields.next()?, fields.next()?, fields.next()?);
+
+        (device == parent && id.parse::<u16>().ok()? == vlan).then(|| name.to_string())
+    })
+}
+
+/// Finds the interface the address is configured on
+pub fn get_interface_with_ip(ip: IpAddr) -> Option<String> {
+    pnet::datalink::interfaces().into_iter()
+        .find(|interface| interface.ips.iter().any(|network| network.ip() == ip))
+        .map(|interface| interface.name)
+}
+
+/// Fetches the cores on the NUMA node the interface's device hangs off of, which are the cheapest to send and
+/// receive its packets from. Falls back to every online core when there's no telling, e.g. for virtual interfaces or
+/// on boxes with a single node.
+pub fn get_interface_local_cores(interface_name: &str) -> Vec<usize> {
+    let numa_node = std::fs::read_to_string(format!("/sys/class/net/{}/device/numa_node", interface_name))
+        .ok()
+        .and_then(|numa_node| numa_node.trim().parse::<usize>().ok());
+
+    numa_node
+        .and_then(|numa_node| std::fs::read_to_string(format!("/sys/devices/system/node/node{}/cpulist", numa_node)).ok())
+        .or_else(|| std::fs::read_to_string("/sys/devices/system/cpu/online").ok())
+        .and_then(|cpu_list| parse_cpu_list(cpu_list.trim()))
+        .filter(|cores| !cores.is_empty())
+        .unwrap_or_else(|| (0..num_online_cores()).collect())
+}
+
+/// Parses a list of cores the way the kernel writes them, e.g. `0-3,8-11`
+pub fn parse_cpu_list(cpu_list: &str) -> Option<Vec<usize>> {
+    let mut cores = Vec::new();
+
+    for range in cpu_list.split(',') {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.trim().parse::<usize>().ok()?, end.trim().parse::<usize>().ok()?);
+
+                if start > end {
+                    return None;
+                }
+
+                cores.extend(start..=end);
+            }
+            None => cores.push(range.trim().parse().ok()?),
+        }
+    }
+
+    Some(cores)
+}
+
+fn num_online_cores() -> usize {
+    std::thread::available_parallelism().map_or(1, |cores| cores.get())
+}
+
+/// Keeps the calling thread on the one core from here on. Memory the thread goes on to allocate is placed on that
+/// core's NUMA node by the kernel as it's first touched.
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread(core: usize) -> anyhow::Result<()> {
+    let result = unsafe {
+        let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut cpu_set);
+
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set)
+    };
+
+    if result != 0 {
+        anyhow::bail!("Unable to pin a worker to core {}: {}", core, std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current_thread(_core: usize) -> anyhow::Result<()> {
+    anyhow::bail!("Pinning workers to cores is only supported on Linux.")
+}